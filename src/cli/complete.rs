@@ -0,0 +1,472 @@
+//! Dynamic shell completion
+//!
+//! `owl completions <shell>` prints a small script that hands the words typed
+//! so far to the hidden `owl __complete <shell> <words...>` entry point, which
+//! prints one candidate per line. Subcommands, flags and enum values come from
+//! the clap definition; package names and config files come from the config on
+//! disk.
+
+use super::handler::Cli;
+use clap::{Arg, ArgAction, Command, CommandFactory, ValueEnum};
+
+/// Shells `owl completions` can generate a script for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// What the word under the cursor is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Context {
+    /// A subcommand of the command at `path` (empty for the root)
+    Subcommand { path: Vec<String> },
+    /// A flag of the command at `path`
+    Flag { path: Vec<String> },
+    /// The value of the argument with id `arg`
+    Value { path: Vec<String>, arg: String },
+    /// Nothing can be completed here
+    None,
+}
+
+/// Where candidates for an argument value come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Packages,
+    ConfigFiles,
+}
+
+/// Arguments whose values are names from the config, keyed by subcommand path and arg id
+const SOURCES: &[(&str, &str, Source)] = &[
+    ("adopt", "items", Source::Packages),
+    ("config-check", "package", Source::Packages),
+    ("explain", "package", Source::Packages),
+    ("config-check", "file", Source::ConfigFiles),
+    ("config-check", "dump_canonical", Source::ConfigFiles),
+    ("config", "file", Source::ConfigFiles),
+    ("config", "package", Source::Packages),
+    ("import-pacman", "into", Source::ConfigFiles),
+];
+
+/// Work out what is being completed
+///
+/// `words` are the arguments after `owl` up to and including the word under
+/// the cursor, which may be empty.
+fn context(root: &Command, words: &[String]) -> Context {
+    let Some((current, before)) = words.split_last() else {
+        return Context::Subcommand { path: Vec::new() };
+    };
+    let mut path: Vec<String> = Vec::new();
+    let mut cmd = root;
+    let mut pending: Option<&Arg> = None;
+    let mut positionals = 0;
+    let mut only_positionals = false;
+
+    for word in before {
+        if pending.take().is_some() {
+            continue;
+        }
+        if !only_positionals && word == "--" {
+            only_positionals = true;
+        } else if !only_positionals && let Some(long) = word.strip_prefix("--") {
+            if !long.contains('=') {
+                pending = find_long(cmd, long).filter(|arg| takes_value(arg));
+            }
+        } else if !only_positionals && word.len() > 1 && word.starts_with('-') {
+            // `-vy`: only the last short flag can take the following word
+            pending = word
+                .chars()
+                .last()
+                .and_then(|short| find_short(cmd, short))
+                .filter(|arg| takes_value(arg));
+        } else if positionals == 0
+            && let Some(sub) = find_subcommand(cmd, word)
+        {
+            path.push(sub.get_name().to_string());
+            cmd = sub;
+        } else if cmd.has_subcommands() && path.is_empty() && positionals == 0 {
+            // Not a known subcommand; whatever it is, there is nothing to offer
+            return Context::None;
+        } else {
+            positionals += 1;
+        }
+    }
+
+    if let Some(arg) = pending {
+        return Context::Value {
+            path,
+            arg: arg.get_id().to_string(),
+        };
+    }
+    if !only_positionals && current.starts_with('-') {
+        if let Some((flag, _)) = current.strip_prefix("--").and_then(|f| f.split_once('='))
+            && let Some(arg) = find_long(cmd, flag).filter(|arg| takes_value(arg))
+        {
+            return Context::Value {
+                path,
+                arg: arg.get_id().to_string(),
+            };
+        }
+        return Context::Flag { path };
+    }
+    if positionals == 0 && cmd.has_subcommands() {
+        return Context::Subcommand { path };
+    }
+    match positional_at(cmd, positionals) {
+        Some(arg) => Context::Value {
+            path,
+            arg: arg.get_id().to_string(),
+        },
+        None => Context::None,
+    }
+}
+
+fn find_subcommand<'a>(cmd: &'a Command, word: &str) -> Option<&'a Command> {
+    cmd.get_subcommands()
+        .find(|sub| sub.get_name() == word || sub.get_all_aliases().any(|alias| alias == word))
+}
+
+fn find_long<'a>(cmd: &'a Command, long: &str) -> Option<&'a Arg> {
+    cmd.get_arguments().find(|arg| arg.get_long() == Some(long))
+}
+
+fn find_short(cmd: &Command, short: char) -> Option<&Arg> {
+    cmd.get_arguments()
+        .find(|arg| arg.get_short() == Some(short))
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+/// The positional argument filling slot `index`; a trailing list takes all the rest
+fn positional_at(cmd: &Command, index: usize) -> Option<&Arg> {
+    let positionals: Vec<&Arg> = cmd.get_positionals().collect();
+    positionals.get(index).copied().or_else(|| {
+        positionals
+            .last()
+            .copied()
+            .filter(|arg| matches!(arg.get_action(), ArgAction::Append))
+    })
+}
+
+fn command_at<'a>(root: &'a Command, path: &[String]) -> Option<&'a Command> {
+    path.iter()
+        .try_fold(root, |cmd, name| cmd.find_subcommand(name))
+}
+
+/// Names the config can supply
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigNames {
+    pub packages: Vec<String>,
+    /// Config files relative to the owl root
+    pub files: Vec<String>,
+}
+
+impl ConfigNames {
+    /// Read names from the owl root, ignoring any errors: completion must stay quiet
+    pub fn load() -> Self {
+        let mut names = Self::default();
+        if let Ok(config) = crate::core::config::Config::load_all_relevant_config_files() {
+            names.packages = config.packages.into_keys().collect();
+            names.packages.sort();
+        }
+        if let Ok(root) = crate::internal::environment::get().owl_dir() {
+            names.files = config_files(&root);
+        }
+        names
+    }
+}
+
+/// The owl root's config files (`files::discover_config_files`), relative to it
+fn config_files(root: &std::path::Path) -> Vec<String> {
+    let mut files: Vec<String> = crate::internal::files::discover_config_files(root)
+        .files
+        .iter()
+        .filter_map(|path| Some(path.strip_prefix(root).ok()?.display().to_string()))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Candidates for the last word, with descriptions where the clap definition has them
+pub fn candidates(
+    words: &[String],
+    names: &dyn Fn() -> ConfigNames,
+) -> Vec<(String, Option<String>)> {
+    let root = Cli::command();
+    let current = words.last().map(String::as_str).unwrap_or("");
+    let (prefix, typed) = match current.split_once('=') {
+        Some((flag, value)) if current.starts_with("--") => (format!("{}=", flag), value),
+        _ => (String::new(), current),
+    };
+    let about = |cmd: &Command| cmd.get_about().map(|about| about.to_string());
+    let mut out: Vec<(String, Option<String>)> = match context(&root, words) {
+        Context::Subcommand { path } => command_at(&root, &path)
+            .map(|cmd| {
+                cmd.get_subcommands()
+                    .filter(|sub| !sub.is_hide_set())
+                    .map(|sub| (sub.get_name().to_string(), about(sub)))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Context::Flag { path } => command_at(&root, &path)
+            .map(|cmd| {
+                cmd.get_arguments()
+                    .filter(|arg| !arg.is_hide_set())
+                    .filter_map(|arg| {
+                        let long = arg.get_long()?;
+                        Some((
+                            format!("--{}", long),
+                            arg.get_help().map(|help| help.to_string()),
+                        ))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Context::Value { path, arg } => {
+            let source = path.first().and_then(|sub| {
+                SOURCES
+                    .iter()
+                    .find(|(cmd, id, _)| cmd == sub && *id == arg)
+                    .map(|(_, _, source)| *source)
+            });
+            match source {
+                Some(Source::Packages) => names().packages.into_iter().map(|p| (p, None)).collect(),
+                Some(Source::ConfigFiles) => names().files.into_iter().map(|f| (f, None)).collect(),
+                None => command_at(&root, &path)
+                    .and_then(|cmd| cmd.get_arguments().find(|a| a.get_id() == arg.as_str()))
+                    .map(|arg| {
+                        arg.get_possible_values()
+                            .iter()
+                            .filter(|value| !value.is_hide_set())
+                            .map(|value| {
+                                (
+                                    value.get_name().to_string(),
+                                    value.get_help().map(|help| help.to_string()),
+                                )
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            }
+        }
+        Context::None => Vec::new(),
+    };
+    out.retain(|(candidate, _)| candidate.starts_with(typed));
+    for (candidate, _) in &mut out {
+        candidate.insert_str(0, &prefix);
+    }
+    out
+}
+
+/// Print candidates for `owl __complete`; fish shows descriptions after a tab
+pub fn run(shell: Shell, words: &[String]) {
+    for (candidate, help) in candidates(words, &ConfigNames::load) {
+        match help {
+            Some(help) if shell == Shell::Fish => outln!("{}\t{}", candidate, help),
+            _ => outln!("{}", candidate),
+        }
+    }
+}
+
+/// Completion script for `shell`
+pub fn script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH_SCRIPT,
+        Shell::Zsh => ZSH_SCRIPT,
+        Shell::Fish => FISH_SCRIPT,
+    }
+}
+
+const BASH_SCRIPT: &str = r#"# owl bash completion: eval "$(owl completions bash)"
+_owl() {
+    local IFS=$'\n'
+    COMPREPLY=($(owl __complete bash "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null))
+}
+complete -o default -F _owl owl
+"#;
+
+const ZSH_SCRIPT: &str = r#"#compdef owl
+# owl zsh completion: eval "$(owl completions zsh)"
+_owl() {
+    local -a candidates
+    candidates=("${(@f)$(owl __complete zsh "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+    if (( ${#candidates[@]} )) && [[ -n "${candidates[1]}" ]]; then
+        compadd -Q -- "${candidates[@]}"
+    else
+        _files
+    fi
+}
+compdef _owl owl
+"#;
+
+const FISH_SCRIPT: &str = r#"# owl fish completion: owl completions fish | source
+complete -c owl -f -a '(owl __complete fish (commandline -opc)[2..-1] (commandline -ct) 2>/dev/null)'
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        // A trailing space means the cursor is on a new, empty word
+        let mut words: Vec<String> = line.split_whitespace().map(String::from).collect();
+        if line.is_empty() || line.ends_with(' ') {
+            words.push(String::new());
+        }
+        words
+    }
+
+    fn path(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn value(names: &[&str], arg: &str) -> Context {
+        Context::Value {
+            path: path(names),
+            arg: arg.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_context_table() {
+        let cases: &[(&str, Context)] = &[
+            ("", Context::Subcommand { path: path(&[]) }),
+            ("ap", Context::Subcommand { path: path(&[]) }),
+            ("-v ", Context::Subcommand { path: path(&[]) }),
+            ("--diff-context 3 ", Context::Subcommand { path: path(&[]) }),
+            ("--diff-context ", value(&[], "diff_context")),
+            ("--", Context::Flag { path: path(&[]) }),
+            (
+                "apply --",
+                Context::Flag {
+                    path: path(&["apply"]),
+                },
+            ),
+            (
+                "apply -",
+                Context::Flag {
+                    path: path(&["apply"]),
+                },
+            ),
+            ("apply --only ", value(&["apply"], "only")),
+            ("apply --only dot", value(&["apply"], "only")),
+            ("apply --only=dot", value(&["apply"], "only")),
+            ("apply --skip ", value(&["apply"], "skip")),
+            ("apply --timing ", Context::None),
+            ("apply --only dotfiles ", Context::None),
+            ("adopt ", value(&["adopt"], "items")),
+            ("adopt git ", value(&["adopt"], "items")),
+            ("adopt --all git ne", value(&["adopt"], "items")),
+            (
+                "config-check --package ",
+                value(&["config-check"], "package"),
+            ),
+            ("config-check ", value(&["config-check"], "file")),
+            ("config-check main.owl ", Context::None),
+            (
+                "config-check --dump-canonical ",
+                value(&["config-check"], "dump_canonical"),
+            ),
+            ("import-pacman --into ", value(&["import-pacman"], "into")),
+            ("edit ", value(&["edit"], "target")),
+            ("edit dots ", value(&["edit"], "argument")),
+            (
+                "dots ",
+                Context::Subcommand {
+                    path: path(&["dots"]),
+                },
+            ),
+            (
+                "dots audit --",
+                Context::Flag {
+                    path: path(&["dots", "audit"]),
+                },
+            ),
+            ("de ", value(&["edit-dots"], "argument")),
+            ("nonsense ", Context::None),
+            ("adopt -- --weird", value(&["adopt"], "items")),
+        ];
+        let root = Cli::command();
+        for (line, expected) in cases {
+            assert_eq!(
+                &context(&root, &words(line)),
+                expected,
+                "completing {:?}",
+                line
+            );
+        }
+    }
+
+    fn names() -> ConfigNames {
+        ConfigNames {
+            packages: path(&["git", "neovim", "networkmanager"]),
+            files: path(&["groups/dev.owl", "hosts/laptop.owl", "main.owl"]),
+        }
+    }
+
+    fn complete(line: &str) -> Vec<String> {
+        candidates(&words(line), &names)
+            .into_iter()
+            .map(|(candidate, _)| candidate)
+            .collect()
+    }
+
+    #[test]
+    fn test_candidates() {
+        assert!(complete("").contains(&"apply".to_string()));
+        assert!(!complete("").iter().any(|c| c.starts_with("__")));
+        assert_eq!(complete("ad"), vec!["add", "adopt"]);
+        assert_eq!(complete("apply --only dot"), vec!["dotfiles"]);
+        assert_eq!(complete("apply --only=dot"), vec!["--only=dotfiles"]);
+        assert_eq!(complete("adopt ne"), vec!["neovim", "networkmanager"]);
+        assert_eq!(complete("config-check --package g"), vec!["git"]);
+        assert_eq!(complete("config-check h"), vec!["hosts/laptop.owl"]);
+        assert_eq!(complete("config explain h"), vec!["hosts/laptop.owl"]);
+        assert_eq!(complete("config trace g"), vec!["git"]);
+        assert_eq!(complete("edit "), vec!["dots", "config"]);
+        assert!(complete("apply --spl").contains(&"--splay".to_string()));
+        assert!(complete("apply --timing ").is_empty());
+    }
+
+    #[test]
+    fn test_descriptions_come_from_clap() {
+        let found = candidates(&words("apply --only inst"), &names);
+        assert_eq!(
+            found,
+            vec![(
+                "install".to_string(),
+                Some("Install missing packages".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_scripts_call_the_helper() {
+        assert!(
+            script(Shell::Bash).contains(r#"owl __complete bash "${COMP_WORDS[@]:1:COMP_CWORD}""#)
+        );
+        assert!(script(Shell::Bash).contains("complete -o default -F _owl owl"));
+        assert!(script(Shell::Zsh).contains(r#"owl __complete zsh "${(@)words[2,CURRENT]}""#));
+        assert!(script(Shell::Zsh).contains("compdef _owl owl"));
+        assert!(
+            script(Shell::Fish)
+                .contains("owl __complete fish (commandline -opc)[2..-1] (commandline -ct)")
+        );
+    }
+
+    #[test]
+    fn test_config_files_listed_relative_to_root() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("hosts")).unwrap();
+        std::fs::write(dir.path().join("main.owl"), "").unwrap();
+        std::fs::write(dir.path().join("hosts/laptop.owl"), "").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        assert_eq!(
+            config_files(dir.path()),
+            vec!["hosts/laptop.owl", "main.owl"]
+        );
+    }
+}
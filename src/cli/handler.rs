@@ -1,4 +1,7 @@
-use crate::commands::{add, adopt, apply, dots, edit, find};
+use crate::commands::{
+    add, adopt, apply, config, daemon, doctor, dots, edit, env, explain, find, history, import,
+    list, plan, pm, services, state, status, tree,
+};
 use crate::internal::color;
 use crate::internal::constants;
 use clap::{Parser, Subcommand};
@@ -15,14 +18,165 @@ pub struct Cli {
     #[arg(long)]
     pub dry_run: bool,
 
-    /// Run in non-interactive mode
+    /// Dry run with a unified diff under each dotfile update (implies --dry-run)
+    #[arg(long)]
+    pub dry_run_with_diff: bool,
+
+    /// Number of context lines in inline dotfile diffs
+    #[arg(long, value_name = "N", default_value_t = crate::core::diff::DEFAULT_CONTEXT)]
+    pub diff_context: usize,
+
+    /// Run in non-interactive mode: AUR builds go ahead, removals and adoptions do not
     #[arg(short = 'y', long)]
     pub non_interactive: bool,
 
+    /// Answer "no" to a confirmation nobody answers within DURATION (30s, 5m)
+    #[arg(long, value_name = "DURATION", value_parser = crate::internal::time::parse_duration)]
+    pub confirm_timeout: Option<std::time::Duration>,
+
+    /// Treat unknown config directives as errors, as if every file had @strict
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Run no commands named in the config, such as `@env KEY <cmd:...>` values
+    #[arg(long, global = true)]
+    pub safe: bool,
+
+    /// When owl runs as root, build AUR packages as this user (paru refuses root)
+    #[arg(long, global = true, value_name = "NAME")]
+    pub build_user: Option<String>,
+
+    /// Read main, host and group files in this format (default: `main.owl`, or
+    /// `main.toml` when there is no `main.owl`)
+    #[arg(long, global = true, value_enum, value_name = "FORMAT")]
+    pub config_format: Option<crate::core::config::format::ConfigFormat>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+/// Options for the apply command
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ApplyArgs {
+    /// Run only these phases (comma separated)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub only: Vec<apply::phases::Phase>,
+
+    /// Only sync dotfiles, without querying the package manager at all
+    #[arg(long, conflicts_with_all = ["only", "skip", "timing", "diff_env"])]
+    pub dotfiles_only: bool,
+
+    /// Skip these phases (comma separated)
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "only")]
+    pub skip: Vec<apply::phases::Phase>,
+
+    /// Install packages one at a time and report the slowest installs
+    #[arg(long)]
+    pub timing: bool,
+
+    /// Show a diff of the bash/fish env files before writing them
+    #[arg(long)]
+    pub diff_env: bool,
+
+    /// Preview everything a run would change without changing anything: package
+    /// installs and removals, dotfile diffs, the env diff and service changes
+    /// (implies --dry-run)
+    #[arg(long, conflicts_with = "dotfiles_only")]
+    pub diff: bool,
+
+    /// Print the package plan as JSON on stdout, with each change's repo, sizes,
+    /// reboot advice and dependent services, and change nothing (implies --dry-run)
+    #[arg(long, conflicts_with_all = ["dotfiles_only", "diff"])]
+    pub plan_json: bool,
+
+    /// With --plan-json, ask a running `owl daemon` for the plan, planning here
+    /// when none answers
+    #[arg(long, requires = "plan_json")]
+    pub via_daemon: bool,
+
+    /// Run only the items ticked in a review file written by `owl plan --review-file`,
+    /// refusing it if the plan has changed since
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["dotfiles_only", "plan_json"])]
+    pub approved_review: Option<std::path::PathBuf>,
+
+    /// Continue an apply that was interrupted, skipping the steps it finished
+    #[arg(long, conflicts_with_all = ["only", "skip", "dotfiles_only", "plan_json", "approved_review"])]
+    pub resume: bool,
+
+    /// Install missing packages in transactions of at most N, so one failure only
+    /// stops its own batch (default: one transaction)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub install_batch_size: Option<u64>,
+
+    /// Wait up to DURATION (e.g. 10m) for another package manager to release the
+    /// pacman database lock, then defer the package phases (default: 2m)
+    #[arg(long, value_name = "DURATION", value_parser = crate::internal::time::parse_duration)]
+    pub db_lock_wait: Option<std::time::Duration>,
+
+    /// Threads checking dotfiles against their destinations (default: 2x CPUs, I/O bound)
+    #[arg(long, value_name = "N")]
+    pub dotfile_concurrency: Option<usize>,
+
+    /// Sync dotfiles on a thread while packages install and update; the dotfile
+    /// output is printed once the packages are done
+    #[arg(long, conflicts_with = "dotfiles_only")]
+    pub parallel_dotfiles_and_packages: bool,
+
+    /// Wait a random time up to DURATION (e.g. 15m) before starting; default $OWL_SPLAY
+    #[arg(long, value_name = "DURATION")]
+    pub splay: Option<String>,
+
+    /// Splay even when stdin is a terminal
+    #[arg(long)]
+    pub splay_always: bool,
+
+    /// Write progress as JSON Lines on stderr instead of the human output
+    #[arg(long)]
+    pub events_json: bool,
+
+    /// Backups kept per dotfile destination (default: `@backups-keep` or 5)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub keep_backups: Option<u64>,
+
+    /// Start managing declared packages that are already installed without asking
+    #[arg(long)]
+    pub adopt_managed: bool,
+
+    /// Do not warn about env vars like PATH, HOME or LD_PRELOAD replacing the login's values
+    #[arg(long)]
+    pub allow_dangerous_env: bool,
+
+    /// Write dotfiles under DIR instead of their real destinations (~/x -> DIR/x, /etc/x -> DIR/etc/x)
+    #[arg(long, value_name = "DIR", alias = "dotfiles-dest-prefix")]
+    pub dest_prefix: Option<std::path::PathBuf>,
+
+    /// Treat problems found in dotfile sources as errors and sync no dotfiles
+    #[arg(long)]
+    pub strict_sources: bool,
+
+    /// Hash comparing dotfile contents when size and mtime cannot tell (default: xxh3)
+    #[arg(long, value_enum, value_name = "ALGO", alias = "dotfiles-hash-algo")]
+    pub hash_algo: Option<crate::core::dotfiles::HashAlgo>,
+
+    /// Merge dotfiles into their destinations without deleting anything already there
+    #[arg(long)]
+    pub no_dotfiles_delete: bool,
+
+    /// Write dotfiles mapped to absolute paths outside home, such as /etc
+    #[arg(long)]
+    pub allow_outside_home: bool,
+
+    /// Dotfiles changed both locally and in their source since the last apply:
+    /// overwrite them (default), keep them, or merge both changes
+    #[arg(long, value_enum, value_name = "HOW")]
+    pub dotfile_diverged: Option<crate::core::dotfiles::Divergence>,
+
+    /// After the packages, clean the package cache and AUR build files and
+    /// remove orphaned dependencies, each once confirmed
+    #[arg(long)]
+    pub clean_aur: bool,
+}
+
 /// Edit target types for better type safety
 #[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
 pub enum EditTarget {
@@ -30,11 +184,72 @@ pub enum EditTarget {
     Config,
 }
 
+/// Subcommands of `owl dots`
+#[derive(Debug, Clone, Subcommand)]
+pub enum DotsCommand {
+    /// Report files in the dotfiles directory that no mapping uses
+    Audit {
+        /// Move unreferenced entries into dotfiles/.attic/<date>/
+        #[arg(long)]
+        archive: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check dotfile sources for broken symlinks, empty config files, CRLF
+    /// line endings and unreadable files (same as owl check-source)
+    CheckSources {
+        /// Exit with an error if anything is found
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Show what owl knows about one destination: who declares it, its source,
+    /// what apply would do and what it last wrote
+    Explain {
+        /// Destination, e.g. ~/.gitconfig
+        destination: String,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Subcommands of `owl services`
+#[derive(Debug, Clone, Subcommand)]
+pub enum ServicesCommand {
+    /// Let owl manage a service that was enabled before owl saw it
+    Adopt {
+        /// Service unit, e.g. cups.service
+        name: String,
+    },
+}
+
+/// Subcommands of `owl config`
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommand {
+    /// Annotate each line of a config file with its effect on this host
+    Explain {
+        /// Config file, relative to the owl root or the current directory
+        file: String,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List every file that declared a package, in load order, and what the merge kept
+    Trace {
+        /// Package to trace
+        package: String,
+        /// Print the journal as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
 /// Available commands for the CLI
 #[derive(Debug, Clone, Subcommand)]
 pub enum Commands {
     /// Apply configuration (default command)
-    Apply,
+    Apply(ApplyArgs),
     /// Edit dotfiles or config
     Edit {
         /// Type to edit (dots or config)
@@ -43,7 +258,30 @@ pub enum Commands {
         argument: String,
     },
     /// List dotfiles
-    Dots,
+    #[command(alias = "dotfile")]
+    Dots {
+        #[command(subcommand)]
+        action: Option<DotsCommand>,
+        /// Write dotfiles mapped to absolute paths outside home, such as /etc
+        #[arg(long)]
+        allow_outside_home: bool,
+    },
+    /// Check dotfile sources for problems before they are deployed
+    CheckSource {
+        /// Exit with an error if anything is found
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Manage the provenance of services owl enables
+    Services {
+        #[command(subcommand)]
+        action: ServicesCommand,
+    },
+    /// Inspect config files
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
     /// Add packages
     Add {
         /// Packages to add
@@ -51,6 +289,13 @@ pub enum Commands {
         /// Search mode
         #[arg(long)]
         search: bool,
+        /// Skip packages the config file already declares instead of failing
+        /// (always on when several packages are selected)
+        #[arg(long)]
+        only_new: bool,
+        /// Add them as `:requires` of this package's block instead of to @packages
+        #[arg(long, value_name = "PACKAGE")]
+        into: Option<String>,
     },
     /// Adopt existing packages
     Adopt {
@@ -65,10 +310,118 @@ pub enum Commands {
         /// Query terms
         query: Vec<String>,
     },
+    /// Import installed packages, or enabled services, into a config file
+    #[command(visible_alias = "import")]
+    ImportPacman {
+        /// Only import explicitly installed packages (pacman -Qe)
+        #[arg(long)]
+        explicit_only: bool,
+        /// Config file to import into (default: main.owl)
+        #[arg(long, value_name = "FILE")]
+        into: Option<String>,
+        /// Import enabled services as :service lines of the packages owning them
+        #[arg(long, conflicts_with = "explicit_only")]
+        services: bool,
+        /// With --services, write every suggestion without asking
+        #[arg(long, requires = "services")]
+        all: bool,
+    },
+    /// Show the config file and group dependency tree
+    Tree {
+        /// Emit Graphviz dot instead of text
+        #[arg(long)]
+        dot: bool,
+    },
+    /// Show which config file provided each field of a package
+    Explain {
+        /// Package to explain
+        package: String,
+    },
+    /// Show the environment variables owl exports
+    Env {
+        /// Print commands to reload the env file in the current shell: eval "$(owl env --reload)"
+        #[arg(long)]
+        reload: bool,
+    },
+    /// Run the package manager with owl's lock, retries and history: owl pm -- -Sc
+    Pm {
+        /// Start managing declared packages the run installs without asking
+        #[arg(long)]
+        adopt_managed: bool,
+        /// Arguments for the package manager, after `--`
+        #[arg(last = true, required = true)]
+        args: Vec<String>,
+    },
+    /// Show recorded apply runs
+    History {
+        /// Show packages that historically took longest to install
+        #[arg(long)]
+        slow: bool,
+    },
+    /// Show pending AUR updates for declared packages via the AUR RPC, without paru
+    Status {
+        /// Ask the AUR again even if the cached metadata is still fresh
+        #[arg(long)]
+        refresh: bool,
+        /// First show the pending changes, from a running `owl daemon`'s cached
+        /// plan or planned here when none answers
+        #[arg(long)]
+        via_daemon: bool,
+        /// Print only what apply would change, one `kind:name` per line
+        /// (install, upgrade, remove, dotfile, service, env)
+        #[arg(long, conflicts_with_all = ["refresh", "via_daemon"])]
+        names_only: bool,
+    },
+    /// Keep the plan cached and serve status, plan, apply and shutdown requests
+    /// on a unix socket in $XDG_RUNTIME_DIR
+    Daemon,
+    /// List what owl considers managed, untracked and hidden, and which of it is installed
+    State {
+        /// Print the lists as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the full apply plan as Markdown with a checkbox per change, for review
+    Plan {
+        /// Write the plan to FILE instead of stdout, for `owl apply --approved-review FILE`
+        #[arg(long, value_name = "FILE")]
+        review_file: Option<std::path::PathBuf>,
+    },
+    /// Check the config, dotfile sources and required tools and list what is wrong
+    Doctor {
+        /// Print the findings as JSON, each with severity, category, message and location
+        #[arg(long)]
+        json: bool,
+        /// Also check against the package manager and the AUR, e.g. that
+        /// `@arch-aur-prefix` names exist
+        #[arg(long)]
+        online: bool,
+    },
+    /// List dependencies nothing installed needs any more
+    Orphans {
+        /// Remove them once confirmed, except declared and untracked packages
+        #[arg(long)]
+        remove: bool,
+    },
+    /// List packages managed by owl with their install dates
+    List {
+        /// Only show packages installed since a date (YYYY-MM-DD, ISO 8601, or 7d/2w/1m)
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+    },
     /// Check configuration
     ConfigCheck {
         /// Specific config file to check
         file: Option<String>,
+        /// Show the effective directives of one package, including @defaults
+        #[arg(long, value_name = "NAME")]
+        package: Option<String>,
+        /// Print the canonical JSON the parser reads FILE as (for bug reports)
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["file", "package"])]
+        dump_canonical: Option<String>,
+        /// Do not warn about env vars like PATH, HOME or LD_PRELOAD replacing the login's values
+        #[arg(long)]
+        allow_dangerous_env: bool,
     },
     /// Show host configuration
     ConfigHost,
@@ -76,6 +429,31 @@ pub enum Commands {
     Clean {
         /// Specific filename to clean
         filename: Option<String>,
+        /// Prune managed entries for packages no longer installed
+        #[arg(long, conflicts_with = "filename")]
+        state: bool,
+        /// Re-hash dotfile backups and report corrupted or missing objects
+        #[arg(long, conflicts_with_all = ["filename", "state"])]
+        verify_backups: bool,
+        /// Clean the package cache and AUR build files, and remove orphaned dependencies
+        #[arg(long, conflicts_with_all = ["filename", "state", "verify_backups"])]
+        aur: bool,
+    },
+    /// Print a completion script: eval "$(owl completions bash)"
+    Completions { shell: super::complete::Shell },
+    /// Completion candidates for the words typed so far (called by completion scripts)
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        shell: super::complete::Shell,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
+    /// Run whitelisted system operations sent on stdin (started as root by apply)
+    #[command(name = "__privileged-helper", hide = true)]
+    PrivilegedHelper {
+        /// Write destinations under this directory instead of /
+        #[arg(long, value_name = "DIR", default_value = "/")]
+        root: std::path::PathBuf,
     },
     /// Alias for edit dots
     #[command(alias = "de")]
@@ -97,14 +475,47 @@ pub struct GlobalFlags {
     pub verbose: bool,
     pub dry_run: bool,
     pub non_interactive: bool,
+    /// Context lines for inline dotfile diffs, set when diffs were requested
+    pub diff_context: Option<usize>,
+    /// Decline confirmations left unanswered this long (`--confirm-timeout`)
+    pub confirm_timeout: Option<std::time::Duration>,
+    /// Run no commands named in the config (`--safe`)
+    pub safe: bool,
+}
+
+impl GlobalFlags {
+    /// How this run answers confirmations; `can_prompt` when a person may be asked
+    ///
+    /// Answers are recorded in the state directory's history, except in dry runs.
+    pub fn confirm_policy(&self, can_prompt: bool) -> crate::core::confirm::ConfirmPolicy {
+        let env = crate::internal::environment::get();
+        crate::core::confirm::ConfirmPolicy {
+            assume_answers: self.non_interactive,
+            can_prompt: can_prompt && env.stdin_is_tty,
+            timeout: self.confirm_timeout,
+            log_dir: env
+                .owl_dir()
+                .ok()
+                .filter(|_| !self.dry_run)
+                .map(|dir| dir.join(crate::internal::constants::STATE_DIR)),
+        }
+    }
 }
 
 impl From<&Cli> for GlobalFlags {
     fn from(cli: &Cli) -> Self {
+        let (preview, plan) = match &cli.command {
+            Some(Commands::Apply(args)) => (args.diff, args.plan_json),
+            _ => (false, false),
+        };
+        let with_diff = cli.dry_run_with_diff || preview;
         Self {
             verbose: cli.verbose,
-            dry_run: cli.dry_run,
+            dry_run: cli.dry_run || with_diff || plan,
             non_interactive: cli.non_interactive,
+            diff_context: with_diff.then_some(cli.diff_context),
+            confirm_timeout: cli.confirm_timeout,
+            safe: cli.safe,
         }
     }
 }
@@ -112,9 +523,12 @@ impl From<&Cli> for GlobalFlags {
 /// Execute the parsed command
 fn execute_command(cli: &Cli) {
     let flags = GlobalFlags::from(cli);
+    crate::core::config::parser::set_strict(cli.strict);
+    crate::core::config::format::set_format(cli.config_format);
+    crate::core::privilege::set_build_user(cli.build_user.clone());
 
     if flags.verbose {
-        println!("{}", color::dim("[verbose] args parsed"));
+        outln!("{}", color::dim("[verbose] args parsed"));
     }
 
     // Normalize command aliases to their canonical form
@@ -131,52 +545,265 @@ fn execute_command(cli: &Cli) {
     };
 
     match command {
-        Some(Commands::Apply) | None => apply::run(&flags),
+        Some(Commands::Apply(args)) => apply::run(&flags, &args),
+        None => apply::run(&flags, &ApplyArgs::default()),
         Some(Commands::Edit { target, argument }) => {
             let typ = match target {
                 EditTarget::Dots => constants::EDIT_TYPE_DOTS,
                 EditTarget::Config => constants::EDIT_TYPE_CONFIG,
             };
             if let Err(err) = edit::run(typ, &argument) {
-                eprintln!("{}", color::red(&err.to_string()));
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Dots {
+            action: None,
+            allow_outside_home,
+        }) => dots::run(&flags, allow_outside_home),
+        Some(Commands::Dots {
+            action: Some(DotsCommand::Audit { archive, json }),
+            ..
+        }) => {
+            if let Err(err) = dots::run_audit(archive, json, flags.dry_run) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Dots {
+            action: Some(DotsCommand::Explain { destination, json }),
+            allow_outside_home,
+        }) => {
+            if let Err(err) = dots::run_explain(&destination, json, allow_outside_home) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Dots {
+            action: Some(DotsCommand::CheckSources { strict }),
+            ..
+        })
+        | Some(Commands::CheckSource { strict }) => {
+            if let Err(err) = dots::run_check_sources(strict) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Services {
+            action: ServicesCommand::Adopt { name },
+        }) => {
+            if let Err(err) = services::adopt(&name) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Config {
+            action: ConfigCommand::Explain { file, json },
+        }) => {
+            if let Err(err) = config::explain(&file, json) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Config {
+            action: ConfigCommand::Trace { package, json },
+        }) => {
+            if let Err(err) = config::trace(&package, json) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Status {
+            refresh,
+            via_daemon,
+            names_only,
+        }) => {
+            let result = if names_only {
+                status::run_names_only(flags.safe)
+            } else if via_daemon {
+                daemon::print_status().and_then(|_| status::run(refresh))
+            } else {
+                status::run(refresh)
+            };
+            if let Err(err) = result {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Daemon) => {
+            if let Err(err) = daemon::run() {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::State { json }) => {
+            if let Err(err) = state::run(json) {
+                errln!("{}", color::red(&err.to_string()));
                 std::process::exit(1);
             }
         }
-        Some(Commands::Dots) => dots::run(&flags),
-        Some(Commands::Add { items, search }) => add::run(&items, search),
+        Some(Commands::Plan { review_file }) => {
+            if let Err(err) = plan::run(review_file.as_deref()) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Doctor { json, online }) => {
+            if let Err(err) = doctor::run(json, online) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Add {
+            items,
+            search,
+            only_new,
+            into,
+        }) => add::run(&items, search, only_new, into.as_deref()),
         Some(Commands::Adopt { items, all }) => adopt::run(&items, all),
         Some(Commands::Find { query }) => find::run(&query),
-        Some(Commands::ConfigCheck { file }) => {
-            if let Some(f) = file {
-                if let Err(err) = crate::core::config::validator::run_configcheck(&f) {
-                    eprintln!("{}", color::red(&err.to_string()));
+        Some(Commands::ImportPacman {
+            explicit_only,
+            into,
+            services,
+            all,
+        }) => {
+            let result = if services {
+                import::run_services(
+                    into.as_deref(),
+                    all,
+                    flags.dry_run,
+                    &flags.confirm_policy(true),
+                )
+            } else {
+                import::run(explicit_only, into.as_deref(), flags.dry_run)
+            };
+            if let Err(err) = result {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Tree { dot }) => {
+            if let Err(err) = tree::run(dot) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Explain { package }) => {
+            if let Err(err) = explain::run(&package) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Env { reload }) => {
+            if let Err(err) = env::run(reload) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Pm {
+            adopt_managed,
+            args,
+        }) => {
+            if let Err(err) = pm::run(&flags, &args, adopt_managed) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::History { slow }) => {
+            if let Err(err) = history::run(slow) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Orphans { remove }) => {
+            if let Err(err) = crate::commands::orphans::run(&flags, remove) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::List { since }) => {
+            if let Err(err) = list::run(since.as_deref()) {
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::ConfigCheck {
+            file,
+            package,
+            dump_canonical,
+            allow_dangerous_env,
+        }) => {
+            if let Some(path) = dump_canonical {
+                if let Err(err) = crate::core::config::validator::run_dump_canonical(&path) {
+                    errln!("{}", color::red(&err.to_string()));
                     std::process::exit(1);
                 }
-            } else if let Err(err) = crate::core::config::validator::run_full_configcheck() {
-                eprintln!("{}", color::red(&err.to_string()));
+            } else if let Some(name) = package {
+                if let Err(err) =
+                    crate::core::config::validator::run_package_check(file.as_deref(), &name)
+                {
+                    errln!("{}", color::red(&err.to_string()));
+                    std::process::exit(1);
+                }
+            } else if let Some(f) = file {
+                if let Err(err) =
+                    crate::core::config::validator::run_configcheck(&f, allow_dangerous_env)
+                {
+                    errln!("{}", color::red(&err.to_string()));
+                    std::process::exit(1);
+                }
+            } else if let Err(err) =
+                crate::core::config::validator::run_full_configcheck(allow_dangerous_env)
+            {
+                errln!("{}", color::red(&err.to_string()));
                 std::process::exit(1);
             }
         }
         Some(Commands::ConfigHost) => {
             if let Err(err) = crate::core::config::validator::run_confighost() {
-                eprintln!("{}", color::red(&err.to_string()));
+                errln!("{}", color::red(&err.to_string()));
                 std::process::exit(1);
             }
         }
-        Some(Commands::Clean { filename }) => {
+        Some(Commands::Clean {
+            filename,
+            state,
+            verify_backups,
+            aur,
+        }) => {
             let result = match filename {
+                _ if aur => crate::commands::clean::handle_clean_aur(&flags),
+                _ if state => crate::commands::clean::handle_clean_state(),
+                _ if verify_backups => crate::commands::clean::handle_verify_backups(),
                 Some(fname) => {
                     let result = crate::commands::clean::handle_clean(&fname);
                     if result.is_ok() {
-                        println!("[{}]", color::blue("clean"));
-                        println!("  {} {}", color::green("✓"), color::dim(&fname));
+                        outln!("[{}]", color::blue("clean"));
+                        outln!("  {} {}", color::green("✓"), color::dim(&fname));
                     }
                     result
                 }
                 None => crate::commands::clean::handle_clean_all(),
             };
             if let Err(err) = result {
-                eprintln!("{}", color::red(&err.to_string()));
+                errln!("{}", color::red(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Completions { shell }) => {
+            out!("{}", super::complete::script(shell));
+        }
+        Some(Commands::Complete { shell, words }) => super::complete::run(shell, &words),
+        Some(Commands::PrivilegedHelper { root }) => {
+            let stdin = std::io::stdin();
+            if let Err(err) = crate::core::privileged_helper::serve(
+                stdin.lock(),
+                std::io::stdout(),
+                &root,
+                "systemctl",
+            ) {
+                errln!("{}", color::red(&err.to_string()));
                 std::process::exit(1);
             }
         }
@@ -190,7 +817,7 @@ pub fn parse_and_execute() {
     let cli = match Cli::try_parse() {
         Ok(cli) => cli,
         Err(err) => {
-            eprintln!("{}", color::red(&err.to_string()));
+            errln!("{}", color::red(&err.to_string()));
             std::process::exit(1);
         }
     };
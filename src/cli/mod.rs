@@ -1,2 +1,4 @@
+pub mod complete;
 pub mod handler;
+pub mod render;
 pub mod ui;
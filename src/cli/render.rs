@@ -0,0 +1,396 @@
+//! Terminal rendering of core progress events, or JSON Lines for tools
+
+use crate::core::absent::AbsentStatus;
+use crate::core::dotfiles::{DotfileAction, DotfileStatus};
+use crate::core::events::{EventPhase, EventSink, OwlEvent};
+use crate::core::reconcile::StateChange;
+use crate::internal::color;
+
+/// Prints events the way the CLI always has
+#[derive(Debug, Default)]
+pub struct CliRenderer {
+    /// Inline a diff under each dotfile update (`--dry-run-with-diff`)
+    pub diff_context: Option<usize>,
+    /// Explain who last changed updated destinations (`-v`); conflicts always are
+    pub forensics: bool,
+}
+
+impl CliRenderer {
+    pub fn new(diff_context: Option<usize>) -> Self {
+        Self {
+            diff_context,
+            forensics: false,
+        }
+    }
+
+    pub fn with_forensics(mut self, forensics: bool) -> Self {
+        self.forensics = forensics;
+        self
+    }
+
+    /// The pending change under a dotfile action, when diffs are on
+    fn print_diff(&self, action: &DotfileAction) {
+        let Some(context) = self.diff_context else {
+            return;
+        };
+        match crate::core::dotfiles::diff_action(action, context) {
+            Ok(diff) => out!("{}", crate::core::diff::colorize_diff(&diff, "    ")),
+            Err(e) => errln!(
+                "{}",
+                color::red(&format!(
+                    "Failed to diff {}: {}",
+                    action.mapping.destination, e
+                ))
+            ),
+        }
+    }
+}
+
+/// Writes each event as one JSON object per line (`--events-json`)
+///
+/// See [`OwlEvent::to_json`] for the schema.
+#[derive(Debug)]
+pub struct JsonLinesRenderer<W: std::io::Write> {
+    out: W,
+}
+
+impl<W: std::io::Write> JsonLinesRenderer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: std::io::Write> EventSink for JsonLinesRenderer<W> {
+    fn emit(&mut self, event: OwlEvent) {
+        // A closed pipe means nobody is listening; the run itself carries on
+        let _ = writeln!(self.out, "{}", event.to_json()).and_then(|_| self.out.flush());
+    }
+}
+
+/// The event sink for an apply run: JSON Lines on stderr, or the terminal renderer
+pub fn apply_sink(
+    flags: &crate::cli::handler::GlobalFlags,
+    events_json: bool,
+) -> Box<dyn EventSink> {
+    if events_json {
+        Box::new(JsonLinesRenderer::new(std::io::stderr()))
+    } else {
+        Box::new(CliRenderer::new(flags.diff_context).with_forensics(flags.verbose))
+    }
+}
+
+/// Indented forensics lines under a dotfile action
+fn print_forensics(action: &DotfileAction) {
+    if let Some(found) = crate::core::forensics::for_action(action) {
+        for line in found.lines() {
+            outln!("    {}", color::dim(&line));
+        }
+    }
+}
+
+impl EventSink for CliRenderer {
+    fn emit(&mut self, event: OwlEvent) {
+        match event {
+            OwlEvent::PhaseStarted(EventPhase::Dotfiles) => {
+                outln!();
+                outln!("[{}]", color::green("config"));
+            }
+            OwlEvent::PhaseStarted(EventPhase::System) => {
+                outln!();
+                outln!("[{}]", color::red("system"));
+            }
+            // The package section is printed by the package manager wrappers
+            OwlEvent::PhaseStarted(EventPhase::Packages) | OwlEvent::PhaseFinished(_) => {}
+            OwlEvent::DotfilesEmpty => {
+                outln!("  {} No dotfiles configured", color::blue("info:"));
+            }
+            OwlEvent::DotfilesUpToDate { count } => {
+                outln!("  {} Up to date: {} dotfiles", color::green("➔"), count);
+            }
+            OwlEvent::DotfileActionCompleted { action } => {
+                let verb = match &action.status {
+                    DotfileStatus::Create => "create",
+                    DotfileStatus::Update => "update",
+                    DotfileStatus::Merge => "merge",
+                    DotfileStatus::UpToDate => return,
+                    DotfileStatus::Conflict(_) | DotfileStatus::MergeConflict { .. } => {
+                        outln!(
+                            "  {} conflict {}: {}",
+                            color::yellow("⚠"),
+                            action.mapping.destination,
+                            action.status.conflict_reason().unwrap_or_default()
+                        );
+                        print_forensics(&action);
+                        if matches!(action.status, DotfileStatus::MergeConflict { .. }) {
+                            self.print_diff(&action);
+                        }
+                        return;
+                    }
+                };
+                outln!(
+                    "  {} {} {} -> {}",
+                    color::green("➔"),
+                    verb,
+                    action.mapping.source,
+                    action.mapping.destination
+                );
+                if action.status == DotfileStatus::Update && self.forensics {
+                    print_forensics(&action);
+                }
+                if matches!(action.status, DotfileStatus::Update | DotfileStatus::Merge) {
+                    self.print_diff(&action);
+                }
+            }
+            OwlEvent::DotfilesFinished {
+                up_to_date,
+                dry_run,
+            } => {
+                if !dry_run {
+                    outln!(
+                        "  {} Up to date: {} dotfiles",
+                        color::green("➔"),
+                        up_to_date
+                    );
+                }
+            }
+            OwlEvent::DotfileSourceIssues {
+                mapping,
+                issues,
+                strict,
+            } => {
+                let icon = if strict {
+                    color::red("✗")
+                } else {
+                    color::yellow("⚠")
+                };
+                outln!("  {} {} -> {}:", icon, mapping.source, mapping.destination);
+                for issue in &issues {
+                    outln!("    {}", issue);
+                }
+            }
+            OwlEvent::SharedHomeConflict {
+                host,
+                applied_at,
+                destinations,
+            } => {
+                let age = crate::internal::time::now_secs().saturating_sub(applied_at);
+                warnln!(
+                    "  {} {} applied into this shared home {} ago with different content for {} \
+                     dotfile(s) this run overwrites:",
+                    color::yellow("⚠ shared home:"),
+                    color::yellow(&host),
+                    crate::internal::time::format_age(age as i64),
+                    destinations.len()
+                );
+                let env = crate::internal::environment::get();
+                for destination in &destinations {
+                    warnln!("    {}", env.display_path(destination));
+                }
+                warnln!(
+                    "  {} the hosts will keep undoing each other; give each variant its own \
+                     destination",
+                    color::blue("info:")
+                );
+            }
+            OwlEvent::DotfilesOrphaned { destinations } => {
+                outln!(
+                    "  {} {} dotfiles deployed earlier are no longer mapped (left in place):",
+                    color::yellow("⚠"),
+                    destinations.len()
+                );
+                let env = crate::internal::environment::get();
+                for destination in &destinations {
+                    outln!("    {}", env.display_path(destination));
+                }
+                outln!(
+                    "  {} remove them by hand, or map them again to keep them managed",
+                    color::blue("info:")
+                );
+            }
+            OwlEvent::AbsentChecked { entry } => {
+                let env = crate::internal::environment::get();
+                let paths: Vec<String> = entry.paths.iter().map(|p| env.display_path(p)).collect();
+                match entry.status {
+                    AbsentStatus::AlreadyAbsent => {}
+                    AbsentStatus::WouldRemove | AbsentStatus::Removed => outln!(
+                        "  {} remove {} (absent)",
+                        color::green("➔"),
+                        paths.join(", ")
+                    ),
+                    AbsentStatus::Refused => outln!(
+                        "  {} absent {} refused: {}",
+                        color::yellow("⚠"),
+                        entry.pattern,
+                        entry.reason.as_deref().unwrap_or_default()
+                    ),
+                }
+            }
+            OwlEvent::StateReconciled { changes } => {
+                for change in &changes {
+                    match change {
+                        StateChange::Dropped { package } => outln!(
+                            "  {} {} is no longer installed or declared; stopped managing it",
+                            color::blue("info:"),
+                            package
+                        ),
+                        StateChange::Renamed { from, to, declared } => {
+                            outln!(
+                                "  {} {} was replaced by {}; now managing {}",
+                                color::blue("info:"),
+                                from,
+                                color::yellow(to),
+                                to
+                            );
+                            if *declared {
+                                outln!(
+                                    "    {} is still declared; update the config to {}",
+                                    from,
+                                    to
+                                );
+                            }
+                        }
+                    }
+                }
+                outln!(
+                    "  {} {}",
+                    color::green("➔"),
+                    crate::core::reconcile::summary(&changes)
+                );
+                outln!();
+            }
+            // Package managers print their own progress
+            OwlEvent::PackageInstallStarted { .. } | OwlEvent::PackageInstallFinished { .. } => {}
+            OwlEvent::ServicesPlanned { services } => {
+                outln!("  {} Plan:", color::blue("info:"));
+                for service in &services {
+                    outln!(
+                        "    ✓ Would manage {} (system) [enable, start]",
+                        color::yellow(service)
+                    );
+                }
+                outln!(
+                    "  {} Planned {} service(s)",
+                    color::blue("info:"),
+                    services.len()
+                );
+                outln!();
+            }
+            OwlEvent::ServiceChangesPlanned { changes } => {
+                outln!("  {} Service changes:", color::blue("info:"));
+                for change in &changes {
+                    let actions: Vec<&str> = [("enable", change.enable), ("start", change.start)]
+                        .into_iter()
+                        .filter_map(|(action, needed)| needed.then_some(action))
+                        .collect();
+                    match &change.error {
+                        Some(error) => outln!(
+                            "    {} {}: could not read its state: {}",
+                            color::red("?"),
+                            color::yellow(&change.service),
+                            error
+                        ),
+                        None if actions.is_empty() => outln!(
+                            "    = {} {}",
+                            change.service,
+                            color::dim("(enabled and running)")
+                        ),
+                        None => outln!(
+                            "    {} {}: {}",
+                            color::yellow("~"),
+                            color::yellow(&change.service),
+                            actions.join(", ")
+                        ),
+                    }
+                }
+                let changing = changes.iter().filter(|c| c.enable || c.start).count();
+                outln!(
+                    "  {} {} of {} service(s) would change",
+                    color::blue("info:"),
+                    changing,
+                    changes.len()
+                );
+                outln!();
+            }
+            OwlEvent::ServicesConfigured { managed, result } => {
+                outln!("  {} Services configured", color::green("⸎"));
+                outln!();
+                outln!("  {} Managed {} service(s)", color::green("⸎"), managed);
+                if !result.enabled_services.is_empty() {
+                    outln!("    Enabled: {}", result.enabled_services.join(", "));
+                }
+                if !result.started_services.is_empty() {
+                    outln!("    Started: {}", result.started_services.join(", "));
+                }
+                if !result.preexisting_services.is_empty() {
+                    outln!(
+                        "    Already enabled (not owl-managed): {}",
+                        result.preexisting_services.join(", ")
+                    );
+                }
+                if !result.failed_services.is_empty() {
+                    outln!(
+                        "    {} Failed: {}",
+                        color::red("✗"),
+                        result.failed_services.join(", ")
+                    );
+                }
+                outln!();
+            }
+            OwlEvent::ServicesVerified { preexisting } => {
+                outln!("  {} Service state verified", color::green("⸎"));
+                if !preexisting.is_empty() {
+                    outln!(
+                        "    Already enabled (not owl-managed): {}",
+                        preexisting.join(", ")
+                    );
+                }
+            }
+            OwlEvent::ServicesDisabled { services } => {
+                outln!(
+                    "  {} Disabled {} service(s): {}",
+                    color::green("⸎"),
+                    services.len(),
+                    services.join(", ")
+                );
+            }
+            OwlEvent::EnvPlanned { vars } => {
+                outln!("  {} Plan:", color::blue("info:"));
+                for var in &vars {
+                    let shells = var.shell.map_or("shells", |shell| shell.as_str());
+                    outln!(
+                        "    ✓ Would export {}={} ({})",
+                        color::yellow(&var.key),
+                        color::green(&var.value),
+                        shells
+                    );
+                }
+            }
+            OwlEvent::EnvDiff { diff } => {
+                if diff.is_empty() {
+                    outln!("  {} Env files unchanged", color::blue("info:"));
+                } else {
+                    out!("{}", crate::core::diff::colorize_diff(&diff, "    "));
+                }
+            }
+            OwlEvent::EnvExported { changed: true } => {
+                outln!("  {} Environment exported (bash, fish)", color::green("⸎"));
+            }
+            OwlEvent::EnvExported { changed: false } => {
+                outln!("  {} Environment unchanged (bash, fish)", color::green("⸎"));
+            }
+            OwlEvent::PackagesDeferred { reason } => {
+                warnln!(
+                    "  {} Package changes deferred to the next run: {}",
+                    color::yellow("deferred:"),
+                    reason
+                );
+            }
+            OwlEvent::Warning(message) => {
+                warnln!("  {} {}", color::yellow("warning:"), message);
+            }
+            OwlEvent::Error(message) => {
+                errln!("{}", color::red(&message));
+            }
+        }
+    }
+}
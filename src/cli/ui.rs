@@ -1,29 +1,7 @@
+use crate::core::confirm::{self, AnsweredBy, ConfirmKind, ConfirmPolicy};
 use crate::internal::color;
-use std::io::Write;
-
-fn confirm_operation(
-    packages: &[String],
-    header_icon: &str,
-    header_text: &str,
-    detail_label: &str,
-    prompt: &str,
-) -> bool {
-    println!("\n  {} {}", color::red(header_icon), header_text);
-    println!(
-        "  {} {}: {}",
-        color::yellow(&packages.len().to_string()),
-        detail_label,
-        packages.join(", ")
-    );
-    print!("  -> {} ", prompt);
-    std::io::stdout().flush().ok();
-
-    let mut input = String::new();
-    match std::io::stdin().read_line(&mut input) {
-        Ok(_) => matches!(input.trim().to_lowercase().as_str(), "y" | "yes"),
-        Err(_) => false,
-    }
-}
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
 
 /// Generate the apply command output display with uninstalled package count
 pub fn generate_apply_output_with_install(
@@ -33,71 +11,130 @@ pub fn generate_apply_output_with_install(
     service_count: usize,
     remove_count: usize,
     managed_count: usize,
+    update_note: Option<&str>,
 ) {
-    let host_name =
-        crate::internal::constants::get_host_name().unwrap_or_else(|_| "unknown".to_string());
-    println!("[{}]", color::blue("info"));
-    println!("  host: {}", color::bold(&host_name));
-    println!(
+    let host_name = crate::internal::environment::get()
+        .hostname()
+        .unwrap_or("unknown");
+    outln!("[{}]", color::blue("info"));
+    outln!("  host: {}", color::bold(host_name));
+    outln!(
         "  packages: {} ({}, {}, {})",
         color::bold(&(package_count + uninstalled_count).to_string()),
         color::green(&format!("install {}", uninstalled_count)),
         color::yellow(&format!("upgrade {}", package_count)),
         color::red(&format!("remove {}", remove_count))
     );
-    println!(
+    outln!(
         "  managed pkgs: {}",
         color::bold(&managed_count.to_string())
     );
     if service_count > 0 {
-        println!("  services: {}", color::bold(&service_count.to_string()));
+        outln!("  services: {}", color::bold(&service_count.to_string()));
+    }
+    if let Some(note) = update_note {
+        outln!("  updates: {}", color::yellow(note));
     }
-    println!();
-    println!("[{}]", color::yellow("packages"));
+    outln!();
+    outln!("[{}]", color::yellow("packages"));
     if package_count > 0 {
-        println!(
+        outln!(
             "  {} packages can be upgraded",
             color::yellow(&package_count.to_string())
         );
     } else {
-        println!(
+        outln!(
             "  {} {}",
             crate::internal::color::green("➔"),
             color::dim("no packages to upgrade")
         );
     }
     if uninstalled_count > 0 {
-        println!(
+        outln!(
             "  {} packages can be installed",
             color::green(&uninstalled_count.to_string())
         );
     }
 }
 
-/// Prompt user for AUR package confirmation
-pub fn confirm_aur_operation(packages: &[String], operation: &str) -> bool {
-    let verb = match operation {
-        "installing" => "install",
-        "updating" => "update",
-        "installing/updating" => "install and/or update",
-        _ => operation.trim_end_matches("ing"),
+/// Ask before `kind` is done to `items`, answering as `policy` says
+///
+/// The prompt is shown even when the answer is automatic, followed by that
+/// answer and why. The answer is recorded in the history of `policy.log_dir`.
+pub fn confirm(kind: ConfirmKind, items: &[String], policy: &ConfirmPolicy) -> bool {
+    outln!("\n  {} {}", color::red("‼"), kind.title());
+    outln!(
+        "  {} {}: {}",
+        color::yellow(&items.len().to_string()),
+        kind.label(),
+        confirm::summarize(items, confirm::SHOWN_ITEMS)
+    );
+    outln!("  {}", kind.consequence());
+
+    let record = confirm::decide(
+        kind,
+        items,
+        policy,
+        crate::internal::time::now_secs(),
+        || {
+            out!("  -> {} (y/N): ", kind.question());
+            let answer = read_answer(policy.timeout);
+            if answer.is_none() {
+                outln!();
+            }
+            answer
+        },
+    );
+    if record.answered_by != AnsweredBy::User {
+        outln!(
+            "  -> {} {} ({})",
+            kind.question(),
+            if record.answer { "yes" } else { "no" },
+            record.answered_by.reason()
+        );
+    }
+
+    let answer = record.answer;
+    if let Some(dir) = &policy.log_dir
+        && let Err(e) = confirm::record(dir, record)
+    {
+        warnln!(
+            "{}",
+            color::yellow(&format!("Failed to record the confirmation: {}", e))
+        );
+    }
+    answer
+}
+
+/// A line from standard input as yes or no; `None` when `timeout` passes first
+fn read_answer(timeout: Option<std::time::Duration>) -> Option<bool> {
+    let lines = stdin_lines().lock().unwrap_or_else(|e| e.into_inner());
+    // Lines typed after an earlier prompt timed out do not answer this one
+    while lines.try_recv().is_ok() {}
+    let line = match timeout {
+        Some(timeout) => match lines.recv_timeout(timeout) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => String::new(),
+        },
+        None => lines.recv().unwrap_or_default(),
     };
-    confirm_operation(
-        packages,
-        "‼",
-        "AUR packages require confirmation",
-        "AUR packages found",
-        &format!("Are you sure you wanna {} AUR packages? (y/N):", verb),
-    )
+    Some(matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
 }
 
-/// Prompt user for removal confirmation
-pub fn confirm_remove_operation(packages: &[String]) -> bool {
-    confirm_operation(
-        packages,
-        "‼",
-        "Package removals require confirmation",
-        "packages to remove",
-        "Are you sure you want to remove these packages? (y/N):",
-    )
+/// Lines of standard input, read on a thread so a prompt can stop waiting
+fn stdin_lines() -> &'static Mutex<Receiver<String>> {
+    static LINES: OnceLock<Mutex<Receiver<String>>> = OnceLock::new();
+    LINES.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut line = String::new();
+            while std::io::stdin().read_line(&mut line).is_ok_and(|n| n > 0) {
+                if tx.send(std::mem::take(&mut line)).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(rx)
+    })
 }
@@ -5,16 +5,20 @@ use anyhow::anyhow;
 /// # Arguments
 /// * `items` - List of package names to search for and add
 /// * `search_mode` - Whether to search for packages first (always true now)
-pub fn run(items: &[String], _search_mode: bool) {
-    run_search_mode(items);
+/// * `only_new` - Skip packages the file already declares instead of failing
+/// * `into` - Parent package whose block gets a `:requires` line instead
+pub fn run(items: &[String], _search_mode: bool, only_new: bool, into: Option<&str>) {
+    run_search_mode(items, only_new, into);
 }
 
 /// Search and select mode - add to config instead of installing
-fn run_search_mode(terms: &[String]) {
+///
+/// Selecting several packages adds them in one go and implies `only_new`.
+fn run_search_mode(terms: &[String], only_new: bool, into: Option<&str>) {
     match crate::core::package::search_packages(terms) {
         Ok(results) => {
             if results.is_empty() {
-                println!(
+                outln!(
                     "{}",
                     crate::internal::color::yellow("No packages found matching the search terms")
                 );
@@ -25,13 +29,18 @@ fn run_search_mode(terms: &[String]) {
             let selection = prompt_package_selection(&results);
 
             match selection {
-                Some(package_name) => {
-                    if let Err(err) = add_package_to_config(&package_name) {
+                Some(packages) => {
+                    let only_new = only_new || packages.len() > 1;
+                    let result = match into {
+                        Some(parent) => add_requires_to_config(&packages, parent, only_new),
+                        None => add_packages_to_config(&packages, only_new),
+                    };
+                    if let Err(err) = result {
                         crate::error::exit_with_error(anyhow::anyhow!(err));
                     }
                 }
                 None => {
-                    println!("{}", crate::internal::color::yellow("No package selected"));
+                    outln!("{}", crate::internal::color::yellow("No package selected"));
                 }
             }
         }
@@ -45,7 +54,7 @@ fn run_search_mode(terms: &[String]) {
 // use crate::domain::package; // no direct uses
 use crate::core::pm::{PackageSource, SearchResult};
 fn display_search_results(results: &[SearchResult]) {
-    println!(
+    outln!(
         "\n{} {} package(s):\n",
         crate::internal::color::bold("Found"),
         results.len()
@@ -78,23 +87,22 @@ fn display_search_results(results: &[SearchResult]) {
             String::new()
         };
 
-        println!("{}{} {}{} {}{}", num_str, name, version, tag, status, desc);
+        outln!("{}{} {}{} {}{}", num_str, name, version, tag, status, desc);
     }
-    println!();
+    outln!();
 }
 
-/// Prompt user to select a package from search results
-fn prompt_package_selection(results: &[SearchResult]) -> Option<String> {
+/// Prompt user to select packages from search results
+fn prompt_package_selection(results: &[SearchResult]) -> Option<Vec<String>> {
     if results.is_empty() {
         return None;
     }
 
     loop {
-        print!(
-            "Select package (0-{}, or 'c' to cancel): ",
+        out!(
+            "Select packages (0-{}, several separated by spaces, or 'c' to cancel): ",
             results.len() - 1
         );
-        std::io::Write::flush(&mut std::io::stdout()).ok()?;
 
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).ok()?;
@@ -104,13 +112,17 @@ fn prompt_package_selection(results: &[SearchResult]) -> Option<String> {
             return None;
         }
 
-        match input.parse::<usize>() {
-            Ok(num) if num < results.len() => {
-                let index = results.len() - 1 - num;
-                return Some(results[index].name.clone());
+        match parse_selection(input, results.len()) {
+            Some(numbers) => {
+                return Some(
+                    numbers
+                        .into_iter()
+                        .map(|num| results[results.len() - 1 - num].name.clone())
+                        .collect(),
+                );
             }
-            _ => {
-                println!(
+            None => {
+                outln!(
                     "{}",
                     crate::internal::color::red("Invalid selection. Please try again.")
                 );
@@ -119,36 +131,43 @@ fn prompt_package_selection(results: &[SearchResult]) -> Option<String> {
     }
 }
 
+/// The numbers in `input` (`3`, `0 2 5` or `0,2`), each below `count`, in
+/// the order given without repeats; `None` when any is not a valid choice
+fn parse_selection(input: &str, count: usize) -> Option<Vec<usize>> {
+    let mut numbers = Vec::new();
+    for word in input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+    {
+        let num = word.parse::<usize>().ok().filter(|num| *num < count)?;
+        if !numbers.contains(&num) {
+            numbers.push(num);
+        }
+    }
+    (!numbers.is_empty()).then_some(numbers)
+}
+
 /// Format a number in brackets like [1], [2], etc.
 fn number_brackets(num: i32) -> String {
     format!("[{}]", num)
 }
 
-/// Add a package to the appropriate configuration file
-fn add_package_to_config(package_name: &str) -> anyhow::Result<()> {
+/// Add packages to the appropriate configuration file
+fn add_packages_to_config(packages: &[String], only_new: bool) -> anyhow::Result<()> {
     let mut config_files = get_relevant_config_files()?;
 
     if config_files.is_empty() {
         // Use main config if no relevant files found
         let main_config = get_main_config_path()?;
-        add_package_to_file(package_name, &main_config)?;
-        println!(
-            "{}",
-            crate::internal::color::success(&format!(
-                "Added '{}' to {}",
-                package_name, main_config
-            ))
-        );
+        let added = add_packages_to_file(packages, &main_config, only_new)?;
+        report_added(&added, &main_config);
         return Ok(());
     }
 
     if config_files.len() == 1 {
         let file_path = &config_files[0];
-        add_package_to_file(package_name, file_path)?;
-        println!(
-            "{}",
-            crate::internal::color::success(&format!("Added '{}' to {}", package_name, file_path))
-        );
+        let added = add_packages_to_file(packages, file_path, only_new)?;
+        report_added(&added, file_path);
         return Ok(());
     }
 
@@ -156,7 +175,7 @@ fn add_package_to_config(package_name: &str) -> anyhow::Result<()> {
     config_files.reverse();
 
     // Multiple files - prompt for selection
-    println!(
+    outln!(
         "\n{} {} config file(s):\n",
         crate::internal::color::bold("Found"),
         config_files.len()
@@ -164,31 +183,25 @@ fn add_package_to_config(package_name: &str) -> anyhow::Result<()> {
 
     for (i, file) in config_files.iter().enumerate() {
         let num_str = number_brackets((config_files.len() - 1 - i) as i32);
-        let friendly = file.replace(&std::env::var("HOME").unwrap_or_default(), "~");
-        println!(
+        let friendly = crate::internal::environment::get().display_path(file);
+        outln!(
             "{} {}",
             num_str,
             crate::internal::color::highlight(&friendly)
         );
     }
-    println!();
+    outln!();
 
     let selection = prompt_file_selection(config_files.len());
     match selection {
         Some(index) => {
             let file_path = &config_files[index];
-            add_package_to_file(package_name, file_path)?;
-            println!(
-                "{}",
-                crate::internal::color::success(&format!(
-                    "Added '{}' to {}",
-                    package_name, file_path
-                ))
-            );
+            let added = add_packages_to_file(packages, file_path, only_new)?;
+            report_added(&added, file_path);
             Ok(())
         }
         None => {
-            println!(
+            outln!(
                 "{}",
                 crate::internal::color::yellow("No config file selected")
             );
@@ -204,16 +217,45 @@ fn get_relevant_config_files() -> anyhow::Result<Vec<String>> {
 
 /// Get the main config file path
 fn get_main_config_path() -> anyhow::Result<String> {
-    use std::path::PathBuf;
-    let home = std::env::var("HOME").map_err(|_| anyhow!("HOME environment variable not set"))?;
-    let path = PathBuf::from(home)
-        .join(crate::internal::constants::OWL_DIR)
+    let path = crate::internal::environment::get()
+        .owl_dir()?
         .join(crate::internal::constants::MAIN_CONFIG_FILE);
     Ok(path.to_string_lossy().into_owned())
 }
 
-/// Add a package to a config file
-fn add_package_to_file(package_name: &str, file_path: &str) -> anyhow::Result<()> {
+/// Packages written to a config file, and those it already declared
+#[derive(Debug, Default, PartialEq)]
+struct Added {
+    added: Vec<String>,
+    skipped: Vec<String>,
+}
+
+fn report_added(result: &Added, file_path: &str) {
+    let message = match result.added.as_slice() {
+        [] => format!("Nothing added to {}", file_path),
+        [name] => format!("Added '{}' to {}", name, file_path),
+        names => format!("Added {} packages to {}", names.len(), file_path),
+    };
+    outln!("{}", crate::internal::color::success(&message));
+    if !result.skipped.is_empty() {
+        outln!(
+            "  {} {} already present, skipped: {}",
+            crate::internal::color::blue("info:"),
+            result.skipped.len(),
+            result.skipped.join(", ")
+        );
+    }
+}
+
+/// Add packages to a config file in one write
+///
+/// A package the file already declares is an error, and nothing is written,
+/// unless `only_new` is set: then it is skipped and listed in the result.
+fn add_packages_to_file(
+    packages: &[String],
+    file_path: &str,
+    only_new: bool,
+) -> anyhow::Result<Added> {
     use std::fs;
 
     // Read existing content
@@ -223,44 +265,127 @@ fn add_package_to_file(package_name: &str, file_path: &str) -> anyhow::Result<()
         String::new()
     };
 
-    // Check if package already exists
-    if content.lines().any(|line| line.trim() == package_name) {
+    let mut result = Added::default();
+    for package_name in packages {
+        crate::core::names::validate_package_name(package_name)?;
+        if result.added.contains(package_name) || result.skipped.contains(package_name) {
+            continue;
+        }
+        if !declares(&content, package_name) {
+            result.added.push(package_name.clone());
+        } else if only_new {
+            result.skipped.push(package_name.clone());
+        } else {
+            return Err(anyhow!(
+                "Package '{}' already exists in {}",
+                package_name,
+                file_path
+            ));
+        }
+    }
+    if result.added.is_empty() {
+        return Ok(result);
+    }
+
+    // Add packages to the owl-managed block (or an existing @packages section)
+    let new_content = crate::core::config::managed::insert_packages(&content, &result.added);
+    fs::write(file_path, new_content)
+        .map_err(|e| anyhow!("Failed to write to config file: {}", e))?;
+
+    Ok(result)
+}
+
+/// Add packages as `:requires` of `parent`, in the highest-precedence file declaring it
+fn add_requires_to_config(packages: &[String], parent: &str, only_new: bool) -> anyhow::Result<()> {
+    let owl_dir = crate::internal::environment::get().owl_dir()?;
+    let config = crate::core::config::Config::load_all_relevant_config_files_from_path(&owl_dir)?;
+    let declaration = config
+        .declarations
+        .get(parent)
+        .and_then(|declarations| declarations.first())
+        .ok_or_else(|| {
+            anyhow!(
+                "Package '{}' is not declared in any loaded config file",
+                parent
+            )
+        })?;
+    let file_path = owl_dir
+        .join(&declaration.file)
+        .to_string_lossy()
+        .into_owned();
+    let added = add_requires_to_file(packages, parent, &file_path, only_new)?;
+    report_added(&added, &file_path);
+    Ok(())
+}
+
+/// Add packages to `parent`'s block in a config file as one `:requires` line
+///
+/// Names the file already declares, by `:requires` or otherwise, are handled
+/// as in `add_packages_to_file`.
+fn add_requires_to_file(
+    packages: &[String],
+    parent: &str,
+    file_path: &str,
+    only_new: bool,
+) -> anyhow::Result<Added> {
+    if crate::core::config::format::ConfigFormat::of_file(std::path::Path::new(file_path))
+        != crate::core::config::format::ConfigFormat::Owl
+    {
         return Err(anyhow!(
-            "Package '{}' already exists in {}",
-            package_name,
-            file_path
+            "{} is not an .owl file; add the packages to requires of '{}' by hand",
+            file_path,
+            parent
         ));
     }
-
-    // Add package to @packages section or create one
-    let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-    let mut added = false;
-
-    // Look for @packages section
-    for i in 0..lines.len() {
-        if lines[i].trim() == "@packages" || lines[i].trim() == "@pkgs" {
-            // Add after the @packages line
-            lines.insert(i + 1, package_name.to_string());
-            added = true;
-            break;
+    let content = std::fs::read_to_string(file_path)
+        .map_err(|e| anyhow!("Failed to read config file: {}", e))?;
+    let declared = crate::core::config::Config::parse(&content)?.packages;
+
+    let mut result = Added::default();
+    for package_name in packages {
+        crate::core::names::validate_package_name(package_name)?;
+        if result.added.contains(package_name) || result.skipped.contains(package_name) {
+            continue;
         }
-    }
-
-    // If no @packages section, add one at the end
-    if !added {
-        if !lines.is_empty() && !lines.last().unwrap().is_empty() {
-            lines.push(String::new()); // Add blank line
+        if package_name == parent {
+            return Err(anyhow!("Package '{}' cannot require itself", parent));
+        }
+        if !declared.contains_key(package_name) {
+            result.added.push(package_name.clone());
+        } else if only_new {
+            result.skipped.push(package_name.clone());
+        } else {
+            return Err(anyhow!(
+                "Package '{}' already exists in {}",
+                package_name,
+                file_path
+            ));
         }
-        lines.push("@packages".to_string());
-        lines.push(package_name.to_string());
+    }
+    if result.added.is_empty() {
+        return Ok(result);
     }
 
-    // Write back to file
-    let new_content = lines.join("\n") + "\n";
-    fs::write(file_path, new_content)
+    let new_content =
+        crate::core::config::managed::insert_requires(&content, parent, &result.added)
+            .ok_or_else(|| anyhow!("{} has no @package {} block", file_path, parent))?;
+    std::fs::write(file_path, new_content)
         .map_err(|e| anyhow!("Failed to write to config file: {}", e))?;
+    Ok(result)
+}
 
-    Ok(())
+/// Whether `content` declares `package_name`, comparing the form the parser sees
+fn declares(content: &str, package_name: &str) -> bool {
+    content.lines().any(|line| {
+        let line = crate::core::names::normalize_line(line);
+        // Options such as `[aur]` follow the name
+        let line = line
+            .split_once('[')
+            .map_or(line.as_ref(), |(name, _)| name.trim_end());
+        line == package_name
+            || line.strip_prefix("@package ") == Some(package_name)
+            || line.strip_prefix("@pkg ") == Some(package_name)
+    })
 }
 
 /// Prompt user to select a config file from search results
@@ -270,8 +395,7 @@ fn prompt_file_selection(count: usize) -> Option<usize> {
     }
 
     loop {
-        print!("Select config file (0-{}, or 'c' to cancel): ", count - 1);
-        std::io::Write::flush(&mut std::io::stdout()).ok()?;
+        out!("Select config file (0-{}, or 'c' to cancel): ", count - 1);
 
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).ok()?;
@@ -287,7 +411,7 @@ fn prompt_file_selection(count: usize) -> Option<usize> {
                 return Some(index);
             }
             _ => {
-                println!(
+                outln!(
                     "{}",
                     crate::internal::color::red("Invalid selection. Please try again.")
                 );
@@ -295,3 +419,96 @@ fn prompt_file_selection(count: usize) -> Option<usize> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_only_new_adds_just_the_missing_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("main.owl");
+        std::fs::write(&file, "@package git\n@packages\nripgrep\nyay [aur]\n").unwrap();
+        let path = file.to_string_lossy();
+
+        let result =
+            add_packages_to_file(&names(&["fd", "git", "yay", "bat", "fd"]), &path, true).unwrap();
+        assert_eq!(
+            result,
+            Added {
+                added: names(&["fd", "bat"]),
+                skipped: names(&["git", "yay"]),
+            }
+        );
+        let config =
+            crate::core::config::Config::parse(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        let mut declared: Vec<&String> = config.packages.keys().collect();
+        declared.sort();
+        assert_eq!(declared, ["bat", "fd", "git", "ripgrep", "yay"]);
+
+        // Everything present: the file is left alone
+        let before = std::fs::read_to_string(&file).unwrap();
+        let result = add_packages_to_file(&names(&["git", "bat"]), &path, true).unwrap();
+        assert!(result.added.is_empty());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), before);
+    }
+
+    #[test]
+    fn test_duplicate_without_only_new_is_an_error_and_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("main.owl");
+        std::fs::write(&file, "@package git\n").unwrap();
+        let path = file.to_string_lossy();
+
+        let err = add_packages_to_file(&names(&["fd", "git"]), &path, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Package 'git' already exists"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "@package git\n");
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("3", 5), Some(vec![3]));
+        assert_eq!(parse_selection("0 2,4  2", 5), Some(vec![0, 2, 4]));
+        assert_eq!(parse_selection("5", 5), None);
+        assert_eq!(parse_selection("1 x", 5), None);
+        assert_eq!(parse_selection("", 5), None);
+    }
+
+    #[test]
+    fn test_into_appends_requires_to_the_parent_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("dev.owl");
+        std::fs::write(&file, "@package devtools\n:requires gdb\n\n@package git\n").unwrap();
+        let path = file.to_string_lossy();
+
+        let result =
+            add_requires_to_file(&names(&["strace", "gdb", "git"]), "devtools", &path, true)
+                .unwrap();
+        assert_eq!(
+            result,
+            Added {
+                added: names(&["strace"]),
+                skipped: names(&["gdb", "git"]),
+            }
+        );
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            "@package devtools\n:requires gdb\n:requires strace\n\n@package git\n"
+        );
+
+        let err = add_requires_to_file(&names(&["fd"]), "ripgrep", &path, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("no @package ripgrep block"), "{}", err);
+        let err = add_requires_to_file(&names(&["gdb"]), "devtools", &path, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Package 'gdb' already exists"), "{}", err);
+    }
+}
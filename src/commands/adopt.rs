@@ -6,7 +6,7 @@ pub fn run(items: &[String], all: bool) {
         match crate::core::config::Config::load_all_relevant_config_files() {
             Ok(cfg) => cfg.packages.keys().cloned().collect(),
             Err(e) => {
-                eprintln!("{}", color::red(&format!("Failed to load config: {}", e)));
+                errln!("{}", color::red(&format!("Failed to load config: {}", e)));
                 return;
             }
         }
@@ -15,14 +15,14 @@ pub fn run(items: &[String], all: bool) {
     };
 
     if targets.is_empty() {
-        println!("{}", color::yellow("No packages to adopt"));
+        outln!("{}", color::yellow("No packages to adopt"));
         return;
     }
 
     let mut state = match crate::core::state::PackageState::load() {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("{}", color::red(&format!("Failed to load state: {}", e)));
+            errln!("{}", color::red(&format!("Failed to load state: {}", e)));
             return;
         }
     };
@@ -43,18 +43,18 @@ pub fn run(items: &[String], all: bool) {
             }
             Ok(false) => skipped_not_installed.push(pkg),
             Err(e) => {
-                eprintln!("{}", color::red(&format!("Failed to check {}: {}", pkg, e)));
+                errln!("{}", color::red(&format!("Failed to check {}: {}", pkg, e)));
             }
         }
     }
 
     if let Err(e) = state.save() {
-        eprintln!("{}", color::red(&format!("Failed to save state: {}", e)));
+        errln!("{}", color::red(&format!("Failed to save state: {}", e)));
         return;
     }
 
     if !adopted.is_empty() {
-        println!(
+        outln!(
             "{} Adopted {} package(s): {}",
             color::green("✓"),
             adopted.len(),
@@ -62,14 +62,14 @@ pub fn run(items: &[String], all: bool) {
         );
     }
     if !skipped_already.is_empty() {
-        println!(
+        outln!(
             "{} Already managed: {}",
             color::blue("info:"),
             skipped_already.join(", ")
         );
     }
     if !skipped_not_installed.is_empty() {
-        println!(
+        outln!(
             "{} Not installed (skipped): {}",
             color::yellow("!"),
             skipped_not_installed.join(", ")
@@ -0,0 +1,228 @@
+//! Adopting declared packages that were installed before owl managed them
+//!
+//! Managed packages are proposed for removal once they leave the config, so a
+//! package the user installed by hand only becomes managed when they agree.
+//! A "no" is remembered in `declined.json`; packages declared later are asked
+//! about on their own.
+
+use crate::core::confirm::ConfirmKind;
+use crate::core::events::{EventSink, OwlEvent};
+use crate::core::state::PackageState;
+
+/// How this run treats adoption candidates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// `--adopt-managed` or `@option auto_adopt=true`
+    Adopt,
+    /// Prompt once for all candidates
+    Ask,
+    /// Cannot prompt; leave the candidates for an interactive run
+    Defer,
+    /// Dry run: report the candidates only
+    Preview,
+}
+
+impl Mode {
+    pub fn resolve(adopt: bool, dry_run: bool, can_prompt: bool) -> Mode {
+        if dry_run {
+            Mode::Preview
+        } else if adopt {
+            Mode::Adopt
+        } else if can_prompt {
+            Mode::Ask
+        } else {
+            Mode::Defer
+        }
+    }
+}
+
+/// Settle the analysis' adoption candidates and save the state if it changed
+pub fn run(
+    analysis: &mut super::analysis::Analysis,
+    adopt_flag: bool,
+    dry_run: bool,
+    policy: &crate::core::confirm::ConfirmPolicy,
+    sink: &mut dyn EventSink,
+) {
+    if analysis.adoption_candidates.is_empty() {
+        return;
+    }
+    let auto_adopt = analysis.config.auto_adopt().unwrap_or_else(|e| {
+        sink.emit(OwlEvent::Warning(e.to_string()));
+        false
+    });
+    let mode = Mode::resolve(adopt_flag || auto_adopt, dry_run, policy.asks());
+    let candidates = std::mem::take(&mut analysis.adoption_candidates);
+    if settle(
+        &mut analysis.state,
+        &candidates,
+        mode,
+        |candidates| crate::cli::ui::confirm(ConfirmKind::Adoption, candidates, policy),
+        sink,
+    ) && let Err(e) = analysis.state.save()
+    {
+        sink.emit(OwlEvent::Warning(format!(
+            "Failed to save package state: {}",
+            e
+        )));
+    }
+}
+
+/// Apply `mode` to `candidates`; true when the state changed
+pub fn settle(
+    state: &mut PackageState,
+    candidates: &[String],
+    mode: Mode,
+    confirm: impl FnOnce(&[String]) -> bool,
+    sink: &mut dyn EventSink,
+) -> bool {
+    let adopt = match mode {
+        Mode::Adopt => true,
+        Mode::Ask => confirm(candidates),
+        Mode::Defer => {
+            sink.emit(OwlEvent::Warning(format!(
+                "{} declared packages are installed but not managed ({}); run apply \
+                 interactively or pass --adopt-managed to manage them",
+                candidates.len(),
+                candidates.join(", ")
+            )));
+            return false;
+        }
+        Mode::Preview => {
+            sink.emit(OwlEvent::Warning(format!(
+                "Would ask to manage {} already-installed declared packages: {}",
+                candidates.len(),
+                candidates.join(", ")
+            )));
+            return false;
+        }
+    };
+    for package in candidates {
+        if adopt {
+            state.add_managed(package.clone());
+        } else {
+            state.add_declined(package.clone());
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::Config;
+    use crate::core::state::PackageState;
+
+    fn installed(names: &'static [&'static str]) -> impl Fn(&str) -> anyhow::Result<bool> {
+        move |pkg| Ok(names.contains(&pkg))
+    }
+
+    fn quiet() -> impl FnMut(OwlEvent) {
+        |_| {}
+    }
+
+    #[test]
+    fn test_mode_resolution() {
+        assert_eq!(Mode::resolve(true, false, false), Mode::Adopt);
+        assert_eq!(Mode::resolve(false, false, true), Mode::Ask);
+        assert_eq!(Mode::resolve(false, false, false), Mode::Defer);
+        assert_eq!(Mode::resolve(true, true, true), Mode::Preview);
+    }
+
+    #[test]
+    fn test_only_installed_unmanaged_packages_are_candidates() {
+        let config = Config::parse("@packages\nbat\nfd\nripgrep\n").unwrap();
+        let mut state = PackageState::default();
+        state.add_managed("bat".to_string());
+        let candidates = super::super::analysis::adoption_candidates(
+            &config,
+            &state,
+            installed(&["bat", "ripgrep"]),
+        );
+        assert_eq!(candidates, vec!["ripgrep".to_string()]);
+    }
+
+    #[test]
+    fn test_defer_and_preview_leave_state_alone() {
+        let candidates = vec!["ripgrep".to_string()];
+        for mode in [Mode::Defer, Mode::Preview] {
+            let mut state = PackageState::default();
+            let mut warnings = Vec::new();
+            let changed = settle(
+                &mut state,
+                &candidates,
+                mode,
+                |_| panic!("must not prompt"),
+                &mut |event| warnings.push(event),
+            );
+            assert!(!changed);
+            assert!(state.managed.is_empty() && state.declined.is_empty());
+            assert_eq!(warnings.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_adopt_and_accept_manage_packages() {
+        let candidates = vec!["fd".to_string(), "ripgrep".to_string()];
+        let mut adopted = PackageState::default();
+        assert!(settle(
+            &mut adopted,
+            &candidates,
+            Mode::Adopt,
+            |_| panic!("must not prompt"),
+            &mut quiet()
+        ));
+        let mut accepted = PackageState::default();
+        assert!(settle(
+            &mut accepted,
+            &candidates,
+            Mode::Ask,
+            |_| true,
+            &mut quiet()
+        ));
+        assert_eq!(adopted.managed, candidates);
+        assert_eq!(accepted.managed, candidates);
+    }
+
+    #[test]
+    fn test_decline_persists_and_new_packages_are_asked_about() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = PackageState::default();
+        settle(
+            &mut state,
+            &["ripgrep".to_string()],
+            Mode::Ask,
+            |_| false,
+            &mut quiet(),
+        );
+        state.save_to(dir.path()).unwrap();
+
+        let state = PackageState::load_from(dir.path()).unwrap();
+        assert!(state.is_declined("ripgrep"));
+        assert!(!state.is_managed("ripgrep"));
+
+        // A later config declares fd, which is also already installed
+        let config = Config::parse("@packages\nfd\nripgrep\n").unwrap();
+        let candidates = super::super::analysis::adoption_candidates(
+            &config,
+            &state,
+            installed(&["fd", "ripgrep"]),
+        );
+        assert_eq!(candidates, vec!["fd".to_string()]);
+    }
+
+    #[test]
+    fn test_adopting_clears_an_earlier_decline() {
+        let mut state = PackageState::default();
+        state.add_declined("ripgrep".to_string());
+        settle(
+            &mut state,
+            &["ripgrep".to_string()],
+            Mode::Adopt,
+            |_| false,
+            &mut quiet(),
+        );
+        assert!(state.is_managed("ripgrep"));
+        assert!(!state.is_declined("ripgrep"));
+    }
+}
@@ -1,9 +1,8 @@
-use crate::core::pm::PackageManager;
 use anyhow::{Result, anyhow};
 
 /// Get list of AUR packages that can be updated
 pub fn get_aur_updates() -> Result<Vec<String>> {
-    crate::core::pm::ParuPacman::new().get_aur_updates()
+    crate::core::pm::manager().get_aur_updates()
 }
 
 /// Count packages that have dotfile configurations
@@ -32,22 +31,51 @@ pub struct Analysis {
     pub config: crate::core::config::Config,
     pub state: crate::core::state::PackageState,
     pub actions: Vec<crate::core::package::PackageAction>,
+    /// Declared, already installed packages owl could start managing (see `adoption`)
+    pub adoption_candidates: Vec<String>,
+    /// Corrections made to the managed list before planning
+    pub reconciled: Vec<crate::core::reconcile::StateChange>,
     pub dotfile_count: usize,
     pub service_count: usize,
     pub config_package_count: usize,
 }
 
-pub fn analyze_system() -> anyhow::Result<Analysis> {
+/// Load config and state and plan the run; a dry run writes nothing, not even
+/// the default state files
+pub fn analyze_system(dry_run: bool) -> anyhow::Result<Analysis> {
+    analyze(
+        dry_run,
+        crate::core::config::Config::load_all_relevant_config_files,
+    )
+}
+
+/// `analyze_system` for a config loaded earlier
+pub fn analyze_loaded(
+    dry_run: bool,
+    config: crate::core::config::Config,
+) -> anyhow::Result<Analysis> {
+    analyze(dry_run, move || Ok(config))
+}
+
+fn analyze(
+    dry_run: bool,
+    load: impl FnOnce() -> Result<crate::core::config::Config> + Send + 'static,
+) -> anyhow::Result<Analysis> {
     use std::thread;
 
     // Run independent, potentially slow operations in parallel
     // 1) Count upgradable packages
     let count_handle = thread::spawn(crate::core::package::get_package_count);
     // 2) Load config files
-    let config_handle =
-        thread::spawn(|| crate::core::config::Config::load_all_relevant_config_files());
+    let config_handle = thread::spawn(load);
     // 3) Load package state from disk
-    let state_handle = thread::spawn(crate::core::state::PackageState::load);
+    let state_handle = thread::spawn(move || {
+        if dry_run {
+            crate::core::state::PackageState::read()
+        } else {
+            crate::core::state::PackageState::load()
+        }
+    });
     // 4) Prewarm installed package cache to avoid repeated -Q calls later
     let installed_warm_handle = thread::spawn(|| {
         let _ = crate::core::package::get_installed_packages();
@@ -65,23 +93,68 @@ pub fn analyze_system() -> anyhow::Result<Analysis> {
         .map_err(|_| anyhow!("Failed to join state loader thread"))?
         .map_err(|e| anyhow!("Failed to load package state: {}", e))?;
 
-    let config = config_handle
+    let mut config = config_handle
         .join()
         .map_err(|_| anyhow!("Failed to join config loader thread"))?
         .map_err(|e| anyhow!("Failed to load config: {}", e))?;
 
+    // Reject :after cycles before anything touches state or the system
+    config.package_order()?;
+    config.service_order()?;
+
     // Ensure installed cache warm-up finished (best-effort)
     let _ = installed_warm_handle.join();
 
-    // Seed managed state with currently installed packages that are present in config.
-    // This ensures future removals are detected only for packages user explicitly managed via config.
-    if seed_managed_with_desired_installed(&config, &mut state)? {
-        // Best-effort save; don't fail analysis if saving state fails.
-        if let Err(e) = state.save() {
-            eprintln!(
+    // Packages declared @untracked in config are never proposed for removal
+    state.merge_config_untracked(&config);
+
+    // Fix managed entries for packages removed or replaced outside owl
+    let reconciled = match crate::core::package::reconcile_managed(&mut state, &config) {
+        Ok(changes) => {
+            if !changes.is_empty()
+                && !dry_run
+                && let Err(e) = state.save()
+            {
+                errln!(
+                    "{}",
+                    crate::internal::color::red(&format!(
+                        "Failed to save reconciled package state: {}",
+                        e
+                    ))
+                );
+            }
+            changes
+        }
+        Err(e) => {
+            errln!(
                 "{}",
-                crate::internal::color::red(&format!("Failed to save seeded package state: {}", e))
+                crate::internal::color::red(&format!("Failed to reconcile package state: {}", e))
             );
+            Vec::new()
+        }
+    };
+
+    // Declared packages that are installed but not managed are only adopted once the
+    // user agrees (see `adoption`), so removing them from config never surprises anyone
+    let adoption_candidates = adoption_candidates(&config, &state, |pkg| {
+        crate::core::package::is_package_or_group_installed(pkg)
+    });
+
+    // Installed packages older than their :min-version warn, or lose their actions when strict
+    if config
+        .packages
+        .values()
+        .any(|pkg| pkg.min_version.is_some())
+    {
+        match crate::core::pm::manager().list_installed_versions() {
+            Ok(installed) => {
+                let violations = crate::core::version::check_min_versions(&config, &installed);
+                let warnings = crate::core::version::enforce_min_versions(&mut config, &violations);
+                config.warnings.extend(warnings);
+            }
+            Err(e) => config
+                .warnings
+                .push(format!("Could not check :min-version constraints: {}", e)),
         }
     }
 
@@ -99,41 +172,31 @@ pub fn analyze_system() -> anyhow::Result<Analysis> {
         config,
         state,
         actions,
+        adoption_candidates,
+        reconciled,
         dotfile_count,
         service_count,
         config_package_count,
     })
 }
 
-/// Ensure packages that are currently in the config and installed are marked as managed
-pub fn seed_managed_with_desired_installed(
+/// Declared packages that are installed but neither managed nor declined, sorted
+pub fn adoption_candidates(
     config: &crate::core::config::Config,
-    state: &mut crate::core::state::PackageState,
-) -> anyhow::Result<bool> {
-    let mut changed = false;
-
-    // Collect packages to check in batches
-    let packages_to_check: Vec<&String> = config
+    state: &crate::core::state::PackageState,
+    is_installed: impl Fn(&str) -> anyhow::Result<bool>,
+) -> Vec<String> {
+    let mut candidates = Vec::new();
+    for pkg in config
         .packages
         .keys()
-        .filter(|pkg| !state.is_managed(pkg))
-        .collect();
-
-    if packages_to_check.is_empty() {
-        return Ok(false);
-    }
-
-    // Group packages by whether they might be groups or regular packages
-    // to minimize redundant group checks
-    for pkg in packages_to_check {
-        match crate::core::package::is_package_or_group_installed(pkg) {
-            Ok(true) => {
-                state.add_managed(pkg.to_string());
-                changed = true;
-            }
+        .filter(|pkg| !state.is_managed(pkg) && !state.is_declined(pkg))
+    {
+        match is_installed(pkg) {
+            Ok(true) => candidates.push(pkg.clone()),
             Ok(false) => {}
             Err(e) => {
-                eprintln!(
+                errln!(
                     "{}",
                     crate::internal::color::red(&format!(
                         "Failed to verify installation of {}: {}",
@@ -143,6 +206,6 @@ pub fn seed_managed_with_desired_installed(
             }
         }
     }
-
-    Ok(changed)
+    candidates.sort();
+    candidates
 }
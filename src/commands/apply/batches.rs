@@ -0,0 +1,118 @@
+//! Installing a large set in several transactions (`--install-batch-size`)
+//!
+//! One conflicting package fails the whole `pacman -S` it is part of. Split
+//! into batches, a failure only takes its own batch down and the others still
+//! install; the batches that failed are summarised at the end.
+
+use anyhow::Result;
+
+/// A batch whose transaction failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedBatch {
+    /// 1-based position among the batches
+    pub number: usize,
+    pub packages: Vec<String>,
+    pub error: String,
+}
+
+/// `packages` in batches of `size`; without a size they are one batch
+pub fn split(packages: &[String], size: Option<usize>) -> std::slice::Chunks<'_, String> {
+    packages.chunks(size.unwrap_or(packages.len()).max(1))
+}
+
+/// Run `install` on each batch, carrying on past failures
+pub fn install(
+    packages: &[String],
+    size: Option<usize>,
+    mut install: impl FnMut(&[String]) -> Result<()>,
+) -> Vec<FailedBatch> {
+    split(packages, size)
+        .enumerate()
+        .filter_map(|(index, batch)| {
+            install(batch).err().map(|e| FailedBatch {
+                number: index + 1,
+                packages: batch.to_vec(),
+                error: e.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// `2 of 5 install batches failed: #2 (a, b), #4 (c)`, when more than one ran
+pub fn summary(failed: &[FailedBatch], batches: usize) -> Option<String> {
+    if failed.is_empty() || batches < 2 {
+        return None;
+    }
+    let listed: Vec<String> = failed
+        .iter()
+        .map(|f| format!("#{} ({})", f.number, f.packages.join(", ")))
+        .collect();
+    Some(format!(
+        "{} of {} install batches failed: {}",
+        failed.len(),
+        batches,
+        listed.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_sizes() {
+        let packages = names(&["a", "b", "c", "d", "e"]);
+        let sizes = |size| -> Vec<usize> { split(&packages, size).map(<[_]>::len).collect() };
+        assert_eq!(sizes(None), [5]);
+        assert_eq!(sizes(Some(2)), [2, 2, 1]);
+        assert_eq!(sizes(Some(5)), [5]);
+        assert_eq!(sizes(Some(10)), [5]);
+        assert_eq!(split(&[], None).count(), 0);
+    }
+
+    #[test]
+    fn test_failure_is_isolated_to_its_batch() {
+        let packages = names(&["a", "b", "c", "conflict", "e", "f", "g"]);
+        let mut attempted = Vec::new();
+        let failed = install(&packages, Some(3), |batch| {
+            attempted.push(batch.to_vec());
+            if batch.iter().any(|p| p == "conflict") {
+                Err(anyhow!("conflicting files"))
+            } else {
+                Ok(())
+            }
+        });
+
+        // The failing middle batch does not stop the ones after it
+        assert_eq!(
+            attempted,
+            [
+                names(&["a", "b", "c"]),
+                names(&["conflict", "e", "f"]),
+                names(&["g"])
+            ]
+        );
+        assert_eq!(
+            failed,
+            [FailedBatch {
+                number: 2,
+                packages: names(&["conflict", "e", "f"]),
+                error: "conflicting files".to_string(),
+            }]
+        );
+        assert_eq!(
+            summary(&failed, 3).as_deref(),
+            Some("1 of 3 install batches failed: #2 (conflict, e, f)")
+        );
+
+        let one_batch = install(&packages, None, |_| Err(anyhow!("conflicting files")));
+        assert_eq!(one_batch.len(), 1);
+        assert_eq!(summary(&one_batch, 1), None);
+        assert_eq!(summary(&[], 3), None);
+    }
+}
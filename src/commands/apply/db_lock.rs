@@ -0,0 +1,262 @@
+//! Getting the pacman database lock for the package phases (see `core::db_lock`)
+//!
+//! Before the first transaction, and again when one fails on the lock, a
+//! stale lock is removed once confirmed and a held one is waited for. When the
+//! lock cannot be had, the package phases are deferred to the next run while
+//! dotfiles, services and env still apply.
+
+use crate::core::confirm::{ConfirmKind, ConfirmPolicy};
+use crate::core::db_lock::{self, Holder, LockState, WaitOutcome};
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How this run deals with the pacman database lock
+#[derive(Debug)]
+pub struct LockWait {
+    pub lock: PathBuf,
+    /// Where running processes are looked up
+    pub proc: PathBuf,
+    /// Longest wait for a held lock (`--db-lock-wait`)
+    pub limit: Duration,
+    /// Why the package phases were deferred, once they were
+    pub deferred: Mutex<Option<String>>,
+}
+
+impl LockWait {
+    pub fn new(limit: Duration) -> Self {
+        Self {
+            lock: PathBuf::from(db_lock::LOCK_FILE),
+            proc: PathBuf::from("/proc"),
+            limit,
+            deferred: Mutex::new(None),
+        }
+    }
+
+    pub fn is_deferred(&self) -> bool {
+        self.deferred_reason().is_some()
+    }
+
+    /// Why the package phases were deferred, if they were
+    pub fn deferred_reason(&self) -> Option<String> {
+        self.deferred
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Make sure the lock can be taken, deferring the package phases when not
+    ///
+    /// Returns whether they can go ahead.
+    pub fn settle(&self, policy: &ConfirmPolicy) -> bool {
+        if self.is_deferred() {
+            return false;
+        }
+        let reason = match db_lock::state(&self.lock, &self.proc) {
+            LockState::Free => return true,
+            LockState::Stale => self.remove_stale(policy),
+            LockState::Held(holders) => {
+                warnln!(
+                    "  {} The pacman database is locked by {}; waiting up to {}",
+                    crate::internal::color::yellow("warning:"),
+                    list(&holders),
+                    duration(self.limit)
+                );
+                self.wait(policy)
+            }
+        };
+        let ready = reason.is_none();
+        *self.deferred.lock().unwrap_or_else(|e| e.into_inner()) = reason;
+        ready
+    }
+
+    /// Run a package transaction, and when pacman finds its database locked
+    /// settle the lock and run it once more
+    ///
+    /// Once the package phases are deferred, transactions are not started.
+    pub fn retry(
+        &self,
+        policy: &ConfirmPolicy,
+        mut transaction: impl FnMut() -> Result<()>,
+    ) -> Result<()> {
+        if let Some(reason) = self.deferred_reason() {
+            return Err(anyhow!("Deferred: {}", reason));
+        }
+        match transaction() {
+            Err(err) if db_lock::is_locked(&err) => {
+                if self.settle(policy) {
+                    transaction()
+                } else {
+                    Err(err)
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn wait(&self, policy: &ConfirmPolicy) -> Option<String> {
+        let output = crate::internal::output::get();
+        let outcome = db_lock::wait(
+            &self.lock,
+            &self.proc,
+            self.limit,
+            std::thread::sleep,
+            |holders, left| {
+                output.status(&format!(
+                    "Waiting for the pacman database lock held by {} ({} left)",
+                    list(holders),
+                    duration(left)
+                ))
+            },
+        );
+        output.clear_status();
+        match outcome {
+            WaitOutcome::Free => None,
+            WaitOutcome::Stale => self.remove_stale(policy),
+            WaitOutcome::Expired(holders) => Some(format!(
+                "the pacman database was still locked by {} after {}",
+                list(&holders),
+                duration(self.limit)
+            )),
+        }
+    }
+
+    /// Remove a lock nobody holds once confirmed; why not otherwise
+    fn remove_stale(&self, policy: &ConfirmPolicy) -> Option<String> {
+        let items = [self.lock.display().to_string()];
+        if !crate::cli::ui::confirm(ConfirmKind::StaleLock, &items, policy) {
+            return Some(format!(
+                "{} is left from a package manager that is no longer running; remove it to continue",
+                self.lock.display()
+            ));
+        }
+        remove_lock(&self.lock).err().map(|e| e.to_string())
+    }
+}
+
+fn remove_lock(lock: &Path) -> Result<()> {
+    let status = std::process::Command::new("sudo")
+        .args(["rm", "-f", "--"])
+        .arg(lock)
+        .status()
+        .map_err(|e| anyhow!("could not remove the stale lock {}: {}", lock.display(), e))?;
+    if !status.success() {
+        return Err(anyhow!(
+            "could not remove the stale lock {} ({})",
+            lock.display(),
+            status
+        ));
+    }
+    Ok(())
+}
+
+fn list(holders: &[Holder]) -> String {
+    holders
+        .iter()
+        .map(Holder::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn duration(duration: Duration) -> String {
+    crate::internal::time::format_duration_ms(duration.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::db_lock::DatabaseLocked;
+    use std::fs;
+
+    fn lock_wait(dir: &Path, processes: &[(u32, &str)]) -> LockWait {
+        let proc = dir.join("proc");
+        for (pid, command) in processes {
+            fs::create_dir_all(proc.join(pid.to_string())).unwrap();
+            fs::write(
+                proc.join(pid.to_string()).join("cmdline"),
+                command.replace(' ', "\0"),
+            )
+            .unwrap();
+        }
+        fs::create_dir_all(&proc).unwrap();
+        LockWait {
+            lock: dir.join("db.lck"),
+            proc,
+            limit: Duration::ZERO,
+            deferred: Mutex::new(None),
+        }
+    }
+
+    fn locked() -> anyhow::Error {
+        anyhow::Error::new(DatabaseLocked {
+            stderr: "error: failed to init transaction (unable to lock database)".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_locked_transaction_runs_again_once_the_lock_is_free() {
+        let dir = tempfile::tempdir().unwrap();
+        let wait = lock_wait(dir.path(), &[]);
+        let mut runs = 0;
+        wait.retry(&ConfirmPolicy::default(), || {
+            runs += 1;
+            if runs == 1 { Err(locked()) } else { Ok(()) }
+        })
+        .unwrap();
+        assert_eq!(runs, 2);
+        assert!(!wait.is_deferred());
+
+        // Other failures are not retried
+        let mut runs = 0;
+        let err = wait
+            .retry(&ConfirmPolicy::default(), || {
+                runs += 1;
+                Err(anyhow!("target not found: foo"))
+            })
+            .unwrap_err();
+        assert_eq!(
+            (runs, err.to_string().as_str()),
+            (1, "target not found: foo")
+        );
+    }
+
+    #[test]
+    fn test_held_lock_defers_the_remaining_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let wait = lock_wait(dir.path(), &[(4200, "paru -Syu")]);
+        fs::write(&wait.lock, "").unwrap();
+
+        let mut runs = 0;
+        let err = wait
+            .retry(&ConfirmPolicy::default(), || {
+                runs += 1;
+                Err(locked())
+            })
+            .unwrap_err();
+        assert!(db_lock::is_locked(&err));
+        assert_eq!(runs, 1);
+        let reason = wait.deferred_reason().unwrap();
+        assert!(reason.contains("pid 4200 (paru -Syu)"), "{}", reason);
+
+        // Later transactions do not start at all
+        let err = wait
+            .retry(&ConfirmPolicy::default(), || unreachable!())
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Deferred: "));
+        assert!(!wait.settle(&ConfirmPolicy::default()));
+    }
+
+    #[test]
+    fn test_stale_lock_is_kept_without_confirmation() {
+        let dir = tempfile::tempdir().unwrap();
+        let wait = lock_wait(dir.path(), &[(300, "bash")]);
+        fs::write(&wait.lock, "").unwrap();
+
+        // Nobody can be asked, so the lock stays and the packages wait
+        assert!(!wait.settle(&ConfirmPolicy::default()));
+        assert!(wait.lock.exists());
+        let reason = wait.deferred_reason().unwrap();
+        assert!(reason.contains("no longer running"), "{}", reason);
+    }
+}
@@ -1,54 +1,141 @@
-/// Apply dotfile synchronization
-pub fn apply_dotfiles_with_config(config: &crate::core::config::Config, dry_run: bool) {
-    // Config is provided from earlier analysis
+use crate::core::confirm::{ConfirmKind, ConfirmPolicy};
+use crate::core::dotfiles::{Divergence, DotfileMapping, DotfileRoots};
+use crate::core::events::{EventSink, OwlEvent};
 
-    // Get dotfile mappings from config
-    let mappings = crate::core::dotfiles::get_dotfile_mappings(config);
+/// Dotfile roots with the dotfile settings in `params`
+fn roots(
+    config: &crate::core::config::Config,
+    params: &super::packages::PackageOperationParams,
+) -> anyhow::Result<DotfileRoots> {
+    Ok(DotfileRoots::from_env()?
+        .with_dest_prefix(params.dest_prefix.clone())
+        .with_hash_algo(params.hash_algo)
+        .with_no_delete(params.no_dotfiles_delete)
+        .with_allow_outside_home(params.allow_outside_home)
+        .with_shared_host(crate::core::shared_home::host(config)?)
+        .with_mode_policy(config.mode_policy()?)
+        .with_divergence(params.dotfile_divergence)
+        .with_absent(config.absent_paths())
+        .with_declined(params.approval.as_ref().map_or_else(Default::default, |a| {
+            a.declined(crate::core::review::Section::Dotfiles)
+        })))
+}
 
-    // Show section header
-    println!();
-    println!("[{}]", crate::internal::color::green("config"));
+/// System dotfile destinations the run writes through the privileged helper
+/// (`--allow-outside-home`), for its privileged-ops manifest
+pub fn privileged_destinations(
+    config: &crate::core::config::Config,
+    args: &crate::cli::handler::ApplyArgs,
+) -> Vec<std::path::PathBuf> {
+    if !args.allow_outside_home || args.dest_prefix.is_some() {
+        return Vec::new();
+    }
+    let concurrency = args
+        .dotfile_concurrency
+        .unwrap_or_else(crate::core::dotfiles::default_concurrency);
+    DotfileRoots::from_env()
+        .and_then(|roots| {
+            crate::core::dotfiles::privileged_destinations(
+                &roots.with_allow_outside_home(true),
+                &crate::core::dotfiles::get_dotfile_mappings(config),
+                concurrency,
+            )
+        })
+        // The sync reports the same error
+        .unwrap_or_default()
+}
 
-    if mappings.is_empty() {
-        println!(
-            "  {} No dotfiles configured",
-            crate::internal::color::blue("info:")
-        );
-        return;
+/// What happens to diverged dotfiles: `chosen` (`--dotfile-diverged`) when
+/// given, otherwise they are overwritten once confirmed and kept if not
+///
+/// Asked before the package phase, so the question never lands in the middle
+/// of package or parallel dotfile output.
+pub fn settle_divergence(
+    config: &crate::core::config::Config,
+    params: &super::packages::PackageOperationParams,
+    chosen: Option<Divergence>,
+) -> Divergence {
+    if let Some(divergence) = chosen {
+        return divergence;
+    }
+    if params.dry_run || !params.phases.enabled(super::phases::Phase::Dotfiles) {
+        return Divergence::default();
+    }
+    match roots(config, params) {
+        Ok(roots) => confirm_divergence(
+            &roots,
+            &crate::core::dotfiles::get_dotfile_mappings(config),
+            params.dotfile_concurrency,
+            &params.confirm,
+        ),
+        // The sync reports the same error
+        Err(_) => Divergence::default(),
     }
+}
 
-    // Check if any actions are needed
-    let has_actions = match crate::core::dotfiles::has_actionable_dotfiles(&mappings) {
-        Ok(has) => has,
-        Err(err) => {
-            eprintln!(
-                "{}",
-                crate::internal::color::red(&format!("Failed to analyze dotfiles: {}", err))
-            );
-            return;
-        }
-    };
-
-    if !has_actions {
-        println!(
-            "  {} Up to date: {} dotfiles",
-            crate::internal::color::green("➔"),
-            mappings.len()
-        );
-        return;
+/// Ask before overwriting the destinations of `mappings` that diverged
+pub fn confirm_divergence(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    concurrency: usize,
+    policy: &ConfirmPolicy,
+) -> Divergence {
+    let diverged = crate::core::dotfiles::diverged_destinations(roots, mappings, concurrency)
+        .unwrap_or_default();
+    if diverged.is_empty()
+        || crate::cli::ui::confirm(ConfirmKind::DotfileOverwrite, &diverged, policy)
+    {
+        Divergence::Overwrite
+    } else {
+        Divergence::Keep
     }
+}
+
+/// Apply dotfile synchronization with the dotfile settings in `params`
+pub fn apply_dotfiles_with_config(
+    config: &crate::core::config::Config,
+    params: &super::packages::PackageOperationParams,
+    sink: &mut dyn EventSink,
+) {
+    // Config is provided from earlier analysis
+
+    // Get dotfile mappings from config
+    let mappings = crate::core::dotfiles::get_dotfile_mappings(config);
+    let checks =
+        crate::core::source_check::SourceChecks::from_config(config, params.strict_sources, sink);
 
-    // Analyze and apply dotfiles
-    let actions = match crate::core::dotfiles::apply_dotfiles(&mappings, dry_run) {
-        Ok(actions) => actions,
-        Err(err) => {
-            eprintln!(
-                "{}",
-                crate::internal::color::red(&format!("Failed to apply dotfiles: {}", err))
-            );
-            return;
+    let result = roots(config, params).and_then(|roots| {
+        // Staged destinations never overwrite package files
+        if roots.dest_prefix.is_none() {
+            warn_package_owned(&roots, &mappings, sink);
         }
-    };
+        crate::core::dotfiles::sync_dotfiles(
+            &roots,
+            &mappings,
+            params.dry_run,
+            params.dotfile_concurrency,
+            params.keep_backups,
+            Some(&checks),
+            sink,
+        )
+    });
+    if let Err(err) = result {
+        sink.emit(OwlEvent::Error(err.to_string()));
+    }
+}
 
-    crate::core::dotfiles::print_actions(&actions, dry_run);
+/// Warn about mappings that would overwrite files a package owns
+fn warn_package_owned(roots: &DotfileRoots, mappings: &[DotfileMapping], sink: &mut dyn EventSink) {
+    let pm = crate::core::pm::manager();
+    match crate::core::dotfiles::package_owned_destinations(roots, mappings, pm.as_ref()) {
+        Ok(owned) => {
+            for entry in owned {
+                sink.emit(OwlEvent::Warning(entry.warning()));
+            }
+        }
+        Err(err) => sink.emit(OwlEvent::Warning(format!(
+            "Could not check package ownership: {}",
+            err
+        ))),
+    }
 }
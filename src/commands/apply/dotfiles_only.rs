@@ -0,0 +1,242 @@
+//! `owl apply --dotfiles-only`: sync dotfiles without asking the package manager anything
+//!
+//! Unlike `--only dotfiles`, this skips system analysis entirely: no package
+//! count, installed-package cache, install planning or `:min-version` checks.
+
+use crate::core::config::Config;
+use crate::core::dotfiles::DotfileRoots;
+use crate::core::events::{EventSink, OwlEvent};
+
+/// Load the config and sync its dotfiles
+pub fn run(flags: &crate::cli::handler::GlobalFlags, args: &crate::cli::handler::ApplyArgs) {
+    let config = match Config::load_all_relevant_config_files() {
+        Ok(config) => config,
+        Err(err) => {
+            crate::error::exit_with_error(anyhow::anyhow!("Failed to load config: {}", err))
+        }
+    };
+    let mut roots = match DotfileRoots::from_env().and_then(|roots| {
+        Ok(roots
+            .with_dest_prefix(super::dest_prefix(args))
+            .with_hash_algo(args.hash_algo.unwrap_or_default())
+            .with_no_delete(args.no_dotfiles_delete)
+            .with_allow_outside_home(args.allow_outside_home)
+            .with_shared_host(crate::core::shared_home::host(&config)?)
+            .with_mode_policy(config.mode_policy()?)
+            .with_divergence(args.dotfile_diverged.unwrap_or_default())
+            .with_absent(config.absent_paths()))
+    }) {
+        Ok(roots) => roots,
+        Err(err) => crate::error::exit_with_error(err),
+    };
+    let concurrency = args
+        .dotfile_concurrency
+        .unwrap_or_else(crate::core::dotfiles::default_concurrency);
+    if args.dotfile_diverged.is_none() && !flags.dry_run {
+        let divergence = super::dotfiles::confirm_divergence(
+            &roots,
+            &crate::core::dotfiles::get_dotfile_mappings(&config),
+            concurrency,
+            &flags.confirm_policy(!args.events_json),
+        );
+        roots = roots.with_divergence(divergence);
+    }
+    let mut renderer = crate::cli::render::apply_sink(flags, args.events_json);
+    let keep_backups = super::keep_backups(args, &config);
+    sync(
+        &config,
+        &roots,
+        flags.dry_run,
+        concurrency,
+        keep_backups,
+        args.strict_sources,
+        renderer.as_mut(),
+    );
+}
+
+/// Sync the dotfiles `config` declares into `roots`
+pub fn sync(
+    config: &Config,
+    roots: &DotfileRoots,
+    dry_run: bool,
+    concurrency: usize,
+    keep_backups: usize,
+    strict_sources: bool,
+    sink: &mut dyn EventSink,
+) {
+    for warning in &config.warnings {
+        sink.emit(OwlEvent::Warning(warning.clone()));
+    }
+    if config
+        .packages
+        .values()
+        .any(|pkg| pkg.min_version.is_some())
+    {
+        sink.emit(OwlEvent::Warning(
+            ":min-version constraints are not checked with --dotfiles-only".to_string(),
+        ));
+    }
+    let mappings = crate::core::dotfiles::get_dotfile_mappings(config);
+    let checks = crate::core::source_check::SourceChecks::from_config(config, strict_sources, sink);
+    if let Err(err) = crate::core::dotfiles::sync_dotfiles(
+        roots,
+        &mappings,
+        dry_run,
+        concurrency,
+        keep_backups,
+        Some(&checks),
+        sink,
+    ) {
+        sink.emit(OwlEvent::Error(err.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::aur_builds::AurBuild;
+    use crate::core::pm::{PackageInfo, PackageManager, SearchResult};
+    use anyhow::Result;
+    use std::collections::{HashMap, HashSet};
+
+    /// Fails the test on any package manager call
+    struct Forbidden;
+
+    impl PackageManager for Forbidden {
+        fn list_installed(&self) -> Result<HashSet<String>> {
+            panic!("list_installed called")
+        }
+        fn list_explicit(&self) -> Result<HashSet<String>> {
+            panic!("list_explicit called")
+        }
+        fn list_installed_versions(&self) -> Result<HashMap<String, String>> {
+            panic!("list_installed_versions called")
+        }
+        fn list_foreign_versions(&self) -> Result<HashMap<String, String>> {
+            panic!("list_foreign_versions called")
+        }
+        fn batch_repo_available(&self, _: &[String]) -> Result<HashSet<String>> {
+            panic!("batch_repo_available called")
+        }
+        fn upgrade_count(&self) -> Result<usize> {
+            panic!("upgrade_count called")
+        }
+        fn list_upgrades(&self) -> Result<Vec<String>> {
+            panic!("list_upgrades called")
+        }
+        fn get_aur_updates(&self) -> Result<Vec<String>> {
+            panic!("get_aur_updates called")
+        }
+        fn install_repo(&self, _: &[String]) -> Result<()> {
+            panic!("install_repo called")
+        }
+        fn install_aur(&self, _: &[String], _: &mut Vec<AurBuild>) -> Result<()> {
+            panic!("install_aur called")
+        }
+        fn update_repo(&self, _: &[String]) -> Result<()> {
+            panic!("update_repo called")
+        }
+        fn update_aur(&self, _: &[String], _: &[String], _: &mut Vec<AurBuild>) -> Result<()> {
+            panic!("update_aur called")
+        }
+        fn remove_packages(&self, _: &[String], _: bool) -> Result<()> {
+            panic!("remove_packages called")
+        }
+        fn search_packages(&self, _: &[String]) -> Result<Vec<SearchResult>> {
+            panic!("search_packages called")
+        }
+        fn is_package_group(&self, _: &str) -> Result<bool> {
+            panic!("is_package_group called")
+        }
+        fn owner_of(
+            &self,
+            _: &[std::path::PathBuf],
+        ) -> Result<HashMap<std::path::PathBuf, String>> {
+            panic!("owner_of called")
+        }
+        fn passthrough(&self, _: &[String]) -> Result<std::process::ExitStatus> {
+            panic!("passthrough called")
+        }
+        fn list_orphans(&self) -> Result<Vec<String>> {
+            panic!("list_orphans called")
+        }
+        fn clean_cache(&self) -> Result<()> {
+            panic!("clean_cache called")
+        }
+        fn remove_orphans(&self) -> Result<()> {
+            panic!("remove_orphans called")
+        }
+        fn get_group_packages(&self, _: &str) -> Result<Vec<String>> {
+            panic!("get_group_packages called")
+        }
+        fn group_members(&self, _: &[String]) -> Result<HashMap<String, Vec<String>>> {
+            panic!("group_members called")
+        }
+        fn installed_info(&self) -> Result<Vec<PackageInfo>> {
+            panic!("installed_info called")
+        }
+        fn sync_info(&self, _: &[String]) -> Result<Vec<PackageInfo>> {
+            panic!("sync_info called")
+        }
+    }
+
+    #[test]
+    fn test_dotfiles_only_never_calls_package_manager() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = DotfileRoots {
+            owl_dir: dir.path().to_path_buf(),
+            source_dir: dir.path().join("dotfiles"),
+            home: dir.path().join("home").to_string_lossy().into_owned(),
+            backup_dir: dir.path().join("backups"),
+            dest_prefix: None,
+            hash_algo: crate::core::dotfiles::HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        };
+        std::fs::create_dir_all(&roots.source_dir).unwrap();
+        std::fs::write(roots.source_dir.join("gitconfig"), "[user]\n").unwrap();
+        let config = Config::parse(
+            "@package git\n:config gitconfig -> ~/.gitconfig\n:min-version 2.40\n\
+             @package docker\n:service docker\n",
+        )
+        .unwrap();
+
+        let mut events = Vec::new();
+        crate::core::pm::with_manager(
+            || Box::new(Forbidden),
+            || {
+                sync(&config, &roots, false, 1, 1, false, &mut |e: OwlEvent| {
+                    events.push(e)
+                })
+            },
+        );
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("home/.gitconfig")).unwrap(),
+            "[user]\n"
+        );
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, OwlEvent::Warning(w) if w.contains(":min-version")))
+        );
+        assert!(!events.iter().any(|e| matches!(e, OwlEvent::Error(_))));
+    }
+
+    /// Guards the test above: the override really reaches `manager()` callers
+    #[test]
+    fn test_forbidden_manager_catches_package_queries() {
+        let caught = std::panic::catch_unwind(|| {
+            crate::core::pm::with_manager(
+                || Box::new(Forbidden),
+                || crate::core::pm::manager().upgrade_count(),
+            )
+        });
+        assert!(caught.is_err());
+    }
+}
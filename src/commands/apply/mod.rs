@@ -1,87 +1,505 @@
+pub mod adoption;
 pub mod analysis;
+pub mod batches;
+pub mod db_lock;
 pub mod dotfiles;
+pub mod dotfiles_only;
 pub mod packages;
+pub mod phases;
+pub mod review;
+pub mod snapshots;
+pub mod splay;
 pub mod system;
+pub mod timings;
 
+use crate::core::checkpoint::{Checkpoint, Step};
+use crate::core::events::OwlEvent;
 use crate::error::handle_error_with_context;
 
+/// Outcome of the package phases of an apply run
+#[derive(Debug, Default)]
+pub struct ApplyResult {
+    /// `(package, duration_ms)` for each install timed with `--timing`
+    pub install_timings: Vec<(String, u64)>,
+    /// Every package of the AUR install and update session
+    pub aur_builds: Vec<crate::core::aur_builds::AurBuild>,
+    /// Why the package phases were put off to the next run (pacman database locked)
+    pub packages_deferred: Option<String>,
+}
+
+/// The `--plan-json` document
+fn plan_json(
+    config: &crate::core::config::Config,
+    to_install: &[String],
+    to_remove: &[String],
+    updates: &phases::UpdatePhases,
+    allow_outside_home: bool,
+) -> anyhow::Result<String> {
+    let plan = full_plan(config, to_install, to_remove, updates, allow_outside_home)?;
+    serde_json::to_string_pretty(&plan)
+        .map_err(|e| anyhow::anyhow!("Failed to serialize the plan: {}", e))
+}
+
+/// The package plan with the declared absent paths
+fn full_plan(
+    config: &crate::core::config::Config,
+    to_install: &[String],
+    to_remove: &[String],
+    updates: &phases::UpdatePhases,
+    allow_outside_home: bool,
+) -> anyhow::Result<crate::core::plan::ApplyPlan> {
+    let mut plan = package_plan(config, to_install, to_remove, updates)?;
+    let roots = crate::core::dotfiles::DotfileRoots::from_env()?
+        .with_allow_outside_home(allow_outside_home)
+        .with_absent(config.absent_paths());
+    plan.absent =
+        crate::core::absent::analyze(&roots, &crate::core::dotfiles::get_dotfile_mappings(config));
+    Ok(plan)
+}
+
+/// What a plain `owl apply --plan-json` would print, planned without changing anything
+pub fn current_plan() -> anyhow::Result<crate::core::plan::ApplyPlan> {
+    plan_analysis(analysis::analyze_system(true)?)
+}
+
+/// `current_plan` for a config loaded earlier
+pub fn current_plan_for(
+    config: crate::core::config::Config,
+) -> anyhow::Result<crate::core::plan::ApplyPlan> {
+    plan_analysis(analysis::analyze_loaded(true, config)?)
+}
+
+fn plan_analysis(analysis: analysis::Analysis) -> anyhow::Result<crate::core::plan::ApplyPlan> {
+    let (to_install, to_remove) = split_actions(&analysis.actions);
+    let updates =
+        phases::resolve_update_phases(&analysis.config, &phases::PhaseSelection::default());
+    full_plan(&analysis.config, &to_install, &to_remove, &updates, false)
+}
+
+/// This run's installs, removals and the upgrades its update phases would apply
+pub fn package_plan(
+    config: &crate::core::config::Config,
+    to_install: &[String],
+    to_remove: &[String],
+    updates: &phases::UpdatePhases,
+) -> anyhow::Result<crate::core::plan::ApplyPlan> {
+    let pm = crate::core::pm::manager();
+    let upgrades = match (updates.repo, updates.aur) {
+        (false, false) => Vec::new(),
+        (true, true) => pm.list_upgrades()?,
+        (false, true) => pm.get_aur_updates()?,
+        (true, false) => {
+            let aur = pm.get_aur_updates()?;
+            let mut upgrades = pm.list_upgrades()?;
+            upgrades.retain(|name| !aur.contains(name));
+            upgrades
+        }
+    };
+    let upgrades = upgrades
+        .into_iter()
+        .filter(|name| !updates.held.contains(name))
+        .collect();
+    let changes = crate::core::plan::PlannedChanges {
+        installs: to_install.to_vec(),
+        upgrades,
+        removals: to_remove.to_vec(),
+    };
+    let queried: Vec<String> = changes
+        .installs
+        .iter()
+        .chain(&changes.upgrades)
+        .cloned()
+        .collect();
+    Ok(crate::core::plan::derive(
+        &changes,
+        &pm.sync_info(&queried)?,
+        config,
+    ))
+}
+
+/// Packages to install and to remove
+pub fn split_actions(
+    actions: &[crate::core::package::PackageAction],
+) -> (Vec<String>, Vec<String>) {
+    let (mut to_install, mut to_remove) = (Vec::new(), Vec::new());
+    for action in actions {
+        match action {
+            crate::core::package::PackageAction::Install { name } => to_install.push(name.clone()),
+            crate::core::package::PackageAction::Remove { name } => to_remove.push(name.clone()),
+        }
+    }
+    (to_install, to_remove)
+}
+
+/// Number of entries in the `--timing` summary
+const TIMING_SUMMARY_LIMIT: usize = 5;
+
 /// Run the apply command to update packages and system
-#[allow(clippy::collapsible_if)]
-pub fn run(flags: &crate::cli::handler::GlobalFlags) {
-    let dry_run = flags.dry_run;
-    let non_interactive = flags.non_interactive;
-    if dry_run {
-        println!(
+pub fn run(flags: &crate::cli::handler::GlobalFlags, args: &crate::cli::handler::ApplyArgs) {
+    if args.via_daemon
+        && let Some(plan) = crate::commands::daemon::plan_json()
+    {
+        outln!("{}", plan);
+        return;
+    }
+
+    // Before anything else, so a fleet's timers do not all hit the mirror at once
+    let splay_ms = splay::run(args);
+
+    // With --plan-json stdout only carries the plan; progress goes to stderr as JSON
+    let events_json = args.events_json || args.plan_json;
+    let human = !events_json;
+    if flags.dry_run && human {
+        outln!(
             "  {} Dry run mode - no changes will be made to the system",
             crate::internal::color::blue("info:")
         );
-        println!();
+        outln!();
     }
 
-    // Perform analysis with spinner
-    let analysis_result = crate::internal::util::execute_with_progress(
-        analysis::analyze_system,
-        "Analyzing system configuration",
-    );
+    if args.dotfiles_only {
+        dotfiles_only::run(flags, args);
+        return;
+    }
+
+    let mut renderer = crate::cli::render::apply_sink(flags, events_json);
+    if let Err(err) = run_with(flags, args, splay_ms, None, &mut *renderer) {
+        if human {
+            crate::error::exit_with_error(err);
+        }
+        renderer.emit(OwlEvent::Error(err.to_string()));
+        std::process::exit(1);
+    }
+}
 
-    let mut analysis = match analysis_result {
-        Ok(result) => result,
+/// One apply run reporting to `renderer`: `config` when given (`owl daemon`
+/// keeps it loaded), otherwise the config files
+///
+/// Whether the run succeeded; an error that ends the run early is returned
+/// for the caller to report.
+#[allow(clippy::collapsible_if)]
+pub fn run_with(
+    flags: &crate::cli::handler::GlobalFlags,
+    args: &crate::cli::handler::ApplyArgs,
+    splay_ms: Option<u64>,
+    config: Option<crate::core::config::Config>,
+    renderer: &mut dyn crate::core::events::EventSink,
+) -> anyhow::Result<bool> {
+    let dry_run = flags.dry_run;
+    let non_interactive = flags.non_interactive;
+    let events_json = args.events_json || args.plan_json;
+    let human = !events_json;
+    let started = crate::internal::time::now_secs();
+    // A daemon runs many applies; only this run's errors count against it
+    let errors_before = crate::error::hard_errors();
+
+    let mut phase_timings = timings::PhaseTimings::default();
+
+    // Held for the whole run, so `owl pm` cannot change packages underneath it
+    let _lock = if dry_run { None } else { Some(writer_lock()?) };
+
+    // Before any stored timestamp is compared; dry runs only report
+    if let Ok(owl_dir) = crate::internal::environment::get().owl_dir()
+        && let Some(warning) = crate::core::clock::check(
+            &owl_dir.join(crate::internal::constants::STATE_DIR),
+            started,
+            !dry_run,
+        )
+    {
+        renderer.emit(OwlEvent::Warning(warning));
+    }
+
+    // A checkpoint is only left behind by a run that did not get to the end
+    let state_dir = crate::internal::environment::get()
+        .owl_dir()
+        .ok()
+        .map(|dir| dir.join(crate::internal::constants::STATE_DIR));
+    let interrupted = match state_dir.as_deref().map(Checkpoint::load).transpose() {
+        Ok(found) => found.flatten(),
         Err(err) => {
-            crate::error::exit_with_error(anyhow::anyhow!(err));
+            renderer.emit(OwlEvent::Warning(err.to_string()));
+            None
+        }
+    };
+    let resumed = match interrupted {
+        Some(checkpoint) if args.resume => {
+            if human {
+                outln!(
+                    "  {} Resuming the apply started {}; already done: {}",
+                    crate::internal::color::blue("info:"),
+                    crate::internal::time::format_datetime(checkpoint.started),
+                    checkpoint.summary()
+                );
+                outln!();
+            }
+            Some(checkpoint)
+        }
+        None if args.resume => return Err(anyhow::anyhow!("No interrupted apply to resume")),
+        Some(checkpoint) => {
+            renderer.emit(OwlEvent::Warning(format!(
+                "The apply started {} was interrupted (done: {}); this run starts over, \
+                 `owl apply --resume` would skip what it finished",
+                crate::internal::time::format_datetime(checkpoint.started),
+                checkpoint.summary()
+            )));
+            None
+        }
+        None => None,
+    };
+
+    // Perform analysis, with a spinner for humans
+    let analyze = move || match config {
+        Some(config) => analysis::analyze_loaded(dry_run, config),
+        None => analysis::analyze_system(dry_run),
+    };
+    let mut analysis = phase_timings.time("analysis", || {
+        if human {
+            crate::internal::util::execute_with_progress(analyze, "Analyzing system configuration")
+        } else {
+            analyze()
+        }
+    })?;
+
+    if !analysis.reconciled.is_empty() {
+        renderer.emit(OwlEvent::StateReconciled {
+            changes: analysis.reconciled.clone(),
+        });
+    }
+    for warning in &analysis.config.warnings {
+        renderer.emit(OwlEvent::Warning(warning.clone()));
+    }
+    if !args.allow_dangerous_env {
+        let vars = crate::core::env::collect_all_env_vars(&analysis.config);
+        for warning in crate::core::env::dangerous_env_warnings(&vars) {
+            renderer.emit(OwlEvent::Warning(warning));
         }
+    }
+
+    // Machine-read output has nobody at the terminal to answer
+    let confirm_policy = flags.confirm_policy(human);
+    adoption::run(
+        &mut analysis,
+        args.adopt_managed,
+        dry_run,
+        &confirm_policy,
+        &mut *renderer,
+    );
+
+    let phases = match &resumed {
+        Some(checkpoint) => resume_selection(checkpoint),
+        None => phases::PhaseSelection {
+            only: args.only.clone(),
+            skip: args.skip.clone(),
+        },
     };
+    let mut updates = phases::resolve_update_phases(&analysis.config, &phases);
 
     // Separate actions into installs and removals
-    let to_install: Vec<String> = analysis
+    let mut to_install: Vec<String> = analysis
         .actions
         .iter()
         .filter_map(|action| match action {
             crate::core::package::PackageAction::Install { name } => Some(name.clone()),
             _ => None,
         })
+        .filter(|_| phases.enabled(phases::Phase::Install))
         .collect();
 
-    let to_remove: Vec<String> = analysis
+    let mut to_remove: Vec<String> = analysis
         .actions
         .iter()
         .filter_map(|action| match action {
             crate::core::package::PackageAction::Remove { name } => Some(name.clone()),
             _ => None,
         })
+        .filter(|_| phases.enabled(phases::Phase::Remove))
         .collect();
 
-    crate::cli::ui::generate_apply_output_with_install(
-        analysis.package_count,
-        to_install.len(),
-        analysis.dotfile_count,
-        analysis.service_count,
+    if args.plan_json {
+        let json = plan_json(
+            &analysis.config,
+            &to_install,
+            &to_remove,
+            &updates,
+            args.allow_outside_home,
+        )?;
+        outln!("{}", json);
+        return Ok(true);
+    }
+
+    if let Some(dir) = &state_dir
+        && let Some(warning) = staleness_warning(&analysis.config, dir, started)
+    {
+        renderer.emit(OwlEvent::Warning(warning));
+    }
+
+    // Only what was ticked in the review file runs
+    let approval = match &args.approved_review {
+        None => None,
+        Some(path) => {
+            let approved = review::default_roots(&analysis.config).and_then(|roots| {
+                let roots = roots
+                    .with_dest_prefix(dest_prefix(args))
+                    .with_no_delete(args.no_dotfiles_delete)
+                    .with_allow_outside_home(args.allow_outside_home);
+                let current =
+                    review::gather(&analysis.config, &to_install, &to_remove, &updates, &roots)?;
+                Ok((review::approve(path, &current)?, current))
+            });
+            let (approval, current) = approved?;
+            review::restrict(
+                &approval,
+                &current,
+                &mut to_install,
+                &mut to_remove,
+                &mut updates,
+            );
+            if let Some(line) = review::skipped_line(&approval) {
+                renderer.emit(OwlEvent::Warning(line));
+            }
+            Some(approval)
+        }
+    };
+
+    // Only a run over everything can count as the last successful apply
+    let full_run = args.only.is_empty()
+        && args.skip.is_empty()
+        && approval
+            .as_ref()
+            .is_none_or(|approval| review::skipped_line(approval).is_none());
+
+    if human {
+        crate::cli::ui::generate_apply_output_with_install(
+            analysis.package_count,
+            to_install.len(),
+            analysis.dotfile_count,
+            analysis.service_count,
+            to_remove.len(),
+            analysis.config_package_count,
+            updates.note.as_deref(),
+        );
+    }
+
+    let had_uninstalled = !to_install.is_empty();
+
+    // Authenticate once up front so privileged phases don't prompt mid-run
+    let _privileged = if dry_run {
+        crate::core::privilege::Session::none()
+    } else {
+        let ops = crate::core::privilege::PrivilegedOps {
+            installs: to_install.len(),
+            removals: to_remove.len(),
+            system_update: updates.repo || updates.aur,
+            services: if phases.enabled(phases::Phase::Services) {
+                system::managed_units(&analysis.config)
+            } else {
+                Vec::new()
+            },
+            system_files: if phases.enabled(phases::Phase::Dotfiles) {
+                dotfiles::privileged_destinations(&analysis.config, args)
+            } else {
+                Vec::new()
+            },
+        };
+        match crate::core::privilege::authenticate(&ops, non_interactive) {
+            Ok(session) => session,
+            Err(e) => {
+                handle_error_with_context("authenticate with sudo", Err(e));
+                crate::core::privilege::Session::none()
+            }
+        }
+    };
+
+    // Safety net before the first mutating phase
+    let run_id = started.to_string();
+    let (snapshot_policy, mut snapshots) = snapshots::pre_apply(
+        &analysis.config,
         to_remove.len(),
-        analysis.config_package_count,
+        to_install.len() + to_remove.len(),
+        &run_id,
+        dry_run,
+        &mut *renderer,
     );
 
-    let had_uninstalled = !to_install.is_empty();
+    // Another package manager may hold the pacman lock; wait for it or defer
+    let db_lock = db_lock::LockWait::new(
+        args.db_lock_wait
+            .unwrap_or(crate::core::db_lock::DEFAULT_WAIT),
+    );
+    let package_work =
+        !to_install.is_empty() || !to_remove.is_empty() || updates.repo || updates.aur;
+    let packages_ready = dry_run || !package_work || db_lock.settle(&confirm_policy);
+
+    // Progress is recorded from here on, so an interrupted run can be resumed
+    let checkpoint = resumed.unwrap_or_else(|| Checkpoint::new(started));
+    let resumed_installs = checkpoint.installed.clone();
+    let mut progress = crate::core::checkpoint::Recorder::new(
+        &mut *renderer,
+        checkpoint,
+        state_dir.clone().filter(|_| !dry_run),
+    );
 
     // Handle removals first
-    packages::handle_removals(&to_remove, dry_run, &mut analysis.state);
+    if packages_ready {
+        packages::handle_removals(&to_remove, dry_run, &confirm_policy, &mut analysis.state);
+    }
 
     // Handle all package operations (install + update) in one combined phase
-    let package_params = packages::PackageOperationParams {
+    let mut package_params = packages::PackageOperationParams {
         dry_run,
-        non_interactive,
+        confirm: confirm_policy.clone(),
         had_uninstalled,
+        updates,
+        phases,
+        timing: args.timing,
+        approval,
+        install_batch_size: args.install_batch_size.map(|size| size as usize),
+        db_lock,
+        env_diff_context: (args.diff_env || args.diff || (dry_run && flags.verbose)).then_some(
+            flags
+                .diff_context
+                .unwrap_or(crate::core::diff::DEFAULT_CONTEXT),
+        ),
+        safe: flags.safe,
+        parallel_dotfiles: args.parallel_dotfiles_and_packages,
+        dotfile_concurrency: args
+            .dotfile_concurrency
+            .unwrap_or_else(crate::core::dotfiles::default_concurrency),
+        service_changes: args.diff,
+        keep_backups: keep_backups(args, &analysis.config),
+        dest_prefix: dest_prefix(args),
+        strict_sources: args.strict_sources,
+        hash_algo: args.hash_algo.unwrap_or_default(),
+        no_dotfiles_delete: args.no_dotfiles_delete,
+        allow_outside_home: args.allow_outside_home,
+        dotfile_divergence: args.dotfile_diverged.unwrap_or_default(),
     };
-    packages::install_and_update_packages(&to_install, &package_params, &analysis.config);
+    package_params.dotfile_divergence =
+        dotfiles::settle_divergence(&analysis.config, &package_params, args.dotfile_diverged);
+    let result = packages::install_and_update_packages(
+        &to_install,
+        &package_params,
+        &analysis.config,
+        &mut progress,
+        &mut phase_timings,
+    );
+    let failures = progress.failures();
+    let checkpoint = progress.finish();
 
-    // After operations, mark newly installed packages as managed (only if installed by our tool)
+    // After operations, mark newly installed packages as managed (only if installed by our tool),
+    // including those an interrupted run installed before it could
     if !dry_run {
         let mut changed = false;
-        for pkg in &to_install {
+        for pkg in to_install.iter().chain(&resumed_installs) {
             match crate::core::package::is_package_or_group_installed(pkg) {
                 Ok(true) => {
                     if !analysis.state.is_managed(pkg) {
                         analysis.state.add_managed(pkg.clone());
-                        changed = true;
                     }
+                    analysis
+                        .state
+                        .record_installed(pkg, crate::internal::time::now_secs());
+                    changed = true;
                 }
                 Ok(false) => {}
                 Err(e) => {
@@ -94,4 +512,447 @@ pub fn run(flags: &crate::cli::handler::GlobalFlags) {
             handle_error_with_context("save package state", analysis.state.save());
         }
     }
+
+    if args.clean_aur && packages_ready && result.packages_deferred.is_none() {
+        clean_aur(&analysis, dry_run, &package_params.confirm);
+    }
+
+    snapshots::post_apply(
+        &snapshot_policy,
+        &mut snapshots,
+        &run_id,
+        dry_run,
+        &mut *renderer,
+    );
+    // Anything reported through the renderer or `handle_error*` is a hard failure
+    let success = failures == 0
+        && crate::error::hard_errors() == errors_before
+        && result.packages_deferred.is_none();
+    if !dry_run {
+        record_history(
+            started,
+            &result,
+            &snapshots,
+            splay_ms,
+            &analysis.reconciled,
+            success,
+        );
+        if success
+            && full_run
+            && let Some(dir) = &state_dir
+        {
+            let record = crate::core::last_success::LastSuccess {
+                at: started,
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                installed: checkpoint.installed.len(),
+                removed: to_remove.len(),
+                dotfiles: checkpoint.dotfiles.len(),
+            };
+            let saved = crate::internal::environment::get()
+                .hostname()
+                .and_then(|host| crate::core::last_success::save(dir, host, record));
+            handle_error_with_context("record the successful apply", saved);
+        }
+        if let Some(dir) = &state_dir {
+            handle_error_with_context("remove the apply checkpoint", Checkpoint::clear(dir));
+        }
+    }
+
+    if args.timing && human {
+        print_timing_summary(&result);
+    }
+    if flags.verbose && human {
+        phase_timings.print();
+    }
+    Ok(success)
+}
+
+/// Phases a resumed run leaves out: those of the steps the interrupted run finished
+fn resume_selection(checkpoint: &Checkpoint) -> phases::PhaseSelection {
+    use phases::Phase;
+    let steps: [(Step, &[Phase]); 3] = [
+        (
+            Step::Packages,
+            &[
+                Phase::Install,
+                Phase::Remove,
+                Phase::RepoUpdate,
+                Phase::AurUpdate,
+            ],
+        ),
+        (Step::Dotfiles, &[Phase::Dotfiles]),
+        (Step::System, &[Phase::Services, Phase::Env]),
+    ];
+    phases::PhaseSelection {
+        only: Vec::new(),
+        skip: steps
+            .into_iter()
+            .filter(|(step, _)| checkpoint.is_complete(*step))
+            .flat_map(|(_, phases)| phases.iter().copied())
+            .collect(),
+    }
+}
+
+/// `--clean-aur`: the cache and orphan cleanup of `owl clean --aur`
+fn clean_aur(
+    analysis: &analysis::Analysis,
+    dry_run: bool,
+    policy: &crate::core::confirm::ConfirmPolicy,
+) {
+    let cleaned = crate::internal::environment::get().home().and_then(|home| {
+        crate::commands::clean::clean_aur(
+            crate::core::pm::manager().as_ref(),
+            &crate::commands::clean::protected_packages(&analysis.config, &analysis.state),
+            &crate::commands::clean::cache_dirs(home),
+            dry_run,
+            policy,
+        )
+    });
+    handle_error_with_context("clean the package cache", cleaned.map(|_| ()));
+}
+
+fn writer_lock() -> anyhow::Result<crate::core::lock::WriterLock> {
+    let env = crate::internal::environment::get();
+    crate::core::lock::WriterLock::acquire(
+        &env.owl_dir()?.join(crate::internal::constants::STATE_DIR),
+    )
+}
+
+/// Backups kept per dotfile destination: `--keep-backups`, then `@backups-keep`
+fn keep_backups(
+    args: &crate::cli::handler::ApplyArgs,
+    config: &crate::core::config::Config,
+) -> usize {
+    args.keep_backups
+        .map_or_else(|| config.keep_backups(), |keep| keep as usize)
+}
+
+/// `--dest-prefix` as an absolute path, so it does not depend on where dotfiles are written from
+fn dest_prefix(args: &crate::cli::handler::ApplyArgs) -> Option<std::path::PathBuf> {
+    args.dest_prefix
+        .as_deref()
+        .map(|prefix| std::path::absolute(prefix).unwrap_or_else(|_| prefix.to_path_buf()))
+}
+
+/// Append this run to `history.json`
+fn record_history(
+    started: u64,
+    result: &ApplyResult,
+    snapshots: &snapshots::RunSnapshots,
+    splay_ms: Option<u64>,
+    reconciled: &[crate::core::reconcile::StateChange],
+    success: bool,
+) {
+    let record = crate::core::history::ApplyRecord {
+        started,
+        install_timings: result
+            .install_timings
+            .iter()
+            .map(
+                |(package, duration_ms)| crate::core::history::InstallTiming {
+                    package: package.clone(),
+                    duration_ms: *duration_ms,
+                },
+            )
+            .collect(),
+        aur_builds: result.aur_builds.clone(),
+        pre_snapshot: snapshots.pre.clone(),
+        post_snapshot: snapshots.post.clone(),
+        splay_ms,
+        reconciled: reconciled.to_vec(),
+        packages_deferred: result.packages_deferred.clone(),
+        success: Some(success),
+    };
+    let saved = crate::core::history::History::load().and_then(|mut history| {
+        history.record(record);
+        history.save()
+    });
+    handle_error_with_context("record apply history", saved);
+}
+
+/// The startup warning about an old last success or a failed last run
+fn staleness_warning(
+    config: &crate::core::config::Config,
+    state_dir: &std::path::Path,
+    now: u64,
+) -> Option<String> {
+    let stale_after = match config.stale_after() {
+        Ok(stale_after) => stale_after,
+        Err(err) => return Some(err.to_string()),
+    };
+    let last = crate::internal::environment::get()
+        .hostname()
+        .and_then(|host| crate::core::last_success::load(state_dir, host));
+    let last = match last {
+        Ok(last) => last,
+        Err(err) => return Some(err.to_string()),
+    };
+    let last_run = crate::core::history::History::load()
+        .ok()
+        .and_then(|history| history.runs.last().map(|run| (run.started, run.success)));
+    crate::core::last_success::assess(last.as_ref(), last_run, now, stale_after)
+}
+
+fn print_timing_summary(result: &ApplyResult) {
+    if result.install_timings.is_empty() {
+        return;
+    }
+    let mut timings = result.install_timings.clone();
+    timings.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let ranked: Vec<String> = timings
+        .iter()
+        .take(TIMING_SUMMARY_LIMIT)
+        .enumerate()
+        .map(|(i, (package, ms))| {
+            format!(
+                "{}. {} {}",
+                i + 1,
+                package,
+                crate::internal::time::format_duration_ms(*ms)
+            )
+        })
+        .collect();
+    outln!();
+    outln!("  Slowest installs: {}", ranked.join("  "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::Config;
+    use crate::core::dotfiles::{DotfileAction, DotfileMapping, DotfileRoots, DotfileStatus};
+    use crate::core::events::{EventPhase, EventSink};
+
+    fn dry_run_params(phases: phases::PhaseSelection) -> packages::PackageOperationParams {
+        packages::PackageOperationParams {
+            dry_run: true,
+            confirm: Default::default(),
+            had_uninstalled: false,
+            updates: phases::UpdatePhases {
+                repo: true,
+                aur: true,
+                note: None,
+                held: Vec::new(),
+            },
+            phases,
+            timing: false,
+            approval: None,
+            install_batch_size: None,
+            db_lock: db_lock::LockWait::new(std::time::Duration::ZERO),
+            env_diff_context: None,
+            safe: false,
+            service_changes: false,
+            parallel_dotfiles: false,
+            dotfile_concurrency: 2,
+            keep_backups: 1,
+            dest_prefix: None,
+            strict_sources: false,
+            hash_algo: crate::core::dotfiles::HashAlgo::default(),
+            no_dotfiles_delete: false,
+            allow_outside_home: false,
+            dotfile_divergence: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_dry_run_event_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = DotfileRoots {
+            owl_dir: dir.path().to_path_buf(),
+            source_dir: dir.path().join("dotfiles"),
+            home: dir.path().join("home").to_string_lossy().into_owned(),
+            backup_dir: dir.path().join("backups"),
+            dest_prefix: None,
+            hash_algo: crate::core::dotfiles::HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        };
+        std::fs::create_dir_all(roots.source_dir.join("nvim")).unwrap();
+        std::fs::write(roots.source_dir.join("nvim/init.lua"), "-- init").unwrap();
+        let config = Config::parse(
+            "@package neovim\n:config nvim -> ~/.config/nvim\n:env EDITOR=nvim\n\
+             @package docker\n:service docker\n@env LANG=C.UTF-8\n",
+        )
+        .unwrap();
+
+        let mut events = Vec::new();
+        let mut record = |event: OwlEvent| events.push(event);
+        let mappings = crate::core::dotfiles::get_dotfile_mappings(&config);
+        crate::core::dotfiles::sync_dotfiles(&roots, &mappings, true, 2, 1, None, &mut record)
+            .unwrap();
+        system::handle_system_section_with_config(
+            &config,
+            &dry_run_params(phases::PhaseSelection::default()),
+            &mut record,
+            &mut timings::PhaseTimings::default(),
+        );
+
+        assert_eq!(
+            events,
+            vec![
+                OwlEvent::PhaseStarted(EventPhase::Dotfiles),
+                OwlEvent::DotfileActionCompleted {
+                    action: DotfileAction {
+                        mapping: DotfileMapping {
+                            source: "nvim".to_string(),
+                            destination: "~/.config/nvim".to_string(),
+                            root: None,
+                            from: None,
+                            hardlink: false,
+                            force_owned: false,
+                            modes: Default::default(),
+                        },
+                        status: DotfileStatus::Create,
+                    },
+                },
+                OwlEvent::DotfilesFinished {
+                    up_to_date: 0,
+                    dry_run: true,
+                },
+                OwlEvent::PhaseFinished(EventPhase::Dotfiles),
+                OwlEvent::PhaseStarted(EventPhase::System),
+                OwlEvent::ServicesPlanned {
+                    services: vec!["docker.service".to_string()],
+                },
+                OwlEvent::EnvPlanned {
+                    vars: vec![
+                        crate::core::env::EnvVar {
+                            key: "EDITOR".to_string(),
+                            value: "nvim".to_string(),
+                            shell: None,
+                        },
+                        crate::core::env::EnvVar {
+                            key: "LANG".to_string(),
+                            value: "C.UTF-8".to_string(),
+                            shell: None,
+                        },
+                    ],
+                },
+                OwlEvent::PhaseFinished(EventPhase::System),
+            ]
+        );
+        // Dry run leaves the destination untouched
+        assert!(!dir.path().join("home/.config/nvim").exists());
+    }
+
+    #[test]
+    fn test_events_json_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = DotfileRoots {
+            owl_dir: dir.path().to_path_buf(),
+            source_dir: dir.path().join("dotfiles"),
+            home: dir.path().join("home").to_string_lossy().into_owned(),
+            backup_dir: dir.path().join("backups"),
+            dest_prefix: None,
+            hash_algo: crate::core::dotfiles::HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        };
+        std::fs::create_dir_all(&roots.source_dir).unwrap();
+        std::fs::write(roots.source_dir.join("gitconfig"), "[user]\n").unwrap();
+        let config = Config::parse(
+            "@package git\n:config gitconfig -> ~/.gitconfig\n@package docker\n:service docker\n",
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        {
+            let mut sink = crate::cli::render::JsonLinesRenderer::new(&mut out);
+            sink.emit(OwlEvent::PhaseStarted(EventPhase::Packages));
+            sink.emit(OwlEvent::PackageInstallStarted {
+                name: "git".to_string(),
+            });
+            sink.emit(OwlEvent::PackageInstallFinished {
+                name: "git".to_string(),
+                success: true,
+                duration_ms: Some(1200),
+            });
+            sink.emit(OwlEvent::PhaseFinished(EventPhase::Packages));
+            let mappings = crate::core::dotfiles::get_dotfile_mappings(&config);
+            crate::core::dotfiles::sync_dotfiles(&roots, &mappings, true, 2, 1, None, &mut sink)
+                .unwrap();
+            system::handle_system_section_with_config(
+                &config,
+                &dry_run_params(phases::PhaseSelection {
+                    only: Vec::new(),
+                    skip: vec![phases::Phase::Env],
+                }),
+                &mut sink,
+                &mut timings::PhaseTimings::default(),
+            );
+        }
+
+        let events: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            types,
+            vec![
+                "phase_started",
+                "package_install_started",
+                "package_install_finished",
+                "phase_finished",
+                "phase_started",
+                "dotfile_action",
+                "dotfiles_finished",
+                "phase_finished",
+                "phase_started",
+                "services_planned",
+                "phase_finished",
+            ]
+        );
+        assert!(events.iter().all(|e| e["schema"] == 1));
+        assert_eq!(events[0]["phase"], "packages");
+        assert_eq!(events[2]["duration_ms"], 1200);
+        assert_eq!(events[4]["phase"], "dotfiles");
+        assert_eq!(events[5]["destination"], "~/.gitconfig");
+        assert_eq!(events[5]["status"], "create");
+        assert_eq!(events[9]["services"][0], "docker.service");
+    }
+
+    #[test]
+    fn test_dry_run_records_every_phase_timing() {
+        let config =
+            Config::parse("@package docker\n:service docker\n@env LANG=C.UTF-8\n").unwrap();
+        let params = dry_run_params(phases::PhaseSelection::default());
+        let mut phase_timings = timings::PhaseTimings::default();
+        packages::install_and_update_packages(
+            &[],
+            &params,
+            &config,
+            &mut |_: OwlEvent| {},
+            &mut phase_timings,
+        );
+
+        let names: Vec<&str> = phase_timings
+            .phases()
+            .iter()
+            .map(|(name, _)| *name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "categorization",
+                "repo install",
+                "aur",
+                "repo update",
+                "dotfiles",
+                "services",
+                "env"
+            ]
+        );
+    }
 }
@@ -1,17 +1,58 @@
-use crate::core::pm::PackageManager;
+use crate::core::aur_builds::{self, AurBuild};
+use crate::core::confirm::ConfirmKind;
+use crate::core::events::{EventPhase, EventSink, OwlEvent};
 use crate::error::{handle_error, handle_error_with_context};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::time::Instant;
 
 /// Parameters for package operations
 #[derive(Debug)]
 pub struct PackageOperationParams {
     pub dry_run: bool,
-    pub non_interactive: bool,
+    /// How AUR builds are confirmed
+    pub confirm: crate::core::confirm::ConfirmPolicy,
     pub had_uninstalled: bool,
+    pub updates: super::phases::UpdatePhases,
+    pub phases: super::phases::PhaseSelection,
+    /// Install one package per transaction so each can be timed
+    pub timing: bool,
+    /// Ticks from `--approved-review`; unticked AUR upgrades and dotfiles are skipped
+    pub approval: Option<crate::core::review::Approval>,
+    /// Packages per install transaction (`--install-batch-size`), all at once if unset
+    pub install_batch_size: Option<usize>,
+    /// Waiting for the pacman database lock, and whether the package phases gave up on it
+    pub db_lock: super::db_lock::LockWait,
+    /// Context lines for the env file diff, set by `--diff-env`, `--diff` or dry-run verbose
+    pub env_diff_context: Option<usize>,
+    /// Run no commands from the config, such as `<cmd:...>` env values (`--safe`)
+    pub safe: bool,
+    /// Dry run: report what would change for each service (`--diff`)
+    pub service_changes: bool,
+    /// Sync dotfiles during the package phase (`--parallel-dotfiles-and-packages`)
+    pub parallel_dotfiles: bool,
+    /// Threads used to analyse dotfiles (`--dotfile-concurrency`)
+    pub dotfile_concurrency: usize,
+    /// Backups kept per dotfile destination (`--keep-backups` or `@backups-keep`)
+    pub keep_backups: usize,
+    /// Directory dotfile destinations are staged under (`--dest-prefix`)
+    pub dest_prefix: Option<std::path::PathBuf>,
+    /// Problems in dotfile sources stop the dotfile sync (`--strict-sources`)
+    pub strict_sources: bool,
+    /// Hash comparing dotfile contents (`--hash-algo`)
+    pub hash_algo: crate::core::dotfiles::HashAlgo,
+    /// Merge into dotfile destinations without deleting anything (`--no-dotfiles-delete`)
+    pub no_dotfiles_delete: bool,
+    /// Write absolute dotfile destinations outside home (`--allow-outside-home`)
+    pub allow_outside_home: bool,
+    /// Dotfile destinations changed both locally and in their source (`--dotfile-diverged`)
+    pub dotfile_divergence: crate::core::dotfiles::Divergence,
 }
 
 pub fn handle_removals(
     to_remove: &[String],
     dry_run: bool,
+    policy: &crate::core::confirm::ConfirmPolicy,
     state: &mut crate::core::state::PackageState,
 ) {
     if to_remove.is_empty() {
@@ -19,15 +60,15 @@ pub fn handle_removals(
     }
 
     if dry_run {
-        println!("Package cleanup (would remove conflicting packages):");
+        outln!("Package cleanup (would remove conflicting packages):");
         for package in to_remove {
-            println!(
+            outln!(
                 "  {} Would remove: {}",
                 crate::internal::color::red("remove"),
                 crate::internal::color::yellow(package)
             );
         }
-        println!(
+        outln!(
             "  {} Would remove {} package(s)",
             crate::internal::color::blue("info:"),
             to_remove.len()
@@ -35,9 +76,8 @@ pub fn handle_removals(
         return;
     }
 
-    // Ask for explicit confirmation before removing packages
-    if !crate::cli::ui::confirm_remove_operation(to_remove) {
-        println!(
+    if !crate::cli::ui::confirm(ConfirmKind::Removal, to_remove, policy) {
+        outln!(
             "  {}",
             crate::internal::color::blue("Package removal cancelled")
         );
@@ -45,7 +85,7 @@ pub fn handle_removals(
     }
 
     if let Err(e) = crate::core::package::remove_unmanaged_packages(to_remove, true) {
-        eprintln!(
+        errln!(
             "{}",
             crate::internal::color::red(&format!("Failed to remove packages: {}", e))
         );
@@ -58,40 +98,159 @@ pub fn handle_removals(
     }
 
     if let Err(e) = state.save() {
-        eprintln!(
+        errln!(
             "{}",
             crate::internal::color::red(&format!("Failed to update package state: {}", e))
         );
     }
 }
 
-/// Install missing packages and update all packages
+/// Install missing packages and update all packages, then sync dotfiles and
+/// apply the system section
+///
+/// With `parallel_dotfiles` the dotfiles sync on a thread of their own while
+/// the packages install; their output and events are held back and replayed
+/// after the package phase, so each phase still reads as one block.
 pub fn install_and_update_packages(
     to_install: &[String],
     params: &PackageOperationParams,
     config: &crate::core::config::Config,
-) {
-    // First, handle uninstalled packages
-    let (repo_to_install, aur_to_install) = categorize_install_sets(to_install);
+    sink: &mut dyn EventSink,
+    timings: &mut super::timings::PhaseTimings,
+) -> super::ApplyResult {
+    let dotfiles = params.phases.enabled(super::phases::Phase::Dotfiles);
+    let result = if dotfiles && params.parallel_dotfiles {
+        std::thread::scope(|scope| {
+            // Packages stay on this thread: AUR builds and removals prompt
+            let worker = scope.spawn(|| BufferedDotfiles::sync(config, params));
+            let result = package_phase(to_install, params, config, sink, timings);
+            let dotfiles = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            timings.record("dotfiles", dotfiles.duration_ms);
+            dotfiles.replay(sink);
+            result
+        })
+    } else {
+        let result = package_phase(to_install, params, config, sink, timings);
+        if dotfiles {
+            timings.time("dotfiles", || {
+                super::dotfiles::apply_dotfiles_with_config(config, params, sink)
+            });
+        }
+        result
+    };
+
+    // Handle system section (services + environment)
+    super::system::handle_system_section_with_config(config, params, sink, timings);
+
+    result
+}
+
+/// A dotfile sync run on another thread, its output held back
+struct BufferedDotfiles {
+    lines: Vec<crate::internal::output::Captured>,
+    /// Each event with the number of lines written before it
+    events: Vec<(usize, OwlEvent)>,
+    duration_ms: u64,
+}
 
-    // Get AUR packages that need updates
-    let aur_to_update = compute_aur_updates(params.dry_run);
+impl BufferedDotfiles {
+    fn sync(config: &crate::core::config::Config, params: &PackageOperationParams) -> Self {
+        let start = Instant::now();
+        let mut events = Vec::new();
+        let ((), lines) = crate::internal::output::capture(|| {
+            super::dotfiles::apply_dotfiles_with_config(config, params, &mut |event| {
+                events.push((crate::internal::output::captured_len(), event))
+            })
+        });
+        Self {
+            lines,
+            events,
+            duration_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+
+    /// Write the lines and emit the events in the order they happened
+    fn replay(self, sink: &mut dyn EventSink) {
+        let output = crate::internal::output::get();
+        let mut events = self.events.into_iter().peekable();
+        for (written, line) in self.lines.iter().enumerate() {
+            while let Some((_, event)) = events.next_if(|(at, _)| *at <= written) {
+                sink.emit(event);
+            }
+            output.replay(line);
+        }
+        for (_, event) in events {
+            sink.emit(event);
+        }
+    }
+}
+
+/// Install the missing packages and update the installed ones
+fn package_phase(
+    to_install: &[String],
+    params: &PackageOperationParams,
+    config: &crate::core::config::Config,
+    sink: &mut dyn EventSink,
+    timings: &mut super::timings::PhaseTimings,
+) -> super::ApplyResult {
+    let mut result = super::ApplyResult::default();
+    sink.emit(OwlEvent::PhaseStarted(EventPhase::Packages));
+    // Deferred before the first transaction: the rest of the run goes ahead
+    let deferred = params.db_lock.is_deferred();
+    // First, handle uninstalled packages
+    let (repo_to_install, aur_to_install) = timings.time("categorization", || {
+        if deferred {
+            return (Vec::new(), Vec::new());
+        }
+        categorize_install_sets(to_install, &config.aur_hinted())
+    });
 
     // Install repo packages first (no confirmation needed)
-    install_repo_packages(&repo_to_install, params.dry_run);
+    timings.time("repo install", || {
+        install_repo_packages(
+            &repo_to_install,
+            params,
+            config.mirror_refresh().as_deref(),
+            params.timing.then_some(&mut result.install_timings),
+            sink,
+        )
+    });
+
+    timings.time("aur", || {
+        // Get AUR packages that need updates
+        let mut aur_to_update = if params.updates.aur && !deferred {
+            compute_aur_updates(params.dry_run)
+        } else {
+            Vec::new()
+        };
+        let held: Vec<String> = aur_to_update
+            .iter()
+            .filter(|name| params.updates.held.contains(name))
+            .cloned()
+            .collect();
+        aur_to_update.retain(|name| !held.contains(name));
+        report_held("AUR updates", &held);
+        if let Some(approval) = &params.approval {
+            aur_to_update
+                .retain(|name| approval.approves(crate::core::review::Section::Upgrade, name));
+        }
 
-    // Handle all AUR packages together if there are any
-    if !aur_to_install.is_empty() || !aur_to_update.is_empty() {
+        // Handle all AUR packages together if there are any
+        if aur_to_install.is_empty() && aur_to_update.is_empty() {
+            return;
+        }
         // Show detailed breakdown of what will happen
         if !aur_to_install.is_empty() {
-            println!(
+            outln!(
                 "  {} AUR packages to install: {}",
                 crate::internal::color::yellow(&aur_to_install.len().to_string()),
-                aur_to_install.join(", ")
+                display_names(config, &aur_to_install)
             );
         }
         if !aur_to_update.is_empty() {
-            println!(
+            outln!(
                 "  {} AUR packages to update: {}",
                 crate::internal::color::yellow(&aur_to_update.len().to_string()),
                 aur_to_update.join(", ")
@@ -101,31 +260,57 @@ pub fn install_and_update_packages(
         handle_aur_operations(
             &aur_to_install,
             &aur_to_update,
-            params.dry_run,
-            params.non_interactive,
+            params,
+            params.timing.then_some(&mut result.install_timings),
+            &mut result.aur_builds,
+            sink,
         );
-    }
+    });
 
     // Add blank line if we installed packages before this
     if params.had_uninstalled {
-        println!();
+        outln!();
     }
 
     // Update repo packages
-    update_repo_packages(params.dry_run);
-
-    // Apply dotfile synchronization
-    super::dotfiles::apply_dotfiles_with_config(config, params.dry_run);
+    if params.updates.repo && !deferred {
+        timings.time("repo update", || {
+            update_repo_packages(params, config.mirror_refresh().as_deref())
+        });
+    }
+    result.packages_deferred = params.db_lock.deferred_reason();
+    if let Some(reason) = &result.packages_deferred {
+        sink.emit(OwlEvent::PackagesDeferred {
+            reason: reason.clone(),
+        });
+    }
+    sink.emit(OwlEvent::PhaseFinished(EventPhase::Packages));
+    result
+}
 
-    // Handle system section (services + environment)
-    super::system::handle_system_section_with_config(config, params.dry_run);
+/// `names` joined for display, each under its declared name and, where
+/// `@arch-aur-prefix` renamed it, the name it is installed as
+fn display_names(config: &crate::core::config::Config, names: &[String]) -> String {
+    names
+        .iter()
+        .map(
+            |name| match config.declared_name(name, std::env::consts::ARCH) {
+                declared if declared != name => format!("{} ({})", declared, name),
+                _ => name.clone(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-pub fn categorize_install_sets(to_install: &[String]) -> (Vec<String>, Vec<String>) {
+pub fn categorize_install_sets(
+    to_install: &[String],
+    force_aur: &HashSet<String>,
+) -> (Vec<String>, Vec<String>) {
     if to_install.is_empty() {
         return (Vec::new(), Vec::new());
     }
-    match crate::core::package::categorize_packages(to_install) {
+    match crate::core::package::categorize_packages(to_install, force_aur) {
         Ok(result) => result,
         Err(e) => {
             handle_error_with_context("categorize packages", Err(e));
@@ -147,68 +332,193 @@ pub fn compute_aur_updates(dry_run: bool) -> Vec<String> {
     }
 }
 
-pub fn install_repo_packages(repo_to_install: &[String], dry_run: bool) {
+/// Install `packages` in batches of `batch_size` (one transaction if unset), or
+/// one at a time recording how long each took
+fn install_timed(
+    packages: &[String],
+    batch_size: Option<usize>,
+    timings: Option<&mut Vec<(String, u64)>>,
+    sink: &mut dyn EventSink,
+    mut install: impl FnMut(&[String]) -> Result<()>,
+) {
+    let Some(timings) = timings else {
+        let failed = super::batches::install(packages, batch_size, |batch| {
+            for name in batch {
+                sink.emit(OwlEvent::PackageInstallStarted { name: name.clone() });
+            }
+            let result = install(batch);
+            for name in batch {
+                sink.emit(OwlEvent::PackageInstallFinished {
+                    name: name.clone(),
+                    success: result.is_ok(),
+                    duration_ms: None,
+                });
+            }
+            result
+        });
+        for batch in &failed {
+            errln!("{}", crate::internal::color::red(&batch.error));
+        }
+        let batches = super::batches::split(packages, batch_size).len();
+        if let Some(summary) = super::batches::summary(&failed, batches) {
+            errln!("  {}", crate::internal::color::red(&summary));
+        }
+        return;
+    };
+    for package in packages {
+        sink.emit(OwlEvent::PackageInstallStarted {
+            name: package.clone(),
+        });
+        let start = Instant::now();
+        let result = install(std::slice::from_ref(package));
+        let duration_ms = start.elapsed().as_millis() as u64;
+        if result.is_ok() {
+            timings.push((package.clone(), duration_ms));
+        }
+        sink.emit(OwlEvent::PackageInstallFinished {
+            name: package.clone(),
+            success: result.is_ok(),
+            duration_ms: Some(duration_ms),
+        });
+        handle_error(result);
+    }
+}
+
+pub fn install_repo_packages(
+    repo_to_install: &[String],
+    params: &PackageOperationParams,
+    mirror_refresh: Option<&str>,
+    timings: Option<&mut Vec<(String, u64)>>,
+    sink: &mut dyn EventSink,
+) {
     if repo_to_install.is_empty() {
         return;
     }
-    println!(
+    outln!(
         "  {} repo packages found: {}",
         crate::internal::color::yellow(&repo_to_install.len().to_string()),
         repo_to_install.join(", ")
     );
-    if dry_run {
-        println!(
+    if params.dry_run {
+        outln!(
             "  {} Would install {} from official repositories",
             crate::internal::color::blue("info:"),
             repo_to_install.join(", ")
         );
     } else {
-        handle_error(crate::core::pm::ParuPacman::new().install_repo(repo_to_install));
+        install_timed(
+            repo_to_install,
+            params.install_batch_size,
+            timings,
+            sink,
+            |pkgs| {
+                params.db_lock.retry(&params.confirm, || {
+                    crate::core::pm::with_mirror_refresh(mirror_refresh, || {
+                        crate::core::pm::manager().install_repo(pkgs)
+                    })
+                })
+            },
+        );
     }
 }
 
 pub fn handle_aur_operations(
     aur_to_install: &[String],
     aur_to_update: &[String],
-    dry_run: bool,
-    non_interactive: bool,
+    params: &PackageOperationParams,
+    timings: Option<&mut Vec<(String, u64)>>,
+    builds: &mut Vec<AurBuild>,
+    sink: &mut dyn EventSink,
 ) {
-    // Create combined list only when needed for confirmation/display
-    let all_aur_packages: Vec<String> = aur_to_install
-        .iter()
-        .chain(aur_to_update.iter())
-        .cloned()
-        .collect();
-
-    if dry_run
-        || non_interactive
-        || crate::cli::ui::confirm_aur_operation(&all_aur_packages, "installing/updating")
-    {
-        if dry_run {
-            println!(
-                "  {} Would install/update {} from AUR",
-                crate::internal::color::blue("info:"),
-                all_aur_packages.join(", ")
-            );
-            return;
-        }
-        if !aur_to_install.is_empty() {
-            handle_error(crate::core::pm::ParuPacman::new().install_aur(aur_to_install));
-        }
-        if !aur_to_update.is_empty() {
-            handle_error(crate::core::pm::ParuPacman::new().update_aur(aur_to_update));
+    if params.dry_run {
+        let all_aur_packages: Vec<String> = aur_to_install
+            .iter()
+            .chain(aur_to_update.iter())
+            .cloned()
+            .collect();
+        outln!(
+            "  {} Would install/update {} from AUR",
+            crate::internal::color::blue("info:"),
+            all_aur_packages.join(", ")
+        );
+        return;
+    }
+
+    // Each list is confirmed on its own, so declining updates still installs
+    let confirmed = |kind, packages: &[String]| {
+        if packages.is_empty() || crate::cli::ui::confirm(kind, packages, &params.confirm) {
+            return true;
         }
-    } else {
-        println!(
+        outln!(
             "  {}",
             crate::internal::color::blue("AUR package operations cancelled")
         );
+        false
+    };
+    let install = confirmed(ConfirmKind::AurInstall, aur_to_install);
+    let update = confirmed(ConfirmKind::AurUpdate, aur_to_update);
+
+    let first = builds.len();
+    if install && !aur_to_install.is_empty() {
+        install_timed(
+            aur_to_install,
+            params.install_batch_size,
+            timings,
+            sink,
+            |pkgs| {
+                params.db_lock.retry(&params.confirm, || {
+                    crate::core::pm::manager().install_aur(pkgs, builds)
+                })
+            },
+        );
     }
+    if update && !aur_to_update.is_empty() {
+        handle_error(params.db_lock.retry(&params.confirm, || {
+            crate::core::pm::manager().update_aur(aur_to_update, &params.updates.held, builds)
+        }));
+    }
+    // Each paru call numbers its own builds; number them across the session
+    for (position, build) in builds[first..].iter_mut().enumerate() {
+        build.position = position + 1;
+    }
+    print_aur_report(&builds[first..]);
 }
 
-pub fn update_repo_packages(dry_run: bool) {
-    if dry_run {
-        println!(
+/// Where the AUR session spent its time, slowest first
+fn print_aur_report(builds: &[AurBuild]) {
+    if builds.is_empty() {
+        return;
+    }
+    outln!();
+    outln!("  AUR builds by duration:");
+    for line in aur_builds::table(builds) {
+        outln!("  {}", line);
+    }
+    let settings = crate::internal::environment::get()
+        .home()
+        .map(aur_builds::BuildSettings::read)
+        .unwrap_or_default();
+    if let Some(hint) = aur_builds::reorder_hint(builds, &settings) {
+        outln!("  {} {}", crate::internal::color::blue("hint:"), hint);
+    }
+}
+
+/// Tell which packages `[no-upgrade]` kept out of `what`
+fn report_held(what: &str, held: &[String]) {
+    if !held.is_empty() {
+        outln!(
+            "  {} Held back from {} (no-upgrade): {}",
+            crate::internal::color::blue("info:"),
+            what,
+            held.join(", ")
+        );
+    }
+}
+
+pub fn update_repo_packages(params: &PackageOperationParams, mirror_refresh: Option<&str>) {
+    report_held("repo updates", &params.updates.held);
+    if params.dry_run {
+        outln!(
             "  {} Would update official repository packages",
             crate::internal::color::blue("info:")
         );
@@ -216,6 +526,10 @@ pub fn update_repo_packages(dry_run: bool) {
     }
     handle_error_with_context(
         "update repo packages",
-        crate::core::pm::ParuPacman::new().update_repo(),
+        params.db_lock.retry(&params.confirm, || {
+            crate::core::pm::with_mirror_refresh(mirror_refresh, || {
+                crate::core::pm::manager().update_repo(&params.updates.held)
+            })
+        }),
     );
 }
@@ -0,0 +1,221 @@
+//! Phase selection for apply (`--only` / `--skip` and config update policy)
+
+use crate::core::config::Config;
+use crate::core::config::options::AutoUpdate;
+
+/// Apply phases that can be selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Phase {
+    /// Install missing packages
+    Install,
+    /// Remove packages dropped from config
+    Remove,
+    /// Blanket update of official repository packages
+    RepoUpdate,
+    /// Update installed AUR packages
+    AurUpdate,
+    /// Synchronize dotfiles
+    Dotfiles,
+    /// Enable and start services
+    Services,
+    /// Write environment files
+    Env,
+}
+
+/// Phases requested via `--only` / `--skip`
+#[derive(Debug, Clone, Default)]
+pub struct PhaseSelection {
+    pub only: Vec<Phase>,
+    pub skip: Vec<Phase>,
+}
+
+impl PhaseSelection {
+    /// Whether the phase runs according to the command line alone
+    pub fn enabled(&self, phase: Phase) -> bool {
+        if !self.only.is_empty() {
+            return self.only.contains(&phase);
+        }
+        !self.skip.contains(&phase)
+    }
+
+    /// Whether an update phase runs under `policy`: `--only` decides which
+    /// phases run at all, `--skip` only turns off what the policy allows
+    fn update_enabled(&self, phase: Phase, policy: bool) -> bool {
+        if !self.only.is_empty() {
+            return self.only.contains(&phase);
+        }
+        policy && !self.skip.contains(&phase)
+    }
+}
+
+/// Resolved update phases and the header note explaining them
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdatePhases {
+    pub repo: bool,
+    pub aur: bool,
+    /// Shown in the plan header when updates are restricted
+    pub note: Option<String>,
+    /// Packages kept out of both updates (`[no-upgrade]`), sorted
+    pub held: Vec<String>,
+}
+
+/// Combine `@option auto_update` with `--only`/`--skip`, which win for the
+/// phases they name
+pub fn resolve_update_phases(config: &Config, phases: &PhaseSelection) -> UpdatePhases {
+    let option = config.option("auto_update");
+    let policy = match config.auto_update() {
+        Ok(policy) => policy,
+        Err(e) => {
+            errln!("{}", crate::internal::color::red(&e.to_string()));
+            AutoUpdate::All
+        }
+    };
+
+    let repo = phases.update_enabled(Phase::RepoUpdate, policy.repo());
+    let aur = phases.update_enabled(Phase::AurUpdate, policy.aur());
+    let by_command_line = !phases.only.is_empty()
+        || phases.skip.contains(&Phase::RepoUpdate)
+        || phases.skip.contains(&Phase::AurUpdate);
+    // `--only` replaces the policy; `--skip` adds to it
+    let by_config = option
+        .filter(|_| policy != AutoUpdate::All && phases.only.is_empty())
+        .map(|opt| opt.source_display());
+
+    let state = match (repo, aur) {
+        (true, true) => "enabled",
+        (true, false) => "repo only",
+        (false, true) => "aur only",
+        (false, false) => "disabled",
+    };
+    let note = match (by_config, by_command_line) {
+        _ if repo && aur => None,
+        (Some(source), true) => Some(format!("{} by {} and the command line", state, source)),
+        (Some(source), false) => Some(format!("{} by {}", state, source)),
+        (None, true) => Some(format!("{} (command line)", state)),
+        (None, false) => None,
+    };
+
+    UpdatePhases {
+        repo,
+        aur,
+        note,
+        held: config.held_packages(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(value: &str) -> Config {
+        let mut config = Config::parse(&format!("@option auto_update={}", value)).unwrap();
+        config.set_option_source("hosts/server1.owl");
+        config
+    }
+
+    #[test]
+    fn test_auto_update_fixtures() {
+        let none = PhaseSelection::default();
+        for (value, repo, aur, note) in [
+            ("never", false, false, Some("disabled by hosts/server1.owl")),
+            (
+                "aur-only",
+                false,
+                true,
+                Some("aur only by hosts/server1.owl"),
+            ),
+            (
+                "repo-only",
+                true,
+                false,
+                Some("repo only by hosts/server1.owl"),
+            ),
+            ("all", true, true, None),
+        ] {
+            let resolved = resolve_update_phases(&fixture(value), &none);
+            assert_eq!(resolved.repo, repo, "{}", value);
+            assert_eq!(resolved.aur, aur, "{}", value);
+            assert_eq!(resolved.note.as_deref(), note, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_default_runs_all_updates() {
+        let resolved = resolve_update_phases(&Config::new(), &PhaseSelection::default());
+        assert!(resolved.repo && resolved.aur);
+        assert_eq!(resolved.note, None);
+    }
+
+    #[test]
+    fn test_cli_overrides_config() {
+        let only_repo = PhaseSelection {
+            only: vec![Phase::RepoUpdate],
+            skip: Vec::new(),
+        };
+        let resolved = resolve_update_phases(&fixture("never"), &only_repo);
+        assert!(resolved.repo);
+        assert!(!resolved.aur);
+        assert_eq!(resolved.note.as_deref(), Some("repo only (command line)"));
+
+        let skip_aur = PhaseSelection {
+            only: Vec::new(),
+            skip: vec![Phase::AurUpdate],
+        };
+        let resolved = resolve_update_phases(&fixture("never"), &skip_aur);
+        assert!(!resolved.repo, "--skip only turns phases off");
+        assert!(!resolved.aur);
+        assert_eq!(
+            resolved.note.as_deref(),
+            Some("disabled by hosts/server1.owl and the command line")
+        );
+
+        // Skipping one phase leaves the policy for the other
+        let resolved = resolve_update_phases(&fixture("all"), &skip_aur);
+        assert!(resolved.repo && !resolved.aur);
+        assert_eq!(resolved.note.as_deref(), Some("repo only (command line)"));
+        let skip_repo = PhaseSelection {
+            only: Vec::new(),
+            skip: vec![Phase::RepoUpdate],
+        };
+        let resolved = resolve_update_phases(&fixture("repo-only"), &skip_repo);
+        assert!(!resolved.repo && !resolved.aur);
+
+        // Skipping other phases keeps the config's note
+        let skip_dotfiles = PhaseSelection {
+            only: Vec::new(),
+            skip: vec![Phase::Dotfiles],
+        };
+        let resolved = resolve_update_phases(&fixture("aur-only"), &skip_dotfiles);
+        assert!(!resolved.repo && resolved.aur);
+        assert_eq!(
+            resolved.note.as_deref(),
+            Some("aur only by hosts/server1.owl")
+        );
+
+        // --only without an update phase runs none
+        let only_dotfiles = PhaseSelection {
+            only: vec![Phase::Dotfiles],
+            skip: Vec::new(),
+        };
+        let resolved = resolve_update_phases(&fixture("all"), &only_dotfiles);
+        assert!(!resolved.repo && !resolved.aur);
+        assert_eq!(resolved.note.as_deref(), Some("disabled (command line)"));
+    }
+
+    #[test]
+    fn test_phase_selection() {
+        let skip = PhaseSelection {
+            only: Vec::new(),
+            skip: vec![Phase::Dotfiles],
+        };
+        assert!(!skip.enabled(Phase::Dotfiles));
+        assert!(skip.enabled(Phase::Install));
+
+        let only = PhaseSelection {
+            only: vec![Phase::Env],
+            skip: Vec::new(),
+        };
+        assert!(only.enabled(Phase::Env));
+        assert!(!only.enabled(Phase::Services));
+    }
+}
@@ -0,0 +1,106 @@
+//! The plan `owl plan --review-file` writes, and holding `apply
+//! --approved-review` to the items ticked in it (see `core::review`)
+
+use anyhow::{Result, anyhow};
+use std::path::Path;
+
+use crate::core::config::Config;
+use crate::core::dotfiles::{DotfileRoots, DotfileStatus};
+use crate::core::review::{Approval, DotfileChange, Review, Section};
+
+/// Dotfile roots as a plain apply uses them
+pub fn default_roots(config: &Config) -> Result<DotfileRoots> {
+    Ok(DotfileRoots::from_env()?.with_shared_host(crate::core::shared_home::host(config)?))
+}
+
+/// This run's package changes and the dotfiles it would write
+pub fn gather(
+    config: &Config,
+    to_install: &[String],
+    to_remove: &[String],
+    updates: &super::phases::UpdatePhases,
+    roots: &DotfileRoots,
+) -> Result<Review> {
+    let packages = super::package_plan(config, to_install, to_remove, updates)?.packages;
+    let mappings = crate::core::dotfiles::get_dotfile_mappings(config);
+    let statuses = crate::core::dotfiles::analyze_dotfiles(
+        roots,
+        &mappings,
+        crate::core::dotfiles::default_concurrency(),
+    )?;
+    let dotfiles = mappings
+        .into_iter()
+        .zip(statuses)
+        .filter_map(|(mapping, status)| {
+            let create = match status {
+                DotfileStatus::Create => true,
+                DotfileStatus::Update | DotfileStatus::Merge => false,
+                DotfileStatus::UpToDate
+                | DotfileStatus::Conflict(_)
+                | DotfileStatus::MergeConflict { .. } => return None,
+            };
+            Some(DotfileChange {
+                destination: mapping.destination,
+                source: mapping.source,
+                create,
+            })
+        })
+        .collect();
+    Ok(Review { packages, dotfiles })
+}
+
+/// The ticks in the review file at `path`, refused unless written from `current`
+pub fn approve(path: &Path, current: &Review) -> Result<Approval> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let approval =
+        crate::core::review::parse(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    approval
+        .verify(current)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    Ok(approval)
+}
+
+/// Drop unticked installs and removals and turn off the repo update if any
+/// repository upgrade is unticked, since pacman cannot upgrade a subset
+///
+/// Unticked AUR upgrades and dotfiles are left out where they run.
+pub fn restrict(
+    approval: &Approval,
+    review: &Review,
+    to_install: &mut Vec<String>,
+    to_remove: &mut Vec<String>,
+    updates: &mut super::phases::UpdatePhases,
+) {
+    to_install.retain(|name| approval.approves(Section::Install, name));
+    to_remove.retain(|name| approval.approves(Section::Remove, name));
+    let held_back = review.packages.iter().any(|p| {
+        p.change == crate::core::plan::ChangeKind::Upgrade
+            && p.repo.as_deref() != Some("aur")
+            && !approval.approves(Section::Upgrade, &p.name)
+    });
+    if updates.repo && held_back {
+        updates.repo = false;
+        updates.note =
+            Some("repo update skipped: a repository upgrade is unticked in the review".to_string());
+    }
+}
+
+/// `skipped (unticked): install yay; dotfiles ~/.bashrc`
+pub fn skipped_line(approval: &Approval) -> Option<String> {
+    let skipped = approval.skipped();
+    if skipped.is_empty() {
+        return None;
+    }
+    let mut parts: Vec<String> = Vec::new();
+    for (section, name) in &skipped {
+        let label = section.heading().to_lowercase();
+        match parts.last_mut() {
+            Some(last) if last.starts_with(&format!("{} ", label)) => {
+                last.push_str(&format!(", {}", name))
+            }
+            _ => parts.push(format!("{} {}", label, name)),
+        }
+    }
+    Some(format!("skipped (unticked): {}", parts.join("; ")))
+}
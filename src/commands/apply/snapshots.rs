@@ -0,0 +1,95 @@
+//! Pre- and post-apply filesystem snapshots (`@option snapshot_cmd`)
+
+use crate::core::events::{EventSink, OwlEvent};
+use crate::core::snapshot::{self, SnapshotOutcome, SnapshotPolicy};
+use crate::internal::color;
+
+/// Snapshot ids taken during one apply run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunSnapshots {
+    /// Whether this run's plan triggered snapshots at all
+    pub triggered: bool,
+    pub pre: Option<String>,
+    pub post: Option<String>,
+}
+
+/// Take the pre-apply snapshot if the plan warrants one
+///
+/// Exits when the snapshot fails and `snapshot_on_failure=abort`.
+pub fn pre_apply(
+    config: &crate::core::config::Config,
+    removals: usize,
+    changes: usize,
+    run_id: &str,
+    dry_run: bool,
+    sink: &mut dyn EventSink,
+) -> (SnapshotPolicy, RunSnapshots) {
+    let policy = config.snapshot_policy().unwrap_or_else(|e| {
+        sink.emit(OwlEvent::Warning(format!(
+            "Snapshots disabled, invalid option: {}",
+            e
+        )));
+        SnapshotPolicy::default()
+    });
+    let mut snapshots = RunSnapshots {
+        triggered: policy.should_snapshot(removals, changes),
+        ..Default::default()
+    };
+    if let (true, Some(template)) = (snapshots.triggered, &policy.pre_cmd) {
+        snapshots.pre = take(template, run_id, "pre", &policy, dry_run, sink);
+    }
+    (policy, snapshots)
+}
+
+/// Take the post-apply snapshot when the pre-apply one was triggered
+pub fn post_apply(
+    policy: &SnapshotPolicy,
+    snapshots: &mut RunSnapshots,
+    run_id: &str,
+    dry_run: bool,
+    sink: &mut dyn EventSink,
+) {
+    if let (true, Some(template)) = (snapshots.triggered, &policy.post_cmd) {
+        snapshots.post = take(template, run_id, "post", policy, dry_run, sink);
+    }
+}
+
+fn take(
+    template: &str,
+    run_id: &str,
+    phase: &str,
+    policy: &SnapshotPolicy,
+    dry_run: bool,
+    sink: &mut dyn EventSink,
+) -> Option<String> {
+    if dry_run {
+        outln!(
+            "  {} Would take a {}-apply snapshot",
+            color::blue("info:"),
+            phase
+        );
+        return None;
+    }
+    match snapshot::take(
+        template,
+        run_id,
+        phase,
+        policy.on_failure,
+        &snapshot::ShellRunner,
+    ) {
+        Ok(SnapshotOutcome::Taken(id)) => {
+            outln!(
+                "  {} {}-apply snapshot {}",
+                color::green("➔"),
+                phase,
+                id.as_deref().unwrap_or("taken")
+            );
+            id
+        }
+        Ok(SnapshotOutcome::Skipped(warning)) => {
+            sink.emit(OwlEvent::Warning(warning));
+            None
+        }
+        Err(e) => crate::error::exit_with_error(e),
+    }
+}
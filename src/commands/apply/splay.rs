@@ -0,0 +1,250 @@
+//! Random start delay for scheduled applies (`--splay`, `OWL_SPLAY`)
+//!
+//! Machines whose timers fire at the same moment each wait a uniformly random
+//! time up to the splay before doing anything, spreading mirror load. Runs with
+//! a terminal on stdin skip the wait unless `--splay-always` is given.
+
+use crate::internal::color;
+use anyhow::Result;
+use std::time::Duration;
+
+/// How often the remaining wait is reported to systemd
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Source of random numbers
+pub trait Rng {
+    fn next_u64(&mut self) -> u64;
+}
+
+/// splitmix64 seeded from the clock and pid; plenty for spreading start times
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn from_entropy() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self(nanos ^ (u64::from(std::process::id()) << 32))
+    }
+}
+
+impl Rng for SeededRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Waits out the delay
+pub trait Sleeper {
+    /// Sleep for `duration`; false when the wait was interrupted
+    fn sleep(&mut self, duration: Duration) -> bool;
+}
+
+/// Sleeps the current thread
+///
+/// Ctrl-C keeps its default action and ends the process mid-wait, before any
+/// phase has started.
+pub struct ThreadSleeper;
+
+impl Sleeper for ThreadSleeper {
+    fn sleep(&mut self, duration: Duration) -> bool {
+        std::thread::sleep(duration);
+        true
+    }
+}
+
+/// Result of waiting out the splay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplayOutcome {
+    Waited(Duration),
+    /// Interrupted after waiting this long
+    Interrupted(Duration),
+}
+
+/// The maximum delay to use, from `--splay` or `OWL_SPLAY`
+///
+/// `None` when no splay is configured, or when stdin is a terminal and
+/// `always` is not set.
+pub fn resolve(
+    flag: Option<&str>,
+    env: Option<&str>,
+    always: bool,
+    stdin_is_tty: bool,
+) -> Result<Option<Duration>> {
+    let Some(value) = flag.or(env) else {
+        return Ok(None);
+    };
+    let max = crate::internal::time::parse_duration(value)?;
+    if max.is_zero() || (stdin_is_tty && !always) {
+        return Ok(None);
+    }
+    Ok(Some(max))
+}
+
+/// A uniformly random delay in `0..=max`, at millisecond resolution
+pub fn choose_delay(max: Duration, rng: &mut dyn Rng) -> Duration {
+    let max_ms = max.as_millis() as u64;
+    Duration::from_millis(rng.next_u64() % (max_ms + 1))
+}
+
+/// Sleep `delay` in short steps, reporting the time left before each one
+pub fn wait(
+    delay: Duration,
+    sleeper: &mut dyn Sleeper,
+    report: &mut dyn FnMut(Duration),
+) -> SplayOutcome {
+    let mut waited = Duration::ZERO;
+    while waited < delay {
+        let remaining = delay - waited;
+        report(remaining);
+        let step = remaining.min(STATUS_INTERVAL);
+        if !sleeper.sleep(step) {
+            return SplayOutcome::Interrupted(waited);
+        }
+        waited += step;
+    }
+    SplayOutcome::Waited(delay)
+}
+
+/// Wait out the configured splay, returning the chosen delay in milliseconds
+///
+/// Exits when the splay value is invalid or the wait is interrupted.
+pub fn run(args: &crate::cli::handler::ApplyArgs) -> Option<u64> {
+    let env = crate::internal::environment::get();
+    let max = match resolve(
+        args.splay.as_deref(),
+        env.splay.as_deref(),
+        args.splay_always,
+        env.stdin_is_tty,
+    ) {
+        Ok(max) => max?,
+        Err(err) => crate::error::exit_with_error(err),
+    };
+    let delay = choose_delay(max, &mut SeededRng::from_entropy());
+    outln!(
+        "  {} Splay: waiting {} before applying",
+        color::blue("info:"),
+        crate::internal::time::format_duration_ms(delay.as_millis() as u64)
+    );
+    let mut report = |remaining: Duration| {
+        crate::internal::systemd::notify_status(&format!(
+            "Splay: waiting {} before applying",
+            crate::internal::time::format_duration_ms(remaining.as_millis() as u64)
+        ));
+    };
+    match wait(delay, &mut ThreadSleeper, &mut report) {
+        SplayOutcome::Waited(delay) => {
+            crate::internal::systemd::notify_status("Applying configuration");
+            Some(delay.as_millis() as u64)
+        }
+        SplayOutcome::Interrupted(_) => {
+            errln!(
+                "{}",
+                color::red("Interrupted during splay, nothing was applied")
+            );
+            std::process::exit(130);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays fixed values
+    struct FixedRng(Vec<u64>);
+
+    impl Rng for FixedRng {
+        fn next_u64(&mut self) -> u64 {
+            self.0.remove(0)
+        }
+    }
+
+    /// Records sleeps and reports an interrupt after `interrupt_after` of them
+    struct FakeSleeper {
+        slept: Vec<Duration>,
+        interrupt_after: Option<usize>,
+    }
+
+    impl Sleeper for FakeSleeper {
+        fn sleep(&mut self, duration: Duration) -> bool {
+            if self.interrupt_after == Some(self.slept.len()) {
+                return false;
+            }
+            self.slept.push(duration);
+            true
+        }
+    }
+
+    #[test]
+    fn test_delay_stays_in_range() {
+        let max = Duration::from_secs(60);
+        let mut rng = FixedRng(vec![0, 60_000, 60_001, u64::MAX]);
+        assert_eq!(choose_delay(max, &mut rng), Duration::ZERO);
+        assert_eq!(choose_delay(max, &mut rng), max);
+        assert_eq!(choose_delay(max, &mut rng), Duration::ZERO);
+        assert!(choose_delay(max, &mut rng) <= max);
+
+        let mut seeded = SeededRng(42);
+        for _ in 0..1000 {
+            assert!(
+                choose_delay(Duration::from_millis(250), &mut seeded) <= Duration::from_millis(250)
+            );
+        }
+    }
+
+    #[test]
+    fn test_tty_skips_unless_always() {
+        assert_eq!(resolve(Some("10m"), None, false, true).unwrap(), None);
+        assert_eq!(
+            resolve(Some("10m"), None, true, true).unwrap(),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(
+            resolve(None, Some("30s"), false, false).unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        // The flag wins over the environment
+        assert_eq!(
+            resolve(Some("5s"), Some("30s"), false, false).unwrap(),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(resolve(None, None, true, false).unwrap(), None);
+        assert_eq!(resolve(Some("0"), None, true, false).unwrap(), None);
+        assert!(resolve(Some("soon"), None, false, false).is_err());
+    }
+
+    #[test]
+    fn test_wait_reports_remaining_time() {
+        let mut sleeper = FakeSleeper {
+            slept: Vec::new(),
+            interrupt_after: None,
+        };
+        let mut reported = Vec::new();
+        let outcome = wait(Duration::from_millis(2_500), &mut sleeper, &mut |left| {
+            reported.push(left.as_millis())
+        });
+        assert_eq!(outcome, SplayOutcome::Waited(Duration::from_millis(2_500)));
+        assert_eq!(reported, vec![2_500, 1_500, 500]);
+        assert_eq!(
+            sleeper.slept.iter().sum::<Duration>(),
+            Duration::from_millis(2_500)
+        );
+    }
+
+    #[test]
+    fn test_interrupt_stops_wait() {
+        let mut sleeper = FakeSleeper {
+            slept: Vec::new(),
+            interrupt_after: Some(2),
+        };
+        let outcome = wait(Duration::from_secs(10), &mut sleeper, &mut |_| {});
+        assert_eq!(outcome, SplayOutcome::Interrupted(Duration::from_secs(2)));
+        assert_eq!(sleeper.slept.len(), 2);
+    }
+}
@@ -1,101 +1,174 @@
-/// Handle system section (services + environment variables)
-pub fn handle_system_section_with_config(config: &crate::core::config::Config, dry_run: bool) {
-    // no-op placeholder kept for potential future use
+use crate::core::confirm::ConfirmKind;
+use crate::core::events::{EventPhase, EventSink, OwlEvent};
+use crate::core::services::{ServiceLedger, Systemctl};
 
+/// Handle system section (services + environment variables) with the settings in `params`
+pub fn handle_system_section_with_config(
+    config: &crate::core::config::Config,
+    params: &super::packages::PackageOperationParams,
+    sink: &mut dyn EventSink,
+    timings: &mut super::timings::PhaseTimings,
+) {
+    let dry_run = params.dry_run;
+    let run_services = params.phases.enabled(super::phases::Phase::Services);
+    let run_env = params.phases.enabled(super::phases::Phase::Env);
     // Check if we have services or environment variables
-    let services = crate::core::services::get_configured_services(config);
-    let env_var_count = super::analysis::count_environment_variables(config);
+    let services = if run_services {
+        crate::core::services::get_configured_services(config)
+    } else {
+        Vec::new()
+    };
+    let env_var_count = if run_env {
+        super::analysis::count_environment_variables(config)
+    } else {
+        0
+    };
+
+    if run_services {
+        teardown_services(&services, dry_run, &params.confirm, sink);
+    }
 
     if services.is_empty() && env_var_count == 0 {
         return;
     }
 
-    // Show section header
-    println!();
-    println!("[{}]", crate::internal::color::red("system"));
+    sink.emit(OwlEvent::PhaseStarted(EventPhase::System));
 
     // Handle services first
     if !services.is_empty() {
-        if dry_run {
-            println!("  {} Plan:", crate::internal::color::blue("info:"));
-            for service in &services {
-                println!(
-                    "    ✓ Would manage {} (system) [enable, start]",
-                    crate::internal::color::yellow(service)
-                );
-            }
-            println!(
-                "  {} Planned {} service(s)",
-                crate::internal::color::blue("info:"),
-                services.len()
-            );
-            println!();
-        } else {
-            // Use spinner for service validation
-            let spinner_msg = format!("Validating {} services...", services.len());
-            let services_clone = services.clone();
-            let result = match crate::internal::util::execute_with_progress(
-                move || crate::core::services::ensure_services_configured(&services_clone),
-                &spinner_msg,
-            ) {
-                Ok(result) => result,
-                Err(err) => {
-                    eprintln!(
-                        "{}",
-                        crate::internal::color::red(&format!(
-                            "Failed to configure services: {}",
-                            err
-                        ))
-                    );
-                    return;
-                }
-            };
+        timings.time("services", || {
+            configure_services(&services, dry_run, params.service_changes, sink)
+        });
+    }
 
-            if result.changed {
-                println!(
-                    "  {} Services configured",
-                    crate::internal::color::green("⸎")
-                );
-                println!();
-                println!(
-                    "  {} Managed {} service(s)",
-                    crate::internal::color::green("⸎"),
-                    services.len()
-                );
+    // Handle environment variables
+    if env_var_count > 0
+        && let Err(e) = timings.time("env", || {
+            crate::core::env::apply_environment_variables(
+                config,
+                dry_run,
+                params.env_diff_context,
+                params.safe,
+                sink,
+            )
+        })
+    {
+        sink.emit(OwlEvent::Error(format!(
+            "Environment handling failed: {}",
+            e
+        )));
+    }
+    sink.emit(OwlEvent::PhaseFinished(EventPhase::System));
+}
 
-                if !result.enabled_services.is_empty() {
-                    println!("    Enabled: {}", result.enabled_services.join(", "));
-                }
-                if !result.started_services.is_empty() {
-                    println!("    Started: {}", result.started_services.join(", "));
-                }
-                if !result.failed_services.is_empty() {
-                    println!(
-                        "    {} Failed: {}",
-                        crate::internal::color::red("✗"),
-                        result.failed_services.join(", ")
-                    );
-                }
-                println!();
-            } else {
-                println!(
-                    "  {} Service state verified",
-                    crate::internal::color::green("⸎")
+/// Enable and start services, or report the plan when dry running
+///
+/// With `changes`, the plan is each service's current state against the config.
+fn configure_services(services: &[String], dry_run: bool, changes: bool, sink: &mut dyn EventSink) {
+    if dry_run && changes {
+        sink.emit(OwlEvent::ServiceChangesPlanned {
+            changes: crate::core::services::plan_service_changes(services, &Systemctl),
+        });
+    } else if dry_run {
+        sink.emit(OwlEvent::ServicesPlanned {
+            services: services.to_vec(),
+        });
+    } else {
+        // Use spinner for service validation
+        let spinner_msg = format!("Validating {} services...", services.len());
+        let services_clone = services.to_vec();
+        let result = match crate::internal::util::execute_with_progress(
+            move || {
+                let mut ledger = ServiceLedger::load()?;
+                let result = crate::core::services::ensure_services_configured(
+                    &services_clone,
+                    &Systemctl,
+                    &mut ledger,
+                    crate::internal::time::now_secs(),
                 );
+                ledger.save()?;
+                result
+            },
+            &spinner_msg,
+        ) {
+            Ok(result) => result,
+            Err(err) => {
+                sink.emit(OwlEvent::Error(format!(
+                    "Failed to configure services: {}",
+                    err
+                )));
+                return;
             }
+        };
+
+        if result.changed {
+            sink.emit(OwlEvent::ServicesConfigured {
+                managed: services.len(),
+                result,
+            });
+        } else {
+            sink.emit(OwlEvent::ServicesVerified {
+                preexisting: result.preexisting_services,
+            });
         }
     }
+}
 
-    // Handle environment variables
-    if env_var_count > 0 {
-        match crate::core::env::apply_environment_variables(config, dry_run) {
-            Ok(()) => {}
-            Err(e) => {
-                eprintln!(
-                    "{}",
-                    crate::internal::color::red(&format!("Environment handling failed: {}", e))
-                );
-            }
+/// Units the services phase may act on: the configured services and the
+/// ones it may offer to disable
+pub fn managed_units(config: &crate::core::config::Config) -> Vec<String> {
+    let mut units = crate::core::services::get_configured_services(config);
+    if let Ok(ledger) = ServiceLedger::load() {
+        units.extend(ledger.teardown_candidates(&units));
+    }
+    units
+}
+
+/// Offer to disable services owl enabled that are no longer declared
+///
+/// Services that were enabled before owl saw them are left alone unless
+/// adopted. Dry runs and declined ones only get a warning.
+fn teardown_services(
+    services: &[String],
+    dry_run: bool,
+    policy: &crate::core::confirm::ConfirmPolicy,
+    sink: &mut dyn EventSink,
+) {
+    let mut ledger = match ServiceLedger::load() {
+        Ok(ledger) => ledger,
+        Err(e) => {
+            sink.emit(OwlEvent::Warning(format!(
+                "Could not read service records: {}",
+                e
+            )));
+            return;
         }
+    };
+    let candidates = ledger.teardown_candidates(services);
+    if candidates.is_empty() {
+        return;
+    }
+    if dry_run || !crate::cli::ui::confirm(ConfirmKind::Teardown, &candidates, policy) {
+        for service in candidates {
+            sink.emit(OwlEvent::Warning(format!(
+                "{} was enabled by owl and is no longer declared; disable it with: sudo systemctl disable --now {}",
+                service, service
+            )));
+        }
+        return;
+    }
+    let (disabled, failed) =
+        ledger.teardown(&candidates, &Systemctl, crate::internal::time::now_secs());
+    if let Err(e) = ledger.save() {
+        sink.emit(OwlEvent::Error(format!(
+            "Failed to record disabled services: {}",
+            e
+        )));
+    }
+    if !disabled.is_empty() {
+        sink.emit(OwlEvent::ServicesDisabled { services: disabled });
+    }
+    for service in failed {
+        sink.emit(OwlEvent::Error(format!("Failed to disable {}", service)));
     }
 }
@@ -0,0 +1,67 @@
+//! Wall-clock time spent in each apply phase (printed under `--verbose`)
+
+use std::time::Instant;
+
+/// Phase durations in the order the phases ran
+#[derive(Debug, Clone, Default)]
+pub struct PhaseTimings {
+    phases: Vec<(&'static str, u64)>,
+}
+
+impl PhaseTimings {
+    /// Run `f` and record how long it took under `phase`
+    pub fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = f();
+        self.phases
+            .push((phase, start.elapsed().as_millis() as u64));
+        value
+    }
+
+    /// Record `phase` as having taken `duration_ms`, for phases timed elsewhere
+    pub fn record(&mut self, phase: &'static str, duration_ms: u64) {
+        self.phases.push((phase, duration_ms));
+    }
+
+    /// `(phase, duration_ms)` pairs in run order
+    pub fn phases(&self) -> &[(&'static str, u64)] {
+        &self.phases
+    }
+
+    pub fn print(&self) {
+        if self.phases.is_empty() {
+            return;
+        }
+        let parts: Vec<String> = self
+            .phases()
+            .iter()
+            .map(|(phase, ms)| {
+                format!(
+                    "{} {}",
+                    phase,
+                    crate::internal::time::format_duration_ms(*ms)
+                )
+            })
+            .collect();
+        outln!();
+        outln!(
+            "  {} {}",
+            crate::internal::color::dim("Phase timings:"),
+            parts.join("  ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_records_phase_and_returns_value() {
+        let mut timings = PhaseTimings::default();
+        assert_eq!(timings.time("analysis", || 42), 42);
+        timings.time("dotfiles", || ());
+        let names: Vec<&str> = timings.phases().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["analysis", "dotfiles"]);
+    }
+}
@@ -1,7 +1,11 @@
 use anyhow::{Result, anyhow};
+use std::collections::HashSet;
 use std::fs;
+use std::path::{Path, PathBuf};
 
 use crate::core::config::Config;
+use crate::core::confirm::{ConfirmKind, ConfirmPolicy};
+use crate::core::pm::PackageManager;
 use crate::internal::color;
 
 pub fn handle_clean(filename: &str) -> Result<()> {
@@ -24,8 +28,8 @@ pub fn handle_clean_all() -> Result<()> {
         get_all_config_files().map_err(|e| anyhow!("Failed to discover config files: {}", e))?;
 
     if config_files.is_empty() {
-        println!("[{}]", color::blue("clean"));
-        println!(
+        outln!("[{}]", color::blue("clean"));
+        outln!(
             "  {} {}",
             color::green("➔"),
             color::dim("no .owl config files found in ~/.owl directory")
@@ -33,8 +37,8 @@ pub fn handle_clean_all() -> Result<()> {
         return Ok(());
     }
 
-    println!("[{}]", color::blue("clean"));
-    println!(
+    outln!("[{}]", color::blue("clean"));
+    outln!(
         "  {} config files cleaned",
         color::yellow(&config_files.len().to_string())
     );
@@ -48,14 +52,14 @@ pub fn handle_clean_all() -> Result<()> {
             }
             Err(e) => {
                 failed_count += 1;
-                eprintln!("  {} {}: {}", color::red("✗"), color::dim(&filename), e);
+                errln!("  {} {}: {}", color::red("✗"), color::dim(&filename), e);
             }
         }
     }
 
     if failed_count > 0 {
-        println!();
-        println!(
+        outln!();
+        outln!(
             "  {} {}",
             color::red("failed"),
             color::bold(&failed_count.to_string())
@@ -65,10 +69,247 @@ pub fn handle_clean_all() -> Result<()> {
     Ok(())
 }
 
+/// Prune managed state entries for packages that are no longer installed
+pub fn handle_clean_state() -> Result<()> {
+    let mut state = crate::core::state::PackageState::load()
+        .map_err(|e| anyhow!("Failed to load package state: {}", e))?;
+    let pruned = crate::core::package::prune_uninstalled_managed(&mut state)?;
+
+    outln!("[{}]", color::blue("clean"));
+    if pruned.is_empty() {
+        outln!(
+            "  {} {}",
+            color::green("➔"),
+            color::dim("managed state is consistent")
+        );
+        return Ok(());
+    }
+
+    state
+        .save()
+        .map_err(|e| anyhow!("Failed to save package state: {}", e))?;
+    outln!(
+        "  {} stale managed entries pruned",
+        color::yellow(&pruned.len().to_string())
+    );
+    for pkg in &pruned {
+        outln!("  {} {}", color::green("✓"), color::dim(pkg));
+    }
+    Ok(())
+}
+
+/// Check the dotfile backup store for corrupted or missing objects
+pub fn handle_verify_backups() -> Result<()> {
+    let store = crate::core::backup::BackupStore::from_env()?;
+    let migrated = store.migrate_legacy()?;
+    let report = store.verify()?;
+
+    outln!("[{}]", color::blue("clean"));
+    if migrated > 0 {
+        outln!(
+            "  {} legacy backup sets migrated",
+            color::yellow(&migrated.to_string())
+        );
+    }
+    outln!(
+        "  {} backup objects checked",
+        color::yellow(&report.checked.to_string())
+    );
+    for hash in &report.corrupt {
+        outln!("  {} corrupt object {}", color::red("✗"), color::dim(hash));
+    }
+    for hash in &report.missing {
+        outln!("  {} missing object {}", color::red("✗"), color::dim(hash));
+    }
+    if !report.is_ok() {
+        return Err(anyhow!(
+            "Backup store has {} corrupt and {} missing objects",
+            report.corrupt.len(),
+            report.missing.len()
+        ));
+    }
+    outln!("  {} {}", color::green("✓"), color::dim("backups intact"));
+    Ok(())
+}
+
+/// What `clean --aur` removed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AurCleanReport {
+    /// Bytes the cache directories shrank by, when the cache was cleaned
+    pub cache_freed: Option<u64>,
+    pub orphans_removed: Vec<String>,
+    /// Declared or managed orphans that kept the others from being removed
+    pub orphans_kept: Vec<String>,
+    /// Installed size of the removed orphans
+    pub orphans_freed: u64,
+}
+
+/// `owl clean --aur`: clean the package cache and remove orphaned dependencies
+pub fn handle_clean_aur(flags: &crate::cli::handler::GlobalFlags) -> Result<()> {
+    let env = crate::internal::environment::get();
+    let config = Config::load_all_relevant_config_files()
+        .map_err(|e| anyhow!("Failed to load config: {}", e))?;
+    let state = crate::core::state::PackageState::load()
+        .map_err(|e| anyhow!("Failed to load package state: {}", e))?;
+    outln!("[{}]", color::blue("clean"));
+    clean_aur(
+        crate::core::pm::manager().as_ref(),
+        &protected_packages(&config, &state),
+        &cache_dirs(env.home()?),
+        flags.dry_run,
+        &flags.confirm_policy(true),
+    )?;
+    Ok(())
+}
+
+/// Declared and managed packages, which orphan removal must not touch
+pub fn protected_packages(
+    config: &Config,
+    state: &crate::core::state::PackageState,
+) -> HashSet<String> {
+    config
+        .packages
+        .keys()
+        .chain(&state.managed)
+        .cloned()
+        .collect()
+}
+
+/// The pacman package cache and paru's clone directory
+pub fn cache_dirs(home: &Path) -> Vec<PathBuf> {
+    let clone_dir = crate::core::aur_builds::BuildSettings::read(home)
+        .build_dir
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            std::env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| home.join(".cache"))
+                .join("paru/clone")
+        });
+    vec![PathBuf::from("/var/cache/pacman/pkg"), clone_dir]
+}
+
+/// Clean the cache and remove orphans, each once confirmed
+///
+/// Orphans are left alone when any of them is in `protected`: pacman
+/// considers them unneeded, but owl would reinstall them on the next apply.
+pub fn clean_aur(
+    pm: &dyn PackageManager,
+    protected: &HashSet<String>,
+    cache_dirs: &[PathBuf],
+    dry_run: bool,
+    policy: &ConfirmPolicy,
+) -> Result<AurCleanReport> {
+    let mut report = AurCleanReport::default();
+    let dirs: Vec<String> = cache_dirs.iter().map(|d| d.display().to_string()).collect();
+    let before = total_size(cache_dirs);
+    if dry_run {
+        outln!(
+            "  {} Would clean {} ({})",
+            color::yellow("?"),
+            dirs.join(", "),
+            crate::internal::util::format_size(before)
+        );
+    } else if crate::cli::ui::confirm(ConfirmKind::CacheClean, &dirs, policy) {
+        pm.clean_cache()?;
+        let freed = before.saturating_sub(total_size(cache_dirs));
+        outln!(
+            "  {} package cache cleaned, {} freed",
+            color::green("✓"),
+            crate::internal::util::format_size(freed)
+        );
+        report.cache_freed = Some(freed);
+    }
+
+    let orphans = pm.list_orphans()?;
+    if orphans.is_empty() {
+        outln!(
+            "  {} {}",
+            color::green("➔"),
+            color::dim("no orphaned packages")
+        );
+        return Ok(report);
+    }
+    report.orphans_kept = orphans
+        .iter()
+        .filter(|name| protected.contains(*name))
+        .cloned()
+        .collect();
+    if !report.orphans_kept.is_empty() {
+        warnln!(
+            "  {} orphans not removed: {} {} declared or managed; mark them with \
+             pacman -D --asexplicit",
+            color::yellow("!"),
+            report.orphans_kept.join(", "),
+            if report.orphans_kept.len() == 1 {
+                "is"
+            } else {
+                "are"
+            }
+        );
+        return Ok(report);
+    }
+    if dry_run {
+        outln!(
+            "  {} Would remove orphans: {}",
+            color::yellow("?"),
+            orphans.join(", ")
+        );
+        return Ok(report);
+    }
+    if !crate::cli::ui::confirm(ConfirmKind::OrphanRemoval, &orphans, policy) {
+        return Ok(report);
+    }
+    // Sized before removal, while pacman still knows them
+    let freed = pm
+        .installed_info()
+        .unwrap_or_default()
+        .iter()
+        .filter(|info| orphans.contains(&info.name))
+        .filter_map(|info| info.installed_size)
+        .sum();
+    pm.remove_orphans()?;
+    report.orphans_freed = freed;
+    outln!(
+        "  {} {} orphans removed, {} freed",
+        color::green("✓"),
+        color::yellow(&orphans.len().to_string()),
+        crate::internal::util::format_size(report.orphans_freed)
+    );
+    report.orphans_removed = orphans;
+    Ok(report)
+}
+
+/// Bytes under `dirs`; unreadable entries count as empty
+fn total_size(dirs: &[PathBuf]) -> u64 {
+    let mut total = 0;
+    let mut pending: Vec<PathBuf> = dirs.to_vec();
+    while let Some(path) = pending.pop() {
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if meta.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.flatten().map(|e| e.path()));
+            }
+        } else {
+            total += meta.len();
+        }
+    }
+    total
+}
+
 fn get_all_config_files() -> Result<Vec<String>> {
     crate::internal::files::get_all_config_files()
 }
 
+/// `[shell=fish] ` prefix for shell-specific `:env` lines
+fn shell_option(shell: Option<&crate::core::env::Shell>) -> String {
+    shell
+        .map(|shell| format!("[shell={}] ", shell.as_str()))
+        .unwrap_or_default()
+}
+
 fn optimize_config(config: &Config) -> String {
     let mut sections: Vec<String> = Vec::new();
 
@@ -77,8 +318,24 @@ fn optimize_config(config: &Config) -> String {
     let mut packages_with_directives: Vec<String> = Vec::new();
 
     for (name, pkg) in &config.packages {
-        if pkg.config.is_empty() && pkg.service.is_none() && pkg.env_vars.is_empty() {
-            loose_packages.push(name.clone());
+        // Values baked in from @defaults are written back once, in the @defaults block
+        let env_vars: Vec<(&String, &String)> = pkg
+            .env_vars
+            .iter()
+            .filter(|(key, _)| !pkg.default_env_keys.contains(*key))
+            .collect();
+        if pkg.config.is_empty()
+            && pkg.service.is_none()
+            && env_vars.is_empty()
+            && pkg.min_version.is_none()
+            && pkg.after.is_empty()
+            && pkg.absent.is_empty()
+            && pkg.requires.is_empty()
+        {
+            // Its parent's `:requires` line declares it again
+            if pkg.required_by.is_none() {
+                loose_packages.push(name.clone());
+            }
         } else {
             let mut block = format!("@pkg {}\n", name);
             // Output :cfg directives
@@ -89,9 +346,26 @@ fn optimize_config(config: &Config) -> String {
             if let Some(service) = &pkg.service {
                 block.push_str(&format!(":service {}\n", service));
             }
+            if let Some(min_version) = &pkg.min_version {
+                block.push_str(&format!("{}\n", min_version.directive()));
+            }
+            if !pkg.after.is_empty() {
+                block.push_str(&format!(":after {}\n", pkg.after.join(" ")));
+            }
+            for path in &pkg.absent {
+                block.push_str(&format!(":absent {}\n", path));
+            }
+            if !pkg.requires.is_empty() {
+                block.push_str(&format!(":requires {}\n", pkg.requires.join(" ")));
+            }
             // Output :env
-            for (key, value) in &pkg.env_vars {
-                block.push_str(&format!(":env {}={}\n", key, value));
+            for (key, value) in env_vars {
+                block.push_str(&format!(
+                    ":env {}{}={}\n",
+                    shell_option(pkg.env_shells.get(key)),
+                    key,
+                    value
+                ));
             }
             packages_with_directives.push(block.trim_end().to_string());
         }
@@ -125,11 +399,37 @@ fn optimize_config(config: &Config) -> String {
         sorted_groups.sort();
         let mut group_block = String::new();
         for group in sorted_groups {
-            group_block.push_str(&format!("@group {}\n", group));
+            // Keep explicitly quoted names quoted so they round-trip without warnings
+            if group.contains(char::is_whitespace) {
+                group_block.push_str(&format!("@group \"{}\"\n", group));
+            } else {
+                group_block.push_str(&format!("@group {}\n", group));
+            }
         }
         sections.push(group_block.trim_end().to_string());
     }
 
+    // Keep the file's @defaults (must precede every package)
+    if !config.defaults.env_vars.is_empty() {
+        let mut defaults: Vec<_> = config.defaults.env_vars.iter().collect();
+        defaults.sort();
+        let mut defaults_block = String::from("@defaults\n");
+        for (key, value) in defaults {
+            defaults_block.push_str(&format!(
+                ":env {}{}={}\n",
+                shell_option(config.defaults.env_shells.get(key)),
+                key,
+                value
+            ));
+        }
+        sections.push(defaults_block.trim_end().to_string());
+    }
+
+    // Keep the file's dotfiles root
+    if let Some(root) = &config.dotfiles_root {
+        sections.push(format!("@dotfiles-root {}", root));
+    }
+
     // Add global env vars as the second section
     if !config.env_vars.is_empty() {
         let mut env_block = String::new();
@@ -139,6 +439,52 @@ fn optimize_config(config: &Config) -> String {
         sections.push(env_block.trim_end().to_string());
     }
 
+    // Keep @option settings
+    if !config.options.is_empty() {
+        let mut options: Vec<_> = config.options.iter().collect();
+        options.sort_by(|a, b| a.0.cmp(b.0));
+        let mut option_block = String::new();
+        for (key, option) in options {
+            option_block.push_str(&format!("@option {}={}\n", key, option.value));
+        }
+        sections.push(option_block.trim_end().to_string());
+    }
+
+    // Keep untracked declarations
+    if !config.untracked.is_empty() || config.untracked_reset {
+        let mut untracked_block = String::new();
+        if config.untracked_reset {
+            untracked_block.push_str("@untracked-reset\n");
+        }
+        if !config.untracked.is_empty() {
+            let mut names = config.untracked.clone();
+            names.sort();
+            untracked_block.push_str(&format!("@untracked {}\n", names.join(" ")));
+        }
+        sections.push(untracked_block.trim_end().to_string());
+    }
+
+    // Keep architecture-specific AUR suffixes
+    if !config.arch_aur_suffixes.is_empty() {
+        let mut arches: Vec<_> = config.arch_aur_suffixes.iter().collect();
+        arches.sort();
+        let mut arch_block = String::new();
+        for (arch, suffix) in arches {
+            arch_block.push_str(&format!("@arch-aur-prefix {}:suffix={}\n", arch, suffix));
+        }
+        sections.push(arch_block.trim_end().to_string());
+    }
+
+    // Keep paths that must stay absent
+    if !config.absent.is_empty() {
+        let absent: Vec<String> = config
+            .absent
+            .iter()
+            .map(|path| format!("@absent {}", path))
+            .collect();
+        sections.push(absent.join("\n"));
+    }
+
     // Add packages with directives as the third section
     sections.extend(packages_with_directives);
 
@@ -164,6 +510,68 @@ fn optimize_config(config: &Config) -> String {
 mod tests {
     use super::*;
     use crate::core::config::Config;
+    use crate::core::pm::fake;
+
+    const LOG: &str = r#"echo "$(basename "$0") $*" >> "$(dirname "$0")/calls""#;
+
+    fn calls(dir: &tempfile::TempDir) -> String {
+        fs::read_to_string(dir.path().join("calls")).unwrap_or_default()
+    }
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_clean_aur_needs_confirmation() {
+        let (dir, pm) = fake::pm(LOG, &format!("{}\necho meson", LOG));
+        let declined = ConfirmPolicy {
+            can_prompt: false,
+            ..Default::default()
+        };
+        let report = clean_aur(&pm, &set(&[]), &[], false, &declined).unwrap();
+        assert_eq!(report, AurCleanReport::default());
+        assert_eq!(calls(&dir), "pacman -Qdtq\n");
+
+        // Assumed answers clean the cache but leave orphans for a person
+        let assumed = ConfirmPolicy {
+            assume_answers: true,
+            ..Default::default()
+        };
+        let report = clean_aur(&pm, &set(&[]), &[], false, &assumed).unwrap();
+        assert_eq!(report.cache_freed, Some(0));
+        assert!(report.orphans_removed.is_empty());
+        assert!(calls(&dir).ends_with("paru -Sc --noconfirm\npacman -Qdtq\n"));
+        assert!(!calls(&dir).contains(" -c "));
+    }
+
+    #[test]
+    fn test_clean_aur_keeps_declared_orphans() {
+        let cache = tempfile::tempdir().unwrap();
+        fs::write(cache.path().join("meson.pkg.tar.zst"), [0u8; 64]).unwrap();
+        let (dir, pm) = fake::pm(
+            &format!(
+                "{}\n[ \"$1\" = -Sc ] && rm -f {}/*",
+                LOG,
+                cache.path().display()
+            ),
+            &format!("{}\nprintf 'meson\\ngo\\n'", LOG),
+        );
+        let yes = ConfirmPolicy {
+            assume_answers: true,
+            ..Default::default()
+        };
+        let report = clean_aur(&pm, &set(&["go"]), &[cache.path().into()], false, &yes).unwrap();
+        assert_eq!(report.cache_freed, Some(64));
+        assert_eq!(report.orphans_kept, vec!["go".to_string()]);
+        assert!(report.orphans_removed.is_empty());
+        assert!(!calls(&dir).contains("paru -c"));
+
+        // A dry run reports without calling anything that changes the system
+        let (dir, pm) = fake::pm(LOG, &format!("{}\necho meson", LOG));
+        clean_aur(&pm, &set(&[]), &[], true, &yes).unwrap();
+        assert_eq!(calls(&dir), "pacman -Qdtq\n");
+    }
 
     #[test]
     fn test_optimize_config() {
@@ -196,4 +604,14 @@ loose3"#;
 
         assert_eq!(optimized, expected);
     }
+
+    #[test]
+    fn test_optimize_config_keeps_defaults_block() {
+        let content = "@defaults\n:env PATH=/opt/dev/bin\n\n@package go\n:env GOFLAGS=-mod=vendor\n\n@packages\nrustup\n";
+        let optimized = optimize_config(&Config::parse(content).unwrap());
+        assert_eq!(
+            optimized,
+            "@defaults\n:env PATH=/opt/dev/bin\n\n@pkg go\n:env GOFLAGS=-mod=vendor\n\n@pkgs\nrustup"
+        );
+    }
 }
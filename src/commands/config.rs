@@ -0,0 +1,77 @@
+use anyhow::{Result, anyhow};
+use std::path::PathBuf;
+
+use crate::core::config::Config;
+use crate::core::config::annotate::{self, Host};
+use crate::core::config::trace;
+use crate::core::dotfiles::DotfileRoots;
+use crate::core::services::Systemctl;
+use crate::internal::color;
+
+/// Run `owl config explain` to annotate a config file with its effect on this host
+pub fn explain(file: &str, json: bool) -> Result<()> {
+    let env = crate::internal::environment::get();
+    let roots = DotfileRoots::from_env()?;
+    let path = [PathBuf::from(file), roots.owl_dir.join(file)]
+        .into_iter()
+        .find(|p| p.is_file())
+        .ok_or_else(|| anyhow!("Config file '{}' not found", file))?;
+    // The JSON report carries the merge journal of the file's packages
+    let config = if json {
+        Config::load_all_relevant_config_files_traced()?
+    } else {
+        Config::load_all_relevant_config_files()?
+    };
+    let installed = match crate::core::pm::manager().list_installed() {
+        Ok(installed) => Some(installed),
+        Err(e) => {
+            warnln!(
+                "{}",
+                color::yellow(&format!("Cannot tell which packages are installed: {}", e))
+            );
+            None
+        }
+    };
+    let is_installed = |name: &str| installed.as_ref().map(|installed| installed.contains(name));
+    let host = Host {
+        hostname: env.hostname()?,
+        roots: &roots,
+        installed: &is_installed,
+        services: &Systemctl,
+    };
+    let report = annotate::annotate(&config, &path, &host)?;
+
+    if json {
+        let report = serde_json::to_string_pretty(&report)
+            .map_err(|e| anyhow!("Failed to serialize report: {}", e))?;
+        outln!("{}", report);
+    } else {
+        out!("{}", annotate::render_text(&report));
+    }
+    Ok(())
+}
+
+/// Run `owl config trace` to show every file's contribution to a package
+pub fn trace(package: &str, json: bool) -> Result<()> {
+    let config = Config::load_all_relevant_config_files_traced()?;
+    let entries = config
+        .journal
+        .as_ref()
+        .and_then(|journal| journal.packages.get(package))
+        .ok_or_else(|| {
+            anyhow!(
+                "Package '{}' is not declared in any loaded config file",
+                package
+            )
+        })?;
+
+    if json {
+        let journal = serde_json::json!({ "package": package, "journal": entries });
+        let journal = serde_json::to_string_pretty(&journal)
+            .map_err(|e| anyhow!("Failed to serialize journal: {}", e))?;
+        outln!("{}", journal);
+    } else {
+        out!("{}", trace::render_text(package, entries));
+    }
+    Ok(())
+}
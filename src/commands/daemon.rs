@@ -0,0 +1,190 @@
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::core::config::Config;
+use crate::core::daemon::{self, Backend, Daemon, Fingerprint, Request, Response};
+use crate::core::events::OwlEvent;
+use crate::internal::color;
+
+/// Directories of the owl root that do not change the plan
+const UNWATCHED: &[&str] = &[crate::internal::constants::STATE_DIR, "dotfiles", ".git"];
+
+/// Where pacman records installed packages; it changes with every transaction
+const PACMAN_LOCAL_DB: &str = "/var/lib/pacman/local";
+
+/// Plans and applies in the daemon's own process, from the config it keeps
+struct InProcess {
+    owl_dir: PathBuf,
+}
+
+impl Backend for InProcess {
+    type Config = Config;
+
+    fn config_fingerprint(&self) -> Fingerprint {
+        daemon::fingerprint(&self.owl_dir, UNWATCHED)
+    }
+
+    fn packages_fingerprint(&self) -> Fingerprint {
+        let state = self.owl_dir.join(crate::internal::constants::STATE_DIR);
+        daemon::stamps(&[state.join("managed.json"), PathBuf::from(PACMAN_LOCAL_DB)])
+    }
+
+    fn load(&self) -> Result<Config> {
+        Config::load_all_relevant_config_files()
+    }
+
+    fn clear_package_caches(&self) {
+        crate::core::package::clear_caches();
+    }
+
+    fn plan(&self, config: &Config) -> Result<Value> {
+        let plan = crate::commands::apply::current_plan_for(config.clone())?;
+        serde_json::to_value(&plan).map_err(|e| anyhow!("Failed to encode plan: {}", e))
+    }
+
+    fn apply(
+        &self,
+        config: &Config,
+        dry_run: bool,
+        on_event: &mut dyn FnMut(Value) -> Result<()>,
+    ) -> Result<bool> {
+        // Nobody is at a terminal to answer, as with --non-interactive
+        let flags = crate::cli::handler::GlobalFlags {
+            verbose: false,
+            dry_run,
+            non_interactive: true,
+            diff_context: None,
+            confirm_timeout: None,
+            safe: false,
+        };
+        let args = crate::cli::handler::ApplyArgs {
+            events_json: true,
+            ..Default::default()
+        };
+        let mut streamed = Ok(());
+        let mut sink = |event: OwlEvent| {
+            // The client went away; let the run finish all the same
+            if streamed.is_ok() {
+                streamed = on_event(event.to_json());
+            }
+        };
+        let result =
+            crate::commands::apply::run_with(&flags, &args, None, Some(config.clone()), &mut sink);
+        let success = match result {
+            Ok(success) => success,
+            Err(err) => {
+                sink(OwlEvent::Error(err.to_string()));
+                false
+            }
+        };
+        streamed?;
+        Ok(success)
+    }
+}
+
+/// The daemon's socket, from `$XDG_RUNTIME_DIR`
+fn socket() -> Result<PathBuf> {
+    crate::internal::environment::get()
+        .runtime_dir
+        .as_deref()
+        .map(daemon::socket_path)
+        .ok_or_else(|| anyhow!("XDG_RUNTIME_DIR is not set; owl daemon keeps its socket there"))
+}
+
+/// Serve requests on the socket until asked to shut down
+pub fn run() -> Result<()> {
+    let path = socket()?;
+    let backend = InProcess {
+        owl_dir: crate::internal::environment::get().owl_dir()?,
+    };
+    let listener = daemon::bind(&path)?;
+    outln!("[{}]", color::blue("daemon"));
+    outln!("  {} Listening on {}", color::green("➔"), path.display());
+    let daemon = Arc::new(Daemon::new(backend));
+    daemon.watch(daemon::WATCH_INTERVAL);
+    daemon.serve(listener, &path)?;
+    outln!("  {} Stopped", color::dim("➔"));
+    Ok(())
+}
+
+/// Ask a running daemon; `None` when there is none or it failed, so the
+/// caller works in-process
+fn ask(request: Request) -> Option<Response> {
+    let path = socket().ok()?;
+    let message = match daemon::ask(&path, &request, &mut |_| {}) {
+        Ok(Some(Response::Error { message })) => message,
+        Ok(response) => return response,
+        Err(err) => err.to_string(),
+    };
+    warnln!(
+        "{} owl daemon: {}; working without it",
+        color::yellow("warning:"),
+        message
+    );
+    None
+}
+
+/// The `--plan-json` document from the daemon, planned again now
+pub fn plan_json() -> Option<String> {
+    match ask(Request::Plan)? {
+        Response::Plan { plan, .. } => serde_json::to_string_pretty(&plan).ok(),
+        _ => None,
+    }
+}
+
+/// Print the pending changes: from the daemon's cached plan when one is
+/// running, otherwise planned here
+pub fn print_status() -> Result<()> {
+    let (plan, source) = match ask(Request::Status) {
+        Some(Response::Plan {
+            plan, planned_at, ..
+        }) => {
+            let age = crate::internal::time::now_secs().saturating_sub(planned_at);
+            let source = format!(
+                "planned by owl daemon {} ago",
+                crate::internal::time::format_age(age as i64)
+            );
+            (plan, source)
+        }
+        _ => {
+            let plan = crate::commands::apply::current_plan()?;
+            let plan =
+                serde_json::to_value(&plan).map_err(|e| anyhow!("Failed to encode plan: {}", e))?;
+            (plan, "planned now".to_string())
+        }
+    };
+    outln!("[{}]", color::blue("plan"));
+    outln!(
+        "  {} {} ({})",
+        color::green("➔"),
+        summary(&plan),
+        color::dim(&source)
+    );
+    if plan["reboot_advised"] == true {
+        outln!("  {} a reboot is advised after the run", color::yellow("!"));
+    }
+    Ok(())
+}
+
+/// `2 to install, 1 to upgrade, nothing to remove`
+fn summary(plan: &Value) -> String {
+    let count = |change: &str| {
+        plan["packages"].as_array().map_or(0, |packages| {
+            packages.iter().filter(|p| p["change"] == change).count()
+        })
+    };
+    [
+        ("install", count("install")),
+        ("upgrade", count("upgrade")),
+        ("remove", count("remove")),
+    ]
+    .iter()
+    .map(|(what, n)| match n {
+        0 => format!("nothing to {}", what),
+        n => format!("{} to {}", n, what),
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
@@ -0,0 +1,69 @@
+use anyhow::Result;
+
+use crate::core::doctor::{self, Severity};
+use crate::internal::color;
+
+/// Run every doctor check and list the findings, or print them as JSON;
+/// `online` adds the checks that ask the package manager and the AUR
+///
+/// Exits with status 1 when any finding is an error, in both forms.
+pub fn run(json: bool, online: bool) -> Result<()> {
+    let env = crate::internal::environment::get();
+    let (owl_dir, home) = (env.owl_dir()?, env.home()?.to_string_lossy());
+    let report = if online {
+        let pm = crate::core::pm::manager();
+        doctor::diagnose_online(
+            &owl_dir,
+            &home,
+            env.hostname().ok(),
+            &on_path,
+            &doctor::Online::new(
+                pm.as_ref(),
+                &crate::core::aur_rpc::Curl,
+                std::env::consts::ARCH,
+            ),
+        )
+    } else {
+        doctor::diagnose(&owl_dir, &home, env.hostname().ok(), &on_path)
+    };
+
+    if json {
+        let out = serde_json::to_string_pretty(&report)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize the report: {}", e))?;
+        outln!("{}", out);
+    } else {
+        outln!("[{}]", color::blue("doctor"));
+        if report.findings.is_empty() {
+            outln!("  {} No problems found", color::green("✓"));
+        }
+        for finding in &report.findings {
+            let label = match finding.severity {
+                Severity::Error => color::red("error:"),
+                Severity::Warning => color::yellow("warning:"),
+                Severity::Info => color::blue("info:"),
+            };
+            match &finding.location {
+                Some(location) => outln!(
+                    "  {} {} {}",
+                    label,
+                    finding.message,
+                    color::dim(&format!("({})", location))
+                ),
+                None => outln!("  {} {}", label, finding.message),
+            }
+        }
+    }
+
+    if report.count_at_least(Severity::Error) > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Whether the shell finds `program`
+fn on_path(program: &str) -> bool {
+    std::process::Command::new("sh")
+        .args(["-c", "command -v \"$1\" >/dev/null", "sh", program])
+        .status()
+        .is_ok_and(|status| status.success())
+}
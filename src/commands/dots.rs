@@ -1,19 +1,19 @@
 /// Run the dots command to apply dotfile synchronization
-pub fn run(flags: &crate::cli::handler::GlobalFlags) {
+pub fn run(flags: &crate::cli::handler::GlobalFlags, allow_outside_home: bool) {
     let dry_run = flags.dry_run;
     if dry_run {
-        println!(
+        outln!(
             "  {} Dry run mode - no changes will be made to the system",
             crate::internal::color::blue("info:")
         );
-        println!();
+        outln!();
     }
 
     // Load configuration
     let config = match crate::core::config::Config::load_all_relevant_config_files() {
         Ok(config) => config,
         Err(err) => {
-            eprintln!(
+            errln!(
                 "{}",
                 crate::internal::color::red(&format!("Failed to load config: {}", err))
             );
@@ -24,50 +24,143 @@ pub fn run(flags: &crate::cli::handler::GlobalFlags) {
     // Get dotfile mappings from config
     let mappings = crate::core::dotfiles::get_dotfile_mappings(&config);
 
-    // Show section header
-    println!();
-    println!("[{}]", crate::internal::color::green("config"));
-
-    if mappings.is_empty() {
-        println!(
-            "  {} No dotfiles configured",
-            crate::internal::color::blue("info:")
-        );
-        return;
+    let mut renderer =
+        crate::cli::render::CliRenderer::new(flags.diff_context).with_forensics(flags.verbose);
+    let checks =
+        crate::core::source_check::SourceChecks::from_config(&config, false, &mut renderer);
+    let result = crate::core::dotfiles::DotfileRoots::from_env().and_then(|roots| {
+        crate::core::dotfiles::sync_dotfiles(
+            &roots
+                .with_allow_outside_home(allow_outside_home)
+                .with_shared_host(crate::core::shared_home::host(&config)?)
+                .with_mode_policy(config.mode_policy()?),
+            &mappings,
+            dry_run,
+            crate::core::dotfiles::default_concurrency(),
+            config.keep_backups(),
+            Some(&checks),
+            &mut renderer,
+        )
+    });
+    if let Err(err) = result {
+        errln!("{}", crate::internal::color::red(&err.to_string()));
+        std::process::exit(1);
     }
+}
 
-    // Check if any actions are needed
-    let has_actions = match crate::core::dotfiles::has_actionable_dotfiles(&mappings) {
-        Ok(has) => has,
-        Err(err) => {
-            eprintln!(
-                "{}",
-                crate::internal::color::red(&format!("Failed to analyze dotfiles: {}", err))
+/// Run `owl dots audit` to report (and optionally archive) unreferenced dotfiles
+pub fn run_audit(archive: bool, json: bool, dry_run: bool) -> anyhow::Result<()> {
+    use crate::core::dotfile_audit;
+    use crate::internal::color;
+
+    let config = crate::core::config::Config::load_all_relevant_config_files()?;
+    let mappings = crate::core::dotfiles::get_dotfile_mappings(&config);
+    let roots = crate::core::dotfiles::DotfileRoots::from_env()?;
+    let orphans = dotfile_audit::audit(&roots, &mappings)?;
+
+    if json {
+        let report = serde_json::to_string_pretty(&orphans)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize audit: {}", e))?;
+        outln!("{}", report);
+    } else {
+        outln!("[{}]", color::blue("audit"));
+        if orphans.is_empty() {
+            outln!(
+                "  {} {}",
+                color::green("➔"),
+                color::dim("every dotfile is referenced by a mapping")
             );
-            std::process::exit(1);
+            return Ok(());
         }
-    };
-
-    if !has_actions {
-        println!(
-            "  {} Up to date: {} dotfiles",
-            crate::internal::color::green("➔"),
-            mappings.len()
+        outln!(
+            "  {} {} unreferenced entries in {}",
+            color::yellow("⸎"),
+            orphans.len(),
+            roots.source_dir.display()
         );
-        return;
+        for entry in &orphans {
+            outln!(
+                "    {}  {}  {}",
+                entry.path.display(),
+                color::dim(&crate::internal::util::format_size(entry.size)),
+                color::dim(&crate::internal::time::format_date(entry.modified))
+            );
+        }
     }
 
-    // Analyze and apply dotfiles
-    let actions = match crate::core::dotfiles::apply_dotfiles(&mappings, dry_run) {
-        Ok(actions) => actions,
-        Err(err) => {
-            eprintln!(
-                "{}",
-                crate::internal::color::red(&format!("Failed to apply dotfiles: {}", err))
+    if !archive || orphans.is_empty() {
+        if !json && !orphans.is_empty() {
+            outln!(
+                "  {} Run owl dots audit --archive to move them into {}/<date>/",
+                color::blue("info:"),
+                dotfile_audit::ATTIC_DIR
             );
-            std::process::exit(1);
         }
-    };
+        return Ok(());
+    }
+    let date = crate::internal::time::format_date(crate::internal::time::now_secs());
+    if dry_run {
+        if !json {
+            outln!(
+                "  {} Would archive {} entries into {}/{}",
+                color::blue("info:"),
+                orphans.len(),
+                dotfile_audit::ATTIC_DIR,
+                date
+            );
+        }
+        return Ok(());
+    }
+    let attic = dotfile_audit::archive(&roots.source_dir, &orphans, &date)?;
+    if !json {
+        outln!(
+            "  {} Archived {} entries into {}",
+            color::green("✓"),
+            orphans.len(),
+            attic.display()
+        );
+    }
+    Ok(())
+}
+
+/// Run `owl dots explain DEST`
+pub fn run_explain(destination: &str, json: bool, allow_outside_home: bool) -> anyhow::Result<()> {
+    use crate::core::dotfile_explain;
 
-    crate::core::dotfiles::print_actions(&actions, dry_run);
+    let config = crate::core::config::Config::load_all_relevant_config_files()?;
+    let roots = crate::core::dotfiles::DotfileRoots::from_env()?
+        .with_allow_outside_home(allow_outside_home)
+        .with_mode_policy(config.mode_policy()?);
+    let hostname = crate::internal::environment::get().hostname().ok();
+    let explanation = dotfile_explain::explain(&config, &roots, hostname, destination)?;
+    if json {
+        let report = serde_json::to_string_pretty(&explanation)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize the explanation: {}", e))?;
+        outln!("{}", report);
+    } else {
+        out!("{}", dotfile_explain::render_text(&explanation));
+    }
+    Ok(())
+}
+
+/// Run `owl check-source` (`owl dots check-sources`); with `strict`, findings are an error
+pub fn run_check_sources(strict: bool) -> anyhow::Result<()> {
+    use crate::core::source_check;
+    use crate::internal::color;
+
+    let config = crate::core::config::Config::load_all_relevant_config_files()?;
+    let mappings = crate::core::dotfiles::get_dotfile_mappings(&config);
+    let roots = crate::core::dotfiles::DotfileRoots::from_env()?;
+    let mut renderer = crate::cli::render::CliRenderer::new(None);
+    let checks = source_check::SourceChecks::from_config(&config, strict, &mut renderer);
+
+    outln!("[{}]", color::blue("sources"));
+    if source_check::report(&roots, &mappings, &checks, &mut renderer)? == 0 {
+        outln!(
+            "  {} {}",
+            color::green("➔"),
+            color::dim(&format!("{} dotfile sources look fine", mappings.len()))
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,32 @@
+use crate::core::env::{self, Shell};
+use crate::internal::color;
+use anyhow::Result;
+
+/// Run the env command
+///
+/// With `reload`, print shell commands for `eval "$(owl env --reload)"` instead
+/// of listing the configured variables.
+pub fn run(reload: bool) -> Result<()> {
+    if reload {
+        out!("{}", env::reload_current_shell()?);
+        return Ok(());
+    }
+
+    let config = crate::core::config::Config::load_all_relevant_config_files()?;
+    let vars = env::collect_all_env_vars(&config);
+    outln!("[{}]", color::blue("env"));
+    for var in &vars {
+        let only = match var.shell {
+            Some(Shell::Bash) => " (bash)",
+            Some(Shell::Fish) => " (fish)",
+            None => "",
+        };
+        outln!("  {}={}{}", var.key, var.value, color::dim(only));
+    }
+    outln!(
+        "  {} {} variable(s) exported",
+        color::green("➔"),
+        vars.len()
+    );
+    Ok(())
+}
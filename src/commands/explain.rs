@@ -0,0 +1,17 @@
+use anyhow::{Result, anyhow};
+
+/// Run the explain command to show which file each field of a package came from
+pub fn run(package: &str) -> Result<()> {
+    let config = crate::core::config::Config::load_all_relevant_config_files()?;
+    let explanation = crate::core::config::explain::explain(&config, package).ok_or_else(|| {
+        anyhow!(
+            "Package '{}' is not declared in any loaded config file",
+            package
+        )
+    })?;
+    out!(
+        "{}",
+        crate::core::config::explain::render_text(&explanation)
+    );
+    Ok(())
+}
@@ -4,7 +4,7 @@ use anyhow::Result;
 /// Run the find command to find where packages are defined in config files
 pub fn run(query: &[String]) {
     if query.is_empty() {
-        eprintln!(
+        errln!(
             "{}",
             crate::internal::color::red("Error: find command requires at least one argument")
         );
@@ -18,18 +18,22 @@ pub fn run(query: &[String]) {
     let results = if is_config_syntax {
         find_config_syntax_locations(query)
     } else {
-        find_package_locations(&query[0])
+        find_package_locations(query[0].trim())
     };
 
     match results {
         Ok(locations) => {
             if locations.is_empty() {
-                println!(
+                outln!(
                     "{}",
                     crate::internal::color::yellow("No matches found for the given query")
                 );
             } else {
-                display_locations(&locations);
+                // Group files get a secondary line saying why they are loaded
+                let origins = crate::core::config::Config::load_all_relevant_config_files()
+                    .map(|config| config.group_origins)
+                    .unwrap_or_default();
+                display_locations(&locations, &origins);
             }
         }
         Err(err) => {
@@ -90,7 +94,10 @@ fn find_package_in_file(
     let mut locations = Vec::new();
 
     for (line_num, line) in content.lines().enumerate() {
-        let trimmed = line.trim();
+        // Compare the form the parser sees; the `[aur]` hint is not part of the name
+        let normalized = crate::core::names::normalize_line(line);
+        let trimmed = normalized.as_ref();
+        let trimmed = trimmed.strip_suffix("[aur]").map_or(trimmed, str::trim_end);
 
         // Check for @package or @pkg declarations
         if trimmed == format!("@package {}", package_name)
@@ -282,7 +289,8 @@ fn is_in_packages_section(content: &str, line_num: usize) -> bool {
             break;
         }
 
-        let trimmed = line.trim();
+        let trimmed = crate::core::names::normalize_line(line);
+        let trimmed = trimmed.as_ref();
         if trimmed == "@packages" || trimmed == "@pkgs" {
             in_section = true;
         } else if trimmed.starts_with('@') && trimmed != "@packages" && trimmed != "@pkgs" {
@@ -298,8 +306,18 @@ fn get_all_config_files() -> Result<Vec<String>> {
     crate::internal::files::get_all_config_files()
 }
 
+/// Origin of the group defined by `file_path`, if it is a loaded group file
+fn origin_for_file<'a>(
+    file_path: &str,
+    origins: &'a [crate::core::config::loader::GroupOrigin],
+) -> Option<&'a crate::core::config::loader::GroupOrigin> {
+    origins
+        .iter()
+        .find(|origin| file_path.ends_with(&format!("/{}", origin.label())))
+}
+
 /// Display the found locations in a formatted way
-fn display_locations(locations: &[Location]) {
+fn display_locations(locations: &[Location], origins: &[crate::core::config::loader::GroupOrigin]) {
     if locations.is_empty() {
         return;
     }
@@ -314,15 +332,33 @@ fn display_locations(locations: &[Location]) {
             .push(location);
     }
 
-    println!(
+    outln!(
         "\n{} {} location(s):\n",
         crate::internal::color::bold("Found"),
         locations.len()
     );
 
     for (file_path, file_locations) in file_groups {
-        let friendly_path = file_path.replace(&std::env::var("HOME").unwrap_or_default(), "~");
-        println!("{}", crate::internal::color::highlight(&friendly_path));
+        let friendly_path = crate::internal::environment::get().display_path(&file_path);
+        outln!("{}", crate::internal::color::highlight(&friendly_path));
+        if let Some(origin) = origin_for_file(&file_path, origins) {
+            outln!(
+                "  {}",
+                crate::internal::color::dim(&format!("via {}", origin.describe()))
+            );
+            for chain in &origin.also_via {
+                outln!(
+                    "  {}",
+                    crate::internal::color::dim(&format!(
+                        "also via {}",
+                        crate::core::config::loader::GroupOrigin::describe_chain(
+                            chain,
+                            &origin.label()
+                        )
+                    ))
+                );
+            }
+        }
 
         for location in file_locations {
             let context_indicator = match location.context {
@@ -336,14 +372,14 @@ fn display_locations(locations: &[Location]) {
                 LocationContext::GroupDeclaration => crate::internal::color::success("[group]"),
             };
 
-            println!(
+            outln!(
                 "  {} {}: {}",
                 context_indicator,
                 crate::internal::color::dim(&format!("line {}", location.line_number)),
                 crate::internal::color::description(&location.line_content)
             );
         }
-        println!();
+        outln!();
     }
 }
 
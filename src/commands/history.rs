@@ -0,0 +1,114 @@
+use crate::core::history::History;
+use crate::internal::color;
+use crate::internal::time::{format_date, format_duration_ms};
+use anyhow::Result;
+
+/// Number of packages shown by `owl history --slow`
+const SLOW_LIMIT: usize = 10;
+
+/// Run the history command to show recorded apply runs
+pub fn run(slow: bool) -> Result<()> {
+    let history = History::load()?;
+
+    if slow {
+        let packages = history.slowest_packages(SLOW_LIMIT);
+        outln!("[{}]", color::blue("slowest installs"));
+        if packages.is_empty() {
+            outln!(
+                "  {} {}",
+                color::green("➔"),
+                color::dim("no install timings recorded yet (use owl apply --timing)")
+            );
+            return Ok(());
+        }
+        for (i, pkg) in packages.iter().enumerate() {
+            outln!(
+                "  {}. {} {} {}",
+                i + 1,
+                pkg.package,
+                color::yellow(&format_duration_ms(pkg.average_ms)),
+                color::dim(&format!(
+                    "(max {}, {} install(s))",
+                    format_duration_ms(pkg.max_ms),
+                    pkg.installs
+                ))
+            );
+        }
+        return Ok(());
+    }
+
+    outln!("[{}]", color::blue("history"));
+    let mut lines = Vec::new();
+    for run in &history.pm_runs {
+        let mut line = format!(
+            "  {} pm {}",
+            color::dim(&format_date(run.started)),
+            run.args.join(" ")
+        );
+        line.push_str(&format!(
+            " {}",
+            color::dim(&format!("({})", format_duration_ms(run.duration_ms)))
+        ));
+        if !run.success {
+            line.push_str(&format!(" {}", color::red("failed")));
+        }
+        if !run.managed.is_empty() {
+            line.push_str(&format!(
+                " {}",
+                color::dim(&format!("now managing {}", run.managed.join(", ")))
+            ));
+        }
+        lines.push((run.started, line));
+    }
+    for run in &history.runs {
+        let total: u64 = run.install_timings.iter().map(|t| t.duration_ms).sum();
+        let mut line = format!("  {} apply", color::dim(&format_date(run.started)));
+        if !run.install_timings.is_empty() {
+            line.push_str(&format!(
+                " {}",
+                color::dim(&format!(
+                    "({} timed install(s), {})",
+                    run.install_timings.len(),
+                    format_duration_ms(total)
+                ))
+            ));
+        }
+        if let Some(snapshots) = snapshot_column(run) {
+            line.push_str(&format!(" {}", color::cyan(&snapshots)));
+        }
+        if !run.reconciled.is_empty() {
+            line.push_str(&format!(
+                " {}",
+                color::dim(&crate::core::reconcile::summary(&run.reconciled))
+            ));
+        }
+        if let Some(splay_ms) = run.splay_ms {
+            line.push_str(&format!(
+                " {}",
+                color::dim(&format!("splay {}", format_duration_ms(splay_ms)))
+            ));
+        }
+        lines.push((run.started, line));
+    }
+    // Oldest first, apply and pm runs interleaved
+    lines.sort_by_key(|(started, _)| *started);
+    for (_, line) in lines {
+        outln!("{}", line);
+    }
+    let mut total = format!("{} recorded apply run(s)", history.runs.len());
+    if !history.pm_runs.is_empty() {
+        total.push_str(&format!(", {} owl pm run(s)", history.pm_runs.len()));
+    }
+    outln!("  {} {}", color::green("➔"), total);
+    Ok(())
+}
+
+/// `snapshot 41 → 42` for runs that recorded snapshot ids
+fn snapshot_column(run: &crate::core::history::ApplyRecord) -> Option<String> {
+    match (&run.pre_snapshot, &run.post_snapshot) {
+        (None, None) => None,
+        (Some(pre), None) => Some(format!("snapshot {}", pre)),
+        (None, Some(post)) => Some(format!("snapshot → {}", post)),
+        (Some(pre), Some(post)) => Some(format!("snapshot {} → {}", pre, post)),
+    }
+}
@@ -0,0 +1,343 @@
+use crate::core::config::SYSTEM_SERVICES;
+use crate::core::service_import::{Placement, Suggestion};
+use crate::core::services::ServiceManager;
+use crate::internal::color;
+use anyhow::{Result, anyhow};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Run the import-pacman command to add installed packages to a config file
+pub fn run(explicit_only: bool, into: Option<&str>, dry_run: bool) -> Result<()> {
+    let pm = crate::core::pm::manager();
+    let installed = if explicit_only {
+        pm.list_explicit()?
+    } else {
+        pm.list_installed()?
+    };
+
+    let target = target_path(into)?;
+    let content = if target.exists() {
+        std::fs::read_to_string(&target)
+            .map_err(|e| anyhow!("Failed to read {}: {}", target.display(), e))?
+    } else {
+        String::new()
+    };
+
+    // Packages already declared anywhere in the config are left where they are
+    let mut declared: HashSet<String> =
+        crate::core::config::Config::load_all_relevant_config_files()
+            .map(|config| config.packages.into_keys().collect())
+            .unwrap_or_default();
+    declared.extend(
+        crate::core::config::Config::parse(&content)?
+            .packages
+            .into_keys(),
+    );
+
+    let untracked = crate::core::state::default_untracked_packages();
+    let packages = select_imports(&installed, &declared, &untracked);
+
+    outln!("[{}]", color::blue("import"));
+    let display = display_path(&target);
+    if packages.is_empty() {
+        outln!(
+            "  {} {}",
+            color::green("➔"),
+            color::dim("no new packages to import")
+        );
+        return Ok(());
+    }
+    if dry_run {
+        outln!(
+            "  {} Would import {} packages into {}",
+            color::blue("info:"),
+            packages.len(),
+            display
+        );
+        return Ok(());
+    }
+
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", parent.display(), e))?;
+    }
+    std::fs::write(&target, merge_into_packages_section(&content, &packages))
+        .map_err(|e| anyhow!("Failed to write {}: {}", target.display(), e))?;
+
+    let mut state = crate::core::state::PackageState::load()
+        .map_err(|e| anyhow!("Failed to load package state: {}", e))?;
+    for pkg in &packages {
+        state.add_managed(pkg.clone());
+    }
+    state
+        .save()
+        .map_err(|e| anyhow!("Failed to save package state: {}", e))?;
+
+    outln!("  Imported {} packages into {}", packages.len(), display);
+    Ok(())
+}
+
+/// Run `import --services`: add services enabled outside owl as `:service` lines
+///
+/// New `@package` blocks go into `into` (default main.owl); declared packages
+/// get the line in the file declaring them.
+pub fn run_services(
+    into: Option<&str>,
+    all: bool,
+    dry_run: bool,
+    policy: &crate::core::confirm::ConfirmPolicy,
+) -> Result<()> {
+    let owl_dir = crate::internal::environment::get().owl_dir()?;
+    let config = crate::core::config::Config::load_all_relevant_config_files_from_path(&owl_dir)?;
+    let units = crate::core::services::Systemctl.list_enabled()?;
+    let mut files: Vec<PathBuf> = units
+        .iter()
+        .map(crate::core::service_import::unit_file)
+        .collect();
+    files.sort();
+    files.dedup();
+    let owners = crate::core::pm::manager().owner_of(&files)?;
+    let suggestions = crate::core::service_import::plan(&units, &owners, &config);
+
+    let target = target_path(into)?;
+    let target_display = display_path(&target);
+    outln!("[{}]", color::blue("import"));
+    for suggestion in &suggestions {
+        outln!("{}", describe(suggestion, &target_display));
+    }
+    let writes: Vec<&Suggestion> = suggestions
+        .iter()
+        .filter(|s| !matches!(s.placement, Placement::Skip(_)))
+        .collect();
+    if writes.is_empty() {
+        outln!(
+            "  {} {}",
+            color::green("➔"),
+            color::dim("no services to import")
+        );
+        return Ok(());
+    }
+    if dry_run {
+        outln!(
+            "  {} Would add {} service(s)",
+            color::blue("info:"),
+            writes.len()
+        );
+        return Ok(());
+    }
+    if !all {
+        let items: Vec<String> = writes
+            .iter()
+            .map(|s| format!("{} ({})", s.unit.name, owner(s)))
+            .collect();
+        if !crate::cli::ui::confirm(
+            crate::core::confirm::ConfirmKind::ServiceImport,
+            &items,
+            policy,
+        ) {
+            outln!("  {}", color::blue("Service import cancelled"));
+            return Ok(());
+        }
+    }
+
+    let imported = write_services(&writes, &owl_dir, &target)?;
+    outln!("  Imported {} service(s)", imported);
+    Ok(())
+}
+
+/// `  + :service sshd.service -> openssh in groups/net.owl`, or why a unit is left out
+fn describe(suggestion: &Suggestion, target: &str) -> String {
+    let directive = color::green(&suggestion.directive());
+    match &suggestion.placement {
+        Placement::Attach { package, file } => {
+            format!("  + {} -> {} in {}", directive, package, file)
+        }
+        Placement::Declare { package } => {
+            format!(
+                "  + {} -> new @package {} in {}",
+                directive, package, target
+            )
+        }
+        Placement::SystemServices => {
+            format!(
+                "  + {} -> @package {} in {}",
+                directive, SYSTEM_SERVICES, target
+            )
+        }
+        Placement::Skip(reason) => color::dim(&format!("  - {}: {}", suggestion.unit.name, reason)),
+    }
+}
+
+fn owner(suggestion: &Suggestion) -> &str {
+    match &suggestion.placement {
+        Placement::Attach { package, .. } | Placement::Declare { package } => package,
+        Placement::SystemServices => SYSTEM_SERVICES,
+        Placement::Skip(_) => "",
+    }
+}
+
+/// Write the suggestions into their files, returning how many were written
+///
+/// Packages declared by a new block are marked managed, as imported packages are;
+/// the `_system-services` block is created on first use and installs nothing.
+fn write_services(writes: &[&Suggestion], owl_dir: &Path, target: &Path) -> Result<usize> {
+    let mut contents: BTreeMap<PathBuf, String> = BTreeMap::new();
+    let mut declared = Vec::new();
+    let mut written = 0;
+    for suggestion in writes {
+        let path = match &suggestion.placement {
+            Placement::Attach { file, .. } => owl_dir.join(file),
+            _ => target.to_path_buf(),
+        };
+        if !contents.contains_key(&path) {
+            let content = if path.exists() {
+                std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?
+            } else {
+                String::new()
+            };
+            contents.insert(path.clone(), content);
+        }
+        let content = contents.get_mut(&path).expect("read above");
+        let updated = match &suggestion.placement {
+            Placement::Attach { package, .. } => crate::core::config::managed::insert_directive(
+                content,
+                package,
+                &suggestion.directive(),
+            ),
+            Placement::Declare { package } => {
+                declared.push(package.clone());
+                Some(crate::core::config::managed::insert_package_block(
+                    content,
+                    package,
+                    &[suggestion.directive()],
+                ))
+            }
+            Placement::SystemServices => Some(
+                crate::core::config::managed::insert_directive(
+                    content,
+                    SYSTEM_SERVICES,
+                    &suggestion.directive(),
+                )
+                .unwrap_or_else(|| {
+                    crate::core::config::managed::insert_package_block(
+                        content,
+                        SYSTEM_SERVICES,
+                        &[suggestion.directive()],
+                    )
+                }),
+            ),
+            Placement::Skip(_) => None,
+        };
+        match updated {
+            Some(updated) => {
+                *content = updated;
+                written += 1;
+            }
+            None => warnln!(
+                "  {} {}: {} has no @package {} block; add the :service by hand",
+                color::yellow("skipped"),
+                suggestion.unit.name,
+                display_path(&path),
+                owner(suggestion)
+            ),
+        }
+    }
+
+    for (path, content) in &contents {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Failed to create directory {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(path, content)
+            .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+    }
+    if !declared.is_empty() {
+        let mut state = crate::core::state::PackageState::load()
+            .map_err(|e| anyhow!("Failed to load package state: {}", e))?;
+        for package in declared {
+            state.add_managed(package);
+        }
+        state
+            .save()
+            .map_err(|e| anyhow!("Failed to save package state: {}", e))?;
+    }
+    Ok(written)
+}
+
+/// Config file to import into; bare names are relative to `~/.owl`
+fn target_path(into: Option<&str>) -> Result<PathBuf> {
+    let owl_dir = crate::internal::environment::get().owl_dir()?;
+    Ok(match into {
+        None => owl_dir.join(crate::internal::constants::MAIN_CONFIG_FILE),
+        Some(path) if path.contains('/') => PathBuf::from(path),
+        Some(name) => owl_dir.join(name),
+    })
+}
+
+fn display_path(path: &std::path::Path) -> String {
+    crate::internal::environment::get().display_path(&path.to_string_lossy())
+}
+
+/// Installed packages worth importing, sorted
+fn select_imports(
+    installed: &HashSet<String>,
+    declared: &HashSet<String>,
+    untracked: &[String],
+) -> Vec<String> {
+    let mut packages: Vec<String> = installed
+        .iter()
+        .filter(|p| !declared.contains(*p) && !untracked.contains(p))
+        .cloned()
+        .collect();
+    packages.sort();
+    packages
+}
+
+/// Add packages to the owl-managed block, creating one if needed
+fn merge_into_packages_section(content: &str, packages: &[String]) -> String {
+    crate::core::config::managed::insert_packages(content, packages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_of(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_select_imports_filters_declared_and_untracked() {
+        let installed = set_of(&["ripgrep", "linux", "neovim", "fd"]);
+        let declared = set_of(&["neovim"]);
+        let untracked = vec!["linux".to_string()];
+        assert_eq!(
+            select_imports(&installed, &declared, &untracked),
+            vec!["fd", "ripgrep"]
+        );
+    }
+
+    #[test]
+    fn test_merge_appends_to_existing_section() {
+        let content = "@packages\nneovim\n\n@package git\n:config gitconfig\n";
+        let merged = merge_into_packages_section(content, &["fd".to_string()]);
+        assert_eq!(
+            merged,
+            "@packages\nneovim\nfd\n\n@package git\n:config gitconfig\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_creates_section() {
+        let merged = merge_into_packages_section("@env A=1\n", &["fd".to_string()]);
+        assert_eq!(
+            merged,
+            "@env A=1\n\n# >>> owl managed >>>\n@packages\nfd\n# <<< owl managed <<<\n"
+        );
+        assert_eq!(
+            merge_into_packages_section("", &["fd".to_string()]),
+            "# >>> owl managed >>>\n@packages\nfd\n# <<< owl managed <<<\n"
+        );
+    }
+}
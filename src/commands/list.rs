@@ -0,0 +1,45 @@
+use crate::internal::color;
+use anyhow::{Result, anyhow};
+
+/// Run the list command to show packages owl manages
+pub fn run(since: Option<&str>) -> Result<()> {
+    let state = crate::core::state::PackageState::load()
+        .map_err(|e| anyhow!("Failed to load package state: {}", e))?;
+
+    let Some(since) = since else {
+        outln!("[{}]", color::blue("managed"));
+        for pkg in &state.managed {
+            let date = state
+                .installed_at
+                .get(pkg)
+                .map(|when| crate::internal::time::format_date(*when))
+                .unwrap_or_else(|| "-".to_string());
+            outln!("  {} {}", color::dim(&date), pkg);
+        }
+        outln!(
+            "  {} {} managed package(s)",
+            color::green("➔"),
+            state.managed.len()
+        );
+        return Ok(());
+    };
+
+    let cutoff = crate::internal::time::parse_relative_date(since, std::time::SystemTime::now())?;
+    let packages = state.installed_since(crate::internal::time::to_secs(cutoff));
+
+    outln!("[{}]", color::blue("managed"));
+    for (pkg, when) in &packages {
+        outln!(
+            "  {} {}",
+            color::dim(&crate::internal::time::format_date(*when)),
+            pkg
+        );
+    }
+    outln!(
+        "  {} {} package(s) installed by owl since {}",
+        color::green("➔"),
+        packages.len(),
+        crate::internal::time::format_date(crate::internal::time::to_secs(cutoff))
+    );
+    Ok(())
+}
@@ -2,6 +2,21 @@ pub mod add;
 pub mod adopt;
 pub mod apply;
 pub mod clean;
+pub mod config;
+pub mod daemon;
+pub mod doctor;
 pub mod dots;
 pub mod edit;
+pub mod env;
+pub mod explain;
 pub mod find;
+pub mod history;
+pub mod import;
+pub mod list;
+pub mod orphans;
+pub mod plan;
+pub mod pm;
+pub mod services;
+pub mod state;
+pub mod status;
+pub mod tree;
@@ -0,0 +1,130 @@
+//! `owl orphans`: dependencies nothing installed needs any more
+//!
+//! pacman lists them with `-Qdtq`. `--remove` removes them once confirmed,
+//! keeping packages the config declares or the untracked list protects, the
+//! same protection `apply` gives its removals.
+
+use anyhow::Result;
+use std::collections::HashSet;
+
+use crate::core::config::Config;
+use crate::core::confirm::{ConfirmKind, ConfirmPolicy};
+use crate::core::pm::PackageManager;
+use crate::core::state::PackageState;
+use crate::internal::{color, constants};
+
+/// List orphaned packages, and remove the unprotected ones with `remove`
+pub fn run(flags: &crate::cli::handler::GlobalFlags, remove: bool) -> Result<()> {
+    let env = crate::internal::environment::get();
+    let state_dir = env.owl_dir()?.join(constants::STATE_DIR);
+    // Listing needs no lock; removing rewrites the managed list
+    let _lock = if remove && !flags.dry_run {
+        Some(crate::core::lock::WriterLock::acquire(&state_dir)?)
+    } else {
+        None
+    };
+    let config = Config::load_all_relevant_config_files()?;
+    let mut state = PackageState::load_from(&state_dir)?;
+    state.merge_config_untracked(&config);
+
+    let desired: HashSet<String> = config.packages.keys().cloned().collect();
+    execute(
+        &*crate::core::pm::manager(),
+        &desired,
+        &mut state,
+        remove,
+        flags.dry_run,
+        &flags.confirm_policy(true),
+    )?;
+    Ok(())
+}
+
+/// List the orphans `pm` reports and, with `remove`, remove the ones not
+/// protected; returns the removed packages
+pub fn execute(
+    pm: &dyn PackageManager,
+    desired: &HashSet<String>,
+    state: &mut PackageState,
+    remove: bool,
+    dry_run: bool,
+    policy: &ConfirmPolicy,
+) -> Result<Vec<String>> {
+    let orphans = pm.list_orphans()?;
+    let removable = crate::core::package::orphan_removal_set(&orphans, desired, state);
+
+    outln!("[{}]", color::blue("orphans"));
+    for pkg in &orphans {
+        let note = if desired.contains(pkg) {
+            " (declared, kept)"
+        } else if !removable.contains(pkg) {
+            " (untracked, kept)"
+        } else {
+            ""
+        };
+        outln!("  {} {}{}", color::yellow("•"), pkg, color::dim(note));
+    }
+    outln!(
+        "  {} {} orphaned package(s), {} removable",
+        color::green("➔"),
+        orphans.len(),
+        removable.len()
+    );
+
+    if !remove || removable.is_empty() {
+        return Ok(Vec::new());
+    }
+    if dry_run {
+        outln!(
+            "  {} Would remove: {}",
+            color::red("remove"),
+            removable.join(", ")
+        );
+        return Ok(Vec::new());
+    }
+    if !crate::cli::ui::confirm(ConfirmKind::OrphanRemoval, &removable, policy) {
+        outln!("  {}", color::blue("Orphan removal cancelled"));
+        return Ok(Vec::new());
+    }
+    pm.remove_packages(&removable, true)?;
+    for pkg in &removable {
+        state.remove_managed(pkg);
+    }
+    state.save()?;
+    Ok(removable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::pm::fake;
+
+    const LOG: &str = r#"echo "$(basename "$0") $*" >> "$(dirname "$0")/calls""#;
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_removal_needs_confirmation() {
+        let (dir, pm) = fake::pm(LOG, &format!("{}\nprintf 'meson\\ngo\\n'", LOG));
+        let mut state = PackageState::default();
+        let yes = ConfirmPolicy {
+            assume_answers: true,
+            ..Default::default()
+        };
+        // -y leaves orphan removal to a person
+        let removed = execute(&pm, &set(&["go"]), &mut state, true, false, &yes).unwrap();
+        assert!(removed.is_empty());
+        let no_tty = ConfirmPolicy::default();
+        assert!(
+            execute(&pm, &set(&[]), &mut state, true, false, &no_tty)
+                .unwrap()
+                .is_empty()
+        );
+        // A dry run and a plain listing remove nothing either
+        execute(&pm, &set(&[]), &mut state, true, true, &yes).unwrap();
+        execute(&pm, &set(&[]), &mut state, false, false, &yes).unwrap();
+        let calls = std::fs::read_to_string(dir.path().join("calls")).unwrap();
+        assert_eq!(calls, "pacman -Qdtq\n".repeat(4));
+    }
+}
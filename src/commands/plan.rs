@@ -0,0 +1,46 @@
+use anyhow::{Result, anyhow};
+use std::path::Path;
+
+use crate::commands::apply::{analysis, phases, review};
+use crate::internal::color;
+
+/// Write what `owl apply` would do as a Markdown review file, or print it
+///
+/// Changes nothing; the file is read back by `owl apply --approved-review`.
+pub fn run(review_file: Option<&Path>) -> Result<()> {
+    let analysis = crate::internal::util::execute_with_progress(
+        || analysis::analyze_system(true),
+        "Analyzing system configuration",
+    )?;
+    let config = &analysis.config;
+    let (to_install, to_remove) = crate::commands::apply::split_actions(&analysis.actions);
+    let updates = phases::resolve_update_phases(config, &phases::PhaseSelection::default());
+    let plan = review::gather(
+        config,
+        &to_install,
+        &to_remove,
+        &updates,
+        &review::default_roots(config)?,
+    )?;
+
+    let markdown = plan.to_markdown();
+    let Some(path) = review_file else {
+        outln!("{}", markdown);
+        return Ok(());
+    };
+    std::fs::write(path, markdown)
+        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+    outln!("[{}]", color::blue("plan"));
+    outln!(
+        "  {} Wrote {} packages and {} dotfiles to {}",
+        color::green("✓"),
+        plan.packages.len(),
+        plan.dotfiles.len(),
+        path.display()
+    );
+    outln!(
+        "  Review it, then run {}",
+        color::bold(&format!("owl apply --approved-review {}", path.display()))
+    );
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! `owl pm -- ARGS`: a one-off package manager run with owl's bookkeeping
+//!
+//! The arguments are checked by `core::passthrough`, run under the writer
+//! lock with the usual spinner and retries, and recorded in the history. When
+//! the run installed declared packages owl does not manage yet, they are
+//! settled the way `apply` settles already-installed declared packages.
+
+use anyhow::{Result, anyhow};
+use std::collections::HashSet;
+
+use crate::commands::apply::adoption::{self, Mode};
+use crate::core::config::Config;
+use crate::core::confirm::ConfirmKind;
+use crate::core::events::{EventSink, OwlEvent};
+use crate::core::history::{History, PmRecord};
+use crate::core::passthrough::{self, Invocation};
+use crate::core::pm::PackageManager;
+use crate::core::state::PackageState;
+use crate::internal::{color, constants};
+
+/// Run the package manager with `args`
+pub fn run(
+    flags: &crate::cli::handler::GlobalFlags,
+    args: &[String],
+    adopt_managed: bool,
+) -> Result<()> {
+    let invocation = passthrough::classify(args)?;
+    let command = format!(
+        "{} {}",
+        constants::PACKAGE_MANAGER,
+        invocation.args.join(" ")
+    );
+    outln!("[{}]", color::blue("pm"));
+    if flags.dry_run {
+        outln!("  {} Would run {}", color::blue("info:"), command);
+        return Ok(());
+    }
+
+    let env = crate::internal::environment::get();
+    let state_dir = env.owl_dir()?.join(constants::STATE_DIR);
+    let _lock = crate::core::lock::WriterLock::acquire(&state_dir)?;
+    // Only needed to settle installs, so a broken config does not block `-Sc`
+    let config = if invocation.installs.is_empty() {
+        None
+    } else {
+        Some(Config::load_all_relevant_config_files()?)
+    };
+    let mut state = PackageState::load_from(&state_dir)?;
+    let policy = flags.confirm_policy(true);
+    let mode = Mode::resolve(adopt_managed, false, policy.asks());
+    let mut sink = crate::cli::render::CliRenderer::default();
+
+    let record = execute(
+        &*crate::core::pm::manager(),
+        &invocation,
+        config.as_ref(),
+        &mut state,
+        mode,
+        |candidates| crate::cli::ui::confirm(ConfirmKind::Adoption, candidates, &policy),
+        &mut sink,
+    );
+    if invocation.transaction && record.success {
+        state.save_to(&state_dir)?;
+    }
+    let mut history = History::load_from(&state_dir)?;
+    history.record_pm(record.clone());
+    history.save_to(&state_dir)?;
+
+    if !record.success {
+        return Err(anyhow!("{} failed", command));
+    }
+    outln!(
+        "  {} {} {}",
+        color::green("➔"),
+        command,
+        color::dim(&format!(
+            "({})",
+            crate::internal::time::format_duration_ms(record.duration_ms)
+        ))
+    );
+    for package in &record.managed {
+        outln!("  {} now managing {}", color::green("➔"), package);
+    }
+    Ok(())
+}
+
+/// Run `invocation` and settle the declared packages it installed
+///
+/// `config` is only read for runs that install packages by name.
+pub fn execute(
+    pm: &dyn PackageManager,
+    invocation: &Invocation,
+    config: Option<&Config>,
+    state: &mut PackageState,
+    mode: Mode,
+    confirm: impl FnOnce(&[String]) -> bool,
+    sink: &mut dyn EventSink,
+) -> PmRecord {
+    let started = crate::internal::time::now_secs();
+    let start = std::time::Instant::now();
+    let success = match pm.passthrough(&invocation.args) {
+        Ok(status) => status.success(),
+        Err(e) => {
+            sink.emit(OwlEvent::Error(e.to_string()));
+            false
+        }
+    };
+    let mut record = PmRecord {
+        started,
+        args: invocation.args.clone(),
+        success,
+        duration_ms: start.elapsed().as_millis() as u64,
+        managed: Vec::new(),
+    };
+    let Some(config) = config.filter(|_| success && invocation.transaction) else {
+        return record;
+    };
+
+    // Queried again, as the run changed what is installed
+    let installed = match pm.list_installed() {
+        Ok(installed) => installed,
+        Err(e) => {
+            sink.emit(OwlEvent::Warning(format!(
+                "Could not refresh installed packages, managed state left as is: {}",
+                e
+            )));
+            return record;
+        }
+    };
+    let candidates = candidates(&invocation.installs, config, state, &installed);
+    if adoption::settle(state, &candidates, mode, confirm, sink) {
+        record.managed = candidates
+            .into_iter()
+            .filter(|p| state.is_managed(p))
+            .collect();
+        for package in &record.managed {
+            state.record_installed(package, started);
+        }
+    }
+    record
+}
+
+/// Declared packages among `installs` that are installed but not managed
+fn candidates(
+    installs: &[String],
+    config: &Config,
+    state: &PackageState,
+    installed: &HashSet<String>,
+) -> Vec<String> {
+    super::apply::analysis::adoption_candidates(config, state, |package| {
+        Ok(installs.iter().any(|i| i == package) && installed.contains(package))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::pm::fake;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_candidates_are_declared_installed_and_unmanaged() {
+        let config = Config::parse("@packages\nbat\nfd\nfoo\nripgrep\n").unwrap();
+        let mut state = PackageState::default();
+        state.add_managed("bat".to_string());
+        state.add_declined("ripgrep".to_string());
+        let installed: HashSet<String> = names(&["bat", "foo", "ripgrep", "zoxide"])
+            .into_iter()
+            .collect();
+        // fd was not installed by the run, zoxide is not declared
+        let installs = names(&["bat", "fd", "foo", "ripgrep", "zoxide"]);
+        assert_eq!(
+            candidates(&installs, &config, &state, &installed),
+            names(&["foo"])
+        );
+    }
+
+    /// paru that logs its arguments and "installs" foo; `-Qq` lists what is installed
+    fn fake_paru(exit: i32) -> (tempfile::TempDir, crate::core::pm::ParuPacman) {
+        fake::pm(
+            &format!(
+                "dir=$(dirname \"$0\")\n\
+                 if [ \"$1\" = -Qq ]; then cat \"$dir/installed\"; exit 0; fi\n\
+                 echo \"$@\" > \"$dir/args\"\n\
+                 [ {exit} = 0 ] && echo foo >> \"$dir/installed\"\n\
+                 exit {exit}"
+            ),
+            "exit 0",
+        )
+    }
+
+    #[test]
+    fn test_upgrade_from_file_manages_declared_package() {
+        let (dir, pm) = fake_paru(0);
+        std::fs::write(dir.path().join("installed"), "git\n").unwrap();
+        let config = Config::parse("@packages\ngit\nfoo\n").unwrap();
+        let invocation =
+            passthrough::classify(&names(&["-U", "./foo-1.0-1-x86_64.pkg.tar.zst"])).unwrap();
+        let mut state = PackageState::default();
+
+        let record = execute(
+            &pm,
+            &invocation,
+            Some(&config),
+            &mut state,
+            Mode::Ask,
+            |candidates| candidates == ["foo"],
+            &mut |_| {},
+        );
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("args")).unwrap(),
+            "-U ./foo-1.0-1-x86_64.pkg.tar.zst --noconfirm\n"
+        );
+        assert!(record.success);
+        assert_eq!(record.args, invocation.args);
+        assert_eq!(record.managed, names(&["foo"]));
+        assert!(state.is_managed("foo"));
+        assert!(!state.is_managed("git"));
+        assert!(state.installed_at.contains_key("foo"));
+    }
+
+    #[test]
+    fn test_failed_run_is_recorded_and_settles_nothing() {
+        let (dir, pm) = fake_paru(1);
+        std::fs::write(dir.path().join("installed"), "").unwrap();
+        let config = Config::parse("@packages\nfoo\n").unwrap();
+        let invocation =
+            passthrough::classify(&names(&["-U", "./foo-1.0-1-x86_64.pkg.tar.zst"])).unwrap();
+        let mut state = PackageState::default();
+
+        let record = execute(
+            &pm,
+            &invocation,
+            Some(&config),
+            &mut state,
+            Mode::Adopt,
+            |_| panic!("must not prompt"),
+            &mut |_| {},
+        );
+
+        assert!(!record.success);
+        assert!(record.managed.is_empty());
+        assert!(state.managed.is_empty());
+    }
+
+    #[test]
+    fn test_read_only_run_does_not_query_installed_packages() {
+        // -Qq would fail, so only passthrough may be called
+        let (_dir, pm) = fake::pm("if [ \"$1\" = -Qq ]; then exit 1; fi\nexit 0", "exit 0");
+        let config = Config::parse("@packages\nfoo\n").unwrap();
+        let invocation = passthrough::classify(&names(&["-Sc"])).unwrap();
+        let mut warnings = Vec::new();
+        let record = execute(
+            &pm,
+            &invocation,
+            Some(&config),
+            &mut PackageState::default(),
+            Mode::Adopt,
+            |_| panic!("must not prompt"),
+            &mut |event| warnings.push(event),
+        );
+        assert!(record.success);
+        assert!(warnings.is_empty());
+    }
+}
@@ -0,0 +1,18 @@
+use anyhow::Result;
+
+use crate::core::services::{ServiceLedger, Systemctl};
+use crate::internal::color;
+
+/// Take ownership of a service that was enabled before owl saw it
+pub fn adopt(name: &str) -> Result<()> {
+    let name = &crate::core::names::normalize_service_name(name)?;
+    let mut ledger = ServiceLedger::load()?;
+    ledger.adopt(name, &Systemctl, crate::internal::time::now_secs())?;
+    ledger.save()?;
+    outln!(
+        "  {} owl now manages {}; it will propose disabling it once no package declares it",
+        color::green("✓"),
+        color::bold(name)
+    );
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! `owl state`: what the state files say is managed, untracked and hidden
+//!
+//! Managed packages are the ones proposed for removal once they leave the
+//! config; untracked and hidden ones never are. Each entry is shown with
+//! whether it is installed right now, so stale entries stand out.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::core::state::PackageState;
+use crate::internal::color;
+
+/// One state entry and whether the package is installed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub installed: bool,
+}
+
+/// The three package lists of the state, each sorted by name
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StateReport {
+    pub managed: Vec<Entry>,
+    pub untracked: Vec<Entry>,
+    pub hidden: Vec<Entry>,
+}
+
+/// Print the managed, untracked and hidden lists, or them as JSON
+pub fn run(json: bool) -> Result<()> {
+    let state = PackageState::read()?;
+    let installed = crate::core::package::get_installed_packages()?;
+    let report = report(&state, &installed);
+    if json {
+        let out = serde_json::to_string_pretty(&report)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize the state: {}", e))?;
+        outln!("{}", out);
+        return Ok(());
+    }
+    for (i, (title, summary, entries)) in sections(&report).into_iter().enumerate() {
+        if i > 0 {
+            outln!();
+        }
+        outln!("[{}] {}", color::blue(title), color::dim(&summary));
+        for entry in entries {
+            if entry.installed {
+                outln!("  {}", entry.name);
+            } else {
+                outln!(
+                    "  {} {}",
+                    color::yellow(&entry.name),
+                    color::dim("(not installed)")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn report(state: &PackageState, installed: &HashSet<String>) -> StateReport {
+    let entries = |names: &[String]| {
+        let mut entries: Vec<Entry> = names
+            .iter()
+            .map(|name| Entry {
+                name: name.clone(),
+                installed: installed.contains(name),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries.dedup();
+        entries
+    };
+    StateReport {
+        managed: entries(&state.managed),
+        untracked: entries(&state.untracked),
+        hidden: entries(&state.hidden),
+    }
+}
+
+/// `(title, "3 entries, 1 not installed", entries)` per list
+fn sections(report: &StateReport) -> Vec<(&'static str, String, &[Entry])> {
+    [
+        ("managed", &report.managed),
+        ("untracked", &report.untracked),
+        ("hidden", &report.hidden),
+    ]
+    .into_iter()
+    .map(|(title, entries)| {
+        let stale = entries.iter().filter(|e| !e.installed).count();
+        let mut summary = format!(
+            "{} {}",
+            entries.len(),
+            if entries.len() == 1 {
+                "entry"
+            } else {
+                "entries"
+            }
+        );
+        if stale > 0 {
+            summary.push_str(&format!(", {} not installed", stale));
+        }
+        (title, summary, entries.as_slice())
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_lists_with_installed_status() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("managed.json"), r#"["fd", "bat", "gone"]"#).unwrap();
+        std::fs::write(dir.path().join("untracked.json"), r#"["linux"]"#).unwrap();
+        std::fs::write(dir.path().join("hidden.txt"), "steam\n").unwrap();
+        let state = PackageState::read_from(dir.path()).unwrap();
+        let installed: HashSet<String> = ["bat", "fd", "linux"].map(String::from).into();
+
+        let report = report(&state, &installed);
+        let printed: Vec<String> = sections(&report)
+            .into_iter()
+            .map(|(title, summary, entries)| {
+                let names: Vec<String> = entries
+                    .iter()
+                    .map(|e| match e.installed {
+                        true => e.name.clone(),
+                        false => format!("{} (not installed)", e.name),
+                    })
+                    .collect();
+                format!("[{}] {}: {}", title, summary, names.join(", "))
+            })
+            .collect();
+        assert_eq!(
+            printed,
+            [
+                "[managed] 3 entries, 1 not installed: bat, fd, gone (not installed)",
+                "[untracked] 1 entry: linux",
+                "[hidden] 1 entry, 1 not installed: steam (not installed)",
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&report).unwrap()["managed"][2],
+            serde_json::json!({"name": "gone", "installed": false})
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+
+use crate::core::aur_rpc::{self, AurCache, Curl};
+use crate::internal::color;
+
+/// Print everything apply would change as `kind:name` lines (see `core::drift`)
+///
+/// Plans like `apply --dry-run` without printing anything else; `safe` keeps
+/// `<cmd:...>` env values from being run.
+pub fn run_names_only(safe: bool) -> Result<()> {
+    use crate::commands::apply::{analysis, phases, review};
+    let analysis = analysis::analyze_system(true)?;
+    let config = &analysis.config;
+    let (to_install, to_remove) = crate::commands::apply::split_actions(&analysis.actions);
+    let updates = phases::resolve_update_phases(config, &phases::PhaseSelection::default());
+    let plan = review::gather(
+        config,
+        &to_install,
+        &to_remove,
+        &updates,
+        &review::default_roots(config)?,
+    )?;
+    let services = crate::core::services::plan_service_changes(
+        &crate::core::services::get_configured_services(config),
+        &crate::core::services::Systemctl,
+    );
+    let env = crate::core::env::pending_keys(config, safe)?;
+    for line in crate::core::drift::lines(&plan, &services, &env) {
+        outln!("{}", line);
+    }
+    Ok(())
+}
+
+/// `last successful apply: 3d ago`, with what that run did
+fn print_last_success() -> Result<()> {
+    let env = crate::internal::environment::get();
+    let state_dir = env.owl_dir()?.join(crate::internal::constants::STATE_DIR);
+    let last = crate::core::last_success::load(&state_dir, env.hostname()?)?;
+    let age = crate::core::last_success::describe(last.as_ref(), crate::internal::time::now_secs());
+    outln!("[{}]", color::blue("apply"));
+    match &last {
+        Some(last) => outln!(
+            "  {} last successful apply: {} {}",
+            color::green("➔"),
+            age,
+            color::dim(&format!(
+                "(owl {}, {} installed, {} removed, {} dotfile(s))",
+                last.version, last.installed, last.removed, last.dotfiles
+            ))
+        ),
+        None => outln!("  {} last successful apply: {}", color::dim("➔"), age),
+    }
+    Ok(())
+}
+
+/// Show when this host last applied successfully, then pending AUR updates
+/// and out-of-date flags for declared foreign packages
+///
+/// Uses the AUR RPC interface and its cache, never paru; `refresh` ignores the cache TTL.
+pub fn run(refresh: bool) -> Result<()> {
+    let config = crate::core::config::Config::load_all_relevant_config_files()?;
+    print_last_success()?;
+    outln!("[{}]", color::blue("aur"));
+    if !config.aur_rpc()? {
+        outln!(
+            "  {} {}",
+            color::dim("➔"),
+            color::dim("AUR checks are off; enable them with @option aur_rpc=true")
+        );
+        return Ok(());
+    }
+
+    let foreign = crate::core::pm::manager().list_foreign_versions()?;
+    let mut declared: Vec<String> = config
+        .packages
+        .keys()
+        .filter(|name| foreign.contains_key(*name))
+        .cloned()
+        .collect();
+    declared.sort();
+
+    let mut cache = AurCache::load().unwrap_or_default();
+    let lookup = aur_rpc::lookup(
+        &declared,
+        &Curl,
+        &mut cache,
+        crate::internal::time::now_secs(),
+        aur_rpc::DEFAULT_TTL_SECS,
+        refresh,
+    );
+    match &lookup.error {
+        Some(error) => outln!(
+            "  {} {}; using data from {} ago",
+            color::yellow("warning:"),
+            error,
+            crate::internal::time::format_age(lookup.age as i64)
+        ),
+        None => cache.save()?,
+    }
+
+    let updates = aur_rpc::pending_updates(&foreign, &lookup.packages);
+    for update in &updates {
+        outln!(
+            "  {} {} -> {}",
+            color::yellow(&update.name),
+            color::dim(&update.installed),
+            color::green(&update.available)
+        );
+    }
+    for info in lookup.packages.values() {
+        if let Some(flagged) = info.out_of_date {
+            outln!(
+                "  {} {} flagged out of date since {}",
+                color::red("!"),
+                info.name,
+                crate::internal::time::format_date(flagged)
+            );
+        }
+    }
+    let missing: Vec<&str> = declared
+        .iter()
+        .filter(|name| !lookup.packages.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    if lookup.error.is_none() && !missing.is_empty() {
+        outln!(
+            "  {} not in the AUR: {}",
+            color::dim("➔"),
+            missing.join(", ")
+        );
+    }
+    outln!(
+        "  {} {} of {} declared foreign package(s) can be updated",
+        color::green("➔"),
+        updates.len(),
+        declared.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+
+/// Run the tree command to show how config files and groups connect
+pub fn run(dot: bool) -> Result<()> {
+    let env = crate::internal::environment::get();
+    let owl_root = env.owl_dir()?;
+    let roots = crate::core::config::tree::build_config_tree(&owl_root, env.hostname()?)?;
+
+    if dot {
+        out!("{}", crate::core::config::tree::render_dot(&roots));
+    } else if roots.is_empty() {
+        outln!(
+            "  {} No config files found in {}",
+            crate::internal::color::blue("info:"),
+            owl_root.display()
+        );
+    } else {
+        out!("{}", crate::core::config::tree::render_text(&roots));
+    }
+    Ok(())
+}
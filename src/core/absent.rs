@@ -0,0 +1,418 @@
+//! Paths owl keeps absent (`:absent` and `@absent`)
+//!
+//! Old config files can break newer versions of a tool: fish's universal
+//! variables in `~/.config/fish/fishd.*`, a forgotten `~/.xinitrc`. Each
+//! declared path is removed when present, after the dotfile mappings are
+//! synced. A pattern may have one `*` in its file name, never in a directory,
+//! so a typo cannot reach further than one directory's entries.
+//!
+//! Paths go through the same home and staging checks as mapping destinations,
+//! and home, the owl root, dotfile destinations and system directories are
+//! never removed. Removal stashes the path in the dotfile backup store, so a
+//! failed run restores it, and `deployed.json` notes the backup set it went
+//! into.
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::core::backup::BackupStore;
+use crate::core::dotfiles::{DotfileMapping, DotfileRoots, RollbackJournal, normalize};
+use crate::core::events::{EventSink, OwlEvent};
+use crate::core::state::DeployedDotfiles;
+use crate::internal::failpoint::Failpoints;
+
+/// System directories that are never removed themselves
+const SYSTEM_DIRS: &[&str] = &[
+    "/", "/bin", "/boot", "/dev", "/etc", "/home", "/lib", "/lib64", "/mnt", "/opt", "/proc",
+    "/root", "/run", "/sbin", "/srv", "/sys", "/tmp", "/usr", "/var",
+];
+
+/// Trees whose contents belong to the system and its packages
+const SYSTEM_TREES: &[&str] = &["/boot", "/dev", "/proc", "/run", "/sys", "/usr"];
+
+/// What a declared path needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbsentStatus {
+    AlreadyAbsent,
+    WouldRemove,
+    Removed,
+    /// Something matched but may not be removed; nothing was
+    Refused,
+}
+
+/// One `:absent` pattern and what was found for it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AbsentEntry {
+    /// As written in the config
+    pub pattern: String,
+    pub status: AbsentStatus,
+    /// Present paths matching the pattern
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Check an `:absent`/`@absent` path as written in the config
+pub fn parse_pattern(raw: &str) -> Result<String> {
+    let pattern = raw.trim();
+    if !pattern.starts_with("~/") && !pattern.starts_with('/') {
+        return Err(anyhow!("absent path '{}' must start with ~/ or /", pattern));
+    }
+    // Both prefixes contain a `/`
+    let (dir, name) = pattern.rsplit_once('/').unwrap_or(("", pattern));
+    if dir.contains('*') {
+        return Err(anyhow!(
+            "absent path '{}' may only have * in its file name",
+            pattern
+        ));
+    }
+    if name.is_empty() || name == "*" || name == "." || name == ".." {
+        return Err(anyhow!("absent path '{}' needs a file name", pattern));
+    }
+    if name.matches('*').count() > 1 {
+        return Err(anyhow!("absent path '{}' may have only one *", pattern));
+    }
+    Ok(pattern.to_string())
+}
+
+/// What each of `roots.absent` needs, in order
+///
+/// `mappings` are this run's dotfile mappings; their destinations are never
+/// removed.
+pub fn analyze(roots: &DotfileRoots, mappings: &[DotfileMapping]) -> Vec<AbsentEntry> {
+    let mapped: Vec<PathBuf> = mappings
+        .iter()
+        .map(|m| normalize(&roots.destination(m)))
+        .collect();
+    roots
+        .absent
+        .iter()
+        .map(|pattern| analyze_pattern(roots, &mapped, pattern))
+        .collect()
+}
+
+fn analyze_pattern(roots: &DotfileRoots, mapped: &[PathBuf], pattern: &str) -> AbsentEntry {
+    let entry = |status, paths, reason| AbsentEntry {
+        pattern: pattern.to_string(),
+        status,
+        paths,
+        reason,
+    };
+    if let Some(reason) = roots.destination_escape(pattern) {
+        return entry(AbsentStatus::Refused, Vec::new(), Some(reason));
+    }
+    let matches = present_matches(&normalize(&roots.destination_path(pattern)));
+    if matches.is_empty() {
+        return entry(AbsentStatus::AlreadyAbsent, Vec::new(), None);
+    }
+    let refusal = matches
+        .iter()
+        .find_map(|path| protection_reason(roots, mapped, path));
+    let paths = matches
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    match refusal {
+        Some(reason) => entry(AbsentStatus::Refused, paths, Some(reason)),
+        None => entry(AbsentStatus::WouldRemove, paths, None),
+    }
+}
+
+/// Existing paths matching `path`, whose file name may hold one `*`
+fn present_matches(path: &Path) -> Vec<PathBuf> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.contains('*') {
+        return match fs::symlink_metadata(path) {
+            Ok(_) => vec![path.to_path_buf()],
+            Err(_) => Vec::new(),
+        };
+    }
+    let Some(dir) = path.parent() else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut matches: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| {
+            crate::internal::util::glob_match(&name, &entry.file_name().to_string_lossy())
+        })
+        .map(|entry| entry.path())
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Why `path` must not be removed, if it must not
+fn protection_reason(roots: &DotfileRoots, mapped: &[PathBuf], path: &Path) -> Option<String> {
+    let home = normalize(Path::new(&roots.home));
+    let owl_dir = normalize(&roots.owl_dir);
+    let shown = path.display();
+    if home.starts_with(path) {
+        return Some(format!("{} is the home directory or holds it", shown));
+    }
+    if path.starts_with(&owl_dir) || owl_dir.starts_with(path) {
+        return Some(format!("{} holds owl's own files", shown));
+    }
+    if mapped
+        .iter()
+        .any(|dst| dst.starts_with(path) || path.starts_with(dst))
+    {
+        return Some(format!("{} is written by a dotfile mapping", shown));
+    }
+    if SYSTEM_DIRS.iter().any(|dir| path == Path::new(dir))
+        || SYSTEM_TREES.iter().any(|tree| path.starts_with(tree))
+    {
+        return Some(format!("{} belongs to the system", shown));
+    }
+    None
+}
+
+/// Remove the present `roots.absent` paths, reporting each pattern through `sink`
+pub fn enforce(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    dry_run: bool,
+    keep_backups: usize,
+    sink: &mut dyn EventSink,
+) -> Result<()> {
+    if roots.absent.is_empty() {
+        return Ok(());
+    }
+    let mut entries = analyze(roots, mappings);
+    let result = if dry_run {
+        Ok(())
+    } else {
+        Failpoints::from_env()
+            .and_then(|failpoints| remove(roots, &mut entries, &failpoints, keep_backups))
+    };
+    for entry in entries {
+        sink.emit(OwlEvent::AbsentChecked { entry });
+    }
+    result
+}
+
+/// Remove every path of the `WouldRemove` entries into one backup set
+///
+/// A failure restores the paths removed before it.
+fn remove(
+    roots: &DotfileRoots,
+    entries: &mut [AbsentEntry],
+    failpoints: &Failpoints,
+    keep_backups: usize,
+) -> Result<()> {
+    let pending = || {
+        entries
+            .iter()
+            .filter(|e| e.status == AbsentStatus::WouldRemove)
+    };
+    if pending().next().is_none() {
+        return Ok(());
+    }
+    let mut journal =
+        RollbackJournal::new(BackupStore::new(roots.backup_dir.clone()), keep_backups);
+    let paths: Vec<String> = pending().flat_map(|e| e.paths.clone()).collect();
+    for (index, path) in paths.iter().enumerate() {
+        let removed = failpoints
+            .check("absent", index)
+            .and_then(|_| journal.stash(Path::new(path), true));
+        if let Err(e) = removed {
+            return Err(match journal.rollback() {
+                Ok(0) => e,
+                Ok(restored) => anyhow!("{} (restored {} absent path(s))", e, restored),
+                Err(rollback_err) => anyhow!("{}; restoring failed: {}", e, rollback_err),
+            });
+        }
+    }
+    let backup = journal.id().to_string();
+    journal.commit();
+    for entry in entries
+        .iter_mut()
+        .filter(|e| e.status == AbsentStatus::WouldRemove)
+    {
+        entry.status = AbsentStatus::Removed;
+    }
+    // Staged runs leave the real home's record alone
+    if roots.dest_prefix.is_none() {
+        let state_dir = roots.owl_dir.join(crate::internal::constants::STATE_DIR);
+        DeployedDotfiles::load_from(&state_dir).and_then(|mut deployed| {
+            deployed.note_absent(&paths, &backup);
+            deployed.save_to(&state_dir)
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::state::DeployedDotfiles;
+
+    fn roots(dir: &Path, absent: &[&str]) -> DotfileRoots {
+        let home = dir.join("home");
+        fs::create_dir_all(&home).unwrap();
+        DotfileRoots::at(&home.join(".owl"), &home.to_string_lossy())
+            .with_absent(absent.iter().map(|s| s.to_string()).collect())
+    }
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn statuses(entries: &[AbsentEntry]) -> Vec<AbsentStatus> {
+        entries.iter().map(|e| e.status).collect()
+    }
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!(parse_pattern(" ~/.xinitrc ").unwrap(), "~/.xinitrc");
+        parse_pattern("~/.config/fish/fishd.*").unwrap();
+        parse_pattern("/etc/profile.d/old.sh").unwrap();
+        for (bad, expected) in [
+            (".xinitrc", "must start with"),
+            ("~", "must start with"),
+            ("~/", "needs a file name"),
+            ("~/*", "needs a file name"),
+            ("~/.config/*/fishd", "only have * in its file name"),
+            ("~/.config/fish/*d.*", "only one *"),
+            ("~/.config/..", "needs a file name"),
+        ] {
+            let err = parse_pattern(bad).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", bad, err);
+        }
+    }
+
+    #[test]
+    fn test_present_and_absent_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = roots(
+            dir.path(),
+            &["~/.xinitrc", "~/.config/fish/fishd.*", "~/.gone"],
+        );
+        let home = Path::new(&roots.home);
+        write(&home.join(".xinitrc"), "exec i3\n");
+        write(&home.join(".config/fish/fishd.a1"), "x");
+        write(&home.join(".config/fish/fishd.b2"), "y");
+        write(&home.join(".config/fish/config.fish"), "keep");
+
+        let entries = analyze(&roots, &[]);
+        assert_eq!(
+            statuses(&entries),
+            [
+                AbsentStatus::WouldRemove,
+                AbsentStatus::WouldRemove,
+                AbsentStatus::AlreadyAbsent
+            ]
+        );
+        assert_eq!(entries[1].paths.len(), 2);
+
+        let mut events = Vec::new();
+        enforce(&roots, &[], false, 5, &mut |e| events.push(e)).unwrap();
+        assert!(!home.join(".xinitrc").exists());
+        assert!(!home.join(".config/fish/fishd.a1").exists());
+        assert!(home.join(".config/fish/config.fish").exists());
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            &events[0],
+            OwlEvent::AbsentChecked { entry } if entry.status == AbsentStatus::Removed
+        ));
+
+        // The removal is noted with the backup set that holds it
+        let deployed =
+            DeployedDotfiles::load_from(&roots.owl_dir.join(crate::internal::constants::STATE_DIR))
+                .unwrap();
+        let xinitrc = home.join(".xinitrc").to_string_lossy().into_owned();
+        let backup = &deployed.absent[&xinitrc];
+        let store = BackupStore::new(roots.backup_dir.clone());
+        let set = store
+            .manifests()
+            .unwrap()
+            .into_iter()
+            .find(|m| &m.id == backup)
+            .unwrap();
+        store.restore(&set.targets[0]).unwrap();
+        assert_eq!(
+            fs::read_to_string(home.join(".xinitrc")).unwrap(),
+            "exec i3\n"
+        );
+
+        // Nothing left to do on the next run, and dry runs change nothing
+        let entries = analyze(&roots, &[]);
+        assert_eq!(entries[1].status, AbsentStatus::AlreadyAbsent);
+        enforce(&roots, &[], true, 5, &mut |_| {}).unwrap();
+        assert!(home.join(".xinitrc").exists());
+    }
+
+    #[test]
+    fn test_protected_paths_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = roots(
+            dir.path(),
+            &["~/.owl", "~/../x", "/usr", "~/.bashrc", "/etc/hostname"],
+        );
+        let home = Path::new(&roots.home);
+        fs::create_dir_all(&roots.owl_dir).unwrap();
+        write(&home.join(".bashrc"), "mapped");
+        let mappings = [DotfileMapping {
+            source: "bashrc".to_string(),
+            destination: "~/.bashrc".to_string(),
+            root: None,
+            from: None,
+            hardlink: false,
+            force_owned: false,
+            modes: Default::default(),
+        }];
+
+        let entries = analyze(&roots, &mappings);
+        assert!(
+            entries.iter().all(|e| e.status == AbsentStatus::Refused),
+            "{:?}",
+            entries
+        );
+        let reasons: Vec<&str> = entries
+            .iter()
+            .map(|e| e.reason.as_deref().unwrap())
+            .collect();
+        assert!(reasons[0].contains("owl's own files"), "{}", reasons[0]);
+        assert!(reasons[1].contains("escapes"), "{}", reasons[1]);
+        assert!(reasons[2].contains("outside"), "{}", reasons[2]);
+        assert!(reasons[3].contains("dotfile mapping"), "{}", reasons[3]);
+
+        enforce(&roots, &mappings, false, 5, &mut |_| {}).unwrap();
+        assert!(home.join(".bashrc").exists());
+        assert!(roots.owl_dir.exists());
+
+        // Outside home is allowed with the flag, system directories never
+        let roots = roots.with_allow_outside_home(true);
+        let entries = analyze(&roots, &[]);
+        assert!(entries[2].reason.as_deref().unwrap().contains("system"));
+    }
+
+    #[test]
+    fn test_failed_removal_restores_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = roots(dir.path(), &["~/.a", "~/.b"]);
+        let home = Path::new(&roots.home);
+        write(&home.join(".a"), "a");
+        write(&home.join(".b"), "b");
+
+        let mut entries = analyze(&roots, &[]);
+        let failpoints = Failpoints::parse("absent:1").unwrap();
+        let err = remove(&roots, &mut entries, &failpoints, 5)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("restored 1 absent path(s)"), "{}", err);
+        assert_eq!(fs::read_to_string(home.join(".a")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(home.join(".b")).unwrap(), "b");
+        assert_eq!(entries[0].status, AbsentStatus::WouldRemove);
+    }
+}
@@ -0,0 +1,484 @@
+//! Per-package report of an AUR install or update session
+//!
+//! paru streams makepkg's output for every package it builds. The lines that
+//! open and close a build (`==> Making package:` / `==> Finished making:`), the
+//! "found cached package" notice and build errors delimit the packages, so one
+//! timestamped transcript of the session is enough to tell where the time went.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// How one package of the session ended
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildStatus {
+    Built,
+    /// Installed from a package paru had already built
+    Cached,
+    Failed,
+    /// Never built: an earlier failure stopped paru, or there was nothing to build
+    Skipped,
+}
+
+impl BuildStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildStatus::Built => "built",
+            BuildStatus::Cached => "cached",
+            BuildStatus::Failed => "failed",
+            BuildStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// One package of an AUR session, in the order paru got to it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AurBuild {
+    pub package: String,
+    /// 1-based position in the session's build order
+    pub position: usize,
+    /// Milliseconds since the Unix epoch; `None` for skipped packages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_ms: Option<u64>,
+    pub duration_ms: u64,
+    pub status: BuildStatus,
+}
+
+/// Split a session transcript into per-package builds
+///
+/// `lines` are stdout and stderr lines with the time they were read, in
+/// milliseconds since the Unix epoch. `queue` holds the packages owl asked for:
+/// those that never show up are reported as skipped, after everything paru did
+/// get to. Dependencies paru built on its own are reported too.
+pub fn parse_transcript(
+    queue: &[String],
+    lines: &[(u64, String)],
+    succeeded: bool,
+) -> Vec<AurBuild> {
+    let mut builds: Vec<AurBuild> = Vec::new();
+    // Index into `builds` of the package being built
+    let mut current: Option<usize> = None;
+    let last_ms = lines.last().map_or(0, |(at, _)| *at);
+
+    for (at, raw) in lines {
+        let line = strip_ansi(raw);
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("==> Making package:") {
+            if let Some(index) = current.take() {
+                finish(&mut builds[index], *at, BuildStatus::Failed);
+            }
+            let Some(name) = rest.split_whitespace().next() else {
+                continue;
+            };
+            builds.push(AurBuild {
+                package: name.to_string(),
+                position: builds.len() + 1,
+                started_ms: Some(*at),
+                finished_ms: None,
+                duration_ms: 0,
+                status: BuildStatus::Built,
+            });
+            current = Some(builds.len() - 1);
+        } else if line.starts_with("==> Finished making:") {
+            if let Some(index) = current.take() {
+                finish(&mut builds[index], *at, BuildStatus::Built);
+            }
+        } else if line.starts_with("==> ERROR:") {
+            if let Some(index) = current.take() {
+                finish(&mut builds[index], *at, BuildStatus::Failed);
+            }
+        } else if line.contains("found cached package") {
+            let Some(name) = line
+                .trim_start_matches(':')
+                .split_whitespace()
+                .next()
+                .map(|token| package_name(token.trim_end_matches(':'), queue))
+            else {
+                continue;
+            };
+            if !builds.iter().any(|b| b.package == name) {
+                builds.push(AurBuild {
+                    package: name,
+                    position: builds.len() + 1,
+                    started_ms: Some(*at),
+                    finished_ms: Some(*at),
+                    duration_ms: 0,
+                    status: BuildStatus::Cached,
+                });
+            }
+        } else if let Some(rest) = line.strip_prefix("error: packages failed to build:") {
+            // paru's closing summary; builds it names may have failed before makepkg ran
+            for token in rest.split_whitespace() {
+                let name = package_name(token, queue);
+                match builds.iter_mut().find(|b| b.package == name) {
+                    Some(build) => build.status = BuildStatus::Failed,
+                    None => builds.push(AurBuild {
+                        package: name,
+                        position: builds.len() + 1,
+                        started_ms: Some(*at),
+                        finished_ms: Some(*at),
+                        duration_ms: 0,
+                        status: BuildStatus::Failed,
+                    }),
+                }
+            }
+        }
+    }
+
+    if let Some(index) = current {
+        let status = if succeeded {
+            BuildStatus::Built
+        } else {
+            BuildStatus::Failed
+        };
+        finish(&mut builds[index], last_ms, status);
+    }
+    for name in queue {
+        if !builds.iter().any(|b| &b.package == name) {
+            builds.push(AurBuild {
+                package: name.clone(),
+                position: builds.len() + 1,
+                started_ms: None,
+                finished_ms: None,
+                duration_ms: 0,
+                status: BuildStatus::Skipped,
+            });
+        }
+    }
+    builds
+}
+
+fn finish(build: &mut AurBuild, at: u64, status: BuildStatus) {
+    build.finished_ms = Some(at);
+    build.duration_ms = at.saturating_sub(build.started_ms.unwrap_or(at));
+    build.status = status;
+}
+
+/// Package name in a `name-pkgver-pkgrel` token, preferring a queued name
+fn package_name(token: &str, queue: &[String]) -> String {
+    let token = token.trim_matches(|c| c == '\'' || c == '"' || c == ',');
+    if let Some(name) = queue
+        .iter()
+        .filter(|name| {
+            token == name.as_str()
+                || token
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.starts_with('-'))
+        })
+        .max_by_key(|name| name.len())
+    {
+        return name.clone();
+    }
+    let mut parts = token.rsplitn(3, '-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(_), Some(name)) => name.to_string(),
+        _ => token.to_string(),
+    }
+}
+
+/// Drop terminal color sequences; makepkg colors its `==>` lines
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Report lines, slowest first
+pub fn table(builds: &[AurBuild]) -> Vec<String> {
+    let mut sorted: Vec<&AurBuild> = builds.iter().collect();
+    sorted.sort_by(|a, b| {
+        b.duration_ms
+            .cmp(&a.duration_ms)
+            .then_with(|| a.position.cmp(&b.position))
+    });
+    let width = builds.iter().map(|b| b.package.len()).max().unwrap_or(0);
+    sorted
+        .iter()
+        .map(|b| {
+            format!(
+                "{:>3}. {:<width$}  {:>8}  {}",
+                b.position,
+                b.package,
+                crate::internal::time::format_duration_ms(b.duration_ms),
+                b.status.as_str(),
+                width = width
+            )
+        })
+        .collect()
+}
+
+/// paru and makepkg settings that bear on build order, read and never written
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildSettings {
+    /// `-jN` from `MAKEFLAGS`
+    pub jobs: Option<usize>,
+    /// `BuildDir` from paru.conf
+    pub build_dir: Option<String>,
+}
+
+impl BuildSettings {
+    /// `MAKEFLAGS` from the environment or makepkg.conf, `BuildDir` from paru.conf
+    pub fn read(home: &Path) -> Self {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|| home.join(".config"));
+        let makeflags = std::env::var("MAKEFLAGS").ok().or_else(|| {
+            [
+                config_home.join("pacman/makepkg.conf"),
+                home.join(".makepkg.conf"),
+                Path::new("/etc/makepkg.conf").to_path_buf(),
+            ]
+            .iter()
+            .find_map(|path| shell_assignment(&std::fs::read_to_string(path).ok()?, "MAKEFLAGS"))
+        });
+        let build_dir = [config_home.join("paru/paru.conf"), "/etc/paru.conf".into()]
+            .iter()
+            .find_map(|path| paru_option(&std::fs::read_to_string(path).ok()?, "BuildDir"));
+        Self {
+            jobs: makeflags.as_deref().and_then(parse_jobs),
+            build_dir,
+        }
+    }
+}
+
+/// Last `KEY=value` or `KEY="value"` assignment in a shell-style config
+fn shell_assignment(content: &str, key: &str) -> Option<String> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(key)?.strip_prefix('='))
+        .map(|value| {
+            value
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_string()
+        })
+        .next_back()
+}
+
+/// `Key = value` from paru.conf
+fn paru_option(content: &str, key: &str) -> Option<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            (name.trim() == key).then(|| value.trim().to_string())
+        })
+        .next_back()
+}
+
+/// Job count from `-j8`, `-j 8` or `--jobs=8`
+fn parse_jobs(makeflags: &str) -> Option<usize> {
+    let mut words = makeflags.split_whitespace();
+    while let Some(word) = words.next() {
+        let value = if let Some(n) = word.strip_prefix("--jobs=") {
+            n
+        } else if let Some(n) = word.strip_prefix("-j") {
+            if n.is_empty() { words.next()? } else { n }
+        } else {
+            continue;
+        };
+        return value.parse().ok();
+    }
+    None
+}
+
+/// Time to run `durations` in order on `slots` parallel builders
+fn makespan(durations: &[u64], slots: usize) -> u64 {
+    let mut free_at = vec![0u64; slots.max(1)];
+    for duration in durations {
+        let slot = free_at.iter_mut().min().expect("at least one build slot");
+        *slot += duration;
+    }
+    free_at.into_iter().max().unwrap_or(0)
+}
+
+/// Milliseconds saved by building the longest packages first on `slots` builders
+pub fn reorder_saving(builds: &[AurBuild], slots: usize) -> u64 {
+    let observed: Vec<u64> = builds.iter().map(|b| b.duration_ms).collect();
+    let mut longest_first = observed.clone();
+    longest_first.sort_unstable_by(|a, b| b.cmp(a));
+    makespan(&observed, slots).saturating_sub(makespan(&longest_first, slots))
+}
+
+/// Hint about reordering, when parallel builds are enabled and it would help
+pub fn reorder_hint(builds: &[AurBuild], settings: &BuildSettings) -> Option<String> {
+    let jobs = settings.jobs.filter(|&jobs| jobs > 1)?;
+    let saving = reorder_saving(builds, jobs);
+    if saving < 1_000 {
+        return None;
+    }
+    let build_dir = settings
+        .build_dir
+        .as_ref()
+        .map(|dir| format!(", BuildDir {}", dir))
+        .unwrap_or_default();
+    Some(format!(
+        "Building the longest packages first could save about {} with {} parallel jobs (MAKEFLAGS{})",
+        crate::internal::time::format_duration_ms(saving),
+        jobs,
+        build_dir
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A captured transcript, one line per second
+    fn timed(transcript: &str) -> Vec<(u64, String)> {
+        transcript
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (1_000_000 + i as u64 * 1_000, line.to_string()))
+            .collect()
+    }
+
+    fn queue(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn summary(builds: &[AurBuild]) -> Vec<(usize, &str, u64, BuildStatus)> {
+        builds
+            .iter()
+            .map(|b| (b.position, b.package.as_str(), b.duration_ms, b.status))
+            .collect()
+    }
+
+    #[test]
+    fn test_session_with_cached_package() {
+        let lines = timed(include_str!("../../tests/transcripts/paru-cached.txt"));
+        let builds = parse_transcript(&queue(&["paru-bin", "spotify", "yay"]), &lines, true);
+        assert_eq!(
+            summary(&builds),
+            vec![
+                (1, "paru-bin", 0, BuildStatus::Cached),
+                (2, "spotify", 11_000, BuildStatus::Built),
+                (3, "yay", 7_000, BuildStatus::Built),
+            ]
+        );
+        assert_eq!(builds[1].started_ms, Some(1_009_000));
+        assert_eq!(builds[1].finished_ms, Some(1_020_000));
+    }
+
+    #[test]
+    fn test_session_failing_mid_list() {
+        let lines = timed(include_str!("../../tests/transcripts/paru-failure.txt"));
+        let builds = parse_transcript(
+            &queue(&["neovim-git", "bar-git", "zellij-bin"]),
+            &lines,
+            false,
+        );
+        assert_eq!(
+            summary(&builds),
+            vec![
+                (1, "tree-sitter-git", 4_000, BuildStatus::Built),
+                (2, "neovim-git", 6_000, BuildStatus::Built),
+                (3, "bar-git", 5_000, BuildStatus::Failed),
+                (4, "zellij-bin", 0, BuildStatus::Skipped),
+            ]
+        );
+        assert_eq!(builds[3].started_ms, None);
+    }
+
+    #[test]
+    fn test_unfinished_build_at_exit() {
+        let lines = timed("==> Making package: yay 12.4.2-1\n==> Starting build()...\n");
+        let failed = parse_transcript(&queue(&["yay"]), &lines, false);
+        assert_eq!(failed[0].status, BuildStatus::Failed);
+        assert_eq!(failed[0].duration_ms, 1_000);
+        let done = parse_transcript(&queue(&["yay"]), &lines, true);
+        assert_eq!(done[0].status, BuildStatus::Built);
+    }
+
+    #[test]
+    fn test_colored_lines() {
+        let lines = timed(
+            "\x1b[1m\x1b[32m==> \x1b[0m\x1b[1mMaking package: yay 12.4.2-1\x1b[0m\n\
+             \x1b[1m\x1b[32m==> \x1b[0m\x1b[1mFinished making: yay 12.4.2-1\x1b[0m\n",
+        );
+        let builds = parse_transcript(&[], &lines, true);
+        assert_eq!(
+            summary(&builds),
+            vec![(1, "yay", 1_000, BuildStatus::Built)]
+        );
+    }
+
+    #[test]
+    fn test_package_name_from_versioned_token() {
+        let q = queue(&["paru", "paru-bin"]);
+        assert_eq!(package_name("paru-bin-2.0.4-1", &q), "paru-bin");
+        assert_eq!(package_name("paru-2.0.4-1", &q), "paru");
+        assert_eq!(package_name("'spotify-1:1.2.45.454-1'", &q), "spotify");
+        assert_eq!(package_name("yay", &q), "yay");
+    }
+
+    #[test]
+    fn test_table_sorted_by_duration() {
+        let lines = timed(include_str!("../../tests/transcripts/paru-cached.txt"));
+        let builds = parse_transcript(&queue(&["paru-bin", "spotify", "yay"]), &lines, true);
+        let table = table(&builds);
+        assert!(table[0].contains("spotify") && table[0].ends_with("built"));
+        assert!(table[2].contains("paru-bin") && table[2].ends_with("cached"));
+    }
+
+    #[test]
+    fn test_build_settings_parsing() {
+        assert_eq!(parse_jobs("-j8"), Some(8));
+        assert_eq!(parse_jobs("-s -j 4"), Some(4));
+        assert_eq!(parse_jobs("--jobs=12"), Some(12));
+        assert_eq!(parse_jobs("-s"), None);
+        let makepkg = "#MAKEFLAGS=\"-j2\"\nMAKEFLAGS=\"-j$(nproc)\"\nMAKEFLAGS=\"-j16\"\n";
+        assert_eq!(
+            shell_assignment(makepkg, "MAKEFLAGS").as_deref(),
+            Some("-j16")
+        );
+        let paru = "[options]\nBottomUp\nBuildDir = /tmp/paru\n";
+        assert_eq!(paru_option(paru, "BuildDir").as_deref(), Some("/tmp/paru"));
+        assert_eq!(paru_option(paru, "CloneDir"), None);
+    }
+
+    fn build(package: &str, duration_ms: u64) -> AurBuild {
+        AurBuild {
+            package: package.to_string(),
+            position: 0,
+            started_ms: None,
+            finished_ms: None,
+            duration_ms,
+            status: BuildStatus::Built,
+        }
+    }
+
+    #[test]
+    fn test_reorder_saving() {
+        let builds = vec![build("a", 10_000), build("b", 10_000), build("c", 60_000)];
+        // In order on two slots: a+c on one, b alone, done at 70s; longest first: 60s
+        assert_eq!(reorder_saving(&builds, 2), 10_000);
+        assert_eq!(reorder_saving(&builds, 1), 0);
+
+        let settings = BuildSettings {
+            jobs: Some(2),
+            build_dir: Some("/tmp/paru".to_string()),
+        };
+        let hint = reorder_hint(&builds, &settings).unwrap();
+        assert!(
+            hint.contains("10s") && hint.contains("BuildDir /tmp/paru"),
+            "{}",
+            hint
+        );
+        assert_eq!(reorder_hint(&builds, &BuildSettings::default()), None);
+    }
+}
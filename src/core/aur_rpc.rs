@@ -0,0 +1,436 @@
+//! AUR metadata from the RPC interface, without paru
+//!
+//! Declared foreign packages are looked up with the RPC v5 `info` endpoint in
+//! as few requests as the URL length allows. Responses are cached in
+//! `~/.owl/.state/aur-rpc.json`; when the network fails the cached data is
+//! used and its age reported.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::internal::constants;
+
+pub const RPC_INFO_URL: &str = "https://aur.archlinux.org/rpc/?v=5&type=info";
+
+/// Longest request URL sent; the AUR rejects URIs much over 4 KiB
+pub const MAX_URL_LEN: usize = 4000;
+
+/// How long cached metadata is used without asking the AUR again
+pub const DEFAULT_TTL_SECS: u64 = 6 * 60 * 60;
+
+const CACHE_FILE: &str = "aur-rpc.json";
+
+/// Fetches a URL's body
+pub trait HttpGet {
+    fn get(&self, url: &str) -> Result<String>;
+}
+
+/// `curl`, which every Arch system has as a pacman dependency
+pub struct Curl;
+
+impl HttpGet for Curl {
+    fn get(&self, url: &str) -> Result<String> {
+        let output = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--max-time", "15"])
+            .arg(url)
+            .output()
+            .map_err(|e| anyhow!("Failed to run curl: {}", e))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "AUR request failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// What the AUR knows about one package
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AurInfo {
+    pub name: String,
+    pub version: String,
+    /// When the package was flagged out of date (seconds since the Unix epoch)
+    pub out_of_date: Option<u64>,
+    pub last_modified: u64,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    results: Vec<RpcPackage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RpcPackage {
+    name: String,
+    version: String,
+    out_of_date: Option<u64>,
+    last_modified: u64,
+}
+
+/// Parse an `info` response; names the AUR does not know are simply absent
+pub fn parse_response(body: &str) -> Result<Vec<AurInfo>> {
+    let response: RpcResponse =
+        serde_json::from_str(body).map_err(|e| anyhow!("Invalid AUR RPC response: {}", e))?;
+    if response.kind == "error" {
+        return Err(anyhow!(
+            "AUR RPC error: {}",
+            response.error.as_deref().unwrap_or("unknown")
+        ));
+    }
+    Ok(response
+        .results
+        .into_iter()
+        .map(|p| AurInfo {
+            name: p.name,
+            version: p.version,
+            out_of_date: p.out_of_date,
+            last_modified: p.last_modified,
+        })
+        .collect())
+}
+
+/// Request URLs covering `names`, each at most `max_len` bytes
+///
+/// A name too long for any URL still gets a request of its own.
+pub fn batch_urls(names: &[String], max_len: usize) -> Vec<String> {
+    let mut urls = Vec::new();
+    let mut current = RPC_INFO_URL.to_string();
+    for name in names {
+        let arg = format!("&arg[]={}", encode(name));
+        if current.len() > RPC_INFO_URL.len() && current.len() + arg.len() > max_len {
+            urls.push(std::mem::replace(&mut current, RPC_INFO_URL.to_string()));
+        }
+        current.push_str(&arg);
+    }
+    if current.len() > RPC_INFO_URL.len() {
+        urls.push(current);
+    }
+    urls
+}
+
+/// Percent-encode everything but unreserved characters (`+` and `@` appear in package names)
+fn encode(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Cached RPC results
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AurCache {
+    /// When the cached names were last fetched (seconds since the Unix epoch)
+    pub fetched_at: u64,
+    /// Names that were asked for, including ones the AUR does not have
+    pub queried: BTreeSet<String>,
+    pub packages: BTreeMap<String, AurInfo>,
+}
+
+impl AurCache {
+    /// Load the cache for the current user (empty if nothing was fetched yet)
+    pub fn load() -> Result<Self> {
+        Self::load_from(&cache_dir()?)
+    }
+
+    pub fn load_from(dir: &Path) -> Result<Self> {
+        let path = dir.join(CACHE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", CACHE_FILE, e))?;
+        serde_json::from_str(&content).map_err(|e| anyhow!("Failed to parse {}: {}", CACHE_FILE, e))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&cache_dir()?)
+    }
+
+    pub fn save_to(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", dir.display(), e))?;
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize {}: {}", CACHE_FILE, e))?;
+        fs::write(dir.join(CACHE_FILE), content)
+            .map_err(|e| anyhow!("Failed to write {}: {}", CACHE_FILE, e))
+    }
+
+    fn covers(&self, names: &[String]) -> bool {
+        names.iter().all(|name| self.queried.contains(name))
+    }
+}
+
+fn cache_dir() -> Result<PathBuf> {
+    Ok(crate::internal::environment::get()
+        .owl_dir()?
+        .join(constants::STATE_DIR))
+}
+
+/// AUR metadata for a set of names and how current it is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lookup {
+    pub packages: BTreeMap<String, AurInfo>,
+    /// Seconds since the data was fetched
+    pub age: u64,
+    /// Why cached data was used in place of a failed fetch
+    pub error: Option<String>,
+}
+
+/// Look up `names`, from the cache while it is fresh and covers them all
+///
+/// `cache` is updated after a successful fetch. A failed fetch falls back to
+/// whatever the cache has, however old.
+pub fn lookup(
+    names: &[String],
+    http: &dyn HttpGet,
+    cache: &mut AurCache,
+    now: u64,
+    ttl: u64,
+    refresh: bool,
+) -> Lookup {
+    let cached = |cache: &AurCache, error: Option<String>| Lookup {
+        packages: names
+            .iter()
+            .filter_map(|name| {
+                cache
+                    .packages
+                    .get(name)
+                    .map(|info| (name.clone(), info.clone()))
+            })
+            .collect(),
+        age: now.saturating_sub(cache.fetched_at),
+        error,
+    };
+    if names.is_empty()
+        || (!refresh
+            && cache.covers(names)
+            && crate::internal::time::is_fresh(cache.fetched_at, now, ttl))
+    {
+        return cached(cache, None);
+    }
+
+    let mut fetched = Vec::new();
+    for url in batch_urls(names, MAX_URL_LEN) {
+        match http.get(&url).and_then(|body| parse_response(&body)) {
+            Ok(infos) => fetched.extend(infos),
+            Err(e) => return cached(cache, Some(e.to_string())),
+        }
+    }
+    *cache = AurCache {
+        fetched_at: now,
+        queried: names.iter().cloned().collect(),
+        packages: fetched
+            .into_iter()
+            .map(|info| (info.name.clone(), info))
+            .collect(),
+    };
+    cached(cache, None)
+}
+
+/// An installed foreign package the AUR has a newer version of
+#[derive(Debug, Clone, PartialEq)]
+pub struct AurUpdate {
+    pub name: String,
+    pub installed: String,
+    pub available: String,
+}
+
+/// Installed packages whose AUR version is newer, by name
+pub fn pending_updates(
+    installed: &HashMap<String, String>,
+    packages: &BTreeMap<String, AurInfo>,
+) -> Vec<AurUpdate> {
+    packages
+        .values()
+        .filter_map(|info| {
+            let current = installed.get(&info.name)?;
+            (crate::core::version::vercmp(&info.version, current) == Ordering::Greater).then(|| {
+                AurUpdate {
+                    name: info.name.clone(),
+                    installed: current.clone(),
+                    available: info.version.clone(),
+                }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const RESPONSE: &str = r#"{"resultcount":2,"results":[
+        {"ID":1,"Name":"paru-bin","PackageBase":"paru-bin","Version":"2.0.4-1",
+         "OutOfDate":null,"LastModified":1718000000,"NumVotes":100},
+        {"ID":2,"Name":"yay","PackageBase":"yay","Version":"12.3.5-1",
+         "OutOfDate":1719000000,"LastModified":1717000000}
+    ],"type":"multiinfo","version":5}"#;
+
+    /// Serves canned bodies in order and records the requested URLs
+    struct Canned {
+        bodies: RefCell<Vec<Result<String>>>,
+        urls: RefCell<Vec<String>>,
+    }
+
+    impl Canned {
+        fn new(bodies: Vec<Result<String>>) -> Self {
+            Canned {
+                bodies: RefCell::new(bodies),
+                urls: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl HttpGet for Canned {
+        fn get(&self, url: &str) -> Result<String> {
+            self.urls.borrow_mut().push(url.to_string());
+            self.bodies.borrow_mut().remove(0)
+        }
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let infos = parse_response(RESPONSE).unwrap();
+        assert_eq!(
+            infos[1],
+            AurInfo {
+                name: "yay".to_string(),
+                version: "12.3.5-1".to_string(),
+                out_of_date: Some(1_719_000_000),
+                last_modified: 1_717_000_000,
+            }
+        );
+        assert_eq!(infos[0].out_of_date, None);
+
+        let error = r#"{"error":"Too many package results.","resultcount":0,"results":[],"type":"error","version":5}"#;
+        assert!(
+            parse_response(error)
+                .unwrap_err()
+                .to_string()
+                .contains("Too many package results")
+        );
+        assert!(parse_response("<html>").is_err());
+    }
+
+    #[test]
+    fn test_batches_respect_url_length() {
+        let many: Vec<String> = (0..300).map(|i| format!("package-{:03}", i)).collect();
+        let urls = batch_urls(&many, MAX_URL_LEN);
+        assert!(urls.len() > 1);
+        assert!(urls.iter().all(|url| url.len() <= MAX_URL_LEN));
+        assert!(urls.iter().all(|url| url.starts_with(RPC_INFO_URL)));
+        let args: usize = urls.iter().map(|url| url.matches("&arg[]=").count()).sum();
+        assert_eq!(args, many.len());
+
+        assert_eq!(
+            batch_urls(&names(&["libc++", "foo@bar"]), MAX_URL_LEN),
+            vec![format!("{}&arg[]=libc%2B%2B&arg[]=foo%40bar", RPC_INFO_URL)]
+        );
+        assert!(batch_urls(&[], MAX_URL_LEN).is_empty());
+        // A single oversized name still gets a request
+        assert_eq!(batch_urls(&names(&["a", "b"]), 10).len(), 2);
+    }
+
+    #[test]
+    fn test_lookup_uses_fresh_cache() {
+        let http = Canned::new(vec![Ok(RESPONSE.to_string())]);
+        let mut cache = AurCache::default();
+        let wanted = names(&["paru-bin", "yay", "local-build"]);
+        let first = lookup(&wanted, &http, &mut cache, 1_000, 600, false);
+        assert_eq!(first.packages.len(), 2);
+        assert_eq!(first.age, 0);
+        assert!(cache.queried.contains("local-build"));
+
+        // Within the TTL nothing is requested, even for names the AUR lacks
+        let second = lookup(&wanted, &http, &mut cache, 1_500, 600, false);
+        assert_eq!(second.packages, first.packages);
+        assert_eq!(second.age, 500);
+        assert_eq!(http.urls.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_lookup_falls_back_to_stale_cache() {
+        let http = Canned::new(vec![
+            Ok(RESPONSE.to_string()),
+            Err(anyhow!("Could not resolve host")),
+        ]);
+        let mut cache = AurCache::default();
+        let wanted = names(&["paru-bin", "yay"]);
+        lookup(&wanted, &http, &mut cache, 1_000, 600, false);
+        let stale = lookup(&wanted, &http, &mut cache, 5_000, 600, false);
+        assert_eq!(stale.packages.len(), 2);
+        assert_eq!(stale.age, 4_000);
+        assert!(stale.error.unwrap().contains("resolve host"));
+        assert_eq!(cache.fetched_at, 1_000);
+    }
+
+    #[test]
+    fn test_refresh_ignores_ttl() {
+        let http = Canned::new(vec![Ok(RESPONSE.to_string()), Ok(RESPONSE.to_string())]);
+        let mut cache = AurCache::default();
+        let wanted = names(&["yay"]);
+        lookup(&wanted, &http, &mut cache, 1_000, 600, false);
+        lookup(&wanted, &http, &mut cache, 1_001, 600, true);
+        assert_eq!(http.urls.borrow().len(), 2);
+        assert_eq!(cache.fetched_at, 1_001);
+    }
+
+    #[test]
+    fn test_pending_updates_use_vercmp() {
+        let packages: BTreeMap<String, AurInfo> = parse_response(RESPONSE)
+            .unwrap()
+            .into_iter()
+            .map(|info| (info.name.clone(), info))
+            .collect();
+        let installed: HashMap<String, String> = [
+            ("paru-bin".to_string(), "2.0.3-1".to_string()),
+            ("yay".to_string(), "1:12.0.0-1".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        // yay's installed epoch outranks the AUR version
+        assert_eq!(
+            pending_updates(&installed, &packages),
+            vec![AurUpdate {
+                name: "paru-bin".to_string(),
+                installed: "2.0.3-1".to_string(),
+                available: "2.0.4-1".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            AurCache::load_from(dir.path()).unwrap(),
+            AurCache::default()
+        );
+        let http = Canned::new(vec![Ok(RESPONSE.to_string())]);
+        let mut cache = AurCache::default();
+        lookup(&names(&["yay"]), &http, &mut cache, 1_000, 600, false);
+        cache.save_to(dir.path()).unwrap();
+        assert_eq!(AurCache::load_from(dir.path()).unwrap(), cache);
+    }
+}
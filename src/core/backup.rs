@@ -0,0 +1,745 @@
+//! Content-addressed store for dotfile backups
+//!
+//! Before apply overwrites a destination its current contents are recorded in a
+//! backup set. File bodies are stored once under `backups/objects/<sha256>` and
+//! each set is a small JSON manifest under `backups/sets/` that maps destination
+//! paths to object hashes, so unchanged files shared between sets cost nothing.
+//!
+//! The store also keeps the baseline of each file destination in
+//! `backups/baselines.json`: the source content apply last wrote it from, as
+//! an object, and the hash of what it wrote, which differs after a merge. When
+//! both the destination and its source changed since, the stored source is the
+//! common base of a three-way merge (`core::merge`).
+//!
+//! Older stores kept every set as a plain directory copy (`backups/<id>/` with the
+//! destination's absolute path below it); these are migrated on first use.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::internal::constants;
+
+const OBJECTS_DIR: &str = "objects";
+const SETS_DIR: &str = "sets";
+const BASELINES_FILE: &str = "baselines.json";
+
+/// Backups of each destination kept after an apply (`--keep-backups`, `@backups-keep`)
+pub const DEFAULT_KEEP_BACKUPS: usize = 5;
+
+/// Kind of filesystem entry recorded in a manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// A single file, directory or symlink below a backed-up destination
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the target (empty for the target itself)
+    pub rel: String,
+    pub kind: EntryKind,
+    /// Object hash for files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Link target for symlinks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    pub mode: u32,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+/// State of one destination path at backup time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupTarget {
+    pub path: String,
+    /// Entries in walk order, parents before children; empty when the path did not exist
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// A backup set, stored as `sets/<id>.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub id: String,
+    pub created: u64,
+    pub targets: Vec<BackupTarget>,
+}
+
+impl Manifest {
+    pub fn new(created: u64) -> Self {
+        Self {
+            id: format!("{}-{}", created, std::process::id()),
+            created,
+            targets: Vec::new(),
+        }
+    }
+
+    fn hashes(&self) -> impl Iterator<Item = &str> {
+        self.targets
+            .iter()
+            .flat_map(|t| t.entries.iter())
+            .filter_map(|e| e.hash.as_deref())
+    }
+}
+
+/// What apply last wrote to a file destination
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    /// Object holding the source content the destination was written from
+    pub source: String,
+    /// sha256 of the content written, the merged text after a merge
+    pub written: String,
+}
+
+/// Result of re-hashing the object store
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyReport {
+    pub checked: usize,
+    /// Objects whose contents no longer match their name
+    pub corrupt: Vec<String>,
+    /// Objects referenced by a manifest but absent from the store
+    pub missing: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Backup store rooted at a directory (normally `~/.owl/.state/backups`)
+#[derive(Debug, Clone)]
+pub struct BackupStore {
+    root: PathBuf,
+}
+
+/// Name of the object holding `data`
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+fn mtime_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl BackupStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// Store for the current user
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::new(
+            crate::internal::environment::get()
+                .owl_dir()?
+                .join(constants::STATE_DIR)
+                .join(constants::BACKUPS_DIR),
+        ))
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.root.join(OBJECTS_DIR).join(hash)
+    }
+
+    fn manifest_path(&self, id: &str) -> PathBuf {
+        self.root.join(SETS_DIR).join(format!("{}.json", id))
+    }
+
+    /// Store a blob and return its hash; existing objects are reused
+    pub fn put(&self, data: &[u8]) -> Result<String> {
+        let hash = sha256_hex(data);
+        let path = self.object_path(&hash);
+        if path.is_file() {
+            return Ok(hash);
+        }
+        let dir = self.root.join(OBJECTS_DIR);
+        fs::create_dir_all(&dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", dir.display(), e))?;
+        // Write then rename so a crash never leaves a truncated object behind
+        let tmp = dir.join(format!(".{}.tmp", hash));
+        fs::write(&tmp, data).map_err(|e| anyhow!("Failed to write {}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, &path).map_err(|e| anyhow!("Failed to store object {}: {}", hash, e))?;
+        Ok(hash)
+    }
+
+    /// Read a blob by hash
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>> {
+        fs::read(self.object_path(hash))
+            .map_err(|e| anyhow!("Failed to read backup object {}: {}", hash, e))
+    }
+
+    /// Record the current state of `path` (which may not exist)
+    pub fn snapshot(&self, path: &Path) -> Result<BackupTarget> {
+        let mut entries = Vec::new();
+        if path.symlink_metadata().is_ok() {
+            self.snapshot_entry(path, "", &mut entries)?;
+        }
+        Ok(BackupTarget {
+            path: path.to_string_lossy().into_owned(),
+            entries,
+        })
+    }
+
+    fn snapshot_entry(
+        &self,
+        path: &Path,
+        rel: &str,
+        entries: &mut Vec<ManifestEntry>,
+    ) -> Result<()> {
+        let meta = path
+            .symlink_metadata()
+            .map_err(|e| anyhow!("Failed to stat {}: {}", path.display(), e))?;
+        let mode = meta.permissions().mode() & 0o7777;
+        let mtime = mtime_secs(&meta);
+        let ty = meta.file_type();
+        if ty.is_symlink() {
+            let link = fs::read_link(path)
+                .map_err(|e| anyhow!("Failed to read link {}: {}", path.display(), e))?;
+            entries.push(ManifestEntry {
+                rel: rel.to_string(),
+                kind: EntryKind::Symlink,
+                hash: None,
+                link: Some(link.to_string_lossy().into_owned()),
+                mode,
+                mtime,
+            });
+        } else if ty.is_dir() {
+            entries.push(ManifestEntry {
+                rel: rel.to_string(),
+                kind: EntryKind::Dir,
+                hash: None,
+                link: None,
+                mode,
+                mtime,
+            });
+            let mut children: Vec<_> = fs::read_dir(path)
+                .map_err(|e| anyhow!("Failed to read dir {}: {}", path.display(), e))?
+                .collect::<std::io::Result<_>>()
+                .map_err(|e| anyhow!("Failed to read entry in {}: {}", path.display(), e))?;
+            children.sort_by_key(|c| c.file_name());
+            for child in children {
+                let name = child.file_name().to_string_lossy().into_owned();
+                let child_rel = if rel.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", rel, name)
+                };
+                self.snapshot_entry(&child.path(), &child_rel, entries)?;
+            }
+        } else if ty.is_file() {
+            let data =
+                fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            entries.push(ManifestEntry {
+                rel: rel.to_string(),
+                kind: EntryKind::File,
+                hash: Some(self.put(&data)?),
+                link: None,
+                mode,
+                mtime,
+            });
+        }
+        Ok(())
+    }
+
+    /// Put a target back exactly as recorded, replacing whatever is there now
+    pub fn restore(&self, target: &BackupTarget) -> Result<()> {
+        let root = Path::new(&target.path);
+        if let Ok(meta) = root.symlink_metadata() {
+            if meta.is_dir() {
+                fs::remove_dir_all(root)
+            } else {
+                fs::remove_file(root)
+            }
+            .map_err(|e| anyhow!("Failed to remove {}: {}", root.display(), e))?;
+        }
+        for entry in &target.entries {
+            let path = if entry.rel.is_empty() {
+                root.to_path_buf()
+            } else {
+                root.join(&entry.rel)
+            };
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| {
+                    anyhow!("Failed to create directory {}: {}", parent.display(), e)
+                })?;
+            }
+            match entry.kind {
+                EntryKind::Dir => {
+                    fs::create_dir_all(&path).map_err(|e| {
+                        anyhow!("Failed to create directory {}: {}", path.display(), e)
+                    })?;
+                }
+                EntryKind::Symlink => {
+                    let link = entry.link.as_deref().unwrap_or_default();
+                    std::os::unix::fs::symlink(link, &path)
+                        .map_err(|e| anyhow!("Failed to link {}: {}", path.display(), e))?;
+                    continue;
+                }
+                EntryKind::File => {
+                    let hash = entry
+                        .hash
+                        .as_deref()
+                        .ok_or_else(|| anyhow!("Missing hash for {}", path.display()))?;
+                    fs::write(&path, self.get(hash)?)
+                        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+                    let file = fs::File::options()
+                        .write(true)
+                        .open(&path)
+                        .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+                    let _ = file.set_modified(UNIX_EPOCH + Duration::from_secs(entry.mtime));
+                }
+            }
+            fs::set_permissions(&path, fs::Permissions::from_mode(entry.mode))
+                .map_err(|e| anyhow!("Failed to set mode on {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Write a manifest to `sets/<id>.json`
+    pub fn save_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let path = self.manifest_path(&manifest.id);
+        let dir = self.root.join(SETS_DIR);
+        fs::create_dir_all(&dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", dir.display(), e))?;
+        let content = serde_json::to_string_pretty(manifest)
+            .map_err(|e| anyhow!("Failed to serialize backup manifest: {}", e))?;
+        fs::write(&path, content).map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Delete a manifest (its objects are left for `gc`)
+    pub fn remove_manifest(&self, id: &str) -> Result<()> {
+        let path = self.manifest_path(id);
+        fs::remove_file(&path).map_err(|e| anyhow!("Failed to remove {}: {}", path.display(), e))
+    }
+
+    /// All manifests, oldest first
+    pub fn manifests(&self) -> Result<Vec<Manifest>> {
+        let dir = self.root.join(SETS_DIR);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut manifests = Vec::new();
+        for entry in fs::read_dir(&dir)
+            .map_err(|e| anyhow!("Failed to read dir {}: {}", dir.display(), e))?
+        {
+            let path = entry
+                .map_err(|e| anyhow!("Failed to read entry in {}: {}", dir.display(), e))?
+                .path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let content = fs::read_to_string(&path)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            let manifest: Manifest = serde_json::from_str(&content)
+                .map_err(|e| anyhow!("Invalid backup manifest {}: {}", path.display(), e))?;
+            manifests.push(manifest);
+        }
+        manifests.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.id.cmp(&b.id)));
+        Ok(manifests)
+    }
+
+    /// Baselines by destination path
+    pub fn baselines(&self) -> Result<BTreeMap<String, Baseline>> {
+        let path = self.root.join(BASELINES_FILE);
+        if !path.is_file() {
+            return Ok(BTreeMap::new());
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Invalid dotfile baselines {}: {}", path.display(), e))
+    }
+
+    /// Replace the recorded baselines; their objects must already be stored
+    pub fn save_baselines(&self, baselines: &BTreeMap<String, Baseline>) -> Result<()> {
+        fs::create_dir_all(&self.root)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", self.root.display(), e))?;
+        let path = self.root.join(BASELINES_FILE);
+        let content = serde_json::to_string_pretty(baselines)
+            .map_err(|e| anyhow!("Failed to serialize dotfile baselines: {}", e))?;
+        fs::write(&path, content).map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+    }
+
+    fn object_hashes(&self) -> Result<Vec<String>> {
+        let dir = self.root.join(OBJECTS_DIR);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut hashes = Vec::new();
+        for entry in fs::read_dir(&dir)
+            .map_err(|e| anyhow!("Failed to read dir {}: {}", dir.display(), e))?
+        {
+            let entry =
+                entry.map_err(|e| anyhow!("Failed to read entry in {}: {}", dir.display(), e))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') {
+                hashes.push(name);
+            }
+        }
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    /// Remove objects no manifest or baseline references; returns how many were removed
+    pub fn gc(&self) -> Result<usize> {
+        let manifests = self.manifests()?;
+        let baselines = self.baselines()?;
+        let referenced: HashSet<&str> = manifests
+            .iter()
+            .flat_map(|m| m.hashes())
+            .chain(baselines.values().map(|b| b.source.as_str()))
+            .collect();
+        let mut removed = 0;
+        for hash in self.object_hashes()? {
+            if !referenced.contains(hash.as_str()) {
+                let path = self.object_path(&hash);
+                fs::remove_file(&path)
+                    .map_err(|e| anyhow!("Failed to remove {}: {}", path.display(), e))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Keep the newest `keep` backups of each destination, then collect unreferenced objects
+    ///
+    /// Older targets are dropped from their sets and sets left without targets
+    /// are removed. Returns the number of sets and objects removed.
+    pub fn prune(&self, keep: usize) -> Result<(usize, usize)> {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        let mut removed = 0;
+        for mut manifest in self.manifests()?.into_iter().rev() {
+            let before = manifest.targets.len();
+            manifest.targets.retain(|target| {
+                let count = seen.entry(target.path.clone()).or_default();
+                *count += 1;
+                *count <= keep
+            });
+            if manifest.targets.is_empty() {
+                self.remove_manifest(&manifest.id)?;
+                removed += 1;
+            } else if manifest.targets.len() != before {
+                self.save_manifest(&manifest)?;
+            }
+        }
+        Ok((removed, self.gc()?))
+    }
+
+    /// Re-hash every object and check that all manifest references resolve
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        let objects = self.object_hashes()?;
+        for hash in &objects {
+            report.checked += 1;
+            let data = self.get(hash)?;
+            if sha256_hex(&data) != *hash {
+                report.corrupt.push(hash.clone());
+            }
+        }
+        let present: HashSet<&str> = objects.iter().map(String::as_str).collect();
+        let baselines = self.baselines()?;
+        let mut missing: Vec<String> = self
+            .manifests()?
+            .iter()
+            .flat_map(|m| m.hashes())
+            .chain(baselines.values().map(|b| b.source.as_str()))
+            .filter(|h| !present.contains(h))
+            .map(str::to_string)
+            .collect();
+        missing.sort();
+        missing.dedup();
+        report.missing = missing;
+        Ok(report)
+    }
+
+    /// Convert full-copy set directories into manifests; returns how many were migrated
+    pub fn migrate_legacy(&self) -> Result<usize> {
+        if !self.root.is_dir() {
+            return Ok(0);
+        }
+        let mut legacy = Vec::new();
+        for entry in fs::read_dir(&self.root)
+            .map_err(|e| anyhow!("Failed to read dir {}: {}", self.root.display(), e))?
+        {
+            let entry = entry
+                .map_err(|e| anyhow!("Failed to read entry in {}: {}", self.root.display(), e))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_dir() && name != OBJECTS_DIR && name != SETS_DIR {
+                legacy.push((name, entry.path()));
+            }
+        }
+        legacy.sort();
+
+        for (name, dir) in &legacy {
+            let created = fs::metadata(dir).map(|m| mtime_secs(&m)).unwrap_or(0);
+            let mut manifest = Manifest {
+                id: format!("legacy-{}", name),
+                created,
+                targets: Vec::new(),
+            };
+            let mut files = Vec::new();
+            collect_legacy_files(dir, &mut files)?;
+            files.sort();
+            for file in files {
+                let rel = file.strip_prefix(dir).unwrap_or(&file);
+                let mut target = self.snapshot(&file)?;
+                target.path = Path::new("/").join(rel).to_string_lossy().into_owned();
+                manifest.targets.push(target);
+            }
+            self.save_manifest(&manifest)?;
+            fs::remove_dir_all(dir)
+                .map_err(|e| anyhow!("Failed to remove {}: {}", dir.display(), e))?;
+        }
+        Ok(legacy.len())
+    }
+}
+
+fn collect_legacy_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in
+        fs::read_dir(dir).map_err(|e| anyhow!("Failed to read dir {}: {}", dir.display(), e))?
+    {
+        let path = entry
+            .map_err(|e| anyhow!("Failed to read entry in {}: {}", dir.display(), e))?
+            .path();
+        let meta = path
+            .symlink_metadata()
+            .map_err(|e| anyhow!("Failed to stat {}: {}", path.display(), e))?;
+        if meta.is_dir() {
+            collect_legacy_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(dir: &Path) -> BackupStore {
+        BackupStore::new(dir.join("backups"))
+    }
+
+    #[test]
+    fn test_put_get_dedupes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let a = store.put(b"hello").unwrap();
+        let b = store.put(b"hello").unwrap();
+        assert_eq!(a, b);
+        assert_eq!(store.get(&a).unwrap(), b"hello");
+        assert_eq!(store.object_hashes().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_and_restore_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let target = dir.path().join("nvim");
+        fs::create_dir_all(target.join("lua")).unwrap();
+        fs::write(target.join("init.lua"), "init").unwrap();
+        fs::write(target.join("lua/opts.lua"), "opts").unwrap();
+        fs::set_permissions(target.join("init.lua"), fs::Permissions::from_mode(0o600)).unwrap();
+
+        let snap = store.snapshot(&target).unwrap();
+        let rels: Vec<&str> = snap.entries.iter().map(|e| e.rel.as_str()).collect();
+        assert_eq!(rels, vec!["", "init.lua", "lua", "lua/opts.lua"]);
+
+        fs::remove_dir_all(&target).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("stray"), "x").unwrap();
+        store.restore(&snap).unwrap();
+
+        assert_eq!(fs::read_to_string(target.join("init.lua")).unwrap(), "init");
+        assert_eq!(
+            fs::read_to_string(target.join("lua/opts.lua")).unwrap(),
+            "opts"
+        );
+        assert!(!target.join("stray").exists());
+        let mode = fs::metadata(target.join("init.lua"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_restore_absent_target_removes_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let target = dir.path().join("new");
+        let snap = store.snapshot(&target).unwrap();
+        assert!(snap.entries.is_empty());
+        fs::write(&target, "created later").unwrap();
+        store.restore(&snap).unwrap();
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let file = dir.path().join("bashrc");
+        fs::write(&file, "bashrc").unwrap();
+        let mut manifest = Manifest::new(100);
+        manifest.targets.push(store.snapshot(&file).unwrap());
+        store.save_manifest(&manifest).unwrap();
+
+        let loaded = store.manifests().unwrap();
+        assert_eq!(loaded, vec![manifest]);
+        let json = fs::read_to_string(store.manifest_path(&loaded[0].id)).unwrap();
+        assert!(json.contains("\"kind\": \"file\""));
+        assert!(!json.contains("\"link\""));
+    }
+
+    #[test]
+    fn test_gc_keeps_shared_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let shared = dir.path().join("shared");
+        let only_old = dir.path().join("only_old");
+        fs::write(&shared, "shared").unwrap();
+        fs::write(&only_old, "orphan soon").unwrap();
+
+        let mut old = Manifest::new(1);
+        old.targets.push(store.snapshot(&shared).unwrap());
+        old.targets.push(store.snapshot(&only_old).unwrap());
+        store.save_manifest(&old).unwrap();
+        fs::write(&only_old, "replaced").unwrap();
+        let mut new = Manifest::new(2);
+        new.targets.push(store.snapshot(&shared).unwrap());
+        new.targets.push(store.snapshot(&only_old).unwrap());
+        store.save_manifest(&new).unwrap();
+        assert_eq!(store.object_hashes().unwrap().len(), 3);
+
+        assert_eq!(store.prune(1).unwrap(), (1, 1));
+        let mut remaining = store.object_hashes().unwrap();
+        remaining.sort();
+        let mut expected = vec![sha256_hex(b"shared"), sha256_hex(b"replaced")];
+        expected.sort();
+        assert_eq!(remaining, expected);
+        assert_eq!(store.manifests().unwrap(), vec![new]);
+    }
+
+    #[test]
+    fn test_gc_keeps_baseline_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        assert!(store.baselines().unwrap().is_empty());
+        let written = store.put(b"last written").unwrap();
+        store.put(b"unreferenced").unwrap();
+        let baselines = BTreeMap::from([(
+            "/home/u/.bashrc".to_string(),
+            Baseline {
+                source: written.clone(),
+                written: written.clone(),
+            },
+        )]);
+        store.save_baselines(&baselines).unwrap();
+
+        assert_eq!(store.gc().unwrap(), 1);
+        assert_eq!(store.object_hashes().unwrap(), vec![written.clone()]);
+        assert_eq!(store.baselines().unwrap(), baselines);
+
+        fs::remove_file(store.object_path(&written)).unwrap();
+        assert_eq!(store.verify().unwrap().missing, vec![written]);
+    }
+
+    #[test]
+    fn test_prune_keeps_newest_backups_per_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let bashrc = dir.path().join("bashrc");
+        let vimrc = dir.path().join("vimrc");
+        let keep = 3;
+        for i in 0..keep + 2 {
+            fs::write(&bashrc, format!("bashrc {}", i)).unwrap();
+            let mut manifest = Manifest::new(i as u64);
+            manifest.targets.push(store.snapshot(&bashrc).unwrap());
+            // vimrc is only backed up by the oldest set
+            if i == 0 {
+                fs::write(&vimrc, "vimrc").unwrap();
+                manifest.targets.push(store.snapshot(&vimrc).unwrap());
+            }
+            store.save_manifest(&manifest).unwrap();
+        }
+
+        assert_eq!(store.prune(keep).unwrap(), (1, 2));
+        let manifests = store.manifests().unwrap();
+        let bashrc_backups: Vec<u64> = manifests
+            .iter()
+            .filter(|m| m.targets.iter().any(|t| t.path == bashrc.to_string_lossy()))
+            .map(|m| m.created)
+            .collect();
+        assert_eq!(bashrc_backups, vec![2, 3, 4]);
+        // The oldest set survives for vimrc alone
+        assert_eq!(manifests[0].created, 0);
+        assert_eq!(manifests[0].targets.len(), 1);
+        assert_eq!(manifests[0].targets[0].path, vimrc.to_string_lossy());
+        assert_eq!(store.prune(keep).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_verify_reports_corruption_and_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let file = dir.path().join("a");
+        fs::write(&file, "a").unwrap();
+        let mut manifest = Manifest::new(1);
+        manifest.targets.push(store.snapshot(&file).unwrap());
+        store.save_manifest(&manifest).unwrap();
+        let good = store.put(b"b").unwrap();
+        assert!(store.verify().unwrap().is_ok());
+
+        fs::write(store.object_path(&good), "tampered").unwrap();
+        fs::remove_file(store.object_path(&sha256_hex(b"a"))).unwrap();
+        let report = store.verify().unwrap();
+        assert_eq!(report.checked, 1);
+        assert_eq!(report.corrupt, vec![good]);
+        assert_eq!(report.missing, vec![sha256_hex(b"a")]);
+    }
+
+    #[test]
+    fn test_migrate_legacy_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let legacy = store.root.join("1700000000/home/user/.config/app");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("config"), "legacy").unwrap();
+        fs::write(store.root.join("1700000000/home/user/.bashrc"), "rc").unwrap();
+
+        assert_eq!(store.migrate_legacy().unwrap(), 1);
+        assert!(!store.root.join("1700000000").exists());
+        let manifests = store.manifests().unwrap();
+        assert_eq!(manifests.len(), 1);
+        assert_eq!(manifests[0].id, "legacy-1700000000");
+        let paths: Vec<&str> = manifests[0]
+            .targets
+            .iter()
+            .map(|t| t.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["/home/user/.bashrc", "/home/user/.config/app/config"]
+        );
+        assert!(store.verify().unwrap().is_ok());
+        // Already migrated stores are left alone
+        assert_eq!(store.migrate_legacy().unwrap(), 0);
+    }
+}
@@ -0,0 +1,336 @@
+//! Progress of a running `owl apply`, kept so an interrupted one can be resumed
+//!
+//! While apply runs it records in `.state/apply-checkpoint.json` which steps
+//! finished, which packages it installed and which dotfiles it wrote. A run
+//! that gets to the end removes the file, so one left behind means the last
+//! apply was interrupted (Ctrl-C, a crash, power loss). `owl apply --resume`
+//! reads it and skips the finished steps. The dotfile rollback journal undoes
+//! a failed run; this only ever moves forward.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::core::dotfiles::DotfileStatus;
+use crate::core::events::{EventPhase, EventSink, OwlEvent};
+
+const CHECKPOINT_FILE: &str = "apply-checkpoint.json";
+
+/// A part of the run that is skipped on resume once it finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Removals, installs and updates
+    Packages,
+    Dotfiles,
+    /// Services and env files
+    System,
+}
+
+impl Step {
+    fn of(phase: EventPhase) -> Self {
+        match phase {
+            EventPhase::Packages => Step::Packages,
+            EventPhase::Dotfiles => Step::Dotfiles,
+            EventPhase::System => Step::System,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Step::Packages => "packages",
+            Step::Dotfiles => "dotfiles",
+            Step::System => "services and env",
+        }
+    }
+}
+
+/// What an apply run got done so far
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Start of the run, in seconds since the Unix epoch
+    pub started: u64,
+    #[serde(default)]
+    pub completed: Vec<Step>,
+    /// Packages installed, to be marked managed when the run is resumed
+    #[serde(default)]
+    pub installed: Vec<String>,
+    /// Dotfile destinations written
+    #[serde(default)]
+    pub dotfiles: Vec<String>,
+}
+
+impl Checkpoint {
+    pub fn new(started: u64) -> Self {
+        Self {
+            started,
+            ..Default::default()
+        }
+    }
+
+    /// The checkpoint of an interrupted run in `state_dir`, if there is one
+    pub fn load(state_dir: &Path) -> Result<Option<Self>> {
+        let path = state_dir.join(CHECKPOINT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", CHECKPOINT_FILE, e))?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| anyhow!("Failed to parse {}: {}", CHECKPOINT_FILE, e))
+    }
+
+    /// Write the checkpoint, replacing the old one in a single rename so an
+    /// interruption never leaves half a file
+    pub fn save(&self, state_dir: &Path) -> Result<()> {
+        fs::create_dir_all(state_dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", state_dir.display(), e))?;
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize {}: {}", CHECKPOINT_FILE, e))?;
+        let tmp = state_dir.join(format!(".{}.tmp", CHECKPOINT_FILE));
+        fs::write(&tmp, content)
+            .and_then(|_| fs::rename(&tmp, state_dir.join(CHECKPOINT_FILE)))
+            .map_err(|e| anyhow!("Failed to write {}: {}", CHECKPOINT_FILE, e))
+    }
+
+    /// Remove the checkpoint after a run that got to the end
+    pub fn clear(state_dir: &Path) -> Result<()> {
+        match fs::remove_file(state_dir.join(CHECKPOINT_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(anyhow!("Failed to remove {}: {}", CHECKPOINT_FILE, e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_complete(&self, step: Step) -> bool {
+        self.completed.contains(&step)
+    }
+
+    /// `packages (2 installed), 3 dotfiles written`
+    pub fn summary(&self) -> String {
+        let mut parts: Vec<String> = self
+            .completed
+            .iter()
+            .map(|step| match step {
+                Step::Packages if !self.installed.is_empty() => {
+                    format!("packages ({} installed)", self.installed.len())
+                }
+                step => step.label().to_string(),
+            })
+            .collect();
+        if !self.is_complete(Step::Dotfiles) && !self.dotfiles.is_empty() {
+            parts.push(format!("{} dotfile(s) written", self.dotfiles.len()));
+        }
+        if parts.is_empty() {
+            "nothing finished yet".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// Passes events on to `inner` and records the run's progress from them
+///
+/// A step counts as finished once its phase ended without an error, a failed
+/// install or deferred packages, and the next phase started or the run ended;
+/// errors of a phase can be reported just after it. Without a state directory
+/// (dry runs) nothing is written.
+pub struct Recorder<'a> {
+    inner: &'a mut dyn EventSink,
+    checkpoint: Checkpoint,
+    state_dir: Option<PathBuf>,
+    /// Finished phase waiting for the next one to start
+    pending: Option<Step>,
+    /// The current phase reported a problem
+    failed: bool,
+    /// Problems reported over the whole run
+    failures: usize,
+    save_failed: bool,
+}
+
+impl<'a> Recorder<'a> {
+    /// Start recording, writing the checkpoint right away so even a run
+    /// interrupted before its first step can be resumed
+    pub fn new(
+        inner: &'a mut dyn EventSink,
+        checkpoint: Checkpoint,
+        state_dir: Option<PathBuf>,
+    ) -> Self {
+        let mut recorder = Self {
+            inner,
+            checkpoint,
+            state_dir,
+            pending: None,
+            failed: false,
+            failures: 0,
+            save_failed: false,
+        };
+        recorder.save();
+        recorder
+    }
+
+    /// Errors, failed installs and deferred packages seen so far
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// Record the last phase; the run got to the end
+    pub fn finish(mut self) -> Checkpoint {
+        if self.commit_pending() {
+            self.save();
+        }
+        self.checkpoint
+    }
+
+    fn commit_pending(&mut self) -> bool {
+        match self.pending.take() {
+            Some(step) if !self.checkpoint.is_complete(step) => {
+                self.checkpoint.completed.push(step);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn save(&mut self) {
+        let Some(dir) = &self.state_dir else {
+            return;
+        };
+        if let Err(err) = self.checkpoint.save(dir)
+            && !self.save_failed
+        {
+            // Once: the run goes on, it just cannot be resumed
+            self.save_failed = true;
+            self.inner.emit(OwlEvent::Warning(format!(
+                "{}; this run cannot be resumed if interrupted",
+                err
+            )));
+        }
+    }
+}
+
+impl EventSink for Recorder<'_> {
+    fn emit(&mut self, event: OwlEvent) {
+        let changed = match &event {
+            OwlEvent::PhaseStarted(_) => {
+                self.failed = false;
+                self.commit_pending()
+            }
+            OwlEvent::PhaseFinished(phase) => {
+                if !self.failed {
+                    self.pending = Some(Step::of(*phase));
+                }
+                false
+            }
+            OwlEvent::Error(_) | OwlEvent::PackagesDeferred { .. } => {
+                self.failed = true;
+                self.failures += 1;
+                self.pending = None;
+                false
+            }
+            OwlEvent::PackageInstallFinished { name, success, .. } => {
+                if !*success {
+                    self.failed = true;
+                    self.failures += 1;
+                }
+                *success && push_new(&mut self.checkpoint.installed, name)
+            }
+            OwlEvent::DotfileActionCompleted { action } => {
+                matches!(
+                    action.status,
+                    DotfileStatus::Create | DotfileStatus::Update | DotfileStatus::Merge
+                ) && push_new(&mut self.checkpoint.dotfiles, &action.mapping.destination)
+            }
+            _ => false,
+        };
+        self.inner.emit(event);
+        if changed {
+            self.save();
+        }
+    }
+}
+
+/// Add `name` unless a resumed run already recorded it
+fn push_new(list: &mut Vec<String>, name: &str) -> bool {
+    let new = !list.iter().any(|n| n == name);
+    if new {
+        list.push(name.to_string());
+    }
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install(name: &str, success: bool) -> OwlEvent {
+        OwlEvent::PackageInstallFinished {
+            name: name.to_string(),
+            success,
+            duration_ms: None,
+        }
+    }
+
+    #[test]
+    fn test_interrupt_after_packages_keeps_them_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut seen = Vec::new();
+        {
+            let mut forward = |event: OwlEvent| seen.push(event);
+            let mut recorder =
+                Recorder::new(&mut forward, Checkpoint::new(7), Some(dir.path().into()));
+            recorder.emit(OwlEvent::PhaseStarted(EventPhase::Packages));
+            recorder.emit(install("ripgrep", true));
+            recorder.emit(install("fd", true));
+            recorder.emit(OwlEvent::PhaseFinished(EventPhase::Packages));
+            recorder.emit(OwlEvent::PhaseStarted(EventPhase::Dotfiles));
+            // Interrupted here: the recorder is never finished
+        }
+        assert_eq!(seen.len(), 5, "events are passed on");
+
+        let checkpoint = Checkpoint::load(dir.path()).unwrap().unwrap();
+        assert_eq!(checkpoint.started, 7);
+        assert_eq!(checkpoint.completed, vec![Step::Packages]);
+        assert_eq!(checkpoint.installed, vec!["ripgrep", "fd"]);
+        assert!(!checkpoint.is_complete(Step::Dotfiles));
+        assert_eq!(checkpoint.summary(), "packages (2 installed)");
+
+        Checkpoint::clear(dir.path()).unwrap();
+        assert_eq!(Checkpoint::load(dir.path()).unwrap(), None);
+        Checkpoint::clear(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn test_failed_or_deferred_phases_are_not_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = |_: OwlEvent| {};
+        let mut recorder = Recorder::new(&mut sink, Checkpoint::new(1), Some(dir.path().into()));
+        recorder.emit(OwlEvent::PhaseStarted(EventPhase::Packages));
+        recorder.emit(install("ripgrep", false));
+        recorder.emit(OwlEvent::PhaseFinished(EventPhase::Packages));
+        recorder.emit(OwlEvent::PhaseStarted(EventPhase::Dotfiles));
+        recorder.emit(OwlEvent::PhaseFinished(EventPhase::Dotfiles));
+        // A sync error is reported after its phase ended
+        recorder.emit(OwlEvent::Error("source missing".to_string()));
+        recorder.emit(OwlEvent::PhaseStarted(EventPhase::System));
+        recorder.emit(OwlEvent::PhaseFinished(EventPhase::System));
+        assert_eq!(recorder.failures(), 2);
+        let checkpoint = recorder.finish();
+        assert_eq!(checkpoint.completed, vec![Step::System]);
+        assert_eq!(
+            Checkpoint::load(dir.path()).unwrap().unwrap().completed,
+            vec![Step::System]
+        );
+
+        let mut recorder = Recorder::new(&mut sink, Checkpoint::new(1), None);
+        recorder.emit(OwlEvent::PhaseStarted(EventPhase::Packages));
+        recorder.emit(OwlEvent::PackagesDeferred {
+            reason: "locked".to_string(),
+        });
+        recorder.emit(OwlEvent::PhaseFinished(EventPhase::Packages));
+        assert!(recorder.finish().completed.is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+//! Noticing a system clock that moved backwards between runs
+//!
+//! Fast paths and caches compare stored timestamps with the current time. A
+//! clock set back (a dead CMOS battery, a VM restored from a snapshot) makes
+//! stale stamps look fresh and leaves stamps in the future. Each of those
+//! checks guards itself against future stamps; this module records when
+//! `apply` last ran so a jump backwards can also be reported once, on the run
+//! that first sees it.
+
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::path::Path;
+
+const LAST_RUN_FILE: &str = "last-run";
+
+/// Jumps backwards up to this are ordinary corrections, e.g. by NTP
+pub const SKEW_TOLERANCE_SECS: u64 = 5 * 60;
+
+/// Start time of the last `apply` that recorded one, in `state_dir`
+pub fn last_run(state_dir: &Path) -> Option<u64> {
+    fs::read_to_string(state_dir.join(LAST_RUN_FILE))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Record `now` as the start of the last run, replacing any later time
+pub fn record_run(state_dir: &Path, now: u64) -> Result<()> {
+    fs::create_dir_all(state_dir)
+        .map_err(|e| anyhow!("Failed to create directory {}: {}", state_dir.display(), e))?;
+    let path = state_dir.join(LAST_RUN_FILE);
+    fs::write(&path, now.to_string())
+        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+}
+
+/// How far `now` is behind `last_run`, if by more than the tolerance
+pub fn behind(last_run: Option<u64>, now: u64) -> Option<u64> {
+    last_run
+        .and_then(|last| last.checked_sub(now))
+        .filter(|secs| *secs > SKEW_TOLERANCE_SECS)
+}
+
+/// Compare the clock with the last recorded run and, when `record`, record this one
+///
+/// Returns the warning to show when the clock moved backwards. Recording this
+/// run's earlier time means the next run does not warn again.
+pub fn check(state_dir: &Path, now: u64, record: bool) -> Option<String> {
+    let last = last_run(state_dir);
+    let warning = behind(last, now).map(|secs| {
+        format!(
+            "The system clock is {} behind the last owl run ({}); it may have been reset. \
+             Stored timestamps from the future are not trusted, so some files are hashed \
+             and caches refetched",
+            crate::internal::time::format_age(secs as i64),
+            crate::internal::time::format_datetime(last.unwrap_or_default())
+        )
+    });
+    if record && let Err(err) = record_run(state_dir, now) {
+        return Some(warning.map_or(err.to_string(), |w| format!("{}; {}", w, err)));
+    }
+    warning
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn test_behind_table() {
+        let now = 1_000 * DAY;
+        let cases = [
+            (None, None),
+            // Forwards, or within the tolerance, is normal
+            (Some(now - DAY), None),
+            (Some(now), None),
+            (Some(now + SKEW_TOLERANCE_SECS), None),
+            // The clock jumped backwards
+            (
+                Some(now + SKEW_TOLERANCE_SECS + 1),
+                Some(SKEW_TOLERANCE_SECS + 1),
+            ),
+            (Some(now + 400 * DAY), Some(400 * DAY)),
+        ];
+        for (last, expected) in cases {
+            assert_eq!(behind(last, now), expected, "last run {:?}", last);
+        }
+    }
+
+    #[test]
+    fn test_backwards_jump_warns_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = 1_000 * DAY;
+        assert_eq!(check(dir.path(), now, true), None);
+        assert_eq!(last_run(dir.path()), Some(now));
+
+        // A dry run reports but leaves the record alone
+        let reset = now - 300 * DAY;
+        let warning = check(dir.path(), reset, false).unwrap();
+        assert!(warning.contains("300d behind"), "{}", warning);
+        assert_eq!(last_run(dir.path()), Some(now));
+
+        assert!(check(dir.path(), reset, true).is_some());
+        assert_eq!(check(dir.path(), reset + 60, true), None);
+    }
+}
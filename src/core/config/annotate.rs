@@ -0,0 +1,830 @@
+//! A config file annotated line by line with what it does on this host
+//! (`owl config explain <file>`)
+//!
+//! Each directive line is run through the real parser on its own, then looked
+//! up in the merged config and on the machine: whether a package is installed,
+//! where a `:config` mapping lands and whether it is in sync, the state of a
+//! `:service`, which file's `:env` wins, and whether an `@group` file exists.
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::trace::JournalEntry;
+use super::{Config, Package};
+use crate::core::dotfiles::{DotfileRoots, DotfileStatus};
+use crate::core::services::ServiceManager;
+
+/// What the annotations are looked up against
+pub struct Host<'a> {
+    /// Used to tell whether the file is loaded on this host
+    pub hostname: &'a str,
+    pub roots: &'a DotfileRoots,
+    /// `None` when the package manager could not be asked
+    pub installed: &'a dyn Fn(&str) -> Option<bool>,
+    pub services: &'a dyn ServiceManager,
+}
+
+/// Whether a mapping's destination matches its source
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncState {
+    InSync,
+    OutOfSync,
+    /// The destination does not exist yet
+    NotCreated,
+    SourceMissing,
+    Conflict(String),
+}
+
+/// The effect of one directive line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Note {
+    Package {
+        name: String,
+        installed: Option<bool>,
+        /// Higher-precedence files that also declare the package
+        also_declared_in: Vec<String>,
+    },
+    Config {
+        package: String,
+        source: PathBuf,
+        destination: PathBuf,
+        sync: SyncState,
+        /// File whose `:config` is used instead of this one
+        overridden_by: Option<String>,
+    },
+    Service {
+        package: String,
+        name: String,
+        enabled: Option<bool>,
+        active: Option<bool>,
+        overridden_by: Option<String>,
+    },
+    Env {
+        /// `None` for `@env`
+        package: Option<String>,
+        key: String,
+        value: String,
+        /// Value in effect when it is not this line's
+        resolved: Option<String>,
+        /// File the resolved value comes from, when known
+        overridden_by: Option<String>,
+    },
+    Group {
+        name: String,
+        file: String,
+        exists: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LineReport {
+    /// 1-based
+    pub line: usize,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<Note>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileReport {
+    /// Path relative to the owl root when the file lies inside it
+    pub file: String,
+    /// Whether the file is part of this host's config; overrides are only
+    /// reported for loaded files
+    pub loaded: bool,
+    pub lines: Vec<LineReport>,
+    /// Merge journal of each package the file declares, when the config was
+    /// loaded traced (`owl config trace`)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub journal: BTreeMap<String, Vec<JournalEntry>>,
+}
+
+/// Where the parser is in the file, tracked the same way `Config::parse` does
+#[derive(Default)]
+struct Cursor {
+    package: Option<String>,
+    in_packages_section: bool,
+    in_defaults: bool,
+}
+
+/// Annotate every line of `path` against the merged `config`
+pub fn annotate(config: &Config, path: &Path, host: &Host) -> Result<FileReport> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    // Parse the whole file first so errors point at it, and for @dotfiles-root
+    let own = Config::parse_file(path)?;
+    let label = relative_label(&host.roots.owl_dir, path);
+    let loaded = is_loaded(config, &label, host.hostname);
+    let context = Context {
+        config,
+        own: &own,
+        label: &label,
+        loaded,
+        host,
+    };
+
+    let mut cursor = Cursor::default();
+    let mut lines = Vec::new();
+    for (index, raw) in content.lines().enumerate() {
+        let line = raw.trim();
+        let note = if line.is_empty() || line.starts_with('#') {
+            None
+        } else {
+            context.note(&mut cursor, line)?
+        };
+        lines.push(LineReport {
+            line: index + 1,
+            text: raw.to_string(),
+            note,
+        });
+    }
+    let journal = match (&config.journal, loaded) {
+        (Some(journal), true) => own
+            .packages
+            .keys()
+            .filter_map(|name| Some((name.clone(), journal.packages.get(name)?.clone())))
+            .collect(),
+        _ => BTreeMap::new(),
+    };
+    Ok(FileReport {
+        file: label,
+        loaded,
+        lines,
+        journal,
+    })
+}
+
+struct Context<'a> {
+    config: &'a Config,
+    own: &'a Config,
+    label: &'a str,
+    loaded: bool,
+    host: &'a Host<'a>,
+}
+
+impl Context<'_> {
+    fn note(&self, cursor: &mut Cursor, line: &str) -> Result<Option<Note>> {
+        if line.starts_with('@') {
+            cursor.in_defaults = false;
+        }
+        if line == "@defaults" {
+            *cursor = Cursor {
+                in_defaults: true,
+                ..Cursor::default()
+            };
+            return Ok(None);
+        }
+        if cursor.in_defaults {
+            return Ok(None);
+        }
+        if line == "@packages" || line == "@pkgs" {
+            cursor.in_packages_section = true;
+            cursor.package = None;
+            return Ok(None);
+        }
+        if line.starts_with("@package ") || line.starts_with("@pkg ") {
+            cursor.in_packages_section = false;
+            cursor.package = single_package(&Config::parse(line)?);
+            return Ok(cursor
+                .package
+                .as_deref()
+                .map(|name| self.package_note(name)));
+        }
+        if line.starts_with("@group ") {
+            cursor.package = None;
+            return Ok(Config::parse(line)?
+                .groups
+                .pop()
+                .map(|name| self.group_note(name)));
+        }
+        if line.starts_with("@env ") {
+            let parsed = Config::parse(line)?;
+            return Ok(parsed
+                .env_vars
+                .into_iter()
+                .next()
+                .map(|(key, value)| self.global_env_note(key, value)));
+        }
+        if !line.starts_with('@') && !line.starts_with(':') {
+            if !cursor.in_packages_section {
+                return Ok(None);
+            }
+            let parsed = Config::parse(&format!("@packages\n{}", line))?;
+            return Ok(single_package(&parsed).map(|name| self.package_note(&name)));
+        }
+        let Some(name) = &cursor.package else {
+            return Ok(None);
+        };
+        // The directive on its own, under the package it belongs to
+        let parsed = Config::parse(&format!("@package {}\n{}", name, line))?;
+        match parsed.packages.get(name) {
+            Some(directive) => self.package_directive_note(name, line, directive),
+            None => Ok(None),
+        }
+    }
+
+    fn package_directive_note(
+        &self,
+        package: &str,
+        line: &str,
+        directive: &Package,
+    ) -> Result<Option<Note>> {
+        let merged = self
+            .loaded
+            .then(|| self.config.packages.get(package))
+            .flatten();
+        let explanation = merged.and_then(|_| super::explain::explain(self.config, package));
+        let winner = |field: &str| {
+            explanation
+                .as_ref()
+                .and_then(|e| e.fields.iter().find(|f| f.field == field))
+                .and_then(|f| f.file.clone())
+        };
+
+        if line.starts_with(":config ") || line.starts_with(":cfg ") {
+            let Some(entry) = directive.config.first() else {
+                return Ok(None);
+            };
+            let overridden_by = merged
+                .filter(|m| !m.config.contains(entry))
+                .and_then(|_| winner("config"));
+            return self.config_note(package, entry, overridden_by).map(Some);
+        }
+        if line.starts_with(":service ") {
+            let Some(name) = directive.service.clone() else {
+                return Ok(None);
+            };
+            let overridden_by = merged
+                .filter(|m| m.service.as_ref() != Some(&name))
+                .and_then(|_| winner("service"));
+            return Ok(Some(Note::Service {
+                package: package.to_string(),
+                enabled: self.host.services.is_enabled(&name).ok(),
+                active: self.host.services.is_active(&name).ok(),
+                name,
+                overridden_by,
+            }));
+        }
+        if line.starts_with(":env ") {
+            let Some((key, value)) = directive.env_vars.iter().next() else {
+                return Ok(None);
+            };
+            let resolved = merged
+                .and_then(|m| m.env_vars.get(key))
+                .filter(|resolved| *resolved != value)
+                .cloned();
+            let overridden_by = resolved
+                .as_ref()
+                .and_then(|_| winner(&format!("env {}", key)));
+            return Ok(Some(Note::Env {
+                package: Some(package.to_string()),
+                key: key.clone(),
+                value: value.clone(),
+                resolved,
+                overridden_by,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn package_note(&self, name: &str) -> Note {
+        let also_declared_in = match self.config.declarations.get(name) {
+            Some(declarations) if self.loaded => declarations
+                .iter()
+                .map(|d| d.file.clone())
+                .take_while(|file| file != self.label)
+                .collect(),
+            _ => Vec::new(),
+        };
+        Note::Package {
+            name: name.to_string(),
+            installed: (self.host.installed)(name),
+            also_declared_in,
+        }
+    }
+
+    fn config_note(
+        &self,
+        package: &str,
+        entry: &str,
+        overridden_by: Option<String>,
+    ) -> Result<Note> {
+        // Resolve the entry the way apply does, with this file's @dotfiles-root
+        let mut single = Config::new();
+        let mut resolved = single.defaults.package();
+        resolved.config.push(entry.to_string());
+        resolved.dotfiles_root = self
+            .own
+            .packages
+            .get(package)
+            .and_then(|p| p.dotfiles_root.clone());
+        single.packages.insert(package.to_string(), resolved);
+        let mapping = crate::core::dotfiles::get_dotfile_mappings(&single)
+            .pop()
+            .ok_or_else(|| anyhow!("Cannot resolve :config {}", entry))?;
+        let roots = self.host.roots;
+        let source = roots.source(&mapping);
+        let sync = if !source.exists() {
+            SyncState::SourceMissing
+        } else {
+            match crate::core::dotfiles::analyze_dotfiles(roots, std::slice::from_ref(&mapping), 1)?
+                .pop()
+            {
+                Some(DotfileStatus::UpToDate) => SyncState::InSync,
+                Some(DotfileStatus::Update | DotfileStatus::Merge) => SyncState::OutOfSync,
+                Some(
+                    status @ (DotfileStatus::Conflict(_) | DotfileStatus::MergeConflict { .. }),
+                ) => SyncState::Conflict(status.conflict_reason().unwrap_or_default()),
+                Some(DotfileStatus::Create) | None => SyncState::NotCreated,
+            }
+        };
+        Ok(Note::Config {
+            package: package.to_string(),
+            destination: roots.destination(&mapping),
+            source,
+            sync,
+            overridden_by,
+        })
+    }
+
+    fn global_env_note(&self, key: String, value: String) -> Note {
+        let resolved = self
+            .loaded
+            .then(|| self.config.env_vars.get(&key))
+            .flatten()
+            .filter(|resolved| **resolved != value)
+            .cloned();
+        Note::Env {
+            package: None,
+            key,
+            value,
+            resolved,
+            overridden_by: None,
+        }
+    }
+
+    fn group_note(&self, name: String) -> Note {
+        let file = Config::group_label(&name);
+        Note::Group {
+            exists: self.host.roots.owl_dir.join(&file).is_file(),
+            name,
+            file,
+        }
+    }
+}
+
+fn single_package(parsed: &Config) -> Option<String> {
+    parsed.packages.keys().next().cloned()
+}
+
+/// `path` relative to the owl root, or as given when it lies outside
+fn relative_label(owl_root: &Path, path: &Path) -> String {
+    let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+    let path = canonical(path);
+    match path.strip_prefix(canonical(owl_root)) {
+        Ok(rel) => rel.to_string_lossy().into_owned(),
+        Err(_) => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// main.owl, this host's file, or a group one of them pulled in
+fn is_loaded(config: &Config, label: &str, hostname: &str) -> bool {
+    let host_file = format!(
+        "{}/{}{}",
+        crate::internal::constants::HOSTS_DIR,
+        hostname,
+        crate::internal::constants::OWL_EXT
+    );
+    label == crate::internal::constants::MAIN_CONFIG_FILE
+        || label == host_file
+        || config
+            .group_origins
+            .iter()
+            .any(|origin| Config::group_label(&origin.group) == label)
+}
+
+/// What a note says, as shown after the line
+pub fn describe(note: &Note) -> String {
+    let yes_no = |state: Option<bool>, yes: &str, no: &str| match state {
+        Some(true) => yes.to_string(),
+        Some(false) => no.to_string(),
+        None => format!("{} unknown", yes),
+    };
+    match note {
+        Note::Package {
+            installed,
+            also_declared_in,
+            ..
+        } => {
+            let mut text = yes_no(*installed, "installed", "not installed");
+            if !also_declared_in.is_empty() {
+                text.push_str(&format!(
+                    "; also declared in {} (wins)",
+                    also_declared_in.join(", ")
+                ));
+            }
+            text
+        }
+        Note::Config {
+            destination,
+            sync,
+            overridden_by,
+            ..
+        } => {
+            let sync = match sync {
+                SyncState::InSync => "in sync".to_string(),
+                SyncState::OutOfSync => "out of sync".to_string(),
+                SyncState::NotCreated => "not created yet".to_string(),
+                SyncState::SourceMissing => "source missing".to_string(),
+                SyncState::Conflict(reason) => format!("conflict: {}", reason),
+            };
+            let destination =
+                crate::internal::environment::get().display_path(&destination.to_string_lossy());
+            match overridden_by {
+                Some(file) => format!("-> {}, {}; unused, {} wins", destination, sync, file),
+                None => format!("-> {}, {}", destination, sync),
+            }
+        }
+        Note::Service {
+            enabled,
+            active,
+            overridden_by,
+            ..
+        } => {
+            let state = format!(
+                "{}, {}",
+                yes_no(*enabled, "enabled", "disabled"),
+                yes_no(*active, "active", "inactive")
+            );
+            match overridden_by {
+                Some(file) => format!("{}; unused, {} wins", state, file),
+                None => state,
+            }
+        }
+        Note::Env {
+            key,
+            resolved,
+            overridden_by,
+            ..
+        } => match (resolved, overridden_by) {
+            (Some(value), Some(file)) => format!("overridden: {}={} ({})", key, value, file),
+            (Some(value), None) => format!("overridden: {}={}", key, value),
+            (None, _) => "in effect".to_string(),
+        },
+        Note::Group { file, exists, .. } => {
+            if *exists {
+                format!("loads {}", file)
+            } else {
+                format!("missing: {} does not exist", file)
+            }
+        }
+    }
+}
+
+/// The file with each note aligned in a column after its line
+pub fn render_text(report: &FileReport) -> String {
+    /// Lines longer than this push their note further right instead of
+    /// widening the column for every line
+    const MAX_COLUMN: usize = 48;
+    let mut out = format!("[{}]\n", report.file);
+    if !report.loaded {
+        out.push_str("  (not loaded on this host; overrides are not shown)\n");
+    }
+    let width = report
+        .lines
+        .iter()
+        .filter(|l| l.note.is_some())
+        .map(|l| l.text.trim_end().chars().count())
+        .filter(|&len| len <= MAX_COLUMN)
+        .max()
+        .unwrap_or(0);
+    let number_width = report.lines.len().to_string().len();
+    for line in &report.lines {
+        let text = line.text.trim_end();
+        match &line.note {
+            Some(note) => out.push_str(&format!(
+                "{:>nw$}  {:<width$}  # {}\n",
+                line.line,
+                text,
+                describe(note),
+                nw = number_width,
+                width = width
+            )),
+            None => {
+                let plain = format!("{:>nw$}  {}", line.line, text, nw = number_width);
+                out.push_str(plain.trim_end());
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::fs;
+
+    /// dev.owl's neovim mapping loses to main.owl's, its ripgrep mapping is out
+    /// of sync, and main.owl references a group with no file
+    fn fixture() -> (tempfile::TempDir, DotfileRoots) {
+        let dir = tempfile::tempdir().unwrap();
+        let owl = dir.path().join(".owl");
+        let home = dir.path().join("home");
+        fs::create_dir_all(owl.join("groups")).unwrap();
+        fs::create_dir_all(owl.join("dotfiles/nvim")).unwrap();
+        fs::create_dir_all(&home).unwrap();
+        fs::write(
+            owl.join("main.owl"),
+            "@group dev\n@group missing\n@env PAGER=less\n\n\
+             @package neovim\n:config nvim -> ~/.config/nvim\n:env EDITOR=nvim\n",
+        )
+        .unwrap();
+        fs::write(
+            owl.join("groups/dev.owl"),
+            "# editors\n@env PAGER=bat\n@package neovim\n:config nvim-dev -> ~/.config/nvim\n\
+             :env EDITOR=vi\n:service nvim.service\n\n@packages\nripgrep\n\n\
+             @package ripgrep\n:config rg -> ~/.ripgreprc\n",
+        )
+        .unwrap();
+        fs::write(owl.join("dotfiles/nvim/init.lua"), "-- init\n").unwrap();
+        fs::write(owl.join("dotfiles/rg"), "--smart-case\n").unwrap();
+        fs::write(home.join(".ripgreprc"), "--hidden\n").unwrap();
+        let roots = DotfileRoots {
+            source_dir: owl.join("dotfiles"),
+            backup_dir: owl.join(".state/backups"),
+            home: home.to_string_lossy().into_owned(),
+            owl_dir: owl,
+            dest_prefix: None,
+            hash_algo: crate::core::dotfiles::HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        };
+        (dir, roots)
+    }
+
+    #[derive(Default)]
+    struct FakeServices {
+        enabled: HashSet<String>,
+        queried: RefCell<Vec<String>>,
+    }
+
+    impl ServiceManager for FakeServices {
+        fn is_enabled(&self, service: &str) -> Result<bool> {
+            self.queried.borrow_mut().push(service.to_string());
+            Ok(self.enabled.contains(service))
+        }
+
+        fn is_active(&self, _service: &str) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn enable(&self, _service: &str) -> Result<()> {
+            panic!("annotating must not enable services")
+        }
+
+        fn start(&self, _service: &str) -> Result<()> {
+            panic!("annotating must not start services")
+        }
+
+        fn disable(&self, _service: &str) -> Result<()> {
+            panic!("annotating must not disable services")
+        }
+
+        fn list_enabled(&self) -> Result<Vec<crate::core::services::EnabledUnit>> {
+            panic!("annotating does not list services")
+        }
+    }
+
+    fn report(roots: &DotfileRoots, file: &str, hostname: &str) -> FileReport {
+        let config = Config::load_for_host(&roots.owl_dir, hostname).unwrap();
+        let services = FakeServices {
+            enabled: ["nvim.service".to_string()].into(),
+            ..Default::default()
+        };
+        let installed = |name: &str| Some(name == "neovim");
+        let host = Host {
+            hostname,
+            roots,
+            installed: &installed,
+            services: &services,
+        };
+        annotate(&config, &roots.owl_dir.join(file), &host).unwrap()
+    }
+
+    fn note(report: &FileReport, line: usize) -> &Note {
+        report.lines[line - 1].note.as_ref().unwrap()
+    }
+
+    #[test]
+    fn test_group_file_overrides_and_sync() {
+        let (_dir, roots) = fixture();
+        let report = report(&roots, "groups/dev.owl", "box");
+        assert_eq!(report.file, "groups/dev.owl");
+        assert!(report.loaded);
+        assert!(report.lines[0].note.is_none());
+
+        assert_eq!(
+            note(&report, 2),
+            &Note::Env {
+                package: None,
+                key: "PAGER".to_string(),
+                value: "bat".to_string(),
+                resolved: Some("less".to_string()),
+                overridden_by: None,
+            }
+        );
+        assert_eq!(
+            note(&report, 3),
+            &Note::Package {
+                name: "neovim".to_string(),
+                installed: Some(true),
+                also_declared_in: vec!["main.owl".to_string()],
+            }
+        );
+        let Note::Config {
+            sync,
+            overridden_by,
+            destination,
+            ..
+        } = note(&report, 4)
+        else {
+            panic!("expected a config note");
+        };
+        assert_eq!(sync, &SyncState::SourceMissing);
+        assert_eq!(overridden_by.as_deref(), Some("main.owl"));
+        assert_eq!(destination, &Path::new(&roots.home).join(".config/nvim"));
+        let Note::Env {
+            resolved,
+            overridden_by,
+            ..
+        } = note(&report, 5)
+        else {
+            panic!("expected an env note");
+        };
+        assert_eq!(resolved.as_deref(), Some("nvim"));
+        assert_eq!(overridden_by.as_deref(), Some("main.owl"));
+        // Only the group sets the service, so it is in effect
+        assert_eq!(
+            note(&report, 6),
+            &Note::Service {
+                package: "neovim".to_string(),
+                name: "nvim.service".to_string(),
+                enabled: Some(true),
+                active: Some(false),
+                overridden_by: None,
+            }
+        );
+        assert!(matches!(
+            note(&report, 9),
+            Note::Package { name, installed: Some(false), also_declared_in }
+                if name == "ripgrep" && also_declared_in.is_empty()
+        ));
+        let Note::Config {
+            sync,
+            overridden_by,
+            ..
+        } = note(&report, 12)
+        else {
+            panic!("expected a config note");
+        };
+        assert_eq!(sync, &SyncState::OutOfSync);
+        assert_eq!(overridden_by, &None);
+    }
+
+    #[test]
+    fn test_main_file_groups_and_winners() {
+        let (_dir, roots) = fixture();
+        let report = report(&roots, "main.owl", "box");
+        assert_eq!(
+            note(&report, 1),
+            &Note::Group {
+                name: "dev".to_string(),
+                file: "groups/dev.owl".to_string(),
+                exists: true,
+            }
+        );
+        assert_eq!(
+            note(&report, 2),
+            &Note::Group {
+                name: "missing".to_string(),
+                file: "groups/missing.owl".to_string(),
+                exists: false,
+            }
+        );
+        assert!(report.lines[3].note.is_none());
+        let Note::Config {
+            sync,
+            overridden_by,
+            ..
+        } = note(&report, 6)
+        else {
+            panic!("expected a config note");
+        };
+        assert_eq!(sync, &SyncState::NotCreated);
+        assert_eq!(overridden_by, &None);
+        assert!(matches!(note(&report, 7), Note::Env { resolved: None, .. }));
+    }
+
+    #[test]
+    fn test_unloaded_file_reports_no_overrides() {
+        let (_dir, roots) = fixture();
+        let config = Config::load_for_host(&roots.owl_dir, "box").unwrap();
+        let mut config_without_groups = config;
+        config_without_groups.group_origins.clear();
+        let services = FakeServices::default();
+        let installed = |_: &str| None;
+        let host = Host {
+            hostname: "box",
+            roots: &roots,
+            installed: &installed,
+            services: &services,
+        };
+        let report = annotate(
+            &config_without_groups,
+            &roots.owl_dir.join("groups/dev.owl"),
+            &host,
+        )
+        .unwrap();
+        assert!(!report.loaded);
+        assert!(matches!(
+            note(&report, 3),
+            Note::Package { installed: None, also_declared_in, .. } if also_declared_in.is_empty()
+        ));
+        assert!(matches!(
+            note(&report, 4),
+            Note::Config {
+                overridden_by: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_render_text_aligns_notes() {
+        let (_dir, roots) = fixture();
+        let text = render_text(&report(&roots, "main.owl", "box"));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "[main.owl]");
+        assert_eq!(
+            lines[1],
+            "1  @group dev                      # loads groups/dev.owl"
+        );
+        assert_eq!(
+            lines[2],
+            "2  @group missing                  # missing: groups/missing.owl does not exist"
+        );
+        assert_eq!(lines[4], "4");
+        assert!(lines[6].ends_with(", not created yet"), "{}", text);
+        assert_eq!(lines[7], "7  :env EDITOR=nvim                # in effect");
+    }
+
+    #[test]
+    fn test_json_report() {
+        let (_dir, roots) = fixture();
+        let report = report(&roots, "main.owl", "box");
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["file"], "main.owl");
+        assert_eq!(value["lines"][1]["note"]["kind"], "group");
+        assert_eq!(value["lines"][1]["note"]["exists"], false);
+        assert_eq!(value["lines"][5]["note"]["sync"], "not-created");
+        assert!(value["lines"][3].get("note").is_none());
+        assert!(value.get("journal").is_none());
+    }
+
+    #[test]
+    fn test_json_report_carries_journal_when_traced() {
+        let (_dir, roots) = fixture();
+        let config = Config::load_for_host_traced(&roots.owl_dir, "box").unwrap();
+        let services = FakeServices::default();
+        let installed = |_: &str| None;
+        let host = Host {
+            hostname: "box",
+            roots: &roots,
+            installed: &installed,
+            services: &services,
+        };
+        let report = annotate(&config, &roots.owl_dir.join("groups/dev.owl"), &host).unwrap();
+        assert_eq!(
+            report.journal.keys().collect::<Vec<_>>(),
+            vec!["neovim", "ripgrep"]
+        );
+        let value = serde_json::to_value(&report).unwrap();
+        let config_entry = &value["journal"]["neovim"][4];
+        assert_eq!(config_entry["file"], "groups/dev.owl");
+        assert_eq!(config_entry["directive"], "config");
+        assert_eq!(config_entry["outcome"], "overridden");
+        assert_eq!(config_entry["by"], "main.owl");
+    }
+}
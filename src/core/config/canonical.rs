@@ -0,0 +1,205 @@
+//! Canonical JSON dump of a parsed config file
+//!
+//! The dump is the reference form of how the `.owl` dialect reads a file: every
+//! format-1 field is always present, maps are sorted by key and lists keep file
+//! order. Fields added since only appear when set, so existing dumps stay valid.
+//! `tests/corpus/` pairs config files with their dumps; see the README there for
+//! the compatibility policy. `owl config-check --dump-canonical <file>` prints the
+//! same output so parser discrepancies can be reported with it.
+
+use super::{Config, Package};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+/// Version of the dump layout, bumped when fields are added or renamed
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Canonical JSON for a config parsed from a single file's content
+///
+/// Filesystem-dependent data (option sources, the resolved `@dotfiles-root`) is
+/// left out, so the dump depends only on the text.
+pub fn to_canonical_json(config: &Config) -> String {
+    let packages: BTreeMap<&str, Value> = config
+        .packages
+        .iter()
+        .map(|(name, pkg)| (name.as_str(), package_value(pkg)))
+        .collect();
+    let options: BTreeMap<&str, &str> = config
+        .options
+        .iter()
+        .map(|(key, opt)| (key.as_str(), opt.value.as_str()))
+        .collect();
+    let mut value = json!({
+        "format": FORMAT_VERSION,
+        "packages": packages,
+        "groups": config.groups,
+        "env": sorted(&config.env_vars),
+        "arch_aur_suffixes": sorted(&config.arch_aur_suffixes),
+        "untracked": config.untracked,
+        "untracked_reset": config.untracked_reset,
+        "options": options,
+        "dotfiles_root": config.dotfiles_root,
+        "warnings": config.warnings,
+    });
+    if let Some(keep) = config.backups_keep {
+        value["backups_keep"] = json!(keep);
+    }
+    if !config.absent.is_empty() {
+        value["absent"] = json!(config.absent);
+    }
+    if !config.system_services.is_empty() {
+        value["system_services"] = json!(config.system_services);
+    }
+    // serde_json's map is a BTreeMap, so object keys come out sorted
+    serde_json::to_string_pretty(&value).expect("JSON values always serialize") + "\n"
+}
+
+fn package_value(pkg: &Package) -> Value {
+    let mut value = format1_package_value(pkg);
+    if !pkg.after.is_empty() {
+        value["after"] = json!(pkg.after);
+    }
+    if pkg.aur {
+        value["aur"] = json!(true);
+    }
+    if pkg.no_upgrade {
+        value["no_upgrade"] = json!(true);
+    }
+    if !pkg.absent.is_empty() {
+        value["absent"] = json!(pkg.absent);
+    }
+    if !pkg.requires.is_empty() {
+        value["requires"] = json!(pkg.requires);
+    }
+    if let Some(required_by) = &pkg.required_by {
+        value["required_by"] = json!(required_by.parent);
+    }
+    value
+}
+
+fn format1_package_value(pkg: &Package) -> Value {
+    let env_shells: BTreeMap<&str, &str> = pkg
+        .env_shells
+        .iter()
+        .map(|(key, shell)| (key.as_str(), shell.as_str()))
+        .collect();
+    let mut default_env_keys: Vec<&String> = pkg.default_env_keys.iter().collect();
+    default_env_keys.sort();
+    json!({
+        "config": pkg.config,
+        "service": pkg.service,
+        "env": sorted(&pkg.env_vars),
+        "env_shells": env_shells,
+        "env_from_defaults": default_env_keys,
+        "min_version": pkg.min_version.as_ref().map(|min| json!({
+            "version": min.version,
+            "strict": min.strict,
+        })),
+    })
+}
+
+fn sorted(map: &std::collections::HashMap<String, String>) -> BTreeMap<&str, &str> {
+    map.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    /// Set to write dumps for corpus files that have none yet (existing dumps are never touched)
+    const UPDATE_VAR: &str = "OWL_UPDATE_CORPUS";
+
+    fn corpus_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus")
+    }
+
+    #[test]
+    fn test_dump_is_sorted_and_complete() {
+        let config = Config::parse(
+            "@env ZED=1\n@env ALPHA=2\n@option b=2\n@option a=1\n@packages\nzsh\nbash\n",
+        )
+        .unwrap();
+        let dump = to_canonical_json(&config);
+        let value: Value = serde_json::from_str(&dump).unwrap();
+        assert_eq!(value["format"], FORMAT_VERSION);
+        assert_eq!(value["packages"]["bash"]["service"], Value::Null);
+        assert_eq!(value["packages"]["bash"]["min_version"], Value::Null);
+        assert!(dump.find("\"ALPHA\"").unwrap() < dump.find("\"ZED\"").unwrap());
+        assert!(dump.find("\"bash\"").unwrap() < dump.find("\"zsh\"").unwrap());
+        assert!(dump.find("\"a\": \"1\"").unwrap() < dump.find("\"b\": \"2\"").unwrap());
+        assert!(dump.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_dump_is_independent_of_declaration_order() {
+        let a = Config::parse("@package b\n:env [shell=fish] X=1\n@package a\n:env Y=2\n").unwrap();
+        let b = Config::parse("@package a\n:env Y=2\n@package b\n:env [shell=fish] X=1\n").unwrap();
+        assert_eq!(to_canonical_json(&a), to_canonical_json(&b));
+        assert!(to_canonical_json(&a).contains("\"X\": \"fish\""));
+    }
+
+    #[test]
+    fn test_dump_leaves_out_option_sources() {
+        let mut config = Config::parse("@option snapshot_cmd=auto\n").unwrap();
+        config.set_option_source("/home/me/.owl/main.owl");
+        assert!(!to_canonical_json(&config).contains("/home/me"));
+    }
+
+    /// Every corpus file must parse to exactly its stored dump
+    #[test]
+    fn test_corpus_matches_canonical_dumps() {
+        let update = std::env::var_os(UPDATE_VAR).is_some();
+        let mut entries: Vec<PathBuf> = std::fs::read_dir(corpus_dir())
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "owl"))
+            .collect();
+        entries.sort();
+        assert!(!entries.is_empty(), "corpus is empty");
+
+        let mut failures = Vec::new();
+        for owl in &entries {
+            let expected_path = owl.with_extension("json");
+            let content = std::fs::read_to_string(owl).unwrap();
+            let actual = match Config::parse(&content) {
+                Ok(config) => to_canonical_json(&config),
+                Err(e) => {
+                    failures.push(format!("{}: parse error: {}", owl.display(), e));
+                    continue;
+                }
+            };
+            match std::fs::read_to_string(&expected_path) {
+                Ok(expected) if expected == actual => {}
+                Ok(expected) => failures.push(format!(
+                    "{} no longer parses to its stored dump:\n{}",
+                    owl.display(),
+                    crate::core::diff::unified_diff(&expected, &actual, "stored", "parsed", 3)
+                )),
+                Err(_) if update => std::fs::write(&expected_path, &actual).unwrap(),
+                Err(_) => failures.push(format!(
+                    "{} has no dump; run with {}=1 to create it",
+                    owl.display(),
+                    UPDATE_VAR
+                )),
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    /// A dump without its config file would silently stop being checked
+    #[test]
+    fn test_corpus_dumps_have_config_files() {
+        for entry in std::fs::read_dir(corpus_dir()).unwrap().flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                assert!(
+                    path.with_extension("owl").exists(),
+                    "{} has no matching .owl file",
+                    path.display()
+                );
+            }
+        }
+    }
+}
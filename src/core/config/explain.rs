@@ -0,0 +1,363 @@
+//! Which file provided each field of a merged package (`owl explain`)
+//!
+//! The loader records every file's definition of a package before merging
+//! (`Config::declarations`). Comparing those with the merged package shows the
+//! file each field came from and the values that lost to higher precedence
+//! (`@option package_merge`). Packages declared by another block's `:requires`
+//! also say which block and line required them.
+
+use super::loader::Declaration;
+use super::{Config, Package};
+
+/// Reads one field of a package as text; `None` when unset
+type ValueOf = Box<dyn Fn(&Package) -> Option<String>>;
+
+/// One field of the merged package
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSource {
+    /// `config`, `service`, `env EDITOR`, ...
+    pub field: String,
+    /// Merged value; `None` when unset
+    pub value: Option<String>,
+    /// Highest-precedence file that set the merged value
+    pub file: Option<String>,
+    /// `(file, value)` set by other files and not used
+    pub overridden: Vec<(String, String)>,
+}
+
+/// A package's definitions per file and where each merged field came from
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub package: String,
+    /// Highest precedence first
+    pub declarations: Vec<Declaration>,
+    /// `devtools (groups/dev.owl:12)` for each block whose `:requires` declared it
+    pub required_by: Vec<String>,
+    pub fields: Vec<FieldSource>,
+}
+
+/// Explain how `name` was merged; `None` if no loaded file declares it
+pub fn explain(config: &Config, name: &str) -> Option<Explanation> {
+    let merged = config.packages.get(name)?;
+    let declarations = config.declarations.get(name).cloned().unwrap_or_default();
+
+    let mut fields: Vec<(String, ValueOf)> = vec![
+        (
+            "config".to_string(),
+            Box::new(|p: &Package| (!p.config.is_empty()).then(|| p.config.join(", "))),
+        ),
+        (
+            "service".to_string(),
+            Box::new(|p: &Package| p.service.clone()),
+        ),
+        (
+            "min-version".to_string(),
+            Box::new(|p: &Package| p.min_version.as_ref().map(|min| min.directive())),
+        ),
+        (
+            "after".to_string(),
+            Box::new(|p: &Package| (!p.after.is_empty()).then(|| p.after.join(" "))),
+        ),
+        (
+            "requires".to_string(),
+            Box::new(|p: &Package| (!p.requires.is_empty()).then(|| p.requires.join(" "))),
+        ),
+        (
+            "aur".to_string(),
+            Box::new(|p: &Package| p.aur.then(|| "yes".to_string())),
+        ),
+        (
+            "no-upgrade".to_string(),
+            Box::new(|p: &Package| p.no_upgrade.then(|| "yes".to_string())),
+        ),
+    ];
+    let mut env_keys: Vec<&String> = declarations
+        .iter()
+        .flat_map(|d| d.package.env_vars.keys())
+        .chain(merged.env_vars.keys())
+        .collect();
+    env_keys.sort();
+    env_keys.dedup();
+    for key in env_keys {
+        let key = key.clone();
+        fields.push((
+            format!("env {}", key),
+            Box::new(move |p: &Package| env_value(p, &key)),
+        ));
+    }
+
+    let fields = fields
+        .into_iter()
+        .map(|(field, value_of)| field_source(field, &*value_of, merged, &declarations))
+        .collect();
+    let required_by = declarations
+        .iter()
+        .filter_map(|d| {
+            let required_by = d.package.required_by.as_ref()?;
+            Some(match required_by.line {
+                Some(line) => format!("{} ({}:{})", required_by.parent, d.file, line),
+                None => format!("{} ({})", required_by.parent, d.file),
+            })
+        })
+        .collect();
+    Some(Explanation {
+        package: name.to_string(),
+        declarations,
+        required_by,
+        fields,
+    })
+}
+
+/// Each file's directives, then every field with its value and source
+pub fn render_text(explanation: &Explanation) -> String {
+    let mut out = format!("[{}]\n", explanation.package);
+    for parent in &explanation.required_by {
+        out.push_str(&format!("  required by {}\n", parent));
+    }
+    for declaration in &explanation.declarations {
+        out.push_str(&format!("  {}\n", declaration.file));
+        let directives = super::validator::effective_directives(&declaration.package);
+        if directives.is_empty() {
+            out.push_str("    (declared only)\n");
+        }
+        for (directive, from_defaults) in directives {
+            let note = if from_defaults {
+                " (from @defaults)"
+            } else {
+                ""
+            };
+            out.push_str(&format!("    {}{}\n", directive, note));
+        }
+    }
+    out.push_str("\nResolved:\n");
+    let width = explanation
+        .fields
+        .iter()
+        .map(|f| f.field.len())
+        .max()
+        .unwrap_or(0);
+    for field in &explanation.fields {
+        if field.value.is_none() && field.overridden.is_empty() {
+            continue;
+        }
+        let value = field.value.as_deref().unwrap_or("-");
+        match &field.file {
+            Some(file) => out.push_str(&format!(
+                "  {:<width$}  {}  ({})\n",
+                field.field,
+                value,
+                file,
+                width = width
+            )),
+            None => out.push_str(&format!(
+                "  {:<width$}  {}\n",
+                field.field,
+                value,
+                width = width
+            )),
+        }
+        for (file, other) in &field.overridden {
+            out.push_str(&format!(
+                "  {:<width$}    overridden: {}  ({})\n",
+                "",
+                other,
+                file,
+                width = width
+            ));
+        }
+    }
+    out
+}
+
+pub(super) fn env_value(package: &Package, key: &str) -> Option<String> {
+    let value = package.env_vars.get(key)?;
+    Some(match package.env_shells.get(key) {
+        Some(shell) => format!("{} [shell={}]", value, shell.as_str()),
+        None => value.clone(),
+    })
+}
+
+fn field_source(
+    field: String,
+    value_of: &dyn Fn(&Package) -> Option<String>,
+    merged: &Package,
+    declarations: &[Declaration],
+) -> FieldSource {
+    let value = value_of(merged);
+    let file = value.as_ref().and_then(|value| {
+        declarations
+            .iter()
+            .find(|d| value_of(&d.package).as_ref() == Some(value))
+            .map(|d| d.file.clone())
+    });
+    let overridden = declarations
+        .iter()
+        .filter_map(|d| {
+            let other = value_of(&d.package)?;
+            (Some(&other) != value.as_ref()).then(|| (d.file.clone(), other))
+        })
+        .collect();
+    FieldSource {
+        field,
+        value,
+        file,
+        overridden,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// main.owl and the host file both declare neovim; a group declares it too
+    fn fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("hosts")).unwrap();
+        fs::create_dir_all(root.join("groups")).unwrap();
+        fs::write(
+            root.join("main.owl"),
+            "@group dev\n@package neovim\n:config nvim\n:env EDITOR=nvim\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("hosts/box.owl"),
+            "@package neovim\n:config nvim-box -> ~/.config/nvim\n:env EDITOR=vi\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("groups/dev.owl"),
+            "@package neovim\n:service nvim-server.service\n:env [shell=fish] VISUAL=nvim\n\
+             @package ripgrep\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    fn field<'a>(explanation: &'a Explanation, name: &str) -> &'a FieldSource {
+        explanation.fields.iter().find(|f| f.field == name).unwrap()
+    }
+
+    #[test]
+    fn test_explain_across_files() {
+        let dir = fixture();
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        let explanation = explain(&config, "neovim").unwrap();
+
+        let files: Vec<&str> = explanation
+            .declarations
+            .iter()
+            .map(|d| d.file.as_str())
+            .collect();
+        assert_eq!(files, vec!["main.owl", "hosts/box.owl", "groups/dev.owl"]);
+
+        let config_field = field(&explanation, "config");
+        assert_eq!(config_field.value.as_deref(), Some("nvim"));
+        assert_eq!(config_field.file.as_deref(), Some("main.owl"));
+        assert_eq!(
+            config_field.overridden,
+            vec![(
+                "hosts/box.owl".to_string(),
+                "nvim-box -> ~/.config/nvim".to_string()
+            )]
+        );
+
+        let editor = field(&explanation, "env EDITOR");
+        assert_eq!(editor.value.as_deref(), Some("nvim"));
+        assert_eq!(editor.file.as_deref(), Some("main.owl"));
+        assert_eq!(
+            editor.overridden,
+            vec![("hosts/box.owl".to_string(), "vi".to_string())]
+        );
+
+        // Fields only the group sets still reach the merged package
+        let service = field(&explanation, "service");
+        assert_eq!(service.value.as_deref(), Some("nvim-server.service"));
+        assert_eq!(service.file.as_deref(), Some("groups/dev.owl"));
+        assert!(service.overridden.is_empty());
+        let visual = field(&explanation, "env VISUAL");
+        assert_eq!(visual.value.as_deref(), Some("nvim [shell=fish]"));
+        assert_eq!(visual.file.as_deref(), Some("groups/dev.owl"));
+    }
+
+    #[test]
+    fn test_explain_replace_merge() {
+        let dir = fixture();
+        let main = dir.path().join("main.owl");
+        let body = fs::read_to_string(&main).unwrap();
+        fs::write(&main, format!("@option package_merge=replace\n{}", body)).unwrap();
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        let explanation = explain(&config, "neovim").unwrap();
+
+        let service = field(&explanation, "service");
+        assert_eq!(service.value, None);
+        assert_eq!(service.file, None);
+        assert_eq!(
+            service.overridden,
+            vec![(
+                "groups/dev.owl".to_string(),
+                "nvim-server.service".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_render_text() {
+        let dir = fixture();
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        let text = render_text(&explain(&config, "neovim").unwrap());
+        assert!(text.starts_with("[neovim]\n  main.owl\n    :config nvim\n"));
+        assert!(
+            text.contains("\n  service      nvim-server.service  (groups/dev.owl)\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("\n  config       nvim  (main.owl)\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains(concat!(
+                "\n  env EDITOR   nvim  (main.owl)\n",
+                "                 overridden: vi  (hosts/box.owl)\n"
+            )),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_explain_single_declaration() {
+        let dir = fixture();
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        let explanation = explain(&config, "ripgrep").unwrap();
+        assert_eq!(explanation.declarations.len(), 1);
+        assert!(explanation.fields.iter().all(|f| f.value.is_none()));
+        assert!(explain(&config, "missing").is_none());
+    }
+
+    #[test]
+    fn test_required_package_names_its_parent() {
+        let dir = fixture();
+        fs::write(
+            dir.path().join("groups/dev.owl"),
+            "@package neovim\n\n@package devtools\n:requires gdb strace\n",
+        )
+        .unwrap();
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        let explanation = explain(&config, "gdb").unwrap();
+        assert_eq!(explanation.required_by, vec!["devtools (groups/dev.owl:4)"]);
+        assert!(
+            render_text(&explanation).starts_with(
+                "[gdb]\n  required by devtools (groups/dev.owl:4)\n  groups/dev.owl\n"
+            ),
+            "{}",
+            render_text(&explanation)
+        );
+        let requires = field(&explain(&config, "devtools").unwrap(), "requires").clone();
+        assert_eq!(requires.value.as_deref(), Some("gdb strace"));
+        assert_eq!(requires.file.as_deref(), Some("groups/dev.owl"));
+    }
+}
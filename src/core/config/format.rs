@@ -0,0 +1,469 @@
+//! Config file formats: the `.owl` directives and TOML
+//!
+//! A TOML file describes the same things as a `.owl` file, as tables:
+//!
+//! ```toml
+//! groups = ["dev"]
+//!
+//! [env]
+//! EDITOR = "nvim"
+//!
+//! [packages.git]
+//! config = ["git -> ~/.config/git"]
+//! env = { GIT_PAGER = "delta" }
+//!
+//! [packages.yay]
+//! aur = true
+//! ```
+//!
+//! Each entry is turned into the directive it stands for and parsed by the
+//! `.owl` parser, so both formats build the same `Config` with the same checks.
+//! An owl root is in one format: `main.owl`, or `main.toml` when there is no
+//! `main.owl`, decides which extension the host and group files have, unless
+//! `--config-format` says otherwise.
+
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::Config;
+
+/// The format `--config-format` forces on every owl root
+static FORCED: Mutex<Option<ConfigFormat>> = Mutex::new(None);
+
+/// Read every owl root in `format` for the rest of the run (`--config-format`)
+pub fn set_format(format: Option<ConfigFormat>) {
+    *FORCED.lock().unwrap_or_else(|e| e.into_inner()) = format;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ConfigFormat {
+    #[default]
+    Owl,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// The format of the files under `owl_root`
+    pub fn of_root(owl_root: &Path) -> Self {
+        if let Some(format) = *FORCED.lock().unwrap_or_else(|e| e.into_inner()) {
+            return format;
+        }
+        let main = |format: Self| owl_root.join(format.main_file()).exists();
+        if !main(Self::Owl) && main(Self::Toml) {
+            Self::Toml
+        } else {
+            Self::Owl
+        }
+    }
+
+    /// The format of `path`, by its extension
+    pub fn of_file(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::Toml,
+            _ => Self::Owl,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Owl => crate::internal::constants::OWL_EXT,
+            Self::Toml => ".toml",
+        }
+    }
+
+    /// `main.owl` or `main.toml`, relative to the owl root
+    pub fn main_file(self) -> String {
+        match self {
+            Self::Owl => crate::internal::constants::MAIN_CONFIG_FILE.to_string(),
+            Self::Toml => format!("main{}", self.extension()),
+        }
+    }
+
+    /// `hosts/<hostname>.owl`, relative to the owl root
+    pub fn host_file(self, hostname: &str) -> String {
+        format!(
+            "{}/{}{}",
+            crate::internal::constants::HOSTS_DIR,
+            hostname,
+            self.extension()
+        )
+    }
+
+    /// `groups/<name>.owl`, relative to the owl root
+    pub fn group_label(self, name: &str) -> String {
+        format!(
+            "{}/{}{}",
+            crate::internal::constants::GROUPS_DIR,
+            name,
+            self.extension()
+        )
+    }
+
+    /// File that defines `@group <name>`
+    pub fn group_file(self, groups_path: &Path, name: &str) -> PathBuf {
+        groups_path.join(format!("{}{}", name, self.extension()))
+    }
+}
+
+impl Config {
+    /// Parse a TOML config; see the module documentation for its layout
+    pub fn parse_toml(content: &str) -> Result<Self> {
+        Self::parse_toml_with(content, super::parser::strict())
+    }
+
+    /// Parse a TOML config; with `strict`, or `strict = true` in it, an
+    /// unknown key is an error instead of a warning
+    pub fn parse_toml_with(content: &str, strict: bool) -> Result<Self> {
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e: toml::de::Error| anyhow!("Invalid TOML: {}", e.message()))?;
+        let mut directives = Directives::default();
+        for (key, value) in &table {
+            directives.top_level(key, value)?;
+        }
+        let strict = strict || directives.strict;
+        if let Some(key) = directives.unknown.first()
+            && strict
+        {
+            return Err(anyhow!(
+                "Unknown key '{}' (unknown keys are errors under --strict and strict = true)",
+                key
+            ));
+        }
+        let mut config = Self::parse_with(&directives.lines.join("\n"), strict)?;
+        // Line numbers of the generated directives mean nothing in the TOML file
+        for required_by in config
+            .packages
+            .values_mut()
+            .filter_map(|pkg| pkg.required_by.as_mut())
+        {
+            required_by.line = None;
+        }
+        config.warnings.extend(
+            directives
+                .unknown
+                .iter()
+                .map(|key| format!("Unknown key '{}' ignored", key)),
+        );
+        Ok(config)
+    }
+}
+
+/// `.owl` lines equivalent to a TOML document
+#[derive(Default)]
+struct Directives {
+    lines: Vec<String>,
+    /// Dotted paths of keys that mean nothing
+    unknown: Vec<String>,
+    strict: bool,
+}
+
+impl Directives {
+    fn top_level(&mut self, key: &str, value: &toml::Value) -> Result<()> {
+        match key {
+            "groups" => {
+                for group in strings(key, value)? {
+                    self.push(format!("@group {}", group));
+                }
+            }
+            "env" => {
+                for (name, value) in table(key, value)? {
+                    let value = scalar(&format!("env.{}", name), value)?;
+                    self.push(format!("@env {}={}", name, value));
+                }
+            }
+            "options" => {
+                for (name, value) in table(key, value)? {
+                    let value = scalar(&format!("options.{}", name), value)?;
+                    self.push(format!("@option {}={}", name, value));
+                }
+            }
+            "untracked" => self.push(format!("@untracked {}", strings(key, value)?.join(" "))),
+            "untracked_reset" => {
+                if boolean(key, value)? {
+                    self.push("@untracked-reset".to_string());
+                }
+            }
+            "absent" => {
+                for path in strings(key, value)? {
+                    self.push(format!("@absent {}", path));
+                }
+            }
+            "backups_keep" => self.push(format!("@backups-keep {}", scalar(key, value)?)),
+            "dotfiles_root" => self.push(format!("@dotfiles-root {}", string(key, value)?)),
+            "strict" => self.strict = boolean(key, value)?,
+            "packages" => {
+                for (name, value) in table(key, value)? {
+                    self.package(name, value)?;
+                }
+            }
+            _ => self.unknown.push(key.to_string()),
+        }
+        Ok(())
+    }
+
+    fn package(&mut self, name: &str, value: &toml::Value) -> Result<()> {
+        let path = format!("packages.{}", name);
+        let fields = table(&path, value)?;
+        let flag = |key: &str| match fields.get(key) {
+            Some(value) => boolean(&format!("{}.{}", path, key), value),
+            None => Ok(false),
+        };
+        let options: Vec<&str> = [("aur", flag("aur")?), ("no-upgrade", flag("no_upgrade")?)]
+            .into_iter()
+            .filter_map(|(option, set)| set.then_some(option))
+            .collect();
+        if options.is_empty() {
+            self.push(format!("@package {}", name));
+        } else {
+            self.push(format!("@package {} [{}]", name, options.join(", ")));
+        }
+        for (key, value) in fields {
+            let path = format!("{}.{}", path, key);
+            match key.as_str() {
+                // A single mapping may be written without the list
+                "config" => match value {
+                    toml::Value::String(_) => {
+                        self.push(format!(":config {}", string(&path, value)?))
+                    }
+                    _ => {
+                        for mapping in strings(&path, value)? {
+                            self.push(format!(":config {}", mapping));
+                        }
+                    }
+                },
+                // Only the _system-services block takes more than one
+                "service" => match value {
+                    toml::Value::Array(_) => {
+                        for service in strings(&path, value)? {
+                            self.push(format!(":service {}", service));
+                        }
+                    }
+                    _ => self.push(format!(":service {}", string(&path, value)?)),
+                },
+                "env" => {
+                    for (name, value) in table(&path, value)? {
+                        let value = scalar(&format!("{}.{}", path, name), value)?;
+                        self.push(format!(":env {}={}", name, value));
+                    }
+                }
+                "after" => self.push(format!(":after {}", strings(&path, value)?.join(" "))),
+                "requires" => self.push(format!(":requires {}", strings(&path, value)?.join(" "))),
+                "absent" => {
+                    for absent in strings(&path, value)? {
+                        self.push(format!(":absent {}", absent));
+                    }
+                }
+                "min_version" => self.push(format!(":min-version {}", string(&path, value)?)),
+                "aur" | "no_upgrade" => {}
+                _ => self.unknown.push(path),
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, line: String) {
+        self.lines.push(line);
+    }
+}
+
+fn table<'a>(path: &str, value: &'a toml::Value) -> Result<&'a toml::Table> {
+    value
+        .as_table()
+        .ok_or_else(|| anyhow!("'{}' must be a table", path))
+}
+
+fn boolean(path: &str, value: &toml::Value) -> Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| anyhow!("'{}' must be true or false", path))
+}
+
+/// A string on one line, since each becomes one directive
+fn string<'a>(path: &str, value: &'a toml::Value) -> Result<&'a str> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| anyhow!("'{}' must be a string", path))?;
+    if text.contains(['\n', '\r']) {
+        return Err(anyhow!("'{}' must be on one line", path));
+    }
+    Ok(text)
+}
+
+fn strings<'a>(path: &str, value: &'a toml::Value) -> Result<Vec<&'a str>> {
+    value
+        .as_array()
+        .ok_or_else(|| anyhow!("'{}' must be a list of strings", path))?
+        .iter()
+        .map(|item| string(path, item))
+        .collect()
+}
+
+/// Strings, numbers and booleans, written as in a `.owl` file
+fn scalar(path: &str, value: &toml::Value) -> Result<String> {
+    match value {
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::String(_) => string(path, value).map(str::to_string),
+        _ => Err(anyhow!("'{}' must be a string, number or boolean", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::canonical::to_canonical_json;
+
+    const OWL: &str = "\
+@group dev
+@env EDITOR=nvim
+@env GITHUB_TOKEN <file:~/.secrets/github-token>
+@option package_merge=replace
+@untracked base-devel linux-firmware
+@absent ~/.xinitrc
+@backups-keep 3
+
+@package git
+:config git -> ~/.config/git
+:config gitignore -> ~/.gitignore
+:env GIT_PAGER=delta
+
+@package openssh
+:service sshd
+:min-version 9.0 [strict]
+
+@package pgbouncer
+:after openssh git
+:requires pgbouncer-docs
+
+@package yay [aur]
+@package ripgrep
+
+@package _system-services
+:service local-backup
+:service fstrim.timer
+";
+
+    const TOML: &str = r#"
+groups = ["dev"]
+untracked = ["base-devel", "linux-firmware"]
+absent = ["~/.xinitrc"]
+backups_keep = 3
+
+[env]
+EDITOR = "nvim"
+GITHUB_TOKEN = "<file:~/.secrets/github-token>"
+
+[options]
+package_merge = "replace"
+
+[packages.git]
+config = ["git -> ~/.config/git", "gitignore -> ~/.gitignore"]
+env = { GIT_PAGER = "delta" }
+
+[packages.openssh]
+service = "sshd"
+min_version = "9.0 [strict]"
+
+[packages.pgbouncer]
+after = ["openssh", "git"]
+requires = ["pgbouncer-docs"]
+
+[packages.yay]
+aur = true
+
+[packages.ripgrep]
+
+[packages._system-services]
+service = ["local-backup", "fstrim.timer"]
+"#;
+
+    #[test]
+    fn test_toml_matches_owl() {
+        let owl = Config::parse_with(OWL, false).unwrap();
+        let toml = Config::parse_toml_with(TOML, false).unwrap();
+        assert_eq!(to_canonical_json(&toml), to_canonical_json(&owl));
+        assert!(toml.warnings.is_empty(), "{:?}", toml.warnings);
+    }
+
+    #[test]
+    fn test_unknown_keys_warn_or_fail_under_strict() {
+        let toml = "servcie = 1\n[packages.git]\nservcie = \"x\"\n";
+        let config = Config::parse_toml_with(toml, false).unwrap();
+        assert_eq!(
+            config.warnings,
+            [
+                "Unknown key 'packages.git.servcie' ignored",
+                "Unknown key 'servcie' ignored"
+            ]
+        );
+        let err = Config::parse_toml_with(toml, true).unwrap_err();
+        assert!(
+            err.to_string().contains("'packages.git.servcie'"),
+            "{}",
+            err
+        );
+        let err = Config::parse_toml_with(&format!("strict = true\n{}", toml), false).unwrap_err();
+        assert!(err.to_string().contains("Unknown key"), "{}", err);
+    }
+
+    #[test]
+    fn test_invalid_toml_values() {
+        for (toml, expected) in [
+            ("groups = \"dev\"", "'groups' must be a list of strings"),
+            (
+                "[packages.git]\nservice = 1",
+                "'packages.git.service' must be a string",
+            ),
+            ("[packages.git]\naur = \"yes\"", "must be true or false"),
+            ("[env]\nX = \"a\\nb\"", "'env.X' must be on one line"),
+            ("packages = 1", "'packages' must be a table"),
+            ("[packages.\"bad name\"]", "bad name"),
+            ("[packages.git", "Invalid TOML"),
+        ] {
+            let err = Config::parse_toml_with(toml, false)
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains(expected), "{}: {}", toml, err);
+        }
+    }
+
+    #[test]
+    fn test_loader_reads_a_toml_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("groups")).unwrap();
+        std::fs::create_dir_all(root.join("hosts")).unwrap();
+        std::fs::write(
+            root.join("main.toml"),
+            "groups = [\"dev\"]\n[packages.git]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("hosts/box.toml"),
+            "[packages.git]\nservice = \"git-daemon\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("groups/dev.toml"),
+            "[packages.git]\nconfig = \"git -> ~/.config/git\"\n[packages.rustup]\n",
+        )
+        .unwrap();
+        // Files of the other format are not read
+        std::fs::write(root.join("groups/dev.owl"), "@package ignored\n").unwrap();
+
+        assert_eq!(ConfigFormat::of_root(root), ConfigFormat::Toml);
+        let config = Config::load_for_host(root, "box").unwrap();
+        let git = &config.packages["git"];
+        assert_eq!(git.service.as_deref(), Some("git-daemon.service"));
+        assert_eq!(git.config, ["git -> ~/.config/git"]);
+        assert!(config.packages.contains_key("rustup"));
+        assert!(!config.packages.contains_key("ignored"));
+        assert_eq!(config.group_origins[0].label(), "groups/dev.toml");
+
+        // main.owl wins when both exist
+        std::fs::write(root.join("main.owl"), "@package fd\n").unwrap();
+        assert_eq!(ConfigFormat::of_root(root), ConfigFormat::Owl);
+    }
+}
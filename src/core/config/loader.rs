@@ -1,96 +1,255 @@
 use anyhow::{Result, anyhow};
 use std::collections::HashSet;
-use std::env;
-use std::path::Path;
+use std::collections::hash_map::Entry;
+use std::path::{Path, PathBuf};
 
-use super::Config;
+use super::format::ConfigFormat;
+use super::options::PackageMerge;
+use super::trace::Journal;
+use super::{Config, Package};
+
+/// Why a group file was loaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupOrigin {
+    pub group: String,
+    /// Files that led to the group, from `main.owl` or the host file to the one declaring it
+    pub chain: Vec<String>,
+    /// Other reference chains that reach the group after it was loaded
+    pub also_via: Vec<Vec<String>>,
+    /// Format of the owl root the group was loaded from
+    pub format: ConfigFormat,
+}
+
+impl GroupOrigin {
+    /// `main.owl → groups/work.owl → groups/dev.owl`
+    pub fn describe_chain(chain: &[String], label: &str) -> String {
+        let mut parts: Vec<&str> = chain.iter().map(String::as_str).collect();
+        parts.push(label);
+        parts.join(" → ")
+    }
+
+    /// Group file relative to the owl root
+    pub fn label(&self) -> String {
+        self.format.group_label(&self.group)
+    }
+
+    /// The chain that loaded the group
+    pub fn describe(&self) -> String {
+        Self::describe_chain(&self.chain, &self.label())
+    }
+}
+
+/// One file's definition of a package, before files are merged
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    /// Path relative to the owl root, e.g. `groups/dev.owl`
+    pub file: String,
+    pub package: Package,
+}
 
 impl Config {
     pub fn load_all_relevant_config_files() -> Result<Self> {
-        let home = env::var("HOME").map_err(|_| anyhow!("HOME environment variable not set"))?;
         Self::load_all_relevant_config_files_from_path(
-            Path::new(&home).join(crate::internal::constants::OWL_DIR),
+            crate::internal::environment::get().owl_dir()?,
         )
     }
 
+    /// Like `load_all_relevant_config_files`, also keeping the merge journal
+    pub fn load_all_relevant_config_files_traced() -> Result<Self> {
+        let env = crate::internal::environment::get();
+        Self::load_for_host_traced(&env.owl_dir()?, env.hostname()?)
+    }
+
     pub fn load_all_relevant_config_files_from_path<P: AsRef<Path>>(owl_root: P) -> Result<Self> {
+        let hostname = crate::internal::environment::get().hostname()?;
+        Self::load_for_host(owl_root.as_ref(), hostname)
+    }
+
+    /// Load main, the given host's file and all referenced groups, recording why each group loaded
+    pub(crate) fn load_for_host(owl_root: &Path, hostname: &str) -> Result<Self> {
+        Self::load(owl_root, hostname, false)
+    }
+
+    /// Like `load_for_host`, also recording what each file contributed to each package
+    pub(crate) fn load_for_host_traced(owl_root: &Path, hostname: &str) -> Result<Self> {
+        Self::load(owl_root, hostname, true)
+    }
+
+    fn load(owl_root: &Path, hostname: &str, trace: bool) -> Result<Self> {
         let mut config = Config::new();
-        let owl_root = owl_root.as_ref();
+        if trace {
+            config.journal = Some(Journal::default());
+        }
 
         // Load in priority order: main (highest), hostname (medium), groups (lowest)
+        let format = ConfigFormat::of_root(owl_root);
+        let mut top_level: Vec<(String, Vec<String>)> = Vec::new();
+        let host_file = format.host_file(hostname);
+        for rel in [format.main_file(), host_file.clone()] {
+            let path = owl_root.join(&rel);
+            if path.exists() {
+                let loaded = Self::parse_file(&path).map_err(|e| anyhow!("{}: {}", rel, e))?;
+                config.record_declarations(&rel, &loaded);
+                // Options are per-machine settings: the host file overrides main
+                if rel == host_file {
+                    config.options.extend(loaded.options.clone());
+                }
+                top_level.push((rel.clone(), loaded.groups.clone()));
+                config.merge_file(Some(&rel), loaded);
+            }
+        }
 
-        // 1. Load main config (highest priority)
-        let main_config_path = owl_root.join(crate::internal::constants::MAIN_CONFIG_FILE);
-        Self::load_config_if_exists(&mut config, &main_config_path)?;
-
-        // 2. Load host-specific config (medium priority)
-        let hostname = crate::internal::constants::get_host_name()?;
-        let host_config_path = owl_root
-            .join(crate::internal::constants::HOSTS_DIR)
-            .join(format!(
-                "{}{}",
-                hostname,
-                crate::internal::constants::OWL_EXT
-            ));
-        Self::load_config_if_exists(&mut config, &host_config_path)?;
-
-        // 3. Load group configs (lowest priority)
+        // Group configs (lowest priority)
         let groups_path = owl_root.join(crate::internal::constants::GROUPS_DIR);
         if groups_path.exists() && groups_path.is_dir() {
-            let mut processed_groups = HashSet::new();
-            Self::load_groups_with_precedence(&groups_path, &mut config, &mut processed_groups)?;
+            Self::load_groups_with_precedence(&groups_path, &mut config, &top_level, format)?;
         }
 
         Ok(config)
     }
 
-    fn load_config_if_exists(config: &mut Config, path: &Path) -> Result<()> {
-        if path.exists() {
-            let loaded_config = Self::parse_file(path)?;
-            config.add_if_not_exists(loaded_config);
-        }
-        Ok(())
+    /// File that defines `@group <name>`
+    pub(crate) fn group_file_path(groups_path: &Path, name: &str) -> PathBuf {
+        ConfigFormat::Owl.group_file(groups_path, name)
     }
 
+    /// Group file path relative to the owl root, e.g. `groups/dev.owl`
+    pub(crate) fn group_label(name: &str) -> String {
+        ConfigFormat::Owl.group_label(name)
+    }
+
+    /// Load groups referenced by the top-level files, tracking the reference chain of each
+    ///
+    /// `top_level` lists `(file label, declared groups)` in precedence order.
     fn load_groups_with_precedence(
         groups_path: &Path,
         config: &mut Config,
-        processed_groups: &mut HashSet<String>,
+        top_level: &[(String, Vec<String>)],
+        format: ConfigFormat,
     ) -> Result<()> {
-        let mut groups_to_process: Vec<String> = config.groups.clone();
+        // Each entry is a group and the files that led to it; the stack pops from the end.
+        // References repeated by a lower-precedence file go to the bottom so they are
+        // only seen after the first path loaded the group.
+        let mut first: Vec<(String, Vec<String>)> = Vec::new();
+        let mut repeated: Vec<(String, Vec<String>)> = Vec::new();
+        for (label, groups) in top_level {
+            for group in groups {
+                let entry = (group.clone(), vec![label.clone()]);
+                if first.iter().any(|(g, _)| g == group) {
+                    repeated.push(entry);
+                } else {
+                    first.push(entry);
+                }
+            }
+        }
+        repeated.reverse();
+        let mut groups_to_process = repeated;
+        groups_to_process.extend(first);
 
-        while let Some(group_name) = groups_to_process.pop() {
-            if processed_groups.contains(&group_name) {
+        let mut processed_groups = HashSet::new();
+        while let Some((group_name, chain)) = groups_to_process.pop() {
+            let label = format.group_label(&group_name);
+            if chain.contains(&label) {
+                config.warnings.push(format!(
+                    "Group cycle: {}",
+                    GroupOrigin::describe_chain(&chain, &label)
+                ));
+                continue;
+            }
+            if let Some(origin) = config
+                .group_origins
+                .iter_mut()
+                .find(|o| o.group == group_name)
+            {
+                origin.also_via.push(chain);
+                continue;
+            }
+            if !processed_groups.insert(group_name.clone()) {
                 continue;
             }
-            processed_groups.insert(group_name.clone());
 
-            let group_file = groups_path.join(format!(
-                "{}{}",
-                group_name,
-                crate::internal::constants::OWL_EXT
-            ));
+            let group_file = format.group_file(groups_path, &group_name);
+            if crate::internal::files::is_leftover(&format!("{}{}", group_name, format.extension()))
+            {
+                config.warnings.push(format!(
+                    "Group '{}' not loaded: {} looks like an editor, merge or backup leftover",
+                    group_name, label
+                ));
+                continue;
+            }
             if group_file.exists() {
-                let group_config = Self::parse_file(&group_file)?;
-                // Add any new groups found in this group file
+                let group_config =
+                    Self::parse_file(&group_file).map_err(|e| anyhow!("{}: {}", label, e))?;
+                config.record_declarations(&label, &group_config);
+                // Add any groups referenced from this group file
+                let mut next = chain.clone();
+                next.push(label.clone());
                 for new_group in &group_config.groups {
-                    if !processed_groups.contains(new_group) {
-                        groups_to_process.push(new_group.clone());
-                    }
+                    groups_to_process.push((new_group.clone(), next.clone()));
                 }
+                config.group_origins.push(GroupOrigin {
+                    group: group_name,
+                    chain,
+                    also_via: Vec::new(),
+                    format,
+                });
                 // Add packages from group config only if not already defined
-                config.add_if_not_exists(group_config);
+                config.merge_file(Some(&label), group_config);
             }
         }
 
         Ok(())
     }
 
-    // Adds packages/env vars from other config only if they don't already exist (respects precedence)
+    /// Remember `file`'s packages; call in precedence order, before merging the file
+    fn record_declarations(&mut self, file: &str, other: &Self) {
+        for (name, package) in &other.packages {
+            self.declarations
+                .entry(name.clone())
+                .or_default()
+                .push(Declaration {
+                    file: file.to_string(),
+                    package: package.clone(),
+                });
+        }
+    }
+
+    /// Merge a config that was not loaded from a file
+    #[cfg(test)]
     pub(crate) fn add_if_not_exists(&mut self, other: Self) {
-        // Only add packages that don't already exist (higher priority configs win)
+        self.merge_file(None, other);
+    }
+
+    // Adds packages/env vars from other config only if they don't already exist (respects precedence);
+    // when tracing, the journal records what `file` contributed
+    fn merge_file(&mut self, file: Option<&str>, other: Self) {
+        // Options first, so a file can choose how its own packages merge
+        for (key, option) in other.options {
+            self.options.entry(key).or_insert(option);
+        }
+        let merge = self.package_merge().unwrap_or_else(|e| {
+            let warning = format!("{}; merging package fields", e);
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
+            PackageMerge::Fields
+        });
+
+        // Packages declared again merge field by field unless `package_merge=replace`;
+        // either way the higher priority config wins
         for (name, package) in other.packages {
-            self.packages.entry(name).or_insert(package);
+            if let (Some(journal), Some(file)) = (self.journal.as_mut(), file) {
+                journal.record(file, &name, &package, self.packages.get(&name), merge);
+            }
+            match self.packages.entry(name) {
+                Entry::Occupied(mut existing) if merge == PackageMerge::Fields => {
+                    existing.get_mut().merge_lower(package);
+                }
+                Entry::Occupied(_) => {}
+                Entry::Vacant(slot) => {
+                    slot.insert(package);
+                }
+            }
         }
 
         // Add groups (avoid duplicates)
@@ -104,5 +263,241 @@ impl Config {
         for (key, value) in other.env_vars {
             self.env_vars.entry(key).or_insert(value);
         }
+
+        for (arch, suffix) in other.arch_aur_suffixes {
+            self.arch_aur_suffixes.entry(arch).or_insert(suffix);
+        }
+
+        self.backups_keep = self.backups_keep.or(other.backups_keep);
+
+        // Untracked lists are additive across files
+        for name in other.untracked {
+            if !self.untracked.contains(&name) {
+                self.untracked.push(name);
+            }
+        }
+        self.untracked_reset |= other.untracked_reset;
+        for path in other.absent {
+            if !self.absent.contains(&path) {
+                self.absent.push(path);
+            }
+        }
+        for service in other.system_services {
+            if !self.system_services.contains(&service) {
+                self.system_services.push(service);
+            }
+        }
+        self.warnings.extend(other.warnings);
+    }
+}
+
+impl Package {
+    /// Fill the fields this declaration leaves unset from a lower-precedence one
+    ///
+    /// `:config` keeps the `@dotfiles-root` of the file it came from; env vars
+    /// merge key by key, except those the lower file's `@defaults` filled in.
+    fn merge_lower(&mut self, lower: Package) {
+        if self.config.is_empty() && !lower.config.is_empty() {
+            self.config = lower.config;
+            self.dotfiles_root = lower.dotfiles_root;
+        }
+        self.service = self.service.take().or(lower.service);
+        self.min_version = self.min_version.take().or(lower.min_version);
+        if self.after.is_empty() {
+            self.after = lower.after;
+        }
+        self.aur |= lower.aur;
+        self.no_upgrade |= lower.no_upgrade;
+        // Absence is additive: any file may ask for a path to be gone
+        for path in lower.absent {
+            if !self.absent.contains(&path) {
+                self.absent.push(path);
+            }
+        }
+        for name in lower.requires {
+            if !self.requires.contains(&name) {
+                self.requires.push(name);
+            }
+        }
+        self.required_by = self.required_by.take().or(lower.required_by);
+        for (key, value) in lower.env_vars {
+            if self.env_vars.contains_key(&key) || lower.default_env_keys.contains(&key) {
+                continue;
+            }
+            if let Some(shell) = lower.env_shells.get(&key) {
+                self.env_shells.insert(key.clone(), *shell);
+            }
+            self.env_vars.insert(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// main -> work -> dev and main -> play -> dev
+    fn diamond() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("groups")).unwrap();
+        fs::write(root.join("main.owl"), "@group work\n@group play\n").unwrap();
+        fs::write(
+            root.join("groups/work.owl"),
+            "@group dev\n@packages\nslack\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("groups/play.owl"),
+            "@group dev\n@packages\nsteam\n",
+        )
+        .unwrap();
+        fs::write(root.join("groups/dev.owl"), "@packages\ngit\n").unwrap();
+        dir
+    }
+
+    fn chain(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diamond_records_first_chain_and_alternatives() {
+        let dir = diamond();
+        let config = Config::load_for_host(dir.path(), "nohost").unwrap();
+        let dev = config
+            .group_origins
+            .iter()
+            .find(|o| o.group == "dev")
+            .unwrap();
+        assert_eq!(dev.chain, chain(&["main.owl", "groups/play.owl"]));
+        assert_eq!(dev.also_via, vec![chain(&["main.owl", "groups/work.owl"])]);
+        assert_eq!(
+            dev.describe(),
+            "main.owl → groups/play.owl → groups/dev.owl"
+        );
+        assert!(config.packages.contains_key("git"));
+    }
+
+    #[test]
+    fn test_cycle_warning_uses_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("groups")).unwrap();
+        fs::write(root.join("main.owl"), "@group a\n").unwrap();
+        fs::write(root.join("groups/a.owl"), "@group b\n").unwrap();
+        fs::write(root.join("groups/b.owl"), "@group a\n").unwrap();
+
+        let config = Config::load_for_host(root, "nohost").unwrap();
+        assert_eq!(
+            config.warnings,
+            vec!["Group cycle: main.owl → groups/a.owl → groups/b.owl → groups/a.owl".to_string()]
+        );
+        assert!(config.group_origins[0].also_via.is_empty());
+    }
+
+    #[test]
+    fn test_leftover_group_files_are_not_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("groups")).unwrap();
+        fs::write(root.join("main.owl"), "@group dev\n@group dev.orig\n").unwrap();
+        fs::write(root.join("groups/dev.owl"), "@packages\ngit\n").unwrap();
+        fs::write(root.join("groups/dev.orig.owl"), "@packages\nold-tool\n").unwrap();
+
+        let config = Config::load_for_host(root, "nohost").unwrap();
+        assert!(config.packages.contains_key("git"));
+        assert!(!config.packages.contains_key("old-tool"));
+        assert_eq!(
+            config.warnings,
+            [
+                "Group 'dev.orig' not loaded: groups/dev.orig.owl looks like an editor, merge or backup leftover"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_direct_host_reference_loads_before_nested_ones() {
+        let dir = diamond();
+        fs::create_dir_all(dir.path().join("hosts")).unwrap();
+        fs::write(dir.path().join("hosts/box.owl"), "@group dev\n").unwrap();
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        let dev = config
+            .group_origins
+            .iter()
+            .find(|o| o.group == "dev")
+            .unwrap();
+        assert_eq!(dev.chain, chain(&["hosts/box.owl"]));
+        assert_eq!(dev.also_via.len(), 2);
+    }
+
+    /// A group sets the service and env, the host file redefines config and one env var
+    fn layered(main_extra: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("hosts")).unwrap();
+        fs::create_dir_all(root.join("groups/shared")).unwrap();
+        fs::write(root.join("main.owl"), format!("{}@group dev\n", main_extra)).unwrap();
+        fs::write(
+            root.join("hosts/box.owl"),
+            "@package syncthing\n:config syncthing-box\n:env STNODEFAULTFOLDER=1\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("groups/dev.owl"),
+            "@dotfiles-root shared\n@package syncthing\n:config syncthing\n\
+             :service syncthing@.service\n:env STNODEFAULTFOLDER=0\n:env [shell=fish] STGUIADDRESS=:8384\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_fields_merge_across_files() {
+        let dir = layered("");
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        let package = &config.packages["syncthing"];
+        assert_eq!(package.config, vec!["syncthing-box"]);
+        // The host's :config resolves against the default root, not the group's
+        assert_eq!(package.dotfiles_root, None);
+        assert_eq!(package.service.as_deref(), Some("syncthing@.service"));
+        assert_eq!(package.env_vars["STNODEFAULTFOLDER"], "1");
+        assert_eq!(package.env_vars["STGUIADDRESS"], ":8384");
+        assert_eq!(
+            package.env_shells.get("STGUIADDRESS"),
+            Some(&crate::core::env::Shell::Fish)
+        );
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_replace_merge_keeps_highest_declaration() {
+        let dir = layered("@option package_merge=replace\n");
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        let package = &config.packages["syncthing"];
+        assert_eq!(package.config, vec!["syncthing-box"]);
+        assert_eq!(package.service, None);
+        assert!(!package.env_vars.contains_key("STGUIADDRESS"));
+    }
+
+    #[test]
+    fn test_lower_config_brings_its_dotfiles_root() {
+        let mut host = Config::parse("@package syncthing\n:service syncthing.service\n").unwrap();
+        let group = Config::parse("@dotfiles-root shared\n@package syncthing\n:config syncthing\n")
+            .unwrap();
+        host.add_if_not_exists(group);
+        let package = &host.packages["syncthing"];
+        assert_eq!(package.config, vec!["syncthing"]);
+        assert!(package.dotfiles_root.is_some());
+        assert_eq!(package.service.as_deref(), Some("syncthing.service"));
+    }
+
+    #[test]
+    fn test_invalid_package_merge_warns_once() {
+        let mut main = Config::parse("@option package_merge=deep\n@package a\n").unwrap();
+        main.add_if_not_exists(Config::parse("@package a\n").unwrap());
+        main.add_if_not_exists(Config::parse("@package a\n").unwrap());
+        assert_eq!(main.warnings.len(), 1);
+        assert!(main.warnings[0].contains("package_merge"));
     }
 }
@@ -0,0 +1,265 @@
+//! Edits owl makes to config files on the user's behalf
+//!
+//! Entries owl adds go into a block delimited by [`MANAGED_BEGIN`] and
+//! [`MANAGED_END`] so they stay apart from hand-written sections. Files that
+//! already have a hand-written `@packages` section and no managed block keep
+//! receiving entries there. Packages added into another package's block become
+//! a `:requires` line at the end of that block; other directives, such as an
+//! imported `:service`, are added the same way.
+
+/// First line of the owl-managed block
+pub const MANAGED_BEGIN: &str = "# >>> owl managed >>>";
+/// Last line of the owl-managed block
+pub const MANAGED_END: &str = "# <<< owl managed <<<";
+
+/// Line indices of the begin and end markers, if the file has a complete block
+fn managed_block(lines: &[String]) -> Option<(usize, usize)> {
+    let begin = lines.iter().position(|l| l.trim() == MANAGED_BEGIN)?;
+    let end = lines[begin + 1..]
+        .iter()
+        .position(|l| l.trim() == MANAGED_END)?;
+    Some((begin, begin + 1 + end))
+}
+
+fn is_packages_header(line: &str) -> bool {
+    line.trim() == "@packages" || line.trim() == "@pkgs"
+}
+
+/// Index just past the last entry of the `@packages` section starting at `start`
+///
+/// The section runs until the next directive or `limit`; trailing blank lines are skipped.
+fn section_end(lines: &[String], start: usize, limit: usize) -> usize {
+    let mut end = lines[start + 1..limit]
+        .iter()
+        .position(|l| l.trim().starts_with('@'))
+        .map(|offset| start + 1 + offset)
+        .unwrap_or(limit);
+    while end > start + 1 && lines[end - 1].trim().is_empty() {
+        end -= 1;
+    }
+    end
+}
+
+/// Add package names to a config file's content
+///
+/// Names go into the managed block when there is one, otherwise into an existing
+/// `@packages` section; if neither exists a new managed block is appended.
+pub fn insert_packages(content: &str, packages: &[String]) -> String {
+    let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+
+    if let Some((begin, end)) = managed_block(&lines) {
+        match (begin + 1..end).find(|&i| is_packages_header(&lines[i])) {
+            Some(header) => {
+                let at = section_end(&lines, header, end);
+                lines.splice(at..at, packages.iter().cloned());
+            }
+            None => {
+                let mut section = vec!["@packages".to_string()];
+                section.extend(packages.iter().cloned());
+                lines.splice(end..end, section);
+            }
+        }
+    } else if let Some(header) = lines.iter().position(|l| is_packages_header(l)) {
+        let at = section_end(&lines, header, lines.len());
+        lines.splice(at..at, packages.iter().cloned());
+    } else {
+        if lines.last().is_some_and(|l| !l.is_empty()) {
+            lines.push(String::new());
+        }
+        lines.push(MANAGED_BEGIN.to_string());
+        lines.push("@packages".to_string());
+        lines.extend(packages.iter().cloned());
+        lines.push(MANAGED_END.to_string());
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Whether `line` opens the block of `package` (`@package NAME`, `@pkg NAME [aur]`,
+/// `@package NAME [aur, no-upgrade]`)
+fn opens_block(line: &str, package: &str) -> bool {
+    let line = crate::core::names::normalize_line(line);
+    let Some(rest) = line
+        .strip_prefix("@package ")
+        .or_else(|| line.strip_prefix("@pkg "))
+    else {
+        return false;
+    };
+    rest.split_once('[').map_or(rest, |(name, _)| name).trim() == package
+}
+
+/// Append `:requires` for `packages` to the block of `parent`
+///
+/// `None` when the content has no `@package parent` block.
+pub fn insert_requires(content: &str, parent: &str, packages: &[String]) -> Option<String> {
+    insert_directive(
+        content,
+        parent,
+        &format!(":requires {}", packages.join(" ")),
+    )
+}
+
+/// Append a directive line to the block of `package`
+///
+/// The line goes after the block's last directive, before any blank lines or
+/// comments that lead into the next one. `None` when the content has no
+/// `@package package` block.
+pub fn insert_directive(content: &str, package: &str, directive: &str) -> Option<String> {
+    let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+    let header = lines.iter().rposition(|l| opens_block(l, package))?;
+    let mut end = lines[header + 1..]
+        .iter()
+        .position(|l| l.trim().starts_with('@'))
+        .map_or(lines.len(), |offset| header + 1 + offset);
+    while end > header + 1 && {
+        let line = lines[end - 1].trim();
+        line.is_empty() || line.starts_with('#')
+    } {
+        end -= 1;
+    }
+    lines.insert(end, directive.to_string());
+    Some(lines.join("\n") + "\n")
+}
+
+/// Add a `@package` block with `directives` at the end of the managed block,
+/// appending a managed block if there is none
+pub fn insert_package_block(content: &str, package: &str, directives: &[String]) -> String {
+    let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+    let mut block = vec![format!("@package {}", package)];
+    block.extend(directives.iter().cloned());
+    match managed_block(&lines) {
+        Some((_, end)) => {
+            lines.splice(end..end, block);
+        }
+        None => {
+            if lines.last().is_some_and(|l| !l.is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(MANAGED_BEGIN.to_string());
+            lines.extend(block);
+            lines.push(MANAGED_END.to_string());
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::Config;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_new_section_is_wrapped_in_markers() {
+        let merged = insert_packages("@env A=1\n", &names(&["fd"]));
+        assert_eq!(
+            merged,
+            format!(
+                "@env A=1\n\n{}\n@packages\nfd\n{}\n",
+                MANAGED_BEGIN, MANAGED_END
+            )
+        );
+        assert!(Config::parse(&merged).unwrap().packages.contains_key("fd"));
+    }
+
+    #[test]
+    fn test_insert_stays_inside_managed_block() {
+        let content = format!(
+            "@packages\nneovim\n\n{}\n@packages\nfd\n{}\n\n@package git\n:config gitconfig\n",
+            MANAGED_BEGIN, MANAGED_END
+        );
+        let merged = insert_packages(&content, &names(&["ripgrep"]));
+        assert_eq!(
+            merged,
+            format!(
+                "@packages\nneovim\n\n{}\n@packages\nfd\nripgrep\n{}\n\n@package git\n:config gitconfig\n",
+                MANAGED_BEGIN, MANAGED_END
+            )
+        );
+    }
+
+    #[test]
+    fn test_managed_block_without_section_gets_one() {
+        let content = format!("{}\n{}\n", MANAGED_BEGIN, MANAGED_END);
+        assert_eq!(
+            insert_packages(&content, &names(&["fd"])),
+            format!("{}\n@packages\nfd\n{}\n", MANAGED_BEGIN, MANAGED_END)
+        );
+    }
+
+    #[test]
+    fn test_hand_written_section_used_without_block() {
+        let content = "@packages\nneovim\n\n@package git\n:config gitconfig\n";
+        assert_eq!(
+            insert_packages(content, &names(&["fd"])),
+            "@packages\nneovim\nfd\n\n@package git\n:config gitconfig\n"
+        );
+    }
+
+    #[test]
+    fn test_requires_goes_at_the_end_of_the_parent_block() {
+        let content = format!(
+            "@package devtools\n:service docker\n\n# tools\n{}\n@packages\nfd\n{}\n@pkg git [aur]\n",
+            MANAGED_BEGIN, MANAGED_END
+        );
+        let merged = insert_requires(&content, "devtools", &names(&["gdb", "strace"])).unwrap();
+        assert_eq!(
+            merged,
+            format!(
+                "@package devtools\n:service docker\n:requires gdb strace\n\n# tools\n{}\n@packages\nfd\n{}\n@pkg git [aur]\n",
+                MANAGED_BEGIN, MANAGED_END
+            )
+        );
+        let config = Config::parse(&merged).unwrap();
+        assert_eq!(
+            config.packages["devtools"].requires,
+            names(&["gdb", "strace"])
+        );
+        assert!(config.packages.contains_key("strace"));
+
+        assert_eq!(
+            insert_requires("@pkg git [aur]", "git", &names(&["tig"])).unwrap(),
+            "@pkg git [aur]\n:requires tig\n"
+        );
+        assert_eq!(insert_requires(&content, "ripgrep", &names(&["fd"])), None);
+    }
+
+    #[test]
+    fn test_service_goes_into_its_block_or_a_new_managed_one() {
+        let content = format!(
+            "@package openssh\n:config ssh -> ~/.ssh/config\n\n{}\n@packages\nfd\n{}\n",
+            MANAGED_BEGIN, MANAGED_END
+        );
+        let attached = insert_directive(&content, "openssh", ":service sshd.service").unwrap();
+        assert!(attached.starts_with(
+            "@package openssh\n:config ssh -> ~/.ssh/config\n:service sshd.service\n\n"
+        ));
+
+        let declared = insert_package_block(&attached, "cups", &names(&[":service cups.service"]));
+        assert!(declared.ends_with(&format!(
+            "@packages\nfd\n@package cups\n:service cups.service\n{}\n",
+            MANAGED_END
+        )));
+        // A later package import still finds the block's @packages section
+        let config = Config::parse(&insert_packages(&declared, &names(&["jq"]))).unwrap();
+        assert_eq!(
+            config.packages["cups"].service.as_deref(),
+            Some("cups.service")
+        );
+        assert_eq!(
+            config.packages["openssh"].service.as_deref(),
+            Some("sshd.service")
+        );
+        assert!(config.packages["jq"].service.is_none());
+
+        assert_eq!(
+            insert_package_block("@package git\n", "cups", &names(&[":service cups.service"])),
+            format!(
+                "@package git\n\n{}\n@package cups\n:service cups.service\n{}\n",
+                MANAGED_BEGIN, MANAGED_END
+            )
+        );
+    }
+}
@@ -1,21 +1,137 @@
-use std::collections::HashMap;
+use crate::internal::toposort::{CycleError, topo_sort};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+pub mod annotate;
+pub mod canonical;
+pub mod explain;
+pub mod format;
 pub mod loader;
+pub mod managed;
+pub mod options;
 pub mod parser;
+pub mod trace;
+pub mod tree;
 pub mod validator;
 
+pub use options::ConfigOption;
+
+/// Block holding services no package owns (`@package _system-services`); it
+/// takes any number of `:service` lines and installs nothing
+pub const SYSTEM_SERVICES: &str = "_system-services";
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct Package {
     pub config: Vec<String>,
     pub service: Option<String>,
     pub env_vars: HashMap<String, String>,
+    /// Directory `:config` sources resolve against when the declaring file set `@dotfiles-root`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dotfiles_root: Option<String>,
+    /// Keys in `env_vars` that are only written for one shell (`:env [shell=fish]`)
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub env_shells: HashMap<String, crate::core::env::Shell>,
+    /// Keys in `env_vars` that were filled in from the file's `@defaults` block
+    #[serde(skip)]
+    pub default_env_keys: HashSet<String>,
+    /// Oldest installed version the package's dotfiles and services work with (`:min-version`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<crate::core::version::MinVersion>,
+    /// Packages to install, and whose services to start, before this one (`:after`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+    /// Install from the AUR even when a repository has the same name (`[aur]`)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub aur: bool,
+    /// Left out of repository and AUR updates (`[no-upgrade]`)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_upgrade: bool,
+    /// Paths removed when present (`:absent`, see `core::absent`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub absent: Vec<String>,
+    /// Packages declared as part of this one's block (`:requires`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    /// The block whose `:requires` declared this package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required_by: Option<RequiredBy>,
 }
 
-#[derive(Debug, serde::Serialize)]
+/// Where a package was declared by `:requires`
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RequiredBy {
+    pub parent: String,
+    /// Line of the `:requires` directive; `None` for TOML files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+/// Directives from an `@defaults` block, baked into each package declared in the same file
+#[derive(Debug, Clone, Default)]
+pub struct PackageDefaults {
+    pub env_vars: HashMap<String, String>,
+    pub env_shells: HashMap<String, crate::core::env::Shell>,
+}
+
+impl PackageDefaults {
+    /// A new package carrying these defaults
+    pub fn package(&self) -> Package {
+        Package {
+            config: Vec::new(),
+            service: None,
+            env_vars: self.env_vars.clone(),
+            env_shells: self.env_shells.clone(),
+            dotfiles_root: None,
+            default_env_keys: self.env_vars.keys().cloned().collect(),
+            min_version: None,
+            after: Vec::new(),
+            aur: false,
+            no_upgrade: false,
+            absent: Vec::new(),
+            requires: Vec::new(),
+            required_by: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct Config {
     pub packages: HashMap<String, Package>,
     pub groups: Vec<String>,
     pub env_vars: HashMap<String, String>,
+    /// AUR package name suffixes keyed by architecture (`@arch-aur-prefix`)
+    pub arch_aur_suffixes: HashMap<String, String>,
+    /// Extra packages never proposed for removal (`@untracked`)
+    pub untracked: Vec<String>,
+    /// Drop the built-in untracked defaults (`@untracked-reset`)
+    pub untracked_reset: bool,
+    /// Paths removed when present, outside any package (`@absent`)
+    pub absent: Vec<String>,
+    /// `@option key=value` settings
+    pub options: HashMap<String, ConfigOption>,
+    /// `:service` lines of the `@package _system-services` block
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub system_services: Vec<String>,
+    /// Backups kept per dotfile destination (`@backups-keep`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backups_keep: Option<usize>,
+    /// `@dotfiles-root` as written in this file (not merged across files)
+    #[serde(skip)]
+    pub dotfiles_root: Option<String>,
+    /// Groups in load order with the reference chain that loaded each (set by the loader)
+    #[serde(skip)]
+    pub group_origins: Vec<loader::GroupOrigin>,
+    /// Every file's definition of each package, highest precedence first (set by the loader)
+    #[serde(skip)]
+    pub declarations: HashMap<String, Vec<loader::Declaration>>,
+    /// What each file contributed to each package; only kept by a traced load
+    #[serde(skip)]
+    pub journal: Option<trace::Journal>,
+    /// `@defaults` of this file (already applied to its packages, never merged)
+    #[serde(skip)]
+    pub defaults: PackageDefaults,
+    /// Non-fatal problems found while parsing
+    #[serde(skip)]
+    pub warnings: Vec<String>,
 }
 
 impl Config {
@@ -24,6 +140,126 @@ impl Config {
             packages: HashMap::new(),
             groups: Vec::new(),
             env_vars: HashMap::new(),
+            arch_aur_suffixes: HashMap::new(),
+            untracked: Vec::new(),
+            untracked_reset: false,
+            absent: Vec::new(),
+            options: HashMap::new(),
+            system_services: Vec::new(),
+            backups_keep: None,
+            dotfiles_root: None,
+            group_origins: Vec::new(),
+            declarations: HashMap::new(),
+            journal: None,
+            defaults: PackageDefaults::default(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Print warnings collected while parsing
+    pub fn print_warnings(&self) {
+        for warning in &self.warnings {
+            warnln!(
+                "  {} {}",
+                crate::internal::color::yellow("warning:"),
+                warning
+            );
+        }
+    }
+
+    /// Every `@absent` and `:absent` path, sorted and without duplicates
+    pub fn absent_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .absent
+            .iter()
+            .chain(self.packages.values().flat_map(|pkg| &pkg.absent))
+            .cloned()
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// `:after` dependencies keyed by package
+    pub fn after_graph(&self) -> BTreeMap<String, Vec<String>> {
+        self.packages
+            .iter()
+            .filter(|(_, pkg)| !pkg.after.is_empty())
+            .map(|(name, pkg)| (name.clone(), pkg.after.clone()))
+            .collect()
+    }
+
+    /// Declared packages in `:after` order
+    pub fn package_order(&self) -> Result<Vec<String>, CycleError> {
+        let names: Vec<String> = self.packages.keys().cloned().collect();
+        topo_sort(&names, &self.after_graph())
+    }
+
+    /// Configured services, each after the services of the packages its package follows
+    pub fn service_order(&self) -> Result<Vec<String>, CycleError> {
+        let mut deps: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for pkg in self.packages.values() {
+            let Some(service) = &pkg.service else {
+                continue;
+            };
+            let before = pkg
+                .after
+                .iter()
+                .filter_map(|name| self.packages.get(name)?.service.clone());
+            deps.entry(service.clone()).or_default().extend(before);
+        }
+        for service in &self.system_services {
+            deps.entry(service.clone()).or_default();
+        }
+        let services: Vec<String> = deps.keys().cloned().collect();
+        topo_sort(&services, &deps)
+    }
+
+    /// Packages declared with the `[aur]` hint
+    pub fn aur_hinted(&self) -> HashSet<String> {
+        self.packages
+            .iter()
+            .filter(|(_, pkg)| pkg.aur)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Packages declared with `[no-upgrade]`, sorted
+    pub fn held_packages(&self) -> Vec<String> {
+        let mut held: Vec<String> = self
+            .packages
+            .iter()
+            .filter(|(_, pkg)| pkg.no_upgrade)
+            .map(|(name, _)| name.clone())
+            .collect();
+        held.sort();
+        held
+    }
+
+    /// Name to install an AUR package under on the given architecture
+    pub fn aur_package_name(&self, package: &str, arch: &str) -> String {
+        match self.arch_aur_suffixes.get(arch) {
+            Some(suffix) if !package.ends_with(suffix.as_str()) => {
+                format!("{}{}", package, suffix)
+            }
+            _ => package.to_string(),
+        }
+    }
+
+    /// The declared package `name` stands for on the given architecture: the
+    /// one `@arch-aur-prefix` renamed to `name`, or `name` itself
+    pub fn declared_name<'a>(&self, name: &'a str, arch: &str) -> &'a str {
+        match self
+            .arch_aur_suffixes
+            .get(arch)
+            .and_then(|suffix| name.strip_suffix(suffix.as_str()))
+        {
+            Some(declared)
+                if !self.packages.contains_key(name) && self.packages.contains_key(declared) =>
+            {
+                declared
+            }
+            _ => name,
         }
     }
 }
@@ -62,7 +298,7 @@ mod tests {
         let config = Config::parse(content).unwrap();
 
         let package = &config.packages["test-service"];
-        assert_eq!(package.service.as_ref().unwrap(), "test-service");
+        assert_eq!(package.service.as_ref().unwrap(), "test-service.service");
         assert!(package.config.is_empty());
     }
 
@@ -163,15 +399,45 @@ package2"#;
         assert!(config.packages["test"].config.is_empty());
     }
 
+    #[test]
+    fn test_after_orders_packages_and_services() {
+        let config = Config::parse(
+            "@package zsh\n@package gitea\n:after postgresql\n:service gitea.service\n\
+             @package postgresql\n:service postgresql.service\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.package_order().unwrap(),
+            vec!["postgresql", "gitea", "zsh"]
+        );
+        assert_eq!(
+            config.service_order().unwrap(),
+            vec!["postgresql.service", "gitea.service"]
+        );
+
+        let cyclic = Config::parse("@package a\n:after b\n@package b\n:after a\n").unwrap();
+        assert_eq!(cyclic.package_order().unwrap_err().members, vec!["a", "b"]);
+    }
+
     #[test]
     fn test_add_if_not_exists() {
-        let mut config1 = Config::new();
+        let mut config1 = Config::parse("@option package_merge=replace").unwrap();
         config1.packages.insert(
             "test".to_string(),
             Package {
                 config: vec!["config1".to_string()],
                 service: None,
                 env_vars: std::collections::HashMap::new(),
+                env_shells: HashMap::new(),
+                dotfiles_root: None,
+                default_env_keys: HashSet::new(),
+                min_version: None,
+                after: Vec::new(),
+                aur: false,
+                no_upgrade: false,
+                absent: Vec::new(),
+                requires: Vec::new(),
+                required_by: None,
             },
         );
 
@@ -182,6 +448,16 @@ package2"#;
                 config: vec!["config2".to_string()],
                 service: Some("service2".to_string()),
                 env_vars: std::collections::HashMap::new(),
+                env_shells: HashMap::new(),
+                dotfiles_root: None,
+                default_env_keys: HashSet::new(),
+                min_version: None,
+                after: Vec::new(),
+                aur: false,
+                no_upgrade: false,
+                absent: Vec::new(),
+                requires: Vec::new(),
+                required_by: None,
             },
         );
 
@@ -203,6 +479,16 @@ package2"#;
                 config: Vec::new(),
                 service: None,
                 env_vars: std::collections::HashMap::new(),
+                env_shells: HashMap::new(),
+                dotfiles_root: None,
+                default_env_keys: HashSet::new(),
+                min_version: None,
+                after: Vec::new(),
+                aur: false,
+                no_upgrade: false,
+                absent: Vec::new(),
+                requires: Vec::new(),
+                required_by: None,
             },
         );
 
@@ -212,6 +498,16 @@ package2"#;
                 config: Vec::new(),
                 service: None,
                 env_vars: std::collections::HashMap::new(),
+                env_shells: HashMap::new(),
+                dotfiles_root: None,
+                default_env_keys: HashSet::new(),
+                min_version: None,
+                after: Vec::new(),
+                aur: false,
+                no_upgrade: false,
+                absent: Vec::new(),
+                requires: Vec::new(),
+                required_by: None,
             },
         );
 
@@ -297,6 +593,51 @@ vi
         );
     }
 
+    #[test]
+    fn test_parse_arch_aur_prefix() {
+        let content = "@arch-aur-prefix aarch64:suffix=-aarch64\n@pkg spotify";
+        let config = Config::parse(content).unwrap();
+
+        assert_eq!(config.arch_aur_suffixes["aarch64"], "-aarch64");
+        assert_eq!(
+            config.aur_package_name("spotify", "aarch64"),
+            "spotify-aarch64"
+        );
+        assert_eq!(
+            config.aur_package_name("spotify-aarch64", "aarch64"),
+            "spotify-aarch64"
+        );
+        assert_eq!(config.aur_package_name("spotify", "x86_64"), "spotify");
+        assert_eq!(
+            config.declared_name("spotify-aarch64", "aarch64"),
+            "spotify"
+        );
+        assert_eq!(
+            config.declared_name("spotify-aarch64", "x86_64"),
+            "spotify-aarch64"
+        );
+        assert_eq!(
+            config.declared_name("vim-aarch64", "aarch64"),
+            "vim-aarch64"
+        );
+    }
+
+    #[test]
+    fn test_parse_arch_aur_prefix_malformed() {
+        assert!(Config::parse("@arch-aur-prefix aarch64").is_err());
+        assert!(Config::parse("@arch-aur-prefix aarch64:-aarch64").is_err());
+    }
+
+    #[test]
+    fn test_parse_untracked_directives() {
+        let content = "@untracked nvidia-dkms  linux-zen\n@untracked-reset";
+        let config = Config::parse(content).unwrap();
+
+        assert_eq!(config.untracked, vec!["nvidia-dkms", "linux-zen"]);
+        assert!(config.untracked_reset);
+        assert!(!Config::parse("@untracked foo").unwrap().untracked_reset);
+    }
+
     #[test]
     fn test_parse_cfg_alias() {
         let content = "@package test\n:cfg test -> ~/.config/test";
@@ -0,0 +1,351 @@
+//! `@option key=value` settings declared in config files
+
+use anyhow::{Result, anyhow};
+
+use super::Config;
+
+/// A single `@option` value together with the file that declared it
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ConfigOption {
+    pub value: String,
+    /// Path of the declaring file (empty when parsed from a string)
+    pub source: String,
+}
+
+impl ConfigOption {
+    /// Declaring file relative to the owl root, e.g. `hosts/server1.owl`
+    pub fn source_display(&self) -> String {
+        if self.source.is_empty() {
+            return "config".to_string();
+        }
+        let owl_root = crate::internal::environment::get()
+            .owl_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.source
+            .strip_prefix(&format!("{}/", owl_root))
+            .unwrap_or(&self.source)
+            .to_string()
+    }
+}
+
+/// Which automatic update phases apply may run (`@option auto_update=...`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutoUpdate {
+    Never,
+    AurOnly,
+    RepoOnly,
+    All,
+}
+
+impl AutoUpdate {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "never" => Ok(AutoUpdate::Never),
+            "aur-only" => Ok(AutoUpdate::AurOnly),
+            "repo-only" => Ok(AutoUpdate::RepoOnly),
+            "all" => Ok(AutoUpdate::All),
+            other => Err(anyhow!(
+                "Invalid auto_update value '{}', expected never, aur-only, repo-only or all",
+                other
+            )),
+        }
+    }
+
+    pub fn repo(self) -> bool {
+        matches!(self, AutoUpdate::RepoOnly | AutoUpdate::All)
+    }
+
+    pub fn aur(self) -> bool {
+        matches!(self, AutoUpdate::AurOnly | AutoUpdate::All)
+    }
+}
+
+/// How a package declared in several files is merged (`@option package_merge=...`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PackageMerge {
+    /// Each field comes from the highest-precedence file that sets it
+    Fields,
+    /// The highest-precedence declaration replaces the others whole
+    Replace,
+}
+
+impl Config {
+    /// Look up an `@option` by key
+    pub fn option(&self, key: &str) -> Option<&ConfigOption> {
+        self.options.get(key)
+    }
+
+    /// Resolved `auto_update` policy (defaults to all)
+    pub fn auto_update(&self) -> Result<AutoUpdate> {
+        match self.option("auto_update") {
+            Some(opt) => AutoUpdate::parse(&opt.value),
+            None => Ok(AutoUpdate::All),
+        }
+    }
+
+    /// Resolved `package_merge` mode (defaults to fields)
+    pub fn package_merge(&self) -> Result<PackageMerge> {
+        match self.option("package_merge").map(|opt| opt.value.as_str()) {
+            None | Some("fields") => Ok(PackageMerge::Fields),
+            Some("replace") => Ok(PackageMerge::Replace),
+            Some(other) => Err(anyhow!(
+                "Invalid package_merge value '{}', expected fields or replace",
+                other
+            )),
+        }
+    }
+
+    /// Start managing declared packages that are already installed without asking
+    /// (`@option auto_adopt=true`)
+    pub fn auto_adopt(&self) -> Result<bool> {
+        self.flag_option("auto_adopt")
+    }
+
+    /// Look up AUR metadata over the RPC interface for `owl status` (`@option aur_rpc=true`)
+    pub fn aur_rpc(&self) -> Result<bool> {
+        self.flag_option("aur_rpc")
+    }
+
+    /// Report CRLF line endings in dotfile sources (`@option enforce_lf=true`)
+    pub fn enforce_lf(&self) -> Result<bool> {
+        self.flag_option("enforce_lf")
+    }
+
+    /// File name patterns that are suspicious when empty in a dotfile source
+    /// (`@option suspect_empty=*.conf,*.toml`; an empty value turns the check off)
+    pub fn suspect_empty(&self) -> Vec<String> {
+        self.list_option(
+            "suspect_empty",
+            crate::core::source_check::DEFAULT_SUSPECT_EMPTY,
+        )
+    }
+
+    /// Several hosts apply into this home directory (`@option shared_home=true`)
+    pub fn shared_home(&self) -> Result<bool> {
+        self.flag_option("shared_home")
+    }
+
+    /// Command refreshing the mirrors after repeated repo sync failures on a
+    /// mirror (`@option mirror_refresh=...`), off when unset or empty
+    pub fn mirror_refresh(&self) -> Option<String> {
+        self.option("mirror_refresh")
+            .map(|opt| opt.value.trim().to_string())
+            .filter(|command| !command.is_empty())
+    }
+
+    /// Package name patterns whose change advises a reboot in `--plan-json`
+    /// (`@option reboot_advised=linux*,systemd`; an empty value matches nothing)
+    pub fn reboot_advised(&self) -> Vec<String> {
+        self.list_option("reboot_advised", crate::core::plan::DEFAULT_REBOOT_ADVISED)
+    }
+
+    /// Modes of dotfile destinations created under home
+    /// (`@option default_file_mode=600` and `default_dir_mode=700`)
+    pub fn mode_policy(&self) -> Result<crate::core::file_modes::ModePolicy> {
+        let mode = |key| {
+            self.option(key)
+                .map(|opt| {
+                    crate::core::file_modes::parse_mode(&opt.value)
+                        .map_err(|e| anyhow!("@option {}: {}", key, e))
+                })
+                .transpose()
+        };
+        Ok(crate::core::file_modes::ModePolicy {
+            file: mode("default_file_mode")?,
+            dir: mode("default_dir_mode")?,
+        })
+    }
+
+    /// Unit name patterns `import --services` leaves out: the built-in noise
+    /// list and `@option service_import_ignore=avahi*,cups-browsed.service`
+    pub fn service_import_ignore(&self) -> Vec<String> {
+        let mut patterns: Vec<String> = crate::core::service_import::DEFAULT_NOISE
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        patterns.extend(self.list_option("service_import_ignore", &[]));
+        patterns
+    }
+
+    /// How old the last successful apply may get before apply warns
+    /// (`@option stale_after=7d`, `off` to never warn)
+    pub fn stale_after(&self) -> Result<Option<std::time::Duration>> {
+        match self.option("stale_after").map(|opt| opt.value.trim()) {
+            None => Ok(Some(crate::core::last_success::DEFAULT_STALE_AFTER)),
+            Some("off") => Ok(None),
+            Some(value) => crate::internal::time::parse_duration(value)
+                .map(Some)
+                .map_err(|e| anyhow!("@option stale_after: {}", e)),
+        }
+    }
+
+    /// A comma-separated option, `default` when unset
+    fn list_option(&self, key: &str, default: &[&str]) -> Vec<String> {
+        match self.option(key) {
+            Some(opt) => opt
+                .value
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect(),
+            None => default.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// A true/false option that defaults to false
+    fn flag_option(&self, key: &str) -> Result<bool> {
+        match self.option(key).map(|opt| opt.value.as_str()) {
+            None | Some("false") => Ok(false),
+            Some("true") => Ok(true),
+            Some(other) => Err(anyhow!(
+                "Invalid {} value '{}', expected true or false",
+                key,
+                other
+            )),
+        }
+    }
+
+    /// Backups kept per dotfile destination (`@backups-keep`, default 5)
+    pub fn keep_backups(&self) -> usize {
+        self.backups_keep
+            .unwrap_or(crate::core::backup::DEFAULT_KEEP_BACKUPS)
+    }
+
+    /// Record the declaring file on every option parsed from it
+    pub(crate) fn set_option_source(&mut self, source: &str) {
+        for opt in self.options.values_mut() {
+            opt.source = source.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_option_directive() {
+        let config = Config::parse("@option auto_update = repo-only").unwrap();
+        assert_eq!(config.option("auto_update").unwrap().value, "repo-only");
+        assert_eq!(config.auto_update().unwrap(), AutoUpdate::RepoOnly);
+    }
+
+    #[test]
+    fn test_auto_update_values() {
+        for (value, repo, aur) in [
+            ("never", false, false),
+            ("aur-only", false, true),
+            ("repo-only", true, false),
+            ("all", true, true),
+        ] {
+            let policy = AutoUpdate::parse(value).unwrap();
+            assert_eq!(policy.repo(), repo, "{}", value);
+            assert_eq!(policy.aur(), aur, "{}", value);
+        }
+        assert!(AutoUpdate::parse("sometimes").is_err());
+        assert_eq!(Config::new().auto_update().unwrap(), AutoUpdate::All);
+    }
+
+    #[test]
+    fn test_auto_adopt_values() {
+        assert!(!Config::new().auto_adopt().unwrap());
+        assert!(
+            Config::parse("@option auto_adopt=true")
+                .unwrap()
+                .auto_adopt()
+                .unwrap()
+        );
+        assert!(
+            !Config::parse("@option auto_adopt=false")
+                .unwrap()
+                .auto_adopt()
+                .unwrap()
+        );
+        assert!(
+            Config::parse("@option auto_adopt=yes")
+                .unwrap()
+                .auto_adopt()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_source_check_options() {
+        let config = Config::new();
+        assert!(!config.enforce_lf().unwrap());
+        assert!(config.suspect_empty().contains(&"*.toml".to_string()));
+
+        let config =
+            Config::parse("@option enforce_lf=true\n@option suspect_empty=*.rc, config\n").unwrap();
+        assert!(config.enforce_lf().unwrap());
+        assert_eq!(config.suspect_empty(), vec!["*.rc", "config"]);
+
+        let config = Config::parse("@option suspect_empty=\n").unwrap();
+        assert!(config.suspect_empty().is_empty());
+    }
+
+    #[test]
+    fn test_stale_after_values() {
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+        assert_eq!(Config::new().stale_after().unwrap(), Some(7 * day));
+        let config = Config::parse(
+            "@option stale_after=2d
+",
+        )
+        .unwrap();
+        assert_eq!(config.stale_after().unwrap(), Some(2 * day));
+        let config = Config::parse(
+            "@option stale_after=off
+",
+        )
+        .unwrap();
+        assert_eq!(config.stale_after().unwrap(), None);
+        let config = Config::parse(
+            "@option stale_after=soon
+",
+        )
+        .unwrap();
+        assert!(config.stale_after().is_err());
+    }
+
+    #[test]
+    fn test_option_precedence_host_over_main_over_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("hosts")).unwrap();
+        std::fs::create_dir_all(root.join("groups")).unwrap();
+        std::fs::write(
+            root.join("main.owl"),
+            "@group servers\n@option auto_update=all\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("groups/servers.owl"),
+            "@option auto_update=repo-only\n@option mirror_refresh=reflector\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("hosts/server1.owl"),
+            "@option auto_update=never\n",
+        )
+        .unwrap();
+
+        // The host file beats main
+        let config = Config::load_for_host(root, "server1").unwrap();
+        let opt = config.option("auto_update").unwrap();
+        assert_eq!(opt.value, "never");
+        assert!(opt.source.ends_with("hosts/server1.owl"), "{}", opt.source);
+        assert_eq!(config.auto_update().unwrap(), AutoUpdate::Never);
+
+        // Main beats a group, which still fills in what main leaves unset
+        let config = Config::load_for_host(root, "desktop").unwrap();
+        let opt = config.option("auto_update").unwrap();
+        assert_eq!(opt.value, "all");
+        assert!(opt.source.ends_with("main.owl"), "{}", opt.source);
+        let opt = config.option("mirror_refresh").unwrap();
+        assert_eq!(opt.value, "reflector");
+        assert!(opt.source.ends_with("groups/servers.owl"), "{}", opt.source);
+    }
+}
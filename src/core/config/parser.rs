@@ -1,67 +1,226 @@
 use anyhow::{Result, anyhow};
-use std::collections::HashMap;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use super::{Config, Package};
+use super::{Config, ConfigOption};
+
+/// Unknown directives are errors in every file, as if each had `@strict` (`--strict`)
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Make unknown directives errors for the rest of the run
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Whether `--strict` was given
+pub(crate) fn strict() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
 
 impl Config {
     pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = std::fs::read_to_string(path)
+        let content = std::fs::read_to_string(path.as_ref())
             .map_err(|e| anyhow!("Failed to read config file: {}", e))?;
-        Self::parse(&content)
+        let mut config = match super::format::ConfigFormat::of_file(path.as_ref()) {
+            super::format::ConfigFormat::Owl => Self::parse(&content)?,
+            super::format::ConfigFormat::Toml => Self::parse_toml(&content)?,
+        };
+        let source = path.as_ref().to_string_lossy();
+        config.set_option_source(&source);
+        if let Some(root) = &config.dotfiles_root {
+            let resolved = resolve_dotfiles_root(root, path.as_ref())?;
+            for pkg in config.packages.values_mut() {
+                pkg.dotfiles_root = Some(resolved.clone());
+            }
+        }
+        for warning in &mut config.warnings {
+            *warning = format!("{}: {}", source, warning);
+        }
+        Ok(config)
     }
 
     pub fn parse(content: &str) -> Result<Self> {
+        Self::parse_with(content, strict())
+    }
+
+    /// Parse `content`; with `strict`, or `@strict` anywhere in it, an unknown
+    /// `@`/`:` directive is an error instead of being skipped
+    pub fn parse_with(content: &str, strict: bool) -> Result<Self> {
         let mut config = Config::new();
         let mut current_package: Option<String> = None;
         let mut in_packages_section = false;
+        let mut in_defaults = false;
 
-        for line in content.lines() {
-            let line = line.trim();
+        // Editors on some systems save a byte order mark
+        let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+        let strict = strict
+            || content
+                .lines()
+                .any(|line| crate::core::names::normalize_line(line) == "@strict");
+        for (index, line) in content.lines().enumerate() {
+            let line = crate::core::names::normalize_line(line);
+            let line = line.as_ref();
 
             // Skip empty lines and comments
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
 
-            Self::parse_line(
+            if line.starts_with('@') {
+                in_defaults = false;
+            }
+            if line == "@defaults" {
+                if !config.packages.is_empty() {
+                    return Err(anyhow!(
+                        "@defaults must come before any package in the file"
+                    ));
+                }
+                in_defaults = true;
+                in_packages_section = false;
+                current_package = None;
+                continue;
+            }
+            if in_defaults {
+                Self::parse_defaults_directive(&mut config, line)?;
+                continue;
+            }
+
+            let known = Self::parse_line(
                 &mut config,
                 &mut current_package,
                 &mut in_packages_section,
                 line,
+                index + 1,
             )?;
+            if !known && strict {
+                let directive = line.split_whitespace().next().unwrap_or(line);
+                return Err(anyhow!(
+                    "Unknown directive '{}' on line {} (unknown directives are errors under --strict and @strict)",
+                    directive,
+                    index + 1
+                ));
+            }
+        }
+
+        // Packages declared in a file with @dotfiles-root take their sources from there
+        if let Some(root) = &config.dotfiles_root {
+            for pkg in config.packages.values_mut() {
+                pkg.dotfiles_root = Some(root.clone());
+            }
         }
 
         Ok(config)
     }
 
+    /// Apply line `number`; `false` for an `@`/`:` directive that was not recognized
     fn parse_line(
         config: &mut Config,
         current_package: &mut Option<String>,
         in_packages_section: &mut bool,
         line: &str,
-    ) -> Result<()> {
+        number: usize,
+    ) -> Result<bool> {
+        if current_package.as_deref() == Some(super::SYSTEM_SERVICES)
+            && line.starts_with(':')
+            && !line.starts_with(":service")
+        {
+            let directive = line.split_whitespace().next().unwrap_or(line);
+            return Err(anyhow!(
+                "'{}' on line {}: @package {} only takes :service lines",
+                directive,
+                number,
+                super::SYSTEM_SERVICES
+            ));
+        }
         if line.starts_with("@package ") || line.starts_with("@pkg ") {
-            Self::parse_package_declaration(config, current_package, in_packages_section, line);
+            Self::parse_package_declaration(config, current_package, in_packages_section, line)?;
         } else if line == "@packages" || line == "@pkgs" {
             Self::parse_packages_section(in_packages_section, current_package);
         } else if line.starts_with(":config ") {
             Self::parse_config_directive(config, current_package, line, ":config ")?;
         } else if line.starts_with(":cfg ") {
             Self::parse_config_directive(config, current_package, line, ":cfg ")?;
+        } else if line == ":service" {
+            return Err(anyhow!(":service requires a unit name"));
         } else if line.starts_with(":service ") {
             Self::parse_service_directive(config, current_package, line)?;
         } else if line.starts_with(":env ") {
             Self::parse_package_env_directive(config, current_package, line)?;
+        } else if let Some(rest) = line.strip_prefix(":after ") {
+            if let Some(package) = current_package
+                .as_ref()
+                .and_then(|name| config.packages.get_mut(name))
+            {
+                for name in rest.split_whitespace() {
+                    crate::core::names::validate_package_name(name)?;
+                    if !package.after.iter().any(|dep| dep == name) {
+                        package.after.push(name.to_string());
+                    }
+                }
+            }
+        } else if let Some(rest) = line.strip_prefix(":requires ") {
+            Self::parse_requires_directive(config, current_package, rest, number)?;
+        } else if let Some(rest) = line.strip_prefix(":absent ") {
+            let path = crate::core::absent::parse_pattern(rest)?;
+            if let Some(package) = current_package
+                .as_ref()
+                .and_then(|name| config.packages.get_mut(name))
+                && !package.absent.contains(&path)
+            {
+                package.absent.push(path);
+            }
+        } else if let Some(rest) = line.strip_prefix("@absent ") {
+            let path = crate::core::absent::parse_pattern(rest)?;
+            if !config.absent.contains(&path) {
+                config.absent.push(path);
+            }
+        } else if let Some(rest) = line.strip_prefix(":min-version ") {
+            Self::parse_min_version_directive(config, current_package, rest)?;
         } else if line.starts_with("@env ") {
             Self::parse_global_env_directive(config, line)?;
+        } else if line == "@untracked-reset" {
+            config.untracked_reset = true;
+        } else if let Some(rest) = line.strip_prefix("@untracked ") {
+            for name in rest.split_whitespace() {
+                crate::core::names::validate_package_name(name)?;
+                if !config.untracked.iter().any(|p| p == name) {
+                    config.untracked.push(name.to_string());
+                }
+            }
+        } else if line.starts_with("@option ") {
+            Self::parse_option_directive(config, line)?;
+        } else if let Some(rest) = line.strip_prefix("@backups-keep ") {
+            let keep = rest
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Invalid @backups-keep '{}', expected a positive number",
+                        rest.trim()
+                    )
+                })?;
+            config.backups_keep = Some(keep);
+        } else if line.starts_with("@arch-aur-prefix ") {
+            Self::parse_arch_aur_prefix_directive(config, line)?;
+        } else if let Some(root) = line.strip_prefix("@dotfiles-root ") {
+            let root = root.trim();
+            if root.is_empty() {
+                return Err(anyhow!("@dotfiles-root requires a directory"));
+            }
+            config.dotfiles_root = Some(root.to_string());
         } else if line.starts_with("@group ") {
-            Self::parse_group_declaration(config, current_package, line);
+            Self::parse_group_declaration(config, current_package, line)?;
+        } else if line == "@strict" {
+            // Read before parsing, see `parse_with`
         } else if !line.starts_with('@') && !line.starts_with(':') && *in_packages_section {
-            Self::parse_package_in_section(config, line);
+            Self::parse_package_in_section(config, line)?;
+        } else if line.starts_with(['@', ':']) {
+            return Ok(false);
         }
-        // Ignore unknown lines
-        Ok(())
+        // Other unknown lines are ignored
+        Ok(true)
     }
 
     fn parse_package_declaration(
@@ -69,25 +228,94 @@ impl Config {
         current_package: &mut Option<String>,
         in_packages_section: &mut bool,
         line: &str,
-    ) {
+    ) -> Result<()> {
         *in_packages_section = false;
-        let name = if let Some(name) = line.strip_prefix("@package ") {
-            name.trim().to_string()
-        } else if let Some(name) = line.strip_prefix("@pkg ") {
-            name.trim().to_string()
-        } else {
+        let rest = line
+            .strip_prefix("@package ")
+            .or_else(|| line.strip_prefix("@pkg "))
             // This shouldn't happen since we check the prefix in parse_line
-            line.trim().to_string()
-        };
+            .unwrap_or(line);
+        let (name, options) = parse_package_name(rest)?;
         *current_package = Some(name.clone());
-        config.packages.insert(
-            name.clone(),
-            Package {
-                config: Vec::new(),
-                service: None,
-                env_vars: HashMap::new(),
-            },
-        );
+        if name == super::SYSTEM_SERVICES {
+            if options.aur || options.no_upgrade {
+                return Err(anyhow!(
+                    "@package {} installs nothing and takes no options",
+                    name
+                ));
+            }
+            return Ok(());
+        }
+        Self::declare_package(config, name, options);
+        Ok(())
+    }
+
+    /// A fresh package from `@defaults`, still required by whatever block required it
+    fn declare_package(config: &mut Config, name: String, options: PackageOptions) {
+        let mut package = config.defaults.package();
+        package.aur = options.aur;
+        package.no_upgrade = options.no_upgrade;
+        package.required_by = config
+            .packages
+            .get(&name)
+            .and_then(|existing| existing.required_by.clone());
+        config.packages.insert(name, package);
+    }
+
+    /// `:requires a b`: declare each name as a package of its own, recording the parent
+    fn parse_requires_directive(
+        config: &mut Config,
+        current_package: &Option<String>,
+        rest: &str,
+        number: usize,
+    ) -> Result<()> {
+        let Some(parent) = current_package.clone() else {
+            return Ok(());
+        };
+        for name in rest.split_whitespace() {
+            crate::core::names::validate_package_name(name)?;
+            if name == parent {
+                return Err(anyhow!("{} cannot :requires itself", parent));
+            }
+            if let Some(package) = config.packages.get_mut(&parent)
+                && !package.requires.iter().any(|required| required == name)
+            {
+                package.requires.push(name.to_string());
+            }
+            let required_by = super::RequiredBy {
+                parent: parent.clone(),
+                line: Some(number),
+            };
+            config
+                .packages
+                .entry(name.to_string())
+                .or_insert_with(|| config.defaults.package())
+                .required_by
+                .get_or_insert(required_by);
+        }
+        Ok(())
+    }
+
+    /// Directives inside an `@defaults` block
+    fn parse_defaults_directive(config: &mut Config, line: &str) -> Result<()> {
+        let Some(env_part) = line.strip_prefix(":env ") else {
+            return Err(anyhow!(
+                "Unsupported directive in @defaults: '{}' (only :env is allowed)",
+                line
+            ));
+        };
+        let Some((shell, key, value)) = parse_env_assignment(env_part)? else {
+            return Err(anyhow!(
+                "Invalid :env '{}', expected <key>=<value>",
+                env_part
+            ));
+        };
+        match shell {
+            Some(shell) => config.defaults.env_shells.insert(key.clone(), shell),
+            None => config.defaults.env_shells.remove(&key),
+        };
+        config.defaults.env_vars.insert(key, value);
+        Ok(())
     }
 
     fn parse_packages_section(
@@ -102,23 +330,18 @@ impl Config {
         config: &mut Config,
         current_package: &mut Option<String>,
         line: &str,
-    ) {
-        config
-            .groups
-            .push(line.strip_prefix("@group ").unwrap().trim().to_string());
+    ) -> Result<()> {
+        let (name, warning) = parse_group_name(line.strip_prefix("@group ").unwrap())?;
+        config.warnings.extend(warning);
+        config.groups.push(name);
         *current_package = None;
+        Ok(())
     }
 
-    fn parse_package_in_section(config: &mut Config, line: &str) {
-        let package_name = line.trim().to_string();
-        config.packages.insert(
-            package_name.clone(),
-            Package {
-                config: Vec::new(),
-                service: None,
-                env_vars: HashMap::new(),
-            },
-        );
+    fn parse_package_in_section(config: &mut Config, line: &str) -> Result<()> {
+        let (name, options) = parse_package_name(line)?;
+        Self::declare_package(config, name, options);
+        Ok(())
     }
 
     #[allow(clippy::collapsible_if)]
@@ -129,6 +352,16 @@ impl Config {
         prefix: &str,
     ) -> Result<()> {
         let rest = line.strip_prefix(prefix).unwrap();
+        let (options, mapping) = crate::core::dotfiles::split_options(rest.trim())?;
+        if options.from.is_some()
+            && (mapping.starts_with(crate::core::dotfiles::OWL_ROOT_PREFIX)
+                || mapping.starts_with('/'))
+        {
+            return Err(anyhow!(
+                ":config {} cannot use [from=] with an owl-root or absolute source",
+                rest.trim()
+            ));
+        }
         if let Some((source, sink)) = rest.split_once(" -> ") {
             if let Some(pkg_name) = current_package {
                 if let Some(package) = config.packages.get_mut(pkg_name) {
@@ -139,6 +372,12 @@ impl Config {
                 }
             }
         } else {
+            if mapping.starts_with(crate::core::dotfiles::OWL_ROOT_PREFIX) {
+                return Err(anyhow!(
+                    ":config {} needs an explicit destination (`-> ~/path`)",
+                    rest.trim()
+                ));
+            }
             // Handle configs without explicit source (assume source is same as destination filename)
             if let Some(pkg_name) = current_package {
                 if let Some(package) = config.packages.get_mut(pkg_name) {
@@ -161,14 +400,56 @@ impl Config {
             .next()
             .unwrap_or(service_part)
             .trim();
+        let service_name = crate::core::names::normalize_service_name(service_name)
+            .map_err(|e| anyhow!(":service {}: {}", service_part.trim(), e))?;
         if let Some(pkg_name) = current_package {
-            if let Some(package) = config.packages.get_mut(pkg_name) {
-                package.service = Some(service_name.to_string());
+            if pkg_name == super::SYSTEM_SERVICES {
+                if !config.system_services.contains(&service_name) {
+                    config.system_services.push(service_name);
+                }
+            } else if let Some(package) = config.packages.get_mut(pkg_name) {
+                package.service = Some(service_name);
             }
         }
         Ok(())
     }
 
+    /// `:min-version 3.7` or `:min-version 3.7 [strict]`
+    fn parse_min_version_directive(
+        config: &mut Config,
+        current_package: &Option<String>,
+        rest: &str,
+    ) -> Result<()> {
+        let (version, strict) = match rest.trim().split_once('[') {
+            Some((version, options)) => {
+                let options = options
+                    .strip_suffix(']')
+                    .ok_or_else(|| anyhow!("Unclosed option list in ':min-version {}'", rest))?;
+                if options.trim() != "strict" {
+                    return Err(anyhow!(
+                        "Unknown :min-version option '{}' (expected strict)",
+                        options.trim()
+                    ));
+                }
+                (version.trim(), true)
+            }
+            None => (rest.trim(), false),
+        };
+        if version.is_empty() || version.contains(char::is_whitespace) {
+            return Err(anyhow!(":min-version requires a single version"));
+        }
+        if let Some(package) = current_package
+            .as_ref()
+            .and_then(|name| config.packages.get_mut(name))
+        {
+            package.min_version = Some(crate::core::version::MinVersion {
+                version: version.to_string(),
+                strict,
+            });
+        }
+        Ok(())
+    }
+
     #[allow(clippy::collapsible_if)]
     fn parse_package_env_directive(
         config: &mut Config,
@@ -176,25 +457,705 @@ impl Config {
         line: &str,
     ) -> Result<()> {
         let env_part = line.strip_prefix(":env ").unwrap();
-        if let Some((key, value)) = env_part.split_once('=') {
+        if let Some((shell, key, value)) = parse_env_assignment(env_part)? {
             if let Some(pkg_name) = current_package {
                 if let Some(package) = config.packages.get_mut(pkg_name) {
-                    package
-                        .env_vars
-                        .insert(key.trim().to_string(), value.trim().to_string());
+                    // Package-level values override @defaults key by key
+                    package.default_env_keys.remove(&key);
+                    match shell {
+                        Some(shell) => package.env_shells.insert(key.clone(), shell),
+                        None => package.env_shells.remove(&key),
+                    };
+                    package.env_vars.insert(key, value);
                 }
             }
         }
         Ok(())
     }
 
+    fn parse_option_directive(config: &mut Config, line: &str) -> Result<()> {
+        let rest = line.strip_prefix("@option ").unwrap().trim();
+        let (key, value) = rest
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid @option '{}', expected <key>=<value>", rest))?;
+        crate::core::names::validate_key(key.trim(), "option key")?;
+        config.options.insert(
+            key.trim().to_string(),
+            ConfigOption {
+                value: value.trim().to_string(),
+                source: String::new(),
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_arch_aur_prefix_directive(config: &mut Config, line: &str) -> Result<()> {
+        let rest = line.strip_prefix("@arch-aur-prefix ").unwrap().trim();
+        let (arch, suffix) = rest
+            .split_once(':')
+            .and_then(|(arch, opt)| opt.trim().strip_prefix("suffix=").map(|s| (arch, s)))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid @arch-aur-prefix '{}', expected <arch>:suffix=<suffix>",
+                    rest
+                )
+            })?;
+        config
+            .arch_aur_suffixes
+            .insert(arch.trim().to_string(), suffix.trim().to_string());
+        Ok(())
+    }
+
     fn parse_global_env_directive(config: &mut Config, line: &str) -> Result<()> {
         let env_part = line.strip_prefix("@env ").unwrap();
-        if let Some((key, value)) = env_part.split_once('=') {
-            config
-                .env_vars
-                .insert(key.trim().to_string(), value.trim().to_string());
+        if let Some((key, value)) = split_env_assignment(env_part) {
+            config.env_vars.insert(key, value);
         }
         Ok(())
     }
 }
+
+/// Options in brackets after a package name
+#[derive(Debug, Clone, Copy, Default)]
+struct PackageOptions {
+    aur: bool,
+    no_upgrade: bool,
+}
+
+/// `name`, or `name [aur]`, `name [no-upgrade]`, `name [aur, no-upgrade]`
+///
+/// Names are checked with [`crate::core::names::validate_package_name`].
+fn parse_package_name(raw: &str) -> Result<(String, PackageOptions)> {
+    let raw = raw.trim();
+    let Some((name, options)) = raw.split_once('[') else {
+        crate::core::names::validate_package_name(raw)?;
+        return Ok((raw.to_string(), PackageOptions::default()));
+    };
+    let options = options
+        .strip_suffix(']')
+        .ok_or_else(|| anyhow!("Unclosed option list in package '{}'", raw))?;
+    let mut parsed = PackageOptions::default();
+    for option in options.split(',').map(str::trim) {
+        match option {
+            "aur" => parsed.aur = true,
+            "no-upgrade" => parsed.no_upgrade = true,
+            other => {
+                return Err(anyhow!(
+                    "Unknown package option '{}' (expected aur or no-upgrade)",
+                    other
+                ));
+            }
+        }
+    }
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(anyhow!("Missing package name before '[{}]'", options));
+    }
+    crate::core::names::validate_package_name(name)?;
+    Ok((name.to_string(), parsed))
+}
+
+/// Split `[shell=fish] KEY=value` into its shell restriction, key and value
+///
+/// Returns `None` when there is no `=` (such lines are ignored, as before).
+fn parse_env_assignment(
+    env_part: &str,
+) -> Result<Option<(Option<crate::core::env::Shell>, String, String)>> {
+    let mut rest = env_part.trim_start();
+    let mut shell = None;
+    if let Some(options) = rest.strip_prefix('[') {
+        let (options, after) = options
+            .split_once(']')
+            .ok_or_else(|| anyhow!("Unterminated option list in :env '{}'", env_part))?;
+        for option in options.split(',').map(str::trim) {
+            match option.split_once('=') {
+                Some(("shell", name)) => {
+                    shell = Some(crate::core::env::Shell::parse(name.trim())?);
+                }
+                _ => return Err(anyhow!("Unknown :env option '{}'", option)),
+            }
+        }
+        rest = after;
+    }
+    let Some((key, value)) = split_env_assignment(rest) else {
+        return Ok(None);
+    };
+    crate::core::names::validate_key(&key, "env key")?;
+    Ok(Some((shell, key, value)))
+}
+
+/// `KEY=value`, or `KEY+=value` which appends to the inherited value: `KEY=$KEY:value`
+///
+/// `KEY <file:PATH>` and `KEY <cmd:COMMAND>` keep the source as the value; it
+/// is read when the env files are written (see `core::env::ValueSource`).
+fn split_env_assignment(assignment: &str) -> Option<(String, String)> {
+    if let Some((key, source)) = assignment.trim().split_once(char::is_whitespace)
+        && !key.contains('=')
+        && source.trim_start().starts_with('<')
+    {
+        return Some((key.to_string(), source.trim().to_string()));
+    }
+    let (key, value) = assignment.split_once('=')?;
+    let (key, value) = (key.trim(), value.trim());
+    Some(match key.strip_suffix('+') {
+        Some(key) => {
+            let key = key.trim_end();
+            (key.to_string(), format!("${}:{}", key, value))
+        }
+        None => (key.to_string(), value.to_string()),
+    })
+}
+
+/// Resolve `@dotfiles-root` to an absolute directory that must exist
+///
+/// `~` expands to the home directory; relative paths are relative to the declaring file.
+fn resolve_dotfiles_root(root: &str, declared_in: &Path) -> Result<String> {
+    let path = if let Some(rest) = root.strip_prefix("~/") {
+        crate::internal::environment::get().home()?.join(rest)
+    } else if Path::new(root).is_absolute() {
+        Path::new(root).to_path_buf()
+    } else {
+        declared_in
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(root)
+    };
+    if !path.is_dir() {
+        return Err(anyhow!(
+            "@dotfiles-root {} in {} does not exist",
+            path.display(),
+            declared_in.display()
+        ));
+    }
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Whether a group name maps cleanly onto `groups/<name>.owl`
+fn is_filename_safe(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Parse the name in `@group <name>` or `@group "<name>"`
+///
+/// Names with path separators are rejected. Unquoted names with spaces and
+/// names with other characters that are awkward in filenames produce a warning.
+fn parse_group_name(raw: &str) -> Result<(String, Option<String>)> {
+    let raw = raw.trim();
+    let (name, quoted) = match raw.strip_prefix('"') {
+        Some(rest) => {
+            let name = rest
+                .strip_suffix('"')
+                .ok_or_else(|| anyhow!("Unterminated quoted group name: @group {}", raw))?;
+            (name, true)
+        }
+        None => (raw, false),
+    };
+
+    if name.is_empty() {
+        return Err(anyhow!("Empty group name"));
+    }
+    if name.contains('/') || name.contains('\\') || name == "." || name == ".." {
+        return Err(anyhow!(
+            "Invalid group name '{}': group names cannot contain path separators",
+            name
+        ));
+    }
+
+    let warning = if !quoted && name.contains(char::is_whitespace) {
+        Some(format!(
+            "Group name '{}' contains spaces; quote it (@group \"{}\") if this is intended",
+            name, name
+        ))
+    } else if !is_filename_safe(&name.replace(' ', "")) {
+        Some(format!(
+            "Group name '{}' contains characters that are not filename-safe",
+            name
+        ))
+    } else {
+        None
+    };
+    Ok((name.to_string(), warning))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_group_name() {
+        let config = Config::parse("@group dev-tools").unwrap();
+        assert_eq!(config.groups, vec!["dev-tools"]);
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_spaced_group_name_warns() {
+        let config = Config::parse("@group my group").unwrap();
+        assert_eq!(config.groups, vec!["my group"]);
+        assert_eq!(config.warnings.len(), 1);
+        assert!(config.warnings[0].contains("contains spaces"));
+
+        // Quoting makes the intent explicit
+        let config = Config::parse("@group \"my group\"").unwrap();
+        assert_eq!(config.groups, vec!["my group"]);
+        assert!(config.warnings.is_empty());
+    }
+
+    fn dotfiles_root_fixture() -> (tempfile::TempDir, Config) {
+        let dir = tempfile::tempdir().unwrap();
+        let team_root = dir.path().join("team/dotfiles");
+        std::fs::create_dir_all(&team_root).unwrap();
+        std::fs::write(
+            dir.path().join("team.owl"),
+            format!(
+                "@dotfiles-root {}\n@package proxy\n:config proxy.conf\n@package git\n:config gitconfig\n",
+                team_root.display()
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("host.owl"),
+            "@package git\n:config gitconfig\n",
+        )
+        .unwrap();
+
+        // Host (higher precedence) first, then the team group
+        let mut config = Config::parse_file(dir.path().join("host.owl")).unwrap();
+        config.add_if_not_exists(Config::parse_file(dir.path().join("team.owl")).unwrap());
+        (dir, config)
+    }
+
+    #[test]
+    fn test_dotfiles_root_applies_to_group_packages() {
+        let (dir, config) = dotfiles_root_fixture();
+        let team_root = dir.path().join("team/dotfiles");
+        assert_eq!(
+            config.packages["proxy"].dotfiles_root.as_deref(),
+            Some(team_root.to_str().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_host_override_uses_default_root() {
+        let (_dir, config) = dotfiles_root_fixture();
+        assert_eq!(config.packages["git"].dotfiles_root, None);
+    }
+
+    #[test]
+    fn test_missing_dotfiles_root_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("team.owl");
+        std::fs::write(&file, "@dotfiles-root does/not/exist\n@packages\ncurl\n").unwrap();
+        let err = Config::parse_file(&file).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+
+    #[test]
+    fn test_defaults_apply_to_following_packages() {
+        let config = Config::parse(
+            "@defaults\n:env PATH=$HOME/dev/bin:$PATH\n:env GOFLAGS=-mod=mod\n\
+             @package go\n:env GOFLAGS=-mod=vendor\n@packages\nrustup\n",
+        )
+        .unwrap();
+
+        let rustup = &config.packages["rustup"];
+        assert_eq!(rustup.env_vars["PATH"], "$HOME/dev/bin:$PATH");
+        assert!(rustup.default_env_keys.contains("GOFLAGS"));
+
+        // Package-level values win key by key
+        let go = &config.packages["go"];
+        assert_eq!(go.env_vars["GOFLAGS"], "-mod=vendor");
+        assert_eq!(go.env_vars["PATH"], "$HOME/dev/bin:$PATH");
+        assert!(!go.default_env_keys.contains("GOFLAGS"));
+        assert!(go.default_env_keys.contains("PATH"));
+    }
+
+    #[test]
+    fn test_defaults_do_not_leak_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("host.owl"),
+            "@package neovim\n:config nvim\n@group dev\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("dev.owl"),
+            "@defaults\n:env PATH=$HOME/dev/bin:$PATH\n@packages\ngo\nneovim\n",
+        )
+        .unwrap();
+
+        let mut config = Config::parse_file(dir.path().join("host.owl")).unwrap();
+        config.add_if_not_exists(Config::parse_file(dir.path().join("dev.owl")).unwrap());
+        assert_eq!(
+            config.packages["go"].env_vars["PATH"],
+            "$HOME/dev/bin:$PATH"
+        );
+        // The host definition of neovim wins and never sees dev.owl's defaults
+        assert!(config.packages["neovim"].env_vars.is_empty());
+        assert!(config.defaults.env_vars.is_empty());
+    }
+
+    #[test]
+    fn test_env_append_syntax() {
+        let config =
+            Config::parse("@env PATH+=$HOME/.local/bin\n@package go\n:env GOPATH += ~/go\n")
+                .unwrap();
+        assert_eq!(config.env_vars["PATH"], "$PATH:$HOME/.local/bin");
+        assert_eq!(config.packages["go"].env_vars["GOPATH"], "$GOPATH:~/go");
+        // Only a `+` right before the `=` appends
+        let config = Config::parse("@env A=b+=c\n").unwrap();
+        assert_eq!(config.env_vars["A"], "b+=c");
+    }
+
+    #[test]
+    fn test_env_shell_option() {
+        let config =
+            Config::parse("@package fish\n:env [shell=fish] fish_greeting=\n:env EDITOR=nvim\n")
+                .unwrap();
+        let fish = &config.packages["fish"];
+        assert_eq!(fish.env_vars["fish_greeting"], "");
+        assert_eq!(
+            fish.env_shells.get("fish_greeting"),
+            Some(&crate::core::env::Shell::Fish)
+        );
+        assert!(!fish.env_shells.contains_key("EDITOR"));
+
+        assert!(Config::parse("@package a\n:env [shell=zsh] A=1\n").is_err());
+        assert!(Config::parse("@package a\n:env [os=arch] A=1\n").is_err());
+    }
+
+    #[test]
+    fn test_min_version_directive() {
+        let config = Config::parse(
+            "@package fish\n:min-version 3.7\n@package tmux\n:min-version 3.4 [strict]\n",
+        )
+        .unwrap();
+        let fish = config.packages["fish"].min_version.as_ref().unwrap();
+        assert_eq!((fish.version.as_str(), fish.strict), ("3.7", false));
+        let tmux = config.packages["tmux"].min_version.as_ref().unwrap();
+        assert_eq!(tmux.directive(), ":min-version 3.4 [strict]");
+
+        assert!(Config::parse("@package a\n:min-version 1.0 [loose]\n").is_err());
+        assert!(Config::parse("@package a\n:min-version 1.0 2.0\n").is_err());
+    }
+
+    #[test]
+    fn test_after_directive() {
+        let config =
+            Config::parse("@package app\n:after db cache\n:after db\n@package db\n").unwrap();
+        assert_eq!(config.packages["app"].after, vec!["db", "cache"]);
+        assert!(config.packages["db"].after.is_empty());
+    }
+
+    #[test]
+    fn test_system_services_block_takes_many_services_and_no_package() {
+        let config = Config::parse(
+            "@package _system-services
+:service local-backup
+:service fstrim.timer
+\
+             :service local-backup.service
+@package docker
+:service docker
+",
+        )
+        .unwrap();
+        assert_eq!(
+            config.system_services,
+            vec!["local-backup.service", "fstrim.timer"]
+        );
+        assert!(
+            !config
+                .packages
+                .contains_key(crate::core::config::SYSTEM_SERVICES)
+        );
+        assert_eq!(
+            config.service_order().unwrap(),
+            vec!["docker.service", "fstrim.timer", "local-backup.service"]
+        );
+
+        for (content, expected) in [
+            (
+                "@package _system-services
+:config a -> ~/a
+",
+                "':config' on line 2",
+            ),
+            (
+                "@package _system-services [aur]
+",
+                "installs nothing and takes no options",
+            ),
+        ] {
+            let err = Config::parse(content).unwrap_err().to_string();
+            assert!(err.contains(expected), "{:?}: {}", content, err);
+        }
+    }
+
+    #[test]
+    fn test_service_names_are_checked_and_get_a_suffix() {
+        let config = Config::parse(
+            "@package docker\n:service docker\n@package sway\n:service sway-session.target [user]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.packages["docker"].service.as_deref(),
+            Some("docker.service")
+        );
+        assert_eq!(
+            config.packages["sway"].service.as_deref(),
+            Some("sway-session.target")
+        );
+        assert_eq!(
+            Config::parse("@package docker\n:service docker.service\n")
+                .unwrap()
+                .packages["docker"]
+                .service,
+            config.packages["docker"].service
+        );
+
+        for (content, expected) in [
+            ("@package a\n:service \n", ":service requires a unit name"),
+            ("@package a\n:service [user]\n", "Empty service name"),
+            (
+                "@package a\n:service docker compose\n",
+                "SPACE at position 7",
+            ),
+        ] {
+            let err = Config::parse(content).unwrap_err().to_string();
+            assert!(err.contains(expected), "{:?}: {}", content, err);
+        }
+    }
+
+    #[test]
+    fn test_requires_declares_each_package() {
+        let config = Config::parse(
+            "@package devtools\n:requires gdb strace\n:service docker\n:requires gdb\n\
+             @package strace\n:env STRACE=1\n@package git\n",
+        )
+        .unwrap();
+        let devtools = &config.packages["devtools"];
+        assert_eq!(devtools.requires, vec!["gdb", "strace"]);
+        // Directives after :requires still belong to the parent
+        assert_eq!(devtools.service.as_deref(), Some("docker.service"));
+        assert_eq!(devtools.required_by, None);
+        let required = super::super::RequiredBy {
+            parent: "devtools".to_string(),
+            line: Some(2),
+        };
+        assert_eq!(config.packages["gdb"].required_by.as_ref(), Some(&required));
+        // Declaring a required package again keeps where it was required
+        assert_eq!(
+            config.packages["strace"].required_by.as_ref(),
+            Some(&required)
+        );
+        assert_eq!(config.packages["strace"].env_vars["STRACE"], "1");
+        assert_eq!(config.packages["git"].required_by, None);
+
+        assert!(Config::parse("@package a\n:requires a\n").is_err());
+        assert!(Config::parse("@package a\n:requires b/c\n").is_err());
+    }
+
+    #[test]
+    fn test_aur_hint() {
+        let config =
+            Config::parse("@package yay [aur]\n@pkg fd\n@packages\nparu [aur]\nripgrep\n").unwrap();
+        let mut hinted: Vec<String> = config.aur_hinted().into_iter().collect();
+        hinted.sort();
+        assert_eq!(hinted, vec!["paru", "yay"]);
+        assert!(config.packages.contains_key("ripgrep"));
+        assert!(Config::parse("@package yay [repo]\n").is_err());
+        assert!(Config::parse("@package yay [aur\n").is_err());
+        assert!(Config::parse("@packages\n[aur]\n").is_err());
+    }
+
+    #[test]
+    fn test_no_upgrade_option() {
+        let config = Config::parse(
+            "@package nvidia-dkms [no-upgrade]\n@package yay [aur, no-upgrade]\n\
+             @packages\nlinux [no-upgrade]\nripgrep\n",
+        )
+        .unwrap();
+        assert_eq!(config.held_packages(), vec!["linux", "nvidia-dkms", "yay"]);
+        assert!(config.packages["yay"].aur);
+        assert!(!config.packages["nvidia-dkms"].aur);
+        assert!(!config.packages["ripgrep"].no_upgrade);
+        let err = Config::parse("@package a [aur, pinned]\n").unwrap_err();
+        assert!(err.to_string().contains("'pinned'"), "{}", err);
+    }
+
+    #[test]
+    fn test_backups_keep_directive() {
+        let config = Config::parse("@backups-keep 3\n").unwrap();
+        assert_eq!(config.backups_keep, Some(3));
+        assert_eq!(config.keep_backups(), 3);
+        assert_eq!(Config::parse("").unwrap().keep_backups(), 5);
+        assert!(Config::parse("@backups-keep 0\n").is_err());
+        assert!(Config::parse("@backups-keep many\n").is_err());
+    }
+
+    #[test]
+    fn test_owl_root_source_needs_destination() {
+        let config = Config::parse("@package zsh\n:config @/shared/zshrc -> ~/.zshrc\n").unwrap();
+        assert_eq!(
+            config.packages["zsh"].config,
+            vec!["@/shared/zshrc -> ~/.zshrc"]
+        );
+        assert!(Config::parse("@package zsh\n:config @/shared/zshrc\n").is_err());
+    }
+
+    #[test]
+    fn test_config_from_option() {
+        let config =
+            Config::parse("@package neovim\n:config [from=hosts/laptop] nvim -> ~/.config/nvim\n")
+                .unwrap();
+        assert_eq!(
+            config.packages["neovim"].config,
+            vec!["[from=hosts/laptop] nvim -> ~/.config/nvim"]
+        );
+        for (line, expected) in [
+            (":config [from=] nvim", "needs a directory"),
+            (
+                ":config [form=hosts] nvim",
+                "Unknown :config option 'form=hosts'",
+            ),
+            (":config [from=hosts nvim", "Unterminated option"),
+            (":config [host=] nvim", "needs a hostname"),
+            (":config [host=laptop,] nvim", "needs a hostname"),
+            (
+                ":config [host=a] [host=b] nvim -> ~/.config/nvim",
+                "sets [host=] twice",
+            ),
+            (
+                ":config [from=hosts] @/shared/nvim -> ~/.config/nvim",
+                "owl-root or absolute source",
+            ),
+            (
+                ":config [from=hosts] /etc/skel/.bashrc -> ~/.bashrc",
+                "owl-root or absolute source",
+            ),
+        ] {
+            let err = Config::parse(&format!("@package neovim\n{}\n", line))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains(expected), "{}: {}", line, err);
+        }
+    }
+
+    #[test]
+    fn test_misspelled_directive_ignored_unless_strict() {
+        let content = "@package neovim\n:confgi nvim -> ~/.config/nvim\n:env EDITOR=nvim\n";
+        let config = Config::parse_with(content, false).unwrap();
+        assert!(config.packages["neovim"].config.is_empty());
+        assert_eq!(config.packages["neovim"].env_vars["EDITOR"], "nvim");
+
+        let err = Config::parse_with(content, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown directive ':confgi' on line 2 \
+             (unknown directives are errors under --strict and @strict)"
+        );
+        // A directive missing its argument is not recognized either
+        assert!(Config::parse_with("@package neovim\n:config\n", true).is_err());
+    }
+
+    #[test]
+    fn test_strict_directive_applies_to_whole_file() {
+        let err = Config::parse_with("@pakage neovim\n@strict\n", false).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Unknown directive '@pakage' on line 1")
+        );
+        let config = Config::parse_with("@strict\n@packages\nfd\n", false).unwrap();
+        assert!(config.packages.contains_key("fd"));
+    }
+
+    #[test]
+    fn test_strict_error_names_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("groups")).unwrap();
+        std::fs::write(dir.path().join("main.owl"), "@group dev\n").unwrap();
+        std::fs::write(
+            dir.path().join("groups/dev.owl"),
+            "@strict\n@package git\n:servcie git-daemon.socket\n",
+        )
+        .unwrap();
+        let err = Config::load_for_host(dir.path(), "nohost").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("groups/dev.owl: Unknown directive ':servcie' on line 3"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_defaults_after_package_rejected() {
+        assert!(Config::parse("@package go\n@defaults\n:env A=1\n").is_err());
+        assert!(Config::parse("@defaults\n:config nvim\n").is_err());
+    }
+
+    #[test]
+    fn test_group_name_with_slash_rejected() {
+        assert!(Config::parse("@group ../secrets").is_err());
+        assert!(Config::parse("@group \"dev/rust\"").is_err());
+        assert!(Config::parse("@group \"unterminated").is_err());
+        assert!(Config::parse("@group \"\"").is_err());
+    }
+
+    #[test]
+    fn test_absent_paths() {
+        let config = Config::parse(
+            "@absent ~/.xinitrc\n@package fish\n:absent ~/.config/fish/fishd.*\n\
+             :absent ~/.config/fish/fishd.*\n@absent ~/.xinitrc\n",
+        )
+        .unwrap();
+        assert_eq!(config.absent, ["~/.xinitrc"]);
+        assert_eq!(config.packages["fish"].absent, ["~/.config/fish/fishd.*"]);
+        assert_eq!(
+            config.absent_paths(),
+            ["~/.config/fish/fishd.*", "~/.xinitrc"]
+        );
+        assert!(Config::parse("@absent ~/.config/*/old").is_err());
+        assert!(Config::parse("@package fish\n:absent fishd").is_err());
+    }
+
+    #[test]
+    fn test_unicode_whitespace_is_trimmed_from_directives() {
+        let config =
+            Config::parse("@package nvim\u{a0}\n@package\u{a0}git [aur]\u{3000}\n").unwrap();
+        assert!(config.packages.contains_key("nvim"));
+        assert!(config.packages["git"].aur);
+        let config = Config::parse("\u{feff}@packages\nripgrep\u{a0}\n").unwrap();
+        assert!(config.packages.contains_key("ripgrep"));
+    }
+
+    #[test]
+    fn test_invisible_characters_in_names_are_rejected() {
+        let err = Config::parse("@packages\nne\u{200b}ovim\n").unwrap_err();
+        assert!(
+            err.to_string().starts_with(
+                "Invalid package name 'ne\\u{200b}ovim': U+200B ZERO WIDTH SPACE at position 3"
+            ),
+            "{}",
+            err
+        );
+        let err = Config::parse("@package \u{ff47}it [aur]").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("U+FF47 FULLWIDTH 'g' at position 1"),
+            "{}",
+            err
+        );
+        let err = Config::parse("@package git\n:after c\u{301}url\n").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("U+0301 COMBINING MARK at position 2"),
+            "{}",
+            err
+        );
+        let err = Config::parse("@option auto\u{a0}update=all").unwrap_err();
+        assert!(err.to_string().contains("option key"), "{}", err);
+    }
+}
@@ -0,0 +1,399 @@
+//! Every file's contribution to a package and what the merge did with it
+//! (`owl config trace`)
+//!
+//! `owl explain` shows the merged result and which file each field came from.
+//! A trace is the story behind it: in load order, each file that declared the
+//! package, every field it set, and whether that value was kept or lost to a
+//! file with a better precedence rank. The loader only keeps this journal when
+//! asked (`Config::load_for_host_traced`); otherwise `Config::journal` is
+//! `None` and merging records nothing.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use super::Package;
+use super::options::PackageMerge;
+
+/// What the merge did with one contribution
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum Outcome {
+    /// The value is in the merged package
+    Kept,
+    /// A later declaration was merged field by field into the first one
+    Merged { into: String },
+    /// A file with a better rank already set the field
+    Overridden { by: String, by_rank: usize },
+    /// Left out of the merge for another reason
+    Dropped { reason: String },
+}
+
+/// One contribution of one file to a package
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JournalEntry {
+    /// Path relative to the owl root, e.g. `groups/dev.owl`
+    pub file: String,
+    /// Precedence rank of the file; 1 wins over everything else
+    pub rank: usize,
+    /// `declared`, `config`, `service`, `env EDITOR`, ...
+    pub directive: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl JournalEntry {
+    /// The contribution as written in a config file
+    pub fn describe(&self) -> String {
+        let value = self.value.as_deref().unwrap_or_default();
+        match self.directive.as_str() {
+            "declared" => "declared".to_string(),
+            "aur" => "[aur]".to_string(),
+            "min-version" => value.to_string(),
+            directive => match directive.strip_prefix("env ") {
+                Some(key) => format!(":env {}={}", key, value),
+                None => format!(":{} {}", directive, value),
+            },
+        }
+    }
+}
+
+/// Merge events per package, recorded while files are loaded
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    /// Files in load order, which is also precedence order
+    files: Vec<String>,
+    pub packages: HashMap<String, Vec<JournalEntry>>,
+}
+
+impl Journal {
+    /// Rank of `file`, counting it as loaded if it is new
+    fn rank(&mut self, file: &str) -> usize {
+        match self.files.iter().position(|f| f == file) {
+            Some(index) => index + 1,
+            None => {
+                self.files.push(file.to_string());
+                self.files.len()
+            }
+        }
+    }
+
+    /// Record `file`'s declaration of `name`, before it is merged into
+    /// `current` (the package merged from better-ranked files so far)
+    pub(super) fn record(
+        &mut self,
+        file: &str,
+        name: &str,
+        declared: &Package,
+        current: Option<&Package>,
+        merge: PackageMerge,
+    ) {
+        let rank = self.rank(file);
+        let entries = self.packages.entry(name.to_string()).or_default();
+        let first = entries.first().map(|e| (e.file.clone(), e.rank));
+        let entry = |directive: &str, value: Option<String>, outcome: Outcome| JournalEntry {
+            file: file.to_string(),
+            rank,
+            directive: directive.to_string(),
+            value,
+            outcome,
+        };
+
+        let (current, (first_file, first_rank)) = match (current, first) {
+            (Some(current), Some(first)) => (current, first),
+            _ => {
+                entries.push(entry("declared", None, Outcome::Kept));
+                for (directive, value) in fields(declared) {
+                    entries.push(entry(&directive, Some(value), Outcome::Kept));
+                }
+                return;
+            }
+        };
+        let replaced = Outcome::Overridden {
+            by: first_file.clone(),
+            by_rank: first_rank,
+        };
+        let mut recorded = vec![entry(
+            "declared",
+            None,
+            match merge {
+                PackageMerge::Fields => Outcome::Merged {
+                    into: first_file.clone(),
+                },
+                PackageMerge::Replace => replaced.clone(),
+            },
+        )];
+        let set: HashSet<String> = fields(current).into_iter().map(|(d, _)| d).collect();
+        for (directive, value) in fields(declared) {
+            let outcome = if merge == PackageMerge::Replace {
+                replaced.clone()
+            } else if directive == "aur" {
+                // `[aur]` anywhere applies
+                Outcome::Kept
+            } else if set.contains(&directive) {
+                let (by, rank) = entries
+                    .iter()
+                    .find(|e| e.directive == directive && e.outcome == Outcome::Kept)
+                    .map(|e| (e.file.clone(), e.rank))
+                    .unwrap_or((first_file.clone(), first_rank));
+                Outcome::Overridden { by, by_rank: rank }
+            } else if directive
+                .strip_prefix("env ")
+                .is_some_and(|key| declared.default_env_keys.contains(key))
+            {
+                Outcome::Dropped {
+                    reason: "from @defaults, which only fills the file's own declarations"
+                        .to_string(),
+                }
+            } else {
+                Outcome::Kept
+            };
+            recorded.push(entry(&directive, Some(value), outcome));
+        }
+        entries.extend(recorded);
+    }
+}
+
+/// The fields a declaration sets, as `(directive, value)`
+fn fields(package: &Package) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    if !package.config.is_empty() {
+        fields.push(("config".to_string(), package.config.join(", ")));
+    }
+    if let Some(service) = &package.service {
+        fields.push(("service".to_string(), service.clone()));
+    }
+    if let Some(min_version) = &package.min_version {
+        fields.push(("min-version".to_string(), min_version.directive()));
+    }
+    if !package.after.is_empty() {
+        fields.push(("after".to_string(), package.after.join(" ")));
+    }
+    if package.aur {
+        fields.push(("aur".to_string(), "yes".to_string()));
+    }
+    if package.no_upgrade {
+        fields.push(("no-upgrade".to_string(), "yes".to_string()));
+    }
+    let mut keys: Vec<&String> = package.env_vars.keys().collect();
+    keys.sort();
+    for key in keys {
+        if let Some(value) = super::explain::env_value(package, key) {
+            fields.push((format!("env {}", key), value));
+        }
+    }
+    fields
+}
+
+/// Each file in load order with what it contributed and what became of it
+pub fn render_text(package: &str, entries: &[JournalEntry]) -> String {
+    let mut out = format!("[{}]\n", package);
+    let width = entries
+        .iter()
+        .map(|e| e.describe().len())
+        .max()
+        .unwrap_or(0);
+    let mut file = None;
+    for entry in entries {
+        if file != Some(&entry.file) {
+            out.push_str(&format!("  #{} {}\n", entry.rank, entry.file));
+            file = Some(&entry.file);
+        }
+        let outcome = match &entry.outcome {
+            Outcome::Kept => "kept".to_string(),
+            Outcome::Merged { into } => format!("merged into {}", into),
+            Outcome::Overridden { by, by_rank } => {
+                format!("overridden by {} (#{})", by, by_rank)
+            }
+            Outcome::Dropped { reason } => format!("dropped: {}", reason),
+        };
+        out.push_str(&format!(
+            "    {:<width$}  {}\n",
+            entry.describe(),
+            outcome,
+            width = width
+        ));
+    }
+    out.push_str(
+        "\n#1 is main.owl, then the host file, then groups in load order; \
+         each field keeps the value of the best-ranked file that sets it\n",
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::Config;
+    use std::fs;
+
+    /// A group sets config and env, the host overrides config, main declares fish bare
+    fn fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("hosts")).unwrap();
+        fs::create_dir_all(root.join("groups")).unwrap();
+        fs::write(root.join("main.owl"), "@group shell\n@package fish\n").unwrap();
+        fs::write(
+            root.join("hosts/box.owl"),
+            "@package fish\n:config fish-box -> ~/.config/fish\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("groups/shell.owl"),
+            "@package fish\n:config fish\n:env [shell=fish] EDITOR=nvim\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    fn entry(
+        file: &str,
+        rank: usize,
+        directive: &str,
+        value: Option<&str>,
+        outcome: Outcome,
+    ) -> JournalEntry {
+        JournalEntry {
+            file: file.to_string(),
+            rank,
+            directive: directive.to_string(),
+            value: value.map(str::to_string),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_journal_across_three_layers() {
+        let dir = fixture();
+        let config = Config::load_for_host_traced(dir.path(), "box").unwrap();
+        let journal = config.journal.as_ref().unwrap();
+        let merged_into_main = || Outcome::Merged {
+            into: "main.owl".to_string(),
+        };
+        assert_eq!(
+            journal.packages["fish"],
+            vec![
+                entry("main.owl", 1, "declared", None, Outcome::Kept),
+                entry("hosts/box.owl", 2, "declared", None, merged_into_main()),
+                entry(
+                    "hosts/box.owl",
+                    2,
+                    "config",
+                    Some("fish-box -> ~/.config/fish"),
+                    Outcome::Kept
+                ),
+                entry("groups/shell.owl", 3, "declared", None, merged_into_main()),
+                entry(
+                    "groups/shell.owl",
+                    3,
+                    "config",
+                    Some("fish"),
+                    Outcome::Overridden {
+                        by: "hosts/box.owl".to_string(),
+                        by_rank: 2
+                    }
+                ),
+                entry(
+                    "groups/shell.owl",
+                    3,
+                    "env EDITOR",
+                    Some("nvim [shell=fish]"),
+                    Outcome::Kept
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_text() {
+        let dir = fixture();
+        let config = Config::load_for_host_traced(dir.path(), "box").unwrap();
+        let text = render_text("fish", &config.journal.unwrap().packages["fish"]);
+        assert_eq!(
+            text,
+            concat!(
+                "[fish]\n",
+                "  #1 main.owl\n",
+                "    declared                            kept\n",
+                "  #2 hosts/box.owl\n",
+                "    declared                            merged into main.owl\n",
+                "    :config fish-box -> ~/.config/fish  kept\n",
+                "  #3 groups/shell.owl\n",
+                "    declared                            merged into main.owl\n",
+                "    :config fish                        overridden by hosts/box.owl (#2)\n",
+                "    :env EDITOR=nvim [shell=fish]       kept\n",
+                "\n#1 is main.owl, then the host file, then groups in load order; \
+                 each field keeps the value of the best-ranked file that sets it\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_replace_merge_overrides_whole_declarations() {
+        let dir = fixture();
+        fs::write(
+            dir.path().join("main.owl"),
+            "@option package_merge=replace\n@group shell\n@package fish\n",
+        )
+        .unwrap();
+        let config = Config::load_for_host_traced(dir.path(), "box").unwrap();
+        let entries = &config.journal.unwrap().packages["fish"];
+        let by_main = Outcome::Overridden {
+            by: "main.owl".to_string(),
+            by_rank: 1,
+        };
+        assert_eq!(entries[0].outcome, Outcome::Kept);
+        assert!(entries[1..].iter().all(|e| e.outcome == by_main));
+    }
+
+    #[test]
+    fn test_defaults_of_lower_file_are_dropped() {
+        let dir = fixture();
+        fs::write(
+            dir.path().join("groups/shell.owl"),
+            "@defaults\n:env PAGER=less\n@package fish\n",
+        )
+        .unwrap();
+        let config = Config::load_for_host_traced(dir.path(), "box").unwrap();
+        let entries = &config.journal.unwrap().packages["fish"];
+        let pager = entries.iter().find(|e| e.directive == "env PAGER").unwrap();
+        assert!(matches!(pager.outcome, Outcome::Dropped { .. }));
+        assert!(!config.packages["fish"].env_vars.contains_key("PAGER"));
+    }
+
+    #[test]
+    fn test_untraced_load_keeps_no_journal() {
+        let dir = fixture();
+        let config = Config::load_for_host(dir.path(), "box").unwrap();
+        assert!(config.journal.is_none());
+    }
+
+    #[test]
+    fn test_json_shape() {
+        let value = serde_json::to_value(entry(
+            "groups/shell.owl",
+            3,
+            "config",
+            Some("fish"),
+            Outcome::Overridden {
+                by: "hosts/box.owl".to_string(),
+                by_rank: 2,
+            },
+        ))
+        .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "file": "groups/shell.owl",
+                "rank": 3,
+                "directive": "config",
+                "value": "fish",
+                "outcome": "overridden",
+                "by": "hosts/box.owl",
+                "by_rank": 2
+            })
+        );
+    }
+}
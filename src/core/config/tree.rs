@@ -0,0 +1,289 @@
+//! Config file and group dependency graph (`owl tree`)
+
+use anyhow::Result;
+use std::path::Path;
+
+use super::Config;
+use super::format::ConfigFormat;
+use super::loader::GroupOrigin;
+use crate::internal::constants;
+
+/// How a node in the config tree was resolved
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeStatus {
+    Loaded,
+    /// `@group` names a file that does not exist
+    Missing,
+    /// The group is already being expanded higher up this branch
+    Cycle,
+}
+
+/// A config file, or a group it references
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeNode {
+    /// Path relative to the owl root, e.g. `groups/dev.owl`
+    pub label: String,
+    pub package_count: usize,
+    pub status: NodeStatus,
+    /// The loader first reached this group through a different parent
+    pub secondary: bool,
+    pub children: Vec<TreeNode>,
+}
+
+/// Build one tree for `main.owl` and one for the host file, skipping files that don't exist
+pub fn build_config_tree(owl_root: &Path, hostname: &str) -> Result<Vec<TreeNode>> {
+    let groups_path = owl_root.join(constants::GROUPS_DIR);
+    let origins = Config::load_for_host(owl_root, hostname)?.group_origins;
+    let mut roots = Vec::new();
+    let format = ConfigFormat::of_root(owl_root);
+    for rel in [format.main_file(), format.host_file(hostname)] {
+        let path = owl_root.join(&rel);
+        if !path.exists() {
+            continue;
+        }
+        let config = Config::parse_file(&path)?;
+        let mut ancestors = Vec::new();
+        let children = group_children(
+            &groups_path,
+            &rel,
+            &config,
+            &origins,
+            format,
+            &mut ancestors,
+        )?;
+        roots.push(TreeNode {
+            label: rel,
+            package_count: config.packages.len(),
+            status: NodeStatus::Loaded,
+            secondary: false,
+            children,
+        });
+    }
+    Ok(roots)
+}
+
+fn group_children(
+    groups_path: &Path,
+    parent: &str,
+    config: &Config,
+    origins: &[GroupOrigin],
+    format: ConfigFormat,
+    ancestors: &mut Vec<String>,
+) -> Result<Vec<TreeNode>> {
+    let mut children = Vec::new();
+    for name in &config.groups {
+        let label = format.group_label(name);
+        if ancestors.contains(name) {
+            children.push(TreeNode {
+                label,
+                package_count: 0,
+                status: NodeStatus::Cycle,
+                secondary: false,
+                children: Vec::new(),
+            });
+            continue;
+        }
+        let path = format.group_file(groups_path, name);
+        if !path.exists() {
+            children.push(TreeNode {
+                label,
+                package_count: 0,
+                status: NodeStatus::Missing,
+                secondary: false,
+                children: Vec::new(),
+            });
+            continue;
+        }
+        let group_config = Config::parse_file(&path)?;
+        ancestors.push(name.clone());
+        let grandchildren = group_children(
+            groups_path,
+            &label,
+            &group_config,
+            origins,
+            format,
+            ancestors,
+        )?;
+        ancestors.pop();
+        let secondary = origins
+            .iter()
+            .find(|o| &o.group == name)
+            .is_some_and(|o| o.chain.last().map(String::as_str) != Some(parent));
+        children.push(TreeNode {
+            label,
+            package_count: group_config.packages.len(),
+            status: NodeStatus::Loaded,
+            secondary,
+            children: grandchildren,
+        });
+    }
+    Ok(children)
+}
+
+fn describe(node: &TreeNode) -> String {
+    match node.status {
+        NodeStatus::Loaded if node.package_count == 1 => format!("{} (1 package)", node.label),
+        NodeStatus::Loaded => format!("{} ({} packages)", node.label, node.package_count),
+        NodeStatus::Missing => format!("{} (missing)", node.label),
+        NodeStatus::Cycle => format!("{} (cycle)", node.label),
+    }
+}
+
+/// Render the trees as indented text with box-drawing branches
+pub fn render_text(roots: &[TreeNode]) -> String {
+    fn walk(node: &TreeNode, prefix: &str, out: &mut String) {
+        for (i, child) in node.children.iter().enumerate() {
+            let last = i + 1 == node.children.len();
+            out.push_str(&format!(
+                "{}{} {}\n",
+                prefix,
+                if last { "└──" } else { "├──" },
+                describe(child)
+            ));
+            let next = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            walk(child, &next, out);
+        }
+    }
+
+    let mut out = String::new();
+    for root in roots {
+        out.push_str(&describe(root));
+        out.push('\n');
+        walk(root, "", &mut out);
+    }
+    out
+}
+
+/// Render the trees as a Graphviz digraph
+pub fn render_dot(roots: &[TreeNode]) -> String {
+    fn walk(node: &TreeNode, lines: &mut Vec<String>) {
+        for child in &node.children {
+            let edge = match child.status {
+                NodeStatus::Cycle => format!(
+                    "  \"{}\" -> \"{}\" [color=red, label=\"cycle\"];",
+                    node.label, child.label
+                ),
+                _ if child.secondary => format!(
+                    "  \"{}\" -> \"{}\" [style=dashed, label=\"also via\"];",
+                    node.label, child.label
+                ),
+                _ => format!("  \"{}\" -> \"{}\";", node.label, child.label),
+            };
+            if child.status != NodeStatus::Cycle {
+                push_unique(lines, node_line(child));
+            }
+            push_unique(lines, edge);
+            walk(child, lines);
+        }
+    }
+
+    fn node_line(node: &TreeNode) -> String {
+        match node.status {
+            NodeStatus::Missing => format!(
+                "  \"{}\" [label=\"{}\\nmissing\", color=red, style=dashed];",
+                node.label, node.label
+            ),
+            _ => format!(
+                "  \"{}\" [label=\"{}\\n{} packages\"];",
+                node.label, node.label, node.package_count
+            ),
+        }
+    }
+
+    fn push_unique(lines: &mut Vec<String>, line: String) {
+        if !lines.contains(&line) {
+            lines.push(line);
+        }
+    }
+
+    let mut lines = Vec::new();
+    for root in roots {
+        push_unique(&mut lines, node_line(root));
+        walk(root, &mut lines);
+    }
+    format!("digraph owl {{\n{}\n}}\n", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("hosts")).unwrap();
+        fs::create_dir_all(root.join("groups")).unwrap();
+        fs::write(root.join("main.owl"), "@group base\n@packages\ngit\n").unwrap();
+        fs::write(root.join("hosts/box.owl"), "@group dev\n@packages\nhtop\n").unwrap();
+        fs::write(root.join("groups/base.owl"), "@packages\nvim\ncurl\n").unwrap();
+        // dev -> rust -> dev is a cycle, rust also references a missing group
+        fs::write(
+            root.join("groups/dev.owl"),
+            "@group rust\n@packages\nmake\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("groups/rust.owl"),
+            "@group dev\n@group gone\n@packages\nrustup\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_tree_marks_cycles_and_missing_groups() {
+        let dir = fixture();
+        let roots = build_config_tree(dir.path(), "box").unwrap();
+        assert_eq!(
+            render_text(&roots),
+            "main.owl (1 package)\n\
+             └── groups/base.owl (2 packages)\n\
+             hosts/box.owl (1 package)\n\
+             └── groups/dev.owl (1 package)\n\
+             \x20   └── groups/rust.owl (1 package)\n\
+             \x20       ├── groups/dev.owl (cycle)\n\
+             \x20       └── groups/gone.owl (missing)\n"
+        );
+    }
+
+    #[test]
+    fn test_dot_output() {
+        let dir = fixture();
+        let roots = build_config_tree(dir.path(), "box").unwrap();
+        let dot = render_dot(&roots);
+        assert!(dot.starts_with("digraph owl {\n"));
+        assert!(dot.contains("\"hosts/box.owl\" -> \"groups/dev.owl\";"));
+        assert!(
+            dot.contains("\"groups/rust.owl\" -> \"groups/dev.owl\" [color=red, label=\"cycle\"];")
+        );
+        assert!(dot.contains(
+            "\"groups/gone.owl\" [label=\"groups/gone.owl\\nmissing\", color=red, style=dashed];"
+        ));
+    }
+
+    #[test]
+    fn test_dot_dashes_edges_the_loader_did_not_use_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("groups")).unwrap();
+        fs::write(root.join("main.owl"), "@group work\n@group play\n").unwrap();
+        fs::write(root.join("groups/work.owl"), "@group dev\n").unwrap();
+        fs::write(root.join("groups/play.owl"), "@group dev\n").unwrap();
+        fs::write(root.join("groups/dev.owl"), "@packages\ngit\n").unwrap();
+
+        let dot = render_dot(&build_config_tree(root, "nohost").unwrap());
+        assert!(dot.contains("\"groups/play.owl\" -> \"groups/dev.owl\";"));
+        assert!(dot.contains(
+            "\"groups/work.owl\" -> \"groups/dev.owl\" [style=dashed, label=\"also via\"];"
+        ));
+    }
+
+    #[test]
+    fn test_missing_host_file_is_skipped() {
+        let dir = fixture();
+        let roots = build_config_tree(dir.path(), "other").unwrap();
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0].label, "main.owl");
+    }
+}
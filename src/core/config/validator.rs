@@ -3,14 +3,16 @@ use anyhow::{Result, anyhow};
 use serde_json;
 
 /// Validate a provided .owl config file can be parsed
-pub fn run_configcheck(path: &str) -> Result<()> {
+pub fn run_configcheck(path: &str, allow_dangerous_env: bool) -> Result<()> {
     let p = std::path::Path::new(path);
     if !p.exists() {
         return Err(anyhow!("Config file not found: {}", path));
     }
     match Config::parse_file(p) {
-        Ok(_) => {
-            println!(
+        Ok(config) => {
+            config.print_warnings();
+            print_env_warnings(&config, allow_dangerous_env);
+            outln!(
                 "{} {}",
                 crate::internal::color::green("✓"),
                 crate::internal::color::bold(&format!("Config valid: {}", path))
@@ -21,23 +23,33 @@ pub fn run_configcheck(path: &str) -> Result<()> {
     }
 }
 
+/// Print the canonical dump of one config file
+///
+/// Only the file's own text is parsed; groups it references are not loaded.
+pub fn run_dump_canonical(path: &str) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read config file {}: {}", path, e))?;
+    let config = Config::parse(&content).map_err(|e| anyhow!("Failed to parse {}: {}", path, e))?;
+    out!("{}", super::canonical::to_canonical_json(&config));
+    Ok(())
+}
+
 /// Validate and print the full config chain (main, hostname, groups)
-pub fn run_full_configcheck() -> Result<()> {
-    let home = std::env::var("HOME").map_err(|_| anyhow!("HOME environment variable not set"))?;
-    let owl_root = std::path::Path::new(&home).join(crate::internal::constants::OWL_DIR);
-    println!("Loading config from: {}", owl_root.display());
+pub fn run_full_configcheck(allow_dangerous_env: bool) -> Result<()> {
+    let env = crate::internal::environment::get();
+    let owl_root = env.owl_dir()?;
+    outln!("Loading config from: {}", owl_root.display());
 
     // Check main config
     let main_config_path = owl_root.join(crate::internal::constants::MAIN_CONFIG_FILE);
-    println!(
+    outln!(
         "Main config: {} (exists: {})",
         main_config_path.display(),
         main_config_path.exists()
     );
 
     // Check host config
-    let hostname =
-        crate::internal::constants::get_host_name().unwrap_or_else(|_| "unknown".to_string());
+    let hostname = env.hostname().unwrap_or("unknown");
     let host_config_path = owl_root
         .join(crate::internal::constants::HOSTS_DIR)
         .join(format!(
@@ -45,7 +57,7 @@ pub fn run_full_configcheck() -> Result<()> {
             hostname,
             crate::internal::constants::OWL_EXT
         ));
-    println!(
+    outln!(
         "Host config: {} (exists: {})",
         host_config_path.display(),
         host_config_path.exists()
@@ -53,30 +65,31 @@ pub fn run_full_configcheck() -> Result<()> {
 
     // Check groups
     let groups_path = owl_root.join(crate::internal::constants::GROUPS_DIR);
-    println!(
+    outln!(
         "Groups dir: {} (exists: {})",
         groups_path.display(),
         groups_path.exists()
     );
-    if groups_path.exists()
-        && let Ok(entries) = std::fs::read_dir(&groups_path)
+    let discovered = crate::internal::files::discover_config_files(&owl_root);
+    for path in discovered
+        .files
+        .iter()
+        .filter(|p| p.starts_with(&groups_path))
     {
-        for entry in entries.flatten() {
-            println!(
-                "  Group file: {} (exists: {})",
-                entry.path().display(),
-                entry.path().exists()
-            );
-        }
+        outln!("  Group file: {}", path.display());
     }
+    discovered.warn_once();
 
     match Config::load_all_relevant_config_files() {
         Ok(config) => {
-            println!(
+            config.print_warnings();
+            print_ownership_warnings(&config);
+            print_env_warnings(&config, allow_dangerous_env);
+            outln!(
                 "{}",
                 crate::internal::color::green("✓ Full config chain loaded successfully")
             );
-            println!(
+            outln!(
                 "{}",
                 serde_json::to_string_pretty(&config)
                     .map_err(|e| anyhow!("Failed to serialize config: {}", e))?
@@ -102,13 +115,13 @@ pub fn run_full_configcheck() -> Result<()> {
                 + config.env_vars.len();
             let group_count = config.groups.len();
 
-            println!();
-            println!("Summary:");
-            println!("  Packages: {}", package_count);
-            println!("  Dotfiles: {}", dotfile_count);
-            println!("  Services: {}", service_count);
-            println!("  Environment variables: {}", env_var_count);
-            println!("  Groups: {}", group_count);
+            outln!();
+            outln!("Summary:");
+            outln!("  Packages: {}", package_count);
+            outln!("  Dotfiles: {}", dotfile_count);
+            outln!("  Services: {}", service_count);
+            outln!("  Environment variables: {}", env_var_count);
+            outln!("  Groups: {}", group_count);
 
             Ok(())
         }
@@ -116,16 +129,118 @@ pub fn run_full_configcheck() -> Result<()> {
     }
 }
 
+/// Warn about env vars that replace PATH, HOME and the like, like apply does
+fn print_env_warnings(config: &Config, allow_dangerous_env: bool) {
+    if allow_dangerous_env {
+        return;
+    }
+    let vars = crate::core::env::collect_all_env_vars(config);
+    for warning in crate::core::env::dangerous_env_warnings(&vars) {
+        warnln!(
+            "  {} {}",
+            crate::internal::color::yellow("warning:"),
+            warning
+        );
+    }
+}
+
+/// Warn about dotfile destinations a package owns, like apply does
+fn print_ownership_warnings(config: &Config) {
+    let mappings = crate::core::dotfiles::get_dotfile_mappings(config);
+    let owned = crate::core::dotfiles::DotfileRoots::from_env().and_then(|roots| {
+        let pm = crate::core::pm::manager();
+        crate::core::dotfiles::package_owned_destinations(&roots, &mappings, pm.as_ref())
+    });
+    let warnings = match owned {
+        Ok(owned) => owned.iter().map(|entry| entry.warning()).collect(),
+        Err(err) => vec![format!("Could not check package ownership: {}", err)],
+    };
+    for warning in warnings {
+        warnln!(
+            "  {} {}",
+            crate::internal::color::yellow("warning:"),
+            warning
+        );
+    }
+}
+
+/// Print the effective directives of one package, marking those inherited from `@defaults`
+pub fn run_package_check(file: Option<&str>, name: &str) -> Result<()> {
+    let config = match file {
+        Some(path) => {
+            Config::parse_file(path).map_err(|e| anyhow!("Failed to parse {}: {}", path, e))?
+        }
+        None => Config::load_all_relevant_config_files()
+            .map_err(|e| anyhow!("Failed to load full config: {}", e))?,
+    };
+    let package = config
+        .packages
+        .get(name)
+        .ok_or_else(|| anyhow!("Package '{}' is not declared in the config", name))?;
+
+    outln!("[{}]", crate::internal::color::blue(name));
+    let directives = effective_directives(package);
+    if directives.is_empty() {
+        outln!("  {}", crate::internal::color::dim("no directives"));
+    }
+    for (directive, from_defaults) in directives {
+        if from_defaults {
+            outln!(
+                "  {} {}",
+                directive,
+                crate::internal::color::dim("(from @defaults)")
+            );
+        } else {
+            outln!("  {}", directive);
+        }
+    }
+    Ok(())
+}
+
+/// Directives that apply to a package, each flagged when it came from `@defaults`
+pub(super) fn effective_directives(package: &super::Package) -> Vec<(String, bool)> {
+    let mut directives: Vec<(String, bool)> = package
+        .config
+        .iter()
+        .map(|cfg| (format!(":config {}", cfg), false))
+        .collect();
+    if let Some(service) = &package.service {
+        directives.push((format!(":service {}", service), false));
+    }
+    if let Some(min_version) = &package.min_version {
+        directives.push((min_version.directive(), false));
+    }
+    if !package.after.is_empty() {
+        directives.push((format!(":after {}", package.after.join(" ")), false));
+    }
+    if !package.requires.is_empty() {
+        directives.push((format!(":requires {}", package.requires.join(" ")), false));
+    }
+    let mut env: Vec<_> = package.env_vars.iter().collect();
+    env.sort();
+    for (key, value) in env {
+        let shell = package
+            .env_shells
+            .get(key)
+            .map(|shell| format!("[shell={}] ", shell.as_str()))
+            .unwrap_or_default();
+        directives.push((
+            format!(":env {}{}={}", shell, key, value),
+            package.default_env_keys.contains(key),
+        ));
+    }
+    directives
+}
+
 /// Show the host-specific config path for this machine
 pub fn run_confighost() -> Result<()> {
-    let hostname =
-        crate::internal::constants::get_host_name().unwrap_or_else(|_| "unknown".to_string());
-    let home = std::env::var("HOME").map_err(|_| anyhow!("HOME environment variable not set"))?;
-    let path = std::path::Path::new(&home)
-        .join(crate::internal::constants::OWL_DIR)
+    let env = crate::internal::environment::get();
+    let hostname = env.hostname().unwrap_or("unknown");
+    let path = env
+        .owl_dir()?
         .join("hosts")
         .join(format!("{}.owl", hostname));
-    println!(
+    outln!(
         "Host config: {}",
         crate::internal::color::bold(&path.to_string_lossy())
     );
@@ -144,3 +259,25 @@ pub fn get_uninstalled_packages(config: &Config) -> Result<Vec<String>> {
     }
     Ok(missing)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_directives_mark_defaults() {
+        let config = Config::parse(
+            "@defaults\n:env PATH=/opt/dev/bin\n:env CC=clang\n\
+             @package go\n:config go/env -> ~/.config/go/env\n:env CC=gcc\n",
+        )
+        .unwrap();
+        assert_eq!(
+            effective_directives(&config.packages["go"]),
+            vec![
+                (":config go/env -> ~/.config/go/env".to_string(), false),
+                (":env CC=gcc".to_string(), false),
+                (":env PATH=/opt/dev/bin".to_string(), true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Confirmations before risky operations, and their audit trail
+//!
+//! Every question owl asks goes through one policy: `-y` gives each kind of
+//! operation its own answer (AUR builds go ahead, removals do not), and a run
+//! without a terminal, or one nobody answers within `--confirm-timeout`,
+//! declines. Each question and its answer, automatic ones with the reason, is
+//! kept in the history.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Items listed in a prompt before the rest are counted as `and N more`
+pub const SHOWN_ITEMS: usize = 10;
+
+/// What is being confirmed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmKind {
+    AurInstall,
+    AurUpdate,
+    Removal,
+    /// Managing declared packages that were installed before owl
+    Adoption,
+    /// Removing a pacman database lock no running package manager holds
+    StaleLock,
+    /// Deleting cached packages and AUR build files (`clean --aur`)
+    CacheClean,
+    /// Removing dependencies nothing needs any more (`clean --aur`)
+    OrphanRemoval,
+    /// Writing `:service` lines for enabled services (`import --services`)
+    ServiceImport,
+    /// Replacing dotfiles changed both locally and in their source
+    DotfileOverwrite,
+    /// Disabling services owl enabled that left the config
+    Teardown,
+}
+
+impl ConfirmKind {
+    /// The answer `-y` gives
+    pub fn assumed_answer(self) -> bool {
+        match self {
+            // A cleaned cache only costs downloads later, and an overwritten
+            // dotfile is kept in the backups
+            Self::AurInstall | Self::AurUpdate | Self::CacheClean | Self::DotfileOverwrite => true,
+            // Undoing these needs a person; leave them for an interactive run
+            Self::Removal
+            | Self::Adoption
+            | Self::StaleLock
+            | Self::OrphanRemoval
+            | Self::ServiceImport
+            | Self::Teardown => false,
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::AurInstall | Self::AurUpdate => "AUR packages require confirmation",
+            Self::Removal => "Package removals require confirmation",
+            Self::Adoption => "Declared packages are already installed",
+            Self::StaleLock => "The pacman database lock looks stale",
+            Self::CacheClean => "Package caches can be cleaned",
+            Self::OrphanRemoval => "Orphaned dependencies can be removed",
+            Self::ServiceImport => "Enabled services can be added to the config",
+            Self::DotfileOverwrite => "Dotfiles were changed both here and in their source",
+            Self::Teardown => "Services owl enabled are no longer declared",
+        }
+    }
+
+    /// What the items are, after their count
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::AurInstall => "AUR packages to install",
+            Self::AurUpdate => "AUR packages to update",
+            Self::Removal => "packages to remove",
+            Self::Adoption => "not yet managed",
+            Self::StaleLock => "lock file",
+            Self::CacheClean => "cache directories",
+            Self::OrphanRemoval => "orphaned packages",
+            Self::ServiceImport => "services",
+            Self::DotfileOverwrite => "dotfiles to overwrite",
+            Self::Teardown => "services to disable",
+        }
+    }
+
+    pub fn consequence(self) -> &'static str {
+        match self {
+            Self::AurInstall | Self::AurUpdate => {
+                "They are built from PKGBUILDs that nobody reviews like repository packages."
+            }
+            Self::Removal => "They are uninstalled together with dependencies nothing else needs.",
+            Self::Adoption => "Removing one from the config will then propose removing it.",
+            Self::StaleLock => {
+                "No pacman, paru or yay is running, so it is probably left from a crash."
+            }
+            Self::CacheClean => {
+                "Packages that are not installed and AUR build files are deleted; \
+                 downgrading to them needs a download."
+            }
+            Self::OrphanRemoval => "They were installed as dependencies and nothing needs them.",
+            Self::ServiceImport => {
+                "Packages that get a new @package block are managed from then on; \
+                 removing one from the config will propose removing it."
+            }
+            Self::DotfileOverwrite => {
+                "The local changes are replaced by the source and kept in the backups; \
+                 declined ones are left alone and listed as conflicts."
+            }
+            Self::Teardown => "They are disabled and stopped with systemctl disable --now.",
+        }
+    }
+
+    pub fn question(self) -> &'static str {
+        match self {
+            Self::AurInstall => "Install them?",
+            Self::AurUpdate => "Update them?",
+            Self::Removal => "Remove them?",
+            Self::Adoption => "Let owl manage them?",
+            Self::StaleLock => "Remove it?",
+            Self::CacheClean => "Clean them?",
+            Self::OrphanRemoval => "Remove them?",
+            Self::ServiceImport => "Add them?",
+            Self::DotfileOverwrite => "Overwrite them?",
+            Self::Teardown => "Disable them?",
+        }
+    }
+}
+
+/// Who or what answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnsweredBy {
+    User,
+    /// `-y`
+    Flag,
+    /// Standard input is not a terminal, or the output is machine-read
+    NoTty,
+    /// Nobody answered within `--confirm-timeout`
+    Timeout,
+}
+
+impl AnsweredBy {
+    /// Why an answer was given automatically
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::User => "answered",
+            Self::Flag => "answered by -y",
+            Self::NoTty => "no terminal to ask on",
+            Self::Timeout => "no answer before the timeout",
+        }
+    }
+}
+
+/// How a run answers confirmations
+#[derive(Debug, Clone, Default)]
+pub struct ConfirmPolicy {
+    /// `-y`: answer with each kind's assumed answer
+    pub assume_answers: bool,
+    /// Someone can be asked on the terminal
+    pub can_prompt: bool,
+    /// Decline when nobody answers within this (`--confirm-timeout`)
+    pub timeout: Option<Duration>,
+    /// State directory the answers are recorded in; `None` records nothing
+    pub log_dir: Option<PathBuf>,
+}
+
+impl ConfirmPolicy {
+    /// Whether a person is asked, rather than an answer given automatically
+    pub fn asks(&self) -> bool {
+        self.can_prompt && !self.assume_answers
+    }
+}
+
+/// One question and its answer, as kept in the history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfirmRecord {
+    /// Seconds since the Unix epoch
+    pub at: u64,
+    pub kind: ConfirmKind,
+    pub items: Vec<String>,
+    pub answer: bool,
+    pub answered_by: AnsweredBy,
+}
+
+/// Answer `kind` for `items` under `policy`
+///
+/// `ask` is only called when a person can be asked; it returns `None` when no
+/// answer came in time.
+pub fn decide(
+    kind: ConfirmKind,
+    items: &[String],
+    policy: &ConfirmPolicy,
+    now: u64,
+    ask: impl FnOnce() -> Option<bool>,
+) -> ConfirmRecord {
+    let (answer, answered_by) = if policy.assume_answers {
+        (kind.assumed_answer(), AnsweredBy::Flag)
+    } else if !policy.can_prompt {
+        (false, AnsweredBy::NoTty)
+    } else {
+        match ask() {
+            Some(answer) => (answer, AnsweredBy::User),
+            None => (false, AnsweredBy::Timeout),
+        }
+    };
+    ConfirmRecord {
+        at: now,
+        kind,
+        items: items.to_vec(),
+        answer,
+        answered_by,
+    }
+}
+
+/// Add `record` to the history in `dir`
+pub fn record(dir: &Path, record: ConfirmRecord) -> Result<()> {
+    let mut history = crate::core::history::History::load_from(dir)?;
+    history.record_confirmation(record);
+    history.save_to(dir)
+}
+
+/// The first `shown` items, then how many more: `a, b and 3 more`
+pub fn summarize(items: &[String], shown: usize) -> String {
+    if items.len() <= shown {
+        return items.join(", ");
+    }
+    format!(
+        "{} and {} more",
+        items[..shown].join(", "),
+        items.len() - shown
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn policy(assume_answers: bool, can_prompt: bool) -> ConfirmPolicy {
+        ConfirmPolicy {
+            assume_answers,
+            can_prompt,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_default_matrix() {
+        use AnsweredBy::*;
+        use ConfirmKind::*;
+        // (kind, -y, terminal, person answers, expected answer, answered by)
+        let cases = [
+            (AurInstall, true, true, None, true, Flag),
+            (AurUpdate, true, false, None, true, Flag),
+            (Removal, true, true, None, false, Flag),
+            (Adoption, true, true, None, false, Flag),
+            (StaleLock, true, true, None, false, Flag),
+            (CacheClean, true, false, None, true, Flag),
+            (OrphanRemoval, true, true, None, false, Flag),
+            (ServiceImport, true, true, None, false, Flag),
+            (DotfileOverwrite, true, false, None, true, Flag),
+            (Teardown, true, true, None, false, Flag),
+            (DotfileOverwrite, false, false, None, false, NoTty),
+            (Teardown, false, false, None, false, NoTty),
+            (DotfileOverwrite, false, true, Some(Some(true)), true, User),
+            (Teardown, false, true, Some(Some(true)), true, User),
+            (DotfileOverwrite, false, true, Some(None), false, Timeout),
+            (Teardown, false, true, Some(None), false, Timeout),
+            (CacheClean, false, false, None, false, NoTty),
+            (AurInstall, false, false, None, false, NoTty),
+            (Removal, false, false, None, false, NoTty),
+            (AurUpdate, false, true, Some(Some(true)), true, User),
+            (Removal, false, true, Some(Some(true)), true, User),
+            (Adoption, false, true, Some(Some(false)), false, User),
+            (AurInstall, false, true, Some(None), false, Timeout),
+            (Removal, false, true, Some(None), false, Timeout),
+        ];
+        for (kind, assume, tty, person, answer, by) in cases {
+            let mut asked = false;
+            let record = decide(kind, &names(&["a"]), &policy(assume, tty), 0, || {
+                asked = true;
+                person.expect("asked although an automatic answer applies")
+            });
+            assert_eq!(
+                (record.answer, record.answered_by),
+                (answer, by),
+                "{:?} -y={} tty={}",
+                kind,
+                assume,
+                tty
+            );
+            assert_eq!(asked, person.is_some(), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_answers_are_recorded_in_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let removal = decide(
+            ConfirmKind::Removal,
+            &names(&["bat", "fd"]),
+            &policy(true, true),
+            1_700_000_000,
+            || unreachable!(),
+        );
+        record(dir.path(), removal).unwrap();
+        let install = decide(
+            ConfirmKind::AurInstall,
+            &names(&["yay"]),
+            &policy(false, true),
+            1_700_000_060,
+            || Some(true),
+        );
+        record(dir.path(), install).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join("history.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            json["confirmations"],
+            serde_json::json!([
+                {
+                    "at": 1_700_000_000,
+                    "kind": "removal",
+                    "items": ["bat", "fd"],
+                    "answer": false,
+                    "answered_by": "flag",
+                },
+                {
+                    "at": 1_700_000_060,
+                    "kind": "aur_install",
+                    "items": ["yay"],
+                    "answer": true,
+                    "answered_by": "user",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_summarize_truncates() {
+        let many: Vec<String> = (1..=13).map(|i| format!("p{}", i)).collect();
+        assert_eq!(
+            summarize(&many, SHOWN_ITEMS),
+            "p1, p2, p3, p4, p5, p6, p7, p8, p9, p10 and 3 more"
+        );
+        assert_eq!(summarize(&many[..10], SHOWN_ITEMS), many[..10].join(", "));
+        assert_eq!(summarize(&many[..2], 1), "p1 and 1 more");
+        assert_eq!(summarize(&[], SHOWN_ITEMS), "");
+    }
+}
@@ -0,0 +1,802 @@
+//! `owl daemon`: a long-lived owl answering requests on a unix socket
+//!
+//! Clients such as a GUI ask for the plan without starting a whole owl each
+//! time. Messages are JSON documents, each framed by its length as a 4-byte
+//! big-endian integer. A client sends a [`Request`] and reads [`Response`]s
+//! until one that is not an `event`; a connection may carry several requests.
+//!
+//! The daemon keeps the parsed config, the package manager's answers and the
+//! last plan in memory. Before each request, and every few seconds from a
+//! watcher thread, it compares fingerprints of the config files and of the
+//! installed packages with those it loaded from: a changed config is parsed
+//! again, changed packages drop the package manager's answers, and either
+//! drops the plan. `status` answers from the kept plan; `plan` always plans
+//! again. An `apply` streams the run's `--events-json` events back and is
+//! refused while another one runs. What loading, planning and applying
+//! actually do is up to the [`Backend`].
+
+use anyhow::{Result, anyhow};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// Socket path under `$XDG_RUNTIME_DIR`
+const SOCKET: &str = "owl/daemon.sock";
+
+/// Frames larger than this are refused rather than allocated
+const MAX_FRAME: u32 = 16 * 1024 * 1024;
+
+/// How often the watcher looks for changed files
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What a client asks for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    /// The cached plan, planned again if a config file changed
+    Status,
+    /// Plan again now
+    Plan,
+    /// Run `owl apply`, streaming its events
+    Apply {
+        #[serde(default)]
+        dry_run: bool,
+    },
+    Shutdown,
+}
+
+/// What the daemon answers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    /// The `--plan-json` document, planned at `planned_at` (seconds since the
+    /// Unix epoch); `cached` when a status request was answered without planning
+    Plan {
+        plan: Value,
+        planned_at: u64,
+        cached: bool,
+    },
+    /// One `--events-json` event of a running apply
+    Event {
+        event: Value,
+    },
+    /// The apply finished
+    Done {
+        success: bool,
+    },
+    Ok,
+    Error {
+        message: String,
+    },
+}
+
+/// The daemon's socket in `runtime_dir`
+pub fn socket_path(runtime_dir: &Path) -> PathBuf {
+    runtime_dir.join(SOCKET)
+}
+
+/// Write `value` as one frame
+pub fn write_frame<T: Serialize>(writer: &mut impl Write, value: &T) -> Result<()> {
+    let body = serde_json::to_vec(value).map_err(|e| anyhow!("Failed to encode message: {}", e))?;
+    let len = u32::try_from(body.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME)
+        .ok_or_else(|| anyhow!("Message of {} bytes is too large", body.len()))?;
+    writer
+        .write_all(&len.to_be_bytes())
+        .and_then(|_| writer.write_all(&body))
+        .and_then(|_| writer.flush())
+        .map_err(|e| anyhow!("Failed to send message: {}", e))
+}
+
+/// Read one frame; `None` when the other side closed the connection between frames
+pub fn read_frame<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut len = [0u8; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match reader.read(&mut len[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(anyhow!("Connection closed inside a message")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(anyhow!("Failed to read message: {}", e)),
+        }
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME {
+        return Err(anyhow!("Message of {} bytes is too large", len));
+    }
+    let mut body = vec![0u8; len as usize];
+    reader
+        .read_exact(&mut body)
+        .map_err(|_| anyhow!("Connection closed inside a message"))?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| anyhow!("Invalid message: {}", e))
+}
+
+/// Size and modification time of each file, to notice changes
+pub type Fingerprint = Vec<(PathBuf, u64, Option<SystemTime>)>;
+
+fn stamp(path: &Path, meta: &std::fs::Metadata) -> (PathBuf, u64, Option<SystemTime>) {
+    (path.to_path_buf(), meta.len(), meta.modified().ok())
+}
+
+/// Fingerprint of the files under `root`, leaving out directories named in `skip`
+pub fn fingerprint(root: &Path, skip: &[&str]) -> Fingerprint {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                if !skip.iter().any(|name| entry.file_name() == *name) {
+                    pending.push(path);
+                }
+            } else {
+                files.push(stamp(&path, &meta));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Fingerprint of `paths` themselves (a directory's changes with its entries)
+pub fn stamps(paths: &[PathBuf]) -> Fingerprint {
+    paths
+        .iter()
+        .filter_map(|path| Some(stamp(path, &std::fs::metadata(path).ok()?)))
+        .collect()
+}
+
+/// Loading, planning and applying for the daemon
+pub trait Backend: Send + Sync {
+    /// The parsed config kept between requests
+    type Config: Send + Sync;
+    /// Changes when a config file changes
+    fn config_fingerprint(&self) -> Fingerprint;
+    /// Changes when installed packages or owl's record of them change
+    fn packages_fingerprint(&self) -> Fingerprint;
+    fn load(&self) -> Result<Self::Config>;
+    /// Forget what the package manager said about installed packages
+    fn clear_package_caches(&self);
+    /// The `--plan-json` document
+    fn plan(&self, config: &Self::Config) -> Result<Value>;
+    /// Run an apply, passing on each event; whether it succeeded
+    fn apply(
+        &self,
+        config: &Self::Config,
+        dry_run: bool,
+        on_event: &mut dyn FnMut(Value) -> Result<()>,
+    ) -> Result<bool>;
+}
+
+/// Whether the daemon goes on after a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+struct Cached {
+    plan: Value,
+    planned_at: u64,
+}
+
+/// The config as loaded, or why it failed to load, and the files it was loaded from
+struct Loaded<C> {
+    config: Result<Arc<C>, String>,
+    fingerprint: Fingerprint,
+}
+
+/// What the daemon keeps between requests
+struct State<C> {
+    loaded: Option<Loaded<C>>,
+    /// Installed packages the package manager's answers are about; `None`
+    /// once they may be out of date
+    packages: Option<Fingerprint>,
+    plan: Option<Cached>,
+}
+
+/// The daemon's state, shared by all connections
+pub struct Daemon<B: Backend> {
+    backend: B,
+    state: Mutex<State<B::Config>>,
+    applying: AtomicBool,
+}
+
+impl<B: Backend> Daemon<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            state: Mutex::new(State {
+                loaded: None,
+                packages: None,
+                plan: None,
+            }),
+            applying: AtomicBool::new(false),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State<B::Config>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Load the config again if its files changed, and drop the package
+    /// manager's answers if installed packages changed; the config to use
+    fn refresh(&self, state: &mut State<B::Config>) -> Result<Arc<B::Config>, String> {
+        let fingerprint = self.backend.config_fingerprint();
+        if state
+            .loaded
+            .as_ref()
+            .is_none_or(|loaded| loaded.fingerprint != fingerprint)
+        {
+            let config = self.backend.load().map(Arc::new).map_err(|e| e.to_string());
+            state.loaded = Some(Loaded {
+                config,
+                fingerprint,
+            });
+            state.plan = None;
+        }
+        let packages = self.backend.packages_fingerprint();
+        if state.packages.as_ref() != Some(&packages) {
+            self.backend.clear_package_caches();
+            state.packages = Some(packages);
+            state.plan = None;
+        }
+        state.loaded.as_ref().expect("loaded above").config.clone()
+    }
+
+    /// Notice changed files every `interval` until the daemon is dropped
+    pub fn watch(self: &Arc<Self>, interval: Duration)
+    where
+        B: 'static,
+    {
+        let daemon = Arc::downgrade(self);
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(interval);
+                let Some(daemon) = daemon.upgrade() else {
+                    return;
+                };
+                let mut state = daemon.state();
+                let _ = daemon.refresh(&mut state);
+            }
+        });
+    }
+
+    /// Answer one request through `reply`
+    pub fn dispatch(
+        &self,
+        request: Request,
+        reply: &mut dyn FnMut(Response) -> Result<()>,
+    ) -> Result<Flow> {
+        match request {
+            Request::Status => reply(self.plan(false))?,
+            Request::Plan => reply(self.plan(true))?,
+            Request::Apply { dry_run } => {
+                let done = self.apply(dry_run, reply)?;
+                reply(done)?
+            }
+            Request::Shutdown if self.applying.load(Ordering::SeqCst) => reply(Response::Error {
+                message: "An apply is running; shut down once it finished".to_string(),
+            })?,
+            Request::Shutdown => {
+                reply(Response::Ok)?;
+                return Ok(Flow::Stop);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn plan(&self, force: bool) -> Response {
+        let mut state = self.state();
+        let config = match self.refresh(&mut state) {
+            Ok(config) => config,
+            Err(message) => return Response::Error { message },
+        };
+        if !force && let Some(cached) = state.plan.as_ref() {
+            return Response::Plan {
+                plan: cached.plan.clone(),
+                planned_at: cached.planned_at,
+                cached: true,
+            };
+        }
+        match self.backend.plan(&config) {
+            Ok(plan) => {
+                let planned_at = crate::internal::time::now_secs();
+                state.plan = Some(Cached {
+                    plan: plan.clone(),
+                    planned_at,
+                });
+                Response::Plan {
+                    plan,
+                    planned_at,
+                    cached: false,
+                }
+            }
+            Err(err) => Response::Error {
+                message: err.to_string(),
+            },
+        }
+    }
+
+    /// Run the apply, streaming its events; the final response is returned
+    fn apply(
+        &self,
+        dry_run: bool,
+        reply: &mut dyn FnMut(Response) -> Result<()>,
+    ) -> Result<Response> {
+        if self.applying.swap(true, Ordering::SeqCst) {
+            return Ok(Response::Error {
+                message: "Another apply is running".to_string(),
+            });
+        }
+        // Not locked during the run, so status still answers
+        let config = self
+            .refresh(&mut self.state())
+            .map_err(|message| anyhow!(message));
+        let result = config.and_then(|config| {
+            self.backend.apply(&config, dry_run, &mut |event| {
+                reply(Response::Event { event })
+            })
+        });
+        // Whatever the run changed, the next request asks and plans again
+        {
+            let mut state = self.state();
+            state.packages = None;
+            state.plan = None;
+        }
+        self.applying.store(false, Ordering::SeqCst);
+        Ok(match result {
+            Ok(success) => Response::Done { success },
+            Err(err) => Response::Error {
+                message: err.to_string(),
+            },
+        })
+    }
+
+    /// Answer the requests of one connection until it closes or asks to stop
+    pub fn handle(&self, stream: &mut UnixStream) -> Result<Flow> {
+        while let Some(request) = read_frame::<Request>(stream)? {
+            let mut writer = stream.try_clone().map_err(|e| anyhow!("{}", e))?;
+            let flow =
+                self.dispatch(request, &mut |response| write_frame(&mut writer, &response))?;
+            if flow == Flow::Stop {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+impl<B: Backend + 'static> Daemon<B> {
+    /// Serve connections on `listener`, each on its own thread, until a
+    /// shutdown request; removes the socket at `path` on the way out
+    pub fn serve(self: Arc<Self>, listener: UnixListener, path: &Path) -> Result<()> {
+        let stopping = Arc::new(AtomicBool::new(false));
+        for stream in listener.incoming() {
+            if stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let daemon = Arc::clone(&self);
+            let stopping = Arc::clone(&stopping);
+            let wake = path.to_path_buf();
+            std::thread::spawn(move || {
+                match daemon.handle(&mut stream) {
+                    Ok(Flow::Stop) => {
+                        stopping.store(true, Ordering::SeqCst);
+                        // Wake the accept loop so it sees the flag
+                        let _ = UnixStream::connect(&wake);
+                    }
+                    Ok(Flow::Continue) => {}
+                    Err(err) => {
+                        let _ = write_frame(
+                            &mut stream,
+                            &Response::Error {
+                                message: err.to_string(),
+                            },
+                        );
+                    }
+                }
+            });
+        }
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+}
+
+/// Listen on `path`, readable and writable by the user only
+///
+/// The directory is created private first, so nobody else can reach the
+/// socket in the moment before its own mode is set. A socket left behind by
+/// a daemon that is gone is replaced; a live one is an error.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", dir.display(), e))?;
+    }
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(anyhow!(
+                "owl daemon is already running on {}",
+                path.display()
+            ));
+        }
+        std::fs::remove_file(path)
+            .map_err(|e| anyhow!("Failed to remove stale socket {}: {}", path.display(), e))?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", path.display(), e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| anyhow!("Failed to restrict {}: {}", path.display(), e))?;
+    Ok(listener)
+}
+
+/// Send `request` to the daemon on `path` and return its final response,
+/// passing events to `on_event`
+///
+/// `None` when no daemon listens there, so the caller can do the work itself.
+pub fn ask(
+    path: &Path,
+    request: &Request,
+    on_event: &mut dyn FnMut(Value),
+) -> Result<Option<Response>> {
+    let Ok(mut stream) = UnixStream::connect(path) else {
+        return Ok(None);
+    };
+    write_frame(&mut stream, request)?;
+    loop {
+        match read_frame::<Response>(&mut stream)? {
+            Some(Response::Event { event }) => on_event(event),
+            Some(response) => return Ok(Some(response)),
+            None => return Err(anyhow!("owl daemon closed the connection")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_frames_round_trip_and_reject_bad_input() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Request::Apply { dry_run: true }).unwrap();
+        write_frame(&mut buf, &Request::Status).unwrap();
+        let body = br#"{"request":"apply","dry_run":true}"#;
+        assert_eq!(&buf[..4], &(body.len() as u32).to_be_bytes());
+        assert_eq!(&buf[4..4 + body.len()], body);
+
+        let mut reader = buf.as_slice();
+        assert_eq!(
+            read_frame::<Request>(&mut reader).unwrap(),
+            Some(Request::Apply { dry_run: true })
+        );
+        assert_eq!(
+            read_frame::<Request>(&mut reader).unwrap(),
+            Some(Request::Status)
+        );
+        assert_eq!(read_frame::<Request>(&mut reader).unwrap(), None);
+
+        // Cut inside the length and inside the body
+        for cut in [2, 10] {
+            let err = read_frame::<Request>(&mut &buf[..cut]).unwrap_err();
+            assert_eq!(err.to_string(), "Connection closed inside a message");
+        }
+        let huge = (MAX_FRAME + 1).to_be_bytes();
+        assert!(
+            read_frame::<Request>(&mut huge.as_slice())
+                .unwrap_err()
+                .to_string()
+                .contains("too large")
+        );
+        let mut unknown = Vec::new();
+        write_frame(&mut unknown, &json!({"request": "reboot"})).unwrap();
+        assert!(
+            read_frame::<Request>(&mut unknown.as_slice())
+                .unwrap_err()
+                .to_string()
+                .starts_with("Invalid message")
+        );
+    }
+
+    /// Loads and plans number themselves; the fingerprints are whatever the test sets
+    struct Fake {
+        loads: AtomicUsize,
+        plans: AtomicUsize,
+        cleared: AtomicUsize,
+        config_stamp: Mutex<u64>,
+        packages_stamp: Mutex<u64>,
+        /// Loading fails while set
+        broken: AtomicBool,
+        /// Applies wait for this to be set, to test overlapping requests
+        release: AtomicBool,
+    }
+
+    impl Fake {
+        fn new() -> Self {
+            Self {
+                loads: AtomicUsize::new(0),
+                plans: AtomicUsize::new(0),
+                cleared: AtomicUsize::new(0),
+                config_stamp: Mutex::new(0),
+                packages_stamp: Mutex::new(0),
+                broken: AtomicBool::new(false),
+                release: AtomicBool::new(true),
+            }
+        }
+    }
+
+    impl Backend for Fake {
+        /// Which load it came from
+        type Config = usize;
+
+        fn config_fingerprint(&self) -> Fingerprint {
+            let stamp = *self.config_stamp.lock().unwrap();
+            vec![(PathBuf::from("main.owl"), stamp, None)]
+        }
+        fn packages_fingerprint(&self) -> Fingerprint {
+            let stamp = *self.packages_stamp.lock().unwrap();
+            vec![(PathBuf::from("local"), stamp, None)]
+        }
+        fn load(&self) -> Result<usize> {
+            if self.broken.load(Ordering::SeqCst) {
+                return Err(anyhow!("main.owl: unknown directive"));
+            }
+            Ok(self.loads.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+        fn clear_package_caches(&self) {
+            self.cleared.fetch_add(1, Ordering::SeqCst);
+        }
+        fn plan(&self, config: &usize) -> Result<Value> {
+            let plan = self.plans.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(json!({ "plan": plan, "config": config }))
+        }
+        fn apply(
+            &self,
+            _: &usize,
+            dry_run: bool,
+            on_event: &mut dyn FnMut(Value) -> Result<()>,
+        ) -> Result<bool> {
+            on_event(json!({ "type": "phase_started", "dry_run": dry_run }))?;
+            while !self.release.load(Ordering::SeqCst) {
+                std::thread::yield_now();
+            }
+            on_event(json!({ "type": "run_finished" }))?;
+            Ok(true)
+        }
+    }
+
+    fn ask_daemon(daemon: &Daemon<Fake>, request: Request) -> (Vec<Response>, Flow) {
+        let mut responses = Vec::new();
+        let flow = daemon
+            .dispatch(request, &mut |response| {
+                responses.push(response);
+                Ok(())
+            })
+            .unwrap();
+        (responses, flow)
+    }
+
+    fn plan_number(responses: &[Response]) -> (i64, bool) {
+        match responses {
+            [Response::Plan { plan, cached, .. }] => (plan["plan"].as_i64().unwrap(), *cached),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dispatch_caches_status_until_files_change() {
+        let daemon = Daemon::new(Fake::new());
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Status).0),
+            (1, false)
+        );
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Status).0),
+            (1, true)
+        );
+        // plan always plans again, and status then answers from that plan
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Plan).0),
+            (2, false)
+        );
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Status).0),
+            (2, true)
+        );
+        // One parse served all of that
+        assert_eq!(daemon.backend.loads.load(Ordering::SeqCst), 1);
+        assert_eq!(daemon.backend.cleared.load(Ordering::SeqCst), 1);
+
+        // A changed config file is parsed again
+        *daemon.backend.config_stamp.lock().unwrap() = 1;
+        let (responses, _) = ask_daemon(&daemon, Request::Status);
+        assert_eq!(plan_number(&responses), (3, false));
+        assert!(matches!(&responses[0], Response::Plan { plan, .. } if plan["config"] == 2));
+        // Changed packages only drop the package manager's answers
+        *daemon.backend.packages_stamp.lock().unwrap() = 1;
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Status).0),
+            (4, false)
+        );
+        assert_eq!(daemon.backend.loads.load(Ordering::SeqCst), 2);
+        assert_eq!(daemon.backend.cleared.load(Ordering::SeqCst), 2);
+
+        let (responses, flow) = ask_daemon(&daemon, Request::Apply { dry_run: true });
+        assert_eq!(flow, Flow::Continue);
+        assert_eq!(
+            responses,
+            vec![
+                Response::Event {
+                    event: json!({ "type": "phase_started", "dry_run": true })
+                },
+                Response::Event {
+                    event: json!({ "type": "run_finished" })
+                },
+                Response::Done { success: true },
+            ]
+        );
+        // An apply changes what there is to do
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Status).0),
+            (5, false)
+        );
+        assert_eq!(daemon.backend.cleared.load(Ordering::SeqCst), 3);
+
+        assert_eq!(
+            ask_daemon(&daemon, Request::Shutdown),
+            (vec![Response::Ok], Flow::Stop)
+        );
+    }
+
+    #[test]
+    fn test_watcher_reloads_a_changed_config_and_a_broken_one_is_reported() {
+        let daemon = Arc::new(Daemon::new(Fake::new()));
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Status).0),
+            (1, false)
+        );
+        daemon.watch(Duration::from_millis(5));
+
+        // Parsed again without a request asking
+        *daemon.backend.config_stamp.lock().unwrap() = 1;
+        let started = std::time::Instant::now();
+        while daemon.backend.loads.load(Ordering::SeqCst) < 2 {
+            assert!(started.elapsed() < Duration::from_secs(10), "no reload");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let (responses, _) = ask_daemon(&daemon, Request::Status);
+        assert_eq!(plan_number(&responses), (2, false));
+        assert!(matches!(&responses[0], Response::Plan { plan, .. } if plan["config"] == 2));
+
+        // A config that does not parse is an error until it is fixed
+        daemon.backend.broken.store(true, Ordering::SeqCst);
+        *daemon.backend.config_stamp.lock().unwrap() = 2;
+        let broken = vec![Response::Error {
+            message: "main.owl: unknown directive".to_string(),
+        }];
+        assert_eq!(ask_daemon(&daemon, Request::Status).0, broken);
+        assert_eq!(ask_daemon(&daemon, Request::Plan).0, broken);
+        daemon.backend.broken.store(false, Ordering::SeqCst);
+        *daemon.backend.config_stamp.lock().unwrap() = 3;
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Status).0),
+            (3, false)
+        );
+    }
+
+    #[test]
+    fn test_overlapping_apply_and_shutdown_are_refused() {
+        let daemon = Arc::new(Daemon::new(Fake::new()));
+        daemon.backend.release.store(false, Ordering::SeqCst);
+        let running = {
+            let daemon = Arc::clone(&daemon);
+            std::thread::spawn(move || ask_daemon(&daemon, Request::Apply { dry_run: false }))
+        };
+        while !daemon.applying.load(Ordering::SeqCst) {
+            std::thread::yield_now();
+        }
+
+        let refused = |message: &str| {
+            vec![Response::Error {
+                message: message.to_string(),
+            }]
+        };
+        assert_eq!(
+            ask_daemon(&daemon, Request::Apply { dry_run: false }).0,
+            refused("Another apply is running")
+        );
+        assert_eq!(
+            ask_daemon(&daemon, Request::Shutdown),
+            (
+                refused("An apply is running; shut down once it finished"),
+                Flow::Continue
+            )
+        );
+        // Status still answers during the run
+        assert_eq!(
+            plan_number(&ask_daemon(&daemon, Request::Status).0),
+            (1, false)
+        );
+
+        daemon.backend.release.store(true, Ordering::SeqCst);
+        let (responses, _) = running.join().unwrap();
+        assert_eq!(responses.last(), Some(&Response::Done { success: true }));
+        assert_eq!(ask_daemon(&daemon, Request::Shutdown).0, vec![Response::Ok]);
+    }
+
+    #[test]
+    fn test_socket_is_private_and_clients_fall_back_without_a_daemon() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = socket_path(dir.path());
+        let mut no_events = |_: Value| panic!("no events expected");
+
+        // Nothing listening: no socket, then a socket left behind
+        assert_eq!(ask(&path, &Request::Status, &mut no_events).unwrap(), None);
+        drop(bind(&path).unwrap());
+        assert!(path.exists());
+        assert_eq!(ask(&path, &Request::Status, &mut no_events).unwrap(), None);
+
+        // The stale socket is replaced; a live one is not
+        let listener = bind(&path).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        assert!(
+            bind(&path)
+                .unwrap_err()
+                .to_string()
+                .starts_with("owl daemon is already running")
+        );
+
+        let daemon = Arc::new(Daemon::new(Fake::new()));
+        let server = {
+            let path = path.clone();
+            std::thread::spawn(move || daemon.serve(listener, &path))
+        };
+        let response = ask(&path, &Request::Plan, &mut no_events).unwrap();
+        assert!(matches!(
+            response,
+            Some(Response::Plan { cached: false, .. })
+        ));
+        let mut events = Vec::new();
+        let response = ask(&path, &Request::Apply { dry_run: true }, &mut |event| {
+            events.push(event)
+        })
+        .unwrap();
+        assert_eq!(response, Some(Response::Done { success: true }));
+        assert_eq!(events.len(), 2);
+
+        assert_eq!(
+            ask(&path, &Request::Shutdown, &mut no_events).unwrap(),
+            Some(Response::Ok)
+        );
+        server.join().unwrap().unwrap();
+        assert!(!path.exists(), "the socket is removed on shutdown");
+    }
+}
@@ -0,0 +1,313 @@
+//! The pacman database lock, `/var/lib/pacman/db.lck`
+//!
+//! pacman creates the lock for every transaction and removes it when done. A
+//! manual `paru -Syu` holds it for its whole run, and a crash leaves it behind;
+//! either way every other transaction fails with "unable to lock database".
+//! owl recognises that failure (`CommandOutcome::DatabaseLocked`), tells a
+//! stale lock from a held one by looking for a running package manager in
+//! `/proc`, and waits for a held one with backoff before giving up on the
+//! package phases of the run.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Where pacman keeps its lock
+pub const LOCK_FILE: &str = "/var/lib/pacman/db.lck";
+
+/// How long `apply` waits for a held lock unless `--db-lock-wait` says otherwise
+pub const DEFAULT_WAIT: Duration = Duration::from_secs(120);
+
+/// Programs that take the lock while they run
+const LOCK_TAKERS: &[&str] = &["pacman", "paru", "yay", "pamac", "pamac-daemon"];
+
+/// Longest pause between two looks at the lock
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Whether package manager output says the database was locked
+pub fn is_locked_message(stderr: &str) -> bool {
+    stderr.contains("unable to lock database") || stderr.contains("could not lock database")
+}
+
+/// A transaction refused because the database was locked; carried in
+/// `anyhow::Error` so callers can tell it from other failures
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseLocked {
+    pub stderr: String,
+}
+
+impl fmt::Display for DatabaseLocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The pacman database is locked ({}); another package manager is running or left it behind",
+            LOCK_FILE
+        )
+    }
+}
+
+impl std::error::Error for DatabaseLocked {}
+
+/// Whether `err` is a [`DatabaseLocked`] failure
+pub fn is_locked(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<DatabaseLocked>().is_some()
+}
+
+/// A running process that can hold the lock
+#[derive(Debug, Clone, PartialEq)]
+pub struct Holder {
+    pub pid: u32,
+    /// Its command line, arguments joined by spaces
+    pub command: String,
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pid {} ({})", self.pid, self.command)
+    }
+}
+
+/// Package manager processes running, from each `PID/cmdline` under `proc`
+///
+/// A process counts when the program it runs, or the program `sudo` runs for
+/// it, is one of the known package managers. Sorted by pid.
+pub fn holders(proc: &Path) -> Vec<Holder> {
+    let Ok(entries) = fs::read_dir(proc) else {
+        return Vec::new();
+    };
+    let own = std::process::id();
+    let mut holders: Vec<Holder> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let cmdline = fs::read(entry.path().join("cmdline")).ok()?;
+            let args: Vec<String> = cmdline
+                .split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            let program = |arg: &String| {
+                Path::new(arg)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(str::to_string)
+            };
+            let mut programs = args.iter().filter_map(program);
+            let takes_lock = match programs.next()?.as_str() {
+                "sudo" | "doas" => programs
+                    .find(|name| !name.starts_with('-'))
+                    .is_some_and(|name| LOCK_TAKERS.contains(&name.as_str())),
+                name => LOCK_TAKERS.contains(&name),
+            };
+            (takes_lock && pid != own).then(|| Holder {
+                pid,
+                command: args.join(" "),
+            })
+        })
+        .collect();
+    holders.sort_by_key(|holder| holder.pid);
+    holders
+}
+
+/// The lock as found on one look
+#[derive(Debug, Clone, PartialEq)]
+pub enum LockState {
+    Free,
+    /// The lock exists and these processes may hold it
+    Held(Vec<Holder>),
+    /// The lock exists but no package manager is running
+    Stale,
+}
+
+/// Look at `lock` and the processes under `proc`
+pub fn state(lock: &Path, proc: &Path) -> LockState {
+    if !lock.exists() {
+        return LockState::Free;
+    }
+    match holders(proc) {
+        holders if holders.is_empty() => LockState::Stale,
+        holders => LockState::Held(holders),
+    }
+}
+
+/// How waiting for a held lock ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaitOutcome {
+    Free,
+    /// Its holder went away without removing it
+    Stale,
+    /// Still held after the whole wait; the last holders seen
+    Expired(Vec<Holder>),
+}
+
+/// Wait up to `limit` for the lock to be released, looking again after 1s, 2s,
+/// 4s and so on up to 30s
+///
+/// `sleep` pauses between looks and `report` hears who holds the lock and how
+/// long is left before each pause; both are parameters so tests need not wait.
+pub fn wait(
+    lock: &Path,
+    proc: &Path,
+    limit: Duration,
+    mut sleep: impl FnMut(Duration),
+    mut report: impl FnMut(&[Holder], Duration),
+) -> WaitOutcome {
+    let mut waited = Duration::ZERO;
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let holders = match state(lock, proc) {
+            LockState::Free => return WaitOutcome::Free,
+            LockState::Stale => return WaitOutcome::Stale,
+            LockState::Held(holders) => holders,
+        };
+        let left = limit.saturating_sub(waited);
+        if left.is_zero() {
+            return WaitOutcome::Expired(holders);
+        }
+        report(&holders, left);
+        let pause = backoff.min(left);
+        sleep(pause);
+        waited += pause;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A `/proc`-like directory with a `cmdline` per process
+    fn fake_proc(processes: &[(u32, &[&str])]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (pid, args) in processes {
+            let process = dir.path().join(pid.to_string());
+            fs::create_dir_all(&process).unwrap();
+            let mut cmdline = args.join("\0").into_bytes();
+            cmdline.push(0);
+            fs::write(process.join("cmdline"), cmdline).unwrap();
+        }
+        // Entries that are not processes, or whose cmdline is gone
+        fs::create_dir_all(dir.path().join("self")).unwrap();
+        fs::create_dir_all(dir.path().join("77")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_detects_lock_messages() {
+        assert!(is_locked_message(
+            "error: failed to init transaction (unable to lock database)\n\
+             error: could not lock database: File exists\n  \
+             if you're sure a package manager is not already running, you can remove \
+             /var/lib/pacman/db.lck"
+        ));
+        assert!(!is_locked_message("error: target not found: foo"));
+        let err = anyhow::Error::new(DatabaseLocked {
+            stderr: String::new(),
+        });
+        assert!(is_locked(&err));
+        assert!(!is_locked(&anyhow::anyhow!("unable to lock database")));
+    }
+
+    #[test]
+    fn test_holders_from_proc_cmdlines() {
+        let proc = fake_proc(&[
+            (4200, &["/usr/bin/paru", "-Syu"]),
+            (4100, &["sudo", "-E", "pacman", "-S", "git"]),
+            (300, &["/usr/bin/bash"]),
+            (301, &["vim", "/etc/pacman.conf"]),
+            (302, &["sudo", "vim"]),
+        ]);
+        assert_eq!(
+            holders(proc.path()),
+            vec![
+                Holder {
+                    pid: 4100,
+                    command: "sudo -E pacman -S git".to_string(),
+                },
+                Holder {
+                    pid: 4200,
+                    command: "/usr/bin/paru -Syu".to_string(),
+                },
+            ]
+        );
+        assert!(holders(Path::new("/nonexistent/proc")).is_empty());
+    }
+
+    #[test]
+    fn test_stale_lock_has_no_running_package_manager() {
+        let root = tempfile::tempdir().unwrap();
+        let lock = root.path().join("db.lck");
+        let idle = fake_proc(&[(300, &["bash"])]);
+        let busy = fake_proc(&[(4200, &["paru", "-Syu"])]);
+
+        assert_eq!(state(&lock, busy.path()), LockState::Free);
+        fs::write(&lock, "").unwrap();
+        assert_eq!(state(&lock, idle.path()), LockState::Stale);
+        assert!(matches!(state(&lock, busy.path()), LockState::Held(h) if h[0].pid == 4200));
+    }
+
+    #[test]
+    fn test_wait_backs_off_until_released_or_expired() {
+        let root = tempfile::tempdir().unwrap();
+        let lock = root.path().join("db.lck");
+        fs::write(&lock, "").unwrap();
+        let busy = fake_proc(&[(4200, &["paru", "-Syu"])]);
+
+        // Released during the third pause
+        let mut pauses = Vec::new();
+        let outcome = wait(
+            &lock,
+            busy.path(),
+            Duration::from_secs(600),
+            |pause| {
+                pauses.push(pause.as_secs());
+                if pauses.len() == 3 {
+                    fs::remove_file(&lock).unwrap();
+                }
+            },
+            |_, _| {},
+        );
+        assert_eq!(outcome, WaitOutcome::Free);
+        assert_eq!(pauses, [1, 2, 4]);
+
+        // Never released: the pauses add up to the limit, capped at 30s each
+        fs::write(&lock, "").unwrap();
+        let mut pauses = Vec::new();
+        let mut reported: Vec<(u32, u64)> = Vec::new();
+        let outcome = wait(
+            &lock,
+            busy.path(),
+            Duration::from_secs(100),
+            |pause| pauses.push(pause.as_secs()),
+            |holders, left| reported.push((holders[0].pid, left.as_secs())),
+        );
+        assert!(matches!(outcome, WaitOutcome::Expired(h) if h[0].pid == 4200));
+        assert_eq!(pauses, [1, 2, 4, 8, 16, 30, 30, 9]);
+        assert_eq!(pauses.iter().sum::<u64>(), 100);
+        assert_eq!(reported[0], (4200, 100));
+
+        // The holder exits without cleaning up
+        let gone: PathBuf = busy.path().join("4200");
+        let outcome = wait(
+            &lock,
+            busy.path(),
+            Duration::from_secs(60),
+            |_| fs::remove_dir_all(&gone).unwrap(),
+            |_, _| {},
+        );
+        assert_eq!(outcome, WaitOutcome::Stale);
+
+        // No wait at all when the limit is zero
+        let busy = fake_proc(&[(4200, &["paru", "-Syu"])]);
+        let outcome = wait(
+            &lock,
+            busy.path(),
+            Duration::ZERO,
+            |_| unreachable!(),
+            |_, _| {},
+        );
+        assert!(matches!(outcome, WaitOutcome::Expired(_)));
+    }
+}
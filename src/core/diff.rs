@@ -0,0 +1,233 @@
+//! Line-based unified diff rendering
+//!
+//! Used to preview pending dotfile changes without touching the destination.
+
+/// Default number of context lines around each change
+pub const DEFAULT_CONTEXT: usize = 3;
+
+// Inputs larger than this (lines old * lines new) are not diffed line by line
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Op<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Compute the line edit script between two texts using an LCS table
+fn line_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Op<'a>> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(Op::Equal(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(Op::Delete(old[i]));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|l| Op::Delete(l)));
+    ops.extend(new[j..].iter().map(|l| Op::Insert(l)));
+    ops
+}
+
+/// For each line of `old`, the line of `new` it is kept as, if any
+///
+/// `None` when the inputs are too large to diff line by line.
+pub(crate) fn line_matches(old: &[&str], new: &[&str]) -> Option<Vec<Option<usize>>> {
+    if old.len().saturating_mul(new.len()) > MAX_DIFF_CELLS {
+        return None;
+    }
+    let mut matches = vec![None; old.len()];
+    let (mut i, mut j) = (0, 0);
+    for op in line_ops(old, new) {
+        match op {
+            Op::Equal(_) => {
+                matches[i] = Some(j);
+                i += 1;
+                j += 1;
+            }
+            Op::Delete(_) => i += 1,
+            Op::Insert(_) => j += 1,
+        }
+    }
+    Some(matches)
+}
+
+/// Render a unified diff between `old` and `new`
+///
+/// Returns an empty string when both texts are identical.
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_label: &str,
+    new_label: &str,
+    context: usize,
+) -> String {
+    if old == new {
+        return String::new();
+    }
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    if old_lines.len().saturating_mul(new_lines.len()) > MAX_DIFF_CELLS {
+        out.push_str("@@ file too large to diff @@\n");
+        return out;
+    }
+
+    let ops = line_ops(&old_lines, &new_lines);
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(_)))
+        .map(|(idx, _)| idx)
+        .collect();
+    if changed.is_empty() {
+        // Only line-ending differences remain
+        out.push_str("@@ whitespace-only changes @@\n");
+        return out;
+    }
+
+    // Group changes into hunks separated by more than 2*context equal lines
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &idx in &changed {
+        let start = idx.saturating_sub(context);
+        let end = (idx + context + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    for (start, end) in hunks {
+        // Line numbers at hunk start
+        let (mut old_no, mut new_no) = (1, 1);
+        for op in &ops[..start] {
+            match op {
+                Op::Equal(_) => {
+                    old_no += 1;
+                    new_no += 1;
+                }
+                Op::Delete(_) => old_no += 1,
+                Op::Insert(_) => new_no += 1,
+            }
+        }
+        let slice = &ops[start..end];
+        let old_count = slice
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert(_)))
+            .count();
+        let new_count = slice
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete(_)))
+            .count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            if old_count == 0 { old_no - 1 } else { old_no },
+            old_count,
+            if new_count == 0 { new_no - 1 } else { new_no },
+            new_count
+        ));
+        for op in slice {
+            match op {
+                Op::Equal(l) => out.push_str(&format!(" {}\n", l)),
+                Op::Delete(l) => out.push_str(&format!("-{}\n", l)),
+                Op::Insert(l) => out.push_str(&format!("+{}\n", l)),
+            }
+        }
+    }
+    out
+}
+
+/// Colorize and indent a unified diff for terminal output
+pub fn colorize_diff(diff: &str, indent: &str) -> String {
+    let mut out = String::new();
+    for line in diff.lines() {
+        let rendered = if line.starts_with("+++") || line.starts_with("---") {
+            crate::internal::color::bold(line)
+        } else if line.starts_with("@@") {
+            crate::internal::color::cyan(line)
+        } else if line.starts_with('+') {
+            crate::internal::color::green(line)
+        } else if line.starts_with('-') {
+            crate::internal::color::red(line)
+        } else {
+            crate::internal::color::dim(line)
+        };
+        out.push_str(indent);
+        out.push_str(&rendered);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_inputs_produce_no_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new", 3), "");
+    }
+
+    #[test]
+    fn test_single_line_change() {
+        let diff = unified_diff("a\nb\nc\n", "a\nB\nc\n", "old", "new", 3);
+        assert_eq!(diff, "--- old\n+++ new\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+    }
+
+    #[test]
+    fn test_context_limits_hunk() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        let diff = unified_diff(old, new, "old", "new", 1);
+        assert_eq!(
+            diff,
+            "--- old\n+++ new\n@@ -4,3 +4,3 @@\n 4\n-5\n+five\n 6\n"
+        );
+    }
+
+    #[test]
+    fn test_separate_hunks() {
+        let old = "a\n1\n2\n3\n4\n5\n6\nb\n";
+        let new = "A\n1\n2\n3\n4\n5\n6\nB\n";
+        let diff = unified_diff(old, new, "old", "new", 1);
+        assert_eq!(diff.matches("@@ -").count(), 2);
+    }
+
+    #[test]
+    fn test_line_matches() {
+        assert_eq!(
+            line_matches(&["a", "b", "c"], &["a", "x", "c", "d"]),
+            Some(vec![Some(0), None, Some(2)])
+        );
+        assert_eq!(line_matches(&[], &["a"]), Some(Vec::new()));
+        let big = vec!["x"; 2001];
+        assert_eq!(line_matches(&big, &big), None);
+    }
+
+    #[test]
+    fn test_added_to_empty_file() {
+        let diff = unified_diff("", "x\n", "old", "new", 3);
+        assert_eq!(diff, "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+x\n");
+    }
+}
@@ -0,0 +1,893 @@
+//! `owl doctor`: checks of the config, dotfile sources and tools, as findings
+//!
+//! Every check reports into one [`Finding`] shape, so the human listing and
+//! `--json` (for CI to threshold on severities) show the same thing. Checks
+//! that need the config are skipped when it does not load; that failure is a
+//! finding of its own. `--online` adds checks that ask the package manager
+//! and the AUR.
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use crate::core::aur_rpc::HttpGet;
+use crate::core::config::Config;
+use crate::core::dotfiles::{DotfileMapping, DotfileRoots};
+use crate::core::pm::PackageManager;
+use crate::internal::probe::Probe;
+
+/// How bad a finding is; `error` means apply would fail or do the wrong thing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// What a finding is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Config,
+    Groups,
+    Dotfiles,
+    Env,
+    PackageManager,
+}
+
+/// One problem or observation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub category: Category,
+    pub message: String,
+    /// Config file (relative to the owl root) or path the finding is about
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+impl Finding {
+    fn new(severity: Severity, category: Category, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            category,
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    fn at(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+}
+
+/// All findings, most severe first
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Findings of at least `severity`
+    pub fn count_at_least(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity >= severity)
+            .count()
+    }
+}
+
+/// What the `--online` checks ask, and what they were told
+///
+/// Answers are kept for the invocation: each name is looked up in the repos
+/// and in the AUR at most once, however many checks ask about it.
+pub struct Online<'a> {
+    pm: &'a dyn PackageManager,
+    aur: &'a dyn HttpGet,
+    /// Architecture `@arch-aur-prefix` suffixes are checked for
+    arch: &'a str,
+    installed: OnceCell<Result<HashSet<String>, String>>,
+    /// Whether each name is a group or a repository package
+    in_repos: RefCell<HashMap<String, bool>>,
+    /// Whether the AUR has each name
+    in_aur: RefCell<HashMap<String, bool>>,
+}
+
+impl<'a> Online<'a> {
+    pub fn new(pm: &'a dyn PackageManager, aur: &'a dyn HttpGet, arch: &'a str) -> Self {
+        Self {
+            pm,
+            aur,
+            arch,
+            installed: OnceCell::new(),
+            in_repos: RefCell::default(),
+            in_aur: RefCell::default(),
+        }
+    }
+
+    fn installed(&self) -> Result<HashSet<String>> {
+        self.installed
+            .get_or_init(|| self.pm.list_installed().map_err(|e| e.to_string()))
+            .clone()
+            .map_err(|e| anyhow!(e))
+    }
+
+    /// Which of `names` are groups or repository packages
+    fn in_repos(&self, names: &[String]) -> Result<HashSet<String>> {
+        let unknown = unknown(&self.in_repos.borrow(), names);
+        if !unknown.is_empty() {
+            let groups = self.pm.group_members(&unknown)?;
+            let rest: Vec<String> = unknown
+                .iter()
+                .filter(|name| !groups.contains_key(*name))
+                .cloned()
+                .collect();
+            let found = self.pm.batch_repo_available(&rest)?;
+            let mut known = self.in_repos.borrow_mut();
+            for name in unknown {
+                let present = groups.contains_key(&name) || found.contains(&name);
+                known.insert(name, present);
+            }
+        }
+        Ok(known_present(&self.in_repos.borrow(), names))
+    }
+
+    /// Which of `names` the AUR has
+    fn in_aur(&self, names: &[String]) -> Result<HashSet<String>, String> {
+        let unknown = unknown(&self.in_aur.borrow(), names);
+        if !unknown.is_empty() {
+            let lookup = crate::core::aur_rpc::lookup(
+                &unknown,
+                self.aur,
+                &mut crate::core::aur_rpc::AurCache::default(),
+                crate::internal::time::now_secs(),
+                0,
+                true,
+            );
+            if let Some(error) = lookup.error {
+                return Err(error);
+            }
+            let mut known = self.in_aur.borrow_mut();
+            for name in unknown {
+                let present = lookup.packages.contains_key(&name);
+                known.insert(name, present);
+            }
+        }
+        Ok(known_present(&self.in_aur.borrow(), names))
+    }
+}
+
+/// `names` not answered yet, without repeats
+fn unknown(known: &HashMap<String, bool>, names: &[String]) -> Vec<String> {
+    let mut unknown: Vec<String> = names
+        .iter()
+        .filter(|name| !known.contains_key(*name))
+        .cloned()
+        .collect();
+    unknown.sort();
+    unknown.dedup();
+    unknown
+}
+
+fn known_present(known: &HashMap<String, bool>, names: &[String]) -> HashSet<String> {
+    names
+        .iter()
+        .filter(|name| known.get(*name) == Some(&true))
+        .cloned()
+        .collect()
+}
+
+/// Run every check against the owl root `owl_dir` for `hostname`
+///
+/// `on_path` says whether a program can be run, so tests need no real paru.
+pub fn diagnose(
+    owl_dir: &Path,
+    home: &str,
+    hostname: Option<&str>,
+    on_path: &dyn Fn(&str) -> bool,
+) -> Report {
+    diagnose_with(owl_dir, home, hostname, on_path, true, None)
+}
+
+/// `diagnose` with the checks that need the package manager and the AUR
+pub fn diagnose_online(
+    owl_dir: &Path,
+    home: &str,
+    hostname: Option<&str>,
+    on_path: &dyn Fn(&str) -> bool,
+    online: &Online,
+) -> Report {
+    diagnose_with(owl_dir, home, hostname, on_path, true, Some(online))
+}
+
+/// `diagnose`, looking up group files and sources one directory listing per
+/// parent when `batched`, or with one `stat` each
+fn diagnose_with(
+    owl_dir: &Path,
+    home: &str,
+    hostname: Option<&str>,
+    on_path: &dyn Fn(&str) -> bool,
+    batched: bool,
+    online: Option<&Online>,
+) -> Report {
+    let mut findings = Vec::new();
+    check_tools(on_path, &mut findings);
+    if let Some(config) = check_config(owl_dir, hostname, &mut findings) {
+        let roots = DotfileRoots::at(owl_dir, home);
+        let groups_dir = owl_dir.join(crate::internal::constants::GROUPS_DIR);
+        let mappings = crate::core::dotfiles::mappings_for_host(&config, hostname);
+        let probe = if batched {
+            let paths: Vec<_> = config
+                .groups
+                .iter()
+                .map(|group| Config::group_file_path(&groups_dir, group))
+                .chain(mappings.iter().map(|m| roots.source(m)))
+                .collect();
+            Probe::listing(&paths, crate::core::dotfiles::default_concurrency())
+        } else {
+            Probe::per_path()
+        };
+        check_groups(&groups_dir, &config, &probe, &mut findings);
+        check_dotfiles(&roots, &mappings, &probe, &mut findings);
+        let vars = crate::core::env::collect_all_env_vars(&config);
+        for warning in crate::core::env::dangerous_env_warnings(&vars) {
+            findings.push(Finding::new(Severity::Warning, Category::Env, warning));
+        }
+        if let Some(online) = online {
+            check_online(&config, online, &mut findings);
+        }
+    }
+    // Stable, so checks keep their order within a severity
+    findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+    Report { findings }
+}
+
+fn check_tools(on_path: &dyn Fn(&str) -> bool, findings: &mut Vec<Finding>) {
+    for program in [crate::internal::constants::PACKAGE_MANAGER, "pacman"] {
+        if !on_path(program) {
+            findings.push(Finding::new(
+                Severity::Error,
+                Category::PackageManager,
+                format!("{} is not installed or not on PATH", program),
+            ));
+        }
+    }
+}
+
+/// Load the config chain, reporting why it failed or what it warned about
+fn check_config(
+    owl_dir: &Path,
+    hostname: Option<&str>,
+    findings: &mut Vec<Finding>,
+) -> Option<Config> {
+    let main = crate::internal::constants::MAIN_CONFIG_FILE;
+    if !owl_dir.join(main).exists() {
+        findings.push(
+            Finding::new(Severity::Warning, Category::Config, "No main config file").at(main),
+        );
+    }
+    let Some(hostname) = hostname else {
+        findings.push(Finding::new(
+            Severity::Warning,
+            Category::Config,
+            "The hostname could not be read, so no host file or [host=] mapping applies",
+        ));
+        return None;
+    };
+    let host_file = format!(
+        "{}/{}{}",
+        crate::internal::constants::HOSTS_DIR,
+        hostname,
+        crate::internal::constants::OWL_EXT
+    );
+    if !owl_dir.join(&host_file).exists() {
+        findings.push(
+            Finding::new(
+                Severity::Info,
+                Category::Config,
+                "No host file for this machine; only main.owl and groups apply",
+            )
+            .at(host_file),
+        );
+    }
+
+    let config = match Config::load_for_host(owl_dir, hostname) {
+        Ok(config) => config,
+        Err(err) => {
+            // Load errors start with the file they are in: `groups/dev.owl: ...`
+            let message = err.to_string();
+            let finding = match message.split_once(": ") {
+                Some((file, rest)) if file.ends_with(crate::internal::constants::OWL_EXT) => {
+                    Finding::new(Severity::Error, Category::Config, rest).at(file)
+                }
+                _ => Finding::new(Severity::Error, Category::Config, message),
+            };
+            findings.push(finding);
+            return None;
+        }
+    };
+    for warning in &config.warnings {
+        findings.push(Finding::new(
+            Severity::Warning,
+            Category::Config,
+            warning.clone(),
+        ));
+    }
+    if let Err(cycle) = config.package_order() {
+        findings.push(Finding::new(
+            Severity::Error,
+            Category::Config,
+            cycle.to_string(),
+        ));
+    }
+    Some(config)
+}
+
+/// Groups referenced with `@group` that have no file, which the loader skips silently
+fn check_groups(groups_dir: &Path, config: &Config, probe: &Probe, findings: &mut Vec<Finding>) {
+    for group in &config.groups {
+        if !probe.exists(&Config::group_file_path(groups_dir, group)) {
+            findings.push(
+                Finding::new(
+                    Severity::Warning,
+                    Category::Groups,
+                    format!("Group '{}' is referenced but has no file", group),
+                )
+                .at(Config::group_label(group)),
+            );
+        }
+    }
+}
+
+/// The checks that ask the package manager and the AUR
+fn check_online(config: &Config, online: &Online, findings: &mut Vec<Finding>) {
+    let names = online.installed().and_then(|installed| {
+        let renamed =
+            crate::core::package::arch_names_with(config, &installed, online.arch, |names| {
+                online.in_repos(names)
+            })?;
+        Ok((installed, renamed))
+    });
+    let (installed, renamed) = match names {
+        Ok(names) => names,
+        Err(err) => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                Category::PackageManager,
+                format!("Could not look up the declared packages: {}", err),
+            ));
+            return;
+        }
+    };
+    check_arch_aur(config, online, &renamed, findings);
+    check_available(config, online, &installed, &renamed, findings);
+}
+
+/// Packages `@arch-aur-prefix` renames on this architecture whose suffixed
+/// name the AUR does not have, so apply would fail to build them
+fn check_arch_aur(
+    config: &Config,
+    online: &Online,
+    renamed: &HashMap<String, String>,
+    findings: &mut Vec<Finding>,
+) {
+    let Some(suffix) = config.arch_aur_suffixes.get(online.arch) else {
+        return;
+    };
+    let mut names: Vec<(&String, &String)> = renamed.iter().collect();
+    names.sort();
+    let suffixed: Vec<String> = names.iter().map(|(_, name)| (*name).clone()).collect();
+    let found = match online.in_aur(&suffixed) {
+        Ok(found) => found,
+        Err(error) => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                Category::PackageManager,
+                format!(
+                    "Could not check the @arch-aur-prefix names in the AUR: {}",
+                    error
+                ),
+            ));
+            return;
+        }
+    };
+    for (declared, name) in names {
+        if !found.contains(name) {
+            findings.push(Finding::new(
+                Severity::Error,
+                Category::Config,
+                format!(
+                    "{} is not in the AUR, but @arch-aur-prefix {}:suffix={} installs {} as it",
+                    name, online.arch, suffix, declared
+                ),
+            ));
+        }
+    }
+}
+
+/// Declared packages, not installed and installed under their own name, that
+/// are no group, in no repository and not in the AUR, so apply would fail to
+/// install them
+fn check_available(
+    config: &Config,
+    online: &Online,
+    installed: &HashSet<String>,
+    renamed: &HashMap<String, String>,
+    findings: &mut Vec<Finding>,
+) {
+    let mut missing: Vec<String> = config
+        .packages
+        .keys()
+        .filter(|name| !installed.contains(*name) && !renamed.contains_key(*name))
+        .cloned()
+        .collect();
+    missing.sort();
+    let in_repos = match online.in_repos(&missing) {
+        Ok(in_repos) => in_repos,
+        Err(err) => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                Category::PackageManager,
+                format!("Could not look up the declared packages: {}", err),
+            ));
+            return;
+        }
+    };
+    missing.retain(|name| !in_repos.contains(name));
+    let in_aur = match online.in_aur(&missing) {
+        Ok(in_aur) => in_aur,
+        Err(error) => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                Category::PackageManager,
+                format!(
+                    "Could not check the declared packages in the AUR: {}",
+                    error
+                ),
+            ));
+            return;
+        }
+    };
+    for name in missing.iter().filter(|name| !in_aur.contains(*name)) {
+        findings.push(Finding::new(
+            Severity::Error,
+            Category::Config,
+            format!("{} is in no repository and not in the AUR", name),
+        ));
+    }
+}
+
+/// Missing or escaping sources, destinations more than one mapping writes,
+/// and destinations that differ only by case (an error where one is on a
+/// case-insensitive filesystem, since they are the same file there)
+fn check_dotfiles(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    probe: &Probe,
+    findings: &mut Vec<Finding>,
+) {
+    let mut by_destination: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for m in mappings {
+        by_destination
+            .entry(m.destination.as_str())
+            .or_default()
+            .push(m.source.as_str());
+        let source = roots.source(m);
+        if let Some(reason) = roots.escape_reason(m) {
+            findings.push(Finding::new(Severity::Error, Category::Dotfiles, reason).at(&m.source));
+        } else if !probe.exists(&source) {
+            findings.push(
+                Finding::new(
+                    Severity::Error,
+                    Category::Dotfiles,
+                    format!("Source of {} -> {} does not exist", m.source, m.destination),
+                )
+                .at(source.display().to_string()),
+            );
+        }
+    }
+    for (destination, sources) in by_destination {
+        if sources.len() > 1 {
+            findings.push(
+                Finding::new(
+                    Severity::Error,
+                    Category::Dotfiles,
+                    format!(
+                        "{} mappings write the same destination: {}",
+                        sources.len(),
+                        sources.join(", ")
+                    ),
+                )
+                .at(destination),
+            );
+        }
+    }
+    let written: BTreeMap<String, &str> = mappings
+        .iter()
+        .map(|m| {
+            let path = roots.destination(m).to_string_lossy().into_owned();
+            (path, m.destination.as_str())
+        })
+        .collect();
+    let paths: Vec<String> = written.keys().cloned().collect();
+    let collisions = crate::internal::case_fold::case_collisions(&paths, |path| {
+        crate::internal::case_fold::insensitive_mount(Path::new(path))
+    });
+    for collision in collisions {
+        let destinations: Vec<&str> = collision.paths.iter().map(|p| written[p]).collect();
+        let (severity, consequence) = if collision.insensitive {
+            (
+                Severity::Error,
+                "on a case-insensitive filesystem they are one file",
+            )
+        } else {
+            (
+                Severity::Warning,
+                "they would be one file on a case-insensitive filesystem",
+            )
+        };
+        findings.push(
+            Finding::new(
+                severity,
+                Category::Dotfiles,
+                format!(
+                    "Destinations differ only by case: {}; {}",
+                    destinations.join(", "),
+                    consequence
+                ),
+            )
+            .at(destinations[0]),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_broken_config_findings_in_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let owl = dir.path().join(".owl");
+        fs::create_dir_all(owl.join("dotfiles/git")).unwrap();
+        fs::write(
+            owl.join("main.owl"),
+            "@group dev\n@package git\n:config git -> ~/.config/git\n:config git -> ~/.config/Git\n\
+             @package neovim\n:config nvim -> ~/.config/nvim\n:config vim -> ~/.config/nvim\n\
+             @env LD_PRELOAD=/tmp/evil.so\n",
+        )
+        .unwrap();
+        let home = dir.path().to_string_lossy().into_owned();
+
+        let report = diagnose(&owl, &home, Some("laptop"), &|program| program == "pacman");
+        let json = serde_json::to_value(&report).unwrap();
+        let findings = json["findings"].as_array().unwrap();
+        let has = |severity: &str, category: &str, text: &str| {
+            findings.iter().any(|f| {
+                f["severity"] == severity
+                    && f["category"] == category
+                    && f["message"].as_str().unwrap().contains(text)
+            })
+        };
+        assert!(has("error", "package_manager", "paru is not installed"));
+        assert!(has("error", "dotfiles", "Source of nvim -> ~/.config/nvim"));
+        assert!(has("error", "dotfiles", "2 mappings write the same"));
+        assert!(has(
+            "warning",
+            "dotfiles",
+            "Destinations differ only by case: ~/.config/Git, ~/.config/git"
+        ));
+        assert!(has("warning", "groups", "Group 'dev'"));
+        assert!(has("warning", "env", "LD_PRELOAD"));
+        assert!(has("info", "config", "No host file"));
+        assert_eq!(
+            findings.iter().find(|f| f["category"] == "groups").unwrap()["location"],
+            "groups/dev.owl"
+        );
+        // Most severe first
+        assert_eq!(findings[0]["severity"], "error");
+        assert_eq!(findings.last().unwrap()["severity"], "info");
+        assert_eq!(report.count_at_least(Severity::Error), 4);
+    }
+
+    #[test]
+    fn test_batched_lookups_find_what_per_path_lookups_find() {
+        let dir = tempfile::tempdir().unwrap();
+        let owl = dir.path().join(".owl");
+        let dotfiles = owl.join("dotfiles");
+        fs::create_dir_all(dotfiles.join("nvim/lua")).unwrap();
+        fs::create_dir_all(owl.join("groups")).unwrap();
+        fs::write(dotfiles.join("gitconfig"), "").unwrap();
+        fs::write(dotfiles.join("nvim/lua/init.lua"), "").unwrap();
+        fs::write(dotfiles.join("plain"), "").unwrap();
+        std::os::unix::fs::symlink(dotfiles.join("gitconfig"), dotfiles.join("linked")).unwrap();
+        std::os::unix::fs::symlink(dotfiles.join("gone"), dotfiles.join("dangling")).unwrap();
+        fs::write(
+            owl.join("groups/dev.owl"),
+            "@package rustup
+",
+        )
+        .unwrap();
+        fs::write(
+            owl.join("main.owl"),
+            "@group dev
+@group work
+             @package git
+:config gitconfig -> ~/.gitconfig
+:config missing -> ~/.missing
+             @package neovim
+:config nvim/lua/init.lua -> ~/.config/nvim/init.lua
+             :config nvim/lua/absent.lua -> ~/.config/nvim/absent.lua
+             :config nvim -> ~/.config/nvim
+             @package extra
+:config linked -> ~/.linked
+:config dangling -> ~/.dangling
+             :config plain/below -> ~/.below
+:config nodir/file -> ~/.nodir
+             :config ../outside -> ~/.outside
+",
+        )
+        .unwrap();
+        let home = dir.path().to_string_lossy().into_owned();
+
+        // Mappings come in package order, which differs between loads
+        let sorted = |batched| {
+            let mut report = diagnose_with(&owl, &home, Some("laptop"), &|_| true, batched, None);
+            report
+                .findings
+                .sort_by(|a, b| (&a.message, &a.location).cmp(&(&b.message, &b.location)));
+            report
+        };
+        let batched = sorted(true);
+        assert_eq!(batched, sorted(false));
+        let mut missing: Vec<&str> = batched
+            .findings
+            .iter()
+            .filter(|f| f.message.starts_with("Source of"))
+            .map(|f| f.message.as_str())
+            .collect();
+        missing.sort();
+        assert_eq!(
+            missing,
+            [
+                "Source of dangling -> ~/.dangling does not exist",
+                "Source of missing -> ~/.missing does not exist",
+                "Source of nodir/file -> ~/.nodir does not exist",
+                "Source of nvim/lua/absent.lua -> ~/.config/nvim/absent.lua does not exist",
+                "Source of plain/below -> ~/.below does not exist",
+            ]
+        );
+        assert!(
+            batched
+                .findings
+                .iter()
+                .any(|f| f.message.contains("'work'"))
+        );
+        assert!(!batched.findings.iter().any(|f| f.message.contains("'dev'")));
+    }
+
+    #[test]
+    fn test_unparsable_file_is_located_and_stops_config_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let owl = dir.path().join(".owl");
+        fs::create_dir_all(owl.join("hosts")).unwrap();
+        fs::write(owl.join("main.owl"), "@package git\n").unwrap();
+        fs::write(owl.join("hosts/laptop.owl"), "@strict\n:confgi nvim\n").unwrap();
+
+        let report = diagnose(&owl, "/home/me", Some("laptop"), &|_| true);
+        assert_eq!(report.findings.len(), 1, "{:?}", report.findings);
+        let finding = &report.findings[0];
+        assert_eq!(finding.severity, Severity::Error);
+        assert_eq!(finding.location.as_deref(), Some("hosts/laptop.owl"));
+        assert!(finding.message.contains(":confgi"), "{}", finding.message);
+
+        let clean = diagnose(&owl, "/home/me", Some("desktop"), &|_| true);
+        assert_eq!(clean.count_at_least(Severity::Warning), 0);
+    }
+
+    /// Answers every AUR request with `body` and records the URLs
+    struct FakeAur {
+        body: Result<String, String>,
+        urls: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl HttpGet for FakeAur {
+        fn get(&self, url: &str) -> anyhow::Result<String> {
+            self.urls.borrow_mut().push(url.to_string());
+            self.body.clone().map_err(|e| anyhow::anyhow!(e))
+        }
+    }
+
+    #[test]
+    fn test_online_checks_arch_suffixed_names_in_the_aur() {
+        let dir = tempfile::tempdir().unwrap();
+        let owl = dir.path().join(".owl");
+        fs::create_dir_all(&owl).unwrap();
+        fs::write(
+            owl.join("main.owl"),
+            "@arch-aur-prefix aarch64:suffix=-aarch64\n@package archdoc-spotify\n\
+             @package archdoc-slack\n@package archdoc-vim\n",
+        )
+        .unwrap();
+        let (_pm_dir, pm) = crate::core::pm::fake::pm(
+            "exit 0",
+            r#"case "$1" in
+  -Sgg) ;;
+  -Si)
+    shift
+    for pkg in "$@"; do
+      case "$pkg" in
+        archdoc-vim) printf 'Repository      : extra\nName            : %s\n\n' "$pkg" ;;
+        *) echo "error: package '$pkg' was not found" >&2; failed=1 ;;
+      esac
+    done
+    exit ${failed:-0} ;;
+esac"#,
+        );
+        let aur = FakeAur {
+            body: Ok(r#"{"resultcount":1,"results":[
+                {"Name":"archdoc-spotify-aarch64","Version":"1.2-1","OutOfDate":null,
+                 "LastModified":1718000000}],"type":"multiinfo","version":5}"#
+                .to_string()),
+            urls: Default::default(),
+        };
+        let online = |arch| Online::new(&pm, &aur, arch);
+        let aur_findings = |report: &Report| -> Vec<Finding> {
+            report
+                .findings
+                .iter()
+                .filter(|f| f.message.contains("@arch-aur-prefix"))
+                .cloned()
+                .collect()
+        };
+
+        let report = diagnose_online(
+            &owl,
+            "/home/me",
+            Some("laptop"),
+            &|_| true,
+            &online("aarch64"),
+        );
+        assert_eq!(
+            aur_findings(&report),
+            vec![Finding::new(
+                Severity::Error,
+                Category::Config,
+                "archdoc-slack-aarch64 is not in the AUR, but @arch-aur-prefix \
+                 aarch64:suffix=-aarch64 installs archdoc-slack as it"
+            )]
+        );
+        // The repo package keeps its name and is not looked up
+        assert_eq!(aur.urls.borrow().len(), 1);
+        assert!(!aur.urls.borrow()[0].contains("archdoc-vim"));
+
+        // Nothing to check on other architectures, or without --online; the
+        // declared names themselves are still looked up
+        let report = diagnose_online(
+            &owl,
+            "/home/me",
+            Some("laptop"),
+            &|_| true,
+            &online("x86_64"),
+        );
+        assert!(aur_findings(&report).is_empty());
+        assert_eq!(aur.urls.borrow().len(), 2);
+        assert!(!aur.urls.borrow()[1].contains("-aarch64"));
+        assert!(aur_findings(&diagnose(&owl, "/home/me", Some("laptop"), &|_| true)).is_empty());
+
+        // An unreachable AUR is a warning, not a verdict
+        let offline = FakeAur {
+            body: Err("Could not resolve host".to_string()),
+            urls: Default::default(),
+        };
+        let report = diagnose_online(
+            &owl,
+            "/home/me",
+            Some("laptop"),
+            &|_| true,
+            &Online::new(&pm, &offline, "aarch64"),
+        );
+        let findings = aur_findings(&report);
+        assert_eq!(findings.len(), 1, "{:?}", findings);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert!(findings[0].message.contains("Could not resolve host"));
+    }
+
+    #[test]
+    fn test_online_lookups_are_made_once_per_invocation() {
+        let dir = tempfile::tempdir().unwrap();
+        let owl = dir.path().join(".owl");
+        fs::create_dir_all(&owl).unwrap();
+        fs::write(
+            owl.join("main.owl"),
+            "@arch-aur-prefix aarch64:suffix=-aarch64\n@package dc-ripgrep\n\
+             @package dc-devel\n@package dc-spotify\n@package dc-fd\n",
+        )
+        .unwrap();
+        let log = dir.path().join("calls.log");
+        let (_pm_dir, pm) = crate::core::pm::fake::pm(
+            &format!(
+                "echo \"paru $*\" >> {}\n[ \"$1\" = -Qq ] && echo dc-fd\nexit 0",
+                log.display()
+            ),
+            &format!(
+                r#"echo "pacman $*" >> {}
+case "$1" in
+  -Sgg) echo "dc-devel gcc" ;;
+  -Si)
+    shift
+    for pkg in "$@"; do
+      case "$pkg" in
+        dc-ripgrep) printf 'Repository      : extra\nName            : %s\n\n' "$pkg" ;;
+        *) echo "error: package '$pkg' was not found" >&2; failed=1 ;;
+      esac
+    done
+    exit ${{failed:-0}} ;;
+esac"#,
+                log.display()
+            ),
+        );
+        let aur = FakeAur {
+            body: Ok(
+                r#"{"resultcount":0,"results":[],"type":"multiinfo","version":5}"#.to_string(),
+            ),
+            urls: Default::default(),
+        };
+        let calls = || -> Vec<String> {
+            fs::read_to_string(&log)
+                .unwrap()
+                .lines()
+                .map(|line| line.split(' ').take(2).collect::<Vec<_>>().join(" "))
+                .collect()
+        };
+
+        // aarch64: the @arch-aur-prefix check looks everything up, and the
+        // availability check gets its answers from what it was told
+        let report = diagnose_online(
+            &owl,
+            "/home/me",
+            Some("laptop"),
+            &|_| true,
+            &Online::new(&pm, &aur, "aarch64"),
+        );
+        assert_eq!(calls(), vec!["paru -Qq", "pacman -Sgg", "pacman -Si"]);
+        assert_eq!(aur.urls.borrow().len(), 1);
+        assert!(aur.urls.borrow()[0].contains("dc-spotify-aarch64"));
+        let errors: Vec<&str> = report
+            .findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| f.message.as_str())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "dc-spotify-aarch64 is not in the AUR, but @arch-aur-prefix \
+                 aarch64:suffix=-aarch64 installs dc-spotify as it"
+            ]
+        );
+
+        // x86_64 renames nothing, so only the availability check asks; which
+        // names are groups stays known for the process
+        fs::remove_file(&log).unwrap();
+        let report = diagnose_online(
+            &owl,
+            "/home/me",
+            Some("laptop"),
+            &|_| true,
+            &Online::new(&pm, &aur, "x86_64"),
+        );
+        assert_eq!(calls(), vec!["paru -Qq", "pacman -Si"]);
+        assert_eq!(aur.urls.borrow().len(), 2);
+        assert!(report.findings.contains(&Finding::new(
+            Severity::Error,
+            Category::Config,
+            "dc-spotify is in no repository and not in the AUR"
+        )));
+    }
+}
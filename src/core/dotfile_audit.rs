@@ -0,0 +1,283 @@
+//! Reverse audit of the dotfiles directory
+//!
+//! Finds entries under `~/.owl/dotfiles` that no current mapping uses, so
+//! stale sources can be archived into `.attic/<date>/`.
+
+use crate::core::dotfiles::{DotfileMapping, DotfileRoots};
+use anyhow::{Result, anyhow};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory inside the dotfiles root that archived entries are moved to
+pub const ATTIC_DIR: &str = ".attic";
+
+/// Entries at the top of the dotfiles root that are never audited
+const SKIPPED: &[&str] = &[ATTIC_DIR, ".git"];
+
+/// An unreferenced file or directory, relative to the dotfiles root
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AuditEntry {
+    pub path: PathBuf,
+    /// Size in bytes (summed over files for directories)
+    pub size: u64,
+    /// Last modification, seconds since the Unix epoch
+    pub modified: u64,
+}
+
+/// Paths relative to `source_dir` that mappings read from
+///
+/// Mappings from other roots (`@dotfiles-root`) only count when that root lies
+/// inside `source_dir`.
+pub fn covered_paths(roots: &DotfileRoots, mappings: &[DotfileMapping]) -> BTreeSet<PathBuf> {
+    mappings
+        .iter()
+        .filter_map(|m| {
+            roots
+                .source(m)
+                .strip_prefix(&roots.source_dir)
+                .ok()
+                .map(Path::to_path_buf)
+        })
+        .collect()
+}
+
+/// Topmost entries of `entries` that neither are covered, lie under a covered
+/// directory, nor contain a covered path
+///
+/// `entries` lists every file and directory relative to the dotfiles root.
+pub fn unreferenced(entries: &[PathBuf], covered: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let mut sorted: Vec<&PathBuf> = entries.iter().collect();
+    sorted.sort();
+    let mut orphans: Vec<PathBuf> = Vec::new();
+    for entry in sorted {
+        let under_covered = entry.ancestors().any(|a| covered.contains(a));
+        let contains_covered = covered.iter().any(|c| c.starts_with(entry));
+        let under_orphan = orphans.iter().any(|o| entry.starts_with(o));
+        if !under_covered && !contains_covered && !under_orphan {
+            orphans.push(entry.clone());
+        }
+    }
+    orphans
+}
+
+/// Every file and directory below `root`, relative to it
+pub fn list_entries(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    if root.is_dir() {
+        walk(root, root, &mut entries)?;
+    }
+    Ok(entries)
+}
+
+fn walk(dir: &Path, base: &Path, entries: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in
+        fs::read_dir(dir).map_err(|e| anyhow!("Failed to read dir {}: {}", dir.display(), e))?
+    {
+        let entry =
+            entry.map_err(|e| anyhow!("Failed to read entry in {}: {}", dir.display(), e))?;
+        let path = entry.path();
+        let rel = path.strip_prefix(base).unwrap_or(&path).to_path_buf();
+        if dir == base && SKIPPED.iter().any(|s| rel == Path::new(s)) {
+            continue;
+        }
+        let is_dir = entry
+            .file_type()
+            .map_err(|e| anyhow!("Failed to stat {}: {}", path.display(), e))?
+            .is_dir();
+        entries.push(rel);
+        if is_dir {
+            walk(&path, base, entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Unreferenced entries under `roots.source_dir` with their size and mtime
+pub fn audit(roots: &DotfileRoots, mappings: &[DotfileMapping]) -> Result<Vec<AuditEntry>> {
+    let covered = covered_paths(roots, mappings);
+    let entries = list_entries(&roots.source_dir)?;
+    unreferenced(&entries, &covered)
+        .into_iter()
+        .map(|path| {
+            let full = roots.source_dir.join(&path);
+            let meta = fs::symlink_metadata(&full)
+                .map_err(|e| anyhow!("Failed to stat {}: {}", full.display(), e))?;
+            let modified = meta
+                .modified()
+                .map(crate::internal::time::to_secs)
+                .unwrap_or(0);
+            Ok(AuditEntry {
+                size: disk_size(&full)?,
+                path,
+                modified,
+            })
+        })
+        .collect()
+}
+
+fn disk_size(path: &Path) -> Result<u64> {
+    let meta = fs::symlink_metadata(path)
+        .map_err(|e| anyhow!("Failed to stat {}: {}", path.display(), e))?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut total = 0;
+    for entry in
+        fs::read_dir(path).map_err(|e| anyhow!("Failed to read dir {}: {}", path.display(), e))?
+    {
+        let entry =
+            entry.map_err(|e| anyhow!("Failed to read entry in {}: {}", path.display(), e))?;
+        total += disk_size(&entry.path())?;
+    }
+    Ok(total)
+}
+
+/// Move entries into `.attic/<date>/`, keeping their relative paths; returns the attic directory
+pub fn archive(source_dir: &Path, entries: &[AuditEntry], date: &str) -> Result<PathBuf> {
+    let attic = source_dir.join(ATTIC_DIR).join(date);
+    for entry in entries {
+        let from = source_dir.join(&entry.path);
+        let to = attic.join(&entry.path);
+        if to.exists() {
+            return Err(anyhow!("{} already exists", to.display()));
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Failed to create directory {}: {}", parent.display(), e))?;
+        }
+        fs::rename(&from, &to).map_err(|e| {
+            anyhow!(
+                "Failed to move {} to {}: {}",
+                from.display(),
+                to.display(),
+                e
+            )
+        })?;
+    }
+    Ok(attic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(list: &[&str]) -> Vec<PathBuf> {
+        list.iter().map(PathBuf::from).collect()
+    }
+
+    fn mapping(source: &str, root: Option<&Path>) -> DotfileMapping {
+        DotfileMapping {
+            source: source.to_string(),
+            destination: format!("~/.{}", source),
+            root: root.map(Path::to_path_buf),
+            from: None,
+            hardlink: false,
+            force_owned: false,
+            modes: Default::default(),
+        }
+    }
+
+    fn roots() -> DotfileRoots {
+        DotfileRoots {
+            owl_dir: PathBuf::from("/owl"),
+            source_dir: PathBuf::from("/owl/dotfiles"),
+            home: "/home/me".to_string(),
+            backup_dir: PathBuf::from("/owl/.state/backups"),
+            dest_prefix: None,
+            hash_algo: crate::core::dotfiles::HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_covered_paths_resolve_every_mapping_form() {
+        let mappings = vec![
+            // explicit and implicit sources
+            mapping("bashrc", None),
+            mapping("nvim", None),
+            // a group root inside the dotfiles dir
+            mapping("proxy.conf", Some(Path::new("/owl/dotfiles/team"))),
+            // a group root elsewhere never covers anything here
+            mapping("gitconfig", Some(Path::new("/work/dotfiles"))),
+        ];
+        assert_eq!(
+            covered_paths(&roots(), &mappings),
+            paths(&["bashrc", "nvim", "team/proxy.conf"])
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn test_unreferenced_reports_topmost_orphans() {
+        let covered: BTreeSet<PathBuf> = paths(&["bashrc", "nvim", "team/proxy.conf"])
+            .into_iter()
+            .collect();
+        let entries = paths(&[
+            "bashrc",
+            "nvim",
+            "nvim/init.lua",
+            "nvim/lua",
+            "nvim/lua/plugins.lua",
+            "team",
+            "team/proxy.conf",
+            "team/old.conf",
+            "zshrc",
+            "old-i3",
+            "old-i3/config",
+        ]);
+        assert_eq!(
+            unreferenced(&entries, &covered),
+            paths(&["old-i3", "team/old.conf", "zshrc"])
+        );
+    }
+
+    #[test]
+    fn test_audit_and_archive_fixture_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = DotfileRoots {
+            owl_dir: dir.path().to_path_buf(),
+            source_dir: dir.path().join("dotfiles"),
+            home: dir.path().join("home").to_string_lossy().into_owned(),
+            backup_dir: dir.path().join("backups"),
+            dest_prefix: None,
+            hash_algo: crate::core::dotfiles::HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        };
+        let src = &roots.source_dir;
+        fs::create_dir_all(src.join("nvim")).unwrap();
+        fs::create_dir_all(src.join("old-i3")).unwrap();
+        fs::create_dir_all(src.join(".git")).unwrap();
+        fs::write(src.join("bashrc"), "bash").unwrap();
+        fs::write(src.join("nvim/init.lua"), "init").unwrap();
+        fs::write(src.join("old-i3/config"), "i3 config").unwrap();
+        fs::write(src.join("zshrc"), "zsh").unwrap();
+
+        let mappings = vec![mapping("bashrc", None), mapping("nvim", None)];
+        let orphans = audit(&roots, &mappings).unwrap();
+        assert_eq!(
+            orphans.iter().map(|e| e.path.clone()).collect::<Vec<_>>(),
+            paths(&["old-i3", "zshrc"])
+        );
+        assert_eq!(orphans[0].size, 9);
+
+        let attic = archive(src, &orphans, "2026-10-16").unwrap();
+        assert!(attic.join("old-i3/config").is_file());
+        assert!(!src.join("zshrc").exists());
+        // Archived entries are not reported again
+        assert!(audit(&roots, &mappings).unwrap().is_empty());
+    }
+}
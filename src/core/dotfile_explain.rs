@@ -0,0 +1,273 @@
+//! `owl dots explain DEST`: everything owl knows about one dotfile destination
+//!
+//! Puts together what is otherwise spread over several commands: the package
+//! and config file that declare the mapping, where its source resolves to,
+//! what `apply` would do with it now and why, and the baseline apply recorded
+//! the last time it wrote the destination.
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::core::backup::BackupStore;
+use crate::core::config::Config;
+use crate::core::dotfiles::{DotfileRoots, DotfileStatus, normalize};
+
+/// How the destination is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingKind {
+    Copy,
+    /// `[hardlink]`
+    Hardlink,
+}
+
+/// What apply recorded when it last wrote the destination
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LastApplied {
+    /// Backup store object holding the source content it was written from
+    pub source_object: String,
+    /// sha256 of what was written
+    pub written: String,
+    /// The destination still has exactly that content
+    pub unchanged: bool,
+}
+
+/// The report for one destination
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DotfileExplanation {
+    /// As written in the config
+    pub destination: String,
+    pub destination_path: String,
+    pub package: String,
+    /// Config file, relative to the owl root, whose `:config` list is in effect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// As written in the config
+    pub source: String,
+    pub source_path: String,
+    pub kind: MappingKind,
+    pub directory: bool,
+    /// `create`, `update`, `up_to_date`, `merge` or `conflict`, as in `dotfile_action`
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_applied: Option<LastApplied>,
+}
+
+/// Explain the mapping that writes `destination` on `hostname`
+///
+/// `destination` is matched against where mappings resolve to, so `~/.gitconfig`
+/// and `/home/me/.gitconfig` find the same one; a relative path is taken
+/// from the current directory.
+pub fn explain(
+    config: &Config,
+    roots: &DotfileRoots,
+    hostname: Option<&str>,
+    destination: &str,
+) -> Result<DotfileExplanation> {
+    let wanted = normalize(&resolve(roots, destination)?);
+    let (package, mapping) = crate::core::dotfiles::package_mappings(config, hostname)
+        .into_iter()
+        .find(|(_, m)| normalize(&roots.destination(m)) == wanted)
+        .ok_or_else(|| {
+            anyhow!(
+                "No dotfile mapping on this host writes {}",
+                wanted.display()
+            )
+        })?;
+    let source_path = roots.source(&mapping);
+    let destination_path = roots.destination(&mapping);
+    let status = crate::core::dotfiles::analyze_dotfiles(roots, std::slice::from_ref(&mapping), 1)?
+        .remove(0);
+    let status_name = match status {
+        DotfileStatus::Create => "create",
+        DotfileStatus::Update => "update",
+        DotfileStatus::UpToDate => "up_to_date",
+        DotfileStatus::Merge => "merge",
+        DotfileStatus::Conflict(_) | DotfileStatus::MergeConflict { .. } => "conflict",
+    };
+    let baselines = BackupStore::new(roots.backup_dir.clone()).baselines()?;
+    let last_applied = baselines
+        .get(destination_path.to_string_lossy().as_ref())
+        .map(|baseline| LastApplied {
+            source_object: baseline.source.clone(),
+            written: baseline.written.clone(),
+            unchanged: std::fs::read(&destination_path)
+                .is_ok_and(|content| crate::core::backup::sha256_hex(&content) == baseline.written),
+        });
+    // `:config` comes whole from the highest-precedence file that sets it
+    let file = config.declarations.get(package).and_then(|declarations| {
+        declarations
+            .iter()
+            .find(|d| !d.package.config.is_empty())
+            .map(|d| d.file.clone())
+    });
+    Ok(DotfileExplanation {
+        destination: mapping.destination.clone(),
+        destination_path: destination_path.to_string_lossy().into_owned(),
+        package: package.to_string(),
+        file,
+        source: mapping.source.clone(),
+        source_path: source_path.to_string_lossy().into_owned(),
+        kind: if mapping.hardlink {
+            MappingKind::Hardlink
+        } else {
+            MappingKind::Copy
+        },
+        directory: source_path.is_dir(),
+        status: status_name,
+        reason: status.conflict_reason(),
+        last_applied,
+    })
+}
+
+/// `destination` as a path: `~/` and absolute ones as in mappings, others
+/// from the current directory
+fn resolve(roots: &DotfileRoots, destination: &str) -> Result<std::path::PathBuf> {
+    if destination.starts_with('~') || Path::new(destination).is_absolute() {
+        return Ok(roots.destination_path(destination));
+    }
+    let cwd = std::env::current_dir()
+        .map_err(|e| anyhow!("Failed to read the current directory: {}", e))?;
+    Ok(cwd.join(destination))
+}
+
+/// The report as text
+pub fn render_text(explanation: &DotfileExplanation) -> String {
+    use crate::internal::color;
+    let env = crate::internal::environment::get();
+    let mut lines = vec![format!(
+        "{} {}",
+        color::bold(&explanation.destination),
+        color::dim(&format!("({})", explanation.destination_path))
+    )];
+    let mut field = |name: &str, value: String| lines.push(format!("  {:<13}{}", name, value));
+    field(
+        "declared by",
+        match &explanation.file {
+            Some(file) => format!("{} in {}", explanation.package, file),
+            None => explanation.package.clone(),
+        },
+    );
+    field(
+        "source",
+        format!(
+            "{} {}",
+            explanation.source,
+            color::dim(&format!("({})", env.display_path(&explanation.source_path)))
+        ),
+    );
+    let kind = match explanation.kind {
+        MappingKind::Copy => "copy",
+        MappingKind::Hardlink => "hardlink",
+    };
+    field(
+        "kind",
+        format!(
+            "{}{}",
+            kind,
+            if explanation.directory {
+                " (directory)"
+            } else {
+                ""
+            }
+        ),
+    );
+    let status = match explanation.status {
+        "up_to_date" => color::green("up to date"),
+        "conflict" => color::red("conflict"),
+        other => color::yellow(other),
+    };
+    field("status", status);
+    if let Some(reason) = &explanation.reason {
+        field("reason", reason.clone());
+    }
+    field(
+        "last applied",
+        match &explanation.last_applied {
+            Some(last) => format!(
+                "sha256 {}{}",
+                last.written,
+                if last.unchanged {
+                    ""
+                } else {
+                    ", changed since"
+                }
+            ),
+            None => color::dim("no record (directories and hardlinks have none)"),
+        },
+    );
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn setup(main: &str) -> (tempfile::TempDir, Config, DotfileRoots) {
+        let dir = tempfile::tempdir().unwrap();
+        let owl = dir.path().join(".owl");
+        fs::create_dir_all(owl.join("dotfiles/nvim")).unwrap();
+        fs::create_dir_all(owl.join("groups")).unwrap();
+        fs::write(owl.join("main.owl"), main).unwrap();
+        fs::write(
+            owl.join("groups/dev.owl"),
+            "@package git\n:config gitconfig -> ~/.gitconfig\n",
+        )
+        .unwrap();
+        fs::write(owl.join("dotfiles/gitconfig"), "[user]\n").unwrap();
+        fs::write(owl.join("dotfiles/nvim/init.lua"), "-- init\n").unwrap();
+        let config = Config::load_for_host(&owl, "box").unwrap();
+        let roots = DotfileRoots::at(&owl, &dir.path().to_string_lossy());
+        (dir, config, roots)
+    }
+
+    #[test]
+    fn test_up_to_date_file_with_baseline() {
+        let (dir, config, roots) = setup("@group dev\n");
+        let mappings = crate::core::dotfiles::mappings_for_host(&config, Some("box"));
+        crate::core::dotfiles::sync_dotfiles(&roots, &mappings, false, 1, 5, None, &mut |_| {})
+            .unwrap();
+
+        let home_path = dir.path().join(".gitconfig").to_string_lossy().into_owned();
+        for query in ["~/.gitconfig", home_path.as_str()] {
+            let explanation = explain(&config, &roots, Some("box"), query).unwrap();
+            assert_eq!(explanation.package, "git");
+            assert_eq!(explanation.file.as_deref(), Some("groups/dev.owl"));
+            assert_eq!(explanation.source, "gitconfig");
+            assert!(explanation.source_path.ends_with(".owl/dotfiles/gitconfig"));
+            assert_eq!(explanation.kind, MappingKind::Copy);
+            assert_eq!(explanation.status, "up_to_date");
+            assert_eq!(explanation.reason, None);
+            let last = explanation.last_applied.unwrap();
+            assert_eq!(last.written, crate::core::backup::sha256_hex(b"[user]\n"));
+            assert!(last.unchanged);
+        }
+    }
+
+    #[test]
+    fn test_conflicting_destination_has_a_reason() {
+        let (_dir, config, roots) =
+            setup("@group dev\n@package neovim\n:config nvim -> /etc/xdg/nvim [hardlink]\n");
+
+        let explanation = explain(&config, &roots, Some("box"), "/etc/xdg/nvim").unwrap();
+        assert_eq!(explanation.package, "neovim");
+        assert_eq!(explanation.file.as_deref(), Some("main.owl"));
+        assert_eq!(explanation.kind, MappingKind::Hardlink);
+        assert!(explanation.directory);
+        assert_eq!(explanation.status, "conflict", "{:?}", explanation);
+        let reason = explanation.reason.as_deref().unwrap();
+        assert!(reason.contains("--allow-outside-home"), "{}", reason);
+        assert_eq!(explanation.last_applied, None);
+
+        let text = render_text(&explanation);
+        assert!(text.contains("neovim in main.owl"), "{}", text);
+        assert!(text.contains("hardlink (directory)"), "{}", text);
+
+        let err = explain(&config, &roots, Some("box"), "~/.zshrc").unwrap_err();
+        assert!(err.to_string().contains("No dotfile mapping"), "{}", err);
+    }
+}
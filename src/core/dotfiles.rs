@@ -3,16 +3,33 @@
 //! This module handles the synchronization of dotfiles from the dotfiles directory
 //! to their target locations in the user's home directory.
 
+use crate::core::backup::{BackupStore, Baseline, Manifest};
+use crate::core::events::{EventPhase, EventSink, OwlEvent};
+use crate::core::file_modes::{MappingModes, ModePolicy};
+use crate::core::merge::Merged;
+use crate::core::state::DeployedDotfiles;
 use anyhow::{Result, anyhow};
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 
 /// Represents a dotfile mapping from source to destination
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DotfileMapping {
     pub source: String,
     pub destination: String,
+    /// Source root from `@dotfiles-root`; `None` uses the default dotfiles directory
+    pub root: Option<PathBuf>,
+    /// `[from=DIR]`: subdirectory of the source root the source is resolved in
+    pub from: Option<String>,
+    /// `[hardlink]`: link destination files to their sources instead of copying
+    pub hardlink: bool,
+    /// `[force-owned]`: the destination is knowingly a file a package owns
+    pub force_owned: bool,
+    /// `[mode=]`, `[private]` and `[preserve-mode]` (see `core::file_modes`)
+    pub modes: MappingModes,
 }
 
 /// Status of a dotfile operation
@@ -21,36 +38,338 @@ pub enum DotfileStatus {
     Create,
     Update,
     UpToDate,
+    /// The destination cannot be written; nothing is changed for this mapping
+    Conflict(String),
+    /// Changed both here and in the source since owl last wrote it; the
+    /// three-way merge of both changes is written (`--dotfile-diverged merge`)
+    Merge,
+    /// As `Merge`, but both sides changed the same lines; nothing is written.
+    /// `markers` is where the merge with conflict markers was saved
+    MergeConflict {
+        conflicts: usize,
+        markers: Option<String>,
+    },
+}
+
+impl DotfileStatus {
+    /// Why nothing is written, for conflicts
+    pub fn conflict_reason(&self) -> Option<String> {
+        match self {
+            DotfileStatus::Conflict(reason) => Some(reason.clone()),
+            DotfileStatus::MergeConflict { conflicts, markers } => Some(format!(
+                "{}; merging the two left {} conflict(s){}",
+                DIVERGED,
+                conflicts,
+                markers
+                    .as_deref()
+                    .map(|path| format!(", see {}", path))
+                    .unwrap_or_default()
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Why a destination is not simply replaced by its source
+const DIVERGED: &str = "changed both here and in the source since owl last wrote it";
+
+/// Suffix of the file next to a destination holding a conflicted merge
+pub const MERGE_MARKERS_SUFFIX: &str = ".owl-merge";
+
+/// What to do with a file destination changed both locally and in its source
+/// since owl last wrote it (`--dotfile-diverged`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Divergence {
+    /// Replace it with the source; the local version is kept in the backups
+    #[default]
+    Overwrite,
+    /// Leave it and report a conflict
+    Keep,
+    /// Merge both changes; binary files and conflicting merges are left alone
+    Merge,
 }
 
 /// Represents a dotfile operation to be performed
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DotfileAction {
     pub mapping: DotfileMapping,
     pub status: DotfileStatus,
 }
 
-fn owl_dotfiles_dir() -> Result<PathBuf> {
-    let home = std::env::var("HOME").map_err(|_| anyhow!("HOME environment variable not set"))?;
-    Ok(Path::new(&home)
-        .join(crate::internal::constants::OWL_DIR)
-        .join(crate::internal::constants::DOTFILES_DIR))
+/// Source prefix resolved against the owl root instead of the dotfiles directory
+pub const OWL_ROOT_PREFIX: &str = "@/";
+
+/// Directories dotfile mappings are resolved against
+#[derive(Debug, Clone)]
+pub struct DotfileRoots {
+    /// The owl root (`~/.owl`), for `@/` sources
+    pub owl_dir: PathBuf,
+    /// Directory holding dotfile sources (`~/.owl/dotfiles`)
+    pub source_dir: PathBuf,
+    /// Home directory used to expand `~` in destinations
+    pub home: String,
+    /// Backup store for replaced destinations (`~/.owl/.state/backups`)
+    pub backup_dir: PathBuf,
+    /// Stage every destination under this directory instead (`--dest-prefix`)
+    pub dest_prefix: Option<PathBuf>,
+    /// Hash comparing contents the metadata fast path cannot settle (`--hash-algo`)
+    pub hash_algo: HashAlgo,
+    /// Merge into destinations instead of replacing them, so nothing already
+    /// there is deleted (`--no-dotfiles-delete`)
+    pub no_delete: bool,
+    /// Write absolute destinations outside home, e.g. `/etc` (`--allow-outside-home`)
+    pub allow_outside_home: bool,
+    /// This machine's hostname when the home is shared with other hosts
+    /// (`@option shared_home=true`); see `core::shared_home`
+    pub shared_host: Option<String>,
+    /// Destinations, as written in the config, left alone this run
+    /// (unticked in `--approved-review`)
+    pub declined: std::collections::HashSet<String>,
+    /// Modes of created destinations under home (`@option default_file_mode`)
+    pub mode_policy: ModePolicy,
+    /// Destinations changed both locally and in their source (`--dotfile-diverged`)
+    pub divergence: Divergence,
+    /// Paths removed when present after the mappings are synced (`:absent`)
+    pub absent: Vec<String>,
 }
 
-fn expand_tilde(path: &str) -> String {
-    if let Some(rest) = path.strip_prefix("~/") {
-        if let Ok(home) = std::env::var("HOME") {
-            return Path::new(&home).join(rest).to_string_lossy().into_owned();
+impl DotfileRoots {
+    /// Roots for the current user
+    pub fn from_env() -> Result<Self> {
+        let env = crate::internal::environment::get();
+        Ok(Self::at(&env.owl_dir()?, &env.home()?.to_string_lossy()))
+    }
+
+    /// The standard layout under the owl root `owl_dir`, for `home`
+    pub fn at(owl_dir: &Path, home: &str) -> Self {
+        Self {
+            source_dir: owl_dir.join(crate::internal::constants::DOTFILES_DIR),
+            backup_dir: owl_dir
+                .join(crate::internal::constants::STATE_DIR)
+                .join(crate::internal::constants::BACKUPS_DIR),
+            home: home.to_string(),
+            owl_dir: owl_dir.to_path_buf(),
+            dest_prefix: None,
+            hash_algo: HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: ModePolicy::default(),
+            divergence: Divergence::default(),
+            absent: Vec::new(),
         }
-    } else if path == "~" {
-        if let Ok(home) = std::env::var("HOME") {
-            return home;
+    }
+
+    /// Re-root destinations under `prefix`: `~/.config/nvim` becomes
+    /// `<prefix>/.config/nvim` and `/etc/hosts` becomes `<prefix>/etc/hosts`
+    pub fn with_dest_prefix(mut self, prefix: Option<PathBuf>) -> Self {
+        self.dest_prefix = prefix;
+        self
+    }
+
+    /// Compare contents with `algo` instead of the default
+    pub fn with_hash_algo(mut self, algo: HashAlgo) -> Self {
+        self.hash_algo = algo;
+        self
+    }
+
+    /// Merge into destinations instead of replacing them
+    pub fn with_no_delete(mut self, no_delete: bool) -> Self {
+        self.no_delete = no_delete;
+        self
+    }
+
+    /// Allow absolute destinations outside home
+    pub fn with_allow_outside_home(mut self, allow: bool) -> Self {
+        self.allow_outside_home = allow;
+        self
+    }
+
+    /// Check and record what this host deploys into a shared home
+    pub fn with_shared_host(mut self, host: Option<String>) -> Self {
+        self.shared_host = host;
+        self
+    }
+
+    /// Give created destinations under home these modes
+    pub fn with_mode_policy(mut self, policy: ModePolicy) -> Self {
+        self.mode_policy = policy;
+        self
+    }
+
+    /// Resolve destinations changed on both sides this way
+    pub fn with_divergence(mut self, divergence: Divergence) -> Self {
+        self.divergence = divergence;
+        self
+    }
+
+    /// Keep these paths absent
+    pub fn with_absent(mut self, absent: Vec<String>) -> Self {
+        self.absent = absent;
+        self
+    }
+
+    /// Leave these destinations alone
+    pub fn with_declined(mut self, declined: std::collections::HashSet<String>) -> Self {
+        self.declined = declined;
+        self
+    }
+
+    /// Why a mapping would read or write outside where its config says, if it would
+    ///
+    /// Paths are normalized lexically: `..` may not climb out of the directory a
+    /// relative source or a `~/` destination is resolved against. Absolute
+    /// destinations outside home also need `allow_outside_home`, unless they are
+    /// staged under `dest_prefix`. Absolute sources are taken as written.
+    pub(crate) fn escape_reason(&self, mapping: &DotfileMapping) -> Option<String> {
+        if let Some(reason) = self.destination_escape(&mapping.destination) {
+            return Some(reason);
+        }
+        if Path::new(&mapping.source).is_absolute() {
+            return None;
+        }
+        let base = match mapping.source.strip_prefix(OWL_ROOT_PREFIX) {
+            Some(_) => self.owl_dir.as_path(),
+            None => self.source_root(mapping),
+        };
+        let src = normalize(&self.source(mapping));
+        (!src.starts_with(normalize(base))).then(|| {
+            format!(
+                "source {} escapes {} via .., refusing to read {}",
+                mapping.source,
+                base.display(),
+                src.display()
+            )
+        })
+    }
+
+    /// Why writing `destination`, as written in the config, would leave
+    /// home or the staging directory, if it would
+    pub(crate) fn destination_escape(&self, destination: &str) -> Option<String> {
+        let home = Path::new(&self.home);
+        let dst = normalize(&self.destination_path(destination));
+        if let Some(prefix) = &self.dest_prefix {
+            (!dst.starts_with(normalize(prefix))).then(|| {
+                format!(
+                    "destination {} escapes the staging directory {} via ..",
+                    destination,
+                    prefix.display()
+                )
+            })
+        } else if destination.starts_with('~') && !dst.starts_with(normalize(home)) {
+            Some(format!(
+                "destination {} escapes {} via .., refusing to write {}",
+                destination,
+                home.display(),
+                dst.display()
+            ))
+        } else if !dst.starts_with(normalize(home)) && !self.allow_outside_home {
+            Some(format!(
+                "destination {} is outside {}; pass --allow-outside-home to write system files",
+                dst.display(),
+                home.display()
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Where a mapping reads from
+    ///
+    /// - `nvim`: relative to the dotfiles directory, or `@dotfiles-root` if set,
+    ///   and within its `[from=DIR]` subdirectory if the mapping has one
+    /// - `@/shared/nvim`: relative to the owl root
+    /// - `/etc/skel/.bashrc`: absolute, used as is
+    pub(crate) fn source(&self, mapping: &DotfileMapping) -> PathBuf {
+        if let Some(rest) = mapping.source.strip_prefix(OWL_ROOT_PREFIX) {
+            return self.owl_dir.join(rest);
+        }
+        match self.subdir(mapping) {
+            Some(dir) => dir.join(&mapping.source),
+            None => self.source_root(mapping).join(&mapping.source),
+        }
+    }
+
+    /// The dotfiles directory, or `@dotfiles-root` if the mapping's file set one
+    fn source_root<'a>(&'a self, mapping: &'a DotfileMapping) -> &'a Path {
+        mapping.root.as_deref().unwrap_or(&self.source_dir)
+    }
+
+    /// The `[from=DIR]` directory a mapping's source is resolved in, if it has one
+    pub(crate) fn subdir(&self, mapping: &DotfileMapping) -> Option<PathBuf> {
+        let from = mapping.from.as_deref()?;
+        Some(self.source_root(mapping).join(from))
+    }
+
+    /// Whether apply writes `mapping` through the privileged helper: a file
+    /// outside home (`allow_outside_home`, not staged) in a directory `identity`
+    /// cannot write
+    pub(crate) fn needs_privilege(&self, mapping: &DotfileMapping, identity: &Identity) -> bool {
+        self.allow_outside_home
+            && self.dest_prefix.is_none()
+            && !mapping.hardlink
+            && !self.in_home(mapping)
+            && self.source(mapping).is_file()
+            && destination_conflict(&self.destination(mapping), identity).is_some()
+    }
+
+    /// Whether the mapping's real destination, before any staging prefix, is under home
+    fn in_home(&self, mapping: &DotfileMapping) -> bool {
+        normalize(Path::new(&expand_tilde(&mapping.destination, &self.home)))
+            .starts_with(normalize(Path::new(&self.home)))
+    }
+
+    pub(crate) fn destination(&self, mapping: &DotfileMapping) -> PathBuf {
+        self.destination_path(&mapping.destination)
+    }
+
+    /// `destination` as written in the config, expanded and staged like a mapping's
+    pub(crate) fn destination_path(&self, destination: &str) -> PathBuf {
+        let dst = PathBuf::from(expand_tilde(destination, &self.home));
+        let Some(prefix) = &self.dest_prefix else {
+            return dst;
+        };
+        let rel = dst
+            .strip_prefix(&self.home)
+            .or_else(|_| dst.strip_prefix("/"))
+            .unwrap_or(&dst);
+        prefix.join(rel)
+    }
+}
+
+/// Resolve `.` and `..` components without touching the filesystem
+///
+/// Symlinks are deliberately not followed: a home directory that links
+/// elsewhere is the user's own setup, not an escape.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
         }
     }
+    out
+}
+
+fn expand_tilde(path: &str, home: &str) -> String {
+    if let Some(rest) = path.strip_prefix("~/") {
+        return Path::new(home).join(rest).to_string_lossy().into_owned();
+    } else if path == "~" {
+        return home.to_string();
+    }
     path.to_string()
 }
 
-fn collect_files_recursively(root: &Path, rels: &mut Vec<PathBuf>, base: &Path) -> Result<()> {
+pub(crate) fn collect_files_recursively(
+    root: &Path,
+    rels: &mut Vec<PathBuf>,
+    base: &Path,
+) -> Result<()> {
     for entry in
         fs::read_dir(root).map_err(|e| anyhow!("Failed to read dir {}: {}", root.display(), e))?
     {
@@ -70,7 +389,15 @@ fn collect_files_recursively(root: &Path, rels: &mut Vec<PathBuf>, base: &Path)
     Ok(())
 }
 
-fn dirs_in_sync(src: &Path, dst: &Path) -> Result<bool> {
+/// Whether the directory `dst` matches `src`; with `allow_extra`, files only
+/// in `dst` do not count (they are never deleted in `--no-dotfiles-delete` mode)
+fn dirs_in_sync(
+    src: &Path,
+    dst: &Path,
+    hardlink: bool,
+    algo: HashAlgo,
+    allow_extra: bool,
+) -> Result<bool> {
     if !dst.exists() || !dst.is_dir() {
         return Ok(false);
     }
@@ -84,7 +411,7 @@ fn dirs_in_sync(src: &Path, dst: &Path) -> Result<bool> {
     collect_files_recursively(dst, &mut dst_files, dst)?;
 
     // Check if file counts match
-    if src_files.len() != dst_files.len() {
+    if !allow_extra && src_files.len() != dst_files.len() {
         return Ok(false);
     }
 
@@ -95,14 +422,25 @@ fn dirs_in_sync(src: &Path, dst: &Path) -> Result<bool> {
         if !d.exists() || !d.is_file() {
             return Ok(false);
         }
-        if sha256_file(&s)? != sha256_file(&d)? {
+        if !file_in_sync(&s, &d, hardlink, algo)? {
             return Ok(false);
         }
     }
 
-    // Check if destination has no extra files (should be covered by count check, but being explicit)
-    for rel in &dst_files {
-        if !src_files.contains(rel) {
+    // Check if destination has no extra files (should be covered by count check, but being explicit);
+    // a case-insensitive destination may list a source file under other case
+    if !allow_extra {
+        let fold = crate::internal::case_fold::insensitive_mount(dst).is_some();
+        let name = |rel: &PathBuf| {
+            let rel = rel.to_string_lossy();
+            if fold {
+                rel.to_lowercase()
+            } else {
+                rel.into_owned()
+            }
+        };
+        let src_names: HashSet<String> = src_files.iter().map(name).collect();
+        if dst_files.iter().any(|rel| !src_names.contains(&name(rel))) {
             return Ok(false);
         }
     }
@@ -110,11 +448,177 @@ fn dirs_in_sync(src: &Path, dst: &Path) -> Result<bool> {
     Ok(true)
 }
 
-fn sha256_file(path: &Path) -> Result<String> {
-    let data = fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
-    let mut hasher = Sha256::new();
-    hasher.update(&data);
-    Ok(format!("{:x}", hasher.finalize()))
+/// Whether `dst` already matches the file `src`
+///
+/// A hardlink to the source is always in sync; a hardlink mapping requires one.
+/// Copies with the same size and mtime as their source are trusted without
+/// hashing, which holds because copies inherit the source mtime; see
+/// [`mtime_settles`] for when matching mtimes are not enough.
+fn file_in_sync(src: &Path, dst: &Path, hardlink: bool, algo: HashAlgo) -> Result<bool> {
+    let src_meta =
+        fs::metadata(src).map_err(|e| anyhow!("Failed to stat {}: {}", src.display(), e))?;
+    let dst_meta =
+        fs::metadata(dst).map_err(|e| anyhow!("Failed to stat {}: {}", dst.display(), e))?;
+    if src_meta.dev() == dst_meta.dev() && src_meta.ino() == dst_meta.ino() {
+        return Ok(true);
+    }
+    if hardlink || src_meta.len() != dst_meta.len() {
+        return Ok(false);
+    }
+    if let (Ok(src_mtime), Ok(dst_mtime)) = (src_meta.modified(), dst_meta.modified())
+        && src_mtime == dst_mtime
+        && mtime_settles(src_mtime, ctime(&dst_meta), std::time::SystemTime::now())
+    {
+        return Ok(true);
+    }
+    Ok(hash_file(src, algo)? == hash_file(dst, algo)?)
+}
+
+/// Files modified more recently than this are hashed even if their mtimes match
+const RACY_MTIME_WINDOW: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Whether a copy whose mtime matches its source's can be trusted unhashed
+///
+/// Not when the mtime is within the racy window of `now`: a same-size edit
+/// within the timestamp granularity would look unchanged. Not when it is in
+/// the future, or later than `copied` (the copy's ctime, when it was written)
+/// by more than that window: the clock moved since, and the mtime says
+/// nothing about when the content last changed.
+fn mtime_settles(
+    mtime: std::time::SystemTime,
+    copied: std::time::SystemTime,
+    now: std::time::SystemTime,
+) -> bool {
+    now.duration_since(mtime)
+        .is_ok_and(|age| age >= RACY_MTIME_WINDOW)
+        && mtime <= copied + RACY_MTIME_WINDOW
+}
+
+/// When the file's inode last changed, which a copy's mtime cannot be set past
+fn ctime(meta: &fs::Metadata) -> std::time::SystemTime {
+    std::time::UNIX_EPOCH
+        + std::time::Duration::new(meta.ctime().max(0) as u64, meta.ctime_nsec() as u32)
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of files hashed on this thread, so tests can assert the fast path was taken
+    static HASH_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Hash used to compare file contents (`--hash-algo`)
+///
+/// Change detection has no adversary, so the default is the non-cryptographic
+/// xxh3, which is many times faster than sha256 on large files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum HashAlgo {
+    #[default]
+    Xxh3,
+    Sha256,
+}
+
+impl HashAlgo {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgo::Xxh3 => "xxh3",
+            HashAlgo::Sha256 => "sha256",
+        }
+    }
+
+    /// Digest of `data`, tagged with the algorithm so digests made with
+    /// different algorithms never compare equal
+    pub fn digest(self, data: &[u8]) -> String {
+        match self {
+            HashAlgo::Xxh3 => format!(
+                "{}:{:032x}",
+                self.as_str(),
+                xxhash_rust::xxh3::xxh3_128(data)
+            ),
+            HashAlgo::Sha256 => {
+                let mut hasher = Sha256::new();
+                hasher.update(data);
+                format!("{}:{:x}", self.as_str(), hasher.finalize())
+            }
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))
+}
+
+fn hash_file(path: &Path, algo: HashAlgo) -> Result<String> {
+    #[cfg(test)]
+    HASH_CALLS.with(|calls| calls.set(calls.get() + 1));
+    Ok(algo.digest(&read_file(path)?))
+}
+
+/// User and groups whose permissions apply to destination writes
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Identity {
+    pub(crate) uid: u32,
+    pub(crate) gids: Vec<u32>,
+}
+
+impl Identity {
+    /// Effective uid and groups of this process, read from `/proc/self/status`
+    pub(crate) fn current() -> Option<Self> {
+        let status = fs::read_to_string("/proc/self/status").ok()?;
+        let field = |name: &str| -> Vec<u32> {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .map(|rest| {
+                    rest.split_whitespace()
+                        .filter_map(|n| n.parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        // Uid/Gid list real, effective, saved and filesystem ids
+        let uid = *field("Uid:").get(1)?;
+        let mut gids = field("Groups:");
+        gids.extend(field("Gid:").get(1));
+        Some(Self { uid, gids })
+    }
+
+    fn can_write(&self, meta: &fs::Metadata) -> bool {
+        let mode = meta.mode();
+        if self.uid == 0 {
+            true
+        } else if meta.uid() == self.uid {
+            mode & 0o200 != 0
+        } else if self.gids.contains(&meta.gid()) {
+            mode & 0o020 != 0
+        } else {
+            mode & 0o002 != 0
+        }
+    }
+}
+
+/// Why `dst` cannot be replaced by `identity`, if it cannot
+///
+/// Replacing a destination removes it and creates it again, so the nearest
+/// existing ancestor of its parent has to be writable.
+fn destination_conflict(dst: &Path, identity: &Identity) -> Option<String> {
+    let parent = dst.parent()?;
+    let (dir, meta) = parent
+        .ancestors()
+        .find_map(|dir| fs::metadata(dir).ok().map(|meta| (dir, meta)))?;
+    if identity.can_write(&meta) {
+        return None;
+    }
+    Some(if dst.starts_with("/etc") {
+        format!(
+            "destination parent {} not writable, run owl with elevated privileges (sudo)",
+            dir.display()
+        )
+    } else {
+        format!(
+            "destination parent {} not writable, try sudo or change target",
+            dir.display()
+        )
+    })
 }
 
 fn ensure_parent_dir(dest: &Path) -> Result<()> {
@@ -125,7 +629,43 @@ fn ensure_parent_dir(dest: &Path) -> Result<()> {
     Ok(())
 }
 
-fn copy_dir_all(src: &Path, dst: &Path) -> Result<()> {
+/// Copy one file and give the copy the source's mtime
+///
+/// `fs::copy` uses `copy_file_range` on Linux, which the kernel turns into a
+/// reflink on filesystems that support it (btrfs, XFS) and a plain copy elsewhere.
+fn copy_file(src: &Path, dst: &Path) -> Result<()> {
+    fs::copy(src, dst).map_err(|e| {
+        anyhow!(
+            "Failed to copy {} to {}: {}",
+            src.display(),
+            dst.display(),
+            e
+        )
+    })?;
+    let modified = fs::metadata(src)
+        .and_then(|meta| meta.modified())
+        .map_err(|e| anyhow!("Failed to stat {}: {}", src.display(), e))?;
+    fs::File::open(dst)
+        .and_then(|file| file.set_modified(modified))
+        .map_err(|e| anyhow!("Failed to set mtime on {}: {}", dst.display(), e))
+}
+
+/// Place `src` at `dst` as a hardlink or a copy
+fn link_or_copy(src: &Path, dst: &Path, hardlink: bool) -> Result<()> {
+    if hardlink {
+        return fs::hard_link(src, dst).map_err(|e| {
+            anyhow!(
+                "Failed to hardlink {} to {}: {}",
+                dst.display(),
+                src.display(),
+                e
+            )
+        });
+    }
+    copy_file(src, dst)
+}
+
+fn copy_dir_all(src: &Path, dst: &Path, hardlink: bool) -> Result<()> {
     if src == dst {
         return Ok(());
     }
@@ -144,151 +684,2296 @@ fn copy_dir_all(src: &Path, dst: &Path) -> Result<()> {
         let src_path = entry.path();
         let dst_path = dst.join(entry.file_name());
         if ty.is_dir() {
-            copy_dir_all(&src_path, &dst_path)?;
+            copy_dir_all(&src_path, &dst_path, hardlink)?;
         } else if ty.is_file() {
-            let data = fs::read(&src_path)
-                .map_err(|e| anyhow!("Failed to read {}: {}", src_path.display(), e))?;
-            fs::write(&dst_path, &data)
-                .map_err(|e| anyhow!("Failed to write {}: {}", dst_path.display(), e))?;
+            link_or_copy(&src_path, &dst_path, hardlink)?;
         }
     }
     Ok(())
 }
 
-/// Build dotfile mappings from config
+/// Place `src` at `dst` without deleting anything only `dst` has
+///
+/// Files are replaced one by one and directories are merged. A file where
+/// `src` has a directory, or the other way round, is an error, since replacing
+/// it would delete it.
+fn merge_path(src: &Path, dst: &Path, hardlink: bool) -> Result<()> {
+    let existing = dst.symlink_metadata().ok();
+    if src.is_dir() {
+        if existing.as_ref().is_some_and(|meta| !meta.is_dir()) {
+            return Err(anyhow!(
+                "{} is not a directory; --no-dotfiles-delete will not replace it",
+                dst.display()
+            ));
+        }
+        fs::create_dir_all(dst)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", dst.display(), e))?;
+        for entry in
+            fs::read_dir(src).map_err(|e| anyhow!("Failed to read dir {}: {}", src.display(), e))?
+        {
+            let entry =
+                entry.map_err(|e| anyhow!("Failed to read entry in {}: {}", src.display(), e))?;
+            let path = entry.path();
+            if path.is_dir() || path.is_file() {
+                merge_path(&path, &dst.join(entry.file_name()), hardlink)?;
+            }
+        }
+        return Ok(());
+    }
+    match existing {
+        Some(meta) if meta.is_dir() => {
+            return Err(anyhow!(
+                "{} is a directory; --no-dotfiles-delete will not replace it",
+                dst.display()
+            ));
+        }
+        // Unlink rather than write through, which would follow symlinks and hardlinks
+        Some(_) => fs::remove_file(dst)
+            .map_err(|e| anyhow!("Failed to replace {}: {}", dst.display(), e))?,
+        None => ensure_parent_dir(dst)?,
+    }
+    link_or_copy(src, dst, hardlink)
+}
+
+/// Build dotfile mappings from config for this machine's hostname
 pub fn get_dotfile_mappings(config: &crate::core::config::Config) -> Vec<DotfileMapping> {
+    let hostname = crate::internal::environment::get().hostname().ok();
+    mappings_for_host(config, hostname)
+}
+
+/// Dotfile mappings that apply on `hostname`
+///
+/// `[host=]` mappings for other hosts are left out, as are all of them when
+/// the hostname is unknown. A mapping for this host replaces an unconditional
+/// one to the same destination.
+pub fn mappings_for_host(
+    config: &crate::core::config::Config,
+    hostname: Option<&str>,
+) -> Vec<DotfileMapping> {
+    package_mappings(config, hostname)
+        .into_iter()
+        .map(|(_, m)| m)
+        .collect()
+}
+
+/// `mappings_for_host`, each with the package that declares it
+pub(crate) fn package_mappings<'a>(
+    config: &'a crate::core::config::Config,
+    hostname: Option<&str>,
+) -> Vec<(&'a str, DotfileMapping)> {
     let mut mappings = Vec::new();
-    for pkg in config.packages.values() {
+    let mut host_destinations = std::collections::HashSet::new();
+    for (name, pkg) in &config.packages {
+        let root = pkg.dotfiles_root.as_ref().map(PathBuf::from);
         for cfg in &pkg.config {
-            // formats: "a -> b" or "b" (same source name)
-            if let Some((source, dest)) = cfg.split_once(" -> ") {
-                mappings.push(DotfileMapping {
-                    source: source.trim().to_string(),
-                    destination: dest.trim().to_string(),
-                });
-            } else {
-                mappings.push(DotfileMapping {
-                    source: cfg.clone(),
-                    destination: cfg.clone(),
-                });
+            // formats: "a -> b" or "b" (same source name), optionally preceded by
+            // "[from=DIR]", "[host=NAME]" and "[mode=OCTAL]" and followed by
+            // "[hardlink]", "[force-owned]", "[private]" and "[preserve-mode]"
+            let (options, cfg) = split_options(cfg).unwrap_or((MappingOptions::default(), cfg));
+            if !options.matches_host(hostname) {
+                continue;
             }
+            let from = options.from.map(str::to_string);
+            let mut cfg = cfg.trim_end();
+            let (mut hardlink, mut force_owned) = (false, false);
+            let mut modes = MappingModes {
+                mode: options
+                    .mode
+                    .and_then(|m| crate::core::file_modes::parse_mode(m).ok()),
+                ..Default::default()
+            };
+            loop {
+                let flag = [
+                    ("[hardlink]", &mut hardlink),
+                    ("[force-owned]", &mut force_owned),
+                    ("[private]", &mut modes.private),
+                    ("[preserve-mode]", &mut modes.preserve),
+                ]
+                .into_iter()
+                .find_map(|(flag, slot)| cfg.strip_suffix(flag).map(|rest| (rest, slot)));
+                let Some((rest, slot)) = flag else {
+                    break;
+                };
+                *slot = true;
+                cfg = rest.trim_end();
+            }
+            let (source, destination) = cfg.split_once(" -> ").unwrap_or((cfg, cfg));
+            let mapping = DotfileMapping {
+                source: source.trim().to_string(),
+                destination: destination.trim().to_string(),
+                root: root.clone(),
+                from,
+                hardlink,
+                force_owned,
+                modes,
+            };
+            if options.host.is_some() {
+                host_destinations.insert(mapping.destination.clone());
+            }
+            mappings.push((name.as_str(), mapping, options.host.is_some()));
         }
     }
     mappings
+        .into_iter()
+        .filter(|(_, m, for_host)| *for_host || !host_destinations.contains(&m.destination))
+        .map(|(name, m, _)| (name, m))
+        .collect()
+}
+
+/// The leading `[key=value]` options of a `:config` value
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct MappingOptions<'a> {
+    /// `[from=DIR]`
+    pub from: Option<&'a str>,
+    /// `[host=NAME]`: comma-separated hostnames the mapping is deployed on
+    pub host: Option<&'a str>,
+    /// `[mode=OCTAL]`: mode of the files written
+    pub mode: Option<&'a str>,
+}
+
+impl MappingOptions<'_> {
+    fn matches_host(&self, hostname: Option<&str>) -> bool {
+        match self.host {
+            None => true,
+            Some(hosts) => hostname.is_some_and(|name| hosts.split(',').any(|h| h.trim() == name)),
+        }
+    }
 }
 
-/// Return true if any mapping requires action
-pub fn has_actionable_dotfiles(mappings: &[DotfileMapping]) -> Result<bool> {
-    for m in mappings {
-        let src = owl_dotfiles_dir()?.join(&m.source);
-        let dst = expand_tilde(&m.destination);
-        let dst_path = Path::new(&dst);
-        if !src.exists() {
+/// Split the leading `[from=DIR]`, `[host=NAME]` and `[mode=OCTAL]` options off a
+/// `:config` value
+pub(crate) fn split_options(cfg: &str) -> Result<(MappingOptions<'_>, &str)> {
+    let mut options = MappingOptions::default();
+    let mut rest = cfg;
+    while let Some(inner) = rest.strip_prefix('[') {
+        let (option, mapping) = inner
+            .split_once(']')
+            .ok_or_else(|| anyhow!("Unterminated option in :config {}", cfg))?;
+        let option = option.trim();
+        let (key, value) = option.split_once('=').unwrap_or((option, ""));
+        let (slot, needs) = match key {
+            "from" => (&mut options.from, "a directory"),
+            "host" => (&mut options.host, "a hostname"),
+            "mode" => (&mut options.mode, "an octal mode"),
+            _ => {
+                return Err(anyhow!(
+                    "Unknown :config option '{}' (expected from=DIR, host=NAME or mode=OCTAL)",
+                    option
+                ));
+            }
+        };
+        let value = value.trim();
+        if value.is_empty() || value.split(',').any(|v| v.trim().is_empty()) {
+            return Err(anyhow!(":config [{}=] needs {}", key, needs));
+        }
+        if key == "mode" {
+            crate::core::file_modes::parse_mode(value)
+                .map_err(|e| anyhow!(":config [mode=]: {}", e))?;
+        }
+        if slot.replace(value).is_some() {
+            return Err(anyhow!(":config {} sets [{}=] twice", cfg, key));
+        }
+        rest = mapping.trim_start();
+    }
+    Ok((options, rest))
+}
+
+/// A destination outside the home directory that a package owns
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedDestination {
+    pub path: PathBuf,
+    pub package: String,
+}
+
+impl OwnedDestination {
+    pub fn warning(&self) -> String {
+        format!(
+            "{} is owned by package {}; upgrades will revert it or conflict. \
+             Add it to NoUpgrade in /etc/pacman.conf so upgrades write a .pacnew instead, \
+             or mark the mapping [force-owned]",
+            self.path.display(),
+            self.package
+        )
+    }
+}
+
+/// Files outside home that mappings write and `pm` reports as package-owned
+///
+/// Directory mappings are checked file by file. `[force-owned]` mappings are
+/// skipped. All paths go to the package manager in one query.
+pub fn package_owned_destinations(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    pm: &dyn crate::core::pm::PackageManager,
+) -> Result<Vec<OwnedDestination>> {
+    let mut paths = Vec::new();
+    for m in mappings.iter().filter(|m| !m.force_owned) {
+        let dst = roots.destination(m);
+        if dst.starts_with(&roots.home) {
             continue;
         }
+        let src = roots.source(m);
         if src.is_dir() {
-            if !dirs_in_sync(&src, dst_path)? {
-                return Ok(true);
-            }
+            let mut rels = Vec::new();
+            collect_files_recursively(&src, &mut rels, &src)?;
+            rels.sort();
+            paths.extend(rels.into_iter().map(|rel| dst.join(rel)));
         } else {
-            if !dst_path.exists() {
-                return Ok(true);
-            }
-            if sha256_file(&src)? != sha256_file(dst_path)? {
-                return Ok(true);
-            }
+            paths.push(dst);
         }
     }
-    Ok(false)
+    let owners = pm.owner_of(&paths)?;
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let package = owners.get(&path)?.clone();
+            Some(OwnedDestination { path, package })
+        })
+        .collect())
 }
 
-/// Analyze and apply dotfiles
-pub fn apply_dotfiles(mappings: &[DotfileMapping], dry_run: bool) -> Result<Vec<DotfileAction>> {
-    let mut actions = Vec::new();
-    for m in mappings {
-        let src = owl_dotfiles_dir()?.join(&m.source);
-        let dst = PathBuf::from(expand_tilde(&m.destination));
-        let status = if src.is_dir() {
-            if !dst.exists() {
-                DotfileStatus::Create
-            } else if dirs_in_sync(&src, &dst)? {
-                DotfileStatus::UpToDate
-            } else {
-                DotfileStatus::Update
-            }
-        } else if !dst.exists() {
+/// Default `--dotfile-concurrency`: copies are I/O bound, so use more threads than CPUs
+pub fn default_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(4, |n| n.get() * 2)
+}
+
+/// Work out what each mapping needs, checking up to `concurrency` mappings at once
+///
+/// Statuses are returned in mapping order.
+pub fn analyze_dotfiles(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    concurrency: usize,
+) -> Result<Vec<DotfileStatus>> {
+    let identity = Identity::current();
+    let baselines = BackupStore::new(roots.backup_dir.clone()).baselines()?;
+    let case_conflicts = case_conflicts(roots, mappings);
+    crate::internal::util::map_bounded(mappings, concurrency, |m| {
+        match case_conflicts.get(roots.destination(m).to_string_lossy().as_ref()) {
+            Some(reason) => Ok(DotfileStatus::Conflict(reason.clone())),
+            None => analyze_mapping(roots, m, identity.as_ref(), &baselines),
+        }
+    })
+    .into_iter()
+    .collect()
+}
+
+/// Destinations apply writes through the privileged helper (see
+/// `DotfileRoots::needs_privilege`), for the run's privileged-ops manifest
+pub fn privileged_destinations(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    concurrency: usize,
+) -> Result<Vec<PathBuf>> {
+    let Some(identity) = Identity::current() else {
+        return Ok(Vec::new());
+    };
+    let candidates: Vec<DotfileMapping> = mappings
+        .iter()
+        .filter(|m| roots.needs_privilege(m, &identity))
+        .cloned()
+        .collect();
+    let statuses = analyze_dotfiles(roots, &candidates, concurrency)?;
+    Ok(candidates
+        .iter()
+        .zip(statuses)
+        .filter(|(_, status)| {
+            matches!(
+                status,
+                DotfileStatus::Create | DotfileStatus::Update | DotfileStatus::Merge
+            )
+        })
+        .map(|(m, _)| normalize(&roots.destination(m)))
+        .collect())
+}
+
+/// Destinations, as written in the config, changed both locally and in their
+/// source since owl last wrote them; declined mappings are left out
+pub fn diverged_destinations(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    concurrency: usize,
+) -> Result<Vec<String>> {
+    let selected: Vec<DotfileMapping> = mappings
+        .iter()
+        .filter(|m| !roots.declined.contains(&m.destination))
+        .cloned()
+        .collect();
+    let kept = roots.clone().with_divergence(Divergence::Keep);
+    let statuses = analyze_dotfiles(&kept, &selected, concurrency)?;
+    Ok(selected
+        .into_iter()
+        .zip(statuses)
+        .filter(|(_, status)| *status == DotfileStatus::Conflict(kept_reason()))
+        .map(|(m, _)| m.destination)
+        .collect())
+}
+
+/// Conflict reason of a diverged destination left alone
+fn kept_reason() -> String {
+    format!("{} (--dotfile-diverged keep)", DIVERGED)
+}
+
+/// Conflicts of destinations that differ only by case from another mapping's
+/// on a case-insensitive filesystem, where each run would overwrite the other;
+/// by destination path
+fn case_conflicts(roots: &DotfileRoots, mappings: &[DotfileMapping]) -> HashMap<String, String> {
+    let paths: Vec<String> = mappings
+        .iter()
+        .map(|m| roots.destination(m).to_string_lossy().into_owned())
+        .collect();
+    let env = crate::internal::environment::get();
+    let mut conflicts = HashMap::new();
+    let collisions = crate::internal::case_fold::case_collisions(&paths, |path| {
+        crate::internal::case_fold::insensitive_mount(Path::new(path))
+    });
+    for collision in collisions.iter().filter(|c| c.insensitive) {
+        for path in &collision.paths {
+            let others: Vec<String> = collision
+                .paths
+                .iter()
+                .filter(|other| *other != path)
+                .map(|other| env.display_path(other))
+                .collect();
+            conflicts.insert(
+                path.clone(),
+                format!(
+                    "differs only by case from {} on a case-insensitive filesystem",
+                    others.join(", ")
+                ),
+            );
+        }
+    }
+    conflicts
+}
+
+fn analyze_mapping(
+    roots: &DotfileRoots,
+    m: &DotfileMapping,
+    identity: Option<&Identity>,
+    baselines: &BTreeMap<String, Baseline>,
+) -> Result<DotfileStatus> {
+    if let Some(reason) = roots.escape_reason(m) {
+        return Ok(DotfileStatus::Conflict(reason));
+    }
+    if let Some(dir) = roots.subdir(m).filter(|dir| !dir.is_dir()) {
+        return Ok(DotfileStatus::Conflict(format!(
+            "[from={}] directory {} does not exist",
+            m.from.as_deref().unwrap_or_default(),
+            dir.display()
+        )));
+    }
+    let src = roots.source(m);
+    let dst = roots.destination(m);
+    let status = if src.is_dir() {
+        if !dst.exists() {
             DotfileStatus::Create
-        } else if sha256_file(&src)? == sha256_file(&dst)? {
+        } else if dirs_in_sync(&src, &dst, m.hardlink, roots.hash_algo, roots.no_delete)? {
             DotfileStatus::UpToDate
         } else {
             DotfileStatus::Update
-        };
+        }
+    } else if !dst.exists() {
+        DotfileStatus::Create
+    } else if file_in_sync(&src, &dst, m.hardlink, roots.hash_algo)? {
+        DotfileStatus::UpToDate
+    } else {
+        match baselines.get(dst.to_string_lossy().as_ref()) {
+            Some(baseline) if !m.hardlink && dst.is_file() => against_baseline(roots, m, baseline)?,
+            _ => DotfileStatus::Update,
+        }
+    };
+    let privileged = |identity: &Identity| {
+        matches!(
+            status,
+            DotfileStatus::Create | DotfileStatus::Update | DotfileStatus::Merge
+        ) && roots.needs_privilege(m, identity)
+    };
+    if status != DotfileStatus::UpToDate
+        && let Some(identity) = identity.filter(|identity| !privileged(identity))
+        && let Some(reason) = destination_conflict(&dst, identity)
+    {
+        return Ok(DotfileStatus::Conflict(reason));
+    }
+    Ok(status)
+}
 
-        if !dry_run {
-            if src.is_dir() {
-                // Remove destination directory if it exists, then copy entire source
-                if dst.exists() {
-                    fs::remove_dir_all(&dst).map_err(|e| {
-                        anyhow!("Failed to remove directory {}: {}", dst.display(), e)
-                    })?;
-                }
-                copy_dir_all(&src, &dst)?;
-            } else {
-                // Remove destination file if it exists, then copy source file
-                if dst.exists() {
-                    fs::remove_file(&dst)
-                        .map_err(|e| anyhow!("Failed to remove file {}: {}", dst.display(), e))?;
-                }
-                ensure_parent_dir(&dst)?;
-                let data = fs::read(&src)
-                    .map_err(|e| anyhow!("Failed to read {}: {}", src.display(), e))?;
-                fs::write(&dst, &data)
-                    .map_err(|e| anyhow!("Failed to write {}: {}", dst.display(), e))?;
+/// Status of a file mapping whose destination differs from its source, by
+/// what changed since apply last wrote the destination
+///
+/// When only one side changed the source is written as always (or nothing,
+/// if neither did since a merge); when both did, `roots.divergence` decides.
+fn against_baseline(
+    roots: &DotfileRoots,
+    m: &DotfileMapping,
+    baseline: &Baseline,
+) -> Result<DotfileStatus> {
+    use crate::core::backup::sha256_hex;
+    let src_data = read_file(&roots.source(m))?;
+    let dst_data = read_file(&roots.destination(m))?;
+    let source_changed = sha256_hex(&src_data) != baseline.source;
+    let destination_changed = sha256_hex(&dst_data) != baseline.written;
+    Ok(match (source_changed, destination_changed) {
+        (false, false) => DotfileStatus::UpToDate,
+        (true, false) | (false, true) => DotfileStatus::Update,
+        (true, true) => match roots.divergence {
+            Divergence::Overwrite => DotfileStatus::Update,
+            Divergence::Keep => DotfileStatus::Conflict(kept_reason()),
+            Divergence::Merge => {
+                merge_status(merge_contents(roots, m, baseline, &src_data, &dst_data)?.as_ref())
             }
-        }
+        },
+    })
+}
 
-        actions.push(DotfileAction {
-            mapping: m.clone(),
-            status,
-        });
+/// Three-way merge of the destination's and the source's changes since the
+/// destination was written from the baseline source; `None` for binary files
+/// and files too large to merge
+fn merge_contents(
+    roots: &DotfileRoots,
+    m: &DotfileMapping,
+    baseline: &Baseline,
+    src_data: &[u8],
+    dst_data: &[u8],
+) -> Result<Option<Merged>> {
+    use crate::core::merge::as_text;
+    let base = BackupStore::new(roots.backup_dir.clone()).get(&baseline.source)?;
+    let (Some(base), Some(ours), Some(theirs)) =
+        (as_text(&base), as_text(dst_data), as_text(src_data))
+    else {
+        return Ok(None);
+    };
+    let labels = crate::core::merge::Labels {
+        ours: &m.destination,
+        base: "last applied",
+        theirs: &m.source,
+    };
+    Ok(crate::core::merge::merge3(base, ours, theirs, labels))
+}
+
+/// Merge a mapping analysis found diverged, from the files as they are now
+fn merge_mapping(
+    roots: &DotfileRoots,
+    m: &DotfileMapping,
+    baselines: &BTreeMap<String, Baseline>,
+) -> Result<Option<Merged>> {
+    let dst = roots.destination(m);
+    let baseline = baselines
+        .get(dst.to_string_lossy().as_ref())
+        .ok_or_else(|| anyhow!("No baseline recorded for {}", dst.display()))?;
+    merge_contents(
+        roots,
+        m,
+        baseline,
+        &read_file(&roots.source(m))?,
+        &read_file(&dst)?,
+    )
+}
+
+fn merge_status(merged: Option<&Merged>) -> DotfileStatus {
+    match merged {
+        Some(merged) if merged.is_clean() => DotfileStatus::Merge,
+        Some(merged) => DotfileStatus::MergeConflict {
+            conflicts: merged.conflicts,
+            markers: None,
+        },
+        None => DotfileStatus::Conflict(format!(
+            "{}, and it is binary or too large to merge; pass --dotfile-diverged \
+             overwrite or keep",
+            DIVERGED
+        )),
     }
-    Ok(actions)
 }
 
-pub fn print_actions(actions: &[DotfileAction], dry_run: bool) {
-    let mut _created = 0usize;
-    let mut _updated = 0usize;
-    let mut up_to_date = 0usize;
-    for a in actions {
-        match a.status {
-            DotfileStatus::Create => {
-                _created += 1;
-                println!(
-                    "  {} create {} -> {}",
-                    crate::internal::color::green("➔"),
-                    a.mapping.source,
-                    a.mapping.destination
-                );
+/// `[private]` destinations whose files or directories are more open than
+/// their policy allows
+pub fn private_warnings(roots: &DotfileRoots, mappings: &[DotfileMapping]) -> Vec<String> {
+    mappings
+        .iter()
+        .filter(|m| m.modes.private && !m.hardlink)
+        .filter_map(|m| {
+            let open = crate::core::file_modes::too_open(
+                &roots.destination(m),
+                &m.modes,
+                &roots.mode_policy,
+            );
+            let (path, mode) = open.first()?;
+            let more = match open.len() {
+                1 => String::new(),
+                n => format!(" and {} more", n - 1),
+            };
+            Some(format!(
+                "{} is {:o}{}, more open than [private] allows for {}",
+                path.display(),
+                mode,
+                more,
+                m.destination
+            ))
+        })
+        .collect()
+}
+
+/// Whether any mapping with an existing source needs work
+fn has_actionable(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    statuses: &[DotfileStatus],
+) -> bool {
+    mappings
+        .iter()
+        .zip(statuses)
+        .any(|(m, status)| *status != DotfileStatus::UpToDate && roots.source(m).exists())
+}
+
+/// Apply analysed mappings in order
+///
+/// Replaced destinations are recorded in the backup store first. If writing any
+/// mapping fails, destinations replaced earlier in the run are restored from that
+/// backup set before the error is returned. After a successful run only the
+/// newest `keep_backups` backups of each destination are kept, and the
+/// baselines of written file destinations are recorded.
+///
+/// Merges are redone on the files as they are now. A conflicted merge is saved
+/// next to its destination with [`MERGE_MARKERS_SUFFIX`], never over it.
+fn apply_analyzed(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    statuses: Vec<DotfileStatus>,
+    dry_run: bool,
+    failpoints: &crate::internal::failpoint::Failpoints,
+    keep_backups: usize,
+) -> Result<Vec<DotfileAction>> {
+    let store = BackupStore::new(roots.backup_dir.clone());
+    let mut baselines = store.baselines()?;
+    let mut journal = RollbackJournal::new(store, keep_backups);
+    let mut actions = Vec::new();
+    for (index, (m, status)) in mappings.iter().zip(statuses).enumerate() {
+        let result = match status {
+            DotfileStatus::Merge | DotfileStatus::MergeConflict { .. } => {
+                merge_mapping(roots, m, &baselines).map(|merged| {
+                    let status = merge_status(merged.as_ref());
+                    (status, merged.map(|merged| merged.text))
+                })
+            }
+            status => Ok((status, None)),
+        }
+        .and_then(|(status, merged)| match status {
+            DotfileStatus::Create | DotfileStatus::Update | DotfileStatus::Merge if !dry_run => {
+                write_mapping(roots, m, index, merged.as_deref(), failpoints, &mut journal)
+                    .map(|_| status)
+            }
+            DotfileStatus::MergeConflict { conflicts, .. } if !dry_run => {
+                save_markers(&roots.destination(m), merged.as_deref().unwrap_or_default()).map(
+                    |path| DotfileStatus::MergeConflict {
+                        conflicts,
+                        markers: Some(path),
+                    },
+                )
             }
-            DotfileStatus::Update => {
-                _updated += 1;
-                println!(
-                    "  {} update {} -> {}",
-                    crate::internal::color::green("➔"),
-                    a.mapping.source,
-                    a.mapping.destination
-                );
+            status => Ok(status),
+        });
+        match result {
+            Ok(status) => actions.push(DotfileAction {
+                mapping: m.clone(),
+                status,
+            }),
+            Err(e) => {
+                return Err(match journal.rollback() {
+                    Ok(0) => e,
+                    Ok(restored) => anyhow!("{} (rolled back {} dotfile(s))", e, restored),
+                    Err(rollback_err) => anyhow!("{}; rollback failed: {}", e, rollback_err),
+                });
             }
-            DotfileStatus::UpToDate => {
-                up_to_date += 1;
+        }
+    }
+    journal.commit();
+    if !dry_run && let Err(e) = record_baselines(roots, &actions, &mut baselines) {
+        warnln!(
+            "{}",
+            crate::internal::color::yellow(&format!("Failed to record dotfile baselines: {}", e))
+        );
+    }
+    Ok(actions)
+}
+
+/// Write a conflicted merge next to `dst`, returning where
+fn save_markers(dst: &Path, merged: &str) -> Result<String> {
+    let path = format!("{}{}", dst.display(), MERGE_MARKERS_SUFFIX);
+    fs::write(&path, merged).map_err(|e| anyhow!("Failed to write {}: {}", path, e))?;
+    Ok(path)
+}
+
+/// Record what this run wrote to file destinations, and the baselines of
+/// destinations already in sync that have none yet
+fn record_baselines(
+    roots: &DotfileRoots,
+    actions: &[DotfileAction],
+    baselines: &mut BTreeMap<String, Baseline>,
+) -> Result<()> {
+    let store = BackupStore::new(roots.backup_dir.clone());
+    let mut changed = false;
+    for action in actions {
+        let m = &action.mapping;
+        let (src, dst) = (roots.source(m), roots.destination(m));
+        let key = dst.to_string_lossy().into_owned();
+        let record = match action.status {
+            DotfileStatus::Create | DotfileStatus::Update | DotfileStatus::Merge => true,
+            DotfileStatus::UpToDate => !baselines.contains_key(&key),
+            _ => false,
+        };
+        if !record || m.hardlink || !src.is_file() || !dst.is_file() {
+            continue;
+        }
+        let baseline = Baseline {
+            source: store.put(&read_file(&src)?)?,
+            written: crate::core::backup::sha256_hex(&read_file(&dst)?),
+        };
+        baselines.insert(key, baseline);
+        changed = true;
+    }
+    if changed {
+        store.save_baselines(baselines)?;
+    }
+    Ok(())
+}
+
+/// Write one mapping, with `merged` instead of its source file if given
+fn write_mapping(
+    roots: &DotfileRoots,
+    m: &DotfileMapping,
+    index: usize,
+    merged: Option<&str>,
+    failpoints: &crate::internal::failpoint::Failpoints,
+    journal: &mut RollbackJournal,
+) -> Result<()> {
+    let src = roots.source(m);
+    let dst = roots.destination(m);
+    failpoints.check("dotfiles", index)?;
+    if Identity::current().is_some_and(|identity| roots.needs_privilege(m, &identity)) {
+        return write_privileged(roots, m, merged, journal);
+    }
+    // Updates keep the modes the destination had
+    let before = crate::core::file_modes::snapshot(&dst);
+    // Back up the current destination so it can be restored on failure, and
+    // clear it unless merging into it
+    journal.stash(&dst, !roots.no_delete)?;
+    if let Some(merged) = merged {
+        ensure_parent_dir(&dst)?;
+        fs::write(&dst, merged).map_err(|e| anyhow!("Failed to write {}: {}", dst.display(), e))?;
+    } else if roots.no_delete {
+        merge_path(&src, &dst, m.hardlink)?;
+    } else if src.is_dir() {
+        copy_dir_all(&src, &dst, m.hardlink)?;
+    } else {
+        ensure_parent_dir(&dst)?;
+        link_or_copy(&src, &dst, m.hardlink)?;
+    }
+    // A hardlink's mode is the source's own
+    if !m.hardlink {
+        crate::core::file_modes::settle(
+            &src,
+            &dst,
+            &m.modes,
+            &roots.mode_policy,
+            roots.in_home(m),
+            &before,
+        )?;
+    }
+    Ok(())
+}
+
+/// Where a merge written through the privileged helper is staged, in the backup directory
+const PRIVILEGED_STAGING: &str = ".privileged-merge";
+
+/// Write a file mapping this user cannot write through the privileged helper
+///
+/// The destination is backed up but left in place, since the helper replaces
+/// it whole; restoring it on rollback needs root too. Its mode is resolved
+/// here as `file_modes::settle` would.
+fn write_privileged(
+    roots: &DotfileRoots,
+    m: &DotfileMapping,
+    merged: Option<&str>,
+    journal: &mut RollbackJournal,
+) -> Result<()> {
+    let src = roots.source(m);
+    let dst = normalize(&roots.destination(m));
+    let existing = fs::metadata(&dst).ok().map(|meta| meta.mode());
+    journal.stash(&dst, false)?;
+    let written = crate::core::file_modes::Written {
+        dir: false,
+        source: fs::metadata(&src)
+            .map_err(|e| anyhow!("Failed to stat {}: {}", src.display(), e))?
+            .mode(),
+        existing,
+        in_home: false,
+    };
+    let mode = crate::core::file_modes::resolve(
+        &m.modes,
+        &roots.mode_policy,
+        written,
+        crate::core::file_modes::umask(),
+    );
+    let staged = match merged {
+        Some(merged) => {
+            let path = roots.backup_dir.join(PRIVILEGED_STAGING);
+            ensure_parent_dir(&path)?;
+            fs::write(&path, merged)
+                .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+            Some(path)
+        }
+        None => None,
+    };
+    let result = crate::core::privilege::run(&crate::core::privileged_helper::Op::WriteFile {
+        source: staged.clone().unwrap_or(src),
+        destination: dst,
+        mode,
+    });
+    if let Some(path) = staged {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// Destinations replaced so far in an apply run, backed by one backup set
+#[derive(Debug)]
+pub(crate) struct RollbackJournal {
+    store: BackupStore,
+    manifest: Manifest,
+    /// Backups of each destination kept when the run commits
+    keep: usize,
+}
+
+impl RollbackJournal {
+    pub(crate) fn new(store: BackupStore, keep: usize) -> Self {
+        Self {
+            store,
+            manifest: Manifest::new(crate::internal::time::now_secs()),
+            keep,
+        }
+    }
+
+    /// Back up `dst`, then remove it if `clear`
+    pub(crate) fn stash(&mut self, dst: &Path, clear: bool) -> Result<()> {
+        let target = self.store.snapshot(dst)?;
+        let existed = !target.entries.is_empty();
+        self.manifest.targets.push(target);
+        // Persist before touching the destination so an interrupted run stays recoverable
+        self.store.save_manifest(&self.manifest)?;
+        if existed && clear {
+            remove_path(dst)?;
+        }
+        Ok(())
+    }
+
+    /// Restore every stashed destination, newest first
+    pub(crate) fn rollback(self) -> Result<usize> {
+        for target in self.manifest.targets.iter().rev() {
+            self.store.restore(target)?;
+        }
+        Ok(self.manifest.targets.len())
+    }
+
+    /// The backup set stashed destinations go into
+    pub(crate) fn id(&self) -> &str {
+        &self.manifest.id
+    }
+
+    /// Keep the backup set (if it recorded anything) and prune old sets
+    pub(crate) fn commit(self) {
+        if self.manifest.targets.is_empty() {
+            return;
+        }
+        let result = (|| -> Result<()> {
+            if self.manifest.targets.iter().all(|t| t.entries.is_empty()) {
+                self.store.remove_manifest(&self.manifest.id)?;
             }
+            self.store.migrate_legacy()?;
+            self.store.prune(self.keep)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            warnln!(
+                "{}",
+                crate::internal::color::yellow(&format!("Failed to prune dotfile backups: {}", e))
+            );
         }
     }
-    if !dry_run {
-        println!(
-            "  {} Up to date: {} dotfiles",
-            crate::internal::color::green("➔"),
-            up_to_date
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    let meta = path
+        .symlink_metadata()
+        .map_err(|e| anyhow!("Failed to stat {}: {}", path.display(), e))?;
+    if meta.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .map_err(|e| anyhow!("Failed to remove {}: {}", path.display(), e))
+}
+
+/// Render a unified diff of the pending change for an `Update` action, or
+/// of the merge for a diverged one
+///
+/// Directory mappings produce one diff per changed file. Binary files are
+/// reported without content.
+pub fn diff_action(action: &DotfileAction, context: usize) -> Result<String> {
+    let roots = DotfileRoots::from_env()?;
+    let src = roots.source(&action.mapping);
+    let dst = roots.destination(&action.mapping);
+    if matches!(
+        action.status,
+        DotfileStatus::Merge | DotfileStatus::MergeConflict { .. }
+    ) {
+        let baselines = BackupStore::new(roots.backup_dir.clone()).baselines()?;
+        if let Some(merged) = merge_mapping(&roots, &action.mapping, &baselines)? {
+            let current = String::from_utf8_lossy(&read_file(&dst)?).into_owned();
+            let label = &action.mapping.destination;
+            return Ok(crate::core::diff::unified_diff(
+                &current,
+                &merged.text,
+                &format!("a/{}", label),
+                &format!("b/{} (merged)", label),
+                context,
+            ));
+        }
+    }
+    if !src.is_dir() {
+        return diff_files(&dst, &src, &action.mapping.destination, context);
+    }
+
+    let mut rels: Vec<PathBuf> = Vec::new();
+    collect_files_recursively(&src, &mut rels, &src)?;
+    if dst.is_dir() {
+        let mut dst_rels: Vec<PathBuf> = Vec::new();
+        collect_files_recursively(&dst, &mut dst_rels, &dst)?;
+        for rel in dst_rels {
+            if !rels.contains(&rel) {
+                rels.push(rel);
+            }
+        }
+    }
+    rels.sort();
+
+    let mut out = String::new();
+    for rel in rels {
+        let label = Path::new(&action.mapping.destination).join(&rel);
+        out.push_str(&diff_files(
+            &dst.join(&rel),
+            &src.join(&rel),
+            &label.to_string_lossy(),
+            context,
+        )?);
+    }
+    Ok(out)
+}
+
+fn diff_files(old: &Path, new: &Path, label: &str, context: usize) -> Result<String> {
+    let read = |p: &Path| -> Result<Option<Vec<u8>>> {
+        if !p.is_file() {
+            return Ok(None);
+        }
+        fs::read(p)
+            .map(Some)
+            .map_err(|e| anyhow!("Failed to read {}: {}", p.display(), e))
+    };
+    let old_data = read(old)?;
+    let new_data = read(new)?;
+    if old_data == new_data {
+        return Ok(String::new());
+    }
+    let old_text = String::from_utf8(old_data.clone().unwrap_or_default());
+    let new_text = String::from_utf8(new_data.clone().unwrap_or_default());
+    match (old_text, new_text) {
+        (Ok(o), Ok(n)) => Ok(crate::core::diff::unified_diff(
+            &o,
+            &n,
+            &format!("a/{}", label),
+            &format!("b/{}", label),
+            context,
+        )),
+        _ => Ok(format!("Binary files a/{} and b/{} differ\n", label, label)),
+    }
+}
+
+/// Synchronize dotfiles, reporting progress through `sink`
+///
+/// With `checks`, sources are checked first (`core::source_check`); strict
+/// checks with findings stop the sync.
+pub fn sync_dotfiles(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    dry_run: bool,
+    concurrency: usize,
+    keep_backups: usize,
+    checks: Option<&crate::core::source_check::SourceChecks>,
+    sink: &mut dyn EventSink,
+) -> Result<()> {
+    sink.emit(OwlEvent::PhaseStarted(EventPhase::Dotfiles));
+    // Staged runs (`--dest-prefix`) do not deploy into the real home
+    let shared = roots
+        .shared_host
+        .as_deref()
+        .filter(|_| roots.dest_prefix.is_none())
+        .and_then(|host| crate::core::shared_home::check(roots, mappings, host, sink));
+    let result = match checks {
+        Some(checks) => crate::core::source_check::report(roots, mappings, checks, sink)
+            .map_err(|e| anyhow!("{}; nothing was synced", e)),
+        None => Ok(0),
+    }
+    .and_then(|_| {
+        // Declined mappings still count as deployed below, they are only not written
+        let selected: Vec<DotfileMapping> = mappings
+            .iter()
+            .filter(|m| !roots.declined.contains(&m.destination))
+            .cloned()
+            .collect();
+        sync_phase(roots, &selected, dry_run, concurrency, keep_backups, sink)
+    })
+    .and_then(|_| crate::core::absent::enforce(roots, mappings, dry_run, keep_backups, sink));
+    if result.is_ok() && roots.dest_prefix.is_none() {
+        track_deployed(roots, mappings, dry_run, sink);
+    }
+    if result.is_ok()
+        && !dry_run
+        && let (Some(host), Some(files)) = (roots.shared_host.as_deref(), shared)
+    {
+        crate::core::shared_home::record(roots, host, files, sink);
+    }
+    sink.emit(OwlEvent::PhaseFinished(EventPhase::Dotfiles));
+    result
+}
+
+/// Report destinations earlier runs deployed that are no longer mapped, then
+/// record this run's destinations
+///
+/// Orphans are never deleted; they stay in the record until they are gone.
+fn track_deployed(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    dry_run: bool,
+    sink: &mut dyn EventSink,
+) {
+    let state_dir = roots.owl_dir.join(crate::internal::constants::STATE_DIR);
+    let exists = |path: &str| fs::symlink_metadata(path).is_ok();
+    let result = DeployedDotfiles::load_from(&state_dir).and_then(|mut deployed| {
+        let current = mappings
+            .iter()
+            .map(|m| roots.destination(m).to_string_lossy().into_owned())
+            .filter(|dest| exists(dest))
+            .collect();
+        let orphans = deployed.orphans(&current, exists, crate::internal::case_fold::path_key);
+        if !orphans.is_empty() {
+            sink.emit(OwlEvent::DotfilesOrphaned {
+                destinations: orphans.clone(),
+            });
+        }
+        if dry_run {
+            return Ok(());
+        }
+        deployed.record(current, &orphans);
+        deployed.save_to(&state_dir)
+    });
+    if let Err(e) = result {
+        sink.emit(OwlEvent::Warning(format!(
+            "Failed to track deployed dotfiles: {}",
+            e
+        )));
+    }
+}
+
+fn sync_phase(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    dry_run: bool,
+    concurrency: usize,
+    keep_backups: usize,
+    sink: &mut dyn EventSink,
+) -> Result<()> {
+    if mappings.is_empty() {
+        sink.emit(OwlEvent::DotfilesEmpty);
+        return Ok(());
+    }
+
+    // Check if any actions are needed
+    let statuses = analyze_dotfiles(roots, mappings, concurrency)
+        .map_err(|e| anyhow!("Failed to analyze dotfiles: {}", e))?;
+    for warning in private_warnings(roots, mappings) {
+        sink.emit(OwlEvent::Warning(warning));
+    }
+    if !has_actionable(roots, mappings, &statuses) {
+        sink.emit(OwlEvent::DotfilesUpToDate {
+            count: mappings.len(),
+        });
+        return Ok(());
+    }
+
+    let failpoints = crate::internal::failpoint::Failpoints::from_env()?;
+    let actions = apply_analyzed(
+        roots,
+        mappings,
+        statuses,
+        dry_run,
+        &failpoints,
+        keep_backups,
+    )
+    .map_err(|e| anyhow!("Failed to apply dotfiles: {}", e))?;
+    let up_to_date = actions
+        .iter()
+        .filter(|a| a.status == DotfileStatus::UpToDate)
+        .count();
+    for action in actions {
+        sink.emit(OwlEvent::DotfileActionCompleted { action });
+    }
+    sink.emit(OwlEvent::DotfilesFinished {
+        up_to_date,
+        dry_run,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::failpoint::Failpoints;
+
+    /// Analyse and apply in one go, as `sync_dotfiles` does
+    fn apply_dotfiles_in(
+        roots: &DotfileRoots,
+        mappings: &[DotfileMapping],
+        dry_run: bool,
+        failpoints: &Failpoints,
+        concurrency: usize,
+    ) -> Result<Vec<DotfileAction>> {
+        let statuses = analyze_dotfiles(roots, mappings, concurrency)?;
+        apply_analyzed(
+            roots,
+            mappings,
+            statuses,
+            dry_run,
+            failpoints,
+            crate::core::backup::DEFAULT_KEEP_BACKUPS,
+        )
+    }
+
+    fn fixture(dir: &Path) -> (DotfileRoots, Vec<DotfileMapping>) {
+        let roots = DotfileRoots {
+            owl_dir: dir.to_path_buf(),
+            source_dir: dir.join("dotfiles"),
+            home: dir.join("home").to_string_lossy().into_owned(),
+            backup_dir: dir.join("backups"),
+            dest_prefix: None,
+            hash_algo: HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        };
+        fs::create_dir_all(roots.source_dir.join("nvim")).unwrap();
+        fs::create_dir_all(&roots.home).unwrap();
+        fs::write(roots.source_dir.join("bashrc"), "new bashrc\n").unwrap();
+        fs::write(roots.source_dir.join("nvim/init.lua"), "new init\n").unwrap();
+        let mappings = vec![
+            DotfileMapping {
+                source: "bashrc".to_string(),
+                destination: "~/.bashrc".to_string(),
+                root: None,
+                from: None,
+                hardlink: false,
+                force_owned: false,
+                modes: Default::default(),
+            },
+            DotfileMapping {
+                source: "nvim".to_string(),
+                destination: "~/.config/nvim".to_string(),
+                root: None,
+                from: None,
+                hardlink: false,
+                force_owned: false,
+                modes: Default::default(),
+            },
+        ];
+        (roots, mappings)
+    }
+
+    fn mapping(source: &str, root: Option<&Path>) -> DotfileMapping {
+        DotfileMapping {
+            source: source.to_string(),
+            destination: "~/.x".to_string(),
+            root: root.map(Path::to_path_buf),
+            from: None,
+            hardlink: false,
+            force_owned: false,
+            modes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_no_delete_keeps_extra_destination_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 2).unwrap();
+        let nvim = Path::new(&roots.home).join(".config/nvim");
+        fs::write(nvim.join("lazy-lock.json"), "{}\n").unwrap();
+        fs::write(roots.source_dir.join("nvim/init.lua"), "newer init\n").unwrap();
+        fs::write(roots.source_dir.join("nvim/keys.lua"), "keys\n").unwrap();
+
+        let roots = roots.with_no_delete(true);
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 2).unwrap();
+        assert_eq!(
+            fs::read_to_string(nvim.join("lazy-lock.json")).unwrap(),
+            "{}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(nvim.join("init.lua")).unwrap(),
+            "newer init\n"
+        );
+        assert_eq!(fs::read_to_string(nvim.join("keys.lua")).unwrap(), "keys\n");
+        // The extra file does not make the mapping look out of date
+        let statuses = analyze_dotfiles(&roots, &mappings, 2).unwrap();
+        assert!(statuses.iter().all(|s| *s == DotfileStatus::UpToDate));
+
+        // Without the flag the destination is replaced whole
+        let roots = roots.with_no_delete(false);
+        assert_eq!(
+            analyze_dotfiles(&roots, &mappings, 2).unwrap()[1],
+            DotfileStatus::Update
+        );
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 2).unwrap();
+        assert!(!nvim.join("lazy-lock.json").exists());
+    }
+
+    #[test]
+    fn test_no_delete_refuses_to_replace_a_directory_with_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        let bashrc = Path::new(&roots.home).join(".bashrc");
+        fs::create_dir_all(bashrc.join("kept")).unwrap();
+
+        let roots = roots.with_no_delete(true);
+        let err =
+            apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 2).unwrap_err();
+        assert!(err.to_string().contains("is a directory"), "{}", err);
+        assert!(bashrc.join("kept").is_dir());
+    }
+
+    #[test]
+    fn test_dotdot_destination_is_a_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, _) = fixture(dir.path());
+        let escaping = DotfileMapping {
+            destination: "~/../../etc/passwd".to_string(),
+            ..mapping("bashrc", None)
+        };
+        // Not even --allow-outside-home lets `~/` climb out of home
+        let roots = roots.with_allow_outside_home(true);
+        let statuses = analyze_dotfiles(&roots, std::slice::from_ref(&escaping), 1).unwrap();
+        let DotfileStatus::Conflict(reason) = &statuses[0] else {
+            panic!("expected a conflict, got {:?}", statuses[0]);
+        };
+        assert!(
+            reason.contains("escapes") && reason.contains(".."),
+            "{}",
+            reason
+        );
+        apply_dotfiles_in(&roots, &[escaping], false, &Failpoints::default(), 1).unwrap();
+
+        // Staged runs may not leave the staging directory either
+        let staged = roots.with_dest_prefix(Some(dir.path().join("stage")));
+        let inside = DotfileMapping {
+            destination: "~/.config/../.bashrc".to_string(),
+            ..mapping("bashrc", None)
+        };
+        assert_eq!(staged.escape_reason(&inside), None);
+        let system = DotfileMapping {
+            destination: "/etc/passwd".to_string(),
+            ..mapping("bashrc", None)
+        };
+        assert_eq!(
+            staged
+                .clone()
+                .with_allow_outside_home(false)
+                .escape_reason(&system),
+            None
+        );
+        let escaping = DotfileMapping {
+            destination: "~/../../../../../../etc/passwd".to_string(),
+            ..mapping("bashrc", None)
+        };
+        assert!(staged.escape_reason(&escaping).is_some());
+    }
+
+    #[test]
+    fn test_system_destination_needs_allow_outside_home() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, _) = fixture(dir.path());
+        let system = DotfileMapping {
+            destination: dir.path().join("etc/hosts").to_string_lossy().into_owned(),
+            ..mapping("bashrc", None)
+        };
+        let statuses = analyze_dotfiles(&roots, std::slice::from_ref(&system), 1).unwrap();
+        assert!(
+            matches!(&statuses[0], DotfileStatus::Conflict(reason) if reason.contains("--allow-outside-home")),
+            "{:?}",
+            statuses[0]
+        );
+
+        let roots = roots.with_allow_outside_home(true);
+        let statuses = analyze_dotfiles(&roots, std::slice::from_ref(&system), 1).unwrap();
+        assert_eq!(statuses[0], DotfileStatus::Create);
+        apply_dotfiles_in(&roots, &[system], false, &Failpoints::default(), 1).unwrap();
+        assert!(dir.path().join("etc/hosts").exists());
+    }
+
+    #[test]
+    fn test_dotdot_source_is_a_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, _) = fixture(dir.path());
+        fs::write(dir.path().join("secret"), "x").unwrap();
+        let reason = roots.escape_reason(&DotfileMapping {
+            destination: "~/.secret".to_string(),
+            ..mapping("../secret", None)
+        });
+        assert!(reason.is_some_and(|r| r.starts_with("source ../secret escapes")));
+        // Inside the dotfiles directory, and the explicit forms, are fine
+        for source in ["nvim/../bashrc", "@/dotfiles/bashrc", "/etc/skel/.bashrc"] {
+            let m = DotfileMapping {
+                destination: "~/.bashrc".to_string(),
+                ..mapping(source, None)
+            };
+            assert_eq!(roots.escape_reason(&m), None, "{}", source);
+        }
+        let m = DotfileMapping {
+            destination: "~/.bashrc".to_string(),
+            ..mapping("@/../bashrc", None)
+        };
+        assert!(roots.escape_reason(&m).is_some());
+    }
+
+    #[test]
+    fn test_dest_prefix_reroots_destinations() {
+        let roots = DotfileRoots {
+            owl_dir: PathBuf::from("/owl"),
+            source_dir: PathBuf::from("/owl/dotfiles"),
+            home: "/home/u".to_string(),
+            backup_dir: PathBuf::from("/owl/.state/backups"),
+            dest_prefix: None,
+            hash_algo: HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        }
+        .with_dest_prefix(Some(PathBuf::from("/tmp/stage")));
+        let to = |destination: &str| DotfileMapping {
+            destination: destination.to_string(),
+            ..mapping("x", None)
+        };
+        assert_eq!(
+            roots.destination(&to("~/.config/nvim")),
+            PathBuf::from("/tmp/stage/.config/nvim")
+        );
+        assert_eq!(roots.destination(&to("~")), PathBuf::from("/tmp/stage"));
+        assert_eq!(
+            roots.destination(&to("/home/u/.bashrc")),
+            PathBuf::from("/tmp/stage/.bashrc")
+        );
+        assert_eq!(
+            roots.destination(&to("/etc/pacman.conf")),
+            PathBuf::from("/tmp/stage/etc/pacman.conf")
+        );
+        assert_eq!(
+            roots
+                .with_dest_prefix(None)
+                .destination(&to("/etc/pacman.conf")),
+            PathBuf::from("/etc/pacman.conf")
+        );
+    }
+
+    #[test]
+    fn test_dest_prefix_sync_leaves_home_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mut mappings) = fixture(dir.path());
+        let stage = dir.path().join("stage");
+        let roots = roots.with_dest_prefix(Some(stage.clone()));
+        fs::write(roots.source_dir.join("hosts"), "127.0.0.1 box\n").unwrap();
+        mappings.push(DotfileMapping {
+            destination: "/etc/hosts".to_string(),
+            ..mapping("hosts", None)
+        });
+
+        sync_dotfiles(&roots, &mappings, false, 2, 1, None, &mut |_| {}).unwrap();
+        assert_eq!(
+            fs::read_to_string(stage.join(".bashrc")).unwrap(),
+            "new bashrc\n"
+        );
+        assert!(stage.join(".config/nvim/init.lua").exists());
+        assert_eq!(
+            fs::read_to_string(stage.join("etc/hosts")).unwrap(),
+            "127.0.0.1 box\n"
+        );
+        assert!(!Path::new(&roots.home).join(".bashrc").exists());
+    }
+
+    #[test]
+    fn test_removed_mapping_is_reported_as_orphan() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        let state_dir = dir.path().join(crate::internal::constants::STATE_DIR);
+        sync_dotfiles(&roots, &mappings, false, 2, 1, None, &mut |_| {}).unwrap();
+
+        // The nvim mapping is removed from the config
+        let mut events = Vec::new();
+        sync_dotfiles(&roots, &mappings[..1], false, 2, 1, None, &mut |e| {
+            events.push(e)
+        })
+        .unwrap();
+        let nvim = format!("{}/.config/nvim", roots.home);
+        let orphaned = OwlEvent::DotfilesOrphaned {
+            destinations: vec![nvim.clone()],
+        };
+        assert!(events.contains(&orphaned), "{:?}", events);
+        assert!(Path::new(&nvim).join("init.lua").exists());
+
+        // Reported again until it is removed
+        let mut events = Vec::new();
+        sync_dotfiles(&roots, &mappings[..1], true, 2, 1, None, &mut |e| {
+            events.push(e)
+        })
+        .unwrap();
+        assert!(events.contains(&orphaned));
+        fs::remove_dir_all(&nvim).unwrap();
+        let mut events = Vec::new();
+        sync_dotfiles(&roots, &mappings[..1], false, 2, 1, None, &mut |e| {
+            events.push(e)
+        })
+        .unwrap();
+        assert!(
+            !events
+                .iter()
+                .any(|e| matches!(e, OwlEvent::DotfilesOrphaned { .. }))
+        );
+        let deployed = DeployedDotfiles::load_from(&state_dir).unwrap();
+        assert_eq!(
+            deployed.destinations.into_iter().collect::<Vec<_>>(),
+            vec![format!("{}/.bashrc", roots.home)]
+        );
+    }
+
+    #[test]
+    fn test_staged_run_does_not_track_deployments() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        let roots = roots.with_dest_prefix(Some(dir.path().join("stage")));
+        sync_dotfiles(&roots, &mappings, false, 2, 1, None, &mut |_| {}).unwrap();
+        let state_dir = dir.path().join(crate::internal::constants::STATE_DIR);
+        assert!(!state_dir.join("deployed.json").exists());
+    }
+
+    #[test]
+    fn test_strict_source_checks_stop_the_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        fs::write(roots.source_dir.join("nvim/lazy.toml"), "").unwrap();
+        let mut checks = crate::core::source_check::SourceChecks {
+            suspect_empty: vec!["*.toml".to_string()],
+            enforce_lf: false,
+            strict: false,
+        };
+
+        let mut events = Vec::new();
+        sync_dotfiles(&roots, &mappings, true, 2, 1, Some(&checks), &mut |e| {
+            events.push(e)
+        })
+        .unwrap();
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, OwlEvent::DotfileSourceIssues { strict: false, .. }))
+        );
+
+        checks.strict = true;
+        let err =
+            sync_dotfiles(&roots, &mappings, false, 2, 1, Some(&checks), &mut |_| {}).unwrap_err();
+        assert!(err.to_string().contains("nothing was synced"), "{}", err);
+        assert!(!Path::new(&roots.home).join(".bashrc").exists());
+    }
+
+    #[test]
+    fn test_source_forms() {
+        let roots = DotfileRoots {
+            owl_dir: PathBuf::from("/owl"),
+            source_dir: PathBuf::from("/owl/dotfiles"),
+            home: "/home/u".to_string(),
+            backup_dir: PathBuf::from("/owl/.state/backups"),
+            dest_prefix: None,
+            hash_algo: HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        };
+        // Dotfiles-relative, against the default directory or `@dotfiles-root`
+        assert_eq!(
+            roots.source(&mapping("nvim", None)),
+            PathBuf::from("/owl/dotfiles/nvim")
+        );
+        assert_eq!(
+            roots.source(&mapping("nvim", Some(Path::new("/team")))),
+            PathBuf::from("/team/nvim")
+        );
+        // `[from=DIR]` is a subdirectory of whichever of those applies
+        let from = |root| DotfileMapping {
+            from: Some("hosts/laptop".to_string()),
+            ..mapping("nvim", root)
+        };
+        assert_eq!(
+            roots.source(&from(None)),
+            PathBuf::from("/owl/dotfiles/hosts/laptop/nvim")
+        );
+        assert_eq!(
+            roots.source(&from(Some(Path::new("/team")))),
+            PathBuf::from("/team/hosts/laptop/nvim")
+        );
+        // Owl-relative, regardless of `@dotfiles-root`
+        assert_eq!(
+            roots.source(&mapping("@/shared/x", None)),
+            PathBuf::from("/owl/shared/x")
+        );
+        assert_eq!(
+            roots.source(&mapping("@/shared/x", Some(Path::new("/team")))),
+            PathBuf::from("/owl/shared/x")
+        );
+        // Absolute, used as is
+        assert_eq!(
+            roots.source(&mapping("/etc/skel/.bashrc", None)),
+            PathBuf::from("/etc/skel/.bashrc")
+        );
+    }
+
+    #[test]
+    fn test_from_subdir_applies_and_missing_subdir_is_a_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, _) = fixture(dir.path());
+        let laptop = roots.source_dir.join("hosts/laptop");
+        fs::create_dir_all(&laptop).unwrap();
+        fs::write(laptop.join("bashrc"), "laptop bashrc\n").unwrap();
+        let m = |from: &str| DotfileMapping {
+            destination: "~/.bashrc".to_string(),
+            from: Some(from.to_string()),
+            ..mapping("bashrc", None)
+        };
+
+        apply_dotfiles_in(
+            &roots,
+            &[m("hosts/laptop")],
+            false,
+            &Failpoints::default(),
+            1,
+        )
+        .unwrap();
+        let dst = Path::new(&roots.home).join(".bashrc");
+        assert_eq!(fs::read_to_string(dst).unwrap(), "laptop bashrc\n");
+
+        let statuses = analyze_dotfiles(&roots, &[m("hosts/desktop")], 1).unwrap();
+        let DotfileStatus::Conflict(reason) = &statuses[0] else {
+            panic!("expected a conflict, got {:?}", statuses[0]);
+        };
+        assert!(
+            reason.starts_with("[from=hosts/desktop] directory"),
+            "{}",
+            reason
+        );
+        assert!(reason.ends_with("does not exist"), "{}", reason);
+
+        // `..` in from= is caught like any other escaping source
+        assert!(roots.escape_reason(&m("..")).is_some());
+    }
+
+    #[test]
+    fn test_from_option_parsed_from_config() {
+        let config = crate::core::config::Config::parse(
+            "@package neovim\n:config [from=hosts/laptop] nvim -> ~/.config/nvim\n\
+             :config [from=hosts/laptop] gitconfig [hardlink]\n:config zshrc -> ~/.zshrc\n",
+        )
+        .unwrap();
+        let mut mappings = get_dotfile_mappings(&config);
+        mappings.sort_by(|a, b| a.source.cmp(&b.source));
+        let parsed: Vec<_> = mappings
+            .iter()
+            .map(|m| {
+                (
+                    m.source.as_str(),
+                    m.destination.as_str(),
+                    m.from.as_deref(),
+                    m.hardlink,
+                )
+            })
+            .collect();
+        assert_eq!(
+            parsed,
+            vec![
+                ("gitconfig", "gitconfig", Some("hosts/laptop"), true),
+                ("nvim", "~/.config/nvim", Some("hosts/laptop"), false),
+                ("zshrc", "~/.zshrc", None, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_option_selects_mappings() {
+        let config = crate::core::config::Config::parse(
+            "@package neovim
+:config nvim -> ~/.config/nvim
+             :config [host=laptop] nvim-laptop -> ~/.config/nvim
+             :config [host=desktop,workstation] [from=hosts/desktop] nvim -> ~/.config/nvim
+             :config [host=laptop] powertop.conf -> ~/.config/powertop.conf
+             :config gitconfig -> ~/.gitconfig
+",
+        )
+        .unwrap();
+        let sources = |hostname: Option<&str>| {
+            let mut mappings: Vec<_> = mappings_for_host(&config, hostname)
+                .into_iter()
+                .map(|m| (m.source, m.destination, m.from))
+                .collect();
+            mappings.sort();
+            mappings
+        };
+        let mapping = |source: &str, dest: &str, from: Option<&str>| {
+            (
+                source.to_string(),
+                dest.to_string(),
+                from.map(str::to_string),
+            )
+        };
+
+        // The host's own mapping wins over the unconditional one to the same destination
+        assert_eq!(
+            sources(Some("laptop")),
+            vec![
+                mapping("gitconfig", "~/.gitconfig", None),
+                mapping("nvim-laptop", "~/.config/nvim", None),
+                mapping("powertop.conf", "~/.config/powertop.conf", None),
+            ]
+        );
+        assert_eq!(
+            sources(Some("workstation")),
+            vec![
+                mapping("gitconfig", "~/.gitconfig", None),
+                mapping("nvim", "~/.config/nvim", Some("hosts/desktop")),
+            ]
+        );
+        // Other hosts, and an unknown hostname, only get unconditional mappings
+        let unconditional = vec![
+            mapping("gitconfig", "~/.gitconfig", None),
+            mapping("nvim", "~/.config/nvim", None),
+        ];
+        assert_eq!(sources(Some("server")), unconditional);
+        assert_eq!(sources(None), unconditional);
+    }
+
+    #[test]
+    fn test_owl_relative_source_applies() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, _) = fixture(dir.path());
+        fs::create_dir_all(dir.path().join("shared")).unwrap();
+        fs::write(dir.path().join("shared/zshrc"), "shared zshrc\n").unwrap();
+        let mappings = vec![DotfileMapping {
+            source: "@/shared/zshrc".to_string(),
+            destination: "~/.zshrc".to_string(),
+            root: None,
+            from: None,
+            hardlink: false,
+            force_owned: false,
+            modes: Default::default(),
+        }];
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        let dst = Path::new(&roots.home).join(".zshrc");
+        assert_eq!(fs::read_to_string(dst).unwrap(), "shared zshrc\n");
+    }
+
+    #[test]
+    fn test_apply_replaces_destinations() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        let home = Path::new(&roots.home);
+        fs::write(home.join(".bashrc"), "old bashrc\n").unwrap();
+
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 4).unwrap();
+        assert_eq!(actions[0].status, DotfileStatus::Update);
+        assert_eq!(actions[1].status, DotfileStatus::Create);
+        assert_eq!(
+            fs::read_to_string(home.join(".bashrc")).unwrap(),
+            "new bashrc\n"
+        );
+        assert!(home.join(".config/nvim/init.lua").is_file());
+
+        // The replaced .bashrc is kept in a backup set
+        let store = BackupStore::new(roots.backup_dir.clone());
+        let manifests = store.manifests().unwrap();
+        assert_eq!(manifests.len(), 1);
+        let backup = &manifests[0].targets[0];
+        assert_eq!(
+            store
+                .get(backup.entries[0].hash.as_deref().unwrap())
+                .unwrap(),
+            b"old bashrc\n"
+        );
+    }
+
+    #[test]
+    fn test_simulated_failure_rolls_back_earlier_dotfiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        let home = Path::new(&roots.home);
+        fs::write(home.join(".bashrc"), "old bashrc\n").unwrap();
+
+        let failpoints = Failpoints::parse("dotfiles:1").unwrap();
+        let err = apply_dotfiles_in(&roots, &mappings, false, &failpoints, 4).unwrap_err();
+        assert!(err.to_string().contains("Simulated failure in dotfiles #1"));
+        assert!(err.to_string().contains("rolled back 1 dotfile(s)"));
+
+        // First dotfile restored, second never written
+        assert_eq!(
+            fs::read_to_string(home.join(".bashrc")).unwrap(),
+            "old bashrc\n"
+        );
+        assert!(!home.join(".config/nvim").exists());
+    }
+
+    #[test]
+    fn test_rollback_removes_created_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mut mappings) = fixture(dir.path());
+        mappings.reverse();
+
+        let failpoints = Failpoints::parse("dotfiles:1").unwrap();
+        assert!(apply_dotfiles_in(&roots, &mappings, false, &failpoints, 4).is_err());
+        assert!(!Path::new(&roots.home).join(".config/nvim").exists());
+        assert!(!Path::new(&roots.home).join(".bashrc").exists());
+    }
+
+    #[test]
+    fn test_second_apply_skips_hashing_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        let bashrc = roots.source_dir.join("bashrc");
+        fs::write(&bashrc, vec![b'x'; 4 << 20]).unwrap();
+        // Sources last edited a while ago, outside the racy window
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        fs::File::open(&bashrc).unwrap().set_modified(old).unwrap();
+        fs::File::open(roots.source_dir.join("nvim/init.lua"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 4).unwrap();
+
+        // One worker analyses on this thread, where HASH_CALLS counts
+        HASH_CALLS.with(|calls| calls.set(0));
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        assert!(actions.iter().all(|a| a.status == DotfileStatus::UpToDate));
+        assert_eq!(HASH_CALLS.with(|calls| calls.get()), 0);
+    }
+
+    #[test]
+    fn test_mtime_fast_path_distrusts_skewed_clocks() {
+        let at = |secs: u64| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        let now = 1_000_000;
+        // (mtime, copied, trusted)
+        let cases = [
+            (now - 60, now - 30, true),
+            (now - 60, now - 61, true),
+            // Within the racy window of now
+            (now - 1, now - 1, false),
+            // In the future: the clock was set back after the file was written
+            (now + 3600, now + 3600, false),
+            // Later than the copy was written: the clock was behind at the copy
+            (now - 60, now - 3600, false),
+        ];
+        for (mtime, copied, trusted) in cases {
+            assert_eq!(
+                mtime_settles(at(mtime), at(copied), at(now)),
+                trusted,
+                "mtime {} copied {}",
+                mtime,
+                copied
+            );
+        }
+
+        // Same size and mtime, but that mtime is in the future: the contents decide
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        let future = std::time::SystemTime::now() + std::time::Duration::from_secs(86_400);
+        for (path, contents) in [(&src, "color=blue\n"), (&dst, "color=pink\n")] {
+            fs::write(path, contents).unwrap();
+            fs::File::open(path).unwrap().set_modified(future).unwrap();
+        }
+        assert!(!file_in_sync(&src, &dst, false, HashAlgo::Xxh3).unwrap());
+    }
+
+    #[test]
+    fn test_hash_algos_compare_contents_when_mtimes_differ() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        let touch = |path: &Path, contents: &str, secs: u64| {
+            fs::write(path, contents).unwrap();
+            let mtime = old + std::time::Duration::from_secs(secs);
+            fs::File::open(path).unwrap().set_modified(mtime).unwrap();
+        };
+        touch(&src, "color=blue\n", 0);
+        for algo in [HashAlgo::Xxh3, HashAlgo::Sha256] {
+            // Same size, different mtime: only the hash can tell
+            touch(&dst, "color=pink\n", 1);
+            assert!(
+                !file_in_sync(&src, &dst, false, algo).unwrap(),
+                "{:?}",
+                algo
+            );
+            touch(&dst, "color=blue\n", 1);
+            assert!(file_in_sync(&src, &dst, false, algo).unwrap(), "{:?}", algo);
+        }
+    }
+
+    #[test]
+    fn test_digests_are_tagged_with_their_algorithm() {
+        let xxh3 = HashAlgo::Xxh3.digest(b"same");
+        let sha256 = HashAlgo::Sha256.digest(b"same");
+        assert!(xxh3.starts_with("xxh3:") && xxh3.len() == "xxh3:".len() + 32);
+        assert_eq!(
+            sha256,
+            "sha256:0967115f2813a3541eaef77de9d9d5773f1c0c04314b0bbfe4ff3b3b1c55b5d5"
+        );
+        assert_ne!(xxh3, sha256);
+        assert_eq!(xxh3, HashAlgo::Xxh3.digest(b"same"));
+    }
+
+    /// Not a precise benchmark, but xxh3 has to stay well ahead of sha256
+    /// even in unoptimised test builds for the default to be worth it
+    #[test]
+    fn test_xxh3_hashes_faster_than_sha256() {
+        let data = vec![0x5au8; 8 << 20];
+        let time = |algo: HashAlgo| {
+            let start = std::time::Instant::now();
+            for _ in 0..3 {
+                std::hint::black_box(algo.digest(std::hint::black_box(&data)));
+            }
+            start.elapsed()
+        };
+        let sha256 = time(HashAlgo::Sha256);
+        let xxh3 = time(HashAlgo::Xxh3);
+        println!("8 MiB x3: sha256 {:?}, xxh3 {:?}", sha256, xxh3);
+        assert!(xxh3 < sha256, "xxh3 {:?} vs sha256 {:?}", xxh3, sha256);
+    }
+
+    #[test]
+    fn test_copy_file_preserves_mtime() {
+        // tempdirs are usually tmpfs, where copy_file_range cannot reflink
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::write(&src, "content\n").unwrap();
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        fs::File::open(&src).unwrap().set_modified(old).unwrap();
+
+        copy_file(&src, &dst).unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "content\n");
+        assert_eq!(fs::metadata(&dst).unwrap().modified().unwrap(), old);
+    }
+
+    #[test]
+    fn test_hardlink_mapping() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mut mappings) = fixture(dir.path());
+        mappings.truncate(1);
+        mappings[0].hardlink = true;
+        let dst = Path::new(&roots.home).join(".bashrc");
+
+        // An identical copy is replaced by a link
+        copy_file(&roots.source_dir.join("bashrc"), &dst).unwrap();
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 4).unwrap();
+        assert_eq!(actions[0].status, DotfileStatus::Update);
+        assert_eq!(
+            fs::metadata(&dst).unwrap().ino(),
+            fs::metadata(roots.source_dir.join("bashrc")).unwrap().ino()
+        );
+
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 4).unwrap();
+        assert_eq!(actions[0].status, DotfileStatus::UpToDate);
+    }
+
+    fn mode_of(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().mode() & 0o7777
+    }
+
+    #[test]
+    fn test_mode_policy_on_create_and_update() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mut mappings) = fixture(dir.path());
+        let roots = roots.with_mode_policy(ModePolicy {
+            file: Some(0o640),
+            dir: Some(0o750),
+        });
+        let home = Path::new(&roots.home).to_path_buf();
+        fs::write(home.join(".bashrc"), "old bashrc\n").unwrap();
+        fs::set_permissions(home.join(".bashrc"), fs::Permissions::from_mode(0o604)).unwrap();
+
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        // An update keeps the mode the user gave the file; a create gets the policy
+        assert_eq!(mode_of(&home.join(".bashrc")), 0o604);
+        assert_eq!(mode_of(&home.join(".config/nvim")), 0o750);
+        assert_eq!(mode_of(&home.join(".config/nvim/init.lua")), 0o640);
+
+        // [mode=] wins over the existing mode
+        fs::write(roots.source_dir.join("bashrc"), "newer bashrc\n").unwrap();
+        mappings[0].modes.mode = Some(0o600);
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        assert_eq!(mode_of(&home.join(".bashrc")), 0o600);
+    }
+
+    #[test]
+    fn test_private_destination_more_open_than_allowed_warns() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mut mappings) = fixture(dir.path());
+        mappings[0].modes.private = true;
+        let home = Path::new(&roots.home).to_path_buf();
+        fs::write(home.join(".bashrc"), "old bashrc\n").unwrap();
+        fs::set_permissions(home.join(".bashrc"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        let warnings = private_warnings(&roots, &mappings);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("is 644"), "{}", warnings[0]);
+
+        fs::set_permissions(home.join(".bashrc"), fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(private_warnings(&roots, &mappings).is_empty());
+
+        // A created [private] destination starts out private
+        fs::remove_file(home.join(".bashrc")).unwrap();
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        assert_eq!(mode_of(&home.join(".bashrc")), 0o600);
+    }
+
+    #[test]
+    fn test_mode_flags_parsed_from_config() {
+        let config = crate::core::config::Config::parse(
+            "@package openssh\n:config ssh -> ~/.ssh [private]\n\
+             :config [mode=0640] netrc -> ~/.netrc\n:config bin -> ~/bin [preserve-mode]\n",
+        )
+        .unwrap();
+        let mut mappings = get_dotfile_mappings(&config);
+        mappings.sort_by(|a, b| a.source.cmp(&b.source));
+        assert_eq!(mappings[0].destination, "~/bin");
+        assert!(mappings[0].modes.preserve);
+        assert_eq!(mappings[1].modes.mode, Some(0o640));
+        assert_eq!(mappings[2].destination, "~/.ssh");
+        assert!(mappings[2].modes.private);
+        assert!(!mappings[2].modes.preserve);
+
+        let err = crate::core::config::Config::parse("@package x\n:config [mode=999] a -> ~/a\n")
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("mode"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_hardlink_flag_parsed_from_config() {
+        let config = crate::core::config::Config::parse(
+            "@package git\n:config gitconfig -> ~/.gitconfig [hardlink]\n:config gitignore\n",
+        )
+        .unwrap();
+        let mut mappings = get_dotfile_mappings(&config);
+        mappings.sort_by(|a, b| a.source.cmp(&b.source));
+        assert_eq!(mappings[0].destination, "~/.gitconfig");
+        assert!(mappings[0].hardlink);
+        assert!(!mappings[1].hardlink);
+    }
+
+    #[test]
+    fn test_force_owned_flag_parsed_in_any_order() {
+        let config = crate::core::config::Config::parse(
+            "@package pacman\n:config mirrorlist -> /etc/pacman.d/mirrorlist [force-owned]\n\
+             :config pacman.conf -> /etc/pacman.conf [hardlink] [force-owned]\n\
+             :config makepkg.conf -> /etc/makepkg.conf [force-owned] [hardlink]\n",
+        )
+        .unwrap();
+        let mut mappings = get_dotfile_mappings(&config);
+        mappings.sort_by(|a, b| a.source.cmp(&b.source));
+        let flags: Vec<_> = mappings
+            .iter()
+            .map(|m| (m.destination.as_str(), m.hardlink, m.force_owned))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                ("/etc/makepkg.conf", true, true),
+                ("/etc/pacman.d/mirrorlist", false, true),
+                ("/etc/pacman.conf", true, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_package_owned_destinations() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, _) = fixture(dir.path());
+        fs::create_dir_all(roots.source_dir.join("pacman.d")).unwrap();
+        fs::write(roots.source_dir.join("pacman.d/mirrorlist"), "").unwrap();
+        let etc = dir.path().join("etc");
+        let mapping = |source: &str, destination: PathBuf, force_owned: bool| DotfileMapping {
+            source: source.to_string(),
+            destination: destination.to_string_lossy().into_owned(),
+            root: None,
+            from: None,
+            hardlink: false,
+            force_owned,
+            modes: Default::default(),
+        };
+        let mappings = vec![
+            mapping("pacman.d", etc.join("pacman.d"), false),
+            mapping("pacman.conf", etc.join("pacman.conf"), true),
+            mapping("bashrc", Path::new(&roots.home).join(".bashrc"), false),
+            mapping("owl.conf", etc.join("owl.conf"), false),
+        ];
+        // Every path asked about is owned, so only the filtering decides
+        let (_pm_dir, pm) = crate::core::pm::fake::pm(
+            "exit 0",
+            r#"shift
+for path in "$@"; do echo "$path is owned by filesystem 2024.11-1"; done"#,
+        );
+        let owned = package_owned_destinations(&roots, &mappings, &pm).unwrap();
+        assert_eq!(
+            owned,
+            vec![
+                OwnedDestination {
+                    path: etc.join("pacman.d/mirrorlist"),
+                    package: "filesystem".to_string(),
+                },
+                OwnedDestination {
+                    path: etc.join("owl.conf"),
+                    package: "filesystem".to_string(),
+                },
+            ]
+        );
+        assert!(owned[0].warning().contains("[force-owned]"));
+    }
+
+    #[test]
+    fn test_read_only_parent_is_a_conflict() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+        // Neither the owner of the temp dir nor in its group
+        let other = Identity {
+            uid: 65534,
+            gids: vec![65534],
+        };
+
+        let reason = destination_conflict(&locked.join("app/config.toml"), &other).unwrap();
+        assert_eq!(
+            reason,
+            format!(
+                "destination parent {} not writable, try sudo or change target",
+                locked.display()
+            )
+        );
+        assert!(
+            destination_conflict(
+                &locked.join("file"),
+                &Identity {
+                    uid: 0,
+                    gids: vec![]
+                }
+            )
+            .is_none()
+        );
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(destination_conflict(&locked.join("app/config.toml"), &other).is_none());
+    }
+
+    #[test]
+    fn test_unwritable_system_file_goes_through_the_helper() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, _) = fixture(dir.path());
+        let user = Identity {
+            uid: 65534,
+            gids: vec![65534],
+        };
+        let etc = dir.path().join("etc");
+        fs::create_dir_all(&etc).unwrap();
+        fs::set_permissions(&etc, fs::Permissions::from_mode(0o755)).unwrap();
+        let system = DotfileMapping {
+            destination: etc.join("hosts").to_string_lossy().into_owned(),
+            ..mapping("bashrc", None)
+        };
+        assert!(!roots.needs_privilege(&system, &user));
+        let roots = roots.with_allow_outside_home(true);
+        assert!(roots.needs_privilege(&system, &user));
+
+        // Analysed as that user it is written, not a conflict
+        let baselines = BTreeMap::new();
+        assert_eq!(
+            analyze_mapping(&roots, &system, Some(&user), &baselines).unwrap(),
+            DotfileStatus::Create
+        );
+
+        // Not hardlinks, directories, destinations under home or staged ones
+        let hardlink = DotfileMapping {
+            hardlink: true,
+            ..system.clone()
+        };
+        assert!(!roots.needs_privilege(&hardlink, &user));
+        assert!(matches!(
+            analyze_mapping(&roots, &hardlink, Some(&user), &baselines).unwrap(),
+            DotfileStatus::Conflict(_)
+        ));
+        let dir_source = DotfileMapping {
+            destination: system.destination.clone(),
+            ..mapping("nvim", None)
+        };
+        assert!(!roots.needs_privilege(&dir_source, &user));
+        let staged = roots
+            .clone()
+            .with_dest_prefix(Some(dir.path().join("stage")));
+        assert!(!staged.needs_privilege(&system, &user));
+    }
+
+    #[test]
+    fn test_etc_destination_suggests_privileges() {
+        let user = Identity {
+            uid: 65534,
+            gids: vec![65534],
+        };
+        let reason = destination_conflict(Path::new("/etc/owl-test/app.conf"), &user).unwrap();
+        assert!(reason.contains("elevated privileges"));
+    }
+
+    #[test]
+    fn test_dry_run_ignores_failpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = fixture(dir.path());
+        let failpoints = Failpoints::parse("dotfiles:0").unwrap();
+        let actions = apply_dotfiles_in(&roots, &mappings, true, &failpoints, 4).unwrap();
+        assert_eq!(actions.len(), 2);
+        assert!(!Path::new(&roots.home).join(".bashrc").exists());
+    }
+
+    /// `~/.bashrc` applied once from a five-line source, so it has a baseline
+    fn applied_bashrc(dir: &Path, divergence: Divergence) -> (DotfileRoots, Vec<DotfileMapping>) {
+        let (roots, mut mappings) = fixture(dir);
+        mappings.truncate(1);
+        fs::write(roots.source_dir.join("bashrc"), "1\n2\n3\n4\n5\n").unwrap();
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        (roots.with_divergence(divergence), mappings)
+    }
+
+    fn edit(roots: &DotfileRoots, source: &str, destination: &str) {
+        fs::write(roots.source_dir.join("bashrc"), source).unwrap();
+        fs::write(Path::new(&roots.home).join(".bashrc"), destination).unwrap();
+    }
+
+    fn status_of(roots: &DotfileRoots, mappings: &[DotfileMapping]) -> DotfileStatus {
+        analyze_dotfiles(roots, mappings, 1).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_baseline_recorded_for_written_and_in_sync_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, _) = applied_bashrc(dir.path(), Divergence::Merge);
+        let store = BackupStore::new(roots.backup_dir.clone());
+        let dst = Path::new(&roots.home).join(".bashrc");
+        let baselines = store.baselines().unwrap();
+        let baseline = &baselines[dst.to_string_lossy().as_ref()];
+        assert_eq!(store.get(&baseline.source).unwrap(), b"1\n2\n3\n4\n5\n");
+        assert_eq!(baseline.written, baseline.source);
+
+        // A destination already in sync gets one; directories and dry runs do not
+        let (roots, mappings) = fixture(&dir.path().join("other"));
+        fs::write(Path::new(&roots.home).join(".bashrc"), "new bashrc\n").unwrap();
+        apply_dotfiles_in(&roots, &mappings, true, &Failpoints::default(), 1).unwrap();
+        let store = BackupStore::new(roots.backup_dir.clone());
+        assert!(store.baselines().unwrap().is_empty());
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        let recorded: Vec<String> = store.baselines().unwrap().into_keys().collect();
+        assert_eq!(
+            recorded,
+            [Path::new(&roots.home).join(".bashrc").to_string_lossy()]
+        );
+        assert!(store.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_only_true_divergence_is_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = applied_bashrc(dir.path(), Divergence::Keep);
+        let dst = Path::new(&roots.home).join(".bashrc");
+
+        // Only one side changed: the source is written as always
+        edit(&roots, "1\n2\n3\n4\nfive\n", "1\n2\n3\n4\n5\n");
+        assert_eq!(status_of(&roots, &mappings), DotfileStatus::Update);
+        edit(&roots, "1\n2\n3\n4\n5\n", "one\n2\n3\n4\n5\n");
+        assert_eq!(status_of(&roots, &mappings), DotfileStatus::Update);
+        assert!(
+            diverged_destinations(&roots, &mappings, 1)
+                .unwrap()
+                .is_empty()
+        );
+
+        // Both changed: keep leaves the destination alone
+        edit(&roots, "1\n2\n3\n4\nfive\n", "one\n2\n3\n4\n5\n");
+        // Whatever the divergence setting, this is what apply asks about
+        let merging = roots.clone().with_divergence(Divergence::Merge);
+        assert_eq!(
+            diverged_destinations(&merging, &mappings, 1).unwrap(),
+            vec!["~/.bashrc".to_string()]
+        );
+        let mut declined = roots.clone();
+        declined.declined.insert("~/.bashrc".to_string());
+        assert!(
+            diverged_destinations(&declined, &mappings, 1)
+                .unwrap()
+                .is_empty()
+        );
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        let reason = actions[0].status.conflict_reason().unwrap();
+        assert!(
+            reason.contains("changed both here and in the source"),
+            "{}",
+            reason
+        );
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "one\n2\n3\n4\n5\n");
+
+        // The default replaces it, as before baselines existed
+        let roots = roots.with_divergence(Divergence::Overwrite);
+        assert_eq!(status_of(&roots, &mappings), DotfileStatus::Update);
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "1\n2\n3\n4\nfive\n");
+    }
+
+    #[test]
+    fn test_clean_merge_is_written_and_stays_until_a_side_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = applied_bashrc(dir.path(), Divergence::Merge);
+        let dst = Path::new(&roots.home).join(".bashrc");
+
+        edit(&roots, "1\n2\n3\n4\nfive\n", "one\n2\n3\n4\n5\n");
+        assert_eq!(status_of(&roots, &mappings), DotfileStatus::Merge);
+        // A dry run reports the merge without writing it
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, true, &Failpoints::default(), 1).unwrap();
+        assert_eq!(actions[0].status, DotfileStatus::Merge);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "one\n2\n3\n4\n5\n");
+
+        apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "one\n2\n3\n4\nfive\n");
+        // The local version is in the backups like any replaced destination
+        let store = BackupStore::new(roots.backup_dir.clone());
+        let last = store.manifests().unwrap().pop().unwrap();
+        let hash = last.targets[0].entries[0].hash.clone().unwrap();
+        assert_eq!(store.get(&hash).unwrap(), b"one\n2\n3\n4\n5\n");
+
+        // The merge result is what owl wrote: in sync until either side moves
+        assert_eq!(status_of(&roots, &mappings), DotfileStatus::UpToDate);
+
+        // Merged again against the source last applied, keeping the local edit
+        fs::write(roots.source_dir.join("bashrc"), "1\n2\nthree\n4\nfive\n").unwrap();
+        fs::write(&dst, "one\n2\n3\n4\nfive\nsix\n").unwrap();
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        assert_eq!(actions[0].status, DotfileStatus::Merge);
+        assert_eq!(
+            fs::read_to_string(&dst).unwrap(),
+            "one\n2\nthree\n4\nfive\nsix\n"
+        );
+    }
+
+    #[test]
+    fn test_conflicted_merge_saves_markers_and_leaves_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = applied_bashrc(dir.path(), Divergence::Merge);
+        let dst = Path::new(&roots.home).join(".bashrc");
+        let markers = format!("{}{}", dst.display(), MERGE_MARKERS_SUFFIX);
+        let baselines = BackupStore::new(roots.backup_dir.clone())
+            .baselines()
+            .unwrap();
+        edit(&roots, "1\n2\nsource\n4\n5\n", "1\n2\nlocal\n4\n5\n");
+
+        let conflict = DotfileStatus::MergeConflict {
+            conflicts: 1,
+            markers: None,
+        };
+        assert_eq!(status_of(&roots, &mappings), conflict);
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, true, &Failpoints::default(), 1).unwrap();
+        assert_eq!(actions[0].status, conflict);
+        assert!(!Path::new(&markers).exists());
+
+        let actions =
+            apply_dotfiles_in(&roots, &mappings, false, &Failpoints::default(), 1).unwrap();
+        assert_eq!(
+            actions[0].status,
+            DotfileStatus::MergeConflict {
+                conflicts: 1,
+                markers: Some(markers.clone()),
+            }
+        );
+        assert!(
+            actions[0]
+                .status
+                .conflict_reason()
+                .unwrap()
+                .ends_with(&format!("1 conflict(s), see {}", markers))
+        );
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "1\n2\nlocal\n4\n5\n");
+        assert_eq!(
+            fs::read_to_string(&markers).unwrap(),
+            "1\n2\n<<<<<<< ~/.bashrc\nlocal\n||||||| last applied\n3\n=======\n\
+             source\n>>>>>>> bashrc\n4\n5\n"
+        );
+        // Nothing was written, so the baseline still describes the last apply
+        assert_eq!(
+            BackupStore::new(roots.backup_dir.clone())
+                .baselines()
+                .unwrap(),
+            baselines
+        );
+    }
+
+    #[test]
+    fn test_binary_divergence_is_not_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = applied_bashrc(dir.path(), Divergence::Merge);
+        edit(&roots, "1\n2\n3\0\n4\n5\n", "one\n2\n3\n4\n5\n");
+        let reason = status_of(&roots, &mappings).conflict_reason().unwrap();
+        assert!(
+            reason.contains("binary or too large to merge"),
+            "{}",
+            reason
         );
+        assert!(reason.contains("--dotfile-diverged overwrite or keep"));
     }
 }
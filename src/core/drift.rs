@@ -0,0 +1,143 @@
+//! `owl status --names-only`: everything apply would change, one item per line
+//!
+//! Each line is `kind:name` (`install:ripgrep`, `dotfile:~/.gitconfig`,
+//! `service:sshd.service`, `env:EDITOR`), ordered by kind in the order apply
+//! works through them and by name within a kind, so the output of two runs
+//! over the same drift is identical.
+
+use crate::core::plan::ChangeKind;
+use crate::core::review::Review;
+use crate::core::services::ServiceChange;
+
+/// What kind of item is out of sync, in output order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Install,
+    Upgrade,
+    Remove,
+    Dotfile,
+    Service,
+    Env,
+}
+
+impl Kind {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Kind::Install => "install",
+            Kind::Upgrade => "upgrade",
+            Kind::Remove => "remove",
+            Kind::Dotfile => "dotfile",
+            Kind::Service => "service",
+            Kind::Env => "env",
+        }
+    }
+}
+
+/// The drift lines for the planned package and dotfile changes, the services
+/// that are not enabled or not running, and the env keys that would change
+///
+/// Services whose state could not be read are left out.
+pub fn lines(review: &Review, services: &[ServiceChange], env_keys: &[String]) -> Vec<String> {
+    let packages = review.packages.iter().map(|p| {
+        let kind = match p.change {
+            ChangeKind::Install => Kind::Install,
+            ChangeKind::Upgrade => Kind::Upgrade,
+            ChangeKind::Remove => Kind::Remove,
+        };
+        (kind, p.name.clone())
+    });
+    let dotfiles = review
+        .dotfiles
+        .iter()
+        .map(|d| (Kind::Dotfile, d.destination.clone()));
+    let services = services
+        .iter()
+        .filter(|s| s.error.is_none() && (s.enable || s.start))
+        .map(|s| (Kind::Service, s.service.clone()));
+    let env = env_keys.iter().map(|key| (Kind::Env, key.clone()));
+
+    let mut items: Vec<(Kind, String)> = packages
+        .chain(dotfiles)
+        .chain(services)
+        .chain(env)
+        .collect();
+    items.sort();
+    items.dedup();
+    items
+        .into_iter()
+        .map(|(kind, name)| format!("{}:{}", kind.prefix(), name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::plan::PackageChange;
+    use crate::core::review::DotfileChange;
+
+    fn package(name: &str, change: ChangeKind) -> PackageChange {
+        PackageChange {
+            name: name.to_string(),
+            change,
+            repo: None,
+            reboot_advised: false,
+            download_size: None,
+            installed_size: None,
+            services: Vec::new(),
+        }
+    }
+
+    fn dotfile(destination: &str) -> DotfileChange {
+        DotfileChange {
+            destination: destination.to_string(),
+            source: "src".to_string(),
+            create: false,
+        }
+    }
+
+    fn service(name: &str, enable: bool, start: bool, error: Option<&str>) -> ServiceChange {
+        ServiceChange {
+            service: name.to_string(),
+            enable,
+            start,
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_mixed_drift_is_prefixed_and_ordered() {
+        let review = Review {
+            packages: vec![
+                package("zoxide", ChangeKind::Install),
+                package("bat", ChangeKind::Remove),
+                package("linux", ChangeKind::Upgrade),
+                package("fd", ChangeKind::Install),
+            ],
+            dotfiles: vec![dotfile("~/.gitconfig"), dotfile("~/.config/nvim")],
+        };
+        let services = [
+            service("sshd.service", true, true, None),
+            service("docker.service", false, true, None),
+            service("cups.service", false, false, None),
+            service("bluetooth.service", true, false, Some("no systemd")),
+        ];
+        let env = ["PAGER".to_string(), "EDITOR".to_string()];
+
+        assert_eq!(
+            lines(&review, &services, &env),
+            vec![
+                "install:fd",
+                "install:zoxide",
+                "upgrade:linux",
+                "remove:bat",
+                "dotfile:~/.config/nvim",
+                "dotfile:~/.gitconfig",
+                "service:docker.service",
+                "service:sshd.service",
+                "env:EDITOR",
+                "env:PAGER",
+            ]
+        );
+        assert!(lines(&Review::default(), &services[2..3], &[]).is_empty());
+    }
+}
@@ -1,84 +1,899 @@
+use crate::core::events::{EventSink, OwlEvent};
 use anyhow::{Result, anyhow};
 use std::collections::HashMap;
-use std::env as std_env;
 use std::fs;
 use std::path::Path;
 
 /// Get the Owl directory path
 fn owl_dir() -> Result<std::path::PathBuf> {
-    let home = std_env::var("HOME").map_err(|_| anyhow!("HOME environment variable not set"))?;
-    Ok(Path::new(&home).join(crate::internal::constants::OWL_DIR))
+    crate::internal::environment::get().owl_dir()
 }
 
-/// Get bash environment file path
-fn env_file_bash() -> Result<std::path::PathBuf> {
-    Ok(owl_dir()?.join(crate::internal::constants::ENV_BASH_FILE))
+/// Shell an env file is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Fish,
 }
 
-/// Get fish environment file path
-fn env_file_fish() -> Result<std::path::PathBuf> {
-    Ok(owl_dir()?.join(crate::internal::constants::ENV_FISH_FILE))
+impl Shell {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "bash" => Ok(Shell::Bash),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(anyhow!("Unknown shell '{}', expected bash or fish", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Fish => "fish",
+        }
+    }
+
+    /// Shell whose env file a login shell path (`$SHELL`) sources
+    ///
+    /// Anything other than fish reads the POSIX `export` file.
+    pub fn from_login_shell(path: &str) -> Self {
+        match Path::new(path).file_name().and_then(|name| name.to_str()) {
+            Some("fish") => Shell::Fish,
+            _ => Shell::Bash,
+        }
+    }
+}
+
+/// Comment in an env file listing variables owl exported before but no longer does
+const REMOVED_MARKER: &str = "# owl-removed:";
+
+/// An exported variable, optionally limited to one shell (`:env [shell=fish]`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+    pub shell: Option<Shell>,
+}
+
+impl EnvVar {
+    /// Whether the variable belongs in `shell`'s env file
+    pub fn applies_to(&self, shell: Shell) -> bool {
+        self.shell.is_none_or(|only| only == shell)
+    }
 }
 
-pub fn collect_all_env_vars(config: &crate::core::config::Config) -> Vec<(String, String)> {
-    let mut vars: HashMap<String, String> = HashMap::new();
+pub fn collect_all_env_vars(config: &crate::core::config::Config) -> Vec<EnvVar> {
+    let mut vars: HashMap<String, (String, Option<Shell>)> = HashMap::new();
     // Global first
     for (k, v) in &config.env_vars {
-        vars.insert(k.clone(), v.clone());
+        vars.insert(k.clone(), (v.clone(), None));
     }
     // Package-level, override globals
     for pkg in config.packages.values() {
         for (k, v) in &pkg.env_vars {
-            vars.insert(k.clone(), v.clone());
+            vars.insert(k.clone(), (v.clone(), pkg.env_shells.get(k).copied()));
         }
     }
-    let mut sorted_environment_vars: Vec<(String, String)> = vars.into_iter().collect();
-    sorted_environment_vars.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut sorted_environment_vars: Vec<EnvVar> = vars
+        .into_iter()
+        .map(|(key, (value, shell))| EnvVar { key, value, shell })
+        .collect();
+    sorted_environment_vars.sort_by(|a, b| a.key.cmp(&b.key));
     sorted_environment_vars
 }
 
+/// Where a variable's value is read from instead of being written out
+///
+/// `@env TOKEN <file:~/.secrets/token>` exports the file's content and
+/// `@env TOKEN <cmd:pass show token>` what the command prints, both read when
+/// the env files are written and with trailing newlines trimmed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueSource<'a> {
+    /// A file; `~/` is the home directory, relative paths are under `~/.owl`
+    File(&'a str),
+    /// A command run with `sh -c`, skipped under `--safe`
+    Command(&'a str),
+}
+
+impl<'a> ValueSource<'a> {
+    /// The source a `<file:...>` or `<cmd:...>` value names
+    pub fn parse(value: &'a str) -> Option<Self> {
+        let inner = value.strip_prefix('<')?.strip_suffix('>')?;
+        if let Some(path) = inner.strip_prefix("file:") {
+            Some(ValueSource::File(path.trim()))
+        } else {
+            inner
+                .strip_prefix("cmd:")
+                .map(|command| ValueSource::Command(command.trim()))
+        }
+    }
+
+    /// Read the value; `dir` is the owl directory
+    fn read(self, dir: &Path) -> Result<String> {
+        let raw = match self {
+            ValueSource::File(path) => {
+                let path = match path.strip_prefix("~/") {
+                    Some(rest) => crate::internal::environment::get().home()?.join(rest),
+                    None => dir.join(path),
+                };
+                fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?
+            }
+            ValueSource::Command(command) => {
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(std::process::Stdio::null())
+                    .output()
+                    .map_err(|e| anyhow!("cannot run '{}': {}", command, e))?;
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "'{}' failed ({}): {}",
+                        command,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                String::from_utf8(output.stdout)
+                    .map_err(|_| anyhow!("'{}' printed invalid UTF-8", command))?
+            }
+        };
+        Ok(raw.trim_end_matches(['\n', '\r']).to_string())
+    }
+}
+
+/// `vars` with their `<file:...>` and `<cmd:...>` values read
+///
+/// With `safe`, commands are not run: such a variable keeps the value
+/// `previous` finds in the env file it was written to, or is left out, and a
+/// warning says which. Any source that cannot be read is an error naming the
+/// variable, so nothing is written with a missing secret.
+pub fn resolve_values(
+    vars: &[EnvVar],
+    dir: &Path,
+    safe: bool,
+    previous: &dyn Fn(&EnvVar) -> Option<String>,
+) -> Result<(Vec<EnvVar>, Vec<String>)> {
+    let mut resolved = Vec::with_capacity(vars.len());
+    let mut warnings = Vec::new();
+    for var in vars {
+        let value = match ValueSource::parse(&var.value) {
+            None => var.value.clone(),
+            Some(ValueSource::Command(_)) if safe => {
+                let kept = previous(var);
+                warnings.push(format!(
+                    "{} {} not run under --safe; {}",
+                    var.key,
+                    var.value,
+                    match kept {
+                        Some(_) => "keeping the exported value",
+                        None => "not exported",
+                    }
+                ));
+                match kept {
+                    Some(value) => value,
+                    None => continue,
+                }
+            }
+            Some(source) => source
+                .read(dir)
+                .map_err(|e| anyhow!("Failed to read {} from {}: {}", var.key, var.value, e))?,
+        };
+        resolved.push(EnvVar {
+            value,
+            ..var.clone()
+        });
+    }
+    Ok((resolved, warnings))
+}
+
+/// Variables a login sets up that a config rarely means to replace
+const DANGEROUS_VARS: &[&str] = &["PATH", "HOME", "SHELL", "USER", "LD_PRELOAD"];
+
+/// Warnings for exported variables that can break the user's shell
+///
+/// `PATH` is fine when its value keeps the inherited one (`PATH+=DIR`, or `$PATH`
+/// written out); the others always warn. `--allow-dangerous-env` silences these.
+pub fn dangerous_env_warnings(vars: &[EnvVar]) -> Vec<String> {
+    vars.iter()
+        .filter(|var| DANGEROUS_VARS.contains(&var.key.as_str()))
+        .filter_map(|var| match var.key.as_str() {
+            "PATH" if keeps_inherited(&var.value, "PATH") => None,
+            "PATH" => Some(format!(
+                "PATH={} replaces the whole search path; append with PATH+=DIR or include $PATH \
+                 (--allow-dangerous-env to keep it)",
+                var.value
+            )),
+            "LD_PRELOAD" => Some(format!(
+                "LD_PRELOAD={} loads into every program the shell starts \
+                 (--allow-dangerous-env if intended)",
+                var.value
+            )),
+            key => Some(format!(
+                "{}={} overrides what the login sets for {} (--allow-dangerous-env if intended)",
+                key, var.value, key
+            )),
+        })
+        .collect()
+}
+
+/// Whether `value` expands `$key` or `${key}`
+fn keeps_inherited(value: &str, key: &str) -> bool {
+    value.contains(&format!("${{{}}}", key))
+        || value.match_indices(&format!("${}", key)).any(|(at, m)| {
+            !value[at + m.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+/// Export env vars, or report the plan when dry running
+///
+/// With `diff_context` set, a diff of the env files against what would be
+/// written is emitted first. In a shared home the variables go to this host's
+/// env files behind a dispatcher (see `dispatcher_script`).
 pub fn apply_environment_variables(
     config: &crate::core::config::Config,
     dry_run: bool,
+    diff_context: Option<usize>,
+    safe: bool,
+    sink: &mut dyn EventSink,
 ) -> Result<()> {
     let vars = collect_all_env_vars(config);
     if vars.is_empty() {
         return Ok(());
     }
+    let host = crate::core::shared_home::host(config)?;
+    let host = host.as_deref();
+    let dir = owl_dir()?;
+    // Values read from files and commands are compared like written ones,
+    // but a plain dry run lists them unread
+    let read = |sink: &mut dyn EventSink| -> Result<Vec<EnvVar>> {
+        let (vars, warnings) = resolve_against_files(&vars, &dir, host, safe)?;
+        for warning in warnings {
+            sink.emit(OwlEvent::Warning(warning));
+        }
+        Ok(vars)
+    };
+
+    let mut resolved = None;
+    if let Some(context) = diff_context {
+        let vars = resolved.insert(read(sink)?);
+        let diff = diff_env_files(&dir, vars, host, context)?;
+        sink.emit(OwlEvent::EnvDiff { diff });
+    }
 
     if dry_run {
-        println!("  {} Plan:", crate::internal::color::blue("info:"));
-        for (k, v) in &vars {
-            println!(
-                "    ✓ Would export {}={} (shells)",
-                crate::internal::color::yellow(k),
-                crate::internal::color::green(v)
-            );
-        }
+        sink.emit(OwlEvent::EnvPlanned { vars });
         return Ok(());
     }
 
-    // Write bash
-    let bash_path = env_file_bash()?;
-    let mut bash = String::new();
-    for (k, v) in &vars {
-        bash.push_str(&format!("export {}=\"{}\"\n", k, v));
+    let vars = match resolved {
+        Some(vars) => vars,
+        None => read(sink)?,
+    };
+    let changed = write_env_files(&dir, &vars, host)?;
+    sink.emit(OwlEvent::EnvExported { changed });
+    Ok(())
+}
+
+/// `resolve_values`, keeping under `--safe` what the env files export
+fn resolve_against_files(
+    vars: &[EnvVar],
+    dir: &Path,
+    host: Option<&str>,
+    safe: bool,
+) -> Result<(Vec<EnvVar>, Vec<String>)> {
+    let previous = |var: &EnvVar| {
+        let shell = var.shell.unwrap_or(Shell::Bash);
+        let content = fs::read_to_string(target_path(dir, host, shell)).ok()?;
+        exported_values(&content, shell).remove(&var.key)
+    };
+    resolve_values(vars, dir, safe, &previous)
+}
+
+/// Keys `apply` would export with a new value, add or drop, sorted
+/// (`status --names-only`)
+pub fn pending_keys(config: &crate::core::config::Config, safe: bool) -> Result<Vec<String>> {
+    let vars = collect_all_env_vars(config);
+    if vars.is_empty() {
+        return Ok(Vec::new());
     }
-    fs::write(&bash_path, bash)
-        .map_err(|e| anyhow!("Failed to write {}: {}", bash_path.display(), e))?;
+    let host = crate::core::shared_home::host(config)?;
+    let dir = owl_dir()?;
+    let (vars, _) = resolve_against_files(&vars, &dir, host.as_deref(), safe)?;
+    Ok(changed_keys(&dir, &vars, host.as_deref()))
+}
 
-    // Write fish
-    let fish_path = env_file_fish()?;
-    let mut fish = String::new();
-    for (k, v) in &vars {
-        fish.push_str(&format!("set -x {} \"{}\"\n", k, v));
+/// Keys whose value in the env files of `dir` differs from `vars`, including
+/// keys a file exports that `vars` no longer has, sorted
+fn changed_keys(dir: &Path, vars: &[EnvVar], host: Option<&str>) -> Vec<String> {
+    let mut changed = std::collections::BTreeSet::new();
+    for shell in [Shell::Bash, Shell::Fish] {
+        let content = fs::read_to_string(target_path(dir, host, shell)).unwrap_or_default();
+        let mut exported = exported_values(&content, shell);
+        for var in vars.iter().filter(|v| v.applies_to(shell)) {
+            if exported.remove(&var.key).as_ref() != Some(&var.value) {
+                changed.insert(var.key.clone());
+            }
+        }
+        changed.extend(exported.into_keys());
     }
-    fs::write(&fish_path, fish)
-        .map_err(|e| anyhow!("Failed to write {}: {}", fish_path.display(), e))?;
+    changed.into_iter().collect()
+}
 
-    println!(
-        "  {} Environment exported (bash, fish)",
-        crate::internal::color::green("⸎")
-    );
-    Ok(())
+/// Env file content for `shell`, one line per variable that applies to it
+pub fn render_env_content(vars: &[EnvVar], shell: Shell) -> String {
+    let mut content = String::new();
+    for var in vars.iter().filter(|v| v.applies_to(shell)) {
+        match shell {
+            Shell::Bash => content.push_str(&format!("export {}=\"{}\"\n", var.key, var.value)),
+            Shell::Fish => content.push_str(&format!("set -x {} \"{}\"\n", var.key, var.value)),
+        }
+    }
+    content
+}
+
+/// Keys exported by an env file, and the keys its removed marker lists
+pub fn parse_env_keys(content: &str, shell: Shell) -> (Vec<String>, Vec<String>) {
+    let mut exported = Vec::new();
+    let mut removed = Vec::new();
+    for line in content.lines() {
+        if let Some(keys) = line.strip_prefix(REMOVED_MARKER) {
+            removed.extend(keys.split_whitespace().map(str::to_string));
+            continue;
+        }
+        let key = match shell {
+            Shell::Bash => line
+                .strip_prefix("export ")
+                .and_then(|rest| rest.split_once('='))
+                .map(|(key, _)| key),
+            Shell::Fish => line
+                .strip_prefix("set -x ")
+                .and_then(|rest| rest.split_once(' '))
+                .map(|(key, _)| key),
+        };
+        exported.extend(key.map(str::to_string));
+    }
+    (exported, removed)
+}
+
+/// The values an env file exports, by key
+fn exported_values(content: &str, shell: Shell) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| match shell {
+            Shell::Bash => line.strip_prefix("export ")?.split_once('='),
+            Shell::Fish => line.strip_prefix("set -x ")?.split_once(' '),
+        })
+        .filter_map(|(key, value)| {
+            let value = value.strip_prefix('"')?.strip_suffix('"')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Variables added and dropped between two generations of an env file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvComparison {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl EnvComparison {
+    /// Compare the current file content against the variables about to be written
+    ///
+    /// Keys the old file already listed as removed stay removed until exported again,
+    /// so sessions that missed a reload still get them unset.
+    pub fn between(old_content: &str, vars: &[EnvVar], shell: Shell) -> Self {
+        let (old, previously_removed) = parse_env_keys(old_content, shell);
+        let new: Vec<&str> = vars
+            .iter()
+            .filter(|v| v.applies_to(shell))
+            .map(|v| v.key.as_str())
+            .collect();
+        let mut removed: Vec<String> = old
+            .iter()
+            .chain(&previously_removed)
+            .filter(|key| !new.contains(&key.as_str()))
+            .cloned()
+            .collect();
+        removed.sort();
+        removed.dedup();
+        let mut added: Vec<String> = new
+            .iter()
+            .filter(|key| !old.iter().any(|k| k == *key))
+            .map(|key| key.to_string())
+            .collect();
+        added.sort();
+        Self { added, removed }
+    }
+}
+
+/// Env file content including the marker for removed variables
+fn render_env_file(old_content: &str, vars: &[EnvVar], shell: Shell) -> String {
+    let mut content = render_env_content(vars, shell);
+    let comparison = EnvComparison::between(old_content, vars, shell);
+    if !comparison.removed.is_empty() {
+        content.push_str(&format!(
+            "{} {}\n",
+            REMOVED_MARKER,
+            comparison.removed.join(" ")
+        ));
+    }
+    content
+}
+
+/// Commands that bring a running shell in line with its env file
+///
+/// Removed variables are unset, then the file is sourced again for the rest.
+pub fn reload_script(shell: Shell, env_file: &Path, comparison: &EnvComparison) -> String {
+    let mut script = String::new();
+    match shell {
+        Shell::Bash => {
+            if !comparison.removed.is_empty() {
+                script.push_str(&format!("unset {}\n", comparison.removed.join(" ")));
+            }
+            script.push_str(&format!(". \"{}\"\n", env_file.display()));
+        }
+        Shell::Fish => {
+            for key in &comparison.removed {
+                script.push_str(&format!("set -e {}\n", key));
+            }
+            script.push_str(&format!("source \"{}\"\n", env_file.display()));
+        }
+    }
+    script
+}
+
+/// `eval`-able commands reloading the env file for the shell in `$SHELL`
+///
+/// Only removed variables still set in the calling session are unset.
+pub fn reload_current_shell() -> Result<String> {
+    let env = crate::internal::environment::get();
+    let shell = Shell::from_login_shell(env.shell.as_deref().unwrap_or("bash"));
+    let dir = owl_dir()?;
+    // A shared home keeps the variables, and their removed marker, per host
+    let path = match env.hostname() {
+        Ok(host) if host_env_file(&dir, shell, host).exists() => host_env_file(&dir, shell, host),
+        _ => env_file(&dir, shell),
+    };
+    let content = if path.exists() {
+        fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?
+    } else {
+        String::new()
+    };
+    let (_, removed) = parse_env_keys(&content, shell);
+    let comparison = EnvComparison {
+        added: Vec::new(),
+        removed: removed
+            .into_iter()
+            .filter(|key| std::env::var_os(key).is_some())
+            .collect(),
+    };
+    Ok(reload_script(shell, &path, &comparison))
+}
+
+fn env_file(dir: &Path, shell: Shell) -> std::path::PathBuf {
+    dir.join(match shell {
+        Shell::Bash => crate::internal::constants::ENV_BASH_FILE,
+        Shell::Fish => crate::internal::constants::ENV_FISH_FILE,
+    })
+}
+
+/// `env.<host>.sh` or `env.<host>.fish`: one host's variables in a shared home
+fn host_env_file(dir: &Path, shell: Shell, host: &str) -> std::path::PathBuf {
+    let extension = match shell {
+        Shell::Bash => "sh",
+        Shell::Fish => "fish",
+    };
+    dir.join(format!("env.{}.{}", host, extension))
+}
+
+/// What goes in `env.sh`/`env.fish` in a shared home: source the env file of
+/// whichever host the shell runs on
+///
+/// The host comes from `hostname`, falling back to `/etc/hostname`, which is
+/// where owl reads it from.
+pub fn dispatcher_script(dir: &Path, shell: Shell) -> String {
+    let dir = dir.display();
+    match shell {
+        Shell::Bash => format!(
+            "# owl shared home: sources this host's env file\n\
+             __owl_env=\"{dir}/env.$(hostname 2>/dev/null || cat /etc/hostname).sh\"\n\
+             [ -r \"$__owl_env\" ] && . \"$__owl_env\"\n\
+             unset __owl_env\n"
+        ),
+        Shell::Fish => format!(
+            "# owl shared home: sources this host's env file\n\
+             set -l __owl_host (hostname 2>/dev/null; or cat /etc/hostname)\n\
+             test -r \"{dir}/env.$__owl_host.fish\"; and source \"{dir}/env.$__owl_host.fish\"\n"
+        ),
+    }
+}
+
+/// The env file holding the variables for `shell`: this host's in a shared home
+fn target_path(dir: &Path, host: Option<&str>, shell: Shell) -> std::path::PathBuf {
+    match host {
+        Some(host) => host_env_file(dir, shell, host),
+        None => env_file(dir, shell),
+    }
+}
+
+/// The files written for `shell` with their new content: the env file, or in a
+/// shared home (`host` set) the host's env file and the dispatcher
+fn env_targets(
+    dir: &Path,
+    vars: &[EnvVar],
+    host: Option<&str>,
+    shell: Shell,
+) -> Vec<(std::path::PathBuf, String)> {
+    let path = target_path(dir, host, shell);
+    let current = fs::read_to_string(&path).unwrap_or_default();
+    let mut targets = vec![(path, render_env_file(&current, vars, shell))];
+    if host.is_some() {
+        targets.push((env_file(dir, shell), dispatcher_script(dir, shell)));
+    }
+    targets
+}
+
+/// Unified diff of the env files in `dir` against the content `vars` renders to
+///
+/// Missing files diff as empty; returns an empty string when nothing would change.
+pub fn diff_env_files(
+    dir: &Path,
+    vars: &[EnvVar],
+    host: Option<&str>,
+    context: usize,
+) -> Result<String> {
+    let mut out = String::new();
+    for shell in [Shell::Bash, Shell::Fish] {
+        for (path, content) in env_targets(dir, vars, host, shell) {
+            let current = if path.exists() {
+                fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?
+            } else {
+                String::new()
+            };
+            let label = path.display().to_string();
+            out.push_str(&crate::core::diff::unified_diff(
+                &current, &content, &label, &label, context,
+            ));
+        }
+    }
+    Ok(out)
+}
+
+/// Write the bash and fish env files into `dir`, returning whether any changed
+fn write_env_files(dir: &Path, vars: &[EnvVar], host: Option<&str>) -> Result<bool> {
+    let mut changed = false;
+    for shell in [Shell::Bash, Shell::Fish] {
+        for (path, content) in env_targets(dir, vars, host, shell) {
+            changed |= write_if_changed(&path, &content)?;
+        }
+    }
+    Ok(changed)
+}
+
+/// Write `content` unless the file already holds exactly that, so its mtime only moves on change
+fn write_if_changed(path: &Path, content: &str) -> Result<bool> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(false);
+    }
+    fs::write(path, content).map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<EnvVar> {
+        pairs
+            .iter()
+            .map(|(k, v)| EnvVar {
+                key: k.to_string(),
+                value: v.to_string(),
+                shell: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_env_leaves_files_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = vars(&[("EDITOR", "nvim")]);
+        assert!(write_env_files(dir.path(), &env, None).unwrap());
+
+        let bash = dir.path().join(crate::internal::constants::ENV_BASH_FILE);
+        let fish = dir.path().join(crate::internal::constants::ENV_FISH_FILE);
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        for path in [&bash, &fish] {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+
+        assert!(!write_env_files(dir.path(), &env, None).unwrap());
+        assert_eq!(fs::metadata(&bash).unwrap().modified().unwrap(), old);
+        assert_eq!(fs::metadata(&fish).unwrap().modified().unwrap(), old);
+    }
+
+    #[test]
+    fn test_diff_env_shows_added_export() {
+        let dir = tempfile::tempdir().unwrap();
+        write_env_files(dir.path(), &vars(&[("EDITOR", "nvim")]), None).unwrap();
+        let next = vars(&[("EDITOR", "nvim"), ("NEW", "1")]);
+
+        let diff =
+            diff_env_files(dir.path(), &next, None, crate::core::diff::DEFAULT_CONTEXT).unwrap();
+        assert!(diff.lines().any(|l| l == "+export NEW=\"1\""));
+        assert!(diff.lines().any(|l| l == "+set -x NEW \"1\""));
+        assert!(!diff.lines().any(|l| l.starts_with("-export")));
+
+        write_env_files(dir.path(), &next, None).unwrap();
+        assert!(
+            diff_env_files(dir.path(), &next, None, 3)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_changed_env_rewrites_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_env_files(dir.path(), &vars(&[("EDITOR", "nvim")]), None).unwrap();
+        assert!(write_env_files(dir.path(), &vars(&[("EDITOR", "vim")]), None).unwrap());
+        let bash =
+            fs::read_to_string(dir.path().join(crate::internal::constants::ENV_BASH_FILE)).unwrap();
+        assert_eq!(bash, "export EDITOR=\"vim\"\n");
+    }
+
+    #[test]
+    fn test_removed_vars_are_marked_until_exported_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let bash = dir.path().join(crate::internal::constants::ENV_BASH_FILE);
+        write_env_files(dir.path(), &vars(&[("EDITOR", "nvim"), ("OLD", "1")]), None).unwrap();
+        write_env_files(dir.path(), &vars(&[("EDITOR", "nvim")]), None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&bash).unwrap(),
+            "export EDITOR=\"nvim\"\n# owl-removed: OLD\n"
+        );
+        // A later apply keeps the marker so unreloaded sessions still drop OLD
+        write_env_files(dir.path(), &vars(&[("EDITOR", "vim")]), None).unwrap();
+        assert!(
+            fs::read_to_string(&bash)
+                .unwrap()
+                .contains("# owl-removed: OLD")
+        );
+        write_env_files(dir.path(), &vars(&[("EDITOR", "vim"), ("OLD", "2")]), None).unwrap();
+        assert!(!fs::read_to_string(&bash).unwrap().contains("owl-removed"));
+    }
+
+    #[test]
+    fn test_changed_keys_against_written_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let current = vars(&[("EDITOR", "nvim"), ("PAGER", "less"), ("OLD", "1")]);
+        // Nothing written yet: every key is new
+        assert_eq!(
+            changed_keys(dir.path(), &current, None),
+            vec!["EDITOR", "OLD", "PAGER"]
+        );
+        write_env_files(dir.path(), &current, None).unwrap();
+        assert!(changed_keys(dir.path(), &current, None).is_empty());
+
+        let next = vars(&[("EDITOR", "vim"), ("PAGER", "less"), ("BROWSER", "firefox")]);
+        assert_eq!(
+            changed_keys(dir.path(), &next, None),
+            vec!["BROWSER", "EDITOR", "OLD"]
+        );
+    }
+
+    #[test]
+    fn test_shared_home_sources_each_hosts_env_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let home = tempfile::tempdir().unwrap();
+        let owl = home.path().join(".owl");
+        fs::create_dir_all(&owl).unwrap();
+        write_env_files(&owl, &vars(&[("EDITOR", "nvim")]), Some("alpha")).unwrap();
+        write_env_files(
+            &owl,
+            &vars(&[("EDITOR", "vim"), ("PAGER", "less")]),
+            Some("beta"),
+        )
+        .unwrap();
+
+        assert!(owl.join("env.alpha.sh").exists());
+        assert!(owl.join("env.beta.fish").exists());
+        let dispatcher = fs::read_to_string(owl.join("env.sh")).unwrap();
+        assert_eq!(dispatcher, dispatcher_script(&owl, Shell::Bash));
+        assert!(!dispatcher.contains("EDITOR"));
+        // Rewriting the same host's variables touches nothing
+        assert!(!write_env_files(&owl, &vars(&[("EDITOR", "nvim")]), Some("alpha")).unwrap());
+
+        for (host, expected) in [("alpha", "nvim:"), ("beta", "vim:less")] {
+            let bin = home.path().join(format!("bin-{}", host));
+            fs::create_dir_all(&bin).unwrap();
+            let hostname = bin.join("hostname");
+            fs::write(&hostname, format!("#!/bin/sh\necho {}\n", host)).unwrap();
+            fs::set_permissions(&hostname, fs::Permissions::from_mode(0o755)).unwrap();
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!(
+                    ". \"{}\"; echo \"$EDITOR:$PAGER\"",
+                    owl.join("env.sh").display()
+                ))
+                .env("PATH", format!("{}:/usr/bin:/bin", bin.display()))
+                .env_remove("EDITOR")
+                .env_remove("PAGER")
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), expected);
+        }
+    }
+
+    #[test]
+    fn test_shared_home_diff_covers_host_file_and_dispatcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let diff =
+            diff_env_files(dir.path(), &vars(&[("EDITOR", "nvim")]), Some("alpha"), 3).unwrap();
+        assert!(diff.contains("env.alpha.sh"));
+        assert!(diff.contains("+export EDITOR=\"nvim\""));
+        assert!(diff.contains("+# owl shared home"));
+        assert!(!dir.path().join("env.sh").exists());
+    }
+
+    #[test]
+    fn test_reload_snippets() {
+        let old = "export EDITOR=\"nvim\"\nexport OLD=\"1\"\nexport GONE=\"x\"\n";
+        let comparison =
+            EnvComparison::between(old, &vars(&[("EDITOR", "vim"), ("NEW", "1")]), Shell::Bash);
+        assert_eq!(
+            comparison,
+            EnvComparison {
+                added: vec!["NEW".to_string()],
+                removed: vec!["GONE".to_string(), "OLD".to_string()],
+            }
+        );
+
+        let bash = reload_script(Shell::Bash, Path::new("/home/me/.owl/env.sh"), &comparison);
+        assert_eq!(bash, "unset GONE OLD\n. \"/home/me/.owl/env.sh\"\n");
+        let fish = reload_script(
+            Shell::Fish,
+            Path::new("/home/me/.owl/env.fish"),
+            &comparison,
+        );
+        assert_eq!(
+            fish,
+            "set -e GONE\nset -e OLD\nsource \"/home/me/.owl/env.fish\"\n"
+        );
+        assert_eq!(
+            reload_script(Shell::Fish, Path::new("/e.fish"), &EnvComparison::default()),
+            "source \"/e.fish\"\n"
+        );
+        assert_eq!(Shell::from_login_shell("/usr/bin/fish"), Shell::Fish);
+        assert_eq!(Shell::from_login_shell("/bin/zsh"), Shell::Bash);
+    }
+
+    #[test]
+    fn test_shell_specific_var_only_in_matching_file() {
+        let config = crate::core::config::Config::parse(
+            "@package fish\n:env [shell=fish] fish_greeting=\n:env EDITOR=nvim\n",
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        write_env_files(dir.path(), &collect_all_env_vars(&config), None).unwrap();
+
+        let bash =
+            fs::read_to_string(dir.path().join(crate::internal::constants::ENV_BASH_FILE)).unwrap();
+        let fish =
+            fs::read_to_string(dir.path().join(crate::internal::constants::ENV_FISH_FILE)).unwrap();
+        assert_eq!(bash, "export EDITOR=\"nvim\"\n");
+        assert_eq!(fish, "set -x EDITOR \"nvim\"\nset -x fish_greeting \"\"\n");
+    }
+
+    #[test]
+    fn test_file_values_are_read_and_compared() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("secrets")).unwrap();
+        fs::write(dir.path().join("secrets/token"), "abc123\n\n").unwrap();
+        let config = crate::core::config::Config::parse(
+            "@env TOKEN <file:secrets/token>\n@env EDITOR=nvim\n",
+        )
+        .unwrap();
+        let vars = collect_all_env_vars(&config);
+        assert_eq!(vars[1].value, "<file:secrets/token>");
+
+        let read = || resolve_values(&vars, dir.path(), false, &|_| None).unwrap();
+        let (resolved, warnings) = read();
+        assert!(warnings.is_empty());
+        assert_eq!(resolved[1].value, "abc123");
+        assert!(write_env_files(dir.path(), &resolved, None).unwrap());
+        assert!(!write_env_files(dir.path(), &read().0, None).unwrap());
+
+        // A new token is a change even though the config line is the same
+        fs::write(dir.path().join("secrets/token"), "def456").unwrap();
+        assert!(write_env_files(dir.path(), &read().0, None).unwrap());
+        let bash =
+            fs::read_to_string(dir.path().join(crate::internal::constants::ENV_BASH_FILE)).unwrap();
+        assert!(bash.contains("export TOKEN=\"def456\"\n"), "{}", bash);
+
+        fs::remove_file(dir.path().join("secrets/token")).unwrap();
+        let err = resolve_values(&vars, dir.path(), false, &|_| None)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("Failed to read TOKEN from <file:secrets/token>: cannot read"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_command_values_and_safe_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let vars = vars(&[
+            ("TOKEN", "<cmd:printf 'from-pass\\n'>"),
+            ("OTHER", "<cmd: echo other >"),
+        ]);
+        let (resolved, _) = resolve_values(&vars, dir.path(), false, &|_| None).unwrap();
+        assert_eq!(resolved[0].value, "from-pass");
+        assert_eq!(resolved[1].value, "other");
+
+        // --safe keeps what the env file already exports, or leaves it out
+        let previous = |var: &EnvVar| (var.key == "TOKEN").then(|| "old".to_string());
+        let (resolved, warnings) = resolve_values(&vars, dir.path(), true, &previous).unwrap();
+        assert_eq!(resolved, self::vars(&[("TOKEN", "old")]));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[1].ends_with("not exported"), "{}", warnings[1]);
+
+        let failing = self::vars(&[("TOKEN", "<cmd:echo locked >&2; exit 3>")]);
+        let err = resolve_values(&failing, dir.path(), false, &|_| None)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Failed to read TOKEN"), "{}", err);
+        assert!(err.ends_with("locked"), "{}", err);
+    }
+
+    #[test]
+    fn test_exported_values_read_back_rendered_files() {
+        let env = vars(&[("EDITOR", "nvim"), ("TOKEN", "a b=c")]);
+        for shell in [Shell::Bash, Shell::Fish] {
+            let values = exported_values(&render_env_content(&env, shell), shell);
+            assert_eq!(values["TOKEN"], "a b=c");
+            assert_eq!(values.len(), 2);
+        }
+        assert_eq!(
+            ValueSource::parse("<file: ~/t >"),
+            Some(ValueSource::File("~/t"))
+        );
+        assert_eq!(ValueSource::parse("<other:x>"), None);
+        assert_eq!(ValueSource::parse("plain"), None);
+    }
+
+    #[test]
+    fn test_dangerous_env_warnings() {
+        let config = crate::core::config::Config::parse(
+            "@env PATH=/opt/bin\n@env EDITOR=nvim\n@package x\n:env LD_PRELOAD=/lib/libx.so\n",
+        )
+        .unwrap();
+        let warnings = dangerous_env_warnings(&collect_all_env_vars(&config));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("LD_PRELOAD=/lib/libx.so"));
+        assert!(warnings[1].starts_with("PATH=/opt/bin replaces"));
+
+        let appended = crate::core::config::Config::parse("@env PATH+=/opt/bin\n").unwrap();
+        assert!(dangerous_env_warnings(&collect_all_env_vars(&appended)).is_empty());
+        for value in ["$HOME/bin:$PATH", "${PATH}:/opt/bin"] {
+            assert!(dangerous_env_warnings(&vars(&[("PATH", value)])).is_empty());
+        }
+        // $PATHS is a different variable
+        assert_eq!(
+            dangerous_env_warnings(&vars(&[("PATH", "$PATHS")])).len(),
+            1
+        );
+        assert_eq!(dangerous_env_warnings(&vars(&[("HOME", "/data")])).len(), 1);
+    }
 }
@@ -0,0 +1,278 @@
+//! Progress events emitted by core operations
+//!
+//! Core phases report what they do through an [`EventSink`] instead of printing,
+//! so the CLI is one subscriber among others (the terminal renderer lives in
+//! `cli::render`). Any `FnMut(OwlEvent)` closure is a sink.
+//!
+//! [`OwlEvent::to_json`] is the stable schema behind `owl apply --events-json`.
+
+use crate::core::dotfiles::{DotfileAction, DotfileMapping, DotfileStatus};
+use crate::core::env::EnvVar;
+use crate::core::services::{ServiceChange, ServiceResult};
+use serde_json::{Value, json};
+
+/// Version of the `--events-json` schema, bumped on incompatible changes
+///
+/// New event types and new fields do not bump it; consumers should ignore
+/// what they do not know.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Output sections of an apply run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPhase {
+    Packages,
+    Dotfiles,
+    System,
+}
+
+impl EventPhase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventPhase::Packages => "packages",
+            EventPhase::Dotfiles => "dotfiles",
+            EventPhase::System => "system",
+        }
+    }
+}
+
+/// Something a core operation did or is about to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwlEvent {
+    PhaseStarted(EventPhase),
+    PhaseFinished(EventPhase),
+    /// No dotfile mappings are configured
+    DotfilesEmpty,
+    /// All `count` mappings already match their sources
+    DotfilesUpToDate {
+        count: usize,
+    },
+    /// A mapping was analysed and, unless dry running, applied
+    DotfileActionCompleted {
+        action: DotfileAction,
+    },
+    DotfilesFinished {
+        up_to_date: usize,
+        dry_run: bool,
+    },
+    /// Problems found in a mapping's source before syncing; they stop the
+    /// sync when `strict`
+    DotfileSourceIssues {
+        mapping: DotfileMapping,
+        issues: Vec<String>,
+        strict: bool,
+    },
+    /// Another host sharing this home deployed different content to destinations
+    /// this run overwrites (`@option shared_home=true`)
+    SharedHomeConflict {
+        host: String,
+        applied_at: u64,
+        destinations: Vec<String>,
+    },
+    /// Destinations an earlier run deployed that no mapping covers any more;
+    /// they are left in place
+    DotfilesOrphaned {
+        destinations: Vec<String>,
+    },
+    /// A declared absent path was checked and, unless dry running, enforced
+    AbsentChecked {
+        entry: crate::core::absent::AbsentEntry,
+    },
+    /// The managed list was corrected for packages removed or replaced outside owl
+    StateReconciled {
+        changes: Vec<crate::core::reconcile::StateChange>,
+    },
+    PackageInstallStarted {
+        name: String,
+    },
+    /// `duration_ms` is only known for packages installed on their own
+    PackageInstallFinished {
+        name: String,
+        success: bool,
+        duration_ms: Option<u64>,
+    },
+    /// The package phases were skipped because the pacman database stayed
+    /// locked; the rest of the run went ahead
+    PackagesDeferred {
+        reason: String,
+    },
+    /// Dry run: services that would be enabled and started
+    ServicesPlanned {
+        services: Vec<String>,
+    },
+    /// Dry run with `--diff`: each service's current state against the config
+    ServiceChangesPlanned {
+        changes: Vec<ServiceChange>,
+    },
+    ServicesConfigured {
+        managed: usize,
+        result: ServiceResult,
+    },
+    /// All services were already enabled and running
+    ServicesVerified {
+        /// Enabled before owl saw them; owl never disables these
+        preexisting: Vec<String>,
+    },
+    /// Services owl enabled that left the config, disabled once confirmed
+    ServicesDisabled {
+        services: Vec<String>,
+    },
+    /// Dry run: variables that would be exported
+    EnvPlanned {
+        vars: Vec<EnvVar>,
+    },
+    /// Unified diff of the env files against what would be written (`--diff-env`)
+    EnvDiff {
+        diff: String,
+    },
+    /// `changed` is false when the env files already held these values
+    EnvExported {
+        changed: bool,
+    },
+    Warning(String),
+    Error(String),
+}
+
+impl OwlEvent {
+    /// One JSON object with `schema` and `type` keys plus the event's fields
+    pub fn to_json(&self) -> Value {
+        let (kind, mut fields) = match self {
+            OwlEvent::PhaseStarted(phase) => ("phase_started", json!({ "phase": phase.as_str() })),
+            OwlEvent::PhaseFinished(phase) => {
+                ("phase_finished", json!({ "phase": phase.as_str() }))
+            }
+            OwlEvent::DotfilesEmpty => ("dotfiles_empty", json!({})),
+            OwlEvent::DotfilesUpToDate { count } => {
+                ("dotfiles_up_to_date", json!({ "count": count }))
+            }
+            OwlEvent::DotfileActionCompleted { action } => {
+                let (status, reason) = match &action.status {
+                    DotfileStatus::Create => ("create", None),
+                    DotfileStatus::Update => ("update", None),
+                    DotfileStatus::UpToDate => ("up_to_date", None),
+                    DotfileStatus::Merge => ("merge", None),
+                    DotfileStatus::Conflict(_) | DotfileStatus::MergeConflict { .. } => {
+                        ("conflict", action.status.conflict_reason())
+                    }
+                };
+                let mut fields = json!({
+                    "source": action.mapping.source,
+                    "destination": action.mapping.destination,
+                    "status": status,
+                });
+                if let Some(reason) = reason {
+                    fields["reason"] = json!(reason);
+                }
+                ("dotfile_action", fields)
+            }
+            OwlEvent::DotfilesFinished {
+                up_to_date,
+                dry_run,
+            } => (
+                "dotfiles_finished",
+                json!({ "up_to_date": up_to_date, "dry_run": dry_run }),
+            ),
+            OwlEvent::DotfileSourceIssues {
+                mapping,
+                issues,
+                strict,
+            } => (
+                "dotfile_source_issues",
+                json!({
+                    "source": mapping.source,
+                    "destination": mapping.destination,
+                    "issues": issues,
+                    "strict": strict,
+                }),
+            ),
+            OwlEvent::SharedHomeConflict {
+                host,
+                applied_at,
+                destinations,
+            } => (
+                "shared_home_conflict",
+                json!({
+                    "host": host,
+                    "applied_at": applied_at,
+                    "destinations": destinations,
+                }),
+            ),
+            OwlEvent::DotfilesOrphaned { destinations } => {
+                ("dotfiles_orphaned", json!({ "destinations": destinations }))
+            }
+            OwlEvent::AbsentChecked { entry } => ("absent_checked", json!(entry)),
+            OwlEvent::StateReconciled { changes } => {
+                ("state_reconciled", json!({ "changes": changes }))
+            }
+            OwlEvent::PackageInstallStarted { name } => {
+                ("package_install_started", json!({ "name": name }))
+            }
+            OwlEvent::PackageInstallFinished {
+                name,
+                success,
+                duration_ms,
+            } => {
+                let mut fields = json!({ "name": name, "success": success });
+                if let Some(ms) = duration_ms {
+                    fields["duration_ms"] = json!(ms);
+                }
+                ("package_install_finished", fields)
+            }
+            OwlEvent::PackagesDeferred { reason } => {
+                ("packages_deferred", json!({ "reason": reason }))
+            }
+            OwlEvent::ServicesPlanned { services } => {
+                ("services_planned", json!({ "services": services }))
+            }
+            OwlEvent::ServiceChangesPlanned { changes } => {
+                ("service_changes_planned", json!({ "changes": changes }))
+            }
+            OwlEvent::ServicesConfigured { managed, result } => (
+                "services_configured",
+                json!({
+                    "managed": managed,
+                    "enabled": result.enabled_services,
+                    "started": result.started_services,
+                    "failed": result.failed_services,
+                    "preexisting": result.preexisting_services,
+                }),
+            ),
+            OwlEvent::ServicesVerified { preexisting } => {
+                ("services_verified", json!({ "preexisting": preexisting }))
+            }
+            OwlEvent::ServicesDisabled { services } => {
+                ("services_disabled", json!({ "services": services }))
+            }
+            OwlEvent::EnvPlanned { vars } => {
+                let vars: Vec<Value> = vars
+                    .iter()
+                    .map(|var| {
+                        json!({
+                            "key": var.key,
+                            "value": var.value,
+                            "shell": var.shell.map(|shell| shell.as_str()),
+                        })
+                    })
+                    .collect();
+                ("env_planned", json!({ "vars": vars }))
+            }
+            OwlEvent::EnvDiff { diff } => ("env_diff", json!({ "diff": diff })),
+            OwlEvent::EnvExported { changed } => ("env_exported", json!({ "changed": changed })),
+            OwlEvent::Warning(message) => ("warning", json!({ "message": message })),
+            OwlEvent::Error(message) => ("error", json!({ "message": message })),
+        };
+        fields["schema"] = json!(EVENT_SCHEMA_VERSION);
+        fields["type"] = json!(kind);
+        fields
+    }
+}
+
+/// Receiver for [`OwlEvent`]s
+pub trait EventSink {
+    fn emit(&mut self, event: OwlEvent);
+}
+
+impl<F: FnMut(OwlEvent)> EventSink for F {
+    fn emit(&mut self, event: OwlEvent) {
+        self(event)
+    }
+}
@@ -0,0 +1,325 @@
+//! Modes of the files and directories apply writes into dotfile destinations
+//!
+//! Which mode a written path gets, first match wins:
+//!
+//! 1. `[mode=600]` on the mapping (directories also get search permission
+//!    where they are readable, so `600` gives them `700`)
+//! 2. `[preserve-mode]` on the mapping: the source's mode
+//! 3. On update, the mode the destination had before
+//! 4. Under home, the default policy: `@option default_file_mode` and
+//!    `default_dir_mode`, or `600`/`700` for `[private]` mappings when unset.
+//!    Executable sources stay executable for whoever may read them.
+//! 5. The source's mode with the umask applied
+//!
+//! Hardlinked mappings share the source's inode and are never changed.
+
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Mode `[private]` files get when `default_file_mode` is unset
+pub const PRIVATE_FILE_MODE: u32 = 0o600;
+/// Mode `[private]` directories get when `default_dir_mode` is unset
+pub const PRIVATE_DIR_MODE: u32 = 0o700;
+
+/// `@option default_file_mode` and `default_dir_mode`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ModePolicy {
+    pub file: Option<u32>,
+    pub dir: Option<u32>,
+}
+
+/// Mode flags of one `:config` mapping
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MappingModes {
+    /// `[mode=600]`
+    pub mode: Option<u32>,
+    /// `[private]`: created with the private modes and checked for looser ones
+    pub private: bool,
+    /// `[preserve-mode]`: keep the source's mode
+    pub preserve: bool,
+}
+
+/// An octal mode such as `600` or `0644`
+pub fn parse_mode(value: &str) -> Result<u32> {
+    u32::from_str_radix(value.trim(), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| anyhow!("Invalid mode '{}', expected octal such as 600", value))
+}
+
+/// The process umask, read from `/proc` (`022` when unreadable)
+pub fn umask() -> u32 {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("Umask:"))
+                .and_then(|mask| u32::from_str_radix(mask.trim(), 8).ok())
+        })
+        .unwrap_or(0o022)
+}
+
+/// What is being written and what was there before
+#[derive(Debug, Clone, Copy)]
+pub struct Written {
+    pub dir: bool,
+    /// Mode of the source path
+    pub source: u32,
+    /// Mode of the destination before the write; `None` when created
+    pub existing: Option<u32>,
+    /// The destination is under home, where the default policy applies
+    pub in_home: bool,
+}
+
+/// The mode a written path gets, see the module docs
+pub fn resolve(modes: &MappingModes, policy: &ModePolicy, written: Written, umask: u32) -> u32 {
+    let source = written.source & 0o7777;
+    if let Some(mode) = modes.mode {
+        return if written.dir { searchable(mode) } else { mode };
+    }
+    if modes.preserve {
+        return source;
+    }
+    if let Some(existing) = written.existing {
+        return existing & 0o7777;
+    }
+    let default = match (written.dir, modes.private) {
+        (false, private) => policy.file.or(private.then_some(PRIVATE_FILE_MODE)),
+        (true, private) => policy.dir.or(private.then_some(PRIVATE_DIR_MODE)),
+    };
+    match default.filter(|_| written.in_home) {
+        Some(mode) if written.dir || source & 0o111 == 0 => mode,
+        Some(mode) => searchable(mode),
+        None => source & !umask,
+    }
+}
+
+/// `mode` with execute permission wherever it grants read
+fn searchable(mode: u32) -> u32 {
+    mode | ((mode & 0o444) >> 2)
+}
+
+/// The mode most permissive `[private]` files and directories may have
+pub fn private_limit(modes: &MappingModes, policy: &ModePolicy, dir: bool) -> u32 {
+    match (modes.mode, dir) {
+        (Some(mode), false) => mode,
+        (Some(mode), true) => searchable(mode),
+        (None, false) => policy.file.unwrap_or(PRIVATE_FILE_MODE),
+        (None, true) => policy.dir.unwrap_or(PRIVATE_DIR_MODE),
+    }
+}
+
+/// `base/rel`, or `base` itself for an empty `rel` (joining would add a
+/// trailing slash, which fails for files)
+fn under(base: &Path, rel: &Path) -> PathBuf {
+    if rel.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(rel)
+    }
+}
+
+/// Modes of `dst` and everything under it, by path relative to `dst`
+pub fn snapshot(dst: &Path) -> HashMap<PathBuf, u32> {
+    let mut modes = HashMap::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        let Ok(meta) = fs::symlink_metadata(under(dst, &rel)) else {
+            continue;
+        };
+        if meta.is_dir()
+            && let Ok(entries) = fs::read_dir(under(dst, &rel))
+        {
+            pending.extend(entries.flatten().map(|e| rel.join(e.file_name())));
+        }
+        if !meta.file_type().is_symlink() {
+            modes.insert(rel, meta.permissions().mode());
+        }
+    }
+    modes
+}
+
+/// Give every path written from `src` to `dst` its mode
+///
+/// `before` is the `snapshot` of `dst` taken before the write. Paths only
+/// `dst` has (`--no-dotfiles-delete`) are left alone.
+pub fn settle(
+    src: &Path,
+    dst: &Path,
+    modes: &MappingModes,
+    policy: &ModePolicy,
+    in_home: bool,
+    before: &HashMap<PathBuf, u32>,
+) -> Result<()> {
+    let umask = umask();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        let source = under(src, &rel);
+        let meta = fs::metadata(&source)
+            .map_err(|e| anyhow!("Failed to stat {}: {}", source.display(), e))?;
+        if meta.is_dir() {
+            for entry in fs::read_dir(&source)
+                .map_err(|e| anyhow!("Failed to read dir {}: {}", source.display(), e))?
+            {
+                let entry = entry
+                    .map_err(|e| anyhow!("Failed to read entry in {}: {}", source.display(), e))?;
+                pending.push(rel.join(entry.file_name()));
+            }
+        } else if !meta.is_file() {
+            continue;
+        }
+        let written = Written {
+            dir: meta.is_dir(),
+            source: meta.permissions().mode(),
+            existing: before.get(&rel).copied(),
+            in_home,
+        };
+        let target = under(dst, &rel);
+        let mode = resolve(modes, policy, written, umask);
+        if fs::symlink_metadata(&target).is_ok_and(|m| m.permissions().mode() & 0o7777 != mode) {
+            fs::set_permissions(&target, fs::Permissions::from_mode(mode))
+                .map_err(|e| anyhow!("Failed to set mode on {}: {}", target.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Paths at a `[private]` destination more permissive than its policy, with
+/// their modes
+pub fn too_open(dst: &Path, modes: &MappingModes, policy: &ModePolicy) -> Vec<(PathBuf, u32)> {
+    let mut open: Vec<(PathBuf, u32)> = snapshot(dst)
+        .into_iter()
+        .filter_map(|(rel, mode)| {
+            let path = under(dst, &rel);
+            let limit = private_limit(modes, policy, path.is_dir());
+            (mode & 0o777 & !limit != 0).then_some((path, mode & 0o7777))
+        })
+        .collect();
+    open.sort();
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(dir: bool, source: u32, existing: Option<u32>) -> Written {
+        Written {
+            dir,
+            source,
+            existing,
+            in_home: true,
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let policy = ModePolicy {
+            file: Some(0o640),
+            dir: Some(0o750),
+        };
+        let explicit = MappingModes {
+            mode: Some(0o600),
+            preserve: true,
+            ..Default::default()
+        };
+        let preserve = MappingModes {
+            preserve: true,
+            ..Default::default()
+        };
+        let plain = MappingModes::default();
+        let private = MappingModes {
+            private: true,
+            ..Default::default()
+        };
+        let umask = 0o022;
+
+        // [mode=] beats everything, on create and update
+        assert_eq!(
+            resolve(&explicit, &policy, written(false, 0o644, None), umask),
+            0o600
+        );
+        assert_eq!(
+            resolve(
+                &explicit,
+                &policy,
+                written(false, 0o644, Some(0o666)),
+                umask
+            ),
+            0o600
+        );
+        assert_eq!(
+            resolve(&explicit, &policy, written(true, 0o755, None), umask),
+            0o700
+        );
+        // [preserve-mode] keeps the source's mode
+        assert_eq!(
+            resolve(
+                &preserve,
+                &policy,
+                written(false, 0o644, Some(0o600)),
+                umask
+            ),
+            0o644
+        );
+        // Updates keep the destination's mode
+        assert_eq!(
+            resolve(&plain, &policy, written(false, 0o644, Some(0o600)), umask),
+            0o600
+        );
+        // Creates under home follow the policy, executables stay executable
+        assert_eq!(
+            resolve(&plain, &policy, written(false, 0o644, None), umask),
+            0o640
+        );
+        assert_eq!(
+            resolve(&plain, &policy, written(false, 0o755, None), umask),
+            0o750
+        );
+        assert_eq!(
+            resolve(&plain, &policy, written(true, 0o755, None), umask),
+            0o750
+        );
+        // [private] falls back to 600/700 without a policy
+        let none = ModePolicy::default();
+        assert_eq!(
+            resolve(&private, &none, written(false, 0o644, None), umask),
+            0o600
+        );
+        assert_eq!(
+            resolve(&private, &none, written(true, 0o755, None), umask),
+            0o700
+        );
+        assert_eq!(
+            resolve(&private, &policy, written(false, 0o644, None), umask),
+            0o640
+        );
+        // Without a policy, or outside home, the umask applies to the source's mode
+        assert_eq!(
+            resolve(&plain, &none, written(false, 0o666, None), umask),
+            0o644
+        );
+        assert_eq!(
+            resolve(&plain, &none, written(false, 0o777, None), 0o077),
+            0o700
+        );
+        let outside = Written {
+            in_home: false,
+            ..written(false, 0o666, None)
+        };
+        assert_eq!(resolve(&plain, &policy, outside, umask), 0o644);
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("600").unwrap(), 0o600);
+        assert_eq!(parse_mode("0755").unwrap(), 0o755);
+        assert!(parse_mode("800").is_err());
+        assert!(parse_mode("17777").is_err());
+        assert!(parse_mode("rw").is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Cheap forensics on dotfile destinations
+//!
+//! When a destination no longer matches its source it helps to know what
+//! changed it: a hand edit, the program rewriting its own config, or another
+//! sync tool. Everything here comes from file metadata, the apply history and,
+//! inside a git work tree, one `git log` call.
+
+use crate::core::dotfiles::{DotfileAction, DotfileRoots, Identity};
+use serde::Serialize;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Owner of a destination that is not the current user
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// What the destination's metadata says about its last change
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Forensics {
+    /// Newest modification below the destination, seconds since the Unix epoch
+    pub mtime: u64,
+    /// Seconds between the last recorded apply and `mtime`; negative if earlier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_last_apply: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    /// The destination was modified after its source
+    pub newer_than_source: bool,
+    /// `%h %s` of the last commit touching the destination
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_commit: Option<String>,
+}
+
+impl Forensics {
+    /// One short line per known fact, for terminal output
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let modified = crate::internal::time::format_datetime(self.mtime);
+        lines.push(match self.since_last_apply {
+            Some(delta) if delta > 0 => {
+                format!(
+                    "modified {} ({} after the last apply)",
+                    modified,
+                    crate::internal::time::format_age(delta)
+                )
+            }
+            Some(_) => format!("modified {} (before the last apply)", modified),
+            None => format!("modified {}", modified),
+        });
+        if let Some(owner) = self.owner {
+            lines.push(format!("owned by uid {} gid {}", owner.uid, owner.gid));
+        }
+        if self.newer_than_source {
+            lines.push("newer than its source".to_string());
+        }
+        if let Some(commit) = &self.last_commit {
+            lines.push(format!("git: {}", commit));
+        }
+        lines
+    }
+}
+
+/// Inspect the destination of `action` for the current user
+pub fn for_action(action: &DotfileAction) -> Option<Forensics> {
+    let roots = DotfileRoots::from_env().ok()?;
+    let last_apply = crate::core::history::History::load()
+        .ok()
+        .and_then(|history| history.runs.last().map(|run| run.started));
+    inspect(
+        &roots.destination(&action.mapping),
+        &roots.source(&action.mapping),
+        last_apply,
+        Identity::current().as_ref(),
+    )
+}
+
+/// Gather forensics for `dst`; `None` if it does not exist
+///
+/// `identity` decides whether the owner is worth reporting; without one the
+/// owner is always reported.
+pub fn inspect(
+    dst: &Path,
+    src: &Path,
+    last_apply: Option<u64>,
+    identity: Option<&Identity>,
+) -> Option<Forensics> {
+    let meta = fs::symlink_metadata(dst).ok()?;
+    let mtime = newest_mtime(dst);
+    let owned_by_us =
+        identity.is_some_and(|id| id.uid == meta.uid() && id.gids.contains(&meta.gid()));
+    Some(Forensics {
+        mtime,
+        since_last_apply: last_apply.map(|at| mtime as i64 - at as i64),
+        owner: (!owned_by_us).then_some(Owner {
+            uid: meta.uid(),
+            gid: meta.gid(),
+        }),
+        newer_than_source: src.exists() && mtime > newest_mtime(src),
+        last_commit: last_commit(dst),
+    })
+}
+
+/// Newest mtime of `path` and, for directories, everything below it
+fn newest_mtime(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    let own = meta.mtime().max(0) as u64;
+    if !meta.is_dir() {
+        return own;
+    }
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| newest_mtime(&entry.path()))
+        .fold(own, u64::max)
+}
+
+/// Last commit touching `path`, when it lies in a git work tree
+fn last_commit(path: &Path) -> Option<String> {
+    let root = path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["log", "-1", "--format=%h %s", "--"])
+        .arg(path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let line = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!line.is_empty()).then_some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn write_at(path: &Path, body: &str, secs: u64) {
+        fs::write(path, body).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    fn me(path: &Path) -> Identity {
+        let meta = fs::metadata(path).unwrap();
+        Identity {
+            uid: meta.uid(),
+            gids: vec![meta.gid()],
+        }
+    }
+
+    #[test]
+    fn test_mtimes_against_source_and_last_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        write_at(&src, "a", 1_000_000);
+        write_at(&dst, "b", 1_007_200);
+
+        let found = inspect(&dst, &src, Some(1_000_000), Some(&me(&dst))).unwrap();
+        assert_eq!(found.mtime, 1_007_200);
+        assert_eq!(found.since_last_apply, Some(7_200));
+        assert!(found.newer_than_source);
+        assert_eq!(found.owner, None);
+        assert!(found.lines()[0].ends_with("(2h after the last apply)"));
+
+        write_at(&src, "a", 1_010_000);
+        let found = inspect(&dst, &src, Some(1_010_000), Some(&me(&dst))).unwrap();
+        assert_eq!(found.since_last_apply, Some(-2_800));
+        assert!(!found.newer_than_source);
+        assert!(found.lines()[0].ends_with("(before the last apply)"));
+    }
+
+    #[test]
+    fn test_directory_uses_newest_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("nvim");
+        fs::create_dir_all(dst.join("lua")).unwrap();
+        write_at(&dst.join("init.lua"), "", 1_000_000);
+        write_at(&dst.join("lua/plugins.lua"), "", 2_000_000);
+        assert!(newest_mtime(&dst) >= 2_000_000);
+    }
+
+    #[test]
+    fn test_owner_reported_for_other_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("dst");
+        write_at(&dst, "b", 1_000_000);
+        let meta = fs::metadata(&dst).unwrap();
+        let other = Identity {
+            uid: meta.uid() + 1,
+            gids: vec![],
+        };
+        let found = inspect(&dst, &dir.path().join("missing"), None, Some(&other)).unwrap();
+        assert_eq!(
+            found.owner,
+            Some(Owner {
+                uid: meta.uid(),
+                gid: meta.gid()
+            })
+        );
+        assert!(!found.newer_than_source);
+        assert_eq!(found.since_last_apply, None);
+    }
+
+    #[test]
+    fn test_missing_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(inspect(&dir.path().join("nope"), dir.path(), None, None).is_none());
+    }
+
+    #[test]
+    fn test_last_commit_in_git_work_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args(["-c", "user.name=owl", "-c", "user.email=owl@localhost"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        let dst = dir.path().join("config.toml");
+        fs::write(&dst, "theme = 1\n").unwrap();
+        git(&["add", "config.toml"]);
+        git(&["commit", "-q", "-m", "tweak theme"]);
+
+        let commit = last_commit(&dst).unwrap();
+        assert!(commit.ends_with(" tweak theme"), "{}", commit);
+        let untracked = dir.path().join("other");
+        fs::write(&untracked, "").unwrap();
+        assert_eq!(last_commit(&untracked), None);
+    }
+
+    #[test]
+    fn test_no_commit_outside_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("f");
+        fs::write(&dst, "").unwrap();
+        // A temp dir may itself sit inside a work tree; only assert when it does not
+        if !dir.path().ancestors().any(|d| d.join(".git").exists()) {
+            assert_eq!(last_commit(&dst), None);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Apply and `owl pm` run history stored in `~/.owl/.state/history.json`
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::internal::constants;
+
+const HISTORY_FILE: &str = "history.json";
+
+/// Number of apply runs kept in the history file
+const MAX_RUNS: usize = 100;
+
+/// Wall-clock time spent installing one package
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstallTiming {
+    pub package: String,
+    pub duration_ms: u64,
+}
+
+/// One recorded `owl apply` run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApplyRecord {
+    /// Start time in seconds since the Unix epoch
+    pub started: u64,
+    #[serde(default)]
+    pub install_timings: Vec<InstallTiming>,
+    /// Packages of the AUR session in build order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aur_builds: Vec<crate::core::aur_builds::AurBuild>,
+    /// Id of the snapshot taken before the run (`@option snapshot_cmd`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_snapshot: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_snapshot: Option<String>,
+    /// Random delay waited before the run started (`--splay`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub splay_ms: Option<u64>,
+    /// Managed entries dropped or renamed before the run (see `core::reconcile`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reconciled: Vec<crate::core::reconcile::StateChange>,
+    /// Why the package phases were deferred (pacman database locked)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packages_deferred: Option<String>,
+    /// Whether the run ended without a hard failure; unknown for runs
+    /// recorded before outcomes were kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<bool>,
+}
+
+/// One recorded `owl pm -- ARGS` run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PmRecord {
+    /// Start time in seconds since the Unix epoch
+    pub started: u64,
+    /// Arguments passed to the package manager
+    pub args: Vec<String>,
+    pub success: bool,
+    pub duration_ms: u64,
+    /// Declared packages the run installed that owl now manages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub managed: Vec<String>,
+}
+
+/// Aggregated install timings for one package across recorded runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowPackage {
+    pub package: String,
+    pub average_ms: u64,
+    pub max_ms: u64,
+    pub installs: usize,
+}
+
+/// Recorded runs, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    pub runs: Vec<ApplyRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pm_runs: Vec<PmRecord>,
+    /// Questions asked before risky operations and their answers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub confirmations: Vec<crate::core::confirm::ConfirmRecord>,
+}
+
+impl History {
+    /// Load the history for the current user (empty if none was recorded yet)
+    pub fn load() -> Result<Self> {
+        Self::load_from(&history_dir()?)
+    }
+
+    pub fn load_from(dir: &Path) -> Result<Self> {
+        let path = dir.join(HISTORY_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", HISTORY_FILE, e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse {}: {}", HISTORY_FILE, e))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&history_dir()?)
+    }
+
+    pub fn save_to(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", dir.display(), e))?;
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize {}: {}", HISTORY_FILE, e))?;
+        fs::write(dir.join(HISTORY_FILE), content)
+            .map_err(|e| anyhow!("Failed to write {}: {}", HISTORY_FILE, e))
+    }
+
+    /// Append a run, dropping the oldest beyond the retention limit
+    pub fn record(&mut self, run: ApplyRecord) {
+        self.runs.push(run);
+        let excess = self.runs.len().saturating_sub(MAX_RUNS);
+        self.runs.drain(..excess);
+    }
+
+    /// Append an `owl pm` run, with the same retention as apply runs
+    pub fn record_pm(&mut self, run: PmRecord) {
+        self.pm_runs.push(run);
+        let excess = self.pm_runs.len().saturating_sub(MAX_RUNS);
+        self.pm_runs.drain(..excess);
+    }
+
+    /// Append a confirmation, keeping as many as apply runs
+    pub fn record_confirmation(&mut self, confirmation: crate::core::confirm::ConfirmRecord) {
+        self.confirmations.push(confirmation);
+        let excess = self.confirmations.len().saturating_sub(MAX_RUNS);
+        self.confirmations.drain(..excess);
+    }
+
+    /// Packages ordered by average install time, slowest first
+    pub fn slowest_packages(&self, limit: usize) -> Vec<SlowPackage> {
+        let mut totals: HashMap<&str, (u64, u64, usize)> = HashMap::new();
+        for timing in self.runs.iter().flat_map(|r| &r.install_timings) {
+            let entry = totals.entry(&timing.package).or_insert((0, 0, 0));
+            entry.0 += timing.duration_ms;
+            entry.1 = entry.1.max(timing.duration_ms);
+            entry.2 += 1;
+        }
+        let mut slow: Vec<SlowPackage> = totals
+            .into_iter()
+            .map(|(package, (total, max_ms, installs))| SlowPackage {
+                package: package.to_string(),
+                average_ms: total / installs as u64,
+                max_ms,
+                installs,
+            })
+            .collect();
+        slow.sort_by(|a, b| {
+            b.average_ms
+                .cmp(&a.average_ms)
+                .then_with(|| a.package.cmp(&b.package))
+        });
+        slow.truncate(limit);
+        slow
+    }
+}
+
+fn history_dir() -> Result<PathBuf> {
+    Ok(crate::internal::environment::get()
+        .owl_dir()?
+        .join(constants::STATE_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(started: u64, timings: &[(&str, u64)]) -> ApplyRecord {
+        ApplyRecord {
+            started,
+            install_timings: timings
+                .iter()
+                .map(|(package, duration_ms)| InstallTiming {
+                    package: package.to_string(),
+                    duration_ms: *duration_ms,
+                })
+                .collect(),
+            aur_builds: Vec::new(),
+            pre_snapshot: None,
+            post_snapshot: None,
+            splay_ms: None,
+            reconciled: Vec::new(),
+            packages_deferred: None,
+            success: None,
+        }
+    }
+
+    #[test]
+    fn test_slowest_packages_averages_runs() {
+        let mut history = History::default();
+        history.record(run(1, &[("yay-bin", 40_000), ("ripgrep", 2_000)]));
+        history.record(run(2, &[("yay-bin", 54_000), ("neovim-git", 23_000)]));
+
+        let slow = history.slowest_packages(2);
+        assert_eq!(slow.len(), 2);
+        assert_eq!(slow[0].package, "yay-bin");
+        assert_eq!(slow[0].average_ms, 47_000);
+        assert_eq!(slow[0].max_ms, 54_000);
+        assert_eq!(slow[0].installs, 2);
+        assert_eq!(slow[1].package, "neovim-git");
+    }
+
+    #[test]
+    fn test_record_caps_runs() {
+        let mut history = History::default();
+        for i in 0..(MAX_RUNS as u64 + 5) {
+            history.record(run(i, &[]));
+        }
+        assert_eq!(history.runs.len(), MAX_RUNS);
+        assert_eq!(history.runs[0].started, 5);
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(History::load_from(dir.path()).unwrap(), History::default());
+
+        let mut history = History::default();
+        history.record(run(10, &[("yay-bin", 1_500)]));
+        let mut reconciled = run(11, &[]);
+        reconciled.reconciled = vec![crate::core::reconcile::StateChange::Renamed {
+            from: "jack2".to_string(),
+            to: "pipewire-jack".to_string(),
+            declared: false,
+        }];
+        history.record(reconciled);
+        history.save_to(dir.path()).unwrap();
+        assert_eq!(History::load_from(dir.path()).unwrap(), history);
+        let raw = std::fs::read_to_string(dir.path().join(HISTORY_FILE)).unwrap();
+        assert_eq!(raw.matches("\"reconciled\"").count(), 1);
+        assert!(!raw.contains("pm_runs"));
+
+        history.record_pm(PmRecord {
+            started: 12,
+            args: vec!["-Sc".to_string(), "--noconfirm".to_string()],
+            success: true,
+            duration_ms: 800,
+            managed: Vec::new(),
+        });
+        history.save_to(dir.path()).unwrap();
+        assert_eq!(History::load_from(dir.path()).unwrap(), history);
+    }
+}
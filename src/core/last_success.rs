@@ -0,0 +1,187 @@
+//! When each host last applied without a hard failure
+//!
+//! A run that got to the end with no failed phase, failed install or reported
+//! error writes `.state/last-success.json`, keyed by hostname so a shared home
+//! keeps one record per machine. `owl status` shows its age, and apply warns at
+//! startup when it is older than `@option stale_after` (default 7d) or when the
+//! last recorded run in history failed.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+const FILE: &str = "last-success.json";
+
+/// How old the last success may get before apply warns
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The last fully successful apply on one host
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LastSuccess {
+    /// When the run started (seconds since the Unix epoch)
+    pub at: u64,
+    /// owl version that ran it
+    pub version: String,
+    pub installed: usize,
+    pub removed: usize,
+    /// Dotfiles created or updated
+    pub dotfiles: usize,
+}
+
+/// This host's record, if it ever applied successfully
+pub fn load(state_dir: &Path, host: &str) -> Result<Option<LastSuccess>> {
+    Ok(load_all(state_dir)?.remove(host))
+}
+
+/// Replace this host's record, keeping the other hosts'
+pub fn save(state_dir: &Path, host: &str, record: LastSuccess) -> Result<()> {
+    let mut records = load_all(state_dir)?;
+    records.insert(host.to_string(), record);
+    let path = state_dir.join(FILE);
+    fs::create_dir_all(state_dir)
+        .map_err(|e| anyhow!("Failed to create directory {}: {}", state_dir.display(), e))?;
+    let content = serde_json::to_string_pretty(&records)
+        .map_err(|e| anyhow!("Failed to serialize {}: {}", path.display(), e))?;
+    fs::write(&path, content).map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+}
+
+fn load_all(state_dir: &Path) -> Result<BTreeMap<String, LastSuccess>> {
+    let path = state_dir.join(FILE);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content).map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// `3d ago`, or `never on this host`
+pub fn describe(last: Option<&LastSuccess>, now: u64) -> String {
+    match last {
+        Some(last) => format!(
+            "{} ago",
+            crate::internal::time::format_age(now.saturating_sub(last.at) as i64)
+        ),
+        None => "never on this host".to_string(),
+    }
+}
+
+/// The startup warning, if any
+///
+/// `last_run` is the start and outcome of the newest history record; runs
+/// recorded before outcomes were kept have none and do not count as failed.
+/// With no success and no failed run (a fresh install) nothing is said, and
+/// `stale_after` of `None` turns the age check off.
+pub fn assess(
+    last: Option<&LastSuccess>,
+    last_run: Option<(u64, Option<bool>)>,
+    now: u64,
+    stale_after: Option<Duration>,
+) -> Option<String> {
+    if let Some((started, Some(false))) = last_run
+        && last.is_none_or(|last| last.at < started)
+    {
+        return Some(format!(
+            "The last apply ({} ago) did not finish cleanly; last successful apply: {}",
+            crate::internal::time::format_age(now.saturating_sub(started) as i64),
+            describe(last, now)
+        ));
+    }
+    let (last, stale_after) = (last?, stale_after?);
+    (now.saturating_sub(last.at) > stale_after.as_secs()).then(|| {
+        format!(
+            "The last successful apply was {} (stale_after={})",
+            describe(Some(last), now),
+            crate::internal::time::format_age(stale_after.as_secs() as i64)
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+    const NOW: u64 = 100 * DAY;
+
+    fn success(at: u64) -> LastSuccess {
+        LastSuccess {
+            at,
+            version: "1.0.0".to_string(),
+            installed: 2,
+            removed: 0,
+            dotfiles: 5,
+        }
+    }
+
+    #[test]
+    fn test_records_are_kept_per_host() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(load(dir.path(), "laptop").unwrap(), None);
+        save(dir.path(), "laptop", success(1)).unwrap();
+        save(dir.path(), "desktop", success(2)).unwrap();
+        save(dir.path(), "laptop", success(3)).unwrap();
+        assert_eq!(load(dir.path(), "laptop").unwrap(), Some(success(3)));
+        assert_eq!(load(dir.path(), "desktop").unwrap(), Some(success(2)));
+    }
+
+    #[test]
+    fn test_staleness_against_the_threshold() {
+        let week = Some(DEFAULT_STALE_AFTER);
+        let recent = success(NOW - 3 * DAY);
+        assert_eq!(
+            assess(Some(&recent), Some((recent.at, Some(true))), NOW, week),
+            None
+        );
+        assert_eq!(describe(Some(&recent), NOW), "3d ago");
+
+        let old = success(NOW - 10 * DAY);
+        assert_eq!(
+            assess(Some(&old), Some((old.at, Some(true))), NOW, week).unwrap(),
+            "The last successful apply was 10d ago (stale_after=7d)"
+        );
+        assert_eq!(assess(Some(&old), None, NOW, None), None);
+        assert_eq!(
+            assess(
+                Some(&old),
+                None,
+                NOW,
+                Some(Duration::from_secs(12 * 60 * 60))
+            )
+            .unwrap(),
+            "The last successful apply was 10d ago (stale_after=12h)"
+        );
+    }
+
+    #[test]
+    fn test_failed_and_never_applied_gating() {
+        let week = Some(DEFAULT_STALE_AFTER);
+        // Fresh install, or history from before outcomes were recorded
+        assert_eq!(assess(None, None, NOW, week), None);
+        assert_eq!(assess(None, Some((NOW - DAY, None)), NOW, week), None);
+        assert_eq!(
+            assess(None, Some((NOW - DAY, Some(false))), NOW, week).unwrap(),
+            "The last apply (1d ago) did not finish cleanly; \
+             last successful apply: never on this host"
+        );
+        let recent = success(NOW - 3 * DAY);
+        assert_eq!(
+            assess(Some(&recent), Some((NOW - DAY, Some(false))), NOW, None).unwrap(),
+            "The last apply (1d ago) did not finish cleanly; last successful apply: 3d ago"
+        );
+        // A failure before the last success is over
+        assert_eq!(
+            assess(
+                Some(&recent),
+                Some((recent.at - DAY, Some(false))),
+                NOW,
+                week
+            ),
+            None
+        );
+        assert_eq!(describe(None, NOW), "never on this host");
+    }
+}
@@ -0,0 +1,104 @@
+//! One writer at a time for installed packages and the files under `.state/`
+//!
+//! `owl apply` and `owl pm` both run package transactions and then rewrite
+//! the managed state; two of them at once would race on both. The lock is a
+//! file in the state directory holding the pid of its holder, created
+//! exclusively. A lock left behind by a process that is gone is taken over.
+
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const LOCK_FILE: &str = "owl.lock";
+
+/// Held until dropped
+#[derive(Debug)]
+pub struct WriterLock {
+    path: PathBuf,
+}
+
+impl WriterLock {
+    /// Take the lock in `state_dir`, failing if another live owl holds it
+    pub fn acquire(state_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(state_dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", state_dir.display(), e))?;
+        let path = state_dir.join(LOCK_FILE);
+        // Second attempt after clearing a stale lock
+        for _ in 0..2 {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())
+                        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if let Some(pid) = holder(&path) {
+                        return Err(anyhow!(
+                            "Another owl (pid {}) is changing packages; wait for it to finish, \
+                             or remove {} if that process is not owl",
+                            pid,
+                            path.display()
+                        ));
+                    }
+                    fs::remove_file(&path).map_err(|e| {
+                        anyhow!("Failed to remove stale lock {}: {}", path.display(), e)
+                    })?;
+                }
+                Err(e) => return Err(anyhow!("Failed to create {}: {}", path.display(), e)),
+            }
+        }
+        Err(anyhow!("Failed to take the lock {}", path.display()))
+    }
+}
+
+impl Drop for WriterLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The pid in the lock file, if that process is still running
+fn holder(path: &Path) -> Option<u32> {
+    let pid: u32 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    Path::new("/proc")
+        .join(pid.to_string())
+        .exists()
+        .then_some(pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_writer_is_refused_until_the_first_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = WriterLock::acquire(dir.path()).unwrap();
+        let err = WriterLock::acquire(dir.path()).unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("pid {}", std::process::id())),
+            "{}",
+            err
+        );
+        drop(lock);
+        assert!(!dir.path().join(LOCK_FILE).exists());
+        WriterLock::acquire(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn test_stale_lock_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        // Beyond the kernel's pid limit, so never a running process
+        fs::write(dir.path().join(LOCK_FILE), "4294967295").unwrap();
+        let _lock = WriterLock::acquire(dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join(LOCK_FILE)).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Line-based three-way merge of text files, in the style of `diff3 -m`
+//!
+//! Both changed versions are diffed against their common base. Runs of base
+//! lines that both versions kept unchanged split the files into chunks; a chunk
+//! only one side changed takes that side, a chunk both changed the same way
+//! takes it once, and anything else is a conflict written out between markers
+//! with all three versions, so nothing is lost.
+
+use crate::core::diff::line_matches;
+
+/// Names written after the conflict markers
+#[derive(Debug, Clone, Copy)]
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+/// Result of a merge
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    /// The merged text, with conflict markers if `conflicts` is not zero
+    pub text: String,
+    /// Chunks both sides changed differently
+    pub conflicts: usize,
+}
+
+impl Merged {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+/// `data` as text, if it is UTF-8 without NUL bytes
+pub fn as_text(data: &[u8]) -> Option<&str> {
+    std::str::from_utf8(data)
+        .ok()
+        .filter(|text| !text.contains('\0'))
+}
+
+/// Merge the changes `ours` and `theirs` made to `base`
+///
+/// Returns `None` when the files are too large to diff line by line.
+pub fn merge3(base: &str, ours: &str, theirs: &str, labels: Labels) -> Option<Merged> {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let to_ours = line_matches(&base, &ours)?;
+    let to_theirs = line_matches(&base, &theirs)?;
+
+    let mut merged = Merged {
+        text: String::new(),
+        conflicts: 0,
+    };
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    loop {
+        // The next base line both sides kept ends the chunk
+        let stable = (b..base.len()).find_map(|i| Some((i, to_ours[i]?, to_theirs[i]?)));
+        let (end_b, end_o, end_t) = stable.unwrap_or((base.len(), ours.len(), theirs.len()));
+        merge_chunk(
+            &base[b..end_b],
+            &ours[o..end_o],
+            &theirs[t..end_t],
+            labels,
+            &mut merged,
+        );
+        let Some((i, _, _)) = stable else {
+            return Some(merged);
+        };
+        merged.text.push_str(base[i]);
+        (b, o, t) = (end_b + 1, end_o + 1, end_t + 1);
+    }
+}
+
+fn merge_chunk(base: &[&str], ours: &[&str], theirs: &[&str], labels: Labels, out: &mut Merged) {
+    let take = if ours == base || ours == theirs {
+        theirs
+    } else if theirs == base {
+        ours
+    } else {
+        out.conflicts += 1;
+        push_marker(&mut out.text, "<<<<<<<", labels.ours);
+        push_lines(&mut out.text, ours);
+        push_marker(&mut out.text, "|||||||", labels.base);
+        push_lines(&mut out.text, base);
+        out.text.push_str("=======\n");
+        push_lines(&mut out.text, theirs);
+        push_marker(&mut out.text, ">>>>>>>", labels.theirs);
+        return;
+    };
+    out.text.extend(take.iter().copied());
+}
+
+fn push_marker(text: &mut String, marker: &str, label: &str) {
+    text.push_str(marker);
+    text.push(' ');
+    text.push_str(label);
+    text.push('\n');
+}
+
+/// Lines of one side of a conflict, ending with a newline so the next marker
+/// starts a line of its own
+fn push_lines(text: &mut String, lines: &[&str]) {
+    text.extend(lines.iter().copied());
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: Labels = Labels {
+        ours: "ours",
+        base: "base",
+        theirs: "theirs",
+    };
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> Merged {
+        merge3(base, ours, theirs, LABELS).unwrap()
+    }
+
+    fn clean(text: &str) -> Merged {
+        Merged {
+            text: text.to_string(),
+            conflicts: 0,
+        }
+    }
+
+    #[test]
+    fn test_unchanged_sides() {
+        let base = "a\nb\nc\n";
+        assert_eq!(merge(base, base, base), clean(base));
+        assert_eq!(merge(base, "a\nB\nc\n", base), clean("a\nB\nc\n"));
+        assert_eq!(merge(base, base, "a\nb\nc\nd\n"), clean("a\nb\nc\nd\n"));
+    }
+
+    #[test]
+    fn test_separate_edits_are_combined() {
+        let base = "1\n2\n3\n4\n5\n6\n";
+        let ours = "one\n2\n3\n4\n5\n6\n";
+        let theirs = "1\n2\n3\n4\n5\nsix\nseven\n";
+        assert_eq!(
+            merge(base, ours, theirs),
+            clean("one\n2\n3\n4\n5\nsix\nseven\n")
+        );
+    }
+
+    #[test]
+    fn test_insertions_and_deletions_on_both_sides() {
+        let base = "a\nb\nc\nd\ne\n";
+        // ours deletes b and inserts after d; theirs inserts after a and deletes e
+        let ours = "a\nc\nd\nours\ne\n";
+        let theirs = "a\ntheirs\nb\nc\nd\n";
+        let merged = merge(base, ours, theirs);
+        assert!(!merged.is_clean(), "{}", merged.text);
+
+        let ours = "a\nc\nd\ne\n";
+        let theirs = "a\nb\nc\nd\ne\nf\n";
+        assert_eq!(merge(base, ours, theirs), clean("a\nc\nd\ne\nf\n"));
+    }
+
+    #[test]
+    fn test_same_change_on_both_sides_is_taken_once() {
+        let base = "x = 1\ny = 2\n";
+        let both = "x = 1\ny = 3\n";
+        assert_eq!(merge(base, both, both), clean(both));
+        // The same line appended by both
+        assert_eq!(
+            merge(base, "x = 1\ny = 2\nz\n", "x = 0\ny = 2\nz\n"),
+            clean("x = 0\ny = 2\nz\n")
+        );
+    }
+
+    #[test]
+    fn test_conflicting_edits_get_markers_with_all_three_versions() {
+        let base = "head\ncolor = red\ntail\n";
+        let ours = "head\ncolor = blue\ntail\n";
+        let theirs = "head\ncolor = green\ntail\n";
+        assert_eq!(
+            merge(base, ours, theirs),
+            Merged {
+                text: "head\n\
+                       <<<<<<< ours\n\
+                       color = blue\n\
+                       ||||||| base\n\
+                       color = red\n\
+                       =======\n\
+                       color = green\n\
+                       >>>>>>> theirs\n\
+                       tail\n"
+                    .to_string(),
+                conflicts: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_different_insertions_at_one_place_conflict() {
+        let merged = merge("a\nb\n", "a\nours\nb\n", "a\ntheirs\nb\n");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.text,
+            "a\n<<<<<<< ours\nours\n||||||| base\n=======\ntheirs\n>>>>>>> theirs\nb\n"
+        );
+    }
+
+    #[test]
+    fn test_delete_against_edit_conflicts() {
+        let merged = merge("a\nb\nc\n", "a\nc\n", "a\nB\nc\n");
+        assert_eq!(
+            merged.text,
+            "a\n<<<<<<< ours\n||||||| base\nb\n=======\nB\n>>>>>>> theirs\nc\n"
+        );
+        assert_eq!(merged.conflicts, 1);
+    }
+
+    #[test]
+    fn test_conflicts_are_counted_separately() {
+        let base = "1\n2\n3\n4\n5\n";
+        let ours = "one\n2\n3\n4\nfive\n";
+        let theirs = "uno\n2\n3\n4\ncinco\n";
+        let merged = merge(base, ours, theirs);
+        assert_eq!(merged.conflicts, 2);
+        assert_eq!(merged.text.matches("<<<<<<< ours\n").count(), 2);
+        assert!(merged.text.contains("\n2\n3\n4\n"));
+    }
+
+    #[test]
+    fn test_missing_final_newline() {
+        // Only one side touches the last line: its form wins, newline or not
+        assert_eq!(merge("a\nm\nb", "A\nm\nb", "a\nm\nb\n"), clean("A\nm\nb\n"));
+        // Edits to neighbouring lines overlap, as in diff3
+        assert_eq!(merge("a\nb", "A\nb", "a\nb\n").conflicts, 1);
+        // Markers still start their own lines
+        let merged = merge("a\nb", "a\nours", "a\ntheirs");
+        assert_eq!(
+            merged.text,
+            "a\n<<<<<<< ours\nours\n||||||| base\nb\n=======\ntheirs\n>>>>>>> theirs\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_base() {
+        assert_eq!(merge("", "x\n", "x\n"), clean("x\n"));
+        assert_eq!(merge("", "", "x\n"), clean("x\n"));
+        assert_eq!(merge("", "x\n", "y\n").conflicts, 1);
+        assert_eq!(merge("a\n", "", "").text, "");
+    }
+
+    #[test]
+    fn test_as_text_and_size_limit() {
+        assert_eq!(as_text(b"plain\n"), Some("plain\n"));
+        assert_eq!(as_text(b"nul\0byte"), None);
+        assert_eq!(as_text(&[0xff, 0xfe]), None);
+        let big = "x\n".repeat(2001);
+        assert_eq!(merge3(&big, &big, &big, LABELS), None);
+    }
+}
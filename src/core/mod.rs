@@ -1,7 +1,43 @@
+pub mod absent;
+pub mod aur_builds;
+pub mod aur_rpc;
+pub mod backup;
+pub mod checkpoint;
+pub mod clock;
 pub mod config;
+pub mod confirm;
+pub mod daemon;
+pub mod db_lock;
+pub mod diff;
+pub mod doctor;
+pub mod dotfile_audit;
+pub mod dotfile_explain;
 pub mod dotfiles;
+pub mod drift;
 pub mod env;
+pub mod events;
+#[cfg(unix)]
+pub mod file_modes;
+pub mod forensics;
+pub mod history;
+pub mod last_success;
+pub mod lock;
+pub mod merge;
+pub mod names;
 pub mod package;
+pub mod passthrough;
+#[cfg(test)]
+mod perf;
+pub mod plan;
 pub mod pm;
+pub mod privilege;
+pub mod privileged_helper;
+pub mod reconcile;
+pub mod review;
+pub mod service_import;
 pub mod services;
+pub mod shared_home;
+pub mod snapshot;
+pub mod source_check;
 pub mod state;
+pub mod version;
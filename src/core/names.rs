@@ -0,0 +1,280 @@
+//! Validation of names and keys taken from config files and the command line
+//!
+//! Text pasted from a wiki or chat brings invisible characters along: a
+//! no-break space after a package name, a zero-width space inside it, or a
+//! full-width letter that looks like ASCII. Such a name never matches what
+//! pacman reports, so it is rejected where it is read, with a message that
+//! names the character and where it is.
+
+use anyhow::{Result, anyhow};
+use std::borrow::Cow;
+
+/// Trim Unicode whitespace and separate a directive from its argument by one space
+///
+/// `@package\u{a0}nvim\u{a0}` becomes `@package nvim`. Lines that are not
+/// directives are only trimmed.
+pub fn normalize_line(line: &str) -> Cow<'_, str> {
+    let line = line.trim();
+    if !line.starts_with(['@', ':']) {
+        return line.into();
+    }
+    let Some((directive, rest)) = line.split_once(char::is_whitespace) else {
+        return line.into();
+    };
+    let rest = rest.trim_start();
+    if line.len() == directive.len() + 1 + rest.len() && line[directive.len()..].starts_with(' ') {
+        line.into()
+    } else {
+        format!("{} {}", directive, rest).into()
+    }
+}
+
+/// Check a package name against pacman's rules: ASCII letters, digits and
+/// `@._+-`, not starting with `-` or `.`
+pub fn validate_package_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(anyhow!("Empty package name"));
+    }
+    validate(name, "package name", |c| {
+        c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '_' | '+' | '-')
+    })
+    .map_err(|e| anyhow!("{} (allowed: ASCII letters, digits and @._+-)", e))?;
+    if name.starts_with(['-', '.']) {
+        return Err(anyhow!(
+            "Invalid package name '{}': it cannot start with '{}'",
+            name,
+            &name[..1]
+        ));
+    }
+    Ok(())
+}
+
+/// Check an `@option` or `:env` key: ASCII letters, digits, `_` and `-`
+pub fn validate_key(key: &str, what: &str) -> Result<()> {
+    if key.is_empty() {
+        return Err(anyhow!("Empty {}", what));
+    }
+    validate(key, what, |c| {
+        c.is_ascii_alphanumeric() || matches!(c, '_' | '-')
+    })
+    .map_err(|e| anyhow!("{} (allowed: ASCII letters, digits, _ and -)", e))
+}
+
+/// Suffixes of the unit types systemd knows
+const UNIT_SUFFIXES: &[&str] = &[
+    ".service",
+    ".socket",
+    ".timer",
+    ".target",
+    ".path",
+    ".mount",
+    ".automount",
+    ".swap",
+    ".slice",
+    ".scope",
+    ".device",
+];
+
+/// Check a systemd unit name and add `.service` when it has no unit suffix
+///
+/// Unit names use ASCII letters, digits and `:-_.\@`. As with systemctl,
+/// `docker` and `docker.service` name the same unit.
+pub fn normalize_service_name(name: &str) -> Result<String> {
+    if name.is_empty() {
+        return Err(anyhow!("Empty service name"));
+    }
+    validate(name, "service name", |c| {
+        c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_' | '.' | '\\' | '@')
+    })
+    .map_err(|e| anyhow!("{} (allowed: ASCII letters, digits and :-_.\\@)", e))?;
+    if name.starts_with(['.', '@']) {
+        return Err(anyhow!(
+            "Invalid service name '{}': it cannot start with '{}'",
+            name,
+            &name[..1]
+        ));
+    }
+    if UNIT_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+        Ok(name.to_string())
+    } else {
+        Ok(format!("{}.service", name))
+    }
+}
+
+/// Report the first character of `value` that `allowed` rejects
+fn validate(value: &str, what: &str, allowed: impl Fn(char) -> bool) -> Result<()> {
+    match value.chars().enumerate().find(|(_, c)| !allowed(*c)) {
+        None => Ok(()),
+        Some((index, c)) => Err(anyhow!(
+            "Invalid {} '{}': {} at position {}",
+            what,
+            escape(value),
+            describe_char(c),
+            index + 1
+        )),
+    }
+}
+
+/// `value` with every character outside printable ASCII written as `\u{..}`
+pub fn escape(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c.to_string()
+            } else {
+                format!("\\u{{{:x}}}", c as u32)
+            }
+        })
+        .collect()
+}
+
+/// `U+00A0 NO-BREAK SPACE`, `'/' (U+002F)`, and so on
+pub fn describe_char(c: char) -> String {
+    let code = format!("U+{:04X}", c as u32);
+    if c.is_ascii_graphic() {
+        return format!("'{}' ({})", c, code);
+    }
+    let name = match c {
+        ' ' => Some("SPACE".to_string()),
+        '\t' => Some("TAB".to_string()),
+        '\u{a0}' => Some("NO-BREAK SPACE".to_string()),
+        '\u{200b}' => Some("ZERO WIDTH SPACE".to_string()),
+        '\u{200c}' => Some("ZERO WIDTH NON-JOINER".to_string()),
+        '\u{200d}' => Some("ZERO WIDTH JOINER".to_string()),
+        '\u{2060}' => Some("WORD JOINER".to_string()),
+        '\u{feff}' => Some("ZERO WIDTH NO-BREAK SPACE".to_string()),
+        '\u{3000}' => Some("IDEOGRAPHIC SPACE".to_string()),
+        '\u{300}'..='\u{36f}' => Some("COMBINING MARK".to_string()),
+        // Full-width forms of ASCII sit at a fixed offset from it
+        '\u{ff01}'..='\u{ff5e}' => {
+            char::from_u32(c as u32 - 0xfee0).map(|ascii| format!("FULLWIDTH '{}'", ascii))
+        }
+        _ if c.is_whitespace() => Some("whitespace".to_string()),
+        _ => None,
+    };
+    match name {
+        Some(name) => format!("{} {}", code, name),
+        None => format!("'{}' ({})", c, code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_line() {
+        assert_eq!(normalize_line("@package nvim\u{a0}"), "@package nvim");
+        assert_eq!(
+            normalize_line("\u{3000}@package\u{a0}nvim"),
+            "@package nvim"
+        );
+        assert_eq!(
+            normalize_line("@package  \tnvim [aur]"),
+            "@package nvim [aur]"
+        );
+        assert_eq!(normalize_line(":env FOO=a b"), ":env FOO=a b");
+        assert_eq!(normalize_line("  neovim\u{a0}"), "neovim");
+        assert_eq!(normalize_line("@packages"), "@packages");
+    }
+
+    #[test]
+    fn test_ascii_names_are_untouched() {
+        for name in [
+            "nvim",
+            "lib32-gcc-libs",
+            "python-pip",
+            "gtk+3",
+            "r8168@dkms",
+            "x_y.z",
+        ] {
+            validate_package_name(name).unwrap();
+            assert_eq!(normalize_line(name), name);
+        }
+    }
+
+    #[test]
+    fn test_invisible_and_lookalike_characters_are_named() {
+        let cases = [
+            (
+                "nvim\u{a0}x",
+                "Invalid package name 'nvim\\u{a0}x': U+00A0 NO-BREAK SPACE at position 5",
+            ),
+            (
+                "ne\u{200b}ovim",
+                "Invalid package name 'ne\\u{200b}ovim': U+200B ZERO WIDTH SPACE at position 3",
+            ),
+            (
+                "\u{ff4e}vim",
+                "Invalid package name '\\u{ff4e}vim': U+FF4E FULLWIDTH 'n' at position 1",
+            ),
+            (
+                "ne\u{301}ovim",
+                "Invalid package name 'ne\\u{301}ovim': U+0301 COMBINING MARK at position 3",
+            ),
+            (
+                "caf\u{e9}",
+                "Invalid package name 'caf\\u{e9}': '\u{e9}' (U+00E9) at position 4",
+            ),
+            (
+                "extra/git",
+                "Invalid package name 'extra/git': '/' (U+002F) at position 6",
+            ),
+        ];
+        for (name, expected) in cases {
+            let err = validate_package_name(name).unwrap_err().to_string();
+            assert_eq!(
+                err,
+                format!("{} (allowed: ASCII letters, digits and @._+-)", expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_leading_dash_or_dot_rejected() {
+        let err = validate_package_name("-rf").unwrap_err().to_string();
+        assert_eq!(err, "Invalid package name '-rf': it cannot start with '-'");
+        assert!(validate_package_name(".hidden").is_err());
+    }
+
+    #[test]
+    fn test_service_names_get_a_unit_suffix() {
+        for (name, normalized) in [
+            ("docker", "docker.service"),
+            ("docker.service", "docker.service"),
+            ("sway-session.target", "sway-session.target"),
+            ("getty@tty1", "getty@tty1.service"),
+            (
+                "systemd-tmpfiles-clean.timer",
+                "systemd-tmpfiles-clean.timer",
+            ),
+            ("org.cups.cupsd", "org.cups.cupsd.service"),
+        ] {
+            assert_eq!(normalize_service_name(name).unwrap(), normalized);
+        }
+        for (name, expected) in [
+            ("", "Empty service name"),
+            ("docker compose", "SPACE at position 7"),
+            ("sshd/x", "'/' (U+002F) at position 5"),
+            (".service", "cannot start with '.'"),
+            ("@tty1", "cannot start with '@'"),
+        ] {
+            let err = normalize_service_name(name).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_keys() {
+        validate_key("auto_update", "option key").unwrap();
+        let err = validate_key("auto\u{200b}update", "option key")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "Invalid option key 'auto\\u{200b}update': U+200B ZERO WIDTH SPACE at position 5 \
+             (allowed: ASCII letters, digits, _ and -)"
+        );
+    }
+}
@@ -1,11 +1,11 @@
 //! Package management utilities
 
 use crate::core::config::Config;
-use crate::core::pm::{PackageManager, ParuPacman, SearchResult};
+use crate::core::pm::{PackageManager, SearchResult, manager};
 use crate::core::state::PackageState;
 use anyhow::Result;
-use std::collections::HashSet;
-use std::sync::OnceLock;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 
 /// Package action types for planning installations and removals
 #[derive(Debug, Clone, PartialEq)]
@@ -15,46 +15,174 @@ pub enum PackageAction {
 }
 
 // Cache of installed packages for the current process run
-static INSTALLED_CACHE: OnceLock<HashSet<String>> = OnceLock::new();
-static PACKAGE_COUNT_CACHE: OnceLock<usize> = OnceLock::new();
+static INSTALLED_CACHE: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+static PACKAGE_COUNT_CACHE: Mutex<Option<usize>> = Mutex::new(None);
+
+/// Forget what the package manager said, for a process that outlives a
+/// package transaction (`owl daemon`)
+pub fn clear_caches() {
+    *INSTALLED_CACHE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    *PACKAGE_COUNT_CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = None;
+    crate::core::pm::clear_group_caches();
+}
 
 fn query_installed_packages() -> Result<HashSet<String>> {
-    ParuPacman::new().list_installed()
+    manager().list_installed()
 }
 
 /// Plan package actions by comparing desired config with installed packages
 pub fn plan_package_actions(config: &Config, state: &PackageState) -> Result<Vec<PackageAction>> {
-    let installed = get_installed_packages()?;
-    let desired: HashSet<String> = config.packages.keys().cloned().collect();
+    plan_actions(
+        config,
+        state,
+        &get_installed_packages()?,
+        &*manager(),
+        std::env::consts::ARCH,
+    )
+}
+
+/// `plan_package_actions` on `arch` against an `installed` set, asking `pm`
+/// about groups
+///
+/// Packages `@arch-aur-prefix` renames are planned, checked and recorded under
+/// the name they are installed as (see [`arch_names`]).
+pub(crate) fn plan_actions(
+    config: &Config,
+    state: &PackageState,
+    installed: &HashSet<String>,
+    pm: &dyn PackageManager,
+    arch: &str,
+) -> Result<Vec<PackageAction>> {
+    let renamed = arch_names(config, installed, pm, arch)?;
+    let mut desired: HashSet<String> = config.packages.keys().cloned().collect();
+    desired.extend(renamed.values().cloned());
 
     let mut actions = Vec::new();
 
-    for package in &desired {
-        if !is_package_or_group_installed(package)? {
-            actions.push(PackageAction::Install {
-                name: package.clone(),
-            });
+    // Installs follow `:after`, so dependencies come first
+    let order: Vec<String> = config
+        .package_order()?
+        .into_iter()
+        .map(|name| renamed.get(&name).cloned().unwrap_or(name))
+        .collect();
+    let present = resolve_installed(&order, installed, pm)?;
+    for package in order {
+        if !present.contains(&package) {
+            actions.push(PackageAction::Install { name: package });
         }
     }
 
-    for package in &installed {
-        if !desired.contains(package) && state.is_managed(package) {
-            actions.push(PackageAction::Remove {
-                name: package.clone(),
-            });
-        }
+    for name in removal_candidates(installed, &desired, state) {
+        actions.push(PackageAction::Remove { name });
     }
 
     Ok(actions)
 }
 
+/// The names declared packages go by on `arch` where `@arch-aur-prefix`
+/// renames them, by declared name
+///
+/// A package keeps its declared name when that is installed, or when it is a
+/// group or in the repos; otherwise it is built from the AUR under the
+/// suffixed name.
+pub(crate) fn arch_names(
+    config: &Config,
+    installed: &HashSet<String>,
+    pm: &dyn PackageManager,
+    arch: &str,
+) -> Result<HashMap<String, String>> {
+    arch_names_with(config, installed, arch, |lookup| {
+        let groups = pm.group_members(lookup)?;
+        let rest: Vec<String> = lookup
+            .iter()
+            .filter(|name| !groups.contains_key(*name))
+            .cloned()
+            .collect();
+        let mut found = pm.batch_repo_available(&rest)?;
+        found.extend(groups.into_keys());
+        Ok(found)
+    })
+}
+
+/// [`arch_names`], asking `in_repos` which of the names it needs to check are
+/// groups or repository packages
+pub(crate) fn arch_names_with(
+    config: &Config,
+    installed: &HashSet<String>,
+    arch: &str,
+    in_repos: impl FnOnce(&[String]) -> Result<HashSet<String>>,
+) -> Result<HashMap<String, String>> {
+    let mut names = HashMap::new();
+    let mut lookup = Vec::new();
+    for (declared, package) in &config.packages {
+        let suffixed = config.aur_package_name(declared, arch);
+        if suffixed == *declared || installed.contains(declared) {
+            continue;
+        }
+        if installed.contains(&suffixed) || package.aur {
+            names.insert(declared.clone(), suffixed);
+        } else {
+            lookup.push(declared.clone());
+        }
+    }
+    if lookup.is_empty() {
+        return Ok(names);
+    }
+    let in_repos = in_repos(&lookup)?;
+    for declared in lookup {
+        if !in_repos.contains(&declared) {
+            let suffixed = config.aur_package_name(&declared, arch);
+            names.insert(declared, suffixed);
+        }
+    }
+    Ok(names)
+}
+
+/// Installed packages that owl manages but are no longer declared
+///
+/// Untracked packages (built-in, state, or `@untracked` in config) are never
+/// proposed for removal.
+pub fn removal_candidates(
+    installed: &HashSet<String>,
+    desired: &HashSet<String>,
+    state: &PackageState,
+) -> Vec<String> {
+    let mut candidates: Vec<String> = installed
+        .iter()
+        .filter(|p| !desired.contains(*p) && state.is_managed(p) && !state.is_untracked(p))
+        .cloned()
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+/// Orphans that may be removed: declared and untracked packages are kept,
+/// as in `removal_candidates`
+pub fn orphan_removal_set(
+    orphans: &[String],
+    desired: &HashSet<String>,
+    state: &PackageState,
+) -> Vec<String> {
+    orphans
+        .iter()
+        .filter(|p| !desired.contains(*p) && !state.is_untracked(p))
+        .cloned()
+        .collect()
+}
+
 /// Get list of all installed packages
 pub fn get_installed_packages() -> Result<HashSet<String>> {
-    if let Some(cached) = INSTALLED_CACHE.get() {
+    if let Some(cached) = INSTALLED_CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+    {
         return Ok(cached.clone());
     }
     let installed = query_installed_packages()?;
-    let _ = INSTALLED_CACHE.set(installed.clone());
+    *INSTALLED_CACHE.lock().unwrap_or_else(|e| e.into_inner()) = Some(installed.clone());
     Ok(installed)
 }
 
@@ -63,36 +191,35 @@ pub fn remove_unmanaged_packages(packages: &[String], quiet: bool) -> Result<()>
     if packages.is_empty() {
         return Ok(());
     }
-    println!("Package cleanup (removing conflicting packages):");
+    outln!("Package cleanup (removing conflicting packages):");
     for package in packages {
-        println!(
+        outln!(
             "  {} Removing: {}",
             crate::internal::color::red("remove"),
             crate::internal::color::yellow(package)
         );
     }
-    ParuPacman::new().remove_packages(packages, quiet)
+    manager().remove_packages(packages, quiet)
 }
 
 /// Get the count of packages that can be upgraded
 pub fn get_package_count() -> Result<usize> {
-    if let Some(cached) = PACKAGE_COUNT_CACHE.get() {
-        return Ok(*cached);
+    if let Some(cached) = *PACKAGE_COUNT_CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+    {
+        return Ok(cached);
     }
-    let count = ParuPacman::new().upgrade_count()?;
-    let _ = PACKAGE_COUNT_CACHE.set(count);
+    let count = manager().upgrade_count()?;
+    *PACKAGE_COUNT_CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = Some(count);
     Ok(count)
 }
 
 /// Check if a package is installed
 pub fn is_package_installed(package_name: &str) -> Result<bool> {
-    if let Some(cached) = INSTALLED_CACHE.get() {
-        return Ok(cached.contains(package_name));
-    }
-    let installed = query_installed_packages()?;
-    let contains = installed.contains(package_name);
-    let _ = INSTALLED_CACHE.set(installed.clone());
-    Ok(contains)
+    Ok(get_installed_packages()?.contains(package_name))
 }
 
 /// Check if a package or group is effectively installed
@@ -105,7 +232,7 @@ pub fn is_package_or_group_installed(package_name: &str) -> Result<bool> {
     }
 
     // Check if it's a group (cached to avoid repeated calls)
-    let pm = ParuPacman::new();
+    let pm = manager();
     if pm.is_package_group(package_name)? {
         // It's a group, check if all packages in the group are installed
         let group_packages = pm.get_group_packages(package_name)?;
@@ -127,43 +254,370 @@ pub fn is_package_or_group_installed(package_name: &str) -> Result<bool> {
     Ok(false)
 }
 
+/// Which of `names` are installed packages, or groups with every member installed
+///
+/// Only names missing from `installed` are looked up as groups, all in one
+/// package manager query, so the number of calls does not grow with the config.
+fn resolve_installed(
+    names: &[String],
+    installed: &HashSet<String>,
+    pm: &dyn PackageManager,
+) -> Result<HashSet<String>> {
+    let (mut present, missing): (HashSet<String>, Vec<String>) = (
+        names
+            .iter()
+            .filter(|n| installed.contains(*n))
+            .cloned()
+            .collect(),
+        names
+            .iter()
+            .filter(|n| !installed.contains(*n))
+            .cloned()
+            .collect(),
+    );
+    if missing.is_empty() {
+        return Ok(present);
+    }
+    for (group, members) in pm.group_members(&missing)? {
+        if !members.is_empty() && members.iter().all(|m| installed.contains(m)) {
+            present.insert(group);
+        }
+    }
+    Ok(present)
+}
+
+/// Correct the managed list for packages removed or replaced outside owl
+///
+/// Managed names missing from the package list are checked as groups first;
+/// `pacman -Qi` is only read when some are still missing, for its `Replaces`.
+pub fn reconcile_managed(
+    state: &mut PackageState,
+    config: &Config,
+) -> Result<Vec<crate::core::reconcile::StateChange>> {
+    let mut installed = get_installed_packages()?;
+    let missing: Vec<String> = state
+        .managed
+        .iter()
+        .filter(|p| !installed.contains(*p))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        return Ok(Vec::new());
+    }
+    let pm = manager();
+    let groups = resolve_installed(&missing, &installed, &*pm)?;
+    installed.extend(groups);
+    let replaces: HashMap<String, Vec<String>> = if missing.iter().all(|p| installed.contains(p)) {
+        HashMap::new()
+    } else {
+        pm.installed_info()?
+            .into_iter()
+            .filter(|info| !info.replaces.is_empty())
+            .map(|info| (info.name, info.replaces))
+            .collect()
+    };
+    let declared: HashSet<String> = config.packages.keys().cloned().collect();
+    let changes = crate::core::reconcile::plan(&installed, &declared, &state.managed, &replaces);
+    crate::core::reconcile::apply(state, &changes);
+    Ok(changes)
+}
+
+/// Drop managed entries for packages that were removed outside owl
+///
+/// Names missing from the package list are double-checked as groups before
+/// pruning, and entries whose status can't be determined are kept.
+pub fn prune_uninstalled_managed(state: &mut PackageState) -> Result<Vec<String>> {
+    let mut installed = get_installed_packages()?;
+    for pkg in &state.managed {
+        if installed.contains(pkg) {
+            continue;
+        }
+        if !matches!(is_package_or_group_installed(pkg), Ok(false)) {
+            installed.insert(pkg.clone());
+        }
+    }
+    Ok(state.prune_uninstalled(&installed))
+}
+
 /// Determine if a package is available in official repositories
 #[cfg(test)]
 pub fn is_repo_package(package_name: &str) -> Result<bool, String> {
-    let set = ParuPacman::new()
+    let set = manager()
         .batch_repo_available(&[package_name.to_string()])
         .map_err(|e| e.to_string())?;
     Ok(set.contains(package_name))
 }
 
 /// Categorize packages into repo and AUR lists
-pub fn categorize_packages(packages: &[String]) -> Result<(Vec<String>, Vec<String>)> {
-    if packages.is_empty() {
-        return Ok((Vec::new(), Vec::new()));
-    }
-    let available = ParuPacman::new().batch_repo_available(packages)?;
-    let repo_packages: Vec<String> = packages
+///
+/// A name a repository provides is a repo package, even when the AUR has a
+/// package of the same name; names in `force_aur` (the `[aur]` hint) always go
+/// to the AUR and are not looked up. Both lists keep the order of `packages`.
+pub fn categorize_packages(
+    packages: &[String],
+    force_aur: &HashSet<String>,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let lookup: Vec<String> = packages
         .iter()
-        .filter(|p| available.contains(&**p))
+        .filter(|p| !force_aur.contains(*p))
         .cloned()
         .collect();
-    let aur_packages: Vec<String> = packages
+    if lookup.is_empty() {
+        return Ok((Vec::new(), packages.to_vec()));
+    }
+    let available = manager().batch_repo_available(&lookup)?;
+    Ok(split_repo_aur(packages, &available, force_aur))
+}
+
+fn split_repo_aur(
+    packages: &[String],
+    repo_available: &HashSet<String>,
+    force_aur: &HashSet<String>,
+) -> (Vec<String>, Vec<String>) {
+    packages
         .iter()
-        .filter(|p| !available.contains(&**p))
         .cloned()
-        .collect();
-    Ok((repo_packages, aur_packages))
+        .partition(|p| repo_available.contains(p) && !force_aur.contains(p))
 }
 
 /// Search packages using the PackageManager
 pub fn search_packages(terms: &[String]) -> Result<Vec<SearchResult>> {
-    ParuPacman::new().search_packages(terms)
+    manager().search_packages(terms)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn set_of(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_removal_candidates_respect_config_untracked() {
+        let installed = set_of(&["fish", "htop", "nvidia-dkms"]);
+        let desired = set_of(&["fish"]);
+        let mut state = PackageState {
+            managed: vec!["htop".to_string(), "nvidia-dkms".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            removal_candidates(&installed, &desired, &state),
+            vec!["htop".to_string(), "nvidia-dkms".to_string()]
+        );
+
+        let mut config = Config::new();
+        config.untracked.push("nvidia-dkms".to_string());
+        state.merge_config_untracked(&config);
+        assert_eq!(
+            removal_candidates(&installed, &desired, &state),
+            vec!["htop".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_requires_are_planned_and_removed_with_their_parent() {
+        let (_pm_dir, pm) = crate::core::pm::fake::pm("exit 0", "exit 0");
+        let state = PackageState {
+            managed: vec!["devtools".into(), "gdb".into(), "strace".into()],
+            ..Default::default()
+        };
+        let block = "@package devtools\n:requires gdb strace\n";
+        let config = Config::parse(&format!("{}@package git\n", block)).unwrap();
+        let actions = plan_actions(&config, &state, &set_of(&["git"]), &pm, "x86_64").unwrap();
+        let mut installs: Vec<&str> = actions
+            .iter()
+            .map(|action| match action {
+                PackageAction::Install { name } => name.as_str(),
+                PackageAction::Remove { name } => panic!("unexpected removal of {}", name),
+            })
+            .collect();
+        installs.sort();
+        assert_eq!(installs, ["devtools", "gdb", "strace"]);
+
+        // Dropping the block leaves every package it declared undeclared
+        let installed = set_of(&["devtools", "gdb", "strace", "git"]);
+        let config = Config::parse("@package git\n").unwrap();
+        assert_eq!(
+            plan_actions(&config, &state, &installed, &pm, "x86_64").unwrap(),
+            ["devtools", "gdb", "strace"].map(|name| PackageAction::Remove {
+                name: name.to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_orphan_removal_set_keeps_declared_and_untracked() {
+        let orphans: Vec<String> = ["meson", "go", "base-devel", "python-pip"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut config = Config::new();
+        config.untracked.push("python-pip".to_string());
+        let mut state = PackageState {
+            // Managed orphans are not protected; only declaring them is
+            managed: vec!["meson".to_string()],
+            untracked: vec!["base-devel".to_string()],
+            ..Default::default()
+        };
+        state.merge_config_untracked(&config);
+        assert_eq!(
+            orphan_removal_set(&orphans, &set_of(&["go"]), &state),
+            vec!["meson".to_string()]
+        );
+        assert!(orphan_removal_set(&[], &set_of(&[]), &state).is_empty());
+    }
+
+    #[test]
+    fn test_dual_existence_prefers_repo_unless_hinted() {
+        use crate::core::pm::{PackageManager, PackageSource};
+        let (_pm_dir, pm) = crate::core::pm::fake::pm(
+            r#"printf 'extra/yay 12.4.2-1\n    AUR helper\naur/yay 12.4.2-1\n    AUR helper\n'"#,
+            r#"for pkg in "$@"; do
+  case "$pkg" in
+    -*) ;;
+    yay|fd) printf 'Repository      : extra\nName            : %s\n\n' "$pkg" ;;
+    *) echo "error: package '$pkg' was not found" >&2; failed=1 ;;
+  esac
+done
+exit ${failed:-0}"#,
+        );
+        let sources: Vec<PackageSource> = pm
+            .search_packages(&["yay".to_string()])
+            .unwrap()
+            .into_iter()
+            .map(|r| r.source)
+            .collect();
+        assert_eq!(sources, vec![PackageSource::Repo, PackageSource::Aur]);
+
+        let packages: Vec<String> = ["yay", "fd", "paru-bin"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let available = pm.batch_repo_available(&packages).unwrap();
+        assert_eq!(
+            split_repo_aur(&packages, &available, &HashSet::new()),
+            (vec_of(&["yay", "fd"]), vec_of(&["paru-bin"]))
+        );
+        assert_eq!(
+            split_repo_aur(&packages, &available, &set_of(&["yay"])),
+            (vec_of(&["fd"]), vec_of(&["yay", "paru-bin"]))
+        );
+    }
+
+    #[test]
+    fn test_resolve_installed_queries_groups_once() {
+        let logs = tempfile::tempdir().unwrap();
+        let log = logs.path().join("calls");
+        let (_pm_dir, pm) = crate::core::pm::fake::pm(
+            "exit 1",
+            &format!(
+                r#"echo "$*" >> {}
+[ "$1" = "-Sgg" ] || exit 2
+printf 'batchgrp-full fd\nbatchgrp-full ripgrep\nbatchgrp-part fd\nbatchgrp-part bat\n'"#,
+                log.display()
+            ),
+        );
+        let installed = set_of(&["fd", "ripgrep"]);
+        let calls = || {
+            std::fs::read_to_string(&log)
+                .map(|s| s.lines().count())
+                .unwrap_or(0)
+        };
+
+        // Everything installed: no query at all
+        let names = vec_of(&["fd", "ripgrep"]);
+        assert_eq!(
+            resolve_installed(&names, &installed, &pm).unwrap(),
+            installed
+        );
+        assert_eq!(calls(), 0);
+
+        let mut names: Vec<String> = (0..500).map(|n| format!("batch-missing-{}", n)).collect();
+        names.extend(vec_of(&["fd", "batchgrp-full", "batchgrp-part"]));
+        let present = resolve_installed(&names, &installed, &pm).unwrap();
+        assert_eq!(present, set_of(&["fd", "batchgrp-full"]));
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "-Sgg\n");
+
+        // Answers are cached for the rest of the run
+        resolve_installed(&names, &installed, &pm).unwrap();
+        assert!(pm.is_package_group("batchgrp-part").unwrap());
+        assert!(!pm.is_package_group("batch-missing-7").unwrap());
+        assert_eq!(calls(), 1);
+    }
+
+    #[test]
+    fn test_arch_suffixed_packages_are_planned_by_installed_name() {
+        let (_pm_dir, pm) = crate::core::pm::fake::pm(
+            "exit 1",
+            r#"case "$1" in
+  -Sgg) printf 'archgrp-desktop xterm\n' ;;
+  -Si)
+    shift
+    for pkg in "$@"; do
+      case "$pkg" in
+        archtest-vim) printf 'Repository      : extra\nName            : %s\n\n' "$pkg" ;;
+        *) echo "error: package '$pkg' was not found" >&2; failed=1 ;;
+      esac
+    done
+    exit ${failed:-0} ;;
+esac"#,
+        );
+        let config = Config::parse(
+            "@arch-aur-prefix aarch64:suffix=-aarch64\n@package archtest-spotify\n\
+             @package archtest-vim\n@package archgrp-desktop\n",
+        )
+        .unwrap();
+        let installed_as = set_of(&["archtest-spotify-aarch64", "archtest-vim", "xterm"]);
+        let state = PackageState {
+            managed: vec_of(&["archtest-spotify-aarch64", "archtest-vim"]),
+            ..Default::default()
+        };
+
+        // Already installed under the suffixed name: nothing to do, and the
+        // managed suffixed package is not taken for an undeclared one
+        assert_eq!(
+            plan_actions(&config, &state, &installed_as, &pm, "aarch64").unwrap(),
+            Vec::new()
+        );
+
+        // Missing: the AUR package is installed suffixed, the repo package and
+        // the group under their declared names
+        let installed = set_of(&[]);
+        let mut installs: Vec<PackageAction> = plan_actions(
+            &config,
+            &PackageState::default(),
+            &installed,
+            &pm,
+            "aarch64",
+        )
+        .unwrap();
+        installs.sort_by_key(|action| format!("{:?}", action));
+        assert_eq!(
+            installs,
+            [
+                "archgrp-desktop",
+                "archtest-spotify-aarch64",
+                "archtest-vim"
+            ]
+            .map(|name| {
+                PackageAction::Install {
+                    name: name.to_string(),
+                }
+            })
+        );
+
+        // Other architectures are left alone
+        assert_eq!(
+            arch_names(&config, &installed, &pm, "x86_64").unwrap(),
+            HashMap::new()
+        );
+    }
+
+    fn vec_of(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
     #[test]
     fn test_is_package_installed() {
         let result = is_package_installed("bash");
@@ -187,7 +641,7 @@ mod tests {
     #[test]
     fn test_categorize_packages() {
         let packages = vec!["bash".to_string(), "nonexistentpackage12345".to_string()];
-        let result = categorize_packages(&packages);
+        let result = categorize_packages(&packages, &HashSet::new());
         assert!(result.is_ok());
         let (repo_packages, aur_packages) = result.unwrap();
         assert!(repo_packages.contains(&"bash".to_string()));
@@ -196,7 +650,7 @@ mod tests {
 
     #[test]
     fn test_is_package_group() {
-        let pm = ParuPacman::new();
+        let pm = manager();
         // Test with a known group (using pro-audio as it's in the list)
         let result = pm.is_package_group("pro-audio");
         assert!(result.is_ok());
@@ -215,7 +669,7 @@ mod tests {
 
     #[test]
     fn test_get_group_packages() {
-        let pm = ParuPacman::new();
+        let pm = manager();
         // Test with a known group
         let result = pm.get_group_packages("pro-audio");
         assert!(result.is_ok());
@@ -0,0 +1,316 @@
+//! Reading the arguments of `owl pm -- ARGS` before they reach the package manager
+//!
+//! A one-off paru run (`-U ./foo.pkg.tar.zst`, `-Sc`, `--gendb`) goes through
+//! owl so it gets retries, the writer lock and a history entry. owl has to
+//! know whether the run changes installed packages, and which ones by name, to
+//! update the managed state afterwards. Runs owl could not account for, such
+//! as removals, or that make no sense here are refused up front.
+
+use anyhow::{Result, anyhow};
+
+/// Long options that take the following word as their value
+const VALUE_OPTIONS: &[&str] = &[
+    "arch",
+    "assume-installed",
+    "builddir",
+    "cachedir",
+    "color",
+    "config",
+    "dbpath",
+    "gpgdir",
+    "hookdir",
+    "ignore",
+    "ignoregroup",
+    "logfile",
+    "overwrite",
+    "print-format",
+    "root",
+    "sysroot",
+];
+
+/// Long forms of the operations, as their short letter
+const LONG_OPERATIONS: &[(&str, char)] = &[
+    ("database", 'D'),
+    ("deptest", 'T'),
+    ("files", 'F'),
+    ("getpkgbuild", 'G'),
+    ("query", 'Q'),
+    ("remove", 'R'),
+    ("show", 'P'),
+    ("sync", 'S'),
+    ("upgrade", 'U'),
+];
+
+/// Long forms of the short options classification looks at
+const LONG_LETTERS: &[(&str, char)] = &[
+    ("clean", 'c'),
+    ("dbpath", 'b'),
+    ("downloadonly", 'w'),
+    ("groups", 'g'),
+    ("info", 'i'),
+    ("list", 'l'),
+    ("nodeps", 'd'),
+    ("print", 'p'),
+    ("refresh", 'y'),
+    ("root", 'r'),
+    ("search", 's'),
+    ("sysupgrade", 'u'),
+];
+
+/// Options of `-S` that only read or download
+const SYNC_READ_ONLY: &[char] = &['c', 'g', 'i', 'l', 'p', 's', 'w'];
+
+/// A checked `owl pm` run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    /// Arguments for the package manager, with `--noconfirm` added
+    pub args: Vec<String>,
+    /// The run installs or upgrades packages
+    pub transaction: bool,
+    /// Packages the run installs by name, from `-S` targets and `-U` file names
+    pub installs: Vec<String>,
+}
+
+/// Check `args` and work out what the run does to installed packages
+pub fn classify(args: &[String]) -> Result<Invocation> {
+    if args.is_empty() {
+        return Err(anyhow!(
+            "owl pm needs arguments for the package manager, e.g. owl pm -- -Sc"
+        ));
+    }
+    let mut operations = Vec::new();
+    let mut letters = Vec::new();
+    let mut targets = Vec::new();
+    let mut words = args.iter();
+    while let Some(word) = words.next() {
+        if word == "--" {
+            targets.extend(words.by_ref().cloned());
+        } else if let Some(long) = word.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            if name == "sysroot" {
+                return Err(refused(
+                    word,
+                    "points the package manager at another system",
+                ));
+            }
+            if let Some((_, op)) = LONG_OPERATIONS.iter().find(|(long, _)| *long == name) {
+                operations.push(*op);
+            } else if let Some((_, letter)) = LONG_LETTERS.iter().find(|(long, _)| *long == name) {
+                letters.push(*letter);
+            }
+            if value.is_none() && VALUE_OPTIONS.contains(&name) {
+                words.next();
+            }
+        } else if word.len() > 1 && word.starts_with('-') {
+            for c in word[1..].chars() {
+                if c.is_ascii_uppercase() {
+                    operations.push(c);
+                } else {
+                    letters.push(c);
+                }
+            }
+        } else {
+            targets.push(word.clone());
+        }
+    }
+
+    if letters.contains(&'r') || letters.contains(&'b') {
+        return Err(refused(
+            "--root/--dbpath",
+            "points the package manager at another system",
+        ));
+    }
+    if letters.iter().filter(|c| **c == 'd').count() > 1 {
+        return Err(refused("-dd", "skips every dependency check"));
+    }
+    let (transaction, installs) = match operations.as_slice() {
+        [] if letters.contains(&'c') => {
+            return Err(refused(
+                "-c",
+                "removes unneeded dependencies owl may still manage; run owl apply instead",
+            ));
+        }
+        [] if !targets.is_empty() => {
+            return Err(anyhow!(
+                "owl pm needs an operation such as -S: paru's interactive search cannot \
+                 prompt under owl"
+            ));
+        }
+        ['R'] => {
+            return Err(refused(
+                "-R",
+                "would remove packages owl still considers managed; drop them from your \
+                 config and run owl apply",
+            ));
+        }
+        ['S'] if letters.iter().any(|c| SYNC_READ_ONLY.contains(c)) => (false, Vec::new()),
+        ['S'] if letters.contains(&'y') && !letters.contains(&'u') && !targets.is_empty() => {
+            return Err(refused(
+                "-Sy with targets",
+                "is a partial upgrade; use -Syu with the targets instead",
+            ));
+        }
+        ['S'] if targets.is_empty() && !letters.contains(&'u') => (false, Vec::new()),
+        ['S'] => (true, targets.iter().map(|t| sync_target_name(t)).collect()),
+        ['U'] if letters.contains(&'p') => (false, Vec::new()),
+        ['U'] => (
+            true,
+            targets
+                .iter()
+                .filter_map(|t| package_file_name(t))
+                .collect(),
+        ),
+        [_] | [] => (false, Vec::new()),
+        _ => {
+            return Err(anyhow!(
+                "owl pm takes one operation, got -{}",
+                String::from_iter(&operations)
+            ));
+        }
+    };
+
+    let mut args = args.to_vec();
+    if !args.iter().any(|a| a == "--noconfirm") {
+        // Nothing can answer a prompt under the spinner
+        let at = args.iter().position(|a| a == "--").unwrap_or(args.len());
+        args.insert(at, "--noconfirm".to_string());
+    }
+    Ok(Invocation {
+        args,
+        transaction,
+        installs,
+    })
+}
+
+fn refused(what: &str, why: &str) -> anyhow::Error {
+    anyhow!("owl pm refuses {}: it {}", what, why)
+}
+
+/// `extra/git` installs `git`
+fn sync_target_name(target: &str) -> String {
+    target
+        .rsplit_once('/')
+        .map_or(target, |(_, name)| name)
+        .to_string()
+}
+
+/// `./foo-bar-1.2-1-x86_64.pkg.tar.zst` holds `foo-bar`
+///
+/// Package file names are `NAME-VERSION-RELEASE-ARCH.pkg.tar.*`; the name is
+/// what is left after the last three `-` separated parts.
+pub fn package_file_name(target: &str) -> Option<String> {
+    let file = target.rsplit('/').next()?;
+    let (stem, _) = file.split_once(".pkg.tar")?;
+    let mut parts = stem.rsplitn(4, '-');
+    let (_arch, _release, _version) = (parts.next()?, parts.next()?, parts.next()?);
+    parts
+        .next()
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_classification_table() {
+        let cases: &[(&str, bool, &[&str])] = &[
+            ("-U ./foo-bar-1.2-1-x86_64.pkg.tar.zst", true, &["foo-bar"]),
+            (
+                "--upgrade /tmp/a-1:2.0-3-any.pkg.tar.xz https://x/b-1-1-any.pkg.tar.zst",
+                true,
+                &["a", "b"],
+            ),
+            ("-S git extra/fd", true, &["git", "fd"]),
+            ("-S --needed --overwrite /usr/lib/* git", true, &["git"]),
+            ("-Syu", true, &[]),
+            ("-Syu neovim", true, &["neovim"]),
+            ("-S -y -u", true, &[]),
+            ("-Sy", false, &[]),
+            ("-Sc", false, &[]),
+            ("-Scc", false, &[]),
+            ("-Ss neovim", false, &[]),
+            ("--sync --info git", false, &[]),
+            ("-Sw git", false, &[]),
+            ("-Up ./foo-1-1-any.pkg.tar.zst", false, &[]),
+            ("-Qdt", false, &[]),
+            ("-D --asdeps foo", false, &[]),
+            ("-G neovim-git", false, &[]),
+            ("--gendb", false, &[]),
+        ];
+        for (line, transaction, installs) in cases {
+            let invocation = classify(&words(line)).unwrap();
+            assert_eq!(invocation.transaction, *transaction, "{}", line);
+            assert_eq!(invocation.installs, names(installs), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_deny_list() {
+        let cases = [
+            ("-R foo", "refuses -R"),
+            ("-Rns foo", "refuses -R"),
+            ("--remove foo", "refuses -R"),
+            ("-c", "refuses -c"),
+            ("--clean", "refuses -c"),
+            ("-Sy git", "partial upgrade"),
+            ("-S --root /mnt git", "another system"),
+            ("-S --dbpath=/tmp/db git", "another system"),
+            ("-Sb /tmp/db git", "another system"),
+            (
+                "-U --sysroot /mnt ./a-1-1-any.pkg.tar.zst",
+                "another system",
+            ),
+            ("-Sdd git", "dependency check"),
+            ("-S --nodeps --nodeps git", "dependency check"),
+            ("neovim", "needs an operation"),
+            ("-SQ git", "one operation"),
+        ];
+        for (line, expected) in cases {
+            let err = classify(&words(line)).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", line, err);
+        }
+        assert!(classify(&[]).is_err());
+    }
+
+    #[test]
+    fn test_noconfirm_is_added_once_before_targets_separator() {
+        assert_eq!(
+            classify(&words("-Sc")).unwrap().args,
+            words("-Sc --noconfirm")
+        );
+        assert_eq!(
+            classify(&words("-S --noconfirm git")).unwrap().args,
+            words("-S --noconfirm git")
+        );
+        let invocation = classify(&words("-S -- -weird-name")).unwrap();
+        assert_eq!(invocation.args, words("-S --noconfirm -- -weird-name"));
+        assert_eq!(invocation.installs, names(&["-weird-name"]));
+    }
+
+    #[test]
+    fn test_package_file_name() {
+        assert_eq!(
+            package_file_name("foo-1.0-1-x86_64.pkg.tar.zst").as_deref(),
+            Some("foo")
+        );
+        assert_eq!(
+            package_file_name("/var/cache/lib32-gcc-libs-14.1-1-x86_64.pkg.tar.zst").as_deref(),
+            Some("lib32-gcc-libs")
+        );
+        assert_eq!(package_file_name("foo.tar.gz"), None);
+        assert_eq!(package_file_name("1.0-1-any.pkg.tar.zst"), None);
+    }
+}
@@ -0,0 +1,379 @@
+//! Synthetic workloads for the hot paths, with wall-clock budgets
+//!
+//! Config loading over many groups, dotfile analysis over a large tree and
+//! package planning against a big installed set have no other guard against
+//! slowing down. owl has no library target for a `cargo bench` harness to link
+//! against, so the measurements are ignored tests:
+//!
+//! ```text
+//! cargo test --release -- --ignored --nocapture perf::
+//! ```
+//!
+//! `bench_*` print the median of a few runs. `budget_*` fail when one run
+//! takes longer than a budget loose enough for a slow machine and a debug
+//! build, so only an egregious regression trips them. The generators are
+//! ordinary tests.
+
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::core::aur_builds::AurBuild;
+use crate::core::config::Config;
+use crate::core::dotfiles::{DotfileMapping, DotfileRoots};
+use crate::core::package::PackageAction;
+use crate::core::pm::{PackageInfo, PackageManager, SearchResult};
+use crate::core::state::PackageState;
+
+/// Write `main.owl` naming `groups` group files of `packages` packages each
+///
+/// Every tenth package also has a dotfile and an env var, so the directive
+/// paths are loaded too. Returns the number of packages declared.
+fn config_tree(root: &Path, groups: usize, packages: usize) -> usize {
+    fs::create_dir_all(root.join("groups")).unwrap();
+    let mut main = String::new();
+    for g in 0..groups {
+        main.push_str(&format!("@group g{}\n", g));
+        let mut group = String::new();
+        for p in 0..packages {
+            group.push_str(&format!("@package g{}-pkg{}\n", g, p));
+            if p % 10 == 0 {
+                group.push_str(&format!(
+                    ":config g{g}-{p} -> ~/.config/g{g}-{p}\n:env G{g}_P{p}=on\n"
+                ));
+            }
+        }
+        fs::write(root.join(format!("groups/g{}.owl", g)), group).unwrap();
+    }
+    fs::write(root.join("main.owl"), main).unwrap();
+    groups * packages
+}
+
+/// Write `files` sources of `size` bytes under `root` and map each to its own destination
+///
+/// Sources are spread over directories of 50; every other destination
+/// already has the source's content, so analysis sees both created and
+/// up-to-date files.
+fn dotfiles_tree(root: &Path, files: usize, size: usize) -> (DotfileRoots, Vec<DotfileMapping>) {
+    let home = root.join("home");
+    let roots = DotfileRoots::at(root, &home.to_string_lossy());
+    let mappings = (0..files)
+        .map(|i| {
+            let source = format!("d{}/f{}", i / 50, i);
+            let content = vec![b'a' + (i % 26) as u8; size];
+            let src = roots.source_dir.join(&source);
+            fs::create_dir_all(src.parent().unwrap()).unwrap();
+            fs::write(&src, &content).unwrap();
+            if i % 2 == 1 {
+                let dst = home.join(".bench").join(&source);
+                fs::create_dir_all(dst.parent().unwrap()).unwrap();
+                fs::write(&dst, &content).unwrap();
+            }
+            DotfileMapping {
+                source: source.clone(),
+                destination: format!("~/.bench/{}", source),
+                root: None,
+                from: None,
+                hardlink: false,
+                force_owned: false,
+                modes: Default::default(),
+            }
+        })
+        .collect();
+    (roots, mappings)
+}
+
+/// `installed` packages, half of them declared, a quarter managed but no
+/// longer declared, and a tenth as many new declarations
+fn planner_workload(installed: usize) -> (Config, PackageState, Synthetic) {
+    let mut text = String::new();
+    for i in 0..installed / 2 {
+        text.push_str(&format!("@package pkg{}\n", i));
+    }
+    for i in 0..installed / 10 {
+        // Half the new packages are ordered after an installed one
+        text.push_str(&format!("@package new{}\n", i));
+        if i % 2 == 0 {
+            text.push_str(&format!(":after pkg{}\n", i));
+        }
+    }
+    let state = PackageState {
+        managed: (installed / 2..installed * 3 / 4)
+            .map(|i| format!("pkg{}", i))
+            .collect(),
+        ..Default::default()
+    };
+    let pm = Synthetic {
+        installed: (0..installed).map(|i| format!("pkg{}", i)).collect(),
+    };
+    (Config::parse(&text).unwrap(), state, pm)
+}
+
+fn plan(config: &Config, state: &PackageState, pm: &Synthetic) -> Vec<PackageAction> {
+    crate::core::package::plan_actions(config, state, &pm.list_installed().unwrap(), pm, "x86_64")
+        .unwrap()
+}
+
+/// Median wall-clock time of `runs` calls of `f`
+fn median(runs: usize, mut f: impl FnMut()) -> Duration {
+    let mut times: Vec<Duration> = (0..runs)
+        .map(|_| {
+            let started = Instant::now();
+            f();
+            started.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[runs / 2]
+}
+
+/// Run `f` once and fail if it took longer than `budget`
+fn within_budget(name: &str, budget: Duration, f: impl FnOnce()) {
+    let started = Instant::now();
+    f();
+    let took = started.elapsed();
+    assert!(
+        took <= budget,
+        "{} took {:?}, over its budget of {:?}",
+        name,
+        took,
+        budget
+    );
+}
+
+/// An installed set held in memory; only the queries planning makes are answered
+struct Synthetic {
+    installed: HashSet<String>,
+}
+
+impl Synthetic {
+    fn unused(&self, method: &str) -> ! {
+        panic!("{} is not part of the synthetic workload", method)
+    }
+}
+
+impl PackageManager for Synthetic {
+    fn list_installed(&self) -> Result<HashSet<String>> {
+        Ok(self.installed.clone())
+    }
+    fn list_explicit(&self) -> Result<HashSet<String>> {
+        Ok(self.installed.clone())
+    }
+    fn list_installed_versions(&self) -> Result<HashMap<String, String>> {
+        self.unused("list_installed_versions")
+    }
+    fn list_foreign_versions(&self) -> Result<HashMap<String, String>> {
+        self.unused("list_foreign_versions")
+    }
+    fn batch_repo_available(&self, _: &[String]) -> Result<HashSet<String>> {
+        self.unused("batch_repo_available")
+    }
+    fn upgrade_count(&self) -> Result<usize> {
+        self.unused("upgrade_count")
+    }
+    fn list_upgrades(&self) -> Result<Vec<String>> {
+        self.unused("list_upgrades")
+    }
+    fn get_aur_updates(&self) -> Result<Vec<String>> {
+        self.unused("get_aur_updates")
+    }
+    fn install_repo(&self, _: &[String]) -> Result<()> {
+        self.unused("install_repo")
+    }
+    fn install_aur(&self, _: &[String], _: &mut Vec<AurBuild>) -> Result<()> {
+        self.unused("install_aur")
+    }
+    fn update_repo(&self, _: &[String]) -> Result<()> {
+        self.unused("update_repo")
+    }
+    fn update_aur(&self, _: &[String], _: &[String], _: &mut Vec<AurBuild>) -> Result<()> {
+        self.unused("update_aur")
+    }
+    fn remove_packages(&self, _: &[String], _: bool) -> Result<()> {
+        self.unused("remove_packages")
+    }
+    fn search_packages(&self, _: &[String]) -> Result<Vec<SearchResult>> {
+        self.unused("search_packages")
+    }
+    fn is_package_group(&self, _: &str) -> Result<bool> {
+        Ok(false)
+    }
+    fn get_group_packages(&self, _: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+    fn group_members(&self, _: &[String]) -> Result<HashMap<String, Vec<String>>> {
+        Ok(HashMap::new())
+    }
+    fn installed_info(&self) -> Result<Vec<PackageInfo>> {
+        self.unused("installed_info")
+    }
+    fn sync_info(&self, _: &[String]) -> Result<Vec<PackageInfo>> {
+        self.unused("sync_info")
+    }
+    fn owner_of(&self, _: &[PathBuf]) -> Result<HashMap<PathBuf, String>> {
+        Ok(HashMap::new())
+    }
+    fn passthrough(&self, _: &[String]) -> Result<std::process::ExitStatus> {
+        self.unused("passthrough")
+    }
+    fn list_orphans(&self) -> Result<Vec<String>> {
+        self.unused("list_orphans")
+    }
+    fn clean_cache(&self) -> Result<()> {
+        self.unused("clean_cache")
+    }
+    fn remove_orphans(&self) -> Result<()> {
+        self.unused("remove_orphans")
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::core::dotfiles::{DotfileStatus, analyze_dotfiles};
+
+    const CONCURRENCY: usize = 8;
+
+    #[test]
+    fn test_config_tree_shape() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(config_tree(dir.path(), 3, 20), 60);
+        assert_eq!(fs::read_dir(dir.path().join("groups")).unwrap().count(), 3);
+
+        let config = Config::load_for_host(dir.path(), "bench").unwrap();
+        assert_eq!(config.packages.len(), 60);
+        assert_eq!(config.group_origins.len(), 3);
+        let with_dotfiles = config
+            .packages
+            .values()
+            .filter(|p| !p.config.is_empty())
+            .count();
+        assert_eq!(with_dotfiles, 6);
+        assert!(config.packages["g2-pkg10"].env_vars.contains_key("G2_P10"));
+    }
+
+    #[test]
+    fn test_dotfiles_tree_shape() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = dotfiles_tree(dir.path(), 120, 64);
+        assert_eq!(mappings.len(), 120);
+        assert_eq!(fs::read_dir(&roots.source_dir).unwrap().count(), 3);
+        let source = roots.source_dir.join("d2/f119");
+        assert_eq!(fs::metadata(source).unwrap().len(), 64);
+
+        let statuses = analyze_dotfiles(&roots, &mappings, CONCURRENCY).unwrap();
+        let created = statuses
+            .iter()
+            .filter(|s| **s == DotfileStatus::Create)
+            .count();
+        let current = statuses
+            .iter()
+            .filter(|s| **s == DotfileStatus::UpToDate)
+            .count();
+        assert_eq!((created, current), (60, 60));
+    }
+
+    #[test]
+    fn test_planner_workload_shape() {
+        let (config, state, pm) = planner_workload(200);
+        assert_eq!(pm.installed.len(), 200);
+        assert_eq!(config.packages.len(), 120);
+        assert_eq!(state.managed.len(), 50);
+
+        let actions = plan(&config, &state, &pm);
+        let installs: Vec<&PackageAction> = actions
+            .iter()
+            .filter(|a| matches!(a, PackageAction::Install { .. }))
+            .collect();
+        assert_eq!(installs.len(), 20);
+        assert_eq!(actions.len() - installs.len(), 50);
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    fn bench_config_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let packages = config_tree(dir.path(), 50, 200);
+        let time = median(5, || {
+            Config::load_for_host(dir.path(), "bench").unwrap();
+        });
+        println!("config load, 50 groups, {} packages: {:?}", packages, time);
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    fn bench_doctor() {
+        let dir = tempfile::tempdir().unwrap();
+        config_tree(dir.path(), 40, 50);
+        let home = dir.path().to_string_lossy();
+        let time = median(5, || {
+            crate::core::doctor::diagnose(dir.path(), &home, Some("bench"), &|_| true);
+        });
+        println!("doctor, 41 files, 200 mappings: {:?}", time);
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    fn bench_analyze_dotfiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = dotfiles_tree(dir.path(), 2000, 4096);
+        let time = median(5, || {
+            analyze_dotfiles(&roots, &mappings, CONCURRENCY).unwrap();
+        });
+        println!("analyze_dotfiles, 2000 files of 4 KiB: {:?}", time);
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    fn bench_plan_package_actions() {
+        let (config, state, pm) = planner_workload(20_000);
+        let time = median(5, || {
+            plan(&config, &state, &pm);
+        });
+        println!("plan_package_actions, 20000 installed: {:?}", time);
+    }
+
+    /// The tree of a `owl doctor` pre-commit hook, checked warm
+    #[test]
+    #[ignore = "performance budget; run with --ignored"]
+    fn budget_doctor() {
+        let dir = tempfile::tempdir().unwrap();
+        config_tree(dir.path(), 40, 50);
+        let home = dir.path().to_string_lossy();
+        let diagnose = || {
+            crate::core::doctor::diagnose(dir.path(), &home, Some("bench"), &|_| true);
+        };
+        diagnose();
+        within_budget("doctor", Duration::from_millis(300), diagnose);
+    }
+
+    #[test]
+    #[ignore = "performance budget; run with --ignored"]
+    fn budget_config_load() {
+        let dir = tempfile::tempdir().unwrap();
+        config_tree(dir.path(), 50, 200);
+        within_budget("config load", Duration::from_secs(10), || {
+            Config::load_for_host(dir.path(), "bench").unwrap();
+        });
+    }
+
+    #[test]
+    #[ignore = "performance budget; run with --ignored"]
+    fn budget_analyze_dotfiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let (roots, mappings) = dotfiles_tree(dir.path(), 2000, 4096);
+        within_budget("analyze_dotfiles", Duration::from_secs(10), || {
+            analyze_dotfiles(&roots, &mappings, CONCURRENCY).unwrap();
+        });
+    }
+
+    #[test]
+    #[ignore = "performance budget; run with --ignored"]
+    fn budget_plan_package_actions() {
+        let (config, state, pm) = planner_workload(20_000);
+        within_budget("plan_package_actions", Duration::from_secs(30), || {
+            plan(&config, &state, &pm);
+        });
+    }
+}
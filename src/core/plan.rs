@@ -0,0 +1,233 @@
+//! `owl apply --plan-json`: the package plan with what an orchestrator needs to
+//! schedule the run
+//!
+//! Each package change carries its repository, sizes, whether it is on the
+//! reboot-advised list (`@option reboot_advised`) and which declared services
+//! depend on it; the top-level flags summarise those. Deriving the plan only
+//! reads the planned changes, the sync records and the config.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::core::config::Config;
+use crate::core::pm::PackageInfo;
+
+/// Version of the `--plan-json` document, bumped on incompatible changes
+///
+/// New fields do not bump it; consumers should ignore what they do not know.
+pub const PLAN_SCHEMA_VERSION: u32 = 1;
+
+/// Packages whose update usually needs a reboot to take effect
+pub const DEFAULT_REBOOT_ADVISED: &[&str] = &["linux*", "systemd", "glibc"];
+
+/// What the run does to a package
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Install,
+    Upgrade,
+    Remove,
+}
+
+/// One package change with its scheduling metadata
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackageChange {
+    pub name: String,
+    pub change: ChangeKind,
+    /// Sync repository, `aur` for packages none provides, absent for removals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    pub reboot_advised: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installed_size: Option<u64>,
+    /// Declared services that depend on the package
+    pub services: Vec<String>,
+}
+
+/// The document `--plan-json` prints
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApplyPlan {
+    pub schema: u32,
+    /// Some change is on the reboot-advised list
+    pub reboot_advised: bool,
+    /// Some change touches a package a declared service depends on
+    pub service_restarts_planned: bool,
+    pub packages: Vec<PackageChange>,
+    /// Declared absent paths and what they need (`:absent`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub absent: Vec<crate::core::absent::AbsentEntry>,
+}
+
+/// Packages the run changes, by kind
+#[derive(Debug, Clone, Default)]
+pub struct PlannedChanges {
+    pub installs: Vec<String>,
+    pub upgrades: Vec<String>,
+    pub removals: Vec<String>,
+}
+
+/// Build the plan from `changes`, the `pacman -Si` records of the installed
+/// and upgraded packages, and the config
+pub fn derive(changes: &PlannedChanges, sync_info: &[PackageInfo], config: &Config) -> ApplyPlan {
+    let info: HashMap<&str, &PackageInfo> =
+        sync_info.iter().map(|i| (i.name.as_str(), i)).collect();
+    let patterns = config.reboot_advised();
+    let kinds = [
+        (ChangeKind::Install, &changes.installs),
+        (ChangeKind::Upgrade, &changes.upgrades),
+        (ChangeKind::Remove, &changes.removals),
+    ];
+    let packages: Vec<PackageChange> = kinds
+        .into_iter()
+        .flat_map(|(change, names)| names.iter().map(move |name| (change, name)))
+        .map(|(change, name)| {
+            // `@arch-aur-prefix` installs some packages under another name
+            let declared = config.declared_name(name, std::env::consts::ARCH);
+            // `[aur]` builds from the AUR even when a repository has the name
+            let forced_aur = config.packages.get(declared).is_some_and(|p| p.aur);
+            let info = info
+                .get(name.as_str())
+                .filter(|_| change != ChangeKind::Remove && !forced_aur);
+            let repo = match (change, info) {
+                (ChangeKind::Remove, _) => None,
+                (_, Some(info)) => info.repo.clone(),
+                (_, None) => Some("aur".to_string()),
+            };
+            PackageChange {
+                name: name.clone(),
+                change,
+                repo,
+                reboot_advised: patterns
+                    .iter()
+                    .any(|pattern| crate::internal::util::glob_match(pattern, name)),
+                download_size: info.and_then(|i| i.download_size),
+                installed_size: info.and_then(|i| i.installed_size),
+                services: dependent_services(config, declared),
+            }
+        })
+        .collect();
+    ApplyPlan {
+        schema: PLAN_SCHEMA_VERSION,
+        reboot_advised: packages.iter().any(|p| p.reboot_advised),
+        service_restarts_planned: packages.iter().any(|p| !p.services.is_empty()),
+        packages,
+        absent: Vec::new(),
+    }
+}
+
+/// Services of `package` itself and of packages ordered `:after` it, sorted
+pub fn dependent_services(config: &Config, package: &str) -> Vec<String> {
+    let mut services: Vec<String> = config
+        .packages
+        .iter()
+        .filter(|(name, pkg)| name.as_str() == package || pkg.after.iter().any(|a| a == package))
+        .filter_map(|(_, pkg)| pkg.service.clone())
+        .collect();
+    services.sort();
+    services.dedup();
+    services
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn sync(name: &str, repo: &str, size: u64) -> PackageInfo {
+        PackageInfo {
+            name: name.to_string(),
+            repo: Some(repo.to_string()),
+            download_size: Some(size),
+            installed_size: Some(size * 2),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_derive_plan_metadata() {
+        let config = Config::parse(
+            "@package postgresql\n:service postgresql\n\
+             @package pgbouncer\n:after postgresql\n:service pgbouncer\n\
+             @package yay [aur]\n@package neovim\n",
+        )
+        .unwrap();
+        let changes = PlannedChanges {
+            installs: names(&["neovim", "yay", "paru-bin"]),
+            upgrades: names(&["linux-lts", "postgresql"]),
+            removals: names(&["systemd-resolvconf", "glibc"]),
+        };
+        let info = [
+            sync("neovim", "extra", 10),
+            sync("yay", "extra", 1),
+            sync("linux-lts", "core", 100),
+            sync("postgresql", "extra", 20),
+            sync("glibc", "core", 5),
+        ];
+
+        let plan = derive(&changes, &info, &config);
+        let by_name: HashMap<&str, &PackageChange> =
+            plan.packages.iter().map(|p| (p.name.as_str(), p)).collect();
+
+        assert_eq!(plan.schema, PLAN_SCHEMA_VERSION);
+        assert_eq!(plan.packages.len(), 7);
+        assert_eq!(
+            *by_name["neovim"],
+            PackageChange {
+                name: "neovim".to_string(),
+                change: ChangeKind::Install,
+                repo: Some("extra".to_string()),
+                reboot_advised: false,
+                download_size: Some(10),
+                installed_size: Some(20),
+                services: Vec::new(),
+            }
+        );
+        // [aur] wins over a repository of the same name; unknown names are AUR
+        assert_eq!(by_name["yay"].repo.as_deref(), Some("aur"));
+        assert_eq!(by_name["yay"].download_size, None);
+        assert_eq!(by_name["paru-bin"].repo.as_deref(), Some("aur"));
+        assert!(by_name["linux-lts"].reboot_advised);
+        assert_eq!(
+            by_name["postgresql"].services,
+            names(&["pgbouncer.service", "postgresql.service"])
+        );
+        // Removals have no repo or sizes, but still match the reboot list
+        assert_eq!(by_name["glibc"].repo, None);
+        assert_eq!(by_name["glibc"].installed_size, None);
+        assert!(by_name["glibc"].reboot_advised);
+        assert!(!by_name["systemd-resolvconf"].reboot_advised);
+        assert!(plan.reboot_advised);
+        assert!(plan.service_restarts_planned);
+    }
+
+    #[test]
+    fn test_reboot_list_from_option_and_empty_plan() {
+        let config = Config::parse("@option reboot_advised=nvidia*, mesa\n").unwrap();
+        let changes = PlannedChanges {
+            upgrades: names(&["linux", "nvidia-dkms"]),
+            ..Default::default()
+        };
+        let plan = derive(&changes, &[], &config);
+        assert!(!plan.packages[0].reboot_advised);
+        assert!(plan.packages[1].reboot_advised);
+        assert!(!plan.service_restarts_planned);
+
+        let empty = derive(&PlannedChanges::default(), &[], &Config::new());
+        assert!(!empty.reboot_advised);
+        assert!(empty.packages.is_empty());
+        assert_eq!(
+            serde_json::to_value(&empty).unwrap(),
+            serde_json::json!({
+                "schema": 1,
+                "reboot_advised": false,
+                "service_restarts_planned": false,
+                "packages": [],
+            })
+        );
+    }
+}
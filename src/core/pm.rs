@@ -1,7 +1,9 @@
+use crate::core::aur_builds::{self, AurBuild};
+use crate::internal::util::command::{CommandOutcome, RawOutput};
 use anyhow::{Result, anyhow};
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::io::Write;
+use std::path::PathBuf;
 use std::process::Command;
 use std::sync::{Mutex, OnceLock};
 use std::thread;
@@ -34,20 +36,15 @@ where
                 // Exponential backoff: 1s, 2s, 4s, 8s, 16s
                 let delay = Duration::from_secs(1 << attempt);
 
-                // Clear the current line and show retry status
-                print!("\r\x1b[2K");
-                std::io::stdout().flush().ok();
-                print!(
+                // Show the retry status in place of the spinner
+                let output = crate::internal::output::get();
+                output.status(&format!(
                     "Retrying due to network errors... ({}/{})",
                     attempt + 1,
                     max_retries + 1
-                );
-                std::io::stdout().flush().ok();
+                ));
                 thread::sleep(delay);
-
-                // Clear the line for the next operation
-                print!("\r\x1b[2K");
-                std::io::stdout().flush().ok();
+                output.clear_status();
             }
         }
     }
@@ -55,6 +52,65 @@ where
     Err(last_error.unwrap_or_else(|| anyhow!("Unknown error")))
 }
 
+/// pacman's messages when a mirror is stale (files it no longer has) or down
+const MIRROR_FAILURES: &[&str] = &[
+    "failed retrieving file",
+    "failed to retrieve some files",
+    "failed to synchronize all databases",
+    "returned error: 404",
+];
+
+/// Run a repo sync or install, and when it fails on a mirror twice in a row run
+/// `refresh` (`@option mirror_refresh`) once before the last attempt
+///
+/// Retrying alone hits the same mirror again. Without `refresh` the operation
+/// runs once, as it always has.
+pub fn with_mirror_refresh<T>(
+    refresh: Option<&str>,
+    mut operation: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let Some(refresh) = refresh else {
+        return operation();
+    };
+    let mut mirror_failures = 0;
+    loop {
+        match operation() {
+            Err(err) if mirror_failures < 2 && is_mirror_failure(&err.to_string()) => {
+                mirror_failures += 1;
+                if mirror_failures == 2 {
+                    refresh_mirrors(refresh)
+                        .map_err(|e| anyhow!("{}; after the sync failed with: {}", e, err))?;
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_mirror_failure(message: &str) -> bool {
+    MIRROR_FAILURES
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+fn refresh_mirrors(command: &str) -> Result<()> {
+    let (status, stderr) = crate::internal::util::execute_command_with_stderr_capture(
+        "sh",
+        &["-c", command],
+        "Refreshing mirrors after repeated sync failures",
+        None,
+    )?;
+    if !status.success() {
+        return Err(anyhow!(
+            "Mirror refresh `{}` failed (exit code: {:?}): {}",
+            command,
+            status.code(),
+            stderr_tail(&stderr)
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum PackageSource {
     Repo,
@@ -71,36 +127,251 @@ pub struct SearchResult {
     pub installed: bool,
 }
 
+/// The fields of one `pacman -Qi` or `-Si` record owl reads
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    /// Packages this one replaces (`Replaces`), e.g. after a rename upstream
+    pub replaces: Vec<String>,
+    /// Sync repository (`-Si` only)
+    pub repo: Option<String>,
+    /// Bytes to download (`-Si` only)
+    pub download_size: Option<u64>,
+    /// Bytes on disk once installed
+    pub installed_size: Option<u64>,
+}
+
 pub trait PackageManager {
     fn list_installed(&self) -> Result<HashSet<String>>;
+    fn list_explicit(&self) -> Result<HashSet<String>>;
+    fn list_installed_versions(&self) -> Result<HashMap<String, String>>;
+    /// Installed packages no sync database provides (AUR and local builds) with their versions
+    fn list_foreign_versions(&self) -> Result<HashMap<String, String>>;
     fn batch_repo_available(&self, packages: &[String]) -> Result<HashSet<String>>;
     fn upgrade_count(&self) -> Result<usize>;
+    /// Installed packages with a newer version available, repo and AUR
+    fn list_upgrades(&self) -> Result<Vec<String>>;
     fn get_aur_updates(&self) -> Result<Vec<String>>;
     fn install_repo(&self, packages: &[String]) -> Result<()>;
-    fn install_aur(&self, packages: &[String]) -> Result<()>;
-    fn update_repo(&self) -> Result<()>;
-    fn update_aur(&self, packages: &[String]) -> Result<()>;
+    /// Install from the AUR, appending what happened to each package to `builds`
+    fn install_aur(&self, packages: &[String], builds: &mut Vec<AurBuild>) -> Result<()>;
+    /// `-Syu` of the repository packages, leaving out `held` (`[no-upgrade]`)
+    fn update_repo(&self, held: &[String]) -> Result<()>;
+    /// Update from the AUR, leaving out `held` and appending what happened to
+    /// each package to `builds`
+    fn update_aur(
+        &self,
+        packages: &[String],
+        held: &[String],
+        builds: &mut Vec<AurBuild>,
+    ) -> Result<()>;
     fn remove_packages(&self, packages: &[String], quiet: bool) -> Result<()>;
     fn search_packages(&self, terms: &[String]) -> Result<Vec<SearchResult>>;
     fn is_package_group(&self, package_name: &str) -> Result<bool>;
     fn get_group_packages(&self, group_name: &str) -> Result<Vec<String>>;
+    /// Members of each of `names` that is a package group, in at most one
+    /// `pacman -Sgg` call; names that are not groups are left out
+    fn group_members(&self, names: &[String]) -> Result<HashMap<String, Vec<String>>>;
+    /// Every installed package's `pacman -Qi` record
+    fn installed_info(&self) -> Result<Vec<PackageInfo>>;
+    /// The `pacman -Si` records of `packages`, in one call; names no sync
+    /// repository has (AUR packages) are left out
+    fn sync_info(&self, packages: &[String]) -> Result<Vec<PackageInfo>>;
+    /// Packages owning each of `paths`, in one `pacman -Qo` call; unowned paths are left out
+    fn owner_of(&self, paths: &[PathBuf]) -> Result<HashMap<PathBuf, String>>;
+    /// Run the package manager with `args` as given (`owl pm`), output on the
+    /// spinner and retried on network errors
+    fn passthrough(&self, args: &[String]) -> Result<std::process::ExitStatus>;
+    /// Packages installed as dependencies that nothing installed needs (`pacman -Qdtq`)
+    fn list_orphans(&self) -> Result<Vec<String>>;
+    /// Remove cached packages that are not installed and AUR build files
+    fn clean_cache(&self) -> Result<()>;
+    /// Remove every orphaned dependency
+    fn remove_orphans(&self) -> Result<()>;
+}
+
+/// `paru -Sc`: pacman's cache of packages no longer installed, then paru's AUR clones
+pub const CLEAN_CACHE_ARGS: &[&str] = &["-Sc", "--noconfirm"];
+
+/// `paru -c`: dependencies nothing installed needs any more
+pub const REMOVE_ORPHANS_ARGS: &[&str] = &["-c", "--noconfirm"];
+
+pub struct ParuPacman {
+    paru: String,
+    pacman: String,
+    /// owl runs as root, so AUR builds need a build user
+    root: bool,
 }
 
-pub struct ParuPacman;
 impl ParuPacman {
     pub fn new() -> Self {
-        Self
+        Self {
+            paru: crate::internal::constants::PACKAGE_MANAGER.to_string(),
+            pacman: "pacman".to_string(),
+            root: crate::core::privilege::is_root(),
+        }
+    }
+
+    /// Run against other binaries, e.g. fake scripts in tests
+    #[cfg(test)]
+    fn with_programs(paru: &str, pacman: &str) -> Self {
+        Self {
+            paru: paru.to_string(),
+            pacman: pacman.to_string(),
+            root: false,
+        }
     }
 }
 
+/// Builds the package manager `manager()` hands out in tests
+#[cfg(test)]
+pub type ManagerFactory = fn() -> Box<dyn PackageManager>;
+
+#[cfg(test)]
+thread_local! {
+    static MANAGER_OVERRIDE: std::cell::Cell<Option<ManagerFactory>> =
+        const { std::cell::Cell::new(None) };
+}
+
+/// The package manager owl drives; every package query and transaction goes through it
+pub fn manager() -> Box<dyn PackageManager> {
+    #[cfg(test)]
+    if let Some(make) = MANAGER_OVERRIDE.with(|o| o.get()) {
+        return make();
+    }
+    Box::new(ParuPacman::new())
+}
+
+/// Run `f` with `manager()` on this thread returning what `make` builds
+#[cfg(test)]
+pub fn with_manager<T>(make: ManagerFactory, f: impl FnOnce() -> T) -> T {
+    let previous = MANAGER_OVERRIDE.with(|o| o.replace(Some(make)));
+    let result = f();
+    MANAGER_OVERRIDE.with(|o| o.set(previous));
+    result
+}
+
+/// Package manager invocations, each with its own reading of exit codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    /// `-Qu` / `-Qua`: exit 1 with empty stderr means no updates
+    QueryUpdates,
+    /// `pacman -Si`: exits 1 when any requested package is unknown
+    RepoInfo,
+    /// `-S`: pacman aborts the transaction on unknown targets
+    Install,
+    /// `-Rns`: pacman aborts the transaction on targets that are not installed
+    Remove,
+    /// `--repo -Syu`: exit 1 without an error line means nothing was upgraded
+    RepoUpdate,
+    /// `--aur -Syu <pkgs>`
+    AurUpdate,
+}
+
+impl Operation {
+    /// Map a finished command to its outcome; `requested` are the targets passed to it
+    pub fn interpret(self, requested: &[String], raw: &RawOutput) -> CommandOutcome {
+        if raw.code == Some(0) {
+            return CommandOutcome::Success;
+        }
+        let failure = || CommandOutcome::Failure {
+            stderr: raw.stderr.trim().to_string(),
+        };
+        // Only transactions take the lock; queries report it as any other failure
+        let transaction = !matches!(self, Operation::QueryUpdates | Operation::RepoInfo);
+        if transaction && crate::core::db_lock::is_locked_message(&raw.stderr) {
+            return CommandOutcome::DatabaseLocked {
+                stderr: raw.stderr.trim().to_string(),
+            };
+        }
+        match self {
+            Operation::QueryUpdates if raw.code == Some(1) && raw.stderr.trim().is_empty() => {
+                CommandOutcome::NoChanges
+            }
+            Operation::RepoUpdate if raw.code == Some(1) && !has_error_line(&raw.stderr) => {
+                CommandOutcome::NoChanges
+            }
+            Operation::RepoInfo => {
+                let missing = error_targets(&raw.stderr, "error: package '", "' was not found");
+                if missing.is_empty() {
+                    failure()
+                } else if missing.len() < requested.len() {
+                    CommandOutcome::PartialSuccess {
+                        details: not_found_in_repos(&missing, requested.len()),
+                    }
+                } else {
+                    CommandOutcome::NotFound { names: missing }
+                }
+            }
+            Operation::Install | Operation::Remove | Operation::AurUpdate => {
+                let missing = error_targets(&raw.stderr, "error: target not found: ", "");
+                if missing.is_empty() {
+                    failure()
+                } else {
+                    CommandOutcome::NotFound { names: missing }
+                }
+            }
+            Operation::QueryUpdates | Operation::RepoUpdate => failure(),
+        }
+    }
+}
+
+/// `2 of 5 packages not found in repos: foo, bar`
+pub fn not_found_in_repos(missing: &[String], requested: usize) -> String {
+    format!(
+        "{} of {} packages not found in repos: {}",
+        missing.len(),
+        requested,
+        missing.join(", ")
+    )
+}
+
+fn has_error_line(stderr: &str) -> bool {
+    stderr.lines().any(|line| line.trim().starts_with("error:"))
+}
+
+/// Names from stderr lines of the form `<prefix>NAME<suffix>`
+fn error_targets(stderr: &str, prefix: &str, suffix: &str) -> Vec<String> {
+    let mut names: Vec<String> = stderr
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(prefix)?.strip_suffix(suffix))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.dedup();
+    names
+}
+
+/// The error for a transaction pacman refused because its database was locked
+fn locked(stderr: String) -> anyhow::Error {
+    anyhow::Error::new(crate::core::db_lock::DatabaseLocked { stderr })
+}
+
+/// Last few lines of stderr, for error messages
+fn stderr_tail(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr.trim().lines().collect();
+    lines[lines.len().saturating_sub(3)..].join("\n")
+}
+
 // Cache for package groups to avoid repeated pacman -Sg calls
 static GROUP_CACHE: OnceLock<Mutex<HashMap<String, bool>>> = OnceLock::new();
 static GROUP_PACKAGES_CACHE: OnceLock<Mutex<HashMap<String, Vec<String>>>> = OnceLock::new();
 
-impl PackageManager for ParuPacman {
-    fn list_installed(&self) -> Result<HashSet<String>> {
-        let output = Command::new(crate::internal::constants::PACKAGE_MANAGER)
-            .arg("-Qq")
+/// Forget which names are groups and what they hold
+pub fn clear_group_caches() {
+    if let Some(cache) = GROUP_CACHE.get() {
+        cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+    if let Some(cache) = GROUP_PACKAGES_CACHE.get() {
+        cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+impl ParuPacman {
+    fn query_package_names(&self, flag: &str) -> Result<HashSet<String>> {
+        let output = Command::new(&self.paru)
+            .arg(flag)
             .output()
             .map_err(|e| anyhow::anyhow!("Failed to get installed packages: {}", e))?;
         if !output.status.success() {
@@ -118,6 +389,106 @@ impl PackageManager for ParuPacman {
             .collect::<HashSet<_>>();
         Ok(installed)
     }
+}
+
+impl ParuPacman {
+    /// Run a cleanup transaction on the spinner
+    fn maintenance(&self, args: &[&str], message: &str) -> Result<()> {
+        let (status, stderr) = crate::internal::util::execute_command_with_stderr_capture(
+            &self.paru, args, message, None,
+        )?;
+        if status.success() {
+            return Ok(());
+        }
+        if crate::core::db_lock::is_locked_message(&stderr) {
+            return Err(locked(stderr.trim().to_string()));
+        }
+        Err(anyhow!(
+            "{} {} failed (exit code: {:?}): {}",
+            crate::internal::constants::PACKAGE_MANAGER,
+            args.join(" "),
+            status.code(),
+            stderr_tail(&stderr)
+        ))
+    }
+
+    /// The program and arguments of an AUR transaction: paru itself, or
+    /// through `sudo -u` as `build_user` when owl runs as root
+    fn aur_command(
+        &self,
+        args: Vec<String>,
+        build_user: Option<&str>,
+    ) -> Result<(String, Vec<String>)> {
+        let Some(user) = crate::core::privilege::aur_user(self.root, build_user)? else {
+            return Ok((self.paru.clone(), args));
+        };
+        let mut sudo: Vec<String> = ["-u", user, "-H", "--", &self.paru]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        sudo.extend(args);
+        Ok(("sudo".to_string(), sudo))
+    }
+
+    /// Run `-Rns`, keeping the prompt interactive while capturing stderr
+    fn run_remove(&self, packages: &[String], quiet: bool) -> Result<CommandOutcome> {
+        let mut cmd = Command::new(&self.paru);
+        cmd.arg("-Rns");
+        if quiet {
+            cmd.arg("--noconfirm");
+        }
+        cmd.args(packages)
+            .stdin(std::process::Stdio::inherit())
+            .stdout(std::process::Stdio::inherit());
+        let output: RawOutput = cmd
+            .output()
+            .map_err(|e| anyhow::anyhow!("Failed to remove packages: {}", e))?
+            .into();
+        Ok(Operation::Remove.interpret(packages, &output))
+    }
+}
+
+impl PackageManager for ParuPacman {
+    fn list_installed(&self) -> Result<HashSet<String>> {
+        self.query_package_names("-Qq")
+    }
+
+    /// Packages installed explicitly rather than as dependencies (`-Qqe`)
+    fn list_explicit(&self) -> Result<HashSet<String>> {
+        self.query_package_names("-Qqe")
+    }
+
+    /// Installed package names mapped to their full versions (`-Q`)
+    fn list_installed_versions(&self) -> Result<HashMap<String, String>> {
+        let output = Command::new(&self.paru)
+            .arg("-Q")
+            .output()
+            .map_err(|e| anyhow::anyhow!("Failed to get installed package versions: {}", e))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Package manager failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(parse_version_list(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Foreign packages with their versions (`pacman -Qm`), without paru
+    fn list_foreign_versions(&self) -> Result<HashMap<String, String>> {
+        let output = Command::new(&self.pacman)
+            .arg("-Qm")
+            .output()
+            .map_err(|e| anyhow::anyhow!("Failed to list foreign packages: {}", e))?;
+        // pacman exits 1 without output when no package is foreign
+        let no_foreign = output.stdout.is_empty() && output.stderr.is_empty();
+        if !output.status.success() && !no_foreign {
+            return Err(anyhow::anyhow!(
+                "pacman -Qm failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(parse_version_list(&String::from_utf8_lossy(&output.stdout)))
+    }
 
     fn batch_repo_available(&self, packages: &[String]) -> Result<HashSet<String>> {
         if packages.is_empty() {
@@ -125,16 +496,22 @@ impl PackageManager for ParuPacman {
         }
 
         // Use a single pacman call for all packages to improve performance
-        let mut cmd = Command::new("pacman");
+        let mut cmd = Command::new(&self.pacman);
         cmd.arg("-Si");
         cmd.args(packages);
 
-        let output = cmd
+        let output: RawOutput = cmd
             .output()
-            .map_err(|e| anyhow::anyhow!("Failed to check package info: {}", e))?;
+            .map_err(|e| anyhow::anyhow!("Failed to check package info: {}", e))?
+            .into();
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let repo_names = stdout
+        // Unknown names are expected here: they are AUR packages
+        if let CommandOutcome::Failure { stderr } = Operation::RepoInfo.interpret(packages, &output)
+        {
+            return Err(anyhow!("pacman -Si failed: {}", stderr_tail(&stderr)));
+        }
+        let repo_names = output
+            .stdout
             .lines()
             .filter_map(|line| {
                 line.strip_prefix("Name")
@@ -155,32 +532,28 @@ impl PackageManager for ParuPacman {
     }
 
     fn upgrade_count(&self) -> Result<usize> {
+        Ok(self.list_upgrades()?.len())
+    }
+
+    fn list_upgrades(&self) -> Result<Vec<String>> {
         retry_command(
             || {
-                let output = Command::new(crate::internal::constants::PACKAGE_MANAGER)
+                let output: RawOutput = Command::new(&self.paru)
                     .args(["-Qu", "-q"])
                     .output()
-                    .map_err(|e| {
-                        anyhow::anyhow!(
-                            "Failed to run {} -Qu: {}",
-                            crate::internal::constants::PACKAGE_MANAGER,
-                            e
-                        )
-                    })?;
-                if output.status.success() {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    Ok(stdout.lines().count())
-                } else {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    if output.status.code() == Some(1) && stderr.trim().is_empty() {
-                        Ok(0)
-                    } else {
-                        Err(anyhow::anyhow!(
-                            "{} -Qu failed: {}",
-                            crate::internal::constants::PACKAGE_MANAGER,
-                            stderr
-                        ))
+                    .map_err(|e| anyhow::anyhow!("Failed to run {} -Qu: {}", self.paru, e))?
+                    .into();
+                match Operation::QueryUpdates.interpret(&[], &output) {
+                    CommandOutcome::NoChanges => Ok(Vec::new()),
+                    CommandOutcome::Failure { stderr } => {
+                        Err(anyhow::anyhow!("{} -Qu failed: {}", self.paru, stderr))
                     }
+                    _ => Ok(output
+                        .stdout
+                        .lines()
+                        .filter_map(|line| line.split_whitespace().next())
+                        .map(str::to_string)
+                        .collect()),
                 }
             },
             3, // Max 3 retries
@@ -190,31 +563,22 @@ impl PackageManager for ParuPacman {
     fn get_aur_updates(&self) -> Result<Vec<String>> {
         retry_command(
             || {
-                let output = Command::new(crate::internal::constants::PACKAGE_MANAGER)
+                let output: RawOutput = Command::new(&self.paru)
                     .args(["-Qua", "-q"])
                     .output()
-                    .map_err(|e| anyhow::anyhow!("Failed to check AUR updates: {}", e))?;
-                if output.status.success() {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let packages: Vec<String> = stdout
-                        .lines()
-                        .filter_map(|line| {
-                            let l = line.trim();
-                            if l.is_empty() {
-                                return None;
-                            }
-                            Some(l.split_whitespace().next().unwrap_or(l).to_string())
-                        })
-                        .collect();
-                    Ok(packages)
-                } else {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    if output.status.code() == Some(1) && stderr.trim().is_empty() {
-                        // Treat as no updates
-                        Ok(Vec::new())
-                    } else {
+                    .map_err(|e| anyhow::anyhow!("Failed to check AUR updates: {}", e))?
+                    .into();
+                match Operation::QueryUpdates.interpret(&[], &output) {
+                    CommandOutcome::NoChanges => Ok(Vec::new()),
+                    CommandOutcome::Failure { stderr } => {
                         Err(anyhow::anyhow!("AUR update check failed: {}", stderr))
                     }
+                    _ => Ok(output
+                        .stdout
+                        .lines()
+                        .filter_map(|line| line.split_whitespace().next())
+                        .map(str::to_string)
+                        .collect()),
                 }
             },
             3, // Max 3 retries
@@ -227,18 +591,27 @@ impl PackageManager for ParuPacman {
         }
         let mut args = vec!["--repo", "-S", "--noconfirm"];
         args.extend(packages.iter().map(|s| s.as_str()));
-        let status = crate::internal::util::execute_command_with_spinner(
-            crate::internal::constants::PACKAGE_MANAGER,
+        let (status, stderr) = crate::internal::util::execute_command_with_stderr_capture(
+            &self.paru,
             &args,
             &format!("Installing {} repo packages", packages.len()),
+            None,
         )?;
-        if !status.success() {
-            return Err(anyhow::anyhow!("Repository install failed"));
+        match Operation::Install.interpret(packages, &RawOutput::with_stderr(status, stderr)) {
+            CommandOutcome::NotFound { names } => Err(anyhow!(
+                "Repository install failed, {}",
+                not_found_in_repos(&names, packages.len())
+            )),
+            CommandOutcome::DatabaseLocked { stderr } => Err(locked(stderr)),
+            CommandOutcome::Failure { stderr } => Err(anyhow!(
+                "Repository install failed: {}",
+                stderr_tail(&stderr)
+            )),
+            _ => Ok(()),
         }
-        Ok(())
     }
 
-    fn install_aur(&self, packages: &[String]) -> Result<()> {
+    fn install_aur(&self, packages: &[String], builds: &mut Vec<AurBuild>) -> Result<()> {
         if packages.is_empty() {
             return Ok(());
         }
@@ -251,72 +624,111 @@ impl PackageManager for ParuPacman {
             "--noupgrademenu".to_string(),
         ];
         args.extend(packages.iter().cloned());
+        let (program, args) =
+            self.aur_command(args, crate::core::privilege::build_user().as_deref())?;
+        let transcript = crate::internal::util::Transcript::default();
         let status = crate::internal::util::execute_command_with_retry(
-            crate::internal::constants::PACKAGE_MANAGER,
+            &program,
             &args,
             &format!("Installing {} AUR packages", packages.len()),
             3, // Max 3 retries
+            Some(&transcript),
         )?;
-        if !status.success() {
-            return Err(anyhow::anyhow!("AUR install failed"));
+        builds.extend(aur_builds::parse_transcript(
+            packages,
+            &transcript.lock().unwrap(),
+            status.success(),
+        ));
+        // Output is streamed to the spinner, so only the exit code is available here
+        match Operation::Install.interpret(packages, &RawOutput::status_only(status)) {
+            CommandOutcome::Success => Ok(()),
+            _ => Err(anyhow::anyhow!("AUR install failed")),
         }
-        Ok(())
     }
 
-    fn update_repo(&self) -> Result<()> {
-        let (status, _stderr_out) = crate::internal::util::execute_command_with_stderr_capture(
-            crate::internal::constants::PACKAGE_MANAGER,
-            &["--repo", "-Syu", "--noconfirm"],
+    fn update_repo(&self, held: &[String]) -> Result<()> {
+        let args = update_args("--repo", &[], held);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let (status, stderr) = crate::internal::util::execute_command_with_stderr_capture(
+            &self.paru,
+            &args,
             "Updating official repository packages (syncing databases and upgrading packages)",
+            None,
         )?;
-        if status.success() {
-            println!(
-                "  {} Official repos synced",
-                crate::internal::color::green("⸎")
-            );
-            Ok(())
-        } else if status.code() == Some(1) {
-            println!(
-                "  {} Packages from main repos have been updated",
-                crate::internal::color::green("⸎")
-            );
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!(
-                "Repository update failed (exit code: {:?})",
-                status.code()
-            ))
+        match Operation::RepoUpdate.interpret(&[], &RawOutput::with_stderr(status, stderr)) {
+            CommandOutcome::DatabaseLocked { stderr } => Err(locked(stderr)),
+            CommandOutcome::Failure { stderr } => Err(anyhow::anyhow!(
+                "Repository update failed (exit code: {:?}): {}",
+                status.code(),
+                stderr_tail(&stderr)
+            )),
+            CommandOutcome::NoChanges => {
+                outln!(
+                    "  {} Official repos already up to date",
+                    crate::internal::color::green("⸎")
+                );
+                Ok(())
+            }
+            _ => {
+                outln!(
+                    "  {} Official repos synced",
+                    crate::internal::color::green("⸎")
+                );
+                Ok(())
+            }
         }
     }
 
-    fn update_aur(&self, packages: &[String]) -> Result<()> {
+    fn update_aur(
+        &self,
+        packages: &[String],
+        held: &[String],
+        builds: &mut Vec<AurBuild>,
+    ) -> Result<()> {
         if packages.is_empty() {
             return Ok(());
         }
-        let mut args = vec!["--aur", "-Syu", "--noconfirm"];
-        args.extend(packages.iter().map(|s| s.as_str()));
+        let (program, args) = self.aur_command(
+            update_args("--aur", packages, held),
+            crate::core::privilege::build_user().as_deref(),
+        )?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let transcript = crate::internal::util::Transcript::default();
         let (status, stderr_out) = crate::internal::util::execute_command_with_stderr_capture(
-            crate::internal::constants::PACKAGE_MANAGER,
+            &program,
             &args,
             "Updating AUR packages",
+            Some(&transcript),
         )
         .map_err(|e| anyhow::anyhow!(e))?;
-        if status.success() {
-            println!(
-                "\r\x1b[2K  {} AUR package updates completed",
-                crate::internal::color::green("⸎")
-            );
-            Ok(())
-        } else {
-            let err = stderr_out.trim();
-            if !err.is_empty() {
+        builds.extend(aur_builds::parse_transcript(
+            packages,
+            &transcript.lock().unwrap(),
+            status.success(),
+        ));
+        match Operation::AurUpdate.interpret(packages, &RawOutput::with_stderr(status, stderr_out))
+        {
+            CommandOutcome::NotFound { names } => Err(anyhow!(
+                "AUR package update failed, not found in the AUR: {}",
+                names.join(", ")
+            )),
+            CommandOutcome::DatabaseLocked { stderr } => Err(locked(stderr)),
+            CommandOutcome::Failure { stderr } => {
                 let take = 30usize;
-                err.lines()
+                stderr
+                    .lines()
                     .rev()
                     .take(take)
-                    .for_each(|line| eprintln!("  {}", line));
+                    .for_each(|line| errln!("  {}", line));
+                Err(anyhow::anyhow!("AUR package update failed"))
+            }
+            _ => {
+                outln!(
+                    "  {} AUR package updates completed",
+                    crate::internal::color::green("⸎")
+                );
+                Ok(())
             }
-            Err(anyhow::anyhow!("AUR package update failed"))
         }
     }
 
@@ -324,24 +736,45 @@ impl PackageManager for ParuPacman {
         if packages.is_empty() {
             return Ok(());
         }
-        let mut cmd = Command::new(crate::internal::constants::PACKAGE_MANAGER);
-        cmd.arg("-Rns");
-        if quiet {
-            cmd.arg("--noconfirm");
-        }
-        cmd.args(packages);
-        let status = cmd
-            .status()
-            .map_err(|e| anyhow::anyhow!("Failed to remove packages: {}", e))?;
-        if status.success() {
-            println!(
-                "  {} Removed {} package(s)",
-                crate::internal::color::green("✓"),
-                packages.len()
-            );
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Package removal failed"))
+        match self.run_remove(packages, quiet)? {
+            CommandOutcome::DatabaseLocked { stderr } => Err(locked(stderr)),
+            CommandOutcome::NotFound { names } => {
+                // pacman aborts the whole transaction, so retry without the missing ones
+                let remaining: Vec<String> = packages
+                    .iter()
+                    .filter(|p| !names.contains(p))
+                    .cloned()
+                    .collect();
+                if remaining.len() == packages.len() {
+                    return Err(anyhow!(
+                        "Package removal failed, target not found: {}",
+                        names.join(", ")
+                    ));
+                }
+                outln!(
+                    "  {} Already removed: {}",
+                    crate::internal::color::blue("info:"),
+                    names.join(", ")
+                );
+                if remaining.is_empty() {
+                    return Ok(());
+                }
+                self.remove_packages(&remaining, quiet)
+            }
+            CommandOutcome::Failure { stderr } => {
+                if !stderr.is_empty() {
+                    errln!("{}", stderr);
+                }
+                Err(anyhow::anyhow!("Package removal failed"))
+            }
+            _ => {
+                outln!(
+                    "  {} Removed {} package(s)",
+                    crate::internal::color::green("✓"),
+                    packages.len()
+                );
+                Ok(())
+            }
         }
     }
 
@@ -351,7 +784,7 @@ impl PackageManager for ParuPacman {
         }
         retry_command(
             || {
-                let mut cmd = Command::new("paru");
+                let mut cmd = Command::new(&self.paru);
                 cmd.args(["-Ss", "--bottomup"]);
                 cmd.args(terms);
                 let output = cmd
@@ -379,7 +812,7 @@ impl PackageManager for ParuPacman {
             }
         }
 
-        let output = Command::new("pacman")
+        let output = Command::new(&self.pacman)
             .args(["-Sg", package_name])
             .output()
             .map_err(|e| {
@@ -410,7 +843,7 @@ impl PackageManager for ParuPacman {
             }
         }
 
-        let output = Command::new("pacman")
+        let output = Command::new(&self.pacman)
             .args(["-Sg", group_name])
             .output()
             .map_err(|e| {
@@ -449,6 +882,255 @@ impl PackageManager for ParuPacman {
 
         Ok(packages)
     }
+
+    fn group_members(&self, names: &[String]) -> Result<HashMap<String, Vec<String>>> {
+        let groups = GROUP_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+        let members = GROUP_PACKAGES_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+        let mut found = HashMap::new();
+        let mut unknown = Vec::new();
+        {
+            let groups = groups.lock().unwrap();
+            let members = members.lock().unwrap();
+            for name in names {
+                match (groups.get(name), members.get(name)) {
+                    (Some(false), _) => {}
+                    (_, Some(packages)) => {
+                        found.insert(name.clone(), packages.clone());
+                    }
+                    _ => unknown.push(name),
+                }
+            }
+        }
+        if unknown.is_empty() {
+            return Ok(found);
+        }
+
+        // Every group in the sync databases with its members, as "group package" lines
+        let output = Command::new(&self.pacman)
+            .arg("-Sgg")
+            .output()
+            .map_err(|e| anyhow!("Failed to list package groups: {}", e))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to list package groups: {}",
+                stderr_tail(&String::from_utf8_lossy(&output.stderr))
+            ));
+        }
+        let mut listed: HashMap<&str, Vec<String>> = HashMap::new();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        for line in stdout.lines() {
+            if let Some((group, package)) = line.trim().split_once(' ') {
+                listed
+                    .entry(group)
+                    .or_default()
+                    .push(package.trim().to_string());
+            }
+        }
+
+        let mut groups = groups.lock().unwrap();
+        let mut members = members.lock().unwrap();
+        for name in unknown {
+            let packages = listed.remove(name.as_str());
+            groups.insert(name.clone(), packages.is_some());
+            if let Some(packages) = packages {
+                members.insert(name.clone(), packages.clone());
+                found.insert(name.clone(), packages);
+            }
+        }
+        Ok(found)
+    }
+
+    fn installed_info(&self) -> Result<Vec<PackageInfo>> {
+        // Field names are translated in other locales
+        let output = Command::new(&self.pacman)
+            .arg("-Qi")
+            .env("LC_ALL", "C")
+            .output()
+            .map_err(|e| anyhow!("Failed to run pacman -Qi: {}", e))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "pacman -Qi failed: {}",
+                stderr_tail(&String::from_utf8_lossy(&output.stderr))
+            ));
+        }
+        Ok(parse_package_info(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn sync_info(&self, packages: &[String]) -> Result<Vec<PackageInfo>> {
+        if packages.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Field names and size units are translated in other locales
+        let output: RawOutput = Command::new(&self.pacman)
+            .arg("-Si")
+            .args(packages)
+            .env("LC_ALL", "C")
+            .output()
+            .map_err(|e| anyhow!("Failed to run pacman -Si: {}", e))?
+            .into();
+        if let CommandOutcome::Failure { stderr } = Operation::RepoInfo.interpret(packages, &output)
+        {
+            return Err(anyhow!("pacman -Si failed: {}", stderr_tail(&stderr)));
+        }
+        Ok(parse_package_info(&output.stdout))
+    }
+
+    fn owner_of(&self, paths: &[PathBuf]) -> Result<HashMap<PathBuf, String>> {
+        if paths.is_empty() {
+            return Ok(HashMap::new());
+        }
+        // Exits 1 when any path is unowned; those only show up on stderr
+        let output = Command::new(&self.pacman)
+            .arg("-Qo")
+            .args(paths)
+            .output()
+            .map_err(|e| anyhow!("Failed to run pacman -Qo: {}", e))?;
+        Ok(parse_owners(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn passthrough(&self, args: &[String]) -> Result<std::process::ExitStatus> {
+        crate::internal::util::execute_command_with_retry(
+            &self.paru,
+            args,
+            &format!(
+                "Running {} {}",
+                crate::internal::constants::PACKAGE_MANAGER,
+                args.join(" ")
+            ),
+            3, // Max 3 retries
+            None,
+        )
+    }
+
+    fn list_orphans(&self) -> Result<Vec<String>> {
+        let output = Command::new(&self.pacman)
+            .arg("-Qdtq")
+            .output()
+            .map_err(|e| anyhow!("Failed to list orphaned packages: {}", e))?;
+        // pacman exits 1 without output when nothing is orphaned
+        let none = output.stdout.is_empty() && output.stderr.is_empty();
+        if !output.status.success() && !none {
+            return Err(anyhow!(
+                "pacman -Qdtq failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn clean_cache(&self) -> Result<()> {
+        self.maintenance(
+            CLEAN_CACHE_ARGS,
+            "Cleaning the package cache and AUR build files",
+        )
+    }
+
+    fn remove_orphans(&self) -> Result<()> {
+        self.maintenance(REMOVE_ORPHANS_ARGS, "Removing orphaned dependencies")
+    }
+}
+
+/// `paru SOURCE -Syu --noconfirm [--ignore HELD] [PACKAGES]`
+///
+/// `-Syu` upgrades everything from the source whatever the targets, so held
+/// packages are kept back with `--ignore` rather than by leaving them out.
+fn update_args(source: &str, packages: &[String], held: &[String]) -> Vec<String> {
+    let mut args: Vec<String> = [source, "-Syu", "--noconfirm"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    if !held.is_empty() {
+        args.push("--ignore".to_string());
+        args.push(held.join(","));
+    }
+    args.extend(packages.iter().cloned());
+    args
+}
+
+/// Parse `pacman -Qo` output into path -> owning package
+///
+/// Owned paths print `PATH is owned by NAME VERSION` on stdout; unowned or
+/// missing ones print `error: No package owns PATH` (or a read error) on
+/// stderr, so any other line is ignored.
+fn parse_owners(output: &str) -> HashMap<PathBuf, String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().rsplit_once(" is owned by "))
+        .filter_map(|(path, owner)| {
+            let name = owner.split_whitespace().next()?;
+            Some((PathBuf::from(path), name.to_string()))
+        })
+        .collect()
+}
+
+/// Parse `pacman -Qi` output: `Key : value` records separated by blank lines
+///
+/// Long values wrap onto indented lines; list values are separated by two
+/// spaces and `None` means an empty list.
+fn parse_package_info(output: &str) -> Vec<PackageInfo> {
+    let mut infos = Vec::new();
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut finish = |fields: &mut Vec<(String, String)>| {
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        if let Some(name) = field("Name") {
+            let replaces = match field("Replaces") {
+                None | Some("None") => Vec::new(),
+                Some(list) => list.split_whitespace().map(str::to_string).collect(),
+            };
+            infos.push(PackageInfo {
+                name: name.to_string(),
+                version: field("Version").unwrap_or_default().to_string(),
+                replaces,
+                repo: field("Repository").map(str::to_string),
+                download_size: field("Download Size").and_then(parse_size),
+                installed_size: field("Installed Size").and_then(parse_size),
+            });
+        }
+        fields.clear();
+    };
+    for line in output.lines() {
+        if line.trim().is_empty() {
+            finish(&mut fields);
+        } else if line.starts_with(char::is_whitespace) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push_str("  ");
+                value.push_str(line.trim());
+            }
+        } else if let Some((key, value)) = line.split_once(" : ") {
+            fields.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    finish(&mut fields);
+    infos
+}
+
+/// `1.50 MiB` in bytes, as pacman prints sizes with `LC_ALL=C`
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, unit) = value.split_once(' ')?;
+    let number: f64 = number.parse().ok()?;
+    let exponent = ["B", "KiB", "MiB", "GiB", "TiB"]
+        .iter()
+        .position(|u| *u == unit.trim())?;
+    Some((number * 1024f64.powi(exponent as i32)).round() as u64)
+}
+
+/// Parse `name version` lines as printed by `pacman -Q`
+fn parse_version_list(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(name, version)| (name.to_string(), version.trim().to_string()))
+        .collect()
 }
 
 fn is_header_line(line: &str) -> bool {
@@ -526,9 +1208,30 @@ fn parse_paru_search_output(output: &str) -> Result<Vec<SearchResult>> {
     Ok(results)
 }
 
+/// Shell scripts standing in for paru and pacman
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Each fake gets its own directory, so no script is rewritten after it ran
+    pub fn pm(paru: &str, pacman: &str) -> (tempfile::TempDir, ParuPacman) {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, body: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let pm = ParuPacman::with_programs(&write("paru", paru), &write("pacman", pacman));
+        (dir, pm)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[test]
     fn test_parse_paru_search_output() {
@@ -561,6 +1264,504 @@ extra/nim 2.0.8-1 [13.08 MiB 58.55 MiB]
         assert!(parse_repo_name("invalid-format").is_err());
     }
 
+    #[test]
+    fn test_parse_owners() {
+        let captured = "\
+/etc/pacman.d/mirrorlist is owned by pacman-mirrorlist 20240717-1
+error: No package owns /etc/owl-only.conf
+/usr/share/X11/xkb/symbols/us is owned by xkeyboard-config 2.42-1
+error: failed to read file '/etc/missing': No such file or directory
+/srv/with space/file is owned by my-pkg 1.0-1
+";
+        let owners = parse_owners(captured);
+        assert_eq!(owners.len(), 3);
+        assert_eq!(
+            owners[Path::new("/etc/pacman.d/mirrorlist")],
+            "pacman-mirrorlist"
+        );
+        assert_eq!(
+            owners[Path::new("/usr/share/X11/xkb/symbols/us")],
+            "xkeyboard-config"
+        );
+        assert_eq!(owners[Path::new("/srv/with space/file")], "my-pkg");
+        assert!(parse_owners("error: No package owns /etc/x\n").is_empty());
+    }
+
+    #[test]
+    fn test_fake_owner_of_mixed_results() {
+        let (_pm_dir, pm) = fake::pm(
+            "exit 0",
+            r#"shift
+for path in "$@"; do
+  case "$path" in
+    /etc/pacman.d/*) echo "$path is owned by pacman-mirrorlist 20240717-1" ;;
+    *) echo "error: No package owns $path" >&2; failed=1 ;;
+  esac
+done
+exit ${failed:-0}"#,
+        );
+        let owners = pm
+            .owner_of(&[
+                PathBuf::from("/etc/pacman.d/mirrorlist"),
+                PathBuf::from("/etc/owl.conf"),
+            ])
+            .unwrap();
+        assert_eq!(owners.len(), 1);
+        assert_eq!(
+            owners[Path::new("/etc/pacman.d/mirrorlist")],
+            "pacman-mirrorlist"
+        );
+        assert!(pm.owner_of(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_package_info() {
+        let output = "\
+Name            : pipewire-jack
+Version         : 1:1.2.7-1
+Description     : Low-latency audio/video router and processor - JACK replacement
+Provides        : jack  libjack.so=0-64
+Replaces        : jack  jack2  jack2-dbus  libjack-a
+                  libjack-b
+Install Reason  : Installed as a dependency for another package
+
+Name            : bash
+Version         : 5.2.037-1
+Replaces        : None
+
+";
+        let infos = parse_package_info(output);
+        assert_eq!(
+            infos,
+            vec![
+                PackageInfo {
+                    name: "pipewire-jack".to_string(),
+                    version: "1:1.2.7-1".to_string(),
+                    replaces: names(&["jack", "jack2", "jack2-dbus", "libjack-a", "libjack-b"]),
+                    ..Default::default()
+                },
+                PackageInfo {
+                    name: "bash".to_string(),
+                    version: "5.2.037-1".to_string(),
+                    ..Default::default()
+                },
+            ]
+        );
+        // A last record without a trailing blank line still counts
+        assert_eq!(
+            parse_package_info("Name : fd\nVersion : 10.2.0-1")[0].name,
+            "fd"
+        );
+    }
+
+    #[test]
+    fn test_parse_sync_info_sizes() {
+        let output = "\
+Repository      : core
+Name            : linux
+Version         : 6.12.1.arch1-1
+Download Size   : 141.35 MiB
+Installed Size  : 137.90 MiB
+
+Repository      : extra
+Name            : fd
+Download Size   : 1.25 MiB
+Installed Size  : 512.00 KiB
+";
+        let infos = parse_package_info(output);
+        assert_eq!(infos[0].repo.as_deref(), Some("core"));
+        assert_eq!(infos[0].download_size, Some(148_216_218));
+        assert_eq!(infos[1].installed_size, Some(524_288));
+        assert_eq!(parse_size("12 B"), Some(12));
+        assert_eq!(parse_size("1,5 MiB"), None);
+        assert_eq!(parse_size("3.00 XB"), None);
+    }
+
+    #[test]
+    fn test_parse_version_list() {
+        let versions = parse_version_list("fish 3.6.1-2\ntmux 3.3_a-7\n\n");
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions["fish"], "3.6.1-2");
+        assert_eq!(versions["tmux"], "3.3_a-7");
+    }
+
+    fn raw(code: i32, stdout: &str, stderr: &str) -> RawOutput {
+        RawOutput {
+            code: Some(code),
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+        }
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Outputs captured from pacman/paru runs and how each operation must read them
+    #[test]
+    fn test_interpretation_table() {
+        let not_found = |list: &[&str]| CommandOutcome::NotFound { names: names(list) };
+        let failure = |stderr: &str| CommandOutcome::Failure {
+            stderr: stderr.to_string(),
+        };
+        let cases: Vec<(Operation, &[&str], RawOutput, CommandOutcome)> = vec![
+            (
+                Operation::QueryUpdates,
+                &[],
+                raw(0, "fish\ntmux\n", ""),
+                CommandOutcome::Success,
+            ),
+            (
+                Operation::QueryUpdates,
+                &[],
+                raw(1, "", ""),
+                CommandOutcome::NoChanges,
+            ),
+            (
+                Operation::QueryUpdates,
+                &[],
+                raw(
+                    1,
+                    "",
+                    "error: failed to init transaction (unable to lock database)\n",
+                ),
+                failure("error: failed to init transaction (unable to lock database)"),
+            ),
+            (
+                Operation::RepoInfo,
+                &["bash", "paru-bin"],
+                raw(
+                    1,
+                    "Repository      : core\nName            : bash\n",
+                    "error: package 'paru-bin' was not found\n",
+                ),
+                CommandOutcome::PartialSuccess {
+                    details: "1 of 2 packages not found in repos: paru-bin".to_string(),
+                },
+            ),
+            (
+                Operation::RepoInfo,
+                &["yay", "paru-bin"],
+                raw(
+                    1,
+                    "",
+                    "error: package 'yay' was not found\nerror: package 'paru-bin' was not found\n",
+                ),
+                not_found(&["yay", "paru-bin"]),
+            ),
+            (
+                Operation::RepoInfo,
+                &["bash"],
+                raw(
+                    1,
+                    "",
+                    "error: could not open file /var/lib/pacman/sync/core.db\n",
+                ),
+                failure("error: could not open file /var/lib/pacman/sync/core.db"),
+            ),
+            (
+                Operation::Install,
+                &["fd", "nope", "gone"],
+                raw(
+                    1,
+                    "",
+                    "error: target not found: nope\nerror: target not found: gone\n",
+                ),
+                not_found(&["nope", "gone"]),
+            ),
+            (
+                Operation::Install,
+                &["fd"],
+                raw(
+                    1,
+                    "",
+                    "error: failed to commit transaction (conflicting files)\n",
+                ),
+                failure("error: failed to commit transaction (conflicting files)"),
+            ),
+            (
+                Operation::Remove,
+                &["fd", "gone"],
+                raw(1, "", "error: target not found: gone\n"),
+                not_found(&["gone"]),
+            ),
+            (
+                Operation::Remove,
+                &["glibc"],
+                raw(
+                    1,
+                    "",
+                    "error: failed to prepare transaction (could not satisfy dependencies)\n",
+                ),
+                failure("error: failed to prepare transaction (could not satisfy dependencies)"),
+            ),
+            (
+                Operation::RepoUpdate,
+                &[],
+                raw(1, " there is nothing to do\n", ""),
+                CommandOutcome::NoChanges,
+            ),
+            (
+                Operation::RepoUpdate,
+                &[],
+                raw(1, "", "error: failed retrieving file 'core.db'\n"),
+                failure("error: failed retrieving file 'core.db'"),
+            ),
+            (Operation::RepoUpdate, &[], raw(130, "", ""), failure("")),
+            (
+                Operation::RepoUpdate,
+                &[],
+                raw(
+                    1,
+                    "",
+                    "error: failed to init transaction (unable to lock database)\n",
+                ),
+                CommandOutcome::DatabaseLocked {
+                    stderr: "error: failed to init transaction (unable to lock database)"
+                        .to_string(),
+                },
+            ),
+            (
+                Operation::AurUpdate,
+                &["yay"],
+                raw(1, "", "error: target not found: yay\n"),
+                not_found(&["yay"]),
+            ),
+            (
+                Operation::Install,
+                &["fd"],
+                RawOutput::default(),
+                failure(""),
+            ),
+        ];
+        for (op, requested, output, expected) in cases {
+            assert_eq!(
+                op.interpret(&names(requested), &output),
+                expected,
+                "{:?} {:?}",
+                op,
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn test_fake_foreign_versions() {
+        let (_pm_dir, pm) = fake::pm(
+            "exit 1",
+            r#"[ "$1" = "-Qm" ] || exit 2
+printf 'paru-bin 2.0.3-1\nyay 12.3.5-1\n'"#,
+        );
+        let foreign = pm.list_foreign_versions().unwrap();
+        assert_eq!(foreign["paru-bin"], "2.0.3-1");
+        assert_eq!(foreign.len(), 2);
+
+        let (_none_dir, none) = fake::pm("exit 1", "exit 1");
+        assert!(none.list_foreign_versions().unwrap().is_empty());
+        let (_broken_dir, broken) = fake::pm("exit 0", "echo 'error: database locked' >&2; exit 1");
+        assert!(broken.list_foreign_versions().is_err());
+    }
+
+    #[test]
+    fn test_fake_repo_info_partial_failure() {
+        let (_pm_dir, pm) = fake::pm(
+            "exit 0",
+            r#"for pkg in "$@"; do
+  case "$pkg" in
+    -*) ;;
+    *-bin) echo "error: package '$pkg' was not found" >&2; failed=1 ;;
+    *) printf 'Repository      : extra\nName            : %s\n\n' "$pkg" ;;
+  esac
+done
+exit ${failed:-0}"#,
+        );
+        let found = pm
+            .batch_repo_available(&names(&["fd", "paru-bin", "ripgrep"]))
+            .unwrap();
+        assert_eq!(found, names(&["fd", "ripgrep"]).into_iter().collect());
+
+        let (_broken_dir, broken) = fake::pm("exit 0", "echo 'error: database locked' >&2; exit 1");
+        assert!(broken.batch_repo_available(&names(&["fd"])).is_err());
+    }
+
+    #[test]
+    fn test_fake_remove_retries_without_missing_targets() {
+        let logs = tempfile::tempdir().unwrap();
+        let log = logs.path().join("calls");
+        let (_pm_dir, pm) = fake::pm(
+            &format!(
+                r#"echo "$*" >> {}
+for pkg in "$@"; do
+  [ "$pkg" = gone ] && {{ echo "error: target not found: gone" >&2; exit 1; }}
+done
+exit 0"#,
+                log.display()
+            ),
+            "exit 0",
+        );
+        pm.remove_packages(&names(&["fd", "gone"]), true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "-Rns --noconfirm fd gone\n-Rns --noconfirm fd\n"
+        );
+
+        assert!(pm.remove_packages(&names(&["gone"]), true).is_ok());
+        let (_failing_dir, failing) = fake::pm("echo 'error: dependency' >&2; exit 1", "exit 0");
+        assert!(failing.remove_packages(&names(&["glibc"]), true).is_err());
+    }
+
+    #[test]
+    fn test_fake_locked_database_is_its_own_failure() {
+        let (_pm_dir, pm) = fake::pm(
+            r#"echo 'error: failed to init transaction (unable to lock database)' >&2
+echo 'error: could not lock database: File exists' >&2
+echo "  if you're sure a package manager is not already running, you can remove /var/lib/pacman/db.lck" >&2
+exit 1"#,
+            "exit 0",
+        );
+        let failures = [
+            pm.install_repo(&names(&["fd"])).unwrap_err(),
+            pm.update_repo(&[]).unwrap_err(),
+            pm.update_aur(&names(&["yay"]), &[], &mut Vec::new())
+                .unwrap_err(),
+            pm.remove_packages(&names(&["fd"]), true).unwrap_err(),
+        ];
+        for err in &failures {
+            assert!(crate::core::db_lock::is_locked(err), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_fake_cleanup_argv() {
+        let log = r#"echo "$(basename "$0") $*" >> "$(dirname "$0")/calls""#;
+        let (dir, pm) = fake::pm(log, &format!("{}\nprintf 'go-tools\\nmeson\\n'", log));
+        assert_eq!(pm.list_orphans().unwrap(), names(&["go-tools", "meson"]));
+        pm.clean_cache().unwrap();
+        pm.remove_orphans().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("calls")).unwrap(),
+            "pacman -Qdtq\nparu -Sc --noconfirm\nparu -c --noconfirm\n"
+        );
+
+        let (_none_dir, none) = fake::pm("exit 1", "exit 1");
+        assert!(none.list_orphans().unwrap().is_empty());
+        let err = none.clean_cache().unwrap_err().to_string();
+        assert!(err.contains("-Sc --noconfirm failed"), "{}", err);
+    }
+
+    #[test]
+    fn test_fake_held_packages_are_ignored_by_updates() {
+        let log = r#"echo "$*" >> "$(dirname "$0")/calls""#;
+        let (dir, pm) = fake::pm(log, "exit 0");
+        let held = names(&["nvidia-dkms", "linux"]);
+        pm.update_repo(&held).unwrap();
+        pm.update_aur(&names(&["yay"]), &held, &mut Vec::new())
+            .unwrap();
+        pm.update_repo(&[]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("calls")).unwrap(),
+            "--repo -Syu --noconfirm --ignore nvidia-dkms,linux\n\
+             --aur -Syu --noconfirm --ignore nvidia-dkms,linux yay\n\
+             --repo -Syu --noconfirm\n"
+        );
+    }
+
+    #[test]
+    fn test_fake_aur_builds_as_root_need_a_build_user() {
+        let log = r#"echo "$*" >> "$(dirname "$0")/calls""#;
+        let (dir, mut pm) = fake::pm(log, "exit 0");
+        pm.root = true;
+        let err = pm
+            .install_aur(&names(&["yay"]), &mut Vec::new())
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("paru refuses to run as root"), "{}", err);
+        assert!(!dir.path().join("calls").exists(), "paru never ran");
+
+        let (program, args) = pm
+            .aur_command(names(&["--aur", "-S", "yay"]), Some("builder"))
+            .unwrap();
+        assert_eq!(program, "sudo");
+        assert_eq!(
+            args,
+            names(&["-u", "builder", "-H", "--", &pm.paru, "--aur", "-S", "yay"])
+        );
+    }
+
+    #[test]
+    fn test_fake_update_queries() {
+        let (_none_dir, none) = fake::pm("exit 1", "exit 0");
+        assert_eq!(none.upgrade_count().unwrap(), 0);
+        assert!(none.get_aur_updates().unwrap().is_empty());
+
+        let (_some_dir, some) = fake::pm("printf 'yay 12.3-1 -> 12.4-1\nparu\n'", "exit 0");
+        assert_eq!(some.upgrade_count().unwrap(), 2);
+        assert_eq!(some.get_aur_updates().unwrap(), names(&["yay", "paru"]));
+    }
+
+    #[test]
+    fn test_fake_mirror_failure_refreshes_once() {
+        let logs = tempfile::tempdir().unwrap();
+        let calls = logs.path().join("calls");
+        let refreshed = logs.path().join("refreshed");
+        let refresh = format!("echo refresh >> {}", refreshed.display());
+        let mirror_404 = |fixed_by_refresh: bool| {
+            fake::pm(
+                &format!(
+                    r#"echo "$*" >> {calls}
+{check}
+echo "error: failed retrieving file 'core.db' from mirror : The requested URL returned error: 404" >&2
+echo "error: failed to synchronize all databases (failed to retrieve some files)" >&2
+exit 1"#,
+                    calls = calls.display(),
+                    check = if fixed_by_refresh {
+                        format!("[ -e {} ] && exit 0", refreshed.display())
+                    } else {
+                        String::new()
+                    },
+                ),
+                "exit 0",
+            )
+        };
+        let count = |path: &Path| {
+            std::fs::read_to_string(path)
+                .map(|s| s.lines().count())
+                .unwrap_or(0)
+        };
+
+        // Refresh fixes it: fail, fail, refresh, succeed
+        let (_fixed_dir, fixed) = mirror_404(true);
+        with_mirror_refresh(Some(&refresh), || fixed.update_repo(&[])).unwrap();
+        assert_eq!(count(&calls), 3);
+        assert_eq!(count(&refreshed), 1);
+
+        // A mirror that stays broken still refreshes only once
+        std::fs::remove_file(&calls).unwrap();
+        std::fs::remove_file(&refreshed).unwrap();
+        let (_broken_dir, broken) = mirror_404(false);
+        let err = with_mirror_refresh(Some(&refresh), || broken.install_repo(&names(&["fd"])))
+            .unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+        assert_eq!(count(&calls), 3);
+        assert_eq!(count(&refreshed), 1);
+
+        // Off by default, and other failures are not retried
+        std::fs::remove_file(&calls).unwrap();
+        std::fs::remove_file(&refreshed).unwrap();
+        assert!(with_mirror_refresh(None, || broken.update_repo(&[])).is_err());
+        assert_eq!(count(&calls), 1);
+        let (_other_dir, other) = fake::pm(
+            &format!(
+                "echo \"$*\" >> {}; echo 'error: conflicting files' >&2; exit 1",
+                calls.display()
+            ),
+            "exit 0",
+        );
+        assert!(with_mirror_refresh(Some(&refresh), || other.update_repo(&[])).is_err());
+        assert_eq!(count(&calls), 2);
+        assert_eq!(count(&refreshed), 0);
+    }
+
     #[test]
     fn test_is_header_line() {
         assert!(is_header_line("aur/jet-bin 0.7.27-1 [+5 ~0.00]"));
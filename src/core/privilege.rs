@@ -0,0 +1,498 @@
+//! Upfront sudo authentication for privileged apply phases
+//!
+//! To avoid password prompts at unpredictable points of a run, the apply
+//! command collects what it is going to need into a manifest, shows it and
+//! authenticates once. Package transactions run through paru, which calls sudo
+//! itself, so the sudo timestamp is kept fresh until the run ends. Writing
+//! system dotfiles and managing services go through the privileged helper
+//! (`core::privileged_helper`), started once for the run with the manifest.
+//!
+//! paru refuses to build as root, so when owl itself runs as root (under sudo
+//! in some automation) AUR builds run as `--build-user` instead, or fail
+//! upfront saying why.
+
+use crate::core::privileged_helper::{Client, HELPER_COMMAND, Helper, Manifest, Op};
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock, mpsc};
+use std::thread;
+use std::time::Duration;
+
+/// Interval between sudo timestamp refreshes (sudo's default timeout is 5 minutes)
+const KEEPALIVE_INTERVAL_SECS: u64 = 60;
+
+/// Where pacman reads hooks from; dotfiles mapped here are listed as hooks
+pub const PACMAN_HOOKS_DIR: &str = "/etc/pacman.d/hooks";
+
+/// Privileged operations an apply run is going to perform
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrivilegedOps {
+    pub installs: usize,
+    pub removals: usize,
+    pub system_update: bool,
+    /// Units the services phase may enable, start or disable
+    pub services: Vec<String>,
+    /// Dotfile destinations outside home this user cannot write, pacman hooks included
+    pub system_files: Vec<PathBuf>,
+}
+
+impl PrivilegedOps {
+    pub fn is_empty(&self) -> bool {
+        self.installs == 0
+            && self.removals == 0
+            && !self.system_update
+            && self.helper_manifest().is_empty()
+    }
+
+    /// What the privileged helper of the run may touch
+    pub fn helper_manifest(&self) -> Manifest {
+        Manifest {
+            files: self.system_files.clone(),
+            units: self.services.clone(),
+        }
+    }
+
+    /// Human readable manifest, e.g. "installing 3 packages, managing 1 service"
+    pub fn describe(&self) -> String {
+        let plural = |n: usize, word: &str| {
+            if n == 1 {
+                format!("{} {}", n, word)
+            } else {
+                format!("{} {}s", n, word)
+            }
+        };
+        let mut parts = Vec::new();
+        if self.installs > 0 {
+            parts.push(format!("installing {}", plural(self.installs, "package")));
+        }
+        if self.removals > 0 {
+            parts.push(format!("removing {}", plural(self.removals, "package")));
+        }
+        if self.system_update {
+            parts.push("updating the system".to_string());
+        }
+        let hooks = self
+            .system_files
+            .iter()
+            .filter(|file| file.starts_with(PACMAN_HOOKS_DIR))
+            .count();
+        let dotfiles = self.system_files.len() - hooks;
+        if dotfiles > 0 {
+            parts.push(format!("writing {}", plural(dotfiles, "system dotfile")));
+        }
+        if hooks > 0 {
+            parts.push(format!("writing {}", plural(hooks, "pacman hook")));
+        }
+        if !self.services.is_empty() {
+            parts.push(format!(
+                "managing {}",
+                plural(self.services.len(), "service")
+            ));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Keeps the sudo timestamp fresh until dropped
+pub struct SudoKeepalive {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl SudoKeepalive {
+    /// A keepalive that does nothing (already root, or nothing to authorize)
+    pub fn none() -> Self {
+        Self {
+            stop: None,
+            handle: None,
+        }
+    }
+}
+
+/// What `authenticate` set up for a run: the sudo keepalive and the
+/// privileged helper, both ended when dropped
+pub struct Session {
+    _keepalive: SudoKeepalive,
+    helper: bool,
+}
+
+impl Session {
+    /// A session with nothing running (dry run, already root, nothing to authorize)
+    pub fn none() -> Self {
+        Self {
+            _keepalive: SudoKeepalive::none(),
+            helper: false,
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.helper {
+            HELPER.lock().unwrap_or_else(|e| e.into_inner()).take();
+        }
+    }
+}
+
+/// The privileged helper of the current run, while its `Session` lasts
+static HELPER: Mutex<Option<Client>> = Mutex::new(None);
+
+/// Run a system operation as root
+///
+/// It goes to the run's privileged helper when there is one. Otherwise owl
+/// runs it itself when it is root, or starts a helper for it alone through
+/// sudo, which may prompt.
+pub fn run(op: &Op) -> Result<()> {
+    if let Some(client) = HELPER.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        return client.call(op);
+    }
+    if is_root() {
+        return Helper::new(Path::new("/"), "systemctl", op.manifest()).execute(op);
+    }
+    Client::start(helper_command("sudo", false)?, &op.manifest())?.call(op)
+}
+
+/// `sudo owl __privileged-helper`, with `-n` to use only a cached timestamp
+fn helper_command(sudo: &str, non_interactive: bool) -> Result<Command> {
+    let owl = std::env::current_exe()
+        .map_err(|e| anyhow!("Failed to locate the owl executable: {}", e))?;
+    let mut cmd = Command::new(sudo);
+    if non_interactive {
+        cmd.arg("-n");
+    }
+    cmd.arg("--").arg(owl).arg(HELPER_COMMAND);
+    Ok(cmd)
+}
+
+impl Drop for SudoKeepalive {
+    fn drop(&mut self) {
+        // Dropping the sender disconnects the channel and ends the refresh loop
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The user AUR builds run as when owl runs as root (`--build-user`)
+static BUILD_USER: Mutex<Option<String>> = Mutex::new(None);
+
+/// Build AUR packages as `user` whenever owl runs as root, for the rest of the run
+pub fn set_build_user(user: Option<String>) {
+    *BUILD_USER.lock().unwrap_or_else(|e| e.into_inner()) = user;
+}
+
+pub fn build_user() -> Option<String> {
+    BUILD_USER.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Whether owl runs as root (uid 0); asked once per run
+pub fn is_root() -> bool {
+    static ROOT: OnceLock<bool> = OnceLock::new();
+    *ROOT.get_or_init(|| {
+        Command::new("id")
+            .arg("-u")
+            .output()
+            .is_ok_and(|o| is_root_uid(&String::from_utf8_lossy(&o.stdout)))
+    })
+}
+
+/// Whether `id -u` printed root's uid
+fn is_root_uid(id_output: &str) -> bool {
+    id_output.trim() == "0"
+}
+
+/// The user paru builds AUR packages as: `None` for whoever runs owl, the
+/// build user when that is root
+///
+/// As root without a build user this is the error explaining why paru
+/// cannot go on.
+pub fn aur_user(root: bool, build_user: Option<&str>) -> Result<Option<&str>> {
+    match build_user {
+        _ if !root => Ok(None),
+        Some("root") | Some("") => Err(anyhow!(
+            "--build-user must name a regular user; paru refuses to build as root"
+        )),
+        Some(user) => Ok(Some(user)),
+        None => Err(anyhow!(
+            "paru refuses to run as root, and owl is running as root (uid 0), so AUR \
+             packages cannot be built. Run owl as a regular user (it asks sudo for what \
+             needs root), pass --build-user NAME to build AUR packages as that user, or \
+             use pacman directly for repo-only operations"
+        )),
+    }
+}
+
+/// Show the privileged-ops manifest, authenticate once and start the
+/// privileged helper when the run has system files or services
+///
+/// In non-interactive mode only a cached sudo timestamp is used; no prompt is shown.
+pub fn authenticate(ops: &PrivilegedOps, non_interactive: bool) -> Result<Session> {
+    if ops.is_empty() || is_root() {
+        return Ok(Session::none());
+    }
+    authenticate_with(
+        "sudo",
+        ops,
+        non_interactive,
+        Duration::from_secs(KEEPALIVE_INTERVAL_SECS),
+    )
+}
+
+/// `authenticate` with the sudo program and refresh interval given
+fn authenticate_with(
+    sudo: &str,
+    ops: &PrivilegedOps,
+    non_interactive: bool,
+    interval: Duration,
+) -> Result<Session> {
+    outln!(
+        "  {} owl needs elevated rights for: {}",
+        crate::internal::color::blue("info:"),
+        ops.describe()
+    );
+
+    let mut cmd = Command::new(sudo);
+    if non_interactive {
+        cmd.arg("-n");
+    }
+    let status = cmd
+        .arg("-v")
+        .status()
+        .map_err(|e| anyhow!("Failed to run sudo: {}", e))?;
+    if !status.success() {
+        return Err(anyhow!("sudo authentication failed"));
+    }
+
+    let keepalive = keepalive(sudo, &["-n", "-v"], interval);
+    let manifest = ops.helper_manifest();
+    if manifest.is_empty() {
+        return Ok(Session {
+            _keepalive: keepalive,
+            helper: false,
+        });
+    }
+    let client = Client::start(helper_command(sudo, true)?, &manifest)?;
+    *HELPER.lock().unwrap_or_else(|e| e.into_inner()) = Some(client);
+    Ok(Session {
+        _keepalive: keepalive,
+        helper: true,
+    })
+}
+
+/// Run `program args` every `interval` until the returned guard is dropped
+fn keepalive(program: &str, args: &[&str], interval: Duration) -> SudoKeepalive {
+    let program = program.to_string();
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    let (tx, rx) = mpsc::channel::<()>();
+    let handle = thread::spawn(move || {
+        while let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+            let _ = Command::new(&program)
+                .args(&args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+    });
+
+    SudoKeepalive {
+        stop: Some(tx),
+        handle: Some(handle),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_manifest() {
+        let ops = PrivilegedOps {
+            installs: 3,
+            removals: 1,
+            system_update: true,
+            services: vec!["sshd.service".to_string(), "cups.service".to_string()],
+            system_files: ["/etc/hosts", "/etc/pacman.d/hooks/owl.hook", "/etc/motd"]
+                .map(PathBuf::from)
+                .to_vec(),
+        };
+        assert_eq!(
+            ops.describe(),
+            "installing 3 packages, removing 1 package, updating the system, \
+             writing 2 system dotfiles, writing 1 pacman hook, managing 2 services"
+        );
+        assert_eq!(
+            ops.helper_manifest(),
+            Manifest {
+                files: ops.system_files.clone(),
+                units: ops.services.clone(),
+            }
+        );
+        let hook_only = PrivilegedOps {
+            system_files: vec![PathBuf::from("/etc/pacman.d/hooks/owl.hook")],
+            ..Default::default()
+        };
+        assert!(!hook_only.is_empty());
+        assert_eq!(hook_only.describe(), "writing 1 pacman hook");
+        assert!(PrivilegedOps::default().is_empty());
+        assert_eq!(PrivilegedOps::default().describe(), "");
+    }
+
+    #[test]
+    fn test_root_detection_and_aur_user() {
+        assert!(is_root_uid("0\n"));
+        assert!(!is_root_uid("1000\n"));
+        assert!(!is_root_uid(""));
+
+        assert_eq!(aur_user(false, None).unwrap(), None);
+        assert_eq!(aur_user(false, Some("builder")).unwrap(), None);
+        assert_eq!(aur_user(true, Some("builder")).unwrap(), Some("builder"));
+        let err = aur_user(true, None).unwrap_err().to_string();
+        assert!(err.starts_with("paru refuses to run as root"), "{}", err);
+        assert!(err.contains("--build-user NAME"), "{}", err);
+        assert!(err.contains("use pacman directly"), "{}", err);
+        assert!(aur_user(true, Some("root")).is_err());
+    }
+
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::time::Instant;
+
+    /// A fake sudo that logs its arguments to `log` and exits with `code`
+    fn fake_sudo(dir: &Path, log: &Path, code: i32) -> String {
+        let path = dir.join(format!("sudo-{}", code));
+        std::fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\necho \"$*\" >> '{}'\nexit {}\n",
+                log.display(),
+                code
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn refreshes(log: &Path) -> usize {
+        std::fs::read_to_string(log).map_or(0, |s| s.lines().count())
+    }
+
+    #[test]
+    fn test_keepalive_refreshes_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("sudo.log");
+        let sudo = fake_sudo(dir.path(), &log, 0);
+
+        let guard = keepalive(&sudo, &["-n", "-v"], Duration::from_millis(20));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while refreshes(&log) < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(refreshes(&log) >= 3, "the timestamp was never refreshed");
+        assert!(
+            std::fs::read_to_string(&log)
+                .unwrap()
+                .starts_with("-n -v\n")
+        );
+
+        // Dropping returns promptly and no refresh runs afterwards
+        let start = Instant::now();
+        drop(guard);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let after_drop = refreshes(&log);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(refreshes(&log), after_drop);
+
+        // Drop doesn't wait for the interval either
+        let start = Instant::now();
+        drop(keepalive(&sudo, &["-n", "-v"], Duration::from_secs(3600)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_authenticate_runs_sudo() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("sudo.log");
+        let ops = PrivilegedOps {
+            installs: 1,
+            ..Default::default()
+        };
+        let interval = Duration::from_secs(3600);
+
+        // Non-interactive runs only use a cached timestamp
+        let ok = fake_sudo(dir.path(), &log, 0);
+        drop(authenticate_with(&ok, &ops, true, interval).unwrap());
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "-n -v\n");
+        std::fs::remove_file(&log).unwrap();
+
+        drop(authenticate_with(&ok, &ops, false, interval).unwrap());
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "-v\n");
+        std::fs::remove_file(&log).unwrap();
+
+        let failing = fake_sudo(dir.path(), &log, 1);
+        let Err(err) = authenticate_with(&failing, &ops, true, interval) else {
+            panic!("a failing sudo must fail authentication");
+        };
+        assert_eq!(err.to_string(), "sudo authentication failed");
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "-n -v\n");
+    }
+
+    #[test]
+    fn test_session_sends_operations_to_its_helper() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("sudo.log");
+        let received = dir.path().join("received.log");
+        // Answers every line the way a helper does once started with -n
+        let sudo = dir.path().join("sudo");
+        std::fs::write(
+            &sudo,
+            format!(
+                "#!/bin/sh\necho \"$*\" >> '{}'\n[ \"$1\" = -n ] && [ \"$2\" = -- ] || exit 0\n\
+                 while read -r line; do echo \"$line\" >> '{}'; echo '{{\"ok\":true}}'; done\n",
+                log.display(),
+                received.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&sudo, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let ops = PrivilegedOps {
+            services: vec!["sshd.service".to_string()],
+            ..Default::default()
+        };
+
+        let session = authenticate_with(
+            &sudo.to_string_lossy(),
+            &ops,
+            false,
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        let op = Op::Systemctl {
+            verb: crate::core::privileged_helper::Verb::Enable,
+            unit: "sshd.service".to_string(),
+        };
+        run(&op).unwrap();
+        drop(session);
+        assert!(HELPER.lock().unwrap().is_none());
+
+        let calls = std::fs::read_to_string(&log).unwrap();
+        let calls: Vec<&str> = calls.lines().collect();
+        assert_eq!(calls[0], "-v");
+        assert!(
+            calls[1].starts_with("-n -- ") && calls[1].ends_with(" __privileged-helper"),
+            "{}",
+            calls[1]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&received).unwrap(),
+            format!(
+                "{}\n{}\n",
+                r#"{"files":[],"units":["sshd.service"]}"#,
+                serde_json::to_string(&op).unwrap()
+            )
+        );
+    }
+}
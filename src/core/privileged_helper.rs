@@ -0,0 +1,592 @@
+//! The privileged helper system operations of an apply run go through
+//!
+//! Once apply has authenticated it starts a single `sudo owl __privileged-helper`
+//! child and sends it every operation that needs root over a pipe, instead of
+//! running sudo once per operation. The protocol is line-delimited JSON:
+//!
+//! 1. owl sends the manifest, every destination and unit the run may touch:
+//!    `{"files":["/etc/hosts"],"units":["sshd.service"]}`. The helper answers
+//!    `{"ok":true}` when it accepts it and exits otherwise.
+//! 2. owl sends one operation per line and the helper answers each in order,
+//!    with `{"ok":true}` or `{"ok":false,"error":"..."}`:
+//!    - `{"op":"write_file","source":"...","destination":"/etc/hosts","mode":420}`
+//!    - `{"op":"systemctl","verb":"enable","unit":"sshd.service"}`
+//!
+//! No other operations exist. The helper refuses an operation whose destination
+//! or unit is not in the manifest, and a line that is no operation, and keeps
+//! serving. It ends when its input does.
+//!
+//! `--root DIR` re-roots every destination under `DIR`, so the protocol can be
+//! exercised unprivileged in a sandbox.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// The hidden subcommand the helper runs as
+pub const HELPER_COMMAND: &str = "__privileged-helper";
+
+/// Everything one run may touch through the helper
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Absolute destinations `write_file` may replace
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+    /// Units `systemctl` may act on
+    #[serde(default)]
+    pub units: Vec<String>,
+}
+
+impl Manifest {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.units.is_empty()
+    }
+
+    /// Why the helper refuses this manifest, if it does
+    fn invalid(&self) -> Option<String> {
+        if let Some(file) = self.files.iter().find(|file| !is_plain_file_path(file)) {
+            return Some(format!(
+                "{} is not an absolute file path without . or ..",
+                file.display()
+            ));
+        }
+        self.units
+            .iter()
+            .find(|unit| unit.is_empty() || unit.starts_with('-') || unit.contains('/'))
+            .map(|unit| format!("'{}' is not a unit name", unit))
+    }
+}
+
+/// Whether `path` is absolute, names a file below `/` and has no `.` or `..`
+fn is_plain_file_path(path: &Path) -> bool {
+    path.is_absolute()
+        && path.file_name().is_some()
+        && path
+            .components()
+            .all(|c| matches!(c, Component::RootDir | Component::Normal(_)))
+}
+
+/// The systemctl verbs the helper runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verb {
+    Enable,
+    Start,
+    Disable,
+    Stop,
+}
+
+impl Verb {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Enable => "enable",
+            Self::Start => "start",
+            Self::Disable => "disable",
+            Self::Stop => "stop",
+        }
+    }
+}
+
+/// One operation the helper runs as root
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    /// Replace `destination` with a copy of the regular file `source`, with `mode`
+    WriteFile {
+        source: PathBuf,
+        destination: PathBuf,
+        mode: u32,
+    },
+    /// `systemctl <verb> <unit>`
+    Systemctl { verb: Verb, unit: String },
+}
+
+impl Op {
+    /// A manifest allowing this operation alone
+    pub fn manifest(&self) -> Manifest {
+        match self {
+            Self::WriteFile { destination, .. } => Manifest {
+                files: vec![destination.clone()],
+                ..Default::default()
+            },
+            Self::Systemctl { unit, .. } => Manifest {
+                units: vec![unit.clone()],
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WriteFile { destination, .. } => write!(f, "write {}", destination.display()),
+            Self::Systemctl { verb, unit } => write!(f, "systemctl {} {}", verb.as_str(), unit),
+        }
+    }
+}
+
+/// The helper's answer to the manifest and to each operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<Result<()>> for Reply {
+    fn from(result: Result<()>) -> Self {
+        match result {
+            Ok(()) => Self {
+                ok: true,
+                error: None,
+            },
+            Err(e) => Self {
+                ok: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Runs the operations a manifest allows, with destinations under `root`
+pub struct Helper {
+    root: PathBuf,
+    systemctl: String,
+    manifest: Manifest,
+}
+
+impl Helper {
+    pub fn new(root: &Path, systemctl: &str, manifest: Manifest) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            systemctl: systemctl.to_string(),
+            manifest,
+        }
+    }
+
+    /// Run `op`, or refuse it when the manifest does not allow it
+    pub fn execute(&self, op: &Op) -> Result<()> {
+        match op {
+            Op::WriteFile { destination, .. } if !self.manifest.files.contains(destination) => Err(
+                anyhow!("refused: {} is not in the manifest", destination.display()),
+            ),
+            Op::Systemctl { unit, .. } if !self.manifest.units.contains(unit) => {
+                Err(anyhow!("refused: {} is not in the manifest", unit))
+            }
+            Op::WriteFile {
+                source,
+                destination,
+                mode,
+            } => self.write_file(source, destination, *mode),
+            Op::Systemctl { verb, unit } => self.systemctl(*verb, unit),
+        }
+    }
+
+    /// Copy `source` next to the destination and rename it over it, so the
+    /// destination is replaced whole or not at all
+    fn write_file(&self, source: &Path, destination: &Path, mode: u32) -> Result<()> {
+        let meta = fs::symlink_metadata(source)
+            .map_err(|e| anyhow!("Failed to stat {}: {}", source.display(), e))?;
+        if !meta.is_file() {
+            return Err(anyhow!("{} is not a regular file", source.display()));
+        }
+        let target = self
+            .root
+            .join(destination.strip_prefix("/").unwrap_or(destination));
+        let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+            return Err(anyhow!("{} is not a file path", destination.display()));
+        };
+        fs::create_dir_all(parent)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", parent.display(), e))?;
+        let staged = parent.join(format!(".{}.owl-new", name.to_string_lossy()));
+        let written = fs::copy(source, &staged)
+            .and_then(|_| fs::set_permissions(&staged, fs::Permissions::from_mode(mode & 0o7777)))
+            .and_then(|_| fs::rename(&staged, &target));
+        written.map_err(|e| {
+            let _ = fs::remove_file(&staged);
+            anyhow!("Failed to write {}: {}", destination.display(), e)
+        })
+    }
+
+    fn systemctl(&self, verb: Verb, unit: &str) -> Result<()> {
+        // The helper's stdout carries the protocol, so systemctl talks on stderr
+        let status = Command::new(&self.systemctl)
+            .arg(verb.as_str())
+            .arg(unit)
+            .stdin(Stdio::null())
+            .stdout(std::io::stderr())
+            .status()
+            .map_err(|e| anyhow!("Failed to run systemctl {} {}: {}", verb.as_str(), unit, e))?;
+        if !status.success() {
+            return Err(anyhow!(
+                "systemctl {} {} failed ({})",
+                verb.as_str(),
+                unit,
+                status
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Serve the protocol on `input` and `output` until `input` ends
+///
+/// A manifest the helper refuses is answered and returned as the error.
+pub fn serve(
+    input: impl BufRead,
+    mut output: impl Write,
+    root: &Path,
+    systemctl: &str,
+) -> Result<()> {
+    let mut lines = input.lines();
+    let Some(first) = lines.next() else {
+        return Ok(());
+    };
+    let manifest = serde_json::from_str::<Manifest>(&first?)
+        .map_err(|e| e.to_string())
+        .and_then(|manifest| match manifest.invalid() {
+            Some(reason) => Err(reason),
+            None => Ok(manifest),
+        });
+    let manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(reason) => {
+            let error = anyhow!("Invalid manifest: {}", reason);
+            send(&mut output, &Reply::from(Err(anyhow!("{}", error))))?;
+            return Err(error);
+        }
+    };
+    send(&mut output, &Reply::from(Ok(())))?;
+
+    let helper = Helper::new(root, systemctl, manifest);
+    for line in lines {
+        let result = serde_json::from_str::<Op>(&line?)
+            .map_err(|e| anyhow!("refused: not an operation: {}", e))
+            .and_then(|op| helper.execute(&op));
+        send(&mut output, &Reply::from(result))?;
+    }
+    Ok(())
+}
+
+fn send(output: &mut impl Write, reply: &Reply) -> Result<()> {
+    let line = serde_json::to_string(reply)?;
+    writeln!(output, "{}", line)
+        .and_then(|_| output.flush())
+        .map_err(|e| anyhow!("Failed to answer: {}", e))
+}
+
+/// owl's end of a running helper
+pub struct Client {
+    input: Option<Box<dyn Write + Send>>,
+    output: Box<dyn BufRead + Send>,
+    child: Option<Child>,
+}
+
+impl Client {
+    /// Start `command` as the helper and hand it `manifest`
+    pub fn start(mut command: Command, manifest: &Manifest) -> Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("Failed to start the privileged helper: {}", e))?;
+        let (Some(input), Some(output)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(anyhow!("Failed to start the privileged helper: no pipes"));
+        };
+        Self::connect(
+            Box::new(input),
+            Box::new(BufReader::new(output)),
+            Some(child),
+            manifest,
+        )
+    }
+
+    /// Hand `manifest` to the helper at the other end of `input` and `output`
+    fn connect(
+        input: Box<dyn Write + Send>,
+        output: Box<dyn BufRead + Send>,
+        child: Option<Child>,
+        manifest: &Manifest,
+    ) -> Result<Self> {
+        let mut client = Self {
+            input: Some(input),
+            output,
+            child,
+        };
+        client
+            .exchange(&serde_json::to_string(manifest)?)
+            .map_err(|e| anyhow!("Failed to start the privileged helper: {}", e))?;
+        Ok(client)
+    }
+
+    /// Run `op` in the helper
+    pub fn call(&mut self, op: &Op) -> Result<()> {
+        self.exchange(&serde_json::to_string(op)?)
+            .map_err(|e| anyhow!("Failed to {}: {}", op, e))
+    }
+
+    /// Send one line and read the reply to it
+    fn exchange(&mut self, line: &str) -> Result<()> {
+        let gone = || anyhow!("the privileged helper exited");
+        let input = self.input.as_mut().ok_or_else(gone)?;
+        writeln!(input, "{}", line)
+            .and_then(|_| input.flush())
+            .map_err(|_| gone())?;
+        let mut answer = String::new();
+        if self.output.read_line(&mut answer).map_err(|_| gone())? == 0 {
+            self.input.take();
+            return Err(gone());
+        }
+        let reply: Reply = serde_json::from_str(&answer)
+            .map_err(|e| anyhow!("unexpected answer from the privileged helper: {}", e))?;
+        match reply {
+            Reply { ok: true, .. } => Ok(()),
+            Reply { error, .. } => Err(anyhow!(error.unwrap_or_else(|| "failed".to_string()))),
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // Closing its input ends the helper
+        self.input.take();
+        if let Some(mut child) = self.child.take() {
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::os::unix::fs::MetadataExt;
+
+    /// A systemctl that logs its arguments to `log` and fails for `broken.service`
+    fn fake_systemctl(dir: &Path, log: &Path) -> String {
+        let path = dir.join("systemctl");
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\necho \"$*\" >> '{}'\n[ \"$2\" != broken.service ]\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn replies(output: &[u8]) -> Vec<Reply> {
+        String::from_utf8_lossy(output)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_serve_runs_whitelisted_operations_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let log = dir.path().join("systemctl.log");
+        let systemctl = fake_systemctl(dir.path(), &log);
+        let source = dir.path().join("hosts");
+        fs::write(&source, "127.0.0.1 owl\n").unwrap();
+
+        let manifest = Manifest {
+            files: vec![PathBuf::from("/etc/hosts")],
+            units: vec!["sshd.service".to_string(), "broken.service".to_string()],
+        };
+        let ops = [
+            serde_json::to_string(&manifest).unwrap(),
+            format!(
+                r#"{{"op":"write_file","source":"{}","destination":"/etc/hosts","mode":420}}"#,
+                source.display()
+            ),
+            format!(
+                r#"{{"op":"write_file","source":"{}","destination":"/etc/shadow","mode":420}}"#,
+                source.display()
+            ),
+            r#"{"op":"systemctl","verb":"enable","unit":"sshd.service"}"#.to_string(),
+            r#"{"op":"systemctl","verb":"mask","unit":"sshd.service"}"#.to_string(),
+            r#"{"op":"systemctl","verb":"start","unit":"cups.service"}"#.to_string(),
+            r#"{"op":"run","command":"rm -rf /"}"#.to_string(),
+            r#"{"op":"systemctl","verb":"start","unit":"broken.service"}"#.to_string(),
+        ]
+        .join("\n");
+        let mut output = Vec::new();
+        serve(Cursor::new(ops), &mut output, &root, &systemctl).unwrap();
+
+        let replies = replies(&output);
+        let ok: Vec<bool> = replies.iter().map(|reply| reply.ok).collect();
+        assert_eq!(ok, [true, true, false, true, false, false, false, false]);
+        let error = |i: usize| replies[i].error.clone().unwrap();
+        assert_eq!(error(2), "refused: /etc/shadow is not in the manifest");
+        assert!(
+            error(4).starts_with("refused: not an operation"),
+            "{}",
+            error(4)
+        );
+        assert_eq!(error(5), "refused: cups.service is not in the manifest");
+        assert!(
+            error(6).starts_with("refused: not an operation"),
+            "{}",
+            error(6)
+        );
+        assert!(error(7).starts_with("systemctl start broken.service failed"));
+
+        let written = root.join("etc/hosts");
+        assert_eq!(fs::read_to_string(&written).unwrap(), "127.0.0.1 owl\n");
+        assert_eq!(fs::metadata(&written).unwrap().mode() & 0o7777, 0o644);
+        assert!(!root.join("etc/shadow").exists());
+        assert!(!root.join("etc/.hosts.owl-new").exists());
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            "enable sshd.service\nstart broken.service\n"
+        );
+    }
+
+    #[test]
+    fn test_serve_refuses_an_invalid_manifest() {
+        for manifest in [
+            r#"{"files":["/etc/../root/.ssh/authorized_keys"]}"#,
+            r#"{"files":["etc/hosts"]}"#,
+            r#"{"files":["/"]}"#,
+            r#"{"units":["--root=/"]}"#,
+            "not json",
+        ] {
+            let mut output = Vec::new();
+            let input = format!(
+                "{}\n{}\n",
+                manifest, r#"{"op":"systemctl","verb":"enable","unit":"x"}"#
+            );
+            let err = serve(Cursor::new(input), &mut output, Path::new("/"), "false")
+                .unwrap_err()
+                .to_string();
+            assert!(err.starts_with("Invalid manifest: "), "{}", err);
+            // The operation after it is never answered
+            let replies = replies(&output);
+            assert_eq!(replies.len(), 1, "{}", manifest);
+            assert!(!replies[0].ok);
+        }
+    }
+
+    #[test]
+    fn test_write_file_takes_regular_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::write(&target, "secret").unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let helper = Helper::new(
+            &dir.path().join("root"),
+            "false",
+            Manifest {
+                files: vec![PathBuf::from("/etc/motd")],
+                ..Default::default()
+            },
+        );
+        for source in [link, dir.path().to_path_buf()] {
+            let err = helper
+                .execute(&Op::WriteFile {
+                    source: source.clone(),
+                    destination: PathBuf::from("/etc/motd"),
+                    mode: 0o644,
+                })
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("{} is not a regular file", source.display())
+            );
+        }
+        assert!(!dir.path().join("root/etc/motd").exists());
+    }
+
+    #[test]
+    fn test_client_reports_each_result_over_a_pipe() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let log = dir.path().join("systemctl.log");
+        let systemctl = fake_systemctl(dir.path(), &log);
+
+        let (to_helper, helper_input) = {
+            let (reader, writer) = std::io::pipe().unwrap();
+            (writer, reader)
+        };
+        let (helper_output, from_helper) = {
+            let (reader, writer) = std::io::pipe().unwrap();
+            (writer, reader)
+        };
+        let server = std::thread::spawn(move || {
+            serve(
+                BufReader::new(helper_input),
+                helper_output,
+                &root,
+                &systemctl,
+            )
+        });
+
+        let manifest = Manifest {
+            units: vec!["sshd.service".to_string(), "broken.service".to_string()],
+            ..Default::default()
+        };
+        let mut client = Client::connect(
+            Box::new(to_helper),
+            Box::new(BufReader::new(from_helper)),
+            None,
+            &manifest,
+        )
+        .unwrap();
+        let enable = |unit: &str| Op::Systemctl {
+            verb: Verb::Enable,
+            unit: unit.to_string(),
+        };
+        client.call(&enable("sshd.service")).unwrap();
+        assert_eq!(
+            client
+                .call(&enable("cups.service"))
+                .unwrap_err()
+                .to_string(),
+            "Failed to systemctl enable cups.service: refused: cups.service is not in the manifest"
+        );
+        let err = client.call(&enable("broken.service")).unwrap_err();
+        assert!(
+            err.to_string().starts_with(
+                "Failed to systemctl enable broken.service: systemctl enable broken.service failed"
+            ),
+            "{}",
+            err
+        );
+
+        // Dropping the client ends the helper
+        drop(client);
+        server.join().unwrap().unwrap();
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            "enable sshd.service\nenable broken.service\n"
+        );
+    }
+
+    #[test]
+    fn test_client_reports_a_helper_that_never_answers() {
+        let manifest = Op::Systemctl {
+            verb: Verb::Stop,
+            unit: "sshd.service".to_string(),
+        }
+        .manifest();
+        let err = Client::start(Command::new("true"), &manifest)
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            err,
+            "Failed to start the privileged helper: the privileged helper exited"
+        );
+    }
+}
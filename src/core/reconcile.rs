@@ -0,0 +1,281 @@
+//! Bringing the managed package list back in line with the system
+//!
+//! Packages change outside owl: one is removed by hand and never declared
+//! again, or an upgrade swaps it for a renamed package through pacman's
+//! `Replaces`. Left alone, the managed list keeps the old name, so owl
+//! proposes bogus removals and never manages the replacement. The rules here
+//! only look at the installed, declared and managed sets and the replaces map;
+//! `package::reconcile_managed` gathers those and applies the result.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::core::state::PackageState;
+
+/// One correction to the managed list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StateChange {
+    /// Managed, but neither installed nor declared any more
+    Dropped { package: String },
+    /// Managed and gone, with an installed package that replaces it
+    Renamed {
+        from: String,
+        to: String,
+        /// The old name is still declared, so the config needs updating too
+        declared: bool,
+    },
+}
+
+/// Corrections for `managed`, in its order
+///
+/// `replaces` maps each installed package to the names it replaces. A missing
+/// managed package that an installed one replaces is renamed to it, whether
+/// or not it is still declared; otherwise it is dropped unless declared, since
+/// a declared package is installed again by this run.
+pub fn plan(
+    installed: &HashSet<String>,
+    declared: &HashSet<String>,
+    managed: &[String],
+    replaces: &HashMap<String, Vec<String>>,
+) -> Vec<StateChange> {
+    let mut replaced_by: HashMap<&str, &str> = HashMap::new();
+    let mut replacers: Vec<(&String, &Vec<String>)> = replaces
+        .iter()
+        .filter(|(package, _)| installed.contains(*package))
+        .collect();
+    // The alphabetically first replacement wins when several claim a name
+    replacers.sort();
+    for (package, names) in replacers.into_iter().rev() {
+        for name in names {
+            replaced_by.insert(name, package);
+        }
+    }
+
+    managed
+        .iter()
+        .filter(|package| !installed.contains(*package))
+        .filter_map(|package| match replaced_by.get(package.as_str()) {
+            Some(to) => Some(StateChange::Renamed {
+                from: package.clone(),
+                to: to.to_string(),
+                declared: declared.contains(package),
+            }),
+            None if !declared.contains(package) => Some(StateChange::Dropped {
+                package: package.clone(),
+            }),
+            None => None,
+        })
+        .collect()
+}
+
+/// Apply `changes` to the managed list
+pub fn apply(state: &mut PackageState, changes: &[StateChange]) {
+    for change in changes {
+        match change {
+            StateChange::Dropped { package } => state.remove_managed(package),
+            StateChange::Renamed { from, to, .. } => {
+                state.remove_managed(from);
+                state.add_managed(to.clone());
+            }
+        }
+    }
+}
+
+/// `state reconciled: 2 stale entries removed, 1 rename migrated`
+pub fn summary(changes: &[StateChange]) -> String {
+    let dropped = changes
+        .iter()
+        .filter(|c| matches!(c, StateChange::Dropped { .. }))
+        .count();
+    let renamed = changes.len() - dropped;
+    let mut parts = Vec::new();
+    if dropped > 0 {
+        parts.push(format!(
+            "{} stale {} removed",
+            dropped,
+            if dropped == 1 { "entry" } else { "entries" }
+        ));
+    }
+    if renamed > 0 {
+        parts.push(format!(
+            "{} {} migrated",
+            renamed,
+            if renamed == 1 { "rename" } else { "renames" }
+        ));
+    }
+    format!("state reconciled: {}", parts.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn list(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn dropped(package: &str) -> StateChange {
+        StateChange::Dropped {
+            package: package.to_string(),
+        }
+    }
+
+    fn renamed(from: &str, to: &str, declared: bool) -> StateChange {
+        StateChange::Renamed {
+            from: from.to_string(),
+            to: to.to_string(),
+            declared,
+        }
+    }
+
+    #[test]
+    fn test_plan_table() {
+        struct Case {
+            name: &'static str,
+            installed: &'static [&'static str],
+            declared: &'static [&'static str],
+            managed: &'static [&'static str],
+            replaces: &'static [(&'static str, &'static [&'static str])],
+            expected: Vec<StateChange>,
+        }
+        let cases = [
+            Case {
+                name: "in sync",
+                installed: &["fd", "git"],
+                declared: &["fd", "git"],
+                managed: &["fd", "git"],
+                replaces: &[],
+                expected: vec![],
+            },
+            Case {
+                name: "removed by hand and no longer declared",
+                installed: &["git"],
+                declared: &["git"],
+                managed: &["fd", "git"],
+                replaces: &[],
+                expected: vec![dropped("fd")],
+            },
+            Case {
+                name: "removed by hand but still declared is reinstalled, not dropped",
+                installed: &["git"],
+                declared: &["fd", "git"],
+                managed: &["fd", "git"],
+                replaces: &[],
+                expected: vec![],
+            },
+            Case {
+                name: "installed but undeclared is left for removal planning",
+                installed: &["fd", "git"],
+                declared: &["git"],
+                managed: &["fd", "git"],
+                replaces: &[],
+                expected: vec![],
+            },
+            Case {
+                name: "replaced during an upgrade",
+                installed: &["pipewire-jack"],
+                declared: &[],
+                managed: &["jack2"],
+                replaces: &[("pipewire-jack", &["jack", "jack2"])],
+                expected: vec![renamed("jack2", "pipewire-jack", false)],
+            },
+            Case {
+                name: "replaced while still declared",
+                installed: &["pipewire-jack"],
+                declared: &["jack2"],
+                managed: &["jack2"],
+                replaces: &[("pipewire-jack", &["jack2"])],
+                expected: vec![renamed("jack2", "pipewire-jack", true)],
+            },
+            Case {
+                name: "replaces entries of packages that are not installed are ignored",
+                installed: &["git"],
+                declared: &[],
+                managed: &["jack2"],
+                replaces: &[("pipewire-jack", &["jack2"])],
+                expected: vec![dropped("jack2")],
+            },
+            Case {
+                name: "an installed package is never renamed, even if something replaces it",
+                installed: &["jack2", "pipewire-jack"],
+                declared: &[],
+                managed: &["jack2"],
+                replaces: &[("pipewire-jack", &["jack2"])],
+                expected: vec![],
+            },
+            Case {
+                name: "several replacements pick the first by name",
+                installed: &["b-jack", "a-jack"],
+                declared: &[],
+                managed: &["jack2"],
+                replaces: &[("b-jack", &["jack2"]), ("a-jack", &["jack2"])],
+                expected: vec![renamed("jack2", "a-jack", false)],
+            },
+            Case {
+                name: "mixed, in managed order",
+                installed: &["git", "pipewire-jack"],
+                declared: &["git"],
+                managed: &["bat", "git", "jack2", "zoxide"],
+                replaces: &[("pipewire-jack", &["jack2"])],
+                expected: vec![
+                    dropped("bat"),
+                    renamed("jack2", "pipewire-jack", false),
+                    dropped("zoxide"),
+                ],
+            },
+        ];
+        for case in cases {
+            let replaces: HashMap<String, Vec<String>> = case
+                .replaces
+                .iter()
+                .map(|(package, names)| (package.to_string(), list(names)))
+                .collect();
+            let changes = plan(
+                &set(case.installed),
+                &set(case.declared),
+                &list(case.managed),
+                &replaces,
+            );
+            assert_eq!(changes, case.expected, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn test_apply_migrates_and_drops() {
+        let mut state = PackageState {
+            managed: list(&["bat", "jack2", "pipewire-jack"]),
+            ..Default::default()
+        };
+        state.installed_at.insert("bat".to_string(), 1);
+        apply(
+            &mut state,
+            &[dropped("bat"), renamed("jack2", "pipewire-jack", false)],
+        );
+        assert_eq!(state.managed, list(&["pipewire-jack"]));
+        assert!(state.installed_at.is_empty());
+
+        apply(&mut state, &[renamed("pipewire-jack", "jack", false)]);
+        assert_eq!(state.managed, list(&["jack"]));
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(
+            summary(&[dropped("a"), dropped("b"), renamed("c", "d", false)]),
+            "state reconciled: 2 stale entries removed, 1 rename migrated"
+        );
+        assert_eq!(
+            summary(&[dropped("a")]),
+            "state reconciled: 1 stale entry removed"
+        );
+        assert_eq!(
+            summary(&[renamed("a", "b", true), renamed("c", "d", false)]),
+            "state reconciled: 2 renames migrated"
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Reviewing a long plan in an editor: `owl plan --review-file` and
+//! `owl apply --approved-review`
+//!
+//! The plan is written as Markdown with a section per change kind, a table row
+//! or list item per change and a checkbox in front of each. Reading the file
+//! back gives the checkbox of every item, whatever order the sections and rows
+//! were moved into. The file carries a hash of the plan it was written from;
+//! an apply whose own plan hashes differently refuses the file, since the
+//! ticks no longer describe what would run.
+
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::core::plan::{ChangeKind, PackageChange};
+
+/// Marker of the comment holding the plan hash
+const HASH_MARKER: &str = "<!-- owl-review hash=";
+
+/// A part of the review file, one per kind of change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Section {
+    Install,
+    Upgrade,
+    Remove,
+    Dotfiles,
+}
+
+impl Section {
+    const ALL: [Section; 4] = [
+        Section::Install,
+        Section::Upgrade,
+        Section::Remove,
+        Section::Dotfiles,
+    ];
+
+    pub fn heading(self) -> &'static str {
+        match self {
+            Section::Install => "Install",
+            Section::Upgrade => "Upgrade",
+            Section::Remove => "Remove",
+            Section::Dotfiles => "Dotfiles",
+        }
+    }
+
+    fn of(change: ChangeKind) -> Section {
+        match change {
+            ChangeKind::Install => Section::Install,
+            ChangeKind::Upgrade => Section::Upgrade,
+            ChangeKind::Remove => Section::Remove,
+        }
+    }
+}
+
+/// A dotfile mapping the run would write
+#[derive(Debug, Clone, PartialEq)]
+pub struct DotfileChange {
+    /// Destination as written in the config, e.g. `~/.config/nvim`
+    pub destination: String,
+    pub source: String,
+    /// Nothing is at the destination yet
+    pub create: bool,
+}
+
+/// Everything a review file lists
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Review {
+    pub packages: Vec<PackageChange>,
+    pub dotfiles: Vec<DotfileChange>,
+}
+
+impl Review {
+    /// Every item as `(section, name)`, by section
+    pub fn items(&self) -> Vec<(Section, String)> {
+        let mut items: Vec<(Section, String)> = self
+            .packages
+            .iter()
+            .map(|p| (Section::of(p.change), p.name.clone()))
+            .chain(
+                self.dotfiles
+                    .iter()
+                    .map(|d| (Section::Dotfiles, d.destination.clone())),
+            )
+            .collect();
+        items.sort_by_key(|(section, _)| *section);
+        items
+    }
+
+    /// Hash of what the plan would do
+    ///
+    /// Covers each change, a package's repository and whether a dotfile is
+    /// created or updated, but not sizes, which move with every mirror sync.
+    pub fn hash(&self) -> String {
+        let mut lines: Vec<String> = self
+            .packages
+            .iter()
+            .map(|p| {
+                format!(
+                    "{} {} {}",
+                    Section::of(p.change).heading(),
+                    p.name,
+                    p.repo.as_deref().unwrap_or("-")
+                )
+            })
+            .chain(self.dotfiles.iter().map(|d| {
+                format!(
+                    "Dotfiles {} {} {}",
+                    d.destination,
+                    d.source,
+                    if d.create { "create" } else { "update" }
+                )
+            }))
+            .collect();
+        lines.sort();
+        let digest = Sha256::digest(lines.join("\n").as_bytes());
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// The review file, every item ticked
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::from("# owl plan\n\n");
+        out.push_str(&format!("{}{} -->\n\n", HASH_MARKER, self.hash()));
+        out.push_str(
+            "Untick what should not run, then pass this file to \
+             `owl apply --approved-review`. Unticked items are skipped and reported. \
+             The file is refused once the plan it was written from has changed.\n",
+        );
+        for section in [Section::Install, Section::Upgrade, Section::Remove] {
+            let rows: Vec<&PackageChange> = self
+                .packages
+                .iter()
+                .filter(|p| Section::of(p.change) == section)
+                .collect();
+            if !rows.is_empty() {
+                out.push_str(&package_table(section, &rows));
+            }
+        }
+        if !self.dotfiles.is_empty() {
+            out.push_str(&dotfile_groups(&self.dotfiles));
+        }
+        out
+    }
+}
+
+fn package_table(section: Section, rows: &[&PackageChange]) -> String {
+    let size = |s: Option<u64>| s.map_or("-".to_string(), crate::internal::util::format_size);
+    let mut out = format!("\n## {} ({})\n\n", section.heading(), rows.len());
+    if section == Section::Remove {
+        out.push_str("| Apply | Package | Notes |\n| --- | --- | --- |\n");
+    } else {
+        out.push_str(
+            "| Apply | Package | Repo | Download | Installed | Notes |\n\
+             | --- | --- | --- | --- | --- | --- |\n",
+        );
+    }
+    for p in rows {
+        let mut notes = Vec::new();
+        if p.reboot_advised {
+            notes.push("reboot advised".to_string());
+        }
+        if !p.services.is_empty() {
+            notes.push(format!("services: {}", p.services.join(", ")));
+        }
+        let notes = notes.join("; ");
+        if section == Section::Remove {
+            out.push_str(&format!("| [x] | `{}` | {} |\n", p.name, notes));
+        } else {
+            out.push_str(&format!(
+                "| [x] | `{}` | {} | {} | {} | {} |\n",
+                p.name,
+                p.repo.as_deref().unwrap_or("-"),
+                size(p.download_size),
+                size(p.installed_size),
+                notes
+            ));
+        }
+    }
+    out
+}
+
+/// Dotfiles grouped by the directory of their destination, each group folded
+fn dotfile_groups(dotfiles: &[DotfileChange]) -> String {
+    let mut groups: BTreeMap<&str, Vec<&DotfileChange>> = BTreeMap::new();
+    for d in dotfiles {
+        let dir = d.destination.rsplit_once('/').map_or(".", |(dir, _)| dir);
+        groups.entry(dir).or_default().push(d);
+    }
+    let mut out = format!(
+        "\n## {} ({})\n",
+        Section::Dotfiles.heading(),
+        dotfiles.len()
+    );
+    for (dir, entries) in groups {
+        out.push_str(&format!(
+            "\n<details>\n<summary>{} ({})</summary>\n\n",
+            dir,
+            entries.len()
+        ));
+        for d in entries {
+            out.push_str(&format!(
+                "- [x] `{}` from `{}` ({})\n",
+                d.destination,
+                d.source,
+                if d.create { "create" } else { "update" }
+            ));
+        }
+        out.push_str("\n</details>\n");
+    }
+    out
+}
+
+/// The ticks read back from a review file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Approval {
+    /// Hash of the plan the file was written from
+    pub hash: String,
+    pub ticked: HashMap<(Section, String), bool>,
+}
+
+/// Read the hash and every item's checkbox from a review file
+///
+/// Sections and rows may be in any order; text outside the items is ignored.
+pub fn parse(markdown: &str) -> Result<Approval> {
+    let mut hash = None;
+    let mut section = None;
+    let mut ticked = HashMap::new();
+    for (number, line) in markdown.lines().enumerate() {
+        let line = line.trim();
+        let fail = |what: &str| anyhow!("line {}: {}: {}", number + 1, what, line);
+        if let Some(rest) = line.strip_prefix(HASH_MARKER) {
+            hash = Some(rest.trim_end_matches("-->").trim().to_string());
+        } else if let Some(heading) = line.strip_prefix("## ") {
+            let title = heading.split(" (").next().unwrap_or(heading).trim();
+            section = Section::ALL.into_iter().find(|s| s.heading() == title);
+        } else if let Some(item) = item(line) {
+            let (checkbox, name) = item.ok_or_else(|| fail("unreadable item"))?;
+            let tick = match checkbox {
+                "[x]" | "[X]" => true,
+                "[ ]" => false,
+                _ => return Err(fail("checkbox must be [x] or [ ]")),
+            };
+            let section = section.ok_or_else(|| fail("item outside a plan section"))?;
+            if ticked.insert((section, name.to_string()), tick).is_some() {
+                return Err(fail("item listed twice"));
+            }
+        }
+    }
+    Ok(Approval {
+        hash: hash.ok_or_else(|| anyhow!("not an owl review file: the plan hash is missing"))?,
+        ticked,
+    })
+}
+
+/// `(checkbox, name)` of a table row or list item; `Some(None)` when the line
+/// looks like an item but is not readable
+fn item(line: &str) -> Option<Option<(&str, &str)>> {
+    let rest = line
+        .strip_prefix('|')
+        .or_else(|| line.strip_prefix("- "))?
+        .trim_start();
+    if !rest.starts_with('[') {
+        return None;
+    }
+    let name = rest
+        .get(3..)
+        .and_then(|rest| rest.split_once('`'))
+        .and_then(|(_, rest)| rest.split_once('`'))
+        .map(|(name, _)| name);
+    Some(rest.get(..3).zip(name))
+}
+
+impl Approval {
+    /// Refuse the file unless it was written from `current`, with no items
+    /// added or lost by hand
+    pub fn verify(&self, current: &Review) -> Result<()> {
+        let hash = current.hash();
+        if self.hash != hash {
+            return Err(anyhow!(
+                "the plan changed since the review file was written (hash {}, now {}); \
+                 write and review it again with owl plan --review-file",
+                self.hash,
+                hash
+            ));
+        }
+        let items = current.items();
+        if let Some((section, name)) = items.iter().find(|i| !self.ticked.contains_key(*i)) {
+            return Err(anyhow!(
+                "the review file has no checkbox for {} {}",
+                section.heading().to_lowercase(),
+                name
+            ));
+        }
+        if items.len() != self.ticked.len() {
+            return Err(anyhow!(
+                "the review file lists items the plan does not have"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the item is ticked
+    pub fn approves(&self, section: Section, name: &str) -> bool {
+        self.ticked
+            .get(&(section, name.to_string()))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Names unticked in `section`
+    pub fn declined(&self, section: Section) -> std::collections::HashSet<String> {
+        self.ticked
+            .iter()
+            .filter(|((s, _), tick)| *s == section && !**tick)
+            .map(|((_, name), _)| name.clone())
+            .collect()
+    }
+
+    /// Unticked items by section
+    pub fn skipped(&self) -> Vec<(Section, String)> {
+        let mut skipped: Vec<(Section, String)> = self
+            .ticked
+            .iter()
+            .filter(|(_, tick)| !**tick)
+            .map(|(key, _)| key.clone())
+            .collect();
+        skipped.sort();
+        skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(name: &str, change: ChangeKind, repo: Option<&str>) -> PackageChange {
+        PackageChange {
+            name: name.to_string(),
+            change,
+            repo: repo.map(str::to_string),
+            reboot_advised: name == "linux",
+            download_size: repo.map(|_| 1536),
+            installed_size: repo.map(|_| 4096),
+            services: Vec::new(),
+        }
+    }
+
+    fn dotfile(destination: &str, source: &str, create: bool) -> DotfileChange {
+        DotfileChange {
+            destination: destination.to_string(),
+            source: source.to_string(),
+            create,
+        }
+    }
+
+    fn review() -> Review {
+        Review {
+            packages: vec![
+                change("neovim", ChangeKind::Install, Some("extra")),
+                change("yay", ChangeKind::Install, Some("aur")),
+                change("linux", ChangeKind::Upgrade, Some("core")),
+                change("bat", ChangeKind::Remove, None),
+            ],
+            dotfiles: vec![
+                dotfile("~/.config/nvim", "nvim", false),
+                dotfile("~/.config/git/config", "git/config", true),
+                dotfile("~/.bashrc", "bashrc", false),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_written_file_reads_back_fully_ticked() {
+        let review = review();
+        let markdown = review.to_markdown();
+        assert!(markdown.contains("| [x] | `neovim` | extra | 1.5 KiB | 4.0 KiB |  |"));
+        assert!(markdown.contains("| [x] | `linux` | core | 1.5 KiB | 4.0 KiB | reboot advised |"));
+        assert!(markdown.contains("<summary>~/.config/git (1)</summary>"));
+        assert!(markdown.contains("<summary>~ (1)</summary>"));
+        assert!(markdown.contains("- [x] `~/.config/git/config` from `git/config` (create)"));
+
+        let approval = parse(&markdown).unwrap();
+        assert_eq!(approval.hash, review.hash());
+        approval.verify(&review).unwrap();
+        for (section, name) in review.items() {
+            assert!(approval.approves(section, &name), "{:?} {}", section, name);
+        }
+        assert!(approval.skipped().is_empty());
+    }
+
+    #[test]
+    fn test_hand_edited_file_with_reordered_sections() {
+        let review = review();
+        let markdown = review.to_markdown();
+        // Move the dotfiles above the packages, untick a few items and add notes
+        let (packages, dotfiles) = markdown.split_once("\n## Dotfiles").unwrap();
+        let edited = format!("## Dotfiles{}\nSome notes of mine.\n{}", dotfiles, packages)
+            .replace("| [x] | `yay`", "| [ ] | `yay`")
+            .replace("| [x] | `bat`", "|[ ]| `bat`")
+            .replace("- [x] `~/.bashrc`", "- [ ] `~/.bashrc`")
+            .replace("- [x] `~/.config/nvim`", "- [X] `~/.config/nvim`");
+
+        let approval = parse(&edited).unwrap();
+        approval.verify(&review).unwrap();
+        assert!(approval.approves(Section::Install, "neovim"));
+        assert!(!approval.approves(Section::Install, "yay"));
+        assert!(approval.approves(Section::Dotfiles, "~/.config/nvim"));
+        assert!(!approval.approves(Section::Dotfiles, "unknown"));
+        assert_eq!(
+            approval.declined(Section::Dotfiles),
+            ["~/.bashrc".to_string()].into()
+        );
+        assert_eq!(
+            approval.skipped(),
+            [
+                (Section::Install, "yay".to_string()),
+                (Section::Remove, "bat".to_string()),
+                (Section::Dotfiles, "~/.bashrc".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stale_or_damaged_files_are_refused() {
+        let written = review();
+        let markdown = written.to_markdown();
+
+        // A package became upgradable since the file was written
+        let mut current = written.clone();
+        current
+            .packages
+            .push(change("glibc", ChangeKind::Upgrade, Some("core")));
+        let err = parse(&markdown).unwrap().verify(&current).unwrap_err();
+        assert!(err.to_string().contains("plan changed"), "{}", err);
+        // Sizes alone do not make it stale
+        let mut resized = written.clone();
+        resized.packages[0].download_size = Some(1);
+        parse(&markdown).unwrap().verify(&resized).unwrap();
+
+        let dropped = markdown.replace("| [x] | `yay` | aur | 1.5 KiB | 4.0 KiB |  |\n", "");
+        let err = parse(&dropped).unwrap().verify(&written).unwrap_err();
+        assert!(
+            err.to_string().contains("no checkbox for install yay"),
+            "{}",
+            err
+        );
+
+        let doubled = markdown.replace("- [x] `~/.bashrc`", "- [x] `~/.config/nvim`");
+        assert!(parse(&doubled).unwrap_err().to_string().contains("twice"));
+        let bad_box = markdown.replace("| [x] | `yay`", "| [y] | `yay`");
+        assert!(
+            parse(&bad_box)
+                .unwrap_err()
+                .to_string()
+                .contains("checkbox")
+        );
+        assert!(parse("# notes\n- [x] `a`\n").is_err());
+    }
+}
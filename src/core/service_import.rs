@@ -0,0 +1,362 @@
+//! Turning services enabled outside owl into `:service` lines (`owl import --services`)
+//!
+//! Each enabled unit that is not noise (gettys, systemd's own units, the bus;
+//! `@option service_import_ignore` adds patterns) and not declared yet is
+//! matched to the package owning its unit file. A declared owner gets the
+//! `:service` in its block, an undeclared one a new `@package` block. Units no
+//! package owns go to the `@package _system-services` block, which takes any
+//! number of them. A package block holds one `:service`, and `:service` enables
+//! system units, so user units and a package's second unit are reported but not
+//! written.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::core::config::Config;
+use crate::core::config::format::ConfigFormat;
+use crate::core::services::{EnabledUnit, Scope};
+
+/// Units that come with the base system and are not worth declaring
+pub const DEFAULT_NOISE: &[&str] = &[
+    "getty@*",
+    "serial-getty@*",
+    "container-getty@*",
+    "autovt@*",
+    "systemd-*",
+    "dbus*",
+    "polkit*",
+    "user@*",
+    "p11-kit-*",
+];
+
+/// Where distribution packages install system and user unit files
+const SYSTEM_UNIT_DIR: &str = "/usr/lib/systemd/system";
+const USER_UNIT_DIR: &str = "/usr/lib/systemd/user";
+
+/// Where an imported unit goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Placement {
+    /// `:service` in the block of the declared owner, in `file` (relative to the owl root)
+    Attach { package: String, file: String },
+    /// A new `@package` block for the owner
+    Declare { package: String },
+    /// `:service` in the `@package _system-services` block, for a unit no package owns
+    SystemServices,
+    /// Not written, and why
+    Skip(String),
+}
+
+/// One enabled unit and where it would go
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub unit: EnabledUnit,
+    pub placement: Placement,
+}
+
+impl Suggestion {
+    pub fn directive(&self) -> String {
+        format!(":service {}", self.unit.name)
+    }
+}
+
+/// The packaged unit file of `unit`; instances (`foo@bar.service`) come from
+/// their template (`foo@.service`)
+pub fn unit_file(unit: &EnabledUnit) -> PathBuf {
+    let dir = match unit.scope {
+        Scope::System => SYSTEM_UNIT_DIR,
+        Scope::User => USER_UNIT_DIR,
+    };
+    let file = match unit.name.split_once('@') {
+        Some((prefix, rest)) => {
+            let suffix = rest.rsplit_once('.').map_or("", |(_, suffix)| suffix);
+            format!("{}@.{}", prefix, suffix)
+        }
+        None => unit.name.clone(),
+    };
+    Path::new(dir).join(file)
+}
+
+/// Whether `name` matches one of the noise `patterns`
+pub fn is_noise(name: &str, patterns: &[String]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| crate::internal::util::glob_match(pattern, name))
+}
+
+/// Decide where each enabled unit goes
+///
+/// `owners` maps unit files to the package owning them (`pacman -Qo`). Noise
+/// and units some package already declares are left out.
+pub fn plan(
+    units: &[EnabledUnit],
+    owners: &HashMap<PathBuf, String>,
+    config: &Config,
+) -> Vec<Suggestion> {
+    let noise = config.service_import_ignore();
+    let declared: HashSet<&str> = config
+        .packages
+        .values()
+        .filter_map(|pkg| pkg.service.as_deref())
+        .chain(config.system_services.iter().map(String::as_str))
+        .collect();
+    // Owner -> the unit this import already gives it
+    let mut claimed: HashMap<&str, &str> = HashMap::new();
+    let mut suggestions = Vec::new();
+    for unit in units {
+        if is_noise(&unit.name, &noise) || declared.contains(unit.name.as_str()) {
+            continue;
+        }
+        let owner = owners.get(&unit_file(unit));
+        let placement = if unit.scope == Scope::User {
+            Placement::Skip(":service enables system units; declare user units by hand".to_string())
+        } else if unit.name.contains("@.") {
+            Placement::Skip("a template; declare the instance you use by hand".to_string())
+        } else if let Some(owner) = owner {
+            place(owner, &claimed, config)
+        } else {
+            Placement::SystemServices
+        };
+        if let (Some(owner), Placement::Attach { .. } | Placement::Declare { .. }) =
+            (owner, &placement)
+        {
+            claimed.insert(owner, &unit.name);
+        }
+        suggestions.push(Suggestion {
+            unit: unit.clone(),
+            placement,
+        });
+    }
+    suggestions
+}
+
+/// Where a system unit owned by `owner` goes
+fn place(owner: &str, claimed: &HashMap<&str, &str>, config: &Config) -> Placement {
+    if let Some(unit) = claimed.get(owner) {
+        return Placement::Skip(format!(
+            "{} already gets {}; a package block holds one :service",
+            owner, unit
+        ));
+    }
+    let Some(package) = config.packages.get(owner) else {
+        return Placement::Declare {
+            package: owner.to_string(),
+        };
+    };
+    if let Some(service) = &package.service {
+        return Placement::Skip(format!("{} already has :service {}", owner, service));
+    }
+    match config
+        .declarations
+        .get(owner)
+        .and_then(|declarations| declarations.first())
+    {
+        Some(declaration)
+            if ConfigFormat::of_file(Path::new(&declaration.file)) != ConfigFormat::Owl =>
+        {
+            Placement::Skip(format!(
+                "{} is declared in {}; add the :service there by hand",
+                owner, declaration.file
+            ))
+        }
+        Some(declaration) => Placement::Attach {
+            package: owner.to_string(),
+            file: declaration.file.clone(),
+        },
+        // Declared, but not from a file (a bare parse); a new block merges with it
+        None => Placement::Declare {
+            package: owner.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::loader::Declaration;
+
+    fn system(name: &str) -> EnabledUnit {
+        EnabledUnit {
+            name: name.to_string(),
+            scope: Scope::System,
+        }
+    }
+
+    fn owners(list: &[(&str, &str)]) -> HashMap<PathBuf, String> {
+        list.iter()
+            .map(|(file, owner)| (PathBuf::from(file), owner.to_string()))
+            .collect()
+    }
+
+    fn declared_in(config: &mut Config, package: &str, file: &str) {
+        let declaration = Declaration {
+            file: file.to_string(),
+            package: config.packages[package].clone(),
+        };
+        config
+            .declarations
+            .insert(package.to_string(), vec![declaration]);
+    }
+
+    #[test]
+    fn test_unit_files_and_noise() {
+        assert_eq!(
+            unit_file(&system("sshd.service")),
+            PathBuf::from("/usr/lib/systemd/system/sshd.service")
+        );
+        assert_eq!(
+            unit_file(&system("wg-quick@wg0.service")),
+            PathBuf::from("/usr/lib/systemd/system/wg-quick@.service")
+        );
+        assert_eq!(
+            unit_file(&EnabledUnit {
+                name: "pipewire.service".to_string(),
+                scope: Scope::User,
+            }),
+            PathBuf::from("/usr/lib/systemd/user/pipewire.service")
+        );
+
+        let config = Config::parse("@option service_import_ignore=avahi*\n").unwrap();
+        let noise = config.service_import_ignore();
+        for name in [
+            "getty@.service",
+            "systemd-timesyncd.service",
+            "dbus-broker.service",
+            "avahi-daemon.service",
+        ] {
+            assert!(is_noise(name, &noise), "{}", name);
+        }
+        assert!(!is_noise("sshd.service", &noise));
+        assert!(!is_noise(
+            "avahi-daemon.service",
+            &Config::new().service_import_ignore()
+        ));
+    }
+
+    #[test]
+    fn test_owned_units_are_attached_or_declared() {
+        let mut config = Config::parse(
+            "@package openssh\n:config ssh -> ~/.ssh/config\n\
+             @package docker\n:service docker\n\
+             @package cronie\n@package bluez\n",
+        )
+        .unwrap();
+        declared_in(&mut config, "openssh", "groups/net.owl");
+        declared_in(&mut config, "bluez", "hosts/laptop.toml");
+        let units = [
+            system("sshd.service"),
+            system("docker.service"),
+            system("containerd.service"),
+            system("cups.service"),
+            system("cups-browsed.service"),
+            system("bluetooth.service"),
+            system("getty@.service"),
+            system("wg-quick@.service"),
+            system("local-backup.service"),
+            EnabledUnit {
+                name: "syncthing.service".to_string(),
+                scope: Scope::User,
+            },
+        ];
+        let owners = owners(&[
+            ("/usr/lib/systemd/system/sshd.service", "openssh"),
+            ("/usr/lib/systemd/system/docker.service", "docker"),
+            ("/usr/lib/systemd/system/containerd.service", "docker"),
+            ("/usr/lib/systemd/system/cups.service", "cups"),
+            ("/usr/lib/systemd/system/cups-browsed.service", "cups"),
+            ("/usr/lib/systemd/system/bluetooth.service", "bluez"),
+            (
+                "/usr/lib/systemd/system/wg-quick@.service",
+                "wireguard-tools",
+            ),
+            ("/usr/lib/systemd/user/syncthing.service", "syncthing"),
+        ]);
+
+        let placements: Vec<(String, Placement)> = plan(&units, &owners, &config)
+            .into_iter()
+            .map(|s| (s.unit.name, s.placement))
+            .collect();
+        let skip = |reason: &str| Placement::Skip(reason.to_string());
+        assert_eq!(
+            placements,
+            vec![
+                (
+                    "sshd.service".to_string(),
+                    Placement::Attach {
+                        package: "openssh".to_string(),
+                        file: "groups/net.owl".to_string(),
+                    }
+                ),
+                (
+                    "containerd.service".to_string(),
+                    skip("docker already has :service docker.service")
+                ),
+                (
+                    "cups.service".to_string(),
+                    Placement::Declare {
+                        package: "cups".to_string(),
+                    }
+                ),
+                (
+                    "cups-browsed.service".to_string(),
+                    skip("cups already gets cups.service; a package block holds one :service")
+                ),
+                (
+                    "bluetooth.service".to_string(),
+                    skip("bluez is declared in hosts/laptop.toml; add the :service there by hand")
+                ),
+                (
+                    "wg-quick@.service".to_string(),
+                    skip("a template; declare the instance you use by hand")
+                ),
+                (
+                    "local-backup.service".to_string(),
+                    Placement::SystemServices
+                ),
+                (
+                    "syncthing.service".to_string(),
+                    skip(":service enables system units; declare user units by hand")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unowned_units_go_to_system_services() {
+        let config =
+            Config::parse("@package _system-services\n:service fstrim.timer\n@package cronie\n")
+                .unwrap();
+        let units = [
+            system("local-backup.service"),
+            system("fstrim.timer"),
+            system("cronie.service"),
+            system("nightly-rsync.service"),
+            system("systemd-resolved.service"),
+        ];
+        // pacman -Qo answers only for files some package owns
+        let owners = owners(&[("/usr/lib/systemd/system/cronie.service", "cronie")]);
+
+        let placements: Vec<(String, Placement)> = plan(&units, &owners, &config)
+            .into_iter()
+            .map(|s| (s.unit.name, s.placement))
+            .collect();
+        assert_eq!(
+            placements,
+            vec![
+                (
+                    "local-backup.service".to_string(),
+                    Placement::SystemServices
+                ),
+                (
+                    "cronie.service".to_string(),
+                    Placement::Declare {
+                        package: "cronie".to_string(),
+                    }
+                ),
+                (
+                    "nightly-rsync.service".to_string(),
+                    Placement::SystemServices
+                ),
+            ]
+        );
+    }
+}
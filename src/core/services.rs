@@ -1,132 +1,476 @@
+//! Service enablement and its provenance
+//!
+//! Before owl touches a service it records whether the service was already
+//! enabled and active (`~/.owl/.state/services.json`). Only enablements owl
+//! performed itself, or that the user adopted with `owl services adopt`, are
+//! ever proposed for disabling.
+
 use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use crate::core::privileged_helper::{Op, Verb};
+use crate::internal::constants;
+
+const SERVICES_FILE: &str = "services.json";
+
 /// Result of service configuration operations
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ServiceResult {
     pub changed: bool,
     pub enabled_services: Vec<String>,
     pub started_services: Vec<String>,
     pub failed_services: Vec<String>,
+    /// Enabled before owl saw them and not adopted; owl never disables these
+    pub preexisting_services: Vec<String>,
+}
+
+/// Whether a unit belongs to the system or to the user's service manager
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    System,
+    User,
+}
+
+/// A service unit enabled in one scope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnabledUnit {
+    pub name: String,
+    pub scope: Scope,
+}
+
+/// Queries and changes system services
+pub trait ServiceManager {
+    fn is_enabled(&self, service: &str) -> Result<bool>;
+    fn is_active(&self, service: &str) -> Result<bool>;
+    fn enable(&self, service: &str) -> Result<()>;
+    fn start(&self, service: &str) -> Result<()>;
+    /// Disable the service and stop it (`systemctl disable --now`)
+    fn disable(&self, service: &str) -> Result<()>;
+    /// Service units enabled in the system and the user scope
+    fn list_enabled(&self) -> Result<Vec<EnabledUnit>>;
+}
+
+/// systemctl for system services; changes go through the privileged helper
+/// (`core::privilege::run`), queries run as the user
+pub struct Systemctl;
+
+impl Systemctl {
+    fn query(verb: &str, service: &str) -> Result<bool> {
+        let status = Command::new("systemctl")
+            .args([verb, "--quiet", service])
+            .status()
+            .map_err(|e| anyhow!("Failed to run systemctl {} for {}: {}", verb, service, e))?;
+        Ok(status.success())
+    }
+
+    fn change(verb: Verb, service: &str) -> Result<()> {
+        crate::core::privilege::run(&Op::Systemctl {
+            verb,
+            unit: service.to_string(),
+        })
+    }
+}
+
+impl ServiceManager for Systemctl {
+    fn is_enabled(&self, service: &str) -> Result<bool> {
+        Self::query("is-enabled", service)
+    }
+
+    fn is_active(&self, service: &str) -> Result<bool> {
+        Self::query("is-active", service)
+    }
+
+    fn enable(&self, service: &str) -> Result<()> {
+        Self::change(Verb::Enable, service)
+            .map_err(|e| anyhow!("Failed to enable service {}: {}", service, e))
+    }
+
+    fn start(&self, service: &str) -> Result<()> {
+        Self::change(Verb::Start, service)
+            .map_err(|e| anyhow!("Failed to start service {}: {}", service, e))
+    }
+
+    fn disable(&self, service: &str) -> Result<()> {
+        Self::change(Verb::Disable, service)
+            .and_then(|_| Self::change(Verb::Stop, service))
+            .map_err(|e| anyhow!("Failed to disable service {}: {}", service, e))
+    }
+
+    fn list_enabled(&self) -> Result<Vec<EnabledUnit>> {
+        let mut units = Vec::new();
+        for scope in [Scope::System, Scope::User] {
+            let mut cmd = Command::new("systemctl");
+            if scope == Scope::User {
+                cmd.arg("--user");
+            }
+            let output = cmd
+                .args([
+                    "list-unit-files",
+                    "--type=service",
+                    "--state=enabled",
+                    "--no-legend",
+                    "--no-pager",
+                ])
+                .output()
+                .map_err(|e| anyhow!("Failed to run systemctl list-unit-files: {}", e))?;
+            if !output.status.success() {
+                // Without a user session there is no user manager to ask
+                if scope == Scope::User {
+                    continue;
+                }
+                return Err(anyhow!(
+                    "systemctl list-unit-files failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            units.extend(
+                parse_unit_files(&String::from_utf8_lossy(&output.stdout))
+                    .into_iter()
+                    .map(|name| EnabledUnit { name, scope }),
+            );
+        }
+        Ok(units)
+    }
+}
+
+/// Unit names from `systemctl list-unit-files --no-legend` (`NAME STATE PRESET`)
+fn parse_unit_files(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Something owl did to a service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceAction {
+    Enabled,
+    Started,
+    /// The user took ownership of an existing enablement (`owl services adopt`)
+    Adopted,
+    /// Disabled and stopped after it left the config
+    Disabled,
+}
+
+/// A service's state before owl first touched it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PriorState {
+    pub enabled: bool,
+    pub active: bool,
+    /// Seconds since the Unix epoch
+    pub at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ServiceEvent {
+    pub action: ServiceAction,
+    pub at: u64,
 }
 
-/// Ensure all specified services are configured (enabled and started)
-fn check_enabled(service: &str) -> Result<bool> {
-    let status = Command::new("sudo")
-        .arg("systemctl")
-        .arg("is-enabled")
-        .arg("--quiet")
-        .arg(service)
-        .status()
-        .map_err(|e| anyhow!("Failed to run systemctl is-enabled for {}: {}", service, e))?;
-    Ok(status.success())
+/// Provenance of one service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceRecord {
+    pub first_seen: PriorState,
+    #[serde(default)]
+    pub actions: Vec<ServiceEvent>,
 }
 
-fn check_active(service: &str) -> Result<bool> {
-    let status = Command::new("sudo")
-        .arg("systemctl")
-        .arg("is-active")
-        .arg("--quiet")
-        .arg(service)
-        .status()
-        .map_err(|e| anyhow!("Failed to run systemctl is-active for {}: {}", service, e))?;
-    Ok(status.success())
+impl ServiceRecord {
+    /// Whether owl enabled the service or the user handed its enablement to
+    /// owl, and owl has not disabled it since
+    pub fn owl_managed(&self) -> bool {
+        self.actions
+            .iter()
+            .rev()
+            .find_map(|e| match e.action {
+                ServiceAction::Enabled | ServiceAction::Adopted => Some(true),
+                ServiceAction::Disabled => Some(false),
+                ServiceAction::Started => None,
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// Provenance of every service owl has looked at
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServiceLedger {
+    pub services: BTreeMap<String, ServiceRecord>,
 }
 
-pub fn ensure_services_configured(services: &[String]) -> Result<ServiceResult> {
-    if services.is_empty() {
-        return Ok(ServiceResult {
-            changed: false,
-            enabled_services: Vec::new(),
-            started_services: Vec::new(),
-            failed_services: Vec::new(),
-        });
+impl ServiceLedger {
+    /// Load the ledger for the current user (empty if none was recorded yet)
+    pub fn load() -> Result<Self> {
+        Self::load_from(&ledger_dir()?)
+    }
+
+    pub fn load_from(dir: &Path) -> Result<Self> {
+        let path = dir.join(SERVICES_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", SERVICES_FILE, e))?;
+        let ledger: Self = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse {}: {}", SERVICES_FILE, e))?;
+        Ok(ledger.normalized())
+    }
+
+    /// Records kept under a name without unit suffix (`sshd`, from before
+    /// `:service` added `.service`) moved to the full unit name
+    ///
+    /// When both names have a record the earlier first sighting wins and the
+    /// actions are combined.
+    fn normalized(self) -> Self {
+        let mut services: BTreeMap<String, ServiceRecord> = BTreeMap::new();
+        for (name, record) in self.services {
+            let name = crate::core::names::normalize_service_name(&name).unwrap_or(name);
+            match services.entry(name) {
+                std::collections::btree_map::Entry::Vacant(slot) => {
+                    slot.insert(record);
+                }
+                std::collections::btree_map::Entry::Occupied(mut slot) => {
+                    let existing = slot.get_mut();
+                    if record.first_seen.at < existing.first_seen.at {
+                        existing.first_seen = record.first_seen;
+                    }
+                    existing.actions.extend(record.actions);
+                    existing.actions.sort_by_key(|event| event.at);
+                }
+            }
+        }
+        Self { services }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&ledger_dir()?)
+    }
+
+    pub fn save_to(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", dir.display(), e))?;
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize {}: {}", SERVICES_FILE, e))?;
+        fs::write(dir.join(SERVICES_FILE), content)
+            .map_err(|e| anyhow!("Failed to write {}: {}", SERVICES_FILE, e))
+    }
+
+    /// Record the state of a service the first time owl sees it
+    fn observe(
+        &mut self,
+        service: &str,
+        enabled: bool,
+        active: bool,
+        at: u64,
+    ) -> &mut ServiceRecord {
+        self.services
+            .entry(service.to_string())
+            .or_insert(ServiceRecord {
+                first_seen: PriorState {
+                    enabled,
+                    active,
+                    at,
+                },
+                actions: Vec::new(),
+            })
+    }
+
+    pub fn is_owl_managed(&self, service: &str) -> bool {
+        self.services
+            .get(service)
+            .is_some_and(ServiceRecord::owl_managed)
+    }
+
+    /// Take ownership of an enabled service, so owl may later propose disabling it
+    pub fn adopt(&mut self, service: &str, manager: &dyn ServiceManager, at: u64) -> Result<()> {
+        if self.is_owl_managed(service) {
+            return Err(anyhow!("{} is already managed by owl", service));
+        }
+        let enabled = manager.is_enabled(service)?;
+        if !enabled {
+            return Err(anyhow!(
+                "{} is not enabled; declare it with :service and run owl apply",
+                service
+            ));
+        }
+        let active = manager.is_active(service)?;
+        self.observe(service, enabled, active, at)
+            .actions
+            .push(ServiceEvent {
+                action: ServiceAction::Adopted,
+                at,
+            });
+        Ok(())
+    }
+
+    /// Services owl enabled that are no longer declared, sorted
+    ///
+    /// Pre-existing enablements never show up here unless they were adopted.
+    pub fn teardown_candidates(&self, configured: &[String]) -> Vec<String> {
+        self.services
+            .iter()
+            .filter(|(name, record)| record.owl_managed() && !configured.contains(name))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Disable and stop `services`, recording each one that was
+    ///
+    /// Returns the services that were disabled and those that failed.
+    pub fn teardown(
+        &mut self,
+        services: &[String],
+        manager: &dyn ServiceManager,
+        at: u64,
+    ) -> (Vec<String>, Vec<String>) {
+        let mut disabled = Vec::new();
+        let mut failed = Vec::new();
+        for service in services {
+            if manager.disable(service).is_err() {
+                failed.push(service.clone());
+                continue;
+            }
+            if let Some(record) = self.services.get_mut(service) {
+                record.actions.push(ServiceEvent {
+                    action: ServiceAction::Disabled,
+                    at,
+                });
+            }
+            disabled.push(service.clone());
+        }
+        (disabled, failed)
     }
+}
+
+fn ledger_dir() -> Result<PathBuf> {
+    Ok(crate::internal::environment::get()
+        .owl_dir()?
+        .join(constants::STATE_DIR))
+}
+
+/// What `apply` would do to one service (`apply --diff`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServiceChange {
+    pub service: String,
+    /// Not enabled, so apply would enable it
+    pub enable: bool,
+    /// Not running, so apply would start it
+    pub start: bool,
+    /// Why the state could not be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Read each service's state and work out what applying would change, without changing it
+pub fn plan_service_changes(
+    services: &[String],
+    manager: &dyn ServiceManager,
+) -> Vec<ServiceChange> {
+    services
+        .iter()
+        .map(|service| {
+            let state = manager
+                .is_enabled(service)
+                .and_then(|enabled| Ok((enabled, manager.is_active(service)?)));
+            match state {
+                Ok((enabled, active)) => ServiceChange {
+                    service: service.clone(),
+                    enable: !enabled,
+                    start: !active,
+                    error: None,
+                },
+                Err(e) => ServiceChange {
+                    service: service.clone(),
+                    enable: false,
+                    start: false,
+                    error: Some(e.to_string()),
+                },
+            }
+        })
+        .collect()
+}
 
+/// Ensure all specified services are enabled and started, recording provenance in `ledger`
+///
+/// Each service's enabled and active state is captured before anything changes.
+pub fn ensure_services_configured(
+    services: &[String],
+    manager: &dyn ServiceManager,
+    ledger: &mut ServiceLedger,
+    now: u64,
+) -> Result<ServiceResult> {
     let mut result = ServiceResult {
         changed: false,
         enabled_services: Vec::new(),
         started_services: Vec::new(),
         failed_services: Vec::new(),
+        preexisting_services: Vec::new(),
     };
     for service in services {
-        // Enable only if not enabled
-        match check_enabled(service) {
-            Ok(true) => {}
-            Ok(false) => {
-                match Command::new("sudo")
-                    .arg("systemctl")
-                    .arg("enable")
-                    .arg(service)
-                    .status()
-                {
-                    Ok(status) if status.success() => {
-                        result.changed = true;
-                        result.enabled_services.push(service.to_string());
-                    }
-                    Ok(_) | Err(_) => {
-                        result.failed_services.push(service.to_string());
-                        eprintln!(
-                            "{}",
-                            crate::internal::color::red(&format!(
-                                "Failed to enable service {}",
-                                service
-                            ))
-                        );
-                        continue;
-                    }
-                }
-            }
-            Err(e) => {
+        let state = manager
+            .is_enabled(service)
+            .map_err(|e| (e, "enabled"))
+            .and_then(|enabled| {
+                manager
+                    .is_active(service)
+                    .map(|active| (enabled, active))
+                    .map_err(|e| (e, "active"))
+            });
+        let (enabled, active) = match state {
+            Ok(state) => state,
+            Err((e, check)) => {
                 result.failed_services.push(service.to_string());
-                eprintln!(
+                errln!(
                     "{}",
                     crate::internal::color::red(&format!(
-                        "Service {} status check failed (enabled): {}",
-                        service, e
+                        "Service {} status check failed ({}): {}",
+                        service, check, e
                     ))
                 );
                 continue;
             }
+        };
+        let record = ledger.observe(service, enabled, active, now);
+
+        // Enable only if not enabled
+        if enabled {
+            if !record.owl_managed() {
+                result.preexisting_services.push(service.to_string());
+            }
+        } else if let Err(e) = manager.enable(service) {
+            result.failed_services.push(service.to_string());
+            errln!("{}", crate::internal::color::red(&e.to_string()));
+            continue;
+        } else {
+            record.actions.push(ServiceEvent {
+                action: ServiceAction::Enabled,
+                at: now,
+            });
+            result.changed = true;
+            result.enabled_services.push(service.to_string());
         }
 
         // Start only if not running
-        match check_active(service) {
-            Ok(true) => {}
-            Ok(false) => {
-                match Command::new("sudo")
-                    .arg("systemctl")
-                    .arg("start")
-                    .arg(service)
-                    .status()
-                {
-                    Ok(status) if status.success() => {
-                        result.changed = true;
-                        result.started_services.push(service.to_string());
-                    }
-                    Ok(_) | Err(_) => {
-                        result.failed_services.push(service.to_string());
-                        eprintln!(
-                            "{}",
-                            crate::internal::color::red(&format!(
-                                "Failed to start service {}",
-                                service
-                            ))
-                        );
-                        continue;
-                    }
-                }
+        if active {
+            continue;
+        }
+        match manager.start(service) {
+            Ok(()) => {
+                record.actions.push(ServiceEvent {
+                    action: ServiceAction::Started,
+                    at: now,
+                });
+                result.changed = true;
+                result.started_services.push(service.to_string());
             }
             Err(e) => {
                 result.failed_services.push(service.to_string());
-                eprintln!(
-                    "{}",
-                    crate::internal::color::red(&format!(
-                        "Service {} status check failed (active): {}",
-                        service, e
-                    ))
-                );
-                continue;
+                errln!("{}", crate::internal::color::red(&e.to_string()));
             }
         }
     }
@@ -134,14 +478,341 @@ pub fn ensure_services_configured(services: &[String]) -> Result<ServiceResult>
 }
 
 /// Get configured services from config
+/// Services to enable, in `:after` order
+///
+/// Cycles are rejected during analysis; should one get here, services fall back
+/// to name order.
 pub fn get_configured_services(config: &crate::core::config::Config) -> Vec<String> {
+    if let Ok(services) = config.service_order() {
+        return services;
+    }
     let mut services = Vec::new();
     for pkg in config.packages.values() {
         if let Some(ref svc) = pkg.service {
             services.push(svc.clone());
         }
     }
+    services.extend(config.system_services.iter().cloned());
     services.sort();
     services.dedup();
     services
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+
+    /// Services in `enabled`/`active` start out that way; every call is logged
+    #[derive(Default)]
+    struct FakeServices {
+        enabled: RefCell<HashSet<String>>,
+        active: RefCell<HashSet<String>>,
+        fail_enable: HashSet<String>,
+        fail_disable: HashSet<String>,
+        log: RefCell<Vec<String>>,
+    }
+
+    impl FakeServices {
+        fn with(enabled: &[&str], active: &[&str]) -> Self {
+            FakeServices {
+                enabled: RefCell::new(enabled.iter().map(|s| s.to_string()).collect()),
+                active: RefCell::new(active.iter().map(|s| s.to_string()).collect()),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl ServiceManager for FakeServices {
+        fn is_enabled(&self, service: &str) -> Result<bool> {
+            self.log
+                .borrow_mut()
+                .push(format!("is-enabled {}", service));
+            Ok(self.enabled.borrow().contains(service))
+        }
+
+        fn is_active(&self, service: &str) -> Result<bool> {
+            self.log.borrow_mut().push(format!("is-active {}", service));
+            Ok(self.active.borrow().contains(service))
+        }
+
+        fn enable(&self, service: &str) -> Result<()> {
+            self.log.borrow_mut().push(format!("enable {}", service));
+            if self.fail_enable.contains(service) {
+                return Err(anyhow!("Failed to enable service {}", service));
+            }
+            self.enabled.borrow_mut().insert(service.to_string());
+            Ok(())
+        }
+
+        fn start(&self, service: &str) -> Result<()> {
+            self.log.borrow_mut().push(format!("start {}", service));
+            self.active.borrow_mut().insert(service.to_string());
+            Ok(())
+        }
+
+        fn disable(&self, service: &str) -> Result<()> {
+            self.log.borrow_mut().push(format!("disable {}", service));
+            if self.fail_disable.contains(service) {
+                return Err(anyhow!("Failed to disable service {}", service));
+            }
+            self.enabled.borrow_mut().remove(service);
+            self.active.borrow_mut().remove(service);
+            Ok(())
+        }
+
+        fn list_enabled(&self) -> Result<Vec<EnabledUnit>> {
+            let mut names: Vec<String> = self.enabled.borrow().iter().cloned().collect();
+            names.sort();
+            Ok(names
+                .into_iter()
+                .map(|name| EnabledUnit {
+                    name,
+                    scope: Scope::System,
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_parse_unit_files() {
+        let output = "sshd.service                 enabled disabled\n\
+                      getty@.service               enabled enabled\n\n";
+        assert_eq!(
+            parse_unit_files(output),
+            names(&["sshd.service", "getty@.service"])
+        );
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_prior_state_captured_before_mutation() {
+        let fake = FakeServices::with(&[], &[]);
+        let mut ledger = ServiceLedger::default();
+        let result =
+            ensure_services_configured(&names(&["sshd.service"]), &fake, &mut ledger, 100).unwrap();
+
+        assert_eq!(
+            *fake.log.borrow(),
+            names(&[
+                "is-enabled sshd.service",
+                "is-active sshd.service",
+                "enable sshd.service",
+                "start sshd.service"
+            ])
+        );
+        let record = &ledger.services["sshd.service"];
+        assert_eq!(
+            record.first_seen,
+            PriorState {
+                enabled: false,
+                active: false,
+                at: 100
+            }
+        );
+        assert_eq!(
+            record.actions,
+            vec![
+                ServiceEvent {
+                    action: ServiceAction::Enabled,
+                    at: 100
+                },
+                ServiceEvent {
+                    action: ServiceAction::Started,
+                    at: 100
+                }
+            ]
+        );
+        assert_eq!(result.enabled_services, names(&["sshd.service"]));
+        assert!(result.preexisting_services.is_empty());
+    }
+
+    #[test]
+    fn test_plan_service_changes_only_reads() {
+        let manager = FakeServices::with(&["cups.service", "sshd.service"], &["sshd.service"]);
+        let changes = plan_service_changes(
+            &names(&["cups.service", "docker.service", "sshd.service"]),
+            &manager,
+        );
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|c| (c.service.as_str(), c.enable, c.start))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("cups.service", false, true),
+                ("docker.service", true, true),
+                ("sshd.service", false, false),
+            ]
+        );
+        assert!(
+            manager
+                .log
+                .borrow()
+                .iter()
+                .all(|call| call.starts_with("is-"))
+        );
+    }
+
+    #[test]
+    fn test_preexisting_enablement_is_reported_and_kept() {
+        let fake = FakeServices::with(&["NetworkManager.service"], &[]);
+        let mut ledger = ServiceLedger::default();
+        let services = names(&["NetworkManager.service"]);
+        let result = ensure_services_configured(&services, &fake, &mut ledger, 100).unwrap();
+        assert_eq!(result.preexisting_services, services);
+        assert_eq!(result.started_services, services);
+        assert!(!ledger.is_owl_managed("NetworkManager.service"));
+
+        // Later runs keep the first-seen state and still report it as pre-existing
+        let result = ensure_services_configured(&services, &fake, &mut ledger, 200).unwrap();
+        assert!(!result.changed);
+        assert_eq!(result.preexisting_services, services);
+        assert_eq!(ledger.services["NetworkManager.service"].first_seen.at, 100);
+    }
+
+    #[test]
+    fn test_owl_enabled_service_is_not_preexisting_later() {
+        let fake = FakeServices::with(&[], &[]);
+        let mut ledger = ServiceLedger::default();
+        let services = names(&["sshd.service"]);
+        ensure_services_configured(&services, &fake, &mut ledger, 100).unwrap();
+        let result = ensure_services_configured(&services, &fake, &mut ledger, 200).unwrap();
+        assert!(!result.changed);
+        assert!(result.preexisting_services.is_empty());
+    }
+
+    #[test]
+    fn test_failed_enable_records_no_action() {
+        let mut fake = FakeServices::with(&[], &[]);
+        fake.fail_enable.insert("bad.service".to_string());
+        let mut ledger = ServiceLedger::default();
+        let result =
+            ensure_services_configured(&names(&["bad.service"]), &fake, &mut ledger, 100).unwrap();
+        assert_eq!(result.failed_services, names(&["bad.service"]));
+        assert!(ledger.services["bad.service"].actions.is_empty());
+        assert!(!fake.log.borrow().contains(&"start bad.service".to_string()));
+    }
+
+    #[test]
+    fn test_teardown_only_proposes_owl_enablements() {
+        let fake = FakeServices::with(&["cups.service", "bluetooth.service"], &[]);
+        let mut ledger = ServiceLedger::default();
+        let all = names(&["cups.service", "sshd.service", "bluetooth.service"]);
+        ensure_services_configured(&all, &fake, &mut ledger, 100).unwrap();
+
+        // Every service was dropped from config; only sshd was enabled by owl
+        assert_eq!(ledger.teardown_candidates(&[]), names(&["sshd.service"]));
+        assert!(
+            ledger
+                .teardown_candidates(&names(&["sshd.service"]))
+                .is_empty()
+        );
+
+        ledger.adopt("cups.service", &fake, 300).unwrap();
+        assert_eq!(
+            ledger.teardown_candidates(&[]),
+            names(&["cups.service", "sshd.service"])
+        );
+    }
+
+    #[test]
+    fn test_teardown_disables_and_records() {
+        let mut fake = FakeServices::with(&[], &[]);
+        fake.fail_disable.insert("cups.service".to_string());
+        let mut ledger = ServiceLedger::default();
+        let all = names(&["sshd.service", "cups.service"]);
+        ensure_services_configured(&all, &fake, &mut ledger, 100).unwrap();
+
+        let (disabled, failed) = ledger.teardown(&all, &fake, 200);
+        assert_eq!(disabled, names(&["sshd.service"]));
+        assert_eq!(failed, names(&["cups.service"]));
+        assert!(!fake.enabled.borrow().contains("sshd.service"));
+        assert!(!fake.active.borrow().contains("sshd.service"));
+        // Only the failed one is proposed again
+        assert_eq!(ledger.teardown_candidates(&[]), names(&["cups.service"]));
+
+        // Declaring it again hands it back to owl
+        ensure_services_configured(&names(&["sshd.service"]), &fake, &mut ledger, 300).unwrap();
+        assert!(ledger.is_owl_managed("sshd.service"));
+    }
+
+    #[test]
+    fn test_adopt_requires_enabled_and_unmanaged_service() {
+        let fake = FakeServices::with(&["cups.service"], &["cups.service"]);
+        let mut ledger = ServiceLedger::default();
+        assert!(ledger.adopt("sshd.service", &fake, 100).is_err());
+        assert!(!ledger.services.contains_key("sshd.service"));
+
+        ledger.adopt("cups.service", &fake, 100).unwrap();
+        let record = &ledger.services["cups.service"];
+        assert_eq!(
+            record.first_seen,
+            PriorState {
+                enabled: true,
+                active: true,
+                at: 100
+            }
+        );
+        assert!(ledger.adopt("cups.service", &fake, 200).is_err());
+        assert!(
+            !fake
+                .log
+                .borrow()
+                .iter()
+                .any(|call| call.starts_with("enable"))
+        );
+    }
+
+    #[test]
+    fn test_ledger_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            ServiceLedger::load_from(dir.path()).unwrap(),
+            ServiceLedger::default()
+        );
+        let fake = FakeServices::with(&["cups.service"], &[]);
+        let mut ledger = ServiceLedger::default();
+        ensure_services_configured(
+            &names(&["cups.service", "sshd.service"]),
+            &fake,
+            &mut ledger,
+            100,
+        )
+        .unwrap();
+        ledger.save_to(dir.path()).unwrap();
+
+        let content = fs::read_to_string(dir.path().join(SERVICES_FILE)).unwrap();
+        assert!(content.contains("\"action\": \"enabled\""), "{}", content);
+        assert_eq!(ServiceLedger::load_from(dir.path()).unwrap(), ledger);
+    }
+
+    #[test]
+    fn test_records_under_bare_names_move_to_the_unit_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = FakeServices::default();
+        let mut old = ServiceLedger::default();
+        ensure_services_configured(&names(&["sshd"]), &fake, &mut old, 100).unwrap();
+        ensure_services_configured(&names(&["sshd.service", "cups"]), &fake, &mut old, 200)
+            .unwrap();
+        old.save_to(dir.path()).unwrap();
+
+        let ledger = ServiceLedger::load_from(dir.path()).unwrap();
+        assert_eq!(
+            ledger.services.keys().collect::<Vec<_>>(),
+            ["cups.service", "sshd.service"]
+        );
+        let sshd = &ledger.services["sshd.service"];
+        assert_eq!(sshd.first_seen.at, 100);
+        assert!(sshd.owl_managed());
+        assert_eq!(
+            ledger.teardown_candidates(&names(&["sshd.service"])),
+            names(&["cups.service"])
+        );
+    }
+}
@@ -0,0 +1,373 @@
+//! One home directory applied from several machines (`@option shared_home=true`)
+//!
+//! With an NFS-shared home every host's apply writes into the same `~/.owl` and
+//! the same dotfile destinations, so host-specific variants undo each other on
+//! each run. Each host records the dotfile contents it deployed in
+//! `.state/hosts/<hostname>.json`. Before syncing, the records of other hosts
+//! that applied recently are compared with this host's plan, and destinations
+//! they deployed with different content are reported before being overwritten.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::core::config::Config;
+use crate::core::dotfiles::{DotfileMapping, DotfileRoots, HashAlgo};
+use crate::core::events::{EventSink, OwlEvent};
+
+/// Directory under the state directory holding one record per host
+const HOSTS_DIR: &str = "hosts";
+
+/// Records older than this are from a host that no longer applies here
+pub const RECENT_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// Dotfile contents one host deployed, by destination file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostRecord {
+    /// When the host last applied (seconds since the Unix epoch)
+    pub applied_at: u64,
+    /// Destination file path to the xxh3 digest of the content written there
+    pub files: BTreeMap<String, String>,
+}
+
+impl HostRecord {
+    /// Every host's record in `state_dir`, by hostname
+    pub fn load_all(state_dir: &Path) -> Result<BTreeMap<String, HostRecord>> {
+        let dir = state_dir.join(HOSTS_DIR);
+        let mut records = BTreeMap::new();
+        if !dir.is_dir() {
+            return Ok(records);
+        }
+        for entry in
+            fs::read_dir(&dir).map_err(|e| anyhow!("Failed to read {}: {}", dir.display(), e))?
+        {
+            let path = entry
+                .map_err(|e| anyhow!("Failed to read entry in {}: {}", dir.display(), e))?
+                .path();
+            let Some(host) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".json"))
+            else {
+                continue;
+            };
+            let content = fs::read_to_string(&path)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            let record = serde_json::from_str(&content)
+                .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+            records.insert(host.to_string(), record);
+        }
+        Ok(records)
+    }
+
+    pub fn save(&self, state_dir: &Path, host: &str) -> Result<()> {
+        let path = record_path(state_dir, host)?;
+        let dir = state_dir.join(HOSTS_DIR);
+        fs::create_dir_all(&dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", dir.display(), e))?;
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize {}: {}", path.display(), e))?;
+        fs::write(&path, content).map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+fn record_path(state_dir: &Path, host: &str) -> Result<PathBuf> {
+    if host.is_empty() || host.contains('/') || host.starts_with('.') {
+        return Err(anyhow!("Hostname '{}' cannot name a state file", host));
+    }
+    Ok(state_dir.join(HOSTS_DIR).join(format!("{}.json", host)))
+}
+
+/// Destinations another host deployed with different content than this host would
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostConflict {
+    pub host: String,
+    /// When that host last applied
+    pub applied_at: u64,
+    pub destinations: Vec<String>,
+}
+
+/// This machine's hostname when the config shares its home with other hosts
+pub fn host(config: &Config) -> Result<Option<String>> {
+    if !config.shared_home()? {
+        return Ok(None);
+    }
+    let host = crate::internal::environment::get().hostname()?;
+    Ok(Some(host.to_string()))
+}
+
+/// Every file `mappings` deploy, by destination, with the digest of its source
+///
+/// Mappings whose source is missing or that escape their roots are left out;
+/// the sync reports those itself.
+pub fn planned_files(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    for m in mappings.iter().filter(|m| roots.escape_reason(m).is_none()) {
+        let src = roots.source(m);
+        let dst = roots.destination(m);
+        let pairs = if src.is_dir() {
+            let mut rels = Vec::new();
+            crate::core::dotfiles::collect_files_recursively(&src, &mut rels, &src)?;
+            rels.into_iter()
+                .map(|rel| (src.join(&rel), dst.join(&rel)))
+                .collect()
+        } else if src.is_file() {
+            vec![(src, dst)]
+        } else {
+            Vec::new()
+        };
+        for (src, dst) in pairs {
+            files.insert(dst.to_string_lossy().into_owned(), digest(&src)?);
+        }
+    }
+    Ok(files)
+}
+
+/// Digests are always xxh3 so hosts using different `--hash-algo` still compare
+fn digest(path: &Path) -> Result<String> {
+    let data = fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    Ok(HashAlgo::Xxh3.digest(&data))
+}
+
+/// Destinations other hosts that applied since `now - RECENT_SECS` deployed
+/// with content other than `planned`, where this run would write
+///
+/// `would_write(destination, digest)` tells whether the destination does not
+/// hold `digest` yet; destinations already holding this host's content were
+/// overwritten by an earlier run and are not reported again.
+pub fn conflicts(
+    host: &str,
+    planned: &BTreeMap<String, String>,
+    records: &BTreeMap<String, HostRecord>,
+    now: u64,
+    would_write: impl Fn(&str, &str) -> bool,
+) -> Vec<HostConflict> {
+    records
+        .iter()
+        .filter(|(other, record)| other.as_str() != host && record.applied_at + RECENT_SECS >= now)
+        .filter_map(|(other, record)| {
+            let destinations: Vec<String> = planned
+                .iter()
+                .filter(|(dest, digest)| {
+                    record
+                        .files
+                        .get(*dest)
+                        .is_some_and(|theirs| theirs != *digest)
+                        && would_write(dest, digest)
+                })
+                .map(|(dest, _)| dest.clone())
+                .collect();
+            (!destinations.is_empty()).then(|| HostConflict {
+                host: other.clone(),
+                applied_at: record.applied_at,
+                destinations,
+            })
+        })
+        .collect()
+}
+
+/// Report other hosts' deployments this sync overwrites, returning this host's
+/// planned files for `record`
+///
+/// Failures are warnings: the sync itself does not depend on the records.
+pub fn check(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    host: &str,
+    sink: &mut dyn EventSink,
+) -> Option<BTreeMap<String, String>> {
+    let state_dir = roots.owl_dir.join(crate::internal::constants::STATE_DIR);
+    let result = planned_files(roots, mappings).and_then(|planned| {
+        let records = HostRecord::load_all(&state_dir)?;
+        let would_write = |dest: &str, planned: &str| {
+            digest(Path::new(dest)).map_or(true, |current| current != planned)
+        };
+        let now = crate::internal::time::now_secs();
+        for conflict in conflicts(host, &planned, &records, now, would_write) {
+            sink.emit(OwlEvent::SharedHomeConflict {
+                host: conflict.host,
+                applied_at: conflict.applied_at,
+                destinations: conflict.destinations,
+            });
+        }
+        Ok(planned)
+    });
+    result
+        .map_err(|e| {
+            sink.emit(OwlEvent::Warning(format!(
+                "Failed to check other hosts sharing this home: {}",
+                e
+            )))
+        })
+        .ok()
+}
+
+/// Save `files` as what `host` deployed in this run
+pub fn record(
+    roots: &DotfileRoots,
+    host: &str,
+    files: BTreeMap<String, String>,
+    sink: &mut dyn EventSink,
+) {
+    let state_dir = roots.owl_dir.join(crate::internal::constants::STATE_DIR);
+    let record = HostRecord {
+        applied_at: crate::internal::time::now_secs(),
+        files,
+    };
+    if let Err(e) = record.save(&state_dir, host) {
+        sink.emit(OwlEvent::Warning(format!(
+            "Failed to record this host's dotfiles for the shared home: {}",
+            e
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(home: &Path, host: &str) -> DotfileRoots {
+        DotfileRoots {
+            owl_dir: home.join(".owl"),
+            source_dir: home.join(".owl/dotfiles"),
+            home: home.to_string_lossy().into_owned(),
+            backup_dir: home.join(".owl/.state/backups"),
+            dest_prefix: None,
+            hash_algo: HashAlgo::Sha256,
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: Some(host.to_string()),
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        }
+    }
+
+    fn mapping(source: &str, destination: &str) -> DotfileMapping {
+        DotfileMapping {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            root: None,
+            from: None,
+            hardlink: false,
+            force_owned: false,
+            modes: Default::default(),
+        }
+    }
+
+    /// Sync `mappings` as `host`, returning the conflicts reported
+    fn apply_as(
+        home: &Path,
+        host: &str,
+        mappings: &[DotfileMapping],
+    ) -> Vec<(String, Vec<String>)> {
+        let mut reported = Vec::new();
+        crate::core::dotfiles::sync_dotfiles(
+            &roots(home, host),
+            mappings,
+            false,
+            2,
+            1,
+            None,
+            &mut |event| {
+                if let OwlEvent::SharedHomeConflict {
+                    host, destinations, ..
+                } = event
+                {
+                    reported.push((host, destinations));
+                }
+            },
+        )
+        .unwrap();
+        reported
+    }
+
+    #[test]
+    fn test_two_hosts_over_one_home_report_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path();
+        let sources = home.join(".owl/dotfiles");
+        fs::create_dir_all(sources.join("nvim-alpha")).unwrap();
+        fs::create_dir_all(sources.join("nvim-beta")).unwrap();
+        fs::write(sources.join("gitconfig-alpha"), "[user]\n  name = alpha\n").unwrap();
+        fs::write(sources.join("gitconfig-beta"), "[user]\n  name = beta\n").unwrap();
+        fs::write(sources.join("nvim-alpha/init.lua"), "-- same\n").unwrap();
+        fs::write(sources.join("nvim-beta/init.lua"), "-- same\n").unwrap();
+        let alpha = [
+            mapping("gitconfig-alpha", "~/.gitconfig"),
+            mapping("nvim-alpha", "~/.config/nvim"),
+        ];
+        let beta = [
+            mapping("gitconfig-beta", "~/.gitconfig"),
+            mapping("nvim-beta", "~/.config/nvim"),
+        ];
+        let gitconfig = home.join(".gitconfig").to_string_lossy().into_owned();
+
+        assert!(apply_as(home, "alpha", &alpha).is_empty());
+        // Identical nvim content is not a conflict, only the gitconfig variant is
+        assert_eq!(
+            apply_as(home, "beta", &beta),
+            vec![("alpha".to_string(), vec![gitconfig.clone()])]
+        );
+        assert_eq!(
+            apply_as(home, "alpha", &alpha),
+            vec![("beta".to_string(), vec![gitconfig.clone()])]
+        );
+        // Already holding alpha's content: nothing is overwritten
+        assert_eq!(
+            apply_as(home, "alpha", &alpha),
+            Vec::<(String, Vec<String>)>::new()
+        );
+
+        let records = HostRecord::load_all(&home.join(".owl/.state")).unwrap();
+        assert_eq!(records.keys().collect::<Vec<_>>(), vec!["alpha", "beta"]);
+        assert_eq!(records["alpha"].files.len(), 2);
+    }
+
+    #[test]
+    fn test_conflicts_skip_own_and_stale_records() {
+        let dest = "/home/me/.gitconfig".to_string();
+        let planned = BTreeMap::from([(dest.clone(), "xxh3:1".to_string())]);
+        let record = |applied_at, digest: &str| HostRecord {
+            applied_at,
+            files: BTreeMap::from([(dest.clone(), digest.to_string())]),
+        };
+        let now = 10 * RECENT_SECS;
+        let records = BTreeMap::from([
+            ("alpha".to_string(), record(now, "xxh3:2")),
+            ("beta".to_string(), record(now - 1, "xxh3:1")),
+            ("gamma".to_string(), record(now - RECENT_SECS - 1, "xxh3:3")),
+            ("delta".to_string(), record(now - 60, "xxh3:4")),
+        ]);
+
+        let found = conflicts("alpha", &planned, &records, now, |_, _| true);
+        assert_eq!(
+            found,
+            vec![HostConflict {
+                host: "delta".to_string(),
+                applied_at: now - 60,
+                destinations: vec![dest.clone()],
+            }]
+        );
+        assert!(conflicts("alpha", &planned, &records, now, |_, _| false).is_empty());
+    }
+
+    #[test]
+    fn test_hostname_must_name_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(HostRecord::default().save(dir.path(), "../x").is_err());
+        assert!(HostRecord::default().save(dir.path(), "").is_err());
+        HostRecord::default().save(dir.path(), "box").unwrap();
+        assert!(
+            HostRecord::load_all(dir.path())
+                .unwrap()
+                .contains_key("box")
+        );
+    }
+}
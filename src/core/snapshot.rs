@@ -0,0 +1,280 @@
+//! Filesystem snapshots around destructive apply runs
+//!
+//! With `@option snapshot_cmd=...` set, apply takes a snapshot before the first
+//! mutating phase when the plan removes packages or changes more than
+//! `snapshot_threshold` of them. `snapshot_post_cmd` takes a matching snapshot
+//! afterwards. Commands may use `{run_id}` and `{phase}` (`pre` or `post`);
+//! the last line they print is recorded as the snapshot id. `snapshot_cmd=auto`
+//! picks snapper or timeshift, whichever is installed.
+
+use crate::core::config::Config;
+use anyhow::{Result, anyhow};
+
+/// Package changes above which a snapshot is taken even without removals
+pub const DEFAULT_THRESHOLD: usize = 10;
+
+/// Built-in commands used by `snapshot_cmd=auto`, in order of preference
+const AUTO_COMMANDS: &[(&str, &str)] = &[
+    (
+        "snapper",
+        "snapper -c root create --print-number -d \"owl {phase}-apply {run_id}\"",
+    ),
+    (
+        "timeshift",
+        "timeshift --create --scripted --comments \"owl {phase}-apply {run_id}\"",
+    ),
+];
+
+/// What to do when the snapshot command fails (`@option snapshot_on_failure`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    #[default]
+    Warn,
+    Abort,
+}
+
+/// Snapshot settings resolved from config options
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SnapshotPolicy {
+    pub pre_cmd: Option<String>,
+    pub post_cmd: Option<String>,
+    pub threshold: usize,
+    pub on_failure: FailurePolicy,
+}
+
+/// Runs snapshot commands; mocked in tests
+pub trait CommandRunner {
+    /// Run `command` through the shell and return its stdout
+    fn run(&self, command: &str) -> Result<String>;
+    /// Whether `program` is on PATH
+    fn has_program(&self, program: &str) -> bool;
+}
+
+/// Runs commands with `sh -c`
+pub struct ShellRunner;
+
+impl CommandRunner for ShellRunner {
+    fn run(&self, command: &str) -> Result<String> {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .map_err(|e| anyhow!("Failed to run '{}': {}", command, e))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "'{}' failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn has_program(&self, program: &str) -> bool {
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("command -v {}", program))
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+}
+
+impl Config {
+    /// Snapshot settings from `snapshot_*` options
+    pub fn snapshot_policy(&self) -> Result<SnapshotPolicy> {
+        let value = |key: &str| self.option(key).map(|opt| opt.value.clone());
+        let threshold = match value("snapshot_threshold") {
+            Some(n) => n
+                .parse()
+                .map_err(|_| anyhow!("Invalid snapshot_threshold '{}', expected a number", n))?,
+            None => DEFAULT_THRESHOLD,
+        };
+        let on_failure = match value("snapshot_on_failure").as_deref() {
+            None | Some("warn") => FailurePolicy::Warn,
+            Some("abort") => FailurePolicy::Abort,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Invalid snapshot_on_failure '{}', expected warn or abort",
+                    other
+                ));
+            }
+        };
+        Ok(SnapshotPolicy {
+            pre_cmd: value("snapshot_cmd"),
+            post_cmd: value("snapshot_post_cmd"),
+            threshold,
+            on_failure,
+        })
+    }
+}
+
+impl SnapshotPolicy {
+    /// Whether a plan with these changes warrants a pre-apply snapshot
+    pub fn should_snapshot(&self, removals: usize, changes: usize) -> bool {
+        self.pre_cmd.is_some() && (removals > 0 || changes > self.threshold)
+    }
+}
+
+/// Fill in `{run_id}` and `{phase}`
+pub fn substitute(template: &str, run_id: &str, phase: &str) -> String {
+    template
+        .replace("{run_id}", run_id)
+        .replace("{phase}", phase)
+}
+
+/// The command to run for `template`, resolving `auto` to an installed tool
+pub fn resolve_command(template: &str, runner: &dyn CommandRunner) -> Result<String> {
+    if template != "auto" {
+        return Ok(template.to_string());
+    }
+    AUTO_COMMANDS
+        .iter()
+        .find(|(program, _)| runner.has_program(program))
+        .map(|(_, command)| command.to_string())
+        .ok_or_else(|| anyhow!("snapshot_cmd=auto found neither snapper nor timeshift"))
+}
+
+/// Result of one snapshot attempt under [`FailurePolicy::Warn`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotOutcome {
+    /// Taken; holds the id the command printed, if any
+    Taken(Option<String>),
+    /// Failed, and the run continues with this warning
+    Skipped(String),
+}
+
+/// Take a snapshot, applying the failure policy
+///
+/// Returns an error only when the snapshot failed and the policy is `Abort`.
+pub fn take(
+    template: &str,
+    run_id: &str,
+    phase: &str,
+    on_failure: FailurePolicy,
+    runner: &dyn CommandRunner,
+) -> Result<SnapshotOutcome> {
+    let attempt = resolve_command(template, runner).and_then(|command| {
+        runner
+            .run(&substitute(&command, run_id, phase))
+            .map(|stdout| {
+                stdout
+                    .lines()
+                    .map(str::trim)
+                    .rfind(|line| !line.is_empty())
+                    .map(str::to_string)
+            })
+    });
+    match (attempt, on_failure) {
+        (Ok(id), _) => Ok(SnapshotOutcome::Taken(id)),
+        (Err(e), FailurePolicy::Warn) => Ok(SnapshotOutcome::Skipped(format!(
+            "{}-apply snapshot failed, continuing: {}",
+            phase, e
+        ))),
+        (Err(e), FailurePolicy::Abort) => {
+            Err(anyhow!("{}-apply snapshot failed, aborting: {}", phase, e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Records commands and answers with canned output
+    struct MockRunner {
+        output: Result<String, String>,
+        programs: Vec<&'static str>,
+        ran: RefCell<Vec<String>>,
+    }
+
+    impl MockRunner {
+        fn new(output: Result<&str, &str>) -> Self {
+            Self {
+                output: output.map(str::to_string).map_err(str::to_string),
+                programs: Vec::new(),
+                ran: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl CommandRunner for MockRunner {
+        fn run(&self, command: &str) -> Result<String> {
+            self.ran.borrow_mut().push(command.to_string());
+            self.output.clone().map_err(|e| anyhow!(e))
+        }
+
+        fn has_program(&self, program: &str) -> bool {
+            self.programs.contains(&program)
+        }
+    }
+
+    fn policy(content: &str) -> SnapshotPolicy {
+        Config::parse(content).unwrap().snapshot_policy().unwrap()
+    }
+
+    #[test]
+    fn test_trigger_conditions() {
+        let off = policy("");
+        assert!(!off.should_snapshot(3, 50));
+
+        let on = policy("@option snapshot_cmd=snapper create\n@option snapshot_threshold=5\n");
+        assert!(on.should_snapshot(1, 1));
+        assert!(!on.should_snapshot(0, 5));
+        assert!(on.should_snapshot(0, 6));
+        assert_eq!(
+            policy("@option snapshot_cmd=x").threshold,
+            DEFAULT_THRESHOLD
+        );
+    }
+
+    #[test]
+    fn test_policy_options_validated() {
+        assert_eq!(
+            policy("@option snapshot_on_failure=abort").on_failure,
+            FailurePolicy::Abort
+        );
+        let config = Config::parse("@option snapshot_on_failure=retry").unwrap();
+        assert!(config.snapshot_policy().is_err());
+        let config = Config::parse("@option snapshot_threshold=many").unwrap();
+        assert!(config.snapshot_policy().is_err());
+    }
+
+    #[test]
+    fn test_placeholders_substituted_and_id_recorded() {
+        let runner = MockRunner::new(Ok("creating...\n42\n\n"));
+        let outcome = take(
+            "snap -d \"owl {phase} {run_id}\"",
+            "1700000000",
+            "pre",
+            FailurePolicy::Warn,
+            &runner,
+        )
+        .unwrap();
+        assert_eq!(outcome, SnapshotOutcome::Taken(Some("42".to_string())));
+        assert_eq!(
+            runner.ran.borrow().as_slice(),
+            ["snap -d \"owl pre 1700000000\"".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_failure_policy() {
+        let runner = MockRunner::new(Err("no space left"));
+        let warned = take("snap", "1", "pre", FailurePolicy::Warn, &runner).unwrap();
+        assert!(matches!(warned, SnapshotOutcome::Skipped(msg) if msg.contains("no space left")));
+        assert!(take("snap", "1", "pre", FailurePolicy::Abort, &runner).is_err());
+    }
+
+    #[test]
+    fn test_auto_detects_installed_tool() {
+        let mut runner = MockRunner::new(Ok("7\n"));
+        runner.programs = vec!["timeshift"];
+        take("auto", "9", "post", FailurePolicy::Abort, &runner).unwrap();
+        assert!(runner.ran.borrow()[0].starts_with("timeshift --create"));
+        assert!(runner.ran.borrow()[0].contains("owl post-apply 9"));
+
+        runner.programs.clear();
+        assert!(take("auto", "9", "pre", FailurePolicy::Abort, &runner).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+//! Sanity checks on dotfile sources before they are deployed
+//!
+//! Sources arrive through git, so a broken one reaches every machine: a
+//! directory with a dangling symlink, a config file committed empty, CRLF line
+//! endings a parser chokes on, or a file nobody can read. Each check looks at
+//! one path; [`check_source`] walks a mapping's source and runs all of them.
+
+use crate::core::dotfiles::{DotfileMapping, DotfileRoots};
+use crate::core::events::{EventSink, OwlEvent};
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// File names that are suspicious when empty, unless `@option suspect_empty` says otherwise
+pub const DEFAULT_SUSPECT_EMPTY: &[&str] = &[
+    "*.conf", "*.toml", "*.ini", "*.json", "*.yaml", "*.yml", "*.fish", "*.lua", "*.vim",
+];
+
+/// Bytes read to decide whether a file is text
+const TEXT_SNIFF_LEN: usize = 8192;
+
+/// What the checks look for, from the config and the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceChecks {
+    /// Glob patterns (`*` wildcards) of file names that should not be empty
+    pub suspect_empty: Vec<String>,
+    /// Report CRLF line endings in text files (`@option enforce_lf=true`)
+    pub enforce_lf: bool,
+    /// Findings are errors and stop the sync
+    pub strict: bool,
+}
+
+impl SourceChecks {
+    /// Checks configured by `config`; invalid options are reported to `sink`
+    pub fn from_config(
+        config: &crate::core::config::Config,
+        strict: bool,
+        sink: &mut dyn EventSink,
+    ) -> Self {
+        let enforce_lf = config.enforce_lf().unwrap_or_else(|e| {
+            sink.emit(OwlEvent::Warning(e.to_string()));
+            false
+        });
+        Self {
+            suspect_empty: config.suspect_empty(),
+            enforce_lf,
+            strict,
+        }
+    }
+}
+
+/// Something wrong with a file inside a source
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    DanglingSymlink { target: PathBuf },
+    EmptyFile,
+    CrlfLineEndings,
+    Unreadable { reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Finding {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub issue: Issue,
+}
+
+impl Finding {
+    pub fn describe(&self) -> String {
+        let path = self.path.display();
+        match &self.issue {
+            Issue::DanglingSymlink { target } => {
+                format!("{}: dangling symlink to {}", path, target.display())
+            }
+            Issue::EmptyFile => format!("{}: empty file", path),
+            Issue::CrlfLineEndings => format!("{}: CRLF line endings", path),
+            Issue::Unreadable { reason } => format!("{}: unreadable ({})", path, reason),
+        }
+    }
+}
+
+/// The findings for one mapping
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingFindings {
+    pub mapping: DotfileMapping,
+    pub findings: Vec<Finding>,
+}
+
+/// The target of `path` if it is a symlink that points nowhere
+pub fn dangling_symlink(path: &Path) -> Option<PathBuf> {
+    let meta = fs::symlink_metadata(path).ok()?;
+    if !meta.file_type().is_symlink() || fs::metadata(path).is_ok() {
+        return None;
+    }
+    Some(fs::read_link(path).unwrap_or_default())
+}
+
+/// Whether `path` is an empty regular file whose name matches one of `patterns`
+pub fn suspicious_empty(path: &Path, patterns: &[String]) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    fs::symlink_metadata(path).is_ok_and(|m| m.is_file() && m.len() == 0)
+        && patterns
+            .iter()
+            .any(|pattern| crate::internal::util::glob_match(pattern, name))
+}
+
+/// Whether `path` is a text file with a CRLF line ending
+///
+/// Files with a NUL byte near the start are treated as binary and skipped.
+pub fn has_crlf(path: &Path) -> Result<bool> {
+    let content =
+        fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let head = &content[..content.len().min(TEXT_SNIFF_LEN)];
+    if head.contains(&0) {
+        return Ok(false);
+    }
+    Ok(content.windows(2).any(|pair| pair == b"\r\n"))
+}
+
+/// Why `path` cannot be read, if it cannot
+pub fn unreadable(path: &Path) -> Option<String> {
+    let result = if fs::symlink_metadata(path).ok()?.is_dir() {
+        fs::read_dir(path).map(|_| ())
+    } else if fs::symlink_metadata(path).ok()?.is_file() {
+        fs::File::open(path).and_then(|mut f| f.read(&mut [0u8; 1]).map(|_| ()))
+    } else {
+        return None;
+    };
+    result.err().map(|e| e.to_string())
+}
+
+/// Run every check on `source` and, for a directory, everything below it
+///
+/// Symlinks are checked but not followed.
+pub fn check_source(source: &Path, checks: &SourceChecks) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut stack = vec![source.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        let finding = |issue| Finding {
+            path: path.clone(),
+            issue,
+        };
+        if let Some(target) = dangling_symlink(&path) {
+            findings.push(finding(Issue::DanglingSymlink { target }));
+            continue;
+        }
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if let Some(reason) = unreadable(&path) {
+            findings.push(finding(Issue::Unreadable { reason }));
+            continue;
+        }
+        if meta.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                let mut children: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+                // Popped from the end, so this reports in name order
+                children.sort_by(|a, b| b.cmp(a));
+                stack.extend(children);
+            }
+            continue;
+        }
+        if !meta.is_file() {
+            continue;
+        }
+        if suspicious_empty(&path, &checks.suspect_empty) {
+            findings.push(finding(Issue::EmptyFile));
+        } else if checks.enforce_lf && has_crlf(&path).unwrap_or(false) {
+            findings.push(finding(Issue::CrlfLineEndings));
+        }
+    }
+    findings
+}
+
+/// Check the resolved source of every mapping; mappings without findings are left out
+///
+/// Missing sources are not reported here; the sync itself reports them.
+pub fn check_mappings(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    checks: &SourceChecks,
+) -> Vec<MappingFindings> {
+    mappings
+        .iter()
+        .filter_map(|mapping| {
+            let findings = check_source(&roots.source(mapping), checks);
+            (!findings.is_empty()).then(|| MappingFindings {
+                mapping: mapping.clone(),
+                findings,
+            })
+        })
+        .collect()
+}
+
+/// Report findings for `mappings` to `sink` and return how many there were;
+/// with `strict`, any finding is an error
+pub fn report(
+    roots: &DotfileRoots,
+    mappings: &[DotfileMapping],
+    checks: &SourceChecks,
+    sink: &mut dyn EventSink,
+) -> Result<usize> {
+    let flagged = check_mappings(roots, mappings, checks);
+    let count: usize = flagged.iter().map(|f| f.findings.len()).sum();
+    for entry in flagged {
+        sink.emit(OwlEvent::DotfileSourceIssues {
+            mapping: entry.mapping,
+            issues: entry.findings.iter().map(Finding::describe).collect(),
+            strict: checks.strict,
+        });
+    }
+    if checks.strict && count > 0 {
+        return Err(anyhow!("{} problem(s) in dotfile sources", count));
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{PermissionsExt, symlink};
+
+    fn checks(enforce_lf: bool) -> SourceChecks {
+        SourceChecks {
+            suspect_empty: DEFAULT_SUSPECT_EMPTY
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            enforce_lf,
+            strict: false,
+        }
+    }
+
+    /// A fish config directory with one of each problem
+    fn fish_tree(dir: &Path) -> PathBuf {
+        let fish = dir.join("fish");
+        fs::create_dir_all(fish.join("functions")).unwrap();
+        fs::write(fish.join("config.fish"), "set -x EDITOR nvim\r\n").unwrap();
+        fs::write(fish.join("functions/empty.fish"), "").unwrap();
+        fs::write(fish.join("fish_variables"), "").unwrap();
+        symlink(dir.join("nowhere"), fish.join("functions/gone.fish")).unwrap();
+        symlink(fish.join("config.fish"), fish.join("linked.fish")).unwrap();
+        fish
+    }
+
+    #[test]
+    fn test_dangling_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let fish = fish_tree(dir.path());
+        assert_eq!(
+            dangling_symlink(&fish.join("functions/gone.fish")),
+            Some(dir.path().join("nowhere"))
+        );
+        assert_eq!(dangling_symlink(&fish.join("linked.fish")), None);
+        assert_eq!(dangling_symlink(&fish.join("config.fish")), None);
+    }
+
+    #[test]
+    fn test_suspicious_empty_uses_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let fish = fish_tree(dir.path());
+        let patterns = checks(false).suspect_empty;
+        assert!(suspicious_empty(
+            &fish.join("functions/empty.fish"),
+            &patterns
+        ));
+        // Empty, but not a name on the list
+        assert!(!suspicious_empty(&fish.join("fish_variables"), &patterns));
+        assert!(suspicious_empty(
+            &fish.join("fish_variables"),
+            &["fish_*".to_string()]
+        ));
+        assert!(!suspicious_empty(&fish.join("config.fish"), &patterns));
+    }
+
+    #[test]
+    fn test_has_crlf_skips_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let fish = fish_tree(dir.path());
+        assert!(has_crlf(&fish.join("config.fish")).unwrap());
+        let binary = dir.path().join("font.otf");
+        fs::write(&binary, b"\0\x01\r\n").unwrap();
+        assert!(!has_crlf(&binary).unwrap());
+        let unix = dir.path().join("unix.conf");
+        fs::write(&unix, "a = 1\nb = 2\n").unwrap();
+        assert!(!has_crlf(&unix).unwrap());
+    }
+
+    #[test]
+    fn test_unreadable() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("secret.conf");
+        fs::write(&secret, "token").unwrap();
+        assert_eq!(unreadable(&secret), None);
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads the file regardless of its mode
+        if fs::File::open(&secret).is_ok() {
+            return;
+        }
+        assert!(unreadable(&secret).is_some());
+    }
+
+    #[test]
+    fn test_check_source_walks_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let fish = fish_tree(dir.path());
+        let lenient: Vec<(PathBuf, Issue)> = check_source(&fish, &checks(false))
+            .into_iter()
+            .map(|f| (f.path, f.issue))
+            .collect();
+        assert_eq!(
+            lenient,
+            vec![
+                (fish.join("functions/empty.fish"), Issue::EmptyFile),
+                (
+                    fish.join("functions/gone.fish"),
+                    Issue::DanglingSymlink {
+                        target: dir.path().join("nowhere")
+                    }
+                ),
+            ]
+        );
+        let strict_lf = check_source(&fish, &checks(true));
+        assert_eq!(strict_lf[0].path, fish.join("config.fish"));
+        assert_eq!(strict_lf[0].issue, Issue::CrlfLineEndings);
+        assert_eq!(strict_lf.len(), 3);
+    }
+
+    #[test]
+    fn test_report_is_an_error_when_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        fish_tree(&dir.path().join("dotfiles"));
+        fs::write(dir.path().join("dotfiles/gitconfig"), "[user]\n").unwrap();
+        let roots = DotfileRoots {
+            owl_dir: dir.path().to_path_buf(),
+            source_dir: dir.path().join("dotfiles"),
+            home: dir.path().join("home").to_string_lossy().into_owned(),
+            backup_dir: dir.path().join("backups"),
+            dest_prefix: None,
+            hash_algo: crate::core::dotfiles::HashAlgo::default(),
+            no_delete: false,
+            allow_outside_home: false,
+            shared_host: None,
+            declined: Default::default(),
+            mode_policy: Default::default(),
+            divergence: Default::default(),
+            absent: Vec::new(),
+        };
+        let mapping = |source: &str| DotfileMapping {
+            source: source.to_string(),
+            destination: format!("~/.{}", source),
+            root: None,
+            from: None,
+            hardlink: false,
+            force_owned: false,
+            modes: Default::default(),
+        };
+        let mappings = vec![mapping("fish"), mapping("gitconfig"), mapping("missing")];
+
+        let mut events = Vec::new();
+        let count = report(&roots, &mappings, &checks(false), &mut |e| events.push(e)).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(events.len(), 1);
+        let OwlEvent::DotfileSourceIssues {
+            mapping, issues, ..
+        } = &events[0]
+        else {
+            panic!("unexpected event {:?}", events[0]);
+        };
+        assert_eq!(mapping.source, "fish");
+        assert_eq!(issues.len(), 2);
+
+        let strict = SourceChecks {
+            strict: true,
+            ..checks(false)
+        };
+        let err = report(&roots, &mappings, &strict, &mut |_| {}).unwrap_err();
+        assert!(err.to_string().starts_with("2 problem(s)"), "{}", err);
+    }
+}
@@ -4,6 +4,7 @@ use crate::internal::constants;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
@@ -23,7 +24,15 @@ trait StatePersistence<T> {
             Self::save(state_dir, &default)?;
             return Ok(default);
         }
+        Self::read(state_dir)
+    }
 
+    /// Like `load`, but a missing file is not written
+    fn read(state_dir: &Path) -> Result<T> {
+        let file_path = state_dir.join(Self::FILE_NAME);
+        if !file_path.exists() {
+            return Ok(Self::DEFAULT_VALUE());
+        }
         let content = fs::read_to_string(&file_path)
             .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", Self::FILE_NAME, e))?;
         Self::deserialize(&content)
@@ -40,7 +49,7 @@ trait StatePersistence<T> {
 }
 
 /// Default system packages that should not be tracked
-fn default_untracked_packages() -> Vec<String> {
+pub fn default_untracked_packages() -> Vec<String> {
     vec![
         "linux".to_string(),
         "linux-firmware".to_string(),
@@ -74,11 +83,21 @@ fn default_untracked_packages() -> Vec<String> {
 }
 
 /// Package state information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PackageState {
     pub untracked: Vec<String>,
     pub hidden: Vec<String>,
     pub managed: Vec<String>,
+    /// When owl installed each package (seconds since the Unix epoch)
+    pub installed_at: HashMap<String, u64>,
+    /// Declared packages that were already installed and the user chose not to manage
+    pub declined: Vec<String>,
+    /// Untracked packages declared in config; merged on load, never persisted
+    #[serde(skip)]
+    pub config_untracked: Vec<String>,
+    /// Ignore the built-in untracked defaults (`@untracked-reset`)
+    #[serde(skip)]
+    pub untracked_reset: bool,
 }
 
 /// Specific implementation for untracked packages (JSON format)
@@ -137,48 +156,129 @@ impl StatePersistence<Vec<String>> for ManagedPackages {
     }
 }
 
+/// Specific implementation for declined adoptions (JSON format)
+struct DeclinedPackages;
+
+impl StatePersistence<Vec<String>> for DeclinedPackages {
+    const FILE_NAME: &'static str = "declined.json";
+    const DEFAULT_VALUE: fn() -> Vec<String> = Vec::new;
+
+    fn serialize(data: &Vec<String>) -> Result<String> {
+        serde_json::to_string_pretty(data)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize declined packages: {}", e))
+    }
+
+    fn deserialize(content: &str) -> Result<Vec<String>> {
+        serde_json::from_str(content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse declined packages JSON: {}", e))
+    }
+}
+
+/// Specific implementation for install timestamps (JSON format)
+struct InstalledTimestamps;
+
+impl StatePersistence<HashMap<String, u64>> for InstalledTimestamps {
+    const FILE_NAME: &'static str = "installed_at.json";
+    const DEFAULT_VALUE: fn() -> HashMap<String, u64> = HashMap::new;
+
+    fn serialize(data: &HashMap<String, u64>) -> Result<String> {
+        let sorted: std::collections::BTreeMap<_, _> = data.iter().collect();
+        serde_json::to_string_pretty(&sorted)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize install timestamps: {}", e))
+    }
+
+    fn deserialize(content: &str) -> Result<HashMap<String, u64>> {
+        serde_json::from_str(content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse install timestamps JSON: {}", e))
+    }
+}
+
 // Some methods are part of the public API for future use (e.g., CLI commands for managing
 // hidden/untracked packages). They are tested but not yet used in the main application.
 #[allow(dead_code)]
 impl PackageState {
     /// Load package state from ~/.owl/.state directory
     pub fn load() -> Result<Self> {
-        let state_dir = Self::get_state_dir()?;
+        Self::load_from(&Self::get_state_dir()?)
+    }
+
+    /// Load package state from `state_dir`, creating it if missing
+    pub fn load_from(state_dir: &Path) -> Result<Self> {
         if !state_dir.exists() {
-            fs::create_dir_all(&state_dir)
+            fs::create_dir_all(state_dir)
                 .map_err(|e| anyhow::anyhow!("Failed to create state directory: {}", e))?;
         }
 
         // Use trait-based loading for each state type
-        let untracked = UntrackedPackages::load(&state_dir)?;
-        let hidden = HiddenPackages::load(&state_dir)?;
-        let managed = ManagedPackages::load(&state_dir)?;
+        let untracked = UntrackedPackages::load(state_dir)?;
+        let hidden = HiddenPackages::load(state_dir)?;
+        let managed = ManagedPackages::load(state_dir)?;
+        let installed_at = InstalledTimestamps::load(state_dir)?;
+        let declined = DeclinedPackages::load(state_dir)?;
 
         Ok(PackageState {
             untracked,
             hidden,
             managed,
+            installed_at,
+            declined,
+            ..Default::default()
+        })
+    }
+
+    /// Load package state without creating the directory or missing files (dry runs)
+    pub fn read() -> Result<Self> {
+        Self::read_from(&Self::get_state_dir()?)
+    }
+
+    /// Load package state from `state_dir`, leaving the disk untouched
+    pub fn read_from(state_dir: &Path) -> Result<Self> {
+        Ok(PackageState {
+            untracked: UntrackedPackages::read(state_dir)?,
+            hidden: HiddenPackages::read(state_dir)?,
+            managed: ManagedPackages::read(state_dir)?,
+            installed_at: InstalledTimestamps::read(state_dir)?,
+            declined: DeclinedPackages::read(state_dir)?,
+            ..Default::default()
         })
     }
 
     /// Save package state to disk
     pub fn save(&self) -> Result<()> {
-        let state_dir = Self::get_state_dir()?;
+        self.save_to(&Self::get_state_dir()?)
+    }
+
+    /// Save package state into `state_dir`
+    pub fn save_to(&self, state_dir: &Path) -> Result<()> {
         if !state_dir.exists() {
-            fs::create_dir_all(&state_dir)
+            fs::create_dir_all(state_dir)
                 .map_err(|e| anyhow::anyhow!("Failed to create state directory: {}", e))?;
         }
 
         // Use trait-based saving for each state type
-        UntrackedPackages::save(&state_dir, &self.untracked)?;
-        HiddenPackages::save(&state_dir, &self.hidden)?;
-        ManagedPackages::save(&state_dir, &self.managed)?;
+        UntrackedPackages::save(state_dir, &self.untracked)?;
+        HiddenPackages::save(state_dir, &self.hidden)?;
+        ManagedPackages::save(state_dir, &self.managed)?;
+        InstalledTimestamps::save(state_dir, &self.installed_at)?;
+        DeclinedPackages::save(state_dir, &self.declined)?;
         Ok(())
     }
 
-    /// Check if a package is in the untracked list
+    /// Check if a package is in the untracked list (state or config)
     pub fn is_untracked(&self, package: &str) -> bool {
-        self.untracked.contains(&package.to_string())
+        if self.config_untracked.iter().any(|p| p == package) {
+            return true;
+        }
+        if self.untracked_reset && default_untracked_packages().iter().any(|p| p == package) {
+            return false;
+        }
+        self.untracked.iter().any(|p| p == package)
+    }
+
+    /// Merge the `@untracked` declarations from config into this state
+    pub fn merge_config_untracked(&mut self, config: &crate::core::config::Config) {
+        self.config_untracked = config.untracked.clone();
+        self.untracked_reset = config.untracked_reset;
     }
 
     /// Check if a package is in the hidden list
@@ -217,8 +317,9 @@ impl PackageState {
         self.hidden.retain(|p| p != package);
     }
 
-    /// Add a package to the managed list
+    /// Add a package to the managed list, overriding an earlier decline
     pub fn add_managed(&mut self, package: String) {
+        self.declined.retain(|p| p != &package);
         if !self.managed.contains(&package) {
             self.managed.push(package);
             self.managed.sort();
@@ -228,52 +329,253 @@ impl PackageState {
     /// Remove a package from the managed list
     pub fn remove_managed(&mut self, package: &str) {
         self.managed.retain(|p| p != package);
+        self.installed_at.remove(package);
+    }
+
+    /// Check if the user chose not to have owl manage a package
+    pub fn is_declined(&self, package: &str) -> bool {
+        self.declined.iter().any(|p| p == package)
+    }
+
+    /// Remember that a pre-existing package stays unmanaged
+    pub fn add_declined(&mut self, package: String) {
+        if !self.declined.contains(&package) {
+            self.declined.push(package);
+            self.declined.sort();
+        }
+    }
+
+    /// Record that owl installed a package at the given time
+    pub fn record_installed(&mut self, package: &str, when: u64) {
+        self.installed_at.insert(package.to_string(), when);
+    }
+
+    /// Managed packages owl installed at or after `since`, oldest first
+    pub fn installed_since(&self, since: u64) -> Vec<(String, u64)> {
+        let mut packages: Vec<(String, u64)> = self
+            .installed_at
+            .iter()
+            .filter(|(name, when)| **when >= since && self.is_managed(name))
+            .map(|(name, when)| (name.clone(), *when))
+            .collect();
+        packages.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        packages
+    }
+
+    /// Drop managed entries that are absent from the installed set
+    ///
+    /// Callers are expected to include fully installed package groups in
+    /// `installed`, since group names never appear in the package list.
+    /// Returns the pruned package names.
+    pub fn prune_uninstalled(&mut self, installed: &HashSet<String>) -> Vec<String> {
+        let (kept, pruned): (Vec<String>, Vec<String>) =
+            self.managed.drain(..).partition(|p| installed.contains(p));
+        self.managed = kept;
+        pruned
     }
 
     fn get_state_dir() -> Result<PathBuf> {
-        let home = std::env::var("HOME")
-            .map_err(|_| anyhow::anyhow!("HOME environment variable not set"))?;
-        Ok(PathBuf::from(home)
-            .join(constants::OWL_DIR)
+        Ok(crate::internal::environment::get()
+            .owl_dir()?
             .join(constants::STATE_DIR))
     }
 }
 
+/// File in the state directory listing deployed dotfile destinations
+const DEPLOYED_FILE: &str = "deployed.json";
+
+/// Dotfile destinations written by earlier runs
+///
+/// Kept apart from `PackageState` because the dotfiles phase saves it on its
+/// own, while the package phases hold their own copy of the package state.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeployedDotfiles {
+    pub destinations: BTreeSet<String>,
+    /// Paths removed to keep them absent (`:absent`), with the backup set
+    /// holding what was removed
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub absent: BTreeMap<String, String>,
+}
+
+impl DeployedDotfiles {
+    /// Load the deployed set from `state_dir` (empty if none was recorded yet)
+    pub fn load_from(state_dir: &Path) -> Result<Self> {
+        let path = state_dir.join(DEPLOYED_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", DEPLOYED_FILE, e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", DEPLOYED_FILE, e))
+    }
+
+    pub fn save_to(&self, state_dir: &Path) -> Result<()> {
+        fs::create_dir_all(state_dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create state directory: {}", e))?;
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize {}: {}", DEPLOYED_FILE, e))?;
+        fs::write(state_dir.join(DEPLOYED_FILE), content)
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", DEPLOYED_FILE, e))
+    }
+
+    /// Destinations deployed before that `current` no longer maps and that
+    /// still exist
+    ///
+    /// Paths are compared by `key`, so a destination spelled with other case
+    /// on a case-insensitive filesystem is still the one mapped.
+    pub fn orphans(
+        &self,
+        current: &BTreeSet<String>,
+        exists: impl Fn(&str) -> bool,
+        key: impl Fn(&str) -> String,
+    ) -> Vec<String> {
+        let current: BTreeSet<String> = current.iter().map(|dest| key(dest)).collect();
+        self.destinations
+            .iter()
+            .filter(|dest| !current.contains(&key(dest)) && exists(dest))
+            .cloned()
+            .collect()
+    }
+
+    /// Replace the set with this run's destinations, keeping `orphans` so they
+    /// are reported again until they are removed or mapped again
+    pub fn record(&mut self, current: BTreeSet<String>, orphans: &[String]) {
+        self.destinations = current;
+        self.destinations.extend(orphans.iter().cloned());
+    }
+
+    /// Note `paths` as removed into backup set `backup`; they are no longer deployed
+    pub fn note_absent(&mut self, paths: &[String], backup: &str) {
+        for path in paths {
+            self.destinations.remove(path);
+            self.absent.insert(path.clone(), backup.to_string());
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Mutex;
     use tempfile::tempdir;
 
-    // Use a mutex to ensure tests don't interfere with each other
-    static TEST_MUTEX: Mutex<()> = Mutex::new(());
-
-    fn setup_test_home() -> tempfile::TempDir {
-        let temp_dir = tempdir().expect("Failed to create temp directory");
-        unsafe { std::env::set_var("HOME", temp_dir.path()) };
-        temp_dir
-    }
-
     #[test]
     fn test_load_initial_state() {
-        let _guard = TEST_MUTEX.lock().unwrap();
-        let _temp_dir = setup_test_home();
+        let temp_dir = tempdir().expect("Failed to create temp directory");
 
-        let state = PackageState::load().expect("Failed to load package state");
+        let state = PackageState::load_from(temp_dir.path()).expect("Failed to load package state");
         assert!(!state.untracked.is_empty());
         assert!(state.is_untracked("linux"));
         assert!(state.is_untracked("base"));
     }
 
+    #[test]
+    fn test_read_does_not_seed_state_files() {
+        let temp_dir = tempdir().unwrap();
+        let state_dir = temp_dir.path().join(".state");
+        let state = PackageState::read_from(&state_dir).unwrap();
+        assert!(state.is_untracked("linux"));
+        assert!(!state_dir.exists());
+
+        let mut state = PackageState::load_from(&state_dir).unwrap();
+        state.add_managed("bat".to_string());
+        state.save_to(&state_dir).unwrap();
+        assert_eq!(
+            PackageState::read_from(&state_dir).unwrap().managed,
+            vec!["bat"]
+        );
+    }
+
     #[test]
     fn test_add_remove_untracked() {
-        let _guard = TEST_MUTEX.lock().unwrap();
-        let _temp_dir = setup_test_home();
+        let temp_dir = tempdir().expect("Failed to create temp directory");
 
-        let mut state = PackageState::load().expect("Failed to load package state");
+        let mut state =
+            PackageState::load_from(temp_dir.path()).expect("Failed to load package state");
         state.add_untracked("test-package".to_string());
-        assert!(state.is_untracked("test-package"));
+        state.save_to(temp_dir.path()).unwrap();
+        let reloaded = PackageState::load_from(temp_dir.path()).unwrap();
+        assert!(reloaded.is_untracked("test-package"));
+
         state.remove_untracked("test-package");
-        assert!(!state.is_untracked("test-package"));
+        state.save_to(temp_dir.path()).unwrap();
+        let reloaded = PackageState::load_from(temp_dir.path()).unwrap();
+        assert!(!reloaded.is_untracked("test-package"));
+    }
+
+    #[test]
+    fn test_config_untracked_merge() {
+        let mut config = crate::core::config::Config::new();
+        config.untracked.push("nvidia-dkms".to_string());
+        let mut state = PackageState {
+            untracked: default_untracked_packages(),
+            ..Default::default()
+        };
+
+        state.merge_config_untracked(&config);
+        assert!(state.is_untracked("nvidia-dkms"));
+        assert!(state.is_untracked("linux"));
+
+        config.untracked_reset = true;
+        state.merge_config_untracked(&config);
+        assert!(state.is_untracked("nvidia-dkms"));
+        assert!(!state.is_untracked("linux"));
+    }
+
+    #[test]
+    fn test_installed_since() {
+        let mut state = PackageState {
+            managed: vec!["new".to_string(), "old".to_string(), "newer".to_string()],
+            ..Default::default()
+        };
+        state.record_installed("old", 100);
+        state.record_installed("new", 500);
+        state.record_installed("newer", 900);
+        state.record_installed("unmanaged", 900);
+
+        let recent = state.installed_since(500);
+        assert_eq!(
+            recent,
+            vec![("new".to_string(), 500), ("newer".to_string(), 900)]
+        );
+
+        state.remove_managed("newer");
+        assert_eq!(state.installed_since(500).len(), 1);
+    }
+
+    #[test]
+    fn test_prune_uninstalled() {
+        let mut state = PackageState {
+            managed: vec!["fish".to_string(), "gone".to_string(), "htop".to_string()],
+            ..Default::default()
+        };
+        let installed: HashSet<String> = ["fish", "htop", "vim"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let pruned = state.prune_uninstalled(&installed);
+        assert_eq!(pruned, vec!["gone".to_string()]);
+        assert_eq!(state.managed, vec!["fish".to_string(), "htop".to_string()]);
+    }
+
+    #[test]
+    fn test_orphans_compare_by_key() {
+        let deployed = DeployedDotfiles {
+            destinations: ["/s/Library", "/s/old", "/h/.vimrc"]
+                .iter()
+                .map(|d| d.to_string())
+                .collect(),
+            absent: BTreeMap::new(),
+        };
+        let current: BTreeSet<String> = ["/s/library".to_string(), "/h/.vimrc".to_string()].into();
+        let exact = deployed.orphans(&current, |_| true, str::to_string);
+        assert_eq!(exact, vec!["/s/Library", "/s/old"]);
+        // `/s` folds case: Library is the mapped library, not an orphan
+        let folded = |d: &str| match d.strip_prefix("/s/") {
+            Some(rest) => format!("/s/{}", rest.to_lowercase()),
+            None => d.to_string(),
+        };
+        assert_eq!(deployed.orphans(&current, |_| true, folded), vec!["/s/old"]);
     }
 }
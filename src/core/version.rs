@@ -0,0 +1,343 @@
+//! Package version comparison and `:min-version` constraints
+//!
+//! [`vercmp`] follows pacman's `vercmp` (alpm's `rpmvercmp` plus epoch and
+//! pkgrel handling) so results match what pacman itself reports.
+
+use crate::core::config::Config;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Minimum installed version a package's dotfiles and services rely on
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MinVersion {
+    pub version: String,
+    /// Block the package's dotfile and service actions instead of only warning
+    pub strict: bool,
+}
+
+impl MinVersion {
+    /// Directive form, e.g. `:min-version 3.7 [strict]`
+    pub fn directive(&self) -> String {
+        if self.strict {
+            format!(":min-version {} [strict]", self.version)
+        } else {
+            format!(":min-version {}", self.version)
+        }
+    }
+}
+
+/// An installed package older than its `:min-version`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionViolation {
+    pub package: String,
+    pub installed: String,
+    pub required: MinVersion,
+}
+
+impl VersionViolation {
+    pub fn message(&self) -> String {
+        let consequence = if self.required.strict {
+            format!(
+                "dotfiles and services for {} are skipped ([strict])",
+                self.package
+            )
+        } else {
+            format!(
+                "dotfiles for {} will still sync, use [strict] to block",
+                self.package
+            )
+        };
+        format!(
+            "{} {} installed, config requires >= {} — {}",
+            self.package, self.installed, self.required.version, consequence
+        )
+    }
+}
+
+/// Compare two full package versions (`[epoch:]version[-pkgrel]`) like `vercmp`
+///
+/// The pkgrel only takes part when both sides have one.
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (epoch_a, ver_a, rel_a) = parse_evr(a);
+    let (epoch_b, ver_b, rel_b) = parse_evr(b);
+    rpmvercmp(epoch_a, epoch_b)
+        .then_with(|| rpmvercmp(ver_a, ver_b))
+        .then_with(|| match (rel_a, rel_b) {
+            (Some(rel_a), Some(rel_b)) => rpmvercmp(rel_a, rel_b),
+            _ => Ordering::Equal,
+        })
+}
+
+/// Split `[epoch:]version[-pkgrel]`; a missing epoch is `0`
+fn parse_evr(evr: &str) -> (&str, &str, Option<&str>) {
+    let digits = evr.bytes().take_while(u8::is_ascii_digit).count();
+    let (epoch, rest) = match evr[digits..].strip_prefix(':') {
+        Some(rest) if digits == 0 => ("0", rest),
+        Some(rest) => (&evr[..digits], rest),
+        None => ("0", evr),
+    };
+    match rest.rfind('-') {
+        Some(dash) => (epoch, &rest[..dash], Some(&rest[dash + 1..])),
+        None => (epoch, rest, None),
+    }
+}
+
+/// alpm's segment-wise comparison of a single version component
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let one = a.as_bytes();
+    let two = b.as_bytes();
+    // `i`/`j` walk the strings; `seg_i`/`seg_j` mark where the last segment ended
+    let (mut i, mut j) = (0, 0);
+    let (mut seg_i, mut seg_j) = (0, 0);
+
+    while i < one.len() && j < two.len() {
+        while i < one.len() && !one[i].is_ascii_alphanumeric() {
+            i += 1;
+        }
+        while j < two.len() && !two[j].is_ascii_alphanumeric() {
+            j += 1;
+        }
+        if i >= one.len() || j >= two.len() {
+            break;
+        }
+        // Differently sized separators decide the comparison on their own
+        if i - seg_i != j - seg_j {
+            return (i - seg_i).cmp(&(j - seg_j));
+        }
+
+        let is_num = one[i].is_ascii_digit();
+        let class = |c: &u8| {
+            if is_num {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+        let end_i = i + one[i..].iter().take_while(|c| class(c)).count();
+        let end_j = j + two[j..].iter().take_while(|c| class(c)).count();
+
+        // Segments of different types: numbers are newer than letters
+        if end_j == j {
+            return if is_num {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let mut seg_a = &one[i..end_i];
+        let mut seg_b = &two[j..end_j];
+        if is_num {
+            while seg_a.first() == Some(&b'0') {
+                seg_a = &seg_a[1..];
+            }
+            while seg_b.first() == Some(&b'0') {
+                seg_b = &seg_b[1..];
+            }
+            if seg_a.len() != seg_b.len() {
+                return seg_a.len().cmp(&seg_b.len());
+            }
+        }
+        match seg_a.cmp(seg_b) {
+            Ordering::Equal => {}
+            other => return other,
+        }
+
+        i = end_i;
+        j = end_j;
+        seg_i = i;
+        seg_j = j;
+    }
+
+    let rest_a = &one[i..];
+    let rest_b = &two[j..];
+    if rest_a.is_empty() && rest_b.is_empty() {
+        return Ordering::Equal;
+    }
+    // A remaining alpha segment never beats an empty string
+    let b_alpha = rest_b.first().is_some_and(u8::is_ascii_alphabetic);
+    let a_alpha = rest_a.first().is_some_and(u8::is_ascii_alphabetic);
+    if (rest_a.is_empty() && !b_alpha) || a_alpha {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+/// Declared packages whose installed version is below their `:min-version`
+///
+/// Packages that are not installed are skipped; installing them is a separate action.
+pub fn check_min_versions(
+    config: &Config,
+    installed: &HashMap<String, String>,
+) -> Vec<VersionViolation> {
+    let mut violations: Vec<VersionViolation> = config
+        .packages
+        .iter()
+        .filter_map(|(name, pkg)| {
+            let required = pkg.min_version.as_ref()?;
+            let version = installed.get(name)?;
+            (vercmp(version, &required.version) == Ordering::Less).then(|| VersionViolation {
+                package: name.clone(),
+                installed: version.clone(),
+                required: required.clone(),
+            })
+        })
+        .collect();
+    violations.sort_by(|a, b| a.package.cmp(&b.package));
+    violations
+}
+
+/// Drop dotfile and service actions of packages with a strict violation
+///
+/// Returns one warning per violation, in package order.
+pub fn enforce_min_versions(config: &mut Config, violations: &[VersionViolation]) -> Vec<String> {
+    for violation in violations.iter().filter(|v| v.required.strict) {
+        if let Some(pkg) = config.packages.get_mut(&violation.package) {
+            pkg.config.clear();
+            pkg.service = None;
+        }
+    }
+    violations.iter().map(VersionViolation::message).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mirrors pacman's vercmp test suite
+    #[test]
+    fn test_vercmp_matrix() {
+        let cases = [
+            // all similar length, no pkgrel
+            ("1.5.0", "1.5.0", 0),
+            ("1.5.1", "1.5.0", 1),
+            // mixed length
+            ("1.5.1", "1.5", 1),
+            // with pkgrel, simple
+            ("1.5.0-1", "1.5.0-1", 0),
+            ("1.5.0-1", "1.5.0-2", -1),
+            ("1.5.0-1", "1.5.1-1", -1),
+            ("1.5.0-2", "1.5.1-1", -1),
+            // with pkgrel, mixed lengths
+            ("1.5-1", "1.5.1-1", -1),
+            ("1.5-2", "1.5.1-1", -1),
+            ("1.5-2", "1.5.1-2", -1),
+            // mixed pkgrel inclusion
+            ("1.5", "1.5-1", 0),
+            ("1.5-1", "1.5", 0),
+            ("1.1-1", "1.1", 0),
+            ("1.0-1", "1.1", -1),
+            ("1.1-1", "1.0", 1),
+            // alphanumerics
+            ("1.5b-1", "1.5-1", -1),
+            ("1.5b", "1.5", -1),
+            ("1.5b-1", "1.5", -1),
+            ("1.5b", "1.5.1", -1),
+            // from the manpage
+            ("1.0a", "1.0alpha", -1),
+            ("1.0alpha", "1.0b", -1),
+            ("1.0b", "1.0beta", -1),
+            ("1.0beta", "1.0rc", -1),
+            ("1.0rc", "1.0", -1),
+            // alpha-dotted versions
+            ("1.5.a", "1.5", 1),
+            ("1.5.b", "1.5.a", 1),
+            ("1.5.1", "1.5.b", 1),
+            // alpha dots and dashes
+            ("1.5.b-1", "1.5.b", 0),
+            ("1.5-1", "1.5.b", -1),
+            // same/similar content, differing separators
+            ("2.0", "2_0", 0),
+            ("2.0_a", "2_0.a", 0),
+            ("2.0a", "2.0.a", -1),
+            ("2___a", "2_a", 1),
+            // epoch included version comparisons
+            ("0:1.0", "0:1.0", 0),
+            ("0:1.0", "0:1.1", -1),
+            ("1:1.0", "0:1.0", 1),
+            ("1:1.0", "0:1.1", 1),
+            ("1:1.0", "2:1.1", -1),
+            // epoch + sometimes present pkgrel
+            ("1:1.0", "0:1.0-1", 1),
+            ("1:1.0-1", "0:1.1-1", 1),
+            // epoch included on one version
+            ("0:1.0", "1.0", 0),
+            ("0:1.0", "1.1", -1),
+            ("0:1.1", "1.0", 1),
+            ("1:1.0", "1.0", 1),
+            ("1:1.0", "1.1", 1),
+            ("1:1.1", "1.1", 1),
+            // leading zeros and long numbers
+            ("1.010", "1.10", 0),
+            ("1.0.20240101", "1.0.9", 1),
+        ];
+        for (a, b, expected) in cases {
+            let expected = expected.cmp(&0);
+            assert_eq!(vercmp(a, b), expected, "vercmp {} {}", a, b);
+            assert_eq!(vercmp(b, a), expected.reverse(), "vercmp {} {}", b, a);
+        }
+    }
+
+    fn config_with(min_versions: &[(&str, &str, bool)]) -> Config {
+        let mut config = Config::new();
+        for (name, version, strict) in min_versions {
+            let mut pkg = config.defaults.package();
+            pkg.config.push(format!("{} -> ~/.config/{}", name, name));
+            pkg.service = Some(format!("{}.service", name));
+            pkg.min_version = Some(MinVersion {
+                version: version.to_string(),
+                strict: *strict,
+            });
+            config.packages.insert(name.to_string(), pkg);
+        }
+        config
+    }
+
+    #[test]
+    fn test_check_reports_only_old_installed_packages() {
+        let config = config_with(&[
+            ("fish", "3.7", false),
+            ("tmux", "3.4", false),
+            ("kitty", "0.30", false),
+        ]);
+        let installed: HashMap<String, String> = [("fish", "3.6.1-2"), ("tmux", "3.4-1")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let violations = check_min_versions(&config, &installed);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].message(),
+            "fish 3.6.1-2 installed, config requires >= 3.7 — dotfiles for fish will still sync, use [strict] to block"
+        );
+    }
+
+    #[test]
+    fn test_warn_keeps_actions_and_strict_blocks_them() {
+        let mut config = config_with(&[("fish", "3.7", false), ("tmux", "3.4", true)]);
+        let installed: HashMap<String, String> = [("fish", "3.6.1-2"), ("tmux", "3.3a-7")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let violations = check_min_versions(&config, &installed);
+        let warnings = enforce_min_versions(&mut config, &violations);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[1].contains("skipped ([strict])"));
+
+        let fish = &config.packages["fish"];
+        assert_eq!(fish.config.len(), 1);
+        assert!(fish.service.is_some());
+        let tmux = &config.packages["tmux"];
+        assert!(tmux.config.is_empty());
+        assert!(tmux.service.is_none());
+    }
+}
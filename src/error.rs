@@ -2,10 +2,19 @@
 
 use anyhow::Result;
 use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Errors reported through `handle_error*` so far
+static HARD_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// How many errors this process reported and carried on past
+pub fn hard_errors() -> usize {
+    HARD_ERRORS.load(Ordering::Relaxed)
+}
 
 /// Print an error message and exit with code 1
 pub fn exit_with_error(error: anyhow::Error) -> ! {
-    eprintln!("{}", crate::internal::color::red(&error.to_string()));
+    errln!("{}", crate::internal::color::red(&error.to_string()));
     process::exit(1);
 }
 
@@ -13,7 +22,8 @@ pub fn exit_with_error(error: anyhow::Error) -> ! {
 /// Returns true if there was an error
 pub fn handle_error_with_context(operation: &str, result: Result<()>) -> bool {
     if let Err(e) = result {
-        eprintln!(
+        HARD_ERRORS.fetch_add(1, Ordering::Relaxed);
+        errln!(
             "{}",
             crate::internal::color::red(&format!("Failed to {}: {}", operation, e))
         );
@@ -27,7 +37,8 @@ pub fn handle_error_with_context(operation: &str, result: Result<()>) -> bool {
 /// Returns true if there was an error
 pub fn handle_error(result: Result<()>) -> bool {
     if let Err(e) = result {
-        eprintln!("{}", crate::internal::color::red(&e.to_string()));
+        HARD_ERRORS.fetch_add(1, Ordering::Relaxed);
+        errln!("{}", crate::internal::color::red(&e.to_string()));
         true
     } else {
         false
@@ -37,7 +48,7 @@ pub fn handle_error(result: Result<()>) -> bool {
 /// Handle a Result by printing the error and exiting if failed
 pub fn exit_on_error(result: Result<()>) {
     if let Err(e) = result {
-        eprintln!("{}", crate::internal::color::red(&format!("Error: {}", e)));
+        errln!("{}", crate::internal::color::red(&format!("Error: {}", e)));
         process::exit(1);
     }
 }
@@ -0,0 +1,206 @@
+//! Paths on case-insensitive filesystems
+//!
+//! A home directory can reach into an exFAT or other case-insensitive mount,
+//! where `~/Library` and `~/library` are the same file. Whether a filesystem
+//! folds case is found out by creating a probe file in it and looking it up
+//! under its upper-case name, once per mountpoint. Paths are then compared by
+//! [`key`], which lowercases the part below such a mountpoint, so state keyed
+//! by path and collision checks agree with the filesystem. When the probe
+//! cannot be written the filesystem is taken to be case-sensitive, which is
+//! how every path was compared before.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Mountpoints probed so far, and whether they fold case
+static MOUNTS: Mutex<BTreeMap<PathBuf, bool>> = Mutex::new(BTreeMap::new());
+
+/// Whether the filesystem holding the directory `dir` is case-insensitive,
+/// found out with a probe file created and removed again
+///
+/// `None` when no probe file can be created there (read-only, not writable,
+/// missing).
+pub fn probe(dir: &Path) -> Option<bool> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        ".owl-case-probe-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = dir.join(&name);
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .ok()?;
+    let insensitive = fs::symlink_metadata(dir.join(name.to_uppercase())).is_ok();
+    let _ = fs::remove_file(&path);
+    Some(insensitive)
+}
+
+/// The mountpoint of the case-insensitive filesystem `path` is on, if it is
+/// on one; probed once per mountpoint
+pub fn insensitive_mount(path: &Path) -> Option<PathBuf> {
+    let dir = path.ancestors().find(|p| p.is_dir())?;
+    let mount = mountpoint(dir)?;
+    if let Some(&insensitive) = MOUNTS.lock().unwrap_or_else(|e| e.into_inner()).get(&mount) {
+        return insensitive.then_some(mount);
+    }
+    // A filesystem that could not be probed is asked again next time
+    let insensitive = probe(dir).or_else(|| probe(&mount))?;
+    MOUNTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(mount.clone(), insensitive);
+    insensitive.then_some(mount)
+}
+
+/// The topmost ancestor of `dir` on the same device
+fn mountpoint(dir: &Path) -> Option<PathBuf> {
+    let dev = fs::metadata(dir).ok()?.dev();
+    let mut mount = dir;
+    for ancestor in dir.ancestors().skip(1) {
+        match fs::metadata(ancestor) {
+            Ok(meta) if meta.dev() == dev => mount = ancestor,
+            _ => break,
+        }
+    }
+    Some(mount.to_path_buf())
+}
+
+/// `path` as its filesystem compares it: lowercased below `mount`, the
+/// case-insensitive mountpoint it is on
+pub fn key(path: &str, mount: Option<&Path>) -> String {
+    match mount.and_then(|mount| Some((mount, Path::new(path).strip_prefix(mount).ok()?))) {
+        Some((mount, rest)) => mount
+            .join(rest.to_string_lossy().to_lowercase())
+            .to_string_lossy()
+            .into_owned(),
+        None => path.to_string(),
+    }
+}
+
+/// [`key`] for a path on this machine
+pub fn path_key(path: &str) -> String {
+    key(path, insensitive_mount(Path::new(path)).as_deref())
+}
+
+/// Paths that differ only by case
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseCollision {
+    /// The spellings, sorted
+    pub paths: Vec<String>,
+    /// One of them is on a case-insensitive filesystem, where they can be the same file
+    pub insensitive: bool,
+}
+
+/// Groups of `paths` that differ only by case; `mount_of` gives the
+/// case-insensitive mountpoint a path is on (see [`insensitive_mount`])
+///
+/// Paths given more than once are one spelling, not a collision.
+pub fn case_collisions(
+    paths: &[String],
+    mount_of: impl Fn(&str) -> Option<PathBuf>,
+) -> Vec<CaseCollision> {
+    let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for path in paths {
+        groups
+            .entry(path.to_lowercase())
+            .or_default()
+            .push(path.as_str());
+    }
+    groups
+        .into_values()
+        .filter_map(|mut spellings| {
+            spellings.sort();
+            spellings.dedup();
+            if spellings.len() < 2 {
+                return None;
+            }
+            Some(CaseCollision {
+                insensitive: spellings.iter().any(|path| mount_of(path).is_some()),
+                paths: spellings.into_iter().map(str::to_string).collect(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_cleans_up_and_degrades() {
+        let dir = tempfile::tempdir().unwrap();
+        // The test machine's temp directory is case-sensitive
+        assert_eq!(probe(dir.path()), Some(false));
+        assert_eq!(
+            fs::read_dir(dir.path()).unwrap().count(),
+            0,
+            "probe removed"
+        );
+
+        // Not a writable directory: unknown, and nothing is created
+        fs::write(dir.path().join("file"), "").unwrap();
+        assert_eq!(probe(&dir.path().join("file")), None);
+        assert_eq!(probe(&dir.path().join("missing")), None);
+        assert!(!dir.path().join("missing").exists());
+        assert_eq!(insensitive_mount(&dir.path().join("missing/deeper")), None);
+    }
+
+    #[test]
+    fn test_keys_fold_only_below_the_mount() {
+        let mount = Path::new("/home/me/Shared");
+        assert_eq!(
+            key("/home/me/Shared/Library/Prefs", Some(mount)),
+            "/home/me/Shared/library/prefs"
+        );
+        assert_eq!(key("/home/me/Docs/A", Some(mount)), "/home/me/Docs/A");
+        assert_eq!(key("/home/me/Shared/A", None), "/home/me/Shared/A");
+    }
+
+    #[test]
+    fn test_case_collisions_by_filesystem() {
+        let shared = |path: &str| {
+            path.starts_with("/home/me/Shared/")
+                .then(|| PathBuf::from("/home/me/Shared"))
+        };
+        let paths: Vec<String> = [
+            "/home/me/Shared/Library",
+            "/home/me/Shared/library",
+            "/home/me/Notes",
+            "/home/me/notes",
+            "/home/me/.vimrc",
+            "/home/me/.vimrc",
+        ]
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+        assert_eq!(
+            case_collisions(&paths, shared),
+            vec![
+                CaseCollision {
+                    paths: vec!["/home/me/Notes".to_string(), "/home/me/notes".to_string()],
+                    insensitive: false,
+                },
+                CaseCollision {
+                    paths: vec![
+                        "/home/me/Shared/Library".to_string(),
+                        "/home/me/Shared/library".to_string(),
+                    ],
+                    insensitive: true,
+                },
+            ]
+        );
+        // One of them on the case-insensitive mount is enough
+        let paths = vec![
+            "/home/me/Shared/x".to_string(),
+            "/home/me/shared/x".to_string(),
+        ];
+        assert!(case_collisions(&paths, shared)[0].insensitive);
+    }
+}
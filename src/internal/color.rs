@@ -68,7 +68,12 @@ impl Color {
 }
 
 /// Apply ANSI color codes to text
+///
+/// Plain text is returned when `NO_COLOR` is set or stdout or stderr is not a terminal.
 pub fn colorize(s: &str, color: Color) -> String {
+    if !crate::internal::environment::get().color_enabled() {
+        return s.to_string();
+    }
     format!("\x1b[{}m{}\x1b[0m", color.ansi_code(), s)
 }
 
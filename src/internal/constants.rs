@@ -28,6 +28,7 @@ pub const ENV_FISH_FILE: &str = "env.fish";
 
 // State management paths
 pub const STATE_DIR: &str = ".state";
+pub const BACKUPS_DIR: &str = "backups";
 
 // Package manager
 pub const PACKAGE_MANAGER: &str = "paru";
@@ -0,0 +1,227 @@
+//! Process environment, read once at startup
+//!
+//! Everything owl needs from the environment (HOME, the hostname, `EDITOR`,
+//! `OWL_*` variables and terminal capabilities) is resolved into an
+//! [`Environment`] when the CLI starts. Code reads it through [`get`] instead
+//! of calling `std::env::var` directly; tests build their own `Environment`
+//! and pass explicit paths rather than mutating the process environment.
+
+use anyhow::{Result, anyhow};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static ENVIRONMENT: OnceLock<Environment> = OnceLock::new();
+
+/// Snapshot of the environment values owl depends on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Environment {
+    /// `$HOME`, if set and non-empty
+    pub home: Option<PathBuf>,
+    /// Hostname, or the reason it could not be read
+    pub hostname: std::result::Result<String, String>,
+    /// `$EDITOR`
+    pub editor: Option<String>,
+    /// `$SHELL`, the user's login shell
+    pub shell: Option<String>,
+    /// `$OWL_SIMULATE_FAILURES`
+    pub simulate_failures: Option<String>,
+    /// `$OWL_SPLAY`, the default for `apply --splay`
+    pub splay: Option<String>,
+    /// `$NOTIFY_SOCKET`, set when systemd expects status notifications
+    pub notify_socket: Option<String>,
+    /// `$XDG_RUNTIME_DIR`, where `owl daemon` puts its socket
+    pub runtime_dir: Option<PathBuf>,
+    /// `$NO_COLOR` is set to a non-empty value
+    pub no_color: bool,
+    /// Standard output is a terminal
+    pub stdout_is_tty: bool,
+    /// Standard error is a terminal
+    pub stderr_is_tty: bool,
+    /// Standard output and error are the same file (`>> log 2>&1`)
+    pub streams_shared: bool,
+    /// Standard input is a terminal, i.e. someone is running owl by hand
+    pub stdin_is_tty: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            home: None,
+            hostname: Err("hostname not resolved".to_string()),
+            editor: None,
+            shell: None,
+            simulate_failures: None,
+            splay: None,
+            notify_socket: None,
+            runtime_dir: None,
+            no_color: false,
+            stdout_is_tty: false,
+            stderr_is_tty: false,
+            streams_shared: false,
+            stdin_is_tty: false,
+        }
+    }
+}
+
+impl Environment {
+    /// Read the current process environment
+    pub fn from_process() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        Self {
+            home: var("HOME").map(PathBuf::from),
+            hostname: crate::internal::constants::get_host_name().map_err(|e| e.to_string()),
+            editor: var("EDITOR"),
+            shell: var("SHELL"),
+            simulate_failures: var(crate::internal::failpoint::ENV_VAR),
+            splay: var("OWL_SPLAY"),
+            notify_socket: var("NOTIFY_SOCKET"),
+            runtime_dir: var("XDG_RUNTIME_DIR").map(PathBuf::from),
+            no_color: var("NO_COLOR").is_some(),
+            stdout_is_tty: std::io::stdout().is_terminal(),
+            stderr_is_tty: std::io::stderr().is_terminal(),
+            streams_shared: streams_shared(),
+            stdin_is_tty: std::io::stdin().is_terminal(),
+        }
+    }
+
+    /// An environment with only HOME set, for tests
+    #[cfg(test)]
+    pub fn with_home(home: &Path) -> Self {
+        Self {
+            home: Some(home.to_path_buf()),
+            ..Default::default()
+        }
+    }
+
+    /// The user's home directory
+    pub fn home(&self) -> Result<&Path> {
+        self.home
+            .as_deref()
+            .ok_or_else(|| anyhow!("HOME environment variable not set"))
+    }
+
+    /// The owl root, `~/.owl`
+    pub fn owl_dir(&self) -> Result<PathBuf> {
+        Ok(self.home()?.join(crate::internal::constants::OWL_DIR))
+    }
+
+    /// The machine's hostname, used to pick `hosts/<hostname>.owl`
+    pub fn hostname(&self) -> Result<&str> {
+        self.hostname.as_deref().map_err(|e| anyhow!("{}", e))
+    }
+
+    /// Whether ANSI colors should be written
+    ///
+    /// Both streams must be terminals: a redirected stream would otherwise
+    /// collect escape sequences.
+    pub fn color_enabled(&self) -> bool {
+        !self.no_color && self.stdout_is_tty && self.stderr_is_tty
+    }
+
+    /// `path` with the home directory shown as `~`
+    ///
+    /// Only a whole leading home component is replaced, so `/home/me2` stays as is
+    /// when HOME is `/home/me`.
+    pub fn display_path(&self, path: &str) -> String {
+        let Some(home) = self.home.as_deref().and_then(Path::to_str) else {
+            return path.to_string();
+        };
+        let home = home.trim_end_matches('/');
+        match path.strip_prefix(home) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+            _ => path.to_string(),
+        }
+    }
+}
+
+/// Whether stdout and stderr refer to the same open file
+fn streams_shared() -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let identity = |fd: u32| {
+        std::fs::metadata(format!("/proc/self/fd/{}", fd))
+            .ok()
+            .map(|m| (m.dev(), m.ino()))
+    };
+    matches!((identity(1), identity(2)), (Some(out), Some(err)) if out == err)
+}
+
+/// Resolve the process environment; later calls keep the first snapshot
+pub fn init() -> &'static Environment {
+    get()
+}
+
+/// The environment resolved at startup (resolved on first use if `init` was not called)
+pub fn get() -> &'static Environment {
+    ENVIRONMENT.get_or_init(Environment::from_process)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_home_and_owl_dir() {
+        let env = Environment::with_home(Path::new("/home/me"));
+        assert_eq!(env.home().unwrap(), Path::new("/home/me"));
+        assert_eq!(env.owl_dir().unwrap(), PathBuf::from("/home/me/.owl"));
+
+        let unset = Environment::default();
+        assert_eq!(
+            unset.home().unwrap_err().to_string(),
+            "HOME environment variable not set"
+        );
+        assert!(unset.hostname().is_err());
+    }
+
+    #[test]
+    fn test_display_path_replaces_whole_home_component() {
+        let env = Environment::with_home(Path::new("/home/me"));
+        assert_eq!(
+            env.display_path("/home/me/.owl/main.owl"),
+            "~/.owl/main.owl"
+        );
+        assert_eq!(env.display_path("/home/me"), "~");
+        assert_eq!(env.display_path("/home/me2/file"), "/home/me2/file");
+        assert_eq!(env.display_path("/etc/hosts"), "/etc/hosts");
+        // Without HOME nothing is replaced (an empty prefix would match everything)
+        assert_eq!(Environment::default().display_path("/etc/x"), "/etc/x");
+    }
+
+    #[test]
+    fn test_color_needs_tty_and_no_no_color() {
+        let mut env = Environment {
+            stdout_is_tty: true,
+            ..Default::default()
+        };
+        // A redirected stderr would collect escape sequences
+        assert!(!env.color_enabled());
+        env.stderr_is_tty = true;
+        assert!(env.color_enabled());
+        env.no_color = true;
+        assert!(!env.color_enabled());
+    }
+
+    /// Reading HOME anywhere else would bypass the snapshot
+    #[test]
+    fn test_home_is_only_read_here() {
+        let needle = concat!("env::var(", "\"HOME\")");
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut offenders = Vec::new();
+        let mut stack = vec![src];
+        while let Some(dir) = stack.pop() {
+            for entry in std::fs::read_dir(&dir).unwrap().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    stack.push(path);
+                } else if path.extension().is_some_and(|e| e == "rs")
+                    && !path.ends_with("internal/environment.rs")
+                    && std::fs::read_to_string(&path).unwrap().contains(needle)
+                {
+                    offenders.push(path);
+                }
+            }
+        }
+        assert!(offenders.is_empty(), "direct HOME reads in {:?}", offenders);
+    }
+}
@@ -0,0 +1,73 @@
+//! Simulated failures for exercising apply error paths
+//!
+//! Debug builds read `OWL_SIMULATE_FAILURES`, a comma separated list of
+//! `phase:index` entries (e.g. `dotfiles:1` fails the second dotfile). Release
+//! builds ignore the variable entirely.
+
+use anyhow::{Result, anyhow};
+
+/// Environment variable holding the failure spec
+pub const ENV_VAR: &str = "OWL_SIMULATE_FAILURES";
+
+/// Set of `(phase, index)` points that fail on purpose
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Failpoints {
+    points: Vec<(String, usize)>,
+}
+
+impl Failpoints {
+    /// Parse a spec such as `dotfiles:1,dotfiles:3`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut points = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (phase, index) = entry
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Invalid failure spec '{}', expected phase:index", entry))?;
+            let index = index
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow!("Invalid failure index in '{}'", entry))?;
+            points.push((phase.trim().to_string(), index));
+        }
+        Ok(Self { points })
+    }
+
+    /// Failpoints requested through the environment (always empty in release builds)
+    pub fn from_env() -> Result<Self> {
+        if !cfg!(debug_assertions) {
+            return Ok(Self::default());
+        }
+        match &crate::internal::environment::get().simulate_failures {
+            Some(spec) => Self::parse(spec),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Fail if `phase:index` was requested
+    pub fn check(&self, phase: &str, index: usize) -> Result<()> {
+        if self.points.iter().any(|(p, i)| p == phase && *i == index) {
+            return Err(anyhow!("Simulated failure in {} #{}", phase, index));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_check() {
+        let fp = Failpoints::parse("dotfiles:1, services:0").unwrap();
+        assert!(fp.check("dotfiles", 0).is_ok());
+        assert!(fp.check("dotfiles", 1).is_err());
+        assert!(fp.check("services", 0).is_err());
+        assert_eq!(Failpoints::parse("").unwrap(), Failpoints::default());
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        assert!(Failpoints::parse("dotfiles").is_err());
+        assert!(Failpoints::parse("dotfiles:x").is_err());
+    }
+}
@@ -1,35 +1,120 @@
 //! File operations utilities
 
 use anyhow::{Result, anyhow};
-use std::env;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use crate::core::config::format::ConfigFormat;
 use crate::internal::constants;
 
+/// Files left by editors, merges and backups contain one of these
+const LEFTOVER_MARKERS: &[&str] = &[".orig", ".rej", ".bak"];
+
+/// Set once the excluded files were reported
+static EXCLUDED_WARNED: AtomicBool = AtomicBool::new(false);
+
 /// Get the owl root directory (~/.owl)
 fn owl_dir() -> Result<PathBuf> {
-    let home = env::var("HOME").map_err(|_| anyhow!("HOME environment variable not set"))?;
-    Ok(PathBuf::from(home).join(constants::OWL_DIR))
+    crate::internal::environment::get().owl_dir()
+}
+
+/// Whether a file name looks like an editor swap file (`.dev.owl.swp`, `.#dev.owl`),
+/// a merge leftover (`dev.owl.orig`, `dev.owl.rej`) or a backup (`dev.owl.bak`)
+pub fn is_leftover(name: &str) -> bool {
+    name.starts_with(['.', '#']) || LEFTOVER_MARKERS.iter().any(|m| name.contains(m))
+}
+
+/// Whether `name` is read as a config file: ASCII letters, digits and `._-`
+/// before `extension`, and not a leftover
+pub fn is_config_file_name(name: &str, extension: &str) -> bool {
+    name.strip_suffix(extension).is_some_and(|stem| {
+        !stem.is_empty()
+            && stem
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+            && !is_leftover(name)
+    })
 }
 
-/// Scan a directory for .owl files and add them to the files vector
-pub fn scan_directory_for_owl_files(directory: &Path, files: &mut Vec<String>) {
-    if let Ok(entries) = std::fs::read_dir(directory) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.extension().is_some_and(|ext| ext == "owl") {
-                if let Some(path_str) = path.to_str() {
-                    files.push(path_str.to_string());
-                }
+/// Config files of an owl root, and what was passed over next to them
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigFiles {
+    /// The main file, then `hosts/` and then `groups/`, each directory sorted by name
+    pub files: Vec<PathBuf>,
+    /// Entries in `hosts/` and `groups/` that mention the extension but are not
+    /// read, relative to the owl root
+    pub excluded: Vec<String>,
+}
+
+impl ConfigFiles {
+    /// One line naming the excluded files, if there are any
+    pub fn warning(&self) -> Option<String> {
+        (!self.excluded.is_empty()).then(|| {
+            format!(
+                "Not read as config files (editor, merge or backup leftovers, or unusual names): {}",
+                self.excluded.join(", ")
+            )
+        })
+    }
+
+    /// Print `warning` the first time in this run
+    pub fn warn_once(&self) {
+        if let Some(warning) = self.warning()
+            && !EXCLUDED_WARNED.swap(true, Ordering::Relaxed)
+        {
+            warnln!(
+                "  {} {}",
+                crate::internal::color::yellow("warning:"),
+                warning
+            );
+        }
+    }
+}
+
+/// Find the config files of `owl_root` in its format
+///
+/// Only regular files (or links to them) with a name `is_config_file_name`
+/// accepts are included.
+pub fn discover_config_files(owl_root: &Path) -> ConfigFiles {
+    let format = ConfigFormat::of_root(owl_root);
+    let extension = format.extension();
+    let mut found = ConfigFiles::default();
+    let main = owl_root.join(format.main_file());
+    if main.is_file() {
+        found.files.push(main);
+    }
+    for dir in [constants::HOSTS_DIR, constants::GROUPS_DIR] {
+        let Ok(entries) = std::fs::read_dir(owl_root.join(dir)) else {
+            continue;
+        };
+        let mut entries: Vec<(String, PathBuf)> = entries
+            .flatten()
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().into_owned(),
+                    entry.path(),
+                )
+            })
+            .collect();
+        entries.sort();
+        for (name, path) in entries {
+            if is_config_file_name(&name, extension) && path.is_file() {
+                found.files.push(path);
+            } else if name.contains(extension) {
+                found.excluded.push(format!("{}/{}", dir, name));
             }
         }
     }
+    found
 }
 
 /// Open a file in the user's preferred editor
 pub fn open_editor(path: &str) -> Result<()> {
-    let editor = env::var("EDITOR").unwrap_or_else(|_| constants::DEFAULT_EDITOR.to_string());
+    let editor = crate::internal::environment::get()
+        .editor
+        .clone()
+        .unwrap_or_else(|| constants::DEFAULT_EDITOR.to_string());
 
     Command::new(&editor)
         .arg(path)
@@ -79,23 +164,93 @@ pub fn get_dotfile_path(filename: &str) -> Result<String> {
 }
 
 /// Get all config files from the owl directory (main, hosts, and groups)
+///
+/// See `discover_config_files`; files passed over are reported once per run.
 pub fn get_all_config_files() -> Result<Vec<String>> {
-    let owl = owl_dir()?;
-    let mut files = Vec::new();
-
-    // Check main config
-    let main_config = owl.join(constants::MAIN_CONFIG_FILE);
-    if main_config.exists() {
-        if let Some(path_str) = main_config.to_str() {
-            files.push(path_str.to_string());
-        }
-    }
+    let found = discover_config_files(&owl_dir()?);
+    found.warn_once();
+    Ok(found
+        .files
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
 
-    // Scan hosts directory
-    scan_directory_for_owl_files(&owl.join(constants::HOSTS_DIR), &mut files);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
 
-    // Scan groups directory
-    scan_directory_for_owl_files(&owl.join(constants::GROUPS_DIR), &mut files);
+    #[test]
+    fn test_discovery_skips_leftovers_and_sorts() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in [
+            "main.owl",
+            ".main.owl.swp",
+            "hosts/box.owl",
+            "hosts/.box.owl.swp",
+            "groups/zeta.owl",
+            "groups/dev.owl",
+            "groups/alpha-1.2_x.owl",
+            "groups/.#dev.owl",
+            "groups/#dev.owl#",
+            "groups/dev.owl~",
+            "groups/dev.owl.orig",
+            "groups/dev.owl.rej",
+            "groups/dev.owl.bak",
+            "groups/dev.orig.owl",
+            "groups/.hidden.owl",
+            "groups/my group.owl",
+            "groups/notes.txt",
+            "groups/.owl",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::create_dir_all(root.join("groups/folder.owl")).unwrap();
 
-    Ok(files)
+        let found = discover_config_files(root);
+        let files: Vec<String> = found
+            .files
+            .iter()
+            .map(|p| p.strip_prefix(root).unwrap().display().to_string())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "main.owl",
+                "hosts/box.owl",
+                "groups/alpha-1.2_x.owl",
+                "groups/dev.owl",
+                "groups/zeta.owl"
+            ]
+        );
+        assert_eq!(
+            found.excluded,
+            [
+                "hosts/.box.owl.swp",
+                "groups/#dev.owl#",
+                "groups/.#dev.owl",
+                "groups/.hidden.owl",
+                "groups/.owl",
+                "groups/dev.orig.owl",
+                "groups/dev.owl.bak",
+                "groups/dev.owl.orig",
+                "groups/dev.owl.rej",
+                "groups/dev.owl~",
+                "groups/folder.owl",
+                "groups/my group.owl",
+            ]
+        );
+        let warning = found.warning().unwrap();
+        assert!(
+            warning.starts_with("Not read as config files")
+                && warning.ends_with("groups/folder.owl, groups/my group.owl"),
+            "{}",
+            warning
+        );
+        assert_eq!(ConfigFiles::default().warning(), None);
+    }
 }
@@ -1,4 +1,13 @@
+#[macro_use]
+pub mod output;
+pub mod case_fold;
 pub mod color;
 pub mod constants;
+pub mod environment;
+pub mod failpoint;
 pub mod files;
+pub mod probe;
+pub mod systemd;
+pub mod time;
+pub mod toposort;
 pub mod util;
@@ -0,0 +1,465 @@
+//! Serialized human output
+//!
+//! Every line owl prints for people goes through one [`Output`], which holds a
+//! single lock across stdout and stderr. A line is written and flushed in one
+//! go, so the spinner thread, subprocess readers and error reporting cannot
+//! interleave within a line even when both streams end up in the same file
+//! (`owl apply >> log 2>&1`).
+//!
+//! Spinner frames are status lines redrawn in place with `\r\x1b[2K`. They are
+//! only drawn when both streams are terminals; otherwise status updates are
+//! dropped and no control characters are written at all. When both streams
+//! go to the same file, warning and error lines are tagged with their severity
+//! so the merged log still says which stream a line came from.
+//!
+//! A phase running beside another one can [`capture`] its lines on its own
+//! thread and have them [replayed](Output::replay) once the other is done, so
+//! the two phases print one after the other rather than line by line mixed.
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+thread_local! {
+    /// Lines held back on this thread while [`capture`] runs
+    static CAPTURED: RefCell<Option<Vec<Captured>>> = const { RefCell::new(None) };
+}
+
+/// Erase the current terminal line
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// How severe a line is; warnings and errors go to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn tag(self) -> Option<&'static str> {
+        match self {
+            Severity::Info => None,
+            Severity::Warning => Some("[warning] "),
+            Severity::Error => Some("[error] "),
+        }
+    }
+}
+
+/// Output held back by [`capture`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Captured {
+    Line(Severity, String),
+    Partial(String),
+}
+
+/// Run `f`, holding back every line written on this thread instead of
+/// printing it; status lines are dropped
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<Captured>) {
+    let previous = CAPTURED.with(|c| c.replace(Some(Vec::new())));
+    let value = f();
+    let captured = CAPTURED.with(|c| c.replace(previous)).unwrap_or_default();
+    (value, captured)
+}
+
+/// How many lines [`capture`] has held back on this thread so far
+pub fn captured_len() -> usize {
+    CAPTURED.with(|c| c.borrow().as_ref().map_or(0, Vec::len))
+}
+
+/// Hold back `entry` if this thread is capturing; whether it was
+fn hold(entry: impl FnOnce() -> Captured) -> bool {
+    CAPTURED.with(|c| match c.borrow_mut().as_mut() {
+        Some(captured) => {
+            captured.push(entry());
+            true
+        }
+        None => false,
+    })
+}
+
+/// Where a stream is written
+enum Stream {
+    /// The process stream, through `print!`/`eprint!` so test capture still works
+    Stdout,
+    Stderr,
+    #[cfg(test)]
+    Custom(Box<dyn Write + Send>),
+}
+
+impl Stream {
+    fn write(&mut self, text: &str) {
+        match self {
+            Stream::Stdout => {
+                print!("{}", text);
+                std::io::stdout().flush().ok();
+            }
+            Stream::Stderr => eprint!("{}", text),
+            #[cfg(test)]
+            Stream::Custom(writer) => {
+                writer.write_all(text.as_bytes()).ok();
+                writer.flush().ok();
+            }
+        }
+    }
+}
+
+struct State {
+    stdout: Stream,
+    stderr: Stream,
+    /// A status line is on screen and must be erased before the next line
+    status_shown: bool,
+}
+
+/// The facade all human output is written through
+pub struct Output {
+    state: Mutex<State>,
+    /// Both streams are terminals, so status lines and colors may be drawn
+    control: bool,
+    /// Both streams go to the same file; tag warnings and errors
+    tag: bool,
+}
+
+impl Output {
+    /// Output to the process streams
+    pub fn from_env(env: &crate::internal::environment::Environment) -> Self {
+        Self::with_streams(
+            Stream::Stdout,
+            Stream::Stderr,
+            env.stdout_is_tty && env.stderr_is_tty,
+            env.streams_shared && !(env.stdout_is_tty && env.stderr_is_tty),
+        )
+    }
+
+    /// Output to `stdout` and `stderr` writers
+    #[cfg(test)]
+    pub fn to_writers(
+        stdout: Box<dyn Write + Send>,
+        stderr: Box<dyn Write + Send>,
+        control: bool,
+        tag: bool,
+    ) -> Self {
+        Self::with_streams(Stream::Custom(stdout), Stream::Custom(stderr), control, tag)
+    }
+
+    fn with_streams(stdout: Stream, stderr: Stream, control: bool, tag: bool) -> Self {
+        Self {
+            state: Mutex::new(State {
+                stdout,
+                stderr,
+                status_shown: false,
+            }),
+            control,
+            tag,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Write `text` and a newline as whole lines, erasing any status line first
+    pub fn line(&self, severity: Severity, text: &str) {
+        if hold(|| Captured::Line(severity, text.to_string())) {
+            return;
+        }
+        let mut block = String::with_capacity(text.len() + 16);
+        for line in text.split('\n') {
+            if let Some(tag) = severity.tag().filter(|_| self.tag) {
+                block.push_str(tag);
+            }
+            block.push_str(line);
+            block.push('\n');
+        }
+        self.write(severity, block);
+    }
+
+    /// Write `text` to stdout without a newline, e.g. a prompt
+    pub fn partial(&self, text: &str) {
+        if hold(|| Captured::Partial(text.to_string())) {
+            return;
+        }
+        self.write(Severity::Info, text.to_string());
+    }
+
+    fn write(&self, severity: Severity, text: String) {
+        let mut state = self.lock();
+        let text = if std::mem::take(&mut state.status_shown) {
+            format!("{}{}", CLEAR_LINE, text)
+        } else {
+            text
+        };
+        match severity {
+            Severity::Info => state.stdout.write(&text),
+            Severity::Warning | Severity::Error => state.stderr.write(&text),
+        }
+    }
+
+    /// Draw `text` as the status line; nothing is written unless both streams
+    /// are terminals
+    pub fn status(&self, text: &str) {
+        if !self.control || CAPTURED.with(|c| c.borrow().is_some()) {
+            return;
+        }
+        let mut state = self.lock();
+        state.stdout.write(&format!("{}{}", CLEAR_LINE, text));
+        state.status_shown = true;
+    }
+
+    /// Erase the status line if one is shown
+    pub fn clear_status(&self) {
+        let mut state = self.lock();
+        if std::mem::take(&mut state.status_shown) {
+            state.stdout.write(CLEAR_LINE);
+        }
+    }
+
+    /// Write what [`capture`] held back, as it would have been written then
+    pub fn replay(&self, captured: &Captured) {
+        match captured {
+            Captured::Line(severity, text) => self.line(*severity, text),
+            Captured::Partial(text) => self.partial(text),
+        }
+    }
+}
+
+/// The process-wide output, set up from the environment on first use
+pub fn get() -> &'static Output {
+    OUTPUT.get_or_init(|| Output::from_env(crate::internal::environment::get()))
+}
+
+/// `println!` through the output facade
+macro_rules! outln {
+    () => {
+        $crate::internal::output::get().line($crate::internal::output::Severity::Info, "")
+    };
+    ($($arg:tt)*) => {
+        $crate::internal::output::get()
+            .line($crate::internal::output::Severity::Info, &format!($($arg)*))
+    };
+}
+
+/// `print!` through the output facade
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::internal::output::get().partial(&format!($($arg)*))
+    };
+}
+
+/// A warning line on stderr through the output facade
+macro_rules! warnln {
+    ($($arg:tt)*) => {
+        $crate::internal::output::get()
+            .line($crate::internal::output::Severity::Warning, &format!($($arg)*))
+    };
+}
+
+/// `eprintln!` through the output facade, as an error line
+macro_rules! errln {
+    () => {
+        $crate::internal::output::get().line($crate::internal::output::Severity::Error, "")
+    };
+    ($($arg:tt)*) => {
+        $crate::internal::output::get()
+            .line($crate::internal::output::Severity::Error, &format!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A buffer both streams can write into, like `>> log 2>&1`
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            // Byte at a time, so a writer without the facade's lock would interleave
+            for byte in buf {
+                self.0.lock().unwrap().push(*byte);
+                std::thread::yield_now();
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn merged(control: bool, tag: bool) -> (Output, SharedBuffer) {
+        let buffer = SharedBuffer::default();
+        let output = Output::to_writers(
+            Box::new(buffer.clone()),
+            Box::new(buffer.clone()),
+            control,
+            tag,
+        );
+        (output, buffer)
+    }
+
+    #[test]
+    fn test_concurrent_emitters_keep_lines_intact() {
+        const EMITTERS: usize = 8;
+        const LINES: usize = 200;
+        let (output, buffer) = merged(false, true);
+        std::thread::scope(|scope| {
+            // A spinner redrawing constantly while the others write
+            scope.spawn(|| {
+                for frame in 0..LINES {
+                    output.status(&format!("working {}", frame));
+                    output.clear_status();
+                }
+            });
+            for emitter in 0..EMITTERS {
+                let output = &output;
+                scope.spawn(move || {
+                    for n in 0..LINES {
+                        let severity = match n % 3 {
+                            0 => Severity::Info,
+                            1 => Severity::Warning,
+                            _ => Severity::Error,
+                        };
+                        output.line(severity, &format!("emitter {} line {}", emitter, n));
+                    }
+                });
+            }
+        });
+
+        let text = buffer.text();
+        assert!(!text.contains('\r') && !text.contains('\x1b'));
+        let mut next = [0usize; EMITTERS];
+        for line in text.lines() {
+            let body = line
+                .strip_prefix("[warning] ")
+                .or_else(|| line.strip_prefix("[error] "))
+                .unwrap_or(line);
+            let words: Vec<&str> = body.split(' ').collect();
+            assert!(
+                words.len() == 4 && words[0] == "emitter" && words[2] == "line",
+                "mangled line {:?}",
+                line
+            );
+            let emitter: usize = words[1].parse().unwrap();
+            let n: usize = words[3].parse().unwrap();
+            assert_eq!(n, next[emitter], "emitter {} out of order", emitter);
+            let expected_tag = match n % 3 {
+                0 => "",
+                1 => "[warning] ",
+                _ => "[error] ",
+            };
+            assert!(line.starts_with(expected_tag), "{:?}", line);
+            next[emitter] += 1;
+        }
+        assert_eq!(next, [LINES; EMITTERS]);
+    }
+
+    #[test]
+    fn test_status_line_is_erased_before_the_next_line() {
+        let (output, buffer) = merged(true, false);
+        output.status("Installing 1/2");
+        output.line(Severity::Error, "failed");
+        output.clear_status();
+        output.line(Severity::Info, "done");
+        assert_eq!(
+            buffer.text(),
+            "\r\x1b[2KInstalling 1/2\r\x1b[2Kfailed\ndone\n"
+        );
+    }
+
+    #[test]
+    fn test_untagged_unless_streams_are_merged() {
+        let (output, buffer) = merged(false, false);
+        output.line(Severity::Warning, "careful\nreally");
+        output.partial("Continue? ");
+        assert_eq!(buffer.text(), "careful\nreally\nContinue? ");
+
+        let (output, buffer) = merged(false, true);
+        output.line(Severity::Warning, "careful\nreally");
+        assert_eq!(buffer.text(), "[warning] careful\n[warning] really\n");
+    }
+
+    #[test]
+    fn test_from_env_needs_both_terminals_for_control() {
+        use crate::internal::environment::Environment;
+        let stdout_only = Environment {
+            stdout_is_tty: true,
+            streams_shared: true,
+            ..Default::default()
+        };
+        let output = Output::from_env(&stdout_only);
+        assert!(!output.control);
+        assert!(output.tag);
+        let both = Environment {
+            stdout_is_tty: true,
+            stderr_is_tty: true,
+            streams_shared: true,
+            ..Default::default()
+        };
+        let output = Output::from_env(&both);
+        assert!(output.control);
+        assert!(!output.tag);
+    }
+
+    /// Terminal control sequences written anywhere else would bypass the facade
+    #[test]
+    fn test_clear_line_is_only_written_here() {
+        let needle = concat!("\\r\\x1b", "[2K");
+        let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut offenders = Vec::new();
+        let mut stack = vec![src];
+        while let Some(dir) = stack.pop() {
+            for entry in std::fs::read_dir(&dir).unwrap().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    stack.push(path);
+                } else if path.extension().is_some_and(|e| e == "rs")
+                    && !path.ends_with("internal/output.rs")
+                    && std::fs::read_to_string(&path).unwrap().contains(needle)
+                {
+                    offenders.push(path);
+                }
+            }
+        }
+        assert!(offenders.is_empty(), "raw line clears in {:?}", offenders);
+    }
+
+    #[test]
+    fn test_captured_lines_are_replayed_whole_after_the_other_thread() {
+        let (output, buffer) = merged(true, true);
+        let captured = std::thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                capture(|| {
+                    output.line(Severity::Info, "dotfiles 1");
+                    output.status("checking");
+                    assert_eq!(captured_len(), 1);
+                    output.line(Severity::Warning, "dotfiles 2");
+                    output.partial("done? ");
+                })
+                .1
+            });
+            let captured = worker.join().unwrap();
+            output.line(Severity::Info, "packages");
+            captured
+        });
+        assert_eq!(captured_len(), 0, "only the worker thread captured");
+        assert_eq!(captured.len(), 3);
+        for entry in &captured {
+            output.replay(entry);
+        }
+        assert_eq!(
+            buffer.text(),
+            "packages\ndotfiles 1\n[warning] dotfiles 2\ndone? "
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Existence checks for many paths, with one directory listing per parent
+//!
+//! Checking hundreds of dotfile sources one `stat` at a time costs a syscall
+//! each; most of them share a handful of directories. [`Probe::listing`] reads
+//! each parent once, on several threads, and answers from the listings. The
+//! answers are those of [`Path::exists`]: symlinks are followed, and a parent
+//! that cannot be listed falls back to asking about the path itself, as does a
+//! name missing from the listing of a case-insensitive filesystem, where it
+//! may be listed under other case.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// A read directory: its entry names, and whether each is a symlink
+enum Listing {
+    Entries(HashMap<OsString, bool>),
+    /// Missing, or not a directory, so nothing under it exists
+    Absent,
+    /// Could not be read (e.g. no read permission); ask per path
+    Unreadable,
+}
+
+/// Answers whether paths exist
+pub struct Probe {
+    /// Listings of the parents of the paths given up front; `None` stats each path
+    listings: Option<HashMap<PathBuf, Listing>>,
+}
+
+impl Probe {
+    /// One `stat` per path
+    pub fn per_path() -> Self {
+        Self { listings: None }
+    }
+
+    /// Read the parent directory of each of `paths`, at most `concurrency` at once
+    pub fn listing(paths: &[PathBuf], concurrency: usize) -> Self {
+        let parents: Vec<PathBuf> = paths
+            .iter()
+            .filter_map(|path| split(path).map(|(parent, _)| parent.to_path_buf()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let listings = crate::internal::util::map_bounded(&parents, concurrency, |dir| list(dir));
+        Self {
+            listings: Some(parents.into_iter().zip(listings).collect()),
+        }
+    }
+
+    /// Whether `path` exists, following symlinks
+    pub fn exists(&self, path: &Path) -> bool {
+        let Some(listings) = &self.listings else {
+            return path.exists();
+        };
+        let Some((parent, name)) = split(path) else {
+            return path.exists();
+        };
+        match listings.get(parent) {
+            Some(Listing::Entries(entries)) => match entries.get(name) {
+                // A symlink exists only when its target does
+                Some(true) => path.exists(),
+                Some(false) => true,
+                None => {
+                    crate::internal::case_fold::insensitive_mount(parent).is_some() && path.exists()
+                }
+            },
+            Some(Listing::Absent) => false,
+            Some(Listing::Unreadable) | None => path.exists(),
+        }
+    }
+}
+
+/// The directory to list and the name to look up in it
+///
+/// `None` for paths a listing cannot answer, like `dir/.` or a bare name.
+fn split(path: &Path) -> Option<(&Path, &std::ffi::OsStr)> {
+    let (parent, name) = (path.parent()?, path.file_name()?);
+    (!parent.as_os_str().is_empty() && parent.join(name).as_os_str() == path.as_os_str())
+        .then_some((parent, name))
+}
+
+fn list(dir: &Path) -> Listing {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => Listing::Entries(
+            entries
+                .flatten()
+                .map(|entry| {
+                    let symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+                    (entry.file_name(), symlink)
+                })
+                .collect(),
+        ),
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Listing::Absent
+        }
+        Err(_) => Listing::Unreadable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_listing_answers_like_stat() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/file"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("a/file"), root.join("a/link")).unwrap();
+        std::os::unix::fs::symlink(root.join("gone"), root.join("a/broken")).unwrap();
+
+        let paths: Vec<PathBuf> = [
+            "a",
+            "a/b",
+            "a/file",
+            "a/link",
+            "a/broken",
+            "a/missing",
+            "a/file/x",
+            "nope/x",
+            "a/b/..",
+            "a/b/../file",
+            "a/.",
+        ]
+        .iter()
+        .map(|p| root.join(p))
+        .collect();
+        let listed = Probe::listing(&paths, 4);
+        for path in &paths {
+            assert_eq!(
+                listed.exists(path),
+                Probe::per_path().exists(path),
+                "{}",
+                path.display()
+            );
+        }
+        // Paths that were not listed up front are still answered
+        assert!(listed.exists(&root.join("a/b")));
+        assert!(!listed.exists(&root.join("elsewhere/x")));
+    }
+}
@@ -0,0 +1,17 @@
+//! Status notifications for systemd units (`sd_notify`)
+//!
+//! Built with the `notify` feature (the `sd-notify` crate); without it
+//! notifications are dropped.
+
+/// Send `STATUS=<message>` to the service manager; a no-op outside systemd
+///
+/// Failures are ignored: a missing status line must never break a run.
+#[cfg(feature = "notify")]
+pub fn notify_status(message: &str) {
+    if crate::internal::environment::get().notify_socket.is_some() {
+        let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Status(message)]);
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+pub fn notify_status(_message: &str) {}
@@ -0,0 +1,217 @@
+//! Date parsing and formatting helpers
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local, Months, NaiveDate, TimeZone};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Current time as seconds since the Unix epoch
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Whether a cache stamped at `stamp` is younger than `ttl` at `now`
+///
+/// A stamp in the future means the clock moved backwards since it was written,
+/// so the cache's age is unknown and it counts as expired.
+pub fn is_fresh(stamp: u64, now: u64, ttl: u64) -> bool {
+    stamp <= now && now - stamp < ttl
+}
+
+/// Parse a point in time relative to `now`
+///
+/// Accepts `Nd` (days), `Nw` (weeks), `Nm` (months) before `now`, a plain
+/// `YYYY-MM-DD` date (local midnight) or a full RFC 3339 / ISO 8601 timestamp.
+pub fn parse_relative_date(s: &str, now: SystemTime) -> Result<SystemTime> {
+    let s = s.trim();
+    if let Some(unit) = s.chars().last()
+        && let Ok(n) = s[..s.len() - unit.len_utf8()].parse::<u32>()
+    {
+        let now_dt: DateTime<Local> = now.into();
+        let then = match unit {
+            'd' => now_dt.checked_sub_signed(chrono::Duration::days(n.into())),
+            'w' => now_dt.checked_sub_signed(chrono::Duration::weeks(n.into())),
+            'm' => now_dt.checked_sub_months(Months::new(n)),
+            _ => None,
+        };
+        return then
+            .map(SystemTime::from)
+            .ok_or_else(|| anyhow!("Invalid relative date '{}'", s));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let midnight = date
+            .and_hms_opt(0, 0, 0)
+            .and_then(|dt| Local.from_local_datetime(&dt).earliest())
+            .ok_or_else(|| anyhow!("Invalid date '{}'", s))?;
+        return Ok(midnight.into());
+    }
+
+    DateTime::parse_from_rfc3339(s)
+        .map(SystemTime::from)
+        .map_err(|_| {
+            anyhow!(
+                "Invalid date '{}', expected YYYY-MM-DD, an ISO 8601 timestamp or Nd/Nw/Nm",
+                s
+            )
+        })
+}
+
+/// Convert a `SystemTime` to seconds since the Unix epoch
+pub fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+/// Format epoch seconds as a local `YYYY-MM-DD` date
+pub fn format_date(secs: u64) -> String {
+    Local
+        .timestamp_opt(secs as i64, 0)
+        .single()
+        .map(|dt| dt.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Format epoch seconds as a local `YYYY-MM-DD HH:MM` timestamp
+pub fn format_datetime(secs: u64) -> String {
+    Local
+        .timestamp_opt(secs as i64, 0)
+        .single()
+        .map(|dt| dt.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Parse a duration like `90s`, `15m`, `2h`, `7d`, `2w` or `500ms`; a bare number is seconds
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+    let n: u64 = s[..digits]
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration '{}', expected e.g. 90s, 15m or 2h", s))?;
+    match &s[digits..] {
+        "ms" => Ok(Duration::from_millis(n)),
+        "" | "s" => Ok(Duration::from_secs(n)),
+        "m" => Ok(Duration::from_secs(n * 60)),
+        "h" => Ok(Duration::from_secs(n * 60 * 60)),
+        "d" => Ok(Duration::from_secs(n * 24 * 60 * 60)),
+        "w" => Ok(Duration::from_secs(n * 7 * 24 * 60 * 60)),
+        unit => Err(anyhow!(
+            "Invalid duration unit '{}' in '{}', expected ms, s, m, h, d or w",
+            unit,
+            s
+        )),
+    }
+}
+
+/// Format a duration in milliseconds compactly, e.g. `850ms`, `47s`, `2m 05s`
+pub fn format_duration_ms(ms: u64) -> String {
+    if ms < 1_000 {
+        return format!("{}ms", ms);
+    }
+    let secs = ms / 1_000;
+    if secs < 60 {
+        return format!("{}s", secs);
+    }
+    format!("{}m {:02}s", secs / 60, secs % 60)
+}
+
+/// Coarse age like `45s`, `12m`, `5h` or `3d`
+pub fn format_age(secs: i64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_is_fresh_table() {
+        let now = 1_000 * DAY;
+        let cases = [
+            (now, DAY, true),
+            (now - DAY + 1, DAY, true),
+            (now - DAY, DAY, false),
+            (0, DAY, false),
+            // Written after a clock that has since been set back
+            (now + 1, DAY, false),
+            (now + 300 * DAY, DAY, false),
+        ];
+        for (stamp, ttl, fresh) in cases {
+            assert_eq!(is_fresh(stamp, now, ttl), fresh, "stamp {}", stamp);
+        }
+    }
+
+    #[test]
+    fn test_parse_relative_units() {
+        let now = at(1_000 * DAY);
+        assert_eq!(to_secs(parse_relative_date("7d", now).unwrap()), 993 * DAY);
+        assert_eq!(to_secs(parse_relative_date("2w", now).unwrap()), 986 * DAY);
+        let month_ago = to_secs(parse_relative_date("1m", now).unwrap());
+        assert!((969 * DAY..=973 * DAY).contains(&month_ago));
+    }
+
+    #[test]
+    fn test_parse_absolute_dates() {
+        let now = at(0);
+        let parsed = parse_relative_date("2024-03-01T12:00:00Z", now).unwrap();
+        assert_eq!(to_secs(parsed), 1_709_294_400);
+        let date = parse_relative_date("2024-03-01", now).unwrap();
+        assert_eq!(format_date(to_secs(date)), "2024-03-01");
+    }
+
+    #[test]
+    fn test_parse_invalid_dates() {
+        let now = at(DAY);
+        assert!(parse_relative_date("7x", now).is_err());
+        assert!(parse_relative_date("yesterday", now).is_err());
+        assert!(parse_relative_date("2024-13-01", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_duration(" 2h ").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(
+            parse_duration("7d").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert_eq!(
+            parse_duration("2w").unwrap(),
+            Duration::from_secs(14 * 86400)
+        );
+        assert!(parse_duration("1y").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-5s").is_err());
+    }
+
+    #[test]
+    fn test_format_duration_ms() {
+        assert_eq!(format_duration_ms(850), "850ms");
+        assert_eq!(format_duration_ms(47_300), "47s");
+        assert_eq!(format_duration_ms(125_000), "2m 05s");
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(45), "45s");
+        assert_eq!(format_age(12 * 60), "12m");
+        assert_eq!(format_age(5 * 3600 + 10), "5h");
+        assert_eq!(format_age(3 * 86400), "3d");
+    }
+}
@@ -0,0 +1,141 @@
+//! Dependency ordering shared by package installs and services (`:after`)
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A dependency cycle; `members` lists it in order, starting at its smallest name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleError {
+    pub members: Vec<String>,
+}
+
+impl std::fmt::Display for CycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut chain = self.members.clone();
+        chain.extend(self.members.first().cloned());
+        write!(f, "Circular :after dependency: {}", chain.join(" -> "))
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+/// Order `nodes` so every node comes after the nodes it depends on
+///
+/// `deps` maps a node to the nodes that must come first; dependencies outside
+/// `nodes` are ignored. Nodes that are free at the same time come out sorted, so
+/// the result does not depend on input order.
+pub fn topo_sort(
+    nodes: &[String],
+    deps: &BTreeMap<String, Vec<String>>,
+) -> Result<Vec<String>, CycleError> {
+    let members: BTreeSet<&str> = nodes.iter().map(String::as_str).collect();
+    let prerequisites = |node: &str| -> BTreeSet<&str> {
+        deps.get(node)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .filter(|dep| members.contains(dep) && *dep != node)
+            .collect()
+    };
+
+    let mut pending: BTreeMap<&str, BTreeSet<&str>> = members
+        .iter()
+        .map(|&node| (node, prerequisites(node)))
+        .collect();
+    let mut order = Vec::with_capacity(pending.len());
+    while let Some(next) = pending
+        .iter()
+        .find(|(_, waiting_on)| waiting_on.is_empty())
+        .map(|(&node, _)| node)
+    {
+        pending.remove(next);
+        for waiting_on in pending.values_mut() {
+            waiting_on.remove(next);
+        }
+        order.push(next.to_string());
+    }
+
+    if pending.is_empty() {
+        Ok(order)
+    } else {
+        Err(find_cycle(&pending))
+    }
+}
+
+/// Walk unresolved dependencies from the smallest stuck node until one repeats
+///
+/// Every node left in `pending` waits on another pending node, so the walk
+/// always runs into a cycle.
+fn find_cycle(pending: &BTreeMap<&str, BTreeSet<&str>>) -> CycleError {
+    let mut path: Vec<&str> = Vec::new();
+    let mut node = *pending
+        .keys()
+        .next()
+        .expect("cycle search needs pending nodes");
+    while !path.contains(&node) {
+        path.push(node);
+        node = *pending[node]
+            .iter()
+            .next()
+            .expect("pending nodes always wait on another");
+    }
+    let start = path.iter().position(|&n| n == node).unwrap();
+    let mut members: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+    let smallest = (0..members.len()).min_by_key(|&i| &members[i]).unwrap_or(0);
+    members.rotate_left(smallest);
+    CycleError { members }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        edges
+            .iter()
+            .map(|(node, deps)| {
+                (
+                    node.to_string(),
+                    deps.iter().map(|d| d.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_dag_orders_dependencies_first() {
+        let deps = graph(&[
+            ("app", &["db", "cache"]),
+            ("cache", &["network"]),
+            ("db", &["network"]),
+            ("unrelated", &["not-in-batch"]),
+        ]);
+        let order = topo_sort(
+            &names(&["unrelated", "app", "network", "db", "cache"]),
+            &deps,
+        );
+        assert_eq!(
+            order.unwrap(),
+            names(&["network", "cache", "db", "app", "unrelated"])
+        );
+    }
+
+    #[test]
+    fn test_two_node_cycle_reported() {
+        let deps = graph(&[("b", &["a"]), ("a", &["b"]), ("c", &[])]);
+        let err = topo_sort(&names(&["c", "b", "a"]), &deps).unwrap_err();
+        assert_eq!(err.members, names(&["a", "b"]));
+        assert_eq!(err.to_string(), "Circular :after dependency: a -> b -> a");
+    }
+
+    #[test]
+    fn test_cycle_behind_other_nodes_found() {
+        // "a" is stuck only because it waits on the x -> y -> z cycle
+        let deps = graph(&[("a", &["z"]), ("x", &["y"]), ("y", &["z"]), ("z", &["x"])]);
+        let err = topo_sort(&names(&["a", "x", "y", "z"]), &deps).unwrap_err();
+        assert_eq!(err.members, names(&["x", "y", "z"]));
+    }
+}
@@ -1,5 +1,4 @@
 use anyhow::{Result, anyhow};
-use std::io::{self, Write};
 use std::process::{Command, Stdio};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
@@ -7,25 +6,21 @@ use std::time::Duration;
 
 /// Spinner display functionality
 pub mod spinner {
-    use super::*;
-
     // Shared spinner frames so all spinners look consistent
     const SPINNER_FRAMES: &[&str] = &["⁚", "⁖", "⁘", "⁛", "⁙", "⁛", "⁘", "⁖"];
 
-    /// Print a spinner frame with message
+    /// Draw a spinner frame with message (only when both streams are terminals)
     pub fn print_frame(message: &str, frame_index: usize) {
-        print!(
-            "\r\x1b[2K  {} {}...",
+        crate::internal::output::get().status(&format!(
+            "  {} {}...",
             crate::internal::color::blue(SPINNER_FRAMES[frame_index % SPINNER_FRAMES.len()]),
             message
-        );
-        io::stdout().flush().ok();
+        ));
     }
 
     /// Clear the current spinner line
     pub fn clear_line() {
-        print!("\r\x1b[2K");
-        io::stdout().flush().ok();
+        crate::internal::output::get().clear_status();
     }
 
     /// Configuration for spinner behavior
@@ -86,6 +81,71 @@ pub mod command {
             })
         }
     }
+
+    /// Exit code and captured output of a finished command
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct RawOutput {
+        /// `None` when the process was killed by a signal
+        pub code: Option<i32>,
+        pub stdout: String,
+        pub stderr: String,
+    }
+
+    impl RawOutput {
+        /// A command whose output was not captured
+        pub fn status_only(status: std::process::ExitStatus) -> Self {
+            Self {
+                code: status.code(),
+                ..Default::default()
+            }
+        }
+
+        /// A command whose stderr was captured but not its stdout
+        pub fn with_stderr(status: std::process::ExitStatus, stderr: String) -> Self {
+            Self {
+                code: status.code(),
+                stdout: String::new(),
+                stderr,
+            }
+        }
+    }
+
+    impl From<std::process::Output> for RawOutput {
+        fn from(output: std::process::Output) -> Self {
+            Self {
+                code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }
+        }
+    }
+
+    /// What a command's exit code and output mean for the operation that ran it
+    ///
+    /// Each caller decides how to map its command's output (see
+    /// `core::pm::Operation::interpret`) and then matches on the outcome instead of
+    /// the raw status.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum CommandOutcome {
+        Success,
+        /// Ran fine but had nothing to do
+        NoChanges,
+        /// Part of the request succeeded; `details` says what did not
+        PartialSuccess {
+            details: String,
+        },
+        /// None of the work was done because these targets do not exist
+        NotFound {
+            names: Vec<String>,
+        },
+        /// Nothing was done because another process holds the package database lock
+        DatabaseLocked {
+            stderr: String,
+        },
+        Failure {
+            stderr: String,
+        },
+    }
 }
 
 /// Run a spinner with common timeout and animation logic
@@ -137,47 +197,58 @@ where
     }
 }
 
-/// Execute a command with spinner progress display
-pub fn execute_command_with_spinner(
-    command: &str,
-    args: &[&str],
-    message: &str,
-) -> anyhow::Result<std::process::ExitStatus> {
-    let setup = command::CommandSetup::new(command, args)?;
+/// Output lines of a command with the time each was read, in milliseconds since the Unix epoch
+pub type Transcript = Arc<Mutex<Vec<(u64, String)>>>;
 
-    // Get stdout handle for reading output
-    let stdout = setup
-        .stdout
-        .ok_or_else(|| anyhow!("Failed to get child stdout"))?;
-    let current_status = Arc::new(Mutex::new(message.to_string()));
+/// Handle the output readers append to; each reader holds one until its pipe closes
+#[derive(Clone)]
+struct TranscriptWriter {
+    lines: Transcript,
+    _open: mpsc::Sender<()>,
+}
 
-    // Start thread to read and parse output
-    start_output_reader(stdout, Arc::clone(&current_status));
+impl TranscriptWriter {
+    /// A writer for `transcript` and the receiver that disconnects once every clone is gone
+    fn new(transcript: Option<&Transcript>) -> (Option<Self>, Option<mpsc::Receiver<()>>) {
+        let Some(lines) = transcript else {
+            return (None, None);
+        };
+        let (open, closed) = mpsc::channel();
+        let writer = Self {
+            lines: Arc::clone(lines),
+            _open: open,
+        };
+        (Some(writer), Some(closed))
+    }
 
-    let child_clone = Arc::clone(&setup.child);
-    run_with_spinner_common(
-        spinner::SpinnerConfig::default().with_cleanup(move || {
-            if let Ok(mut child_guard) = child_clone.lock() {
-                let _ = child_guard.kill();
-            }
-        }),
-        || match current_status.lock() {
-            Ok(guard) => guard.clone(),
-            Err(poisoned) => poisoned.into_inner().clone(),
-        },
-        || match setup.child.lock().unwrap().try_wait() {
-            Ok(Some(status)) => Ok(Some(Ok(status))),
-            Ok(None) => Ok(None),
-            Err(e) => Err(anyhow!("Failed to wait for command: {}", e)),
-        },
-    )
+    fn record(&self, line: &str) {
+        let at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        match self.lines.lock() {
+            Ok(mut lines) => lines.push((at, line.to_string())),
+            Err(poisoned) => poisoned.into_inner().push((at, line.to_string())),
+        }
+    }
+}
+
+/// Give the readers a moment to drain their pipes after the command exited
+///
+/// Bounded, since a daemon the command started can keep a pipe open.
+fn wait_for_readers(closed: Option<mpsc::Receiver<()>>) {
+    if let Some(closed) = closed {
+        let _ = closed.recv_timeout(Duration::from_secs(1));
+    }
 }
 
 /// Execute a command with spinner and capture stderr for diagnostics
+///
+/// With a `transcript`, every stdout and stderr line is also appended to it.
 pub fn execute_command_with_stderr_capture(
     command: &str,
     args: &[&str],
     message: &str,
+    transcript: Option<&Transcript>,
 ) -> anyhow::Result<(std::process::ExitStatus, String)> {
     let setup = command::CommandSetup::new(command, args)?;
 
@@ -193,15 +264,21 @@ pub fn execute_command_with_stderr_capture(
     let captured_stderr = Arc::new(Mutex::new(String::new()));
 
     // Start readers
-    start_output_reader(stdout, Arc::clone(&current_status));
+    let (writer, readers_closed) = TranscriptWriter::new(transcript);
+    start_output_reader(stdout, Arc::clone(&current_status), writer.clone());
 
-    // Capture stderr fully for diagnostics
+    // Capture stderr fully for diagnostics; `stderr_closed` disconnects once it is drained
+    let (stderr_open, stderr_closed) = mpsc::channel::<()>();
     {
         let captured_stderr = Arc::clone(&captured_stderr);
         thread::spawn(move || {
+            let _open = stderr_open;
             use std::io::{BufRead, BufReader};
             let reader = BufReader::new(stderr);
             for line in reader.lines().map_while(Result::ok) {
+                if let Some(writer) = &writer {
+                    writer.record(&line);
+                }
                 match captured_stderr.lock() {
                     Ok(mut buf) => {
                         buf.push_str(&line);
@@ -235,6 +312,8 @@ pub fn execute_command_with_stderr_capture(
             Err(e) => Err(anyhow!("Failed to wait for command: {}", e)),
         },
     )?;
+    wait_for_readers(readers_closed);
+    wait_for_readers(Some(stderr_closed));
 
     let stderr_output = match captured_stderr.lock() {
         Ok(guard) => guard.clone(),
@@ -247,15 +326,25 @@ pub fn execute_command_with_stderr_capture(
 }
 
 /// Execute a command with retry logic and spinner progress display
+///
+/// With a `transcript`, it holds the stdout and stderr lines of the last attempt.
 pub fn execute_command_with_retry(
     command: &str,
     args: &[String],
     base_message: &str,
     max_retries: usize,
+    transcript: Option<&Transcript>,
 ) -> anyhow::Result<std::process::ExitStatus> {
     let mut last_error = None;
 
     for attempt in 0..=max_retries {
+        if let Some(transcript) = transcript {
+            match transcript.lock() {
+                Ok(mut lines) => lines.clear(),
+                Err(poisoned) => poisoned.into_inner().clear(),
+            }
+        }
+
         // Create a channel for spinner status updates
         let (status_tx, _status_rx) = mpsc::channel();
 
@@ -265,6 +354,7 @@ pub fn execute_command_with_retry(
             let args = args.to_vec();
             let base_message = base_message.to_string();
             let status_tx = status_tx.clone();
+            let transcript = transcript.cloned();
 
             thread::spawn(move || {
                 execute_command_with_dynamic_spinner(
@@ -274,6 +364,7 @@ pub fn execute_command_with_retry(
                     attempt,
                     max_retries,
                     status_tx,
+                    transcript,
                 )
             })
         };
@@ -304,9 +395,7 @@ pub fn execute_command_with_retry(
                     attempt + 1,
                     max_retries + 1
                 );
-                spinner::clear_line();
-                print!("{}", retry_message);
-                std::io::stdout().flush().ok();
+                crate::internal::output::get().status(&retry_message);
 
                 // Sleep for the retry delay
                 thread::sleep(delay);
@@ -331,6 +420,7 @@ fn execute_command_with_dynamic_spinner(
     attempt: usize,
     max_retries: usize,
     _status_tx: mpsc::Sender<String>,
+    transcript: Option<Transcript>,
 ) -> anyhow::Result<std::process::ExitStatus> {
     let setup = command::CommandSetup::new(
         command,
@@ -344,10 +434,19 @@ fn execute_command_with_dynamic_spinner(
     let current_status = Arc::new(Mutex::new(base_message.to_string()));
 
     // Start thread to read and parse output
-    start_output_reader(stdout, Arc::clone(&current_status));
+    let (writer, readers_closed) = TranscriptWriter::new(transcript.as_ref());
+    if let (Some(stderr), Some(writer)) = (setup.stderr, writer.clone()) {
+        thread::spawn(move || {
+            use std::io::{BufRead, BufReader};
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                writer.record(&line);
+            }
+        });
+    }
+    start_output_reader(stdout, Arc::clone(&current_status), writer);
 
     let child_clone = Arc::clone(&setup.child);
-    run_with_spinner_common(
+    let status = run_with_spinner_common(
         spinner::SpinnerConfig::default().with_cleanup(move || {
             if let Ok(mut child_guard) = child_clone.lock() {
                 let _ = child_guard.kill();
@@ -369,7 +468,67 @@ fn execute_command_with_dynamic_spinner(
             Ok(None) => Ok(None),
             Err(e) => Err(anyhow!("Failed to wait for command: {}", e)),
         },
-    )
+    );
+    wait_for_readers(readers_closed);
+    status
+}
+
+/// `1536` is `1.5 KiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// `*` matches any run of characters; everything else matches itself
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(mut remaining) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let last = parts.pop().unwrap_or("");
+    for part in parts {
+        match remaining.find(part) {
+            Some(at) => remaining = &remaining[at + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.len() >= last.len() && remaining.ends_with(last)
+}
+
+/// Apply `f` to every item on at most `limit` threads, keeping input order
+///
+/// Without the `parallel` feature every call takes the serial path.
+pub fn map_bounded<T, R, F>(items: &[T], limit: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    #[cfg(feature = "parallel")]
+    if limit > 1 && items.len() > 1 {
+        use rayon::prelude::*;
+        // A pool of its own, so `limit` holds whatever else runs on rayon
+        let threads = limit.min(items.len());
+        if let Ok(pool) = rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+            return pool.install(|| items.par_iter().map(&f).collect());
+        }
+    }
+    #[cfg(not(feature = "parallel"))]
+    let _ = limit;
+    items.iter().map(f).collect()
 }
 
 /// Execute an operation with spinner progress display
@@ -400,12 +559,19 @@ where
     )
 }
 
-fn start_output_reader(stdout: std::process::ChildStdout, status: Arc<Mutex<String>>) {
+fn start_output_reader(
+    stdout: std::process::ChildStdout,
+    status: Arc<Mutex<String>>,
+    transcript: Option<TranscriptWriter>,
+) {
     thread::spawn(move || {
         use std::io::{BufRead, BufReader};
         let reader = BufReader::new(stdout);
 
         for line in reader.lines().map_while(Result::ok) {
+            if let Some(writer) = &transcript {
+                writer.record(&line);
+            }
             let line = line.trim();
             if !line.is_empty() && !line.starts_with("::") {
                 let status_msg = if let Some(pkg) = extract_package_name(line) {
@@ -456,6 +622,26 @@ fn extract_package_name(line: &str) -> Option<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_map_bounded_respects_limit_and_order() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let in_flight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items: Vec<usize> = (0..16).collect();
+
+        let doubled = map_bounded(&items, 3, |n| {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            n * 2
+        });
+
+        assert_eq!(doubled, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+        let expected_peak = if cfg!(feature = "parallel") { 3 } else { 1 };
+        assert_eq!(peak.load(Ordering::SeqCst), expected_peak);
+    }
+
     #[test]
     fn test_run_with_spinner() {
         use super::spinner;
@@ -471,4 +657,14 @@ mod tests {
         );
         assert_eq!(result.unwrap(), 42);
     }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.conf", "pacman.conf"));
+        assert!(!glob_match("*.conf", "pacman.conf.bak"));
+        assert!(glob_match("config", "config"));
+        assert!(glob_match("*rc*", "bashrc.local"));
+        assert!(!glob_match("*.toml", ".toml.x"));
+        assert!(glob_match("*", ""));
+    }
 }
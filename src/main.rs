@@ -1,9 +1,11 @@
+#[macro_use]
+mod internal;
 mod cli;
 mod commands;
 mod core;
 mod error;
-mod internal;
 
 fn main() {
+    internal::environment::init();
     cli::handler::parse_and_execute();
 }
@@ -0,0 +1,68 @@
+//! Scaffolding shared by the integration tests: fake programs on PATH that log
+//! their calls, and an owl command against a throwaway HOME
+
+// Each test binary compiles this module and uses a different part of it
+#![allow(dead_code)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+pub fn write(path: &Path, content: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+/// A shell script that appends "name args" to `$FAKE_LOG`, then runs `body`
+pub fn fake_script(body: &str) -> String {
+    format!(
+        "#!/bin/sh\necho \"$(basename \"$0\") $*\" >> \"$FAKE_LOG\"\n{}\n",
+        body
+    )
+}
+
+/// A fake paru or pacman whose `-Qq` lists `installed`; everything else succeeds
+pub fn fake_pm(installed: &[&str]) -> String {
+    let listed: String = installed.iter().map(|p| format!("{}\\n", p)).collect();
+    fake_script(&format!(
+        "if [ \"$1\" = -Qq ]; then printf '{}'; fi\nexit 0",
+        listed
+    ))
+}
+
+/// Install each `(name, script)` as an executable in `dir`
+pub fn install_fake_bins(dir: &Path, scripts: &[(&str, &str)]) {
+    for (name, script) in scripts {
+        let path = dir.join(name);
+        write(&path, script);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+/// The owl binary with `home` as HOME, the fakes in `bin` first on PATH and
+/// their calls logged to `log`
+pub fn owl_cmd(home: &Path, bin: &Path, log: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_owl"));
+    command
+        .env("HOME", home)
+        .env(
+            "PATH",
+            format!("{}:{}", bin.display(), std::env::var("PATH").unwrap()),
+        )
+        .env("FAKE_LOG", log)
+        .env("NO_COLOR", "1")
+        .env_remove("OWL_SPLAY");
+    command
+}
+
+/// Whether the command succeeded, and its stdout followed by its stderr
+pub fn run(command: &mut Command) -> (bool, String) {
+    let output = command.output().unwrap();
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (output.status.success(), text)
+}
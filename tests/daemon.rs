@@ -0,0 +1,173 @@
+//! `owl daemon` against a throwaway HOME and runtime directory with fake paru
+//! and pacman, spoken to over its socket
+
+mod common;
+
+use common::{fake_pm, install_fake_bins, owl_cmd, write};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// Send one request and read the answer, both length-prefixed JSON
+fn request(stream: &mut UnixStream, request: serde_json::Value) -> serde_json::Value {
+    let body = serde_json::to_vec(&request).unwrap();
+    stream
+        .write_all(&(body.len() as u32).to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+    read(stream)
+}
+
+fn read(stream: &mut UnixStream) -> serde_json::Value {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// `(name, change)` of each package in a plan response
+fn changes(response: &serde_json::Value) -> Vec<(String, String)> {
+    assert_eq!(response["response"], "plan", "{}", response);
+    response["plan"]["packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| {
+            (
+                p["name"].as_str().unwrap().to_string(),
+                p["change"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_daemon_serves_status_and_plan_over_its_socket() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let runtime = root.path().join("run");
+    let log = root.path().join("calls.log");
+    let pm = fake_pm(&["git"]);
+    install_fake_bins(&bin, &[("paru", &pm), ("pacman", &pm)]);
+    fs::create_dir_all(&runtime).unwrap();
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n@package ripgrep\n",
+    );
+    write(&home.join(".owl/.state/managed.json"), "[\"git\"]");
+
+    let owl = |args: &[&str]| {
+        let mut command = owl_cmd(&home, &bin, &log);
+        command.args(args).env("XDG_RUNTIME_DIR", &runtime);
+        command
+    };
+
+    let mut daemon = owl(&["daemon"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let socket = runtime.join("owl/daemon.sock");
+    let started = Instant::now();
+    let mut stream = loop {
+        if let Ok(stream) = UnixStream::connect(&socket) {
+            break stream;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "the daemon did not start"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    assert_eq!(
+        fs::metadata(&socket).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    let ripgrep = vec![("ripgrep".to_string(), "install".to_string())];
+    let first = request(&mut stream, serde_json::json!({"request": "status"}));
+    assert_eq!(changes(&first), ripgrep);
+    assert_eq!(first["cached"], false);
+    let again = request(&mut stream, serde_json::json!({"request": "status"}));
+    assert_eq!(again["cached"], true);
+    assert_eq!(again["planned_at"], first["planned_at"]);
+
+    // A config change is noticed by the next status
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n@package ripgrep\n@package fd\n",
+    );
+    let changed = request(&mut stream, serde_json::json!({"request": "status"}));
+    assert_eq!(changed["cached"], false);
+    assert_eq!(
+        changes(&changed),
+        vec![
+            ("fd".to_string(), "install".to_string()),
+            ("ripgrep".to_string(), "install".to_string()),
+        ]
+    );
+    let plan = request(&mut stream, serde_json::json!({"request": "plan"}));
+    assert_eq!(plan["cached"], false);
+    assert_eq!(changes(&plan).len(), 2);
+
+    // What the package manager said about installed packages and groups
+    // served every plan so far
+    let calls = fs::read_to_string(&log).unwrap();
+    let count = |call: &str| calls.lines().filter(|line| *line == call).count();
+    assert_eq!(count("paru -Qq"), 1, "{}", calls);
+    assert_eq!(count("pacman -Sg ripgrep"), 1, "{}", calls);
+
+    // An apply streams its events from the daemon's own process
+    let mut response = request(
+        &mut stream,
+        serde_json::json!({"request": "apply", "dry_run": true}),
+    );
+    let mut events = Vec::new();
+    while response["response"] == "event" {
+        events.push(response["event"]["type"].as_str().unwrap().to_string());
+        response = read(&mut stream);
+    }
+    assert_eq!(
+        response,
+        serde_json::json!({"response": "done", "success": true})
+    );
+    assert_eq!(events.first().map(String::as_str), Some("phase_started"));
+    assert!(
+        events.contains(&"dotfiles_empty".to_string()),
+        "{:?}",
+        events
+    );
+
+    // The CLI routes through the running daemon
+    let output = owl(&["status", "--via-daemon"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout
+            .contains("2 to install, nothing to upgrade, nothing to remove (planned by owl daemon"),
+        "{}",
+        stdout
+    );
+    let output = owl(&["apply", "--plan-json", "--via-daemon"])
+        .output()
+        .unwrap();
+    let routed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(routed["packages"].as_array().unwrap().len(), 2);
+
+    let stopped = request(&mut stream, serde_json::json!({"request": "shutdown"}));
+    assert_eq!(stopped["response"], "ok");
+    assert!(daemon.wait().unwrap().success());
+    assert!(!socket.exists());
+
+    // Without the daemon the same command plans in-process
+    let output = owl(&["status", "--via-daemon"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("2 to install"), "{}", stdout);
+    assert!(stdout.contains("(planned now)"), "{}", stdout);
+}
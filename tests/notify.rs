@@ -0,0 +1,52 @@
+//! systemd status notifications from `owl apply --splay`, received on a
+//! datagram socket standing in for `NOTIFY_SOCKET`
+#![cfg(feature = "notify")]
+
+mod common;
+
+use common::{fake_pm, install_fake_bins, owl_cmd, run, write};
+use std::os::unix::net::UnixDatagram;
+
+#[test]
+fn test_splay_reports_its_status_to_systemd() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    let pm = fake_pm(&[]);
+    install_fake_bins(&bin, &[("paru", &pm), ("pacman", &pm)]);
+    write(&home.join(".owl/main.owl"), "@env EDITOR=nvim\n");
+
+    let socket_path = root.path().join("notify");
+    let socket = UnixDatagram::bind(&socket_path).unwrap();
+    socket.set_nonblocking(true).unwrap();
+
+    let (ok, out) = run(owl_cmd(&home, &bin, &log)
+        .args([
+            "--dry-run",
+            "apply",
+            "--dotfiles-only",
+            "--splay",
+            "200ms",
+            "--splay-always",
+        ])
+        .env("NOTIFY_SOCKET", &socket_path));
+    assert!(ok, "{}", out);
+
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+    while let Ok(n) = socket.recv(&mut buf) {
+        received.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+    }
+    assert!(
+        received
+            .iter()
+            .any(|m| m.starts_with("STATUS=Splay: waiting ")),
+        "{:?}",
+        received
+    );
+    assert_eq!(
+        received.last().map(String::as_str),
+        Some("STATUS=Applying configuration\n")
+    );
+}
@@ -0,0 +1,79 @@
+//! `owl apply --parallel-dotfiles-and-packages` against a throwaway HOME with
+//! fake paru and pacman
+
+mod common;
+
+use common::{fake_script, install_fake_bins, owl_cmd, write};
+use std::fs;
+
+/// `-Qq` lists git as installed and `-Si` finds every package in the repos
+const FAKE_PACMAN: &str = "case \"$1\" in\n\
+    -Qq) printf 'git\\n' ;;\n\
+    -Si) shift; for p in \"$@\"; do printf 'Name            : %s\\n' \"$p\"; done ;;\n\
+    esac\n\
+    exit 0";
+
+/// Lists git as installed; a repo install waits for the dotfile to appear,
+/// which only happens when the dotfiles sync while it runs
+const FAKE_PARU: &str = "if [ \"$1\" = -Qq ]; then printf 'git\\n'; fi\n\
+    if [ \"$1\" = --repo ]; then\n\
+    for i in $(seq 100); do [ -e \"$HOME/.gitconfig\" ] && break; sleep 0.1; done\n\
+    [ -e \"$HOME/.gitconfig\" ] && echo 'dotfile synced during install' >> \"$FAKE_LOG\"\n\
+    fi\n\
+    exit 0";
+
+#[test]
+fn test_parallel_apply_runs_and_reports_both_phases() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    install_fake_bins(
+        &bin,
+        &[
+            ("paru", &fake_script(FAKE_PARU)),
+            ("pacman", &fake_script(FAKE_PACMAN)),
+        ],
+    );
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n:config gitconfig -> ~/.gitconfig\n@package ripgrep\n",
+    );
+    write(&home.join(".owl/dotfiles/gitconfig"), "[user]\n");
+    write(&home.join(".owl/.state/managed.json"), "[\"git\"]");
+
+    let output = owl_cmd(&home, &bin, &log)
+        .args([
+            "--non-interactive",
+            "apply",
+            "--parallel-dotfiles-and-packages",
+            "--skip",
+            "repo-update,aur-update",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}{}", stdout, stderr);
+
+    let calls = fs::read_to_string(&log).unwrap();
+    assert!(
+        calls.contains("paru --repo -S --noconfirm ripgrep"),
+        "{}",
+        calls
+    );
+    assert!(calls.contains("dotfile synced during install"), "{}", calls);
+    assert_eq!(
+        fs::read_to_string(home.join(".gitconfig")).unwrap(),
+        "[user]\n"
+    );
+
+    // The dotfile output is held back until the packages are done
+    let packages = stdout
+        .find("repo packages found: ripgrep")
+        .unwrap_or_else(|| panic!("{}", stdout));
+    let dotfiles = stdout
+        .find(".gitconfig")
+        .unwrap_or_else(|| panic!("{}", stdout));
+    assert!(packages < dotfiles, "{}", stdout);
+}
@@ -0,0 +1,265 @@
+//! `owl apply --diff` against a throwaway HOME with fake paru, pacman and systemctl
+
+mod common;
+
+use common::{fake_pm, fake_script, install_fake_bins, owl_cmd, run, write};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// paru and pacman list git and bat as installed; sshd is neither enabled nor
+/// running, and anything else through systemctl or sudo would be a change
+fn install_fakes(bin: &Path) {
+    let pm = fake_pm(&["git", "bat"]);
+    let failing = fake_script("exit 1");
+    install_fake_bins(
+        bin,
+        &[
+            ("paru", &pm),
+            ("pacman", &pm),
+            ("systemctl", &failing),
+            ("sudo", &failing),
+        ],
+    );
+}
+
+/// Every path under `dir` with its content and modification time
+fn snapshot(dir: &Path) -> BTreeMap<String, (Vec<u8>, Option<SystemTime>)> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let meta = fs::symlink_metadata(&path).unwrap();
+        let key = path.strip_prefix(dir).unwrap().display().to_string();
+        if meta.is_dir() {
+            for entry in fs::read_dir(&path).unwrap() {
+                pending.push(entry.unwrap().path());
+            }
+            files.insert(key, (Vec::new(), None));
+        } else {
+            files.insert(key, (fs::read(&path).unwrap(), meta.modified().ok()));
+        }
+    }
+    files
+}
+
+#[test]
+fn test_diff_preview_reports_every_section_and_changes_nothing() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    install_fakes(&bin);
+
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n:config git -> ~/.config/git\n\
+         @package openssh\n:service sshd\n\
+         @package ripgrep\n@env EDITOR=nvim\n",
+    );
+    write(
+        &home.join(".owl/dotfiles/git/config"),
+        "[user]\n  name = new\n",
+    );
+    write(&home.join(".config/git/config"), "[user]\n  name = old\n");
+    // bat is managed but no longer declared, so it would be removed
+    write(&home.join(".owl/.state/managed.json"), "[\"bat\"]");
+
+    let before = snapshot(&home);
+    let output = owl_cmd(&home, &bin, &log)
+        .args(["apply", "--diff"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}\n{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert_eq!(snapshot(&home), before, "the preview modified HOME");
+    for section in [
+        // packages
+        "Would remove: bat",
+        "Would install/update openssh, ripgrep",
+        // dotfile diff
+        "-  name = old",
+        "+  name = new",
+        // env file diff
+        "+export EDITOR=\"nvim\"",
+        // service delta
+        "Service changes:",
+        "~ sshd.service: enable, start",
+    ] {
+        assert!(
+            stdout.contains(section),
+            "missing {:?} in\n{}",
+            section,
+            stdout
+        );
+    }
+
+    // Only queries reached the fakes, and never through sudo
+    let calls = fs::read_to_string(&log).unwrap();
+    for call in calls.lines() {
+        let read_only = [
+            "paru -Q",
+            "pacman -Q",
+            "pacman -Sg ",
+            "pacman -Si ",
+            "systemctl is-",
+        ]
+        .iter()
+        .any(|prefix| call.starts_with(prefix));
+        assert!(read_only, "unexpected call {:?}", call);
+    }
+}
+
+#[test]
+fn test_plan_json_prints_only_the_plan() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    install_fakes(&bin);
+
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n@package openssh\n:service sshd\n@package linux\n",
+    );
+    write(&home.join(".owl/.state/managed.json"), "[\"bat\"]");
+
+    let before = snapshot(&home);
+    let output = owl_cmd(&home, &bin, &log)
+        .args(["apply", "--plan-json"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}\n{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(snapshot(&home), before, "the plan modified HOME");
+
+    let plan: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(plan["schema"], 1);
+    assert_eq!(plan["reboot_advised"], true);
+    assert_eq!(plan["service_restarts_planned"], true);
+    let changes: Vec<(&str, &str)> = plan["packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| (p["name"].as_str().unwrap(), p["change"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            ("linux", "install"),
+            ("openssh", "install"),
+            ("bat", "remove")
+        ]
+    );
+    assert_eq!(
+        plan["packages"][1]["services"],
+        serde_json::json!(["sshd.service"])
+    );
+}
+
+#[test]
+fn test_review_file_round_trip_runs_only_ticked_items() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    let review = root.path().join("plan.md");
+    install_fakes(&bin);
+
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n:config gitconfig -> ~/.gitconfig\n@package ripgrep\n@package fd\n",
+    );
+    write(&home.join(".owl/dotfiles/gitconfig"), "[user]\n");
+    let owl = |args: &[&str]| run(owl_cmd(&home, &bin, &log).args(args));
+
+    let (ok, out) = owl(&["plan", "--review-file", review.to_str().unwrap()]);
+    assert!(ok, "{}", out);
+    let written = fs::read_to_string(&review).unwrap();
+    assert!(written.contains("| [x] | `fd` |"), "{}", written);
+    assert!(written.contains("- [x] `~/.gitconfig` from `gitconfig` (create)"));
+
+    fs::write(&review, written.replace("| [x] | `fd` |", "| [ ] | `fd` |")).unwrap();
+    let (ok, out) = owl(&[
+        "--dry-run",
+        "apply",
+        "--approved-review",
+        review.to_str().unwrap(),
+    ]);
+    assert!(ok, "{}", out);
+    assert!(out.contains("skipped (unticked): install fd"), "{}", out);
+    // The fakes know no repositories, so both would come from the AUR
+    assert!(out.contains("Would install/update ripgrep from"), "{}", out);
+    assert!(!out.contains("fd from"), "{}", out);
+
+    // A new declaration makes the reviewed plan stale
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n:config gitconfig -> ~/.gitconfig\n@package ripgrep\n@package fd\n\
+         @package jq\n",
+    );
+    let (ok, out) = owl(&[
+        "--dry-run",
+        "apply",
+        "--approved-review",
+        review.to_str().unwrap(),
+    ]);
+    assert!(!ok);
+    assert!(out.contains("the plan changed"), "{}", out);
+}
+
+#[test]
+fn test_status_names_only_lists_every_kind_of_drift() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    install_fakes(&bin);
+
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n:config git -> ~/.config/git\n\
+         @package openssh\n:service sshd\n\
+         @package ripgrep\n@env EDITOR=nvim\n",
+    );
+    write(
+        &home.join(".owl/dotfiles/git/config"),
+        "[user]\n  name = new\n",
+    );
+    write(&home.join(".config/git/config"), "[user]\n  name = old\n");
+    write(&home.join(".owl/.state/managed.json"), "[\"bat\"]");
+
+    let before = snapshot(&home);
+    let output = owl_cmd(&home, &bin, &log)
+        .args(["status", "--names-only"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}\n{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        stdout,
+        "install:openssh\n\
+         install:ripgrep\n\
+         remove:bat\n\
+         dotfile:~/.config/git\n\
+         service:sshd.service\n\
+         env:EDITOR\n"
+    );
+    assert_eq!(snapshot(&home), before, "status modified HOME");
+}
@@ -0,0 +1,154 @@
+//! The privileged helper protocol, spoken to `owl __privileged-helper` run
+//! unprivileged with its destinations under a throwaway root and a fake
+//! systemctl on PATH
+
+mod common;
+
+use common::{fake_script, install_fake_bins, owl_cmd, write};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::process::Stdio;
+
+#[test]
+fn test_helper_runs_only_what_the_manifest_allows() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    let sandbox = root.path().join("sandbox");
+    install_fake_bins(
+        &bin,
+        &[(
+            "systemctl",
+            &fake_script("echo \"Created symlink for $2\"\n[ \"$2\" != broken.service ]"),
+        )],
+    );
+    let source = root.path().join("owl.hook");
+    write(&source, "[Trigger]\nOperation = Upgrade\n");
+
+    let mut helper = owl_cmd(&home, &bin, &log)
+        .args(["__privileged-helper", "--root"])
+        .arg(&sandbox)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = helper.stdin.take().unwrap();
+    let mut output = BufReader::new(helper.stdout.take().unwrap());
+    let mut send = |line: String| {
+        writeln!(input, "{}", line).unwrap();
+        let mut reply = String::new();
+        output.read_line(&mut reply).unwrap();
+        serde_json::from_str::<serde_json::Value>(&reply).unwrap()
+    };
+    let ok = serde_json::json!({"ok": true});
+    let write_file = |destination: &str| {
+        serde_json::json!({
+            "op": "write_file",
+            "source": source,
+            "destination": destination,
+            "mode": 0o644,
+        })
+        .to_string()
+    };
+    let systemctl = |verb: &str, unit: &str| {
+        serde_json::json!({"op": "systemctl", "verb": verb, "unit": unit}).to_string()
+    };
+
+    let manifest = serde_json::json!({
+        "files": ["/etc/pacman.d/hooks/owl.hook"],
+        "units": ["sshd.service", "broken.service"],
+    });
+    assert_eq!(send(manifest.to_string()), ok);
+    assert_eq!(send(write_file("/etc/pacman.d/hooks/owl.hook")), ok);
+    assert_eq!(
+        send(write_file("/etc/sudoers")),
+        serde_json::json!({"ok": false, "error": "refused: /etc/sudoers is not in the manifest"})
+    );
+    // systemctl's own output does not end up in the protocol
+    assert_eq!(send(systemctl("enable", "sshd.service")), ok);
+    assert_eq!(
+        send(systemctl("enable", "cups.service")),
+        serde_json::json!({"ok": false, "error": "refused: cups.service is not in the manifest"})
+    );
+    let reply = send(systemctl("mask", "sshd.service"));
+    assert_eq!(reply["ok"], false);
+    assert!(
+        reply["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("refused: not an operation"),
+        "{}",
+        reply
+    );
+    let reply = send(systemctl("start", "broken.service"));
+    assert_eq!(reply["ok"], false);
+    assert!(
+        reply["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("systemctl start broken.service failed"),
+        "{}",
+        reply
+    );
+
+    // The helper ends with its input
+    drop(input);
+    assert!(helper.wait().unwrap().success());
+
+    let hook = sandbox.join("etc/pacman.d/hooks/owl.hook");
+    assert_eq!(
+        std::fs::read_to_string(&hook).unwrap(),
+        "[Trigger]\nOperation = Upgrade\n"
+    );
+    assert_eq!(
+        std::fs::metadata(&hook).unwrap().permissions().mode() & 0o7777,
+        0o644
+    );
+    assert!(!sandbox.join("etc/sudoers").exists());
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "systemctl enable sshd.service\nsystemctl start broken.service\n"
+    );
+}
+
+#[test]
+fn test_helper_exits_on_a_manifest_it_refuses() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    install_fake_bins(&bin, &[("systemctl", &fake_script("exit 0"))]);
+
+    let mut helper = owl_cmd(&home, &bin, &log)
+        .args(["__privileged-helper", "--root"])
+        .arg(root.path().join("sandbox"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = helper.stdin.take().unwrap();
+    writeln!(input, r#"{{"files":["/etc/../root/.bashrc"]}}"#).unwrap();
+    // Whatever follows is never run
+    let _ = writeln!(
+        input,
+        r#"{{"op":"systemctl","verb":"enable","unit":"sshd.service"}}"#
+    );
+    drop(input);
+    let output = helper.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    let reply: serde_json::Value =
+        serde_json::from_str(String::from_utf8_lossy(&output.stdout).trim()).unwrap();
+    assert_eq!(reply["ok"], false);
+    assert!(
+        reply["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid manifest: /etc/../root/.bashrc"),
+        "{}",
+        reply
+    );
+    assert!(!log.exists());
+}
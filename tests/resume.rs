@@ -0,0 +1,72 @@
+//! `owl apply --resume` after a run interrupted between the package and dotfile
+//! phases, against a throwaway HOME with fake paru, pacman and sudo
+
+mod common;
+
+use common::{fake_pm, fake_script, install_fake_bins, owl_cmd, run, write};
+use std::fs;
+
+#[test]
+fn test_resume_after_the_package_phase_runs_only_the_dotfiles() {
+    let root = tempfile::tempdir().unwrap();
+    let home = root.path().join("home");
+    let bin = root.path().join("bin");
+    let log = root.path().join("calls.log");
+    // ripgrep came from the interrupted run; nothing privileged should be
+    // needed once the packages are done
+    let pm = fake_pm(&["git", "ripgrep"]);
+    install_fake_bins(
+        &bin,
+        &[
+            ("paru", &pm),
+            ("pacman", &pm),
+            ("sudo", &fake_script("exit 1")),
+        ],
+    );
+
+    write(
+        &home.join(".owl/main.owl"),
+        "@package git\n:config gitconfig -> ~/.gitconfig\n@package ripgrep\n@package fd\n",
+    );
+    write(&home.join(".owl/dotfiles/gitconfig"), "[user]\n");
+    write(&home.join(".owl/.state/managed.json"), "[\"git\"]");
+    // The run was interrupted after installing ripgrep and finishing the
+    // package phase, before any dotfile was written
+    let checkpoint = home.join(".owl/.state/apply-checkpoint.json");
+    write(
+        &checkpoint,
+        r#"{"started": 1700000000, "completed": ["packages"], "installed": ["ripgrep"]}"#,
+    );
+
+    let owl = |args: &[&str]| run(owl_cmd(&home, &bin, &log).args(args));
+
+    let (ok, out) = owl(&["--non-interactive", "apply", "--resume"]);
+    assert!(ok, "{}", out);
+    assert!(
+        out.contains("already done: packages (1 installed)"),
+        "{}",
+        out
+    );
+
+    // fd is still missing, but the package phase is not run again: only
+    // queries reached the fakes, and never through sudo
+    let calls = fs::read_to_string(&log).unwrap_or_default();
+    for call in calls.lines() {
+        let read_only = ["paru -Q", "pacman -Q", "pacman -Sg ", "pacman -Si "]
+            .iter()
+            .any(|prefix| call.starts_with(prefix));
+        assert!(read_only, "unexpected call {:?}", call);
+    }
+    assert_eq!(
+        fs::read_to_string(home.join(".gitconfig")).unwrap(),
+        "[user]\n"
+    );
+    // What the interrupted run installed is managed now
+    let managed = fs::read_to_string(home.join(".owl/.state/managed.json")).unwrap();
+    assert!(managed.contains("ripgrep"), "{}", managed);
+    assert!(!checkpoint.exists(), "a finished run clears the checkpoint");
+
+    let (ok, out) = owl(&["apply", "--resume"]);
+    assert!(!ok);
+    assert!(out.contains("No interrupted apply to resume"), "{}", out);
+}